@@ -0,0 +1,179 @@
+//! A benchmark of the allocations made while decoding received header blocks, for a workload
+//! resembling the one of an API gateway: many requests on a connection, each one carrying a
+//! couple of dozen headers, most of which repeat from one request to the next.
+//!
+//! The allocations made by an `HttpConnection` handling the HEADERS frames are compared against
+//! the ones of decoding the same blocks into owned headers (i.e. `hpack::Decoder::decode` and
+//! collecting the result into a `Vec<Header>`), which is what the connection used to do.
+//!
+//! ```text
+//! cargo run --release --example header_decode [<requests>]
+//! ```
+
+extern crate hpack;
+extern crate solicit;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use solicit::http::{Header, HttpScheme, HttpResult, StreamId, ErrorCode};
+use solicit::http::connection::{HttpConnection, HttpFrame, ReceiveFrame};
+use solicit::http::frame::{FrameIR, RawFrame};
+use solicit::http::frame::headers::{HeadersFrame, HeadersFlag};
+use solicit::http::frame::settings::HttpSetting;
+use solicit::http::session::Session;
+
+/// An allocator that counts the allocations that go through it.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Returns the headers of the `i`-th request.
+fn request_headers(i: usize) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let headers: Vec<(&str, String)> = vec![
+        (":method", if i % 4 == 0 { "POST" } else { "GET" }.into()),
+        (":scheme", "https".into()),
+        (":authority", "api.example.com".into()),
+        (":path", format!("/v2/accounts/{}/orders?page={}", i % 97, i % 7)),
+        ("user-agent", "example-mobile-app/4.12.1 (Android 14; Pixel 8)".into()),
+        ("accept", "application/json".into()),
+        ("accept-encoding", "gzip, deflate, br".into()),
+        ("accept-language", "en-US,en;q=0.9".into()),
+        ("authorization", format!("Bearer eyJhbGciOiJIUzI1NiJ9.{:08x}", i % 13)),
+        ("content-type", "application/json".into()),
+        ("x-request-id", format!("9f3c2a6e-{:08x}", i)),
+        ("x-forwarded-for", format!("203.0.113.{}, 10.0.0.{}", i % 250, i % 3)),
+        ("x-forwarded-proto", "https".into()),
+        ("x-real-ip", format!("203.0.113.{}", i % 250)),
+        ("x-api-version", "2024-06-01".into()),
+        ("x-client-id", "mobile-android".into()),
+        ("traceparent", format!("00-4bf92f3577b34da6a3ce929d{:08x}-00f067aa0ba902b7-01", i)),
+        ("cache-control", "no-cache".into()),
+        ("cookie", "session=a3fWa; theme=dark; consent=1".into()),
+    ];
+    headers.into_iter().map(|(n, v)| (n.as_bytes().to_vec(), v.into_bytes())).collect()
+}
+
+/// Returns the HEADERS frames of the given number of requests, encoded by a single encoder.
+fn encode_requests(requests: usize) -> Vec<Vec<u8>> {
+    let mut encoder = hpack::Encoder::new();
+    (0..requests).map(|i| {
+        let headers = request_headers(i);
+        let block = encoder.encode(headers.iter().map(|&(ref n, ref v)| (&n[..], &v[..])));
+        let mut frame = HeadersFrame::new(block, 1);
+        frame.set_flag(HeadersFlag::EndHeaders);
+        frame.set_flag(HeadersFlag::EndStream);
+        let mut buf = io::Cursor::new(Vec::new());
+        frame.serialize_into(&mut buf).unwrap();
+        buf.into_inner()
+    }).collect()
+}
+
+/// Hands out the frames one after the other, without allocating.
+struct FrameReader<'a> {
+    frames: &'a [Vec<u8>],
+    raw: Option<RawFrame<'a>>,
+}
+
+impl<'a> ReceiveFrame for FrameReader<'a> {
+    fn recv_frame(&mut self) -> HttpResult<HttpFrame> {
+        let (first, rest) = self.frames.split_first().unwrap();
+        self.frames = rest;
+        self.raw = Some(RawFrame::from(&first[..]));
+        HttpFrame::from_raw(self.raw.as_ref().unwrap())
+    }
+}
+
+/// A session that only looks at the headers, like a gateway routing the requests would.
+struct RoutingSession {
+    bytes: usize,
+}
+
+impl Session for RoutingSession {
+    fn new_data_chunk(&mut self, _: StreamId, _: &[u8], _: &mut HttpConnection)
+            -> HttpResult<()> {
+        Ok(())
+    }
+
+    fn new_headers<'n, 'v>(&mut self, _: StreamId, headers: Vec<Header<'n, 'v>>,
+                           _: &mut HttpConnection)
+            -> HttpResult<()> {
+        self.bytes += headers.iter().map(|h| h.name().len() + h.value().len()).sum::<usize>();
+        Ok(())
+    }
+
+    fn end_of_stream(&mut self, _: StreamId, _: &mut HttpConnection) -> HttpResult<()> {
+        Ok(())
+    }
+
+    fn rst_stream(&mut self, _: StreamId, _: ErrorCode, _: &mut HttpConnection)
+            -> HttpResult<()> {
+        Ok(())
+    }
+
+    fn new_settings(&mut self, _: Vec<HttpSetting>, _: &mut HttpConnection) -> HttpResult<()> {
+        Ok(())
+    }
+}
+
+/// Runs the given decoding of all the frames, reporting the allocations that it made.
+fn measure<F: FnOnce() -> usize>(name: &str, requests: usize, decode: F) {
+    let start = Instant::now();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes = decode();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!("{:>14}: {:>10} allocations ({:>6.2} per block), {:?} ({} header bytes)",
+             name, allocations, allocations as f64 / requests as f64, start.elapsed(), bytes);
+}
+
+fn main() {
+    let requests: usize = env::args().nth(1)
+                                     .map(|arg| arg.parse().expect("Expected a number"))
+                                     .unwrap_or(100_000);
+    let frames = encode_requests(requests);
+    println!("{} header blocks of {} headers", requests, request_headers(0).len());
+
+    measure("owned headers", requests, || {
+        let mut decoder = hpack::Decoder::new();
+        let mut bytes = 0;
+        for frame in &frames {
+            // The block of a frame without any padding or priority follows the frame header.
+            let headers = decoder.decode(&frame[9..]).unwrap();
+            let headers: Vec<Header> = headers.into_iter().map(|h| h.into()).collect();
+            bytes += headers.iter().map(|h| h.name().len() + h.value().len()).sum::<usize>();
+        }
+        bytes
+    });
+
+    measure("connection", requests, || {
+        let mut conn = HttpConnection::new(HttpScheme::Https);
+        let mut session = RoutingSession { bytes: 0 };
+        let mut reader = FrameReader { frames: &frames, raw: None };
+        for _ in 0..requests {
+            conn.handle_next_frame(&mut reader, &mut session).unwrap();
+        }
+        session.bytes
+    });
+}
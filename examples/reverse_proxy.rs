@@ -0,0 +1,126 @@
+//! An example of using the `SimpleServer` and the async `Client` together, as a reverse proxy
+//! that forwards all requests it receives to a single upstream server.
+//!
+//! Usage: `reverse_proxy <listen address> <upstream host> <upstream port>`, e.g.
+//! `reverse_proxy 127.0.0.1:8080 127.0.0.1 8081`.
+//!
+//! Each downstream connection is served on its own thread, while all of them share the one
+//! upstream connection of the `Client`. Request bodies are streamed through to the upstream
+//! server as they arrive, by deferring the response (see `solicit::server::defer`) and reading
+//! the body with `SimpleServer::request_body`. The upstream responses, on the other hand, are
+//! buffered, since the `Client` only hands out complete responses. Their trailers are forwarded
+//! after the body (see `OpenStream::send_trailers`), while those of the requests are dropped, as
+//! the `Client` cannot send any.
+//!
+//! Requests on a connection are proxied one at a time. Only for demonstration purposes.
+
+extern crate solicit;
+
+use std::cell::RefCell;
+use std::env;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::rc::Rc;
+use std::thread;
+
+use solicit::http::{Header, StaticHeader, StaticResponse, StreamId};
+use solicit::http::client::CleartextConnector;
+use solicit::client::Client;
+use solicit::server::{self, SimpleServer};
+
+/// A request received from a downstream client, waiting to be forwarded.
+struct ProxiedRequest {
+    stream_id: StreamId,
+    method: Vec<u8>,
+    path: Vec<u8>,
+    authority: Option<Vec<u8>>,
+    headers: Vec<StaticHeader>,
+}
+
+/// Forwards the request upstream, piping its body through, and returns the upstream response.
+fn forward<H>(client: &Client, server: &mut SimpleServer<TcpStream, H>, req: &ProxiedRequest)
+        -> Option<StaticResponse>
+        where H: FnMut(server::ServerRequest) -> StaticResponse {
+    let authority = req.authority.as_ref().map(|authority| &authority[..]);
+    let (mut body, rx) = match client.request_streaming(authority, &req.method, &req.path,
+                                                         &req.headers) {
+        Some(upstream) => upstream,
+        None => return None,
+    };
+    if let Some(mut downstream) = server.request_body(req.stream_id) {
+        if io::copy(&mut downstream, &mut body).is_err() {
+            return None;
+        }
+    }
+    if body.finish().is_err() {
+        return None;
+    }
+    rx.recv().ok()
+}
+
+fn handle_client(stream: TcpStream, client: Client) {
+    let queued = Rc::new(RefCell::new(Vec::new()));
+    let handler_queue = queued.clone();
+    let mut server = SimpleServer::new(stream, move |req| {
+        let pseudo = req.pseudo_headers;
+        handler_queue.borrow_mut().push(ProxiedRequest {
+            stream_id: req.stream_id,
+            method: pseudo.method.clone().unwrap_or_else(|| b"GET".to_vec()),
+            path: pseudo.path.clone().unwrap_or_else(|| b"/".to_vec()),
+            authority: pseudo.authority.clone(),
+            headers: req.headers.iter()
+                                .filter(|h| !h.name().starts_with(b":"))
+                                .map(|h| Header::new(h.name().to_vec(), h.value().to_vec()))
+                                .collect(),
+        });
+        // The response is only known once the upstream server answers.
+        server::defer(req.stream_id)
+    }).unwrap();
+    // Every request is deferred, so that its body can be streamed upstream.
+    server.set_streaming_filter(|_| true);
+
+    while let Ok(_) = server.handle_next() {
+        let requests: Vec<_> = queued.borrow_mut().drain(..).collect();
+        for req in requests {
+            let (headers, body, trailers) = match forward(&client, &mut server, &req) {
+                Some(response) => (response.headers, response.body, response.trailers),
+                None => (vec![Header::new(b":status", b"502")], Vec::new(), Vec::new()),
+            };
+            let mut stream = match server.open_stream(req.stream_id) {
+                Some(stream) => stream,
+                // The downstream client reset the stream in the meantime.
+                None => continue,
+            };
+            let res = stream.start_response(headers)
+                            .and_then(|_| stream.send(&body))
+                            .and_then(|_| if trailers.is_empty() {
+                                stream.close()
+                            } else {
+                                stream.send_trailers(trailers)
+                            });
+            if let Err(e) = res {
+                println!("Failed to send the response on stream {}: {:?}", req.stream_id, e);
+            }
+        }
+    }
+    println!("Downstream client disconnected");
+}
+
+fn main() {
+    let args: Vec<_> = env::args().collect();
+    if args.len() != 4 {
+        println!("Usage: {} <listen address> <upstream host> <upstream port>", args[0]);
+        return;
+    }
+    let port = args[3].parse().expect("Invalid upstream port");
+    let client = Client::with_connector(CleartextConnector::with_port(&args[2], port))
+                        .expect("Failed to connect to the upstream server");
+
+    let listener = TcpListener::bind(&args[1][..]).unwrap();
+    println!("Proxying {} to {}:{}...", args[1], args[2], port);
+    for stream in listener.incoming() {
+        let stream = stream.unwrap();
+        let client = client.clone();
+        thread::spawn(move || handle_client(stream, client));
+    }
+}
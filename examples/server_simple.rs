@@ -33,6 +33,7 @@ fn main() {
                     Header::new(b"x-solicit".to_vec(), b"Hello, World!".to_vec()),
                 ],
                 body: req.body.to_vec(),
+                trailers: vec![],
                 stream_id: req.stream_id,
            }
         }).unwrap();
@@ -0,0 +1,72 @@
+//! A soak test that runs a large number of requests through a `SimpleClient` and a `SimpleServer`
+//! connected by an in-memory pipe, so that leaks and unbounded growth become visible.
+//!
+//! The requests are spread over a series of connections. While a connection is alive, the
+//! resources that are outstanding are reported periodically; once both of its ends are gone,
+//! everything that they allocated must have been released again, which is checked by
+//! `soak::debug_assert_balanced`.
+//!
+//! The accounting needs the `soak` feature (and the check a debug build):
+//!
+//! ```text
+//! cargo run --features soak --example soak [<requests> [<requests per connection>]]
+//! ```
+
+extern crate solicit;
+
+use std::env;
+use std::thread;
+use std::time::Instant;
+
+use solicit::http::{HttpScheme, Response};
+use solicit::http::client::write_preface;
+use solicit::http::duplex;
+use solicit::http::soak;
+use solicit::http::transport::TransportStream;
+use solicit::client::SimpleClient;
+use solicit::server::SimpleServer;
+
+/// Runs the given number of requests on a new connection.
+fn run_connection(requests: usize) {
+    let (mut client_end, server_end) = duplex::pipe();
+    let server = thread::spawn(move || {
+        let mut server = SimpleServer::new(server_end, |req| {
+            Response::new(req.stream_id, vec![(b":status".to_vec(), b"200".to_vec())],
+                          req.body.to_vec())
+        }).unwrap();
+        while let Ok(_) = server.handle_next() {}
+    });
+
+    let mut pipe = client_end.try_split().unwrap();
+    write_preface(&mut client_end).unwrap();
+    let mut client = SimpleClient::with_stream(client_end, "localhost".into(), HttpScheme::Http)
+        .unwrap();
+    for i in 0..requests {
+        let response = client.post(b"/soak", &[], vec![b'x'; i % 64]).unwrap();
+        assert_eq!(response.body.len(), i % 64);
+    }
+    println!("  outstanding before closing: {:?}", soak::outstanding());
+
+    pipe.close().unwrap();
+    server.join().unwrap();
+}
+
+fn main() {
+    let mut args = env::args().skip(1).map(|arg| arg.parse().expect("Expected a number"));
+    let total: usize = args.next().unwrap_or(1_000_000);
+    let per_connection: usize = args.next().unwrap_or(1_000);
+    if !cfg!(feature="soak") {
+        println!("Warning: built without the `soak` feature, so nothing is accounted for");
+    }
+
+    let start = Instant::now();
+    let mut done = 0;
+    while done < total {
+        let requests = ::std::cmp::min(per_connection, total - done);
+        run_connection(requests);
+        done += requests;
+        soak::debug_assert_balanced();
+        println!("{} requests done in {:?}, outstanding: {:?}",
+                 done, start.elapsed(), soak::outstanding());
+    }
+}
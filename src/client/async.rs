@@ -7,13 +7,30 @@ use std::collections::HashMap;
 
 use std::sync::mpsc::{Sender, Receiver};
 use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
 use std::io;
+use std::time::Duration;
 
-use http::{StreamId, HttpError, Response, StaticResponse, Header, HttpResult, StaticHeader};
+use http::{
+    StreamId,
+    ConnectionId,
+    HttpError,
+    ErrorCode,
+    Response,
+    ResponseHead,
+    StaticResponse,
+    Header,
+    HttpResult,
+    StaticHeader,
+    PrefaceMismatch,
+};
 use http::frame::{RawFrame, FrameIR};
-use http::transport::TransportStream;
-use http::connection::{SendFrame, ReceiveFrame, HttpFrame, HttpConnection};
+use http::transport::{TransportStream, read_frame_header, read_preface_frame_header};
+use http::transport::check_frame_size;
+use http::connection::{SendFrame, ReceiveFrame, HttpFrame, HttpConnection, ConnectionActivity};
+use http::tracing::{self, Span, Role, TraceContext};
 use http::session::{
     SessionState,
     DefaultSessionState,
@@ -22,6 +39,8 @@ use http::session::{
 };
 use http::session::Client as ClientMarker;
 use http::client::{ClientConnection, HttpConnect, ClientStream, RequestStream};
+use http::config::Http2Config;
+use client::{RetryPolicy, NoRetry, Multipart};
 
 /// A struct representing an asynchronously dispatched request. It is used
 /// internally be the `ClientService` and `Client` structs.
@@ -30,16 +49,118 @@ struct AsyncRequest {
     pub method: Vec<u8>,
     /// The path being requested
     pub path: Vec<u8>,
+    /// The authority of the request, if different from the host that the client is connected to.
+    pub authority: Option<Vec<u8>>,
     /// Extra headers that should be included in the request. Does *not*
     /// include meta-headers.
     pub headers: Vec<StaticHeader>,
     /// The body of the request, if any.
     pub body: Option<Vec<u8>>,
+    /// The ID of the upload that the rest of the body is to arrive with, while the body is
+    /// still being streamed (see `Client::request_streaming`).
+    upload: Option<usize>,
     /// The sender side of a channel where the response to this request should
     /// be delivered.
-    tx: Sender<StaticResponse>,
+    tx: ResponseSender,
+    /// The ID of a request issued with `Client::request_tracked`, along with the channel that
+    /// the ID is sent to once the response headers arrive.
+    head: Option<(RequestId, Sender<RequestId>)>,
+}
+
+impl AsyncRequest {
+    /// Creates a new request, whose response is to be delivered to the given channel.
+    fn new(authority: Option<Vec<u8>>,
+           method: &[u8],
+           path: &[u8],
+           headers: &[StaticHeader],
+           body: Option<Vec<u8>>,
+           tx: ResponseSender)
+            -> AsyncRequest {
+        AsyncRequest {
+            method: method.to_vec(),
+            path: path.to_vec(),
+            authority: authority,
+            headers: headers.to_vec(),
+            body: body,
+            upload: None,
+            tx: tx,
+            head: None,
+        }
+    }
+
+    /// Returns the ID of the request, if it was issued with `Client::request_tracked`.
+    fn id(&self) -> Option<RequestId> {
+        self.head.as_ref().map(|&(id, _)| id)
+    }
 }
 
+/// The sender side of the channel that the response to a request is delivered to.
+#[derive(Clone)]
+enum ResponseSender {
+    /// Only the response is delivered; the channel of a request that fails is dropped.
+    Response(Sender<StaticResponse>),
+    /// The result of the request is delivered, including the error of a request that fails (see
+    /// `Client::request_checked`).
+    Result(Sender<HttpResult<StaticResponse>>),
+}
+
+impl ResponseSender {
+    /// Delivers the response. The client may have stopped waiting for it; that's fine.
+    fn send(&self, response: StaticResponse) {
+        let _ = match *self {
+            ResponseSender::Response(ref tx) => tx.send(response).map_err(|_| ()),
+            ResponseSender::Result(ref tx) => tx.send(Ok(response)).map_err(|_| ()),
+        };
+    }
+
+    /// Delivers the error with which the request failed, if the channel takes errors.
+    fn fail(&self, err: HttpError) {
+        if let ResponseSender::Result(ref tx) = *self {
+            let _ = tx.send(Err(err));
+        }
+    }
+}
+
+/// The IDs of the uploads of all clients, so that they never clash.
+static NEXT_UPLOAD: AtomicUsize = AtomicUsize::new(0);
+
+/// The IDs of the tracked requests of all clients, so that they never clash.
+static NEXT_REQUEST: AtomicUsize = AtomicUsize::new(0);
+
+/// Identifies a request issued with `Client::request_tracked`, so that it can be cancelled.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct RequestId(usize);
+
+/// A request that the server refused to handle over HTTP/2, asking for HTTP/1.1 instead (with the
+/// HTTP_1_1_REQUIRED error code). It is handed to the fallback of a `Client` (see
+/// `Client::set_http11_fallback`), so that the application can retry it with an HTTP/1.1 stack.
+pub struct FallbackRequest {
+    pub method: Vec<u8>,
+    pub path: Vec<u8>,
+    /// The authority of the request (the host that the client is connected to, unless the
+    /// request was issued with another one).
+    pub authority: Vec<u8>,
+    /// The extra headers of the request, without the pseudo-headers.
+    pub headers: Vec<StaticHeader>,
+    pub body: Option<Vec<u8>>,
+    /// The channel on which the client that issued the request waits for the response.
+    tx: ResponseSender,
+}
+
+impl FallbackRequest {
+    /// Delivers the response obtained over HTTP/1.1 to the client that issued the request, as if
+    /// it had been received on the HTTP/2 connection.
+    ///
+    /// Dropping the `FallbackRequest` without responding makes the client observe a
+    /// disconnected channel, as for any other request that fails.
+    pub fn respond(self, response: StaticResponse) {
+        self.tx.send(response);
+    }
+}
+
+/// The callback invoked with the requests that need to be retried over HTTP/1.1.
+type Http11Fallback = Box<FnMut(FallbackRequest) + Send>;
+
 /// A struct that buffers `RawFrame`s in an internal `mpsc` channel and sends them using the
 /// wrapped `SendFrame` instance when the `send_next` method is called.
 ///
@@ -132,39 +253,65 @@ impl SendFrame for ChannelFrameSenderHandle {
 struct ChannelFrameReceiver<TS> where TS: TransportStream {
     /// The sender side of the channel. Buffers the frames read by the wrapped `ReceiveFrame`
     /// instance for future consumation by the associated `ChannelFrameReceiverHandle`.
-    tx: Sender<RawFrame<'static>>,
+    /// A frame that is refused (see `read_next`) is buffered as the error that it causes.
+    tx: Sender<HttpResult<RawFrame<'static>>>,
     /// The `ReceiveFrame` instance that performs the actual reading of the frame, used from within
     /// the `read_next` method.
     inner: TS,
+    /// Whether the first frame (i.e. the server's preface) was already read.
+    preface_read: bool,
+    /// The largest frame payload that the server may send, as advertised by the client.
+    max_frame_size: u32,
 }
 
 use http::frame::unpack_header;
 impl<TS> ChannelFrameReceiver<TS> where TS: TransportStream {
     /// Creates a new `ChannelFrameReceiver`, as well as the associated
     /// `ChannelFrameReceiverHandle`.
-    fn new(inner: TS) -> (ChannelFrameReceiver<TS>, ChannelFrameReceiverHandle) {
+    fn new(inner: TS, max_frame_size: u32)
+            -> (ChannelFrameReceiver<TS>, ChannelFrameReceiverHandle) {
         let (send, recv) = mpsc::channel();
 
         let handle = ChannelFrameReceiverHandle { rx: recv, raw: None };
         let receiver = ChannelFrameReceiver {
             tx: send,
             inner: inner,
+            preface_read: false,
+            max_frame_size: max_frame_size,
         };
         (receiver, handle)
     }
 
+    /// Buffers the given error in place of the next frame, so that the connection fails with it
+    /// once it gets to it.
+    fn fail(&self, err: HttpError) {
+        let _ = self.tx.send(Err(err));
+    }
+
     /// Performs a `recv_frame` operation on the wrapped `ReceiveFrame` instance, possibly blocking
     /// the thread in the process, depending on the implementation of the trait. Once a frame is
     /// returned, it will buffer it within the internal channel.
+    ///
+    /// A frame that is larger than the maximum frame size is not read at all. The
+    /// FRAME_SIZE_ERROR that it causes is buffered in its stead, so that the connection fails
+    /// (with a GOAWAY) once it gets to it, and is also returned, as no more frames can be read.
     fn read_next(&mut self) -> HttpResult<()> {
-        let mut header = [0; 9];
-        try!(TransportStream::read_exact(&mut self.inner, &mut header));
+        let header = if self.preface_read {
+            try!(read_frame_header(&mut self.inner))
+        } else {
+            try!(read_preface_frame_header(&mut self.inner))
+        };
+        self.preface_read = true;
+        if let Err(e) = check_frame_size(&header, self.max_frame_size) {
+            let _ = self.tx.send(Err(e));
+            return Err(HttpError::LocalConnectionError(ErrorCode::FrameSizeError));
+        }
         let total_len = unpack_header(&header).0 as usize;
         let mut buf = Vec::with_capacity(9 + total_len);
         unsafe { buf.set_len(9 + total_len); }
         try!(io::copy(&mut &header[..], &mut &mut buf[..9]));
         try!(TransportStream::read_exact(&mut self.inner, &mut buf[9..]));
-        try!(self.tx.send(buf.into())
+        try!(self.tx.send(Ok(buf.into()))
                     .map_err(|_| {
                         io::Error::new(io::ErrorKind::Other, "Unable to read frame")
                     }));
@@ -181,16 +328,16 @@ impl<TS> ChannelFrameReceiver<TS> where TS: TransportStream {
 /// handles are to be avoided.
 struct ChannelFrameReceiverHandle {
     /// The receiver end of the channel that buffers the received frames.
-    rx: Receiver<RawFrame<'static>>,
+    rx: Receiver<HttpResult<RawFrame<'static>>>,
     raw: Option<RawFrame<'static>>,
 }
 
 impl ReceiveFrame for ChannelFrameReceiverHandle {
     fn recv_frame(&mut self) -> HttpResult<HttpFrame> {
-        let raw = try!(self.rx.recv()
+        let raw = try!(try!(self.rx.recv()
             .map_err(|_| {
                 HttpError::from(io::Error::new(io::ErrorKind::Other, "Unable to read frame"))
-            }));
+            })));
         // Tethers the lifetime of the returned parsed HttpFrame to the lifetime of `self` (i.e.
         // the provider of the frame).
         self.raw = Some(raw);
@@ -217,17 +364,111 @@ impl From<HttpError> for ClientServiceErr {
 enum WorkItem {
     /// Queue a new request to the HTTP/2 connection.
     Request(AsyncRequest),
-    /// Trigger a new `handle_next_frame`. The work item should be queued only when there is a
-    /// frame to be handled to avoid blocking the `run_once` call.
-    HandleFrame,
-    /// Trigger a new `send_next_data` operation.
-    SendData,
+    /// Trigger a new `handle_next_frame` on the connection of the given generation. The work item
+    /// should be queued only when there is a frame to be handled to avoid blocking the `run_once`
+    /// call.
+    HandleFrame(u32),
+    /// Trigger a new `send_next_data` operation on the connection of the given generation.
+    SendData(u32),
+    /// Signals that the connection of the given generation can no longer be read from (or
+    /// written to), as the socket was closed or failed.
+    ConnectionLost(u32),
     /// Signals to the service that a new client is connected. Helps it keep track of whether there
     /// are clients that would expect a response.
     NewClient,
     /// Signals to the service that a client has disconnected. Helps it keep track of whether there
     /// are clients that would expect a response.
     ClientLeft,
+    /// Requests a snapshot of the service's `ClientStats`, which is sent to the given channel.
+    Stats(Sender<ClientStats>),
+    /// Asks the service to signal the given channel once the connection is initialized (i.e. the
+    /// server's SETTINGS are received and acknowledged).
+    AwaitReady(Sender<HttpResult<()>>),
+    /// Sets the callback that is handed the requests that the server wants made over HTTP/1.1.
+    SetHttp11Fallback(Http11Fallback),
+    /// Turns the propagation of trace contexts on or off.
+    SetTracePropagation(bool),
+    /// Carries the next chunk of the body of the request with the given upload ID, or its end.
+    RequestData(usize, Option<Vec<u8>>),
+    /// Cancels the tracked request with the given ID.
+    Cancel(RequestId),
+    /// Sets the policy that decides whether establishing a new connection is retried.
+    SetRetryPolicy(Arc<RetryPolicy>),
+}
+
+/// A snapshot of the load on the connection used by a `Client`, as returned by `Client::stats`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ClientStats {
+    /// The number of requests that were sent, but whose responses have not yet been fully
+    /// received.
+    pub in_flight: u32,
+    /// The number of requests that are queued, but not yet sent, as too many requests are already
+    /// in flight.
+    pub queued: usize,
+    /// The maximum number of requests that the client keeps in flight at a time.
+    pub limit: u32,
+    /// The maximum number of concurrent streams that the server allows, if it announced a limit
+    /// in its SETTINGS.
+    pub peer_max_concurrent_streams: Option<u32>,
+    /// The number of times that the connection was replaced by a new one (see
+    /// `Client::with_reconnect`).
+    pub reconnects: u32,
+    /// When the current connection was established and when it last received and sent a frame.
+    pub activity: ConnectionActivity,
+    /// The ID of the current connection, which changes whenever the connection is replaced.
+    pub connection_id: ConnectionId,
+}
+
+impl ClientStats {
+    /// Returns the number of requests that the client can have in flight at a time: its own
+    /// limit, unless the server allows fewer concurrent streams.
+    pub fn max_in_flight(&self) -> u32 {
+        max_in_flight(self.limit, self.peer_max_concurrent_streams)
+    }
+
+    /// Returns whether the client cannot send another request right away, i.e. whether a new
+    /// request would have to be queued.
+    pub fn is_saturated(&self) -> bool {
+        self.in_flight as u64 + self.queued as u64 >= self.max_in_flight() as u64
+    }
+
+    /// Returns the requests in flight and queued, relative to the number of requests that the
+    /// client can have in flight, e.g. 0.5 for a client that is half way to being saturated.
+    pub fn load(&self) -> f64 {
+        let requests = self.in_flight as f64 + self.queued as f64;
+        match self.max_in_flight() {
+            0 => ::std::f64::INFINITY,
+            max => requests / max as f64,
+        }
+    }
+}
+
+/// Returns the number of requests that a client with the given limit can have in flight at a time,
+/// given the maximum number of concurrent streams that the server allows, if it announced one.
+fn max_in_flight(limit: u32, peer_max_concurrent_streams: Option<u32>) -> u32 {
+    match peer_max_concurrent_streams {
+        Some(peer_max) if peer_max < limit => peer_max,
+        _ => limit,
+    }
+}
+
+/// Establishes a new connection for a `ClientService`, given the generation that the connection
+/// is to have.
+type Reconnect = Box<FnMut(u32) -> HttpResult<Connection> + Send>;
+
+/// The parts of a `ClientService` that belong to a single underlying HTTP/2 connection, which
+/// are replaced as a whole when the service reconnects.
+struct Connection {
+    /// The HTTP/2 connection.
+    conn: ClientConnection,
+    /// The handle to the frames read from the socket by the connection's reader thread.
+    recv_handle: ChannelFrameReceiverHandle,
+    /// The handle to the queue of frames written to the socket by the connection's writer thread.
+    send_handle: ChannelFrameSenderHandle,
+    /// The name of the host the connection is established to.
+    host: Vec<u8>,
+    /// Shuts the socket down, which also stops the connection's IO threads.
+    close: Box<FnMut() + Send>,
 }
 
 /// An internal struct encapsulating a service that lets multiple clients
@@ -249,10 +490,22 @@ enum WorkItem {
 /// user of the `ClientService` needs to provide a dedicated thread in which to run the `run_once`
 /// event loop handler.
 ///
-/// Additionally, the client needs to make sure to perform the actual socket IO (which is fully
-/// blocking, without even timeout support currently in Rust) in threads dedicated for that, by
-/// calling the `send_next` or `read_next` methods of the `ChannelFrameSender` or
-/// `ChannelFrameReceiver`, which are returned from the `ClientService` constructor.
+/// The actual socket IO (which is fully blocking, without even timeout support currently in Rust)
+/// is performed by threads dedicated to it, which `open_connection` spawns along with every
+/// connection. They notify the service of their progress through its work queue, tagging the
+/// work items with the generation of their connection, so that the items of a connection that
+/// was since replaced are ignored.
+///
+/// A service that is given a way to reconnect replaces its connection when it fails or is closed
+/// by the server (e.g. with a GOAWAY). The requests that are yet to be sent are kept in a queue
+/// that does not depend on the connection (the headers are only encoded once a request is sent),
+/// so they carry over to the new connection; the ones that were in flight fail, as there is no
+/// telling whether the server processed them.
+///
+/// Such a service also rotates connections that are older than the configured maximum age: no
+/// more requests are sent on an expired connection, and once the ones in flight complete, the
+/// connection is closed with a GOAWAY and replaced by a new one, which the requests queued in the
+/// meantime go out on.
 ///
 /// TODO: Technically, the `run_once` method could take a `WorkItem`, so a single event loop could
 ///       dispatch work items to a corresponding service, removing the need for the
@@ -268,6 +521,16 @@ struct ClientService {
     limit: u32,
     /// The connection that is used for underlying HTTP/2 communication.
     conn: ClientConnection,
+    /// The generation of the connection, i.e. the number of connections used before it.
+    generation: u32,
+    /// Shuts the socket of the current connection down.
+    close_socket: Box<FnMut() + Send>,
+    /// Establishes a new connection when the current one fails, if the service reconnects.
+    reconnect: Option<Reconnect>,
+    /// The number of times that the connection was replaced.
+    reconnects: u32,
+    /// How long a connection is used before it is replaced, if the service reconnects.
+    max_connection_age: Option<Duration>,
     /// The handle allows the service to get the HTTP/2 frame that has been extracted from the data
     /// read from the socket on another thread.
     recv_handle: ChannelFrameReceiverHandle,
@@ -276,7 +539,7 @@ struct ClientService {
     send_handle: ChannelFrameSenderHandle,
     /// A mapping of stream IDs to the sender side of a channel that is
     /// expecting a response to the request that is to arrive on that stream.
-    chans: HashMap<StreamId, Sender<StaticResponse>>,
+    chans: HashMap<StreamId, ResponseSender>,
     /// The receiver end of a channel to which work items for the service are
     /// queued. Work items include the variants of the `WorkItem` enum.
     work_queue: Receiver<WorkItem>,
@@ -289,75 +552,69 @@ struct ClientService {
     host: Vec<u8>,
     /// Whether the connection has already been initialized.
     initialized: bool,
+    /// The channels that are waiting for the connection to be initialized.
+    ready_waiters: Vec<Sender<HttpResult<()>>>,
+    /// Whether the connection was lost, with the service not reconnecting.
+    connection_lost: bool,
+    /// What the server sent instead of the HTTP/2 preface, once it turned out not to speak
+    /// HTTP/2.
+    not_http2: Option<PrefaceMismatch>,
+    /// The callback that retries requests over HTTP/1.1, if the client set one.
+    http11_fallback: Option<Http11Fallback>,
+    /// The requests in flight, kept (only while there is a fallback) so that they can be handed
+    /// to the fallback.
+    fallback_requests: HashMap<StreamId, FallbackRequest>,
+    /// The streams of the sent requests whose bodies are still being streamed, by upload ID.
+    uploads: HashMap<usize, StreamId>,
+    /// The spans of the requests in flight, while they are being traced (see `http::tracing`).
+    spans: HashMap<StreamId, Span>,
+    /// Whether the requests carry a trace context (see `Client::set_trace_propagation`).
+    propagate_traces: bool,
+    /// The streams of the tracked requests in flight, by request ID.
+    tracked: HashMap<RequestId, StreamId>,
+    /// The channels that are waiting for the response headers of tracked requests in flight,
+    /// along with the IDs that they are to be sent.
+    head_waiters: HashMap<StreamId, (RequestId, Sender<RequestId>)>,
+    /// Decides whether a new connection that could not be established is retried.
+    retry_policy: Arc<RetryPolicy>,
 }
 
-/// A helper wrapper around the components of the `ClientService` that are returned from its
-/// constructor.
-struct Service<S>(
-    ClientService,
-    Sender<WorkItem>,
-    ChannelFrameReceiver<S>,
-    ChannelFrameSender<S>) where S: TransportStream;
-
 impl ClientService {
-    /// Creates a new `ClientService` that will use the provided `ClientStream` for its underlying
-    /// network communication. A handle is returned for both the read, as well as the write end of
-    /// the socket that allows the client that creates the `ClientService` to perform the blocking
-    /// IO without influencing the `ClientService` (i.e. without having its `run_once` method
-    /// block).
-    ///
-    /// # Returns
-    ///
-    /// Returns all the relevant components of the newly created `ClientService`:
-    ///
-    /// - The `ClientService` itself -- processes events (`WorkItem`s) on each `run_once` call.
-    /// - The sender-side of the work queue -- allows `WorkItem`s to be queued into the
-    ///   `ClientService`'s simplified event loop.
-    /// - The `ChannelFrameReceiver` -- the instance that wraps the actual socket that performs
-    ///   the blocking read IO. Allows the caller to block on the IO in a customized manner (e.g.
-    ///   in a separate dedicated thread).
-    /// - The `ChannelFrameSender` -- the instance that wraps the actual socket that performs the
-    ///   blocking write IO. Allows the caller to block on the IO in a customized manner (e.g. in
-    ///   a separate thread).
-    ///
-    /// If no HTTP/2 connection can be established to the given host on the
-    /// given port, returns `None`.
-    pub fn new<S>(client_stream: ClientStream<S>) -> Option<Service<S>>
-            where S: TransportStream {
-        let (tx, rx): (Sender<WorkItem>, Receiver<WorkItem>) =
-                mpsc::channel();
-        let ClientStream(stream, scheme, host) = client_stream;
-
-        // Manually split the stream into the write/read ends, so that we can...
-        let sender = stream.try_split().unwrap();
-        let receiver = stream;
-        // ...wrap them into the adapters...
-        let (recv_frame, recv_handle) = ChannelFrameReceiver::new(receiver);
-        let (send_frame, send_handle) = ChannelFrameSender::new(sender);
-
-        // ...and pass the non-blocking/buffering ends into the `HttpConnect` instead of the
-        // blocking socket itself.
-        let conn = ClientConnection::with_connection(
-                HttpConnection::new(scheme),
-                DefaultSessionState::<ClientMarker, _>::new());
-
-        let service = ClientService {
+    /// Creates a new `ClientService` that uses the given connection (whose IO threads report to
+    /// the sender side of the given work queue) and, when given a way to do so, reconnects once
+    /// the connection fails.
+    fn new(connection: Connection, work_queue: Receiver<WorkItem>, reconnect: Option<Reconnect>)
+            -> ClientService {
+        let Connection { conn, recv_handle, send_handle, host, close } = connection;
+        ClientService {
             outstanding_reqs: 0,
             limit: 3,
             conn: conn,
+            generation: 0,
+            close_socket: close,
+            reconnect: reconnect,
+            reconnects: 0,
+            max_connection_age: None,
             chans: HashMap::new(),
-            work_queue: rx,
+            work_queue: work_queue,
             recv_handle: recv_handle,
             send_handle: send_handle,
             request_queue: Vec::new(),
             client_count: 0,
-            host: host.as_bytes().to_vec(),
+            host: host,
             initialized: false,
-        };
-
-        // Returns the handles to the channel sender/receiver, so that the client can use them to
-        // perform the real IO somewhere.
-        Some(Service(service, tx, recv_frame, send_frame))
+            ready_waiters: Vec::new(),
+            connection_lost: false,
+            not_http2: None,
+            http11_fallback: None,
+            fallback_requests: HashMap::new(),
+            uploads: HashMap::new(),
+            spans: HashMap::new(),
+            propagate_traces: false,
+            tracked: HashMap::new(),
+            head_waiters: HashMap::new(),
+            retry_policy: Arc::new(NoRetry),
+        }
     }
 
     /// Performs one iteration of the service.
@@ -391,7 +648,7 @@ impl ClientService {
     /// corresponds to the `ClientServiceErr::Done` variant.
     ///
     /// Any HTTP/2 error is propagated (wrapped into a ClientServiceErr::Http
-    /// variant).
+    /// variant), unless the service is able to replace the failed connection.
     pub fn run_once(&mut self) -> Result<(), ClientServiceErr> {
         let work_item = match self.work_queue.recv() {
             Ok(item) => item,
@@ -401,28 +658,77 @@ impl ClientService {
             Err(_) => return Err(ClientServiceErr::Done),
         };
 
+        match self.handle_work(work_item) {
+            Err(ClientServiceErr::Http(err)) => self.replace_connection(err),
+            res => res,
+        }
+    }
+
+    /// Performs the given work item.
+    fn handle_work(&mut self, work_item: WorkItem) -> Result<(), ClientServiceErr> {
         // Dispatch the work to the corresponding method...
         match work_item {
             WorkItem::Request(async_req) => {
+                if let Some(ref mismatch) = self.not_http2 {
+                    async_req.tx.fail(HttpError::NotHttp2(mismatch.clone()));
+                    return Ok(());
+                }
                 debug!("Queuing request");
                 self.request_queue.push(async_req);
-                self.queue_next_request();
+                try!(self.queue_next_request());
+                Ok(())
+            },
+            WorkItem::HandleFrame(generation) | WorkItem::SendData(generation) |
+            WorkItem::ConnectionLost(generation) if generation != self.generation => {
+                trace!("Ignoring work for the replaced connection {}", generation);
+                Ok(())
+            },
+            WorkItem::HandleFrame(_) | WorkItem::SendData(_) | WorkItem::ConnectionLost(_)
+                    if self.not_http2.is_some() => {
+                trace!("Ignoring work for the connection to a server that is not an HTTP/2 one");
                 Ok(())
             },
-            WorkItem::HandleFrame => {
+            WorkItem::HandleFrame(_) => {
                 if !self.initialized {
-                    try!(self.conn.expect_settings(&mut self.recv_handle, &mut self.send_handle));
+                    match self.conn.expect_settings(&mut self.recv_handle, &mut self.send_handle) {
+                        Ok(()) => {},
+                        Err(HttpError::NotHttp2(mismatch)) => {
+                            // There is nothing to reconnect to: the service keeps going until all
+                            // clients leave, failing everything that is still to come the same way.
+                            self.fail_not_http2(&mismatch);
+                            self.not_http2 = Some(mismatch);
+                            (self.close_socket)();
+                            return Ok(());
+                        },
+                        Err(e) => return Err(e.into()),
+                    }
                     self.initialized = true;
+                    for tx in self.ready_waiters.drain(..) {
+                        let _ = tx.send(Ok(()));
+                    }
                     Ok(())
                 } else {
                     self.handle_frame()
                 }
             },
-            WorkItem::SendData => {
+            WorkItem::SendData(_) => {
                 debug!("Will queue some request data");
                 try!(self.conn.send_next_data(&mut self.send_handle));
                 Ok(())
-            }
+            },
+            WorkItem::ConnectionLost(_) => {
+                debug!("The connection is lost");
+                if self.reconnect.is_some() {
+                    Err(HttpError::PeerClosed { graceful: false }.into())
+                } else {
+                    // Without reconnecting, the service keeps going until all clients leave, at
+                    // which point they have seen their requests fail. Those waiting for the
+                    // connection to be initialized fail right away.
+                    self.connection_lost = true;
+                    self.ready_waiters.clear();
+                    Ok(())
+                }
+            },
             WorkItem::NewClient => {
                 self.client_count += 1;
                 Ok(())
@@ -434,10 +740,184 @@ impl ClientService {
                 } else {
                     Ok(())
                 }
+            },
+            WorkItem::AwaitReady(tx) => {
+                if self.initialized {
+                    let _ = tx.send(Ok(()));
+                } else if let Some(ref mismatch) = self.not_http2 {
+                    let _ = tx.send(Err(HttpError::NotHttp2(mismatch.clone())));
+                } else if !self.connection_lost {
+                    self.ready_waiters.push(tx);
+                }
+                Ok(())
+            },
+            WorkItem::Stats(tx) => {
+                // The client may have stopped waiting for the stats; that's fine.
+                let _ = tx.send(self.stats());
+                Ok(())
+            },
+            WorkItem::SetHttp11Fallback(fallback) => {
+                self.http11_fallback = Some(fallback);
+                Ok(())
+            },
+            WorkItem::SetTracePropagation(enabled) => {
+                self.propagate_traces = enabled;
+                Ok(())
+            },
+            WorkItem::RequestData(upload, chunk) => {
+                try!(self.handle_upload(upload, chunk));
+                Ok(())
+            },
+            WorkItem::Cancel(id) => {
+                try!(self.cancel(id));
+                Ok(())
+            },
+            WorkItem::SetRetryPolicy(policy) => {
+                self.retry_policy = policy;
+                Ok(())
+            },
+        }
+    }
+
+    /// Returns the number of requests that may be in flight at a time: the limit of the service,
+    /// unless the server allows fewer concurrent streams.
+    fn max_in_flight(&self) -> u32 {
+        max_in_flight(self.limit, self.conn.peer_max_concurrent_streams())
+    }
+
+    /// Returns a snapshot of the service's current load.
+    fn stats(&self) -> ClientStats {
+        ClientStats {
+            in_flight: self.outstanding_reqs,
+            queued: self.request_queue.len(),
+            limit: self.limit,
+            peer_max_concurrent_streams: self.conn.peer_max_concurrent_streams(),
+            reconnects: self.reconnects,
+            activity: self.conn.activity(),
+            connection_id: self.conn.id(),
+        }
+    }
+
+    /// Replaces the connection, which failed with the given error, with a new one, if the service
+    /// reconnects. Otherwise, the error is returned, stopping the service.
+    ///
+    /// Only connections that got initialized are replaced, so that a server which keeps failing
+    /// the connections right away does not keep the service reconnecting in a loop. Neither is a
+    /// connection on which the server requires HTTP/1.1.
+    fn replace_connection(&mut self, err: HttpError) -> Result<(), ClientServiceErr> {
+        let replace = match err {
+            HttpError::Http11Required => false,
+            _ => self.reconnect.is_some() && self.initialized,
+        };
+        if !replace {
+            return Err(err.into());
+        }
+        debug!("Replacing the connection {} after an error: {}", self.conn.id(), err);
+        // The clients waiting for the requests in flight see their channels disconnect, while the
+        // queued requests stay queued for the new connection.
+        self.chans.clear();
+        for (_, span) in self.spans.drain() {
+            span.finish();
+        }
+        self.fallback_requests.clear();
+        self.uploads.clear();
+        self.tracked.clear();
+        self.head_waiters.clear();
+        self.outstanding_reqs = 0;
+        (self.close_socket)();
+
+        try!(self.reconnect());
+        Ok(())
+    }
+
+    /// Returns whether the connection is past its maximum age, so that it needs to be rotated.
+    fn is_expired(&self) -> bool {
+        let max_age = match self.max_connection_age {
+            Some(max_age) if self.reconnect.is_some() && self.initialized => max_age,
+            _ => return false,
+        };
+        self.conn.activity().age() >= max_age
+    }
+
+    /// Gracefully replaces the expired connection, once none of its requests are in flight any
+    /// more: a GOAWAY is sent on it and its socket is shut down as soon as the frames queued
+    /// before it are written out.
+    fn rotate_connection(&mut self) -> HttpResult<()> {
+        if self.outstanding_reqs > 0 {
+            debug!("Draining the expired connection {}: {} requests in flight",
+                   self.conn.id(), self.outstanding_reqs);
+            return Ok(());
+        }
+        debug!("Rotating the connection {} after {:?}", self.conn.id(), self.conn.activity().age());
+        try!(self.conn.send_goaway(ErrorCode::NoError, &mut self.send_handle));
+        self.reconnect()
+    }
+
+    /// Opens a new connection in place of the current one (which is expected to be shut down
+    /// already, or to shut down once its sender handle is dropped) and sends as many of the
+    /// queued requests on it as the limit allows.
+    fn reconnect(&mut self) -> HttpResult<()> {
+        self.generation += 1;
+        let connection = try!(self.open_new_connection());
+        let Connection { conn, recv_handle, send_handle, host, close } = connection;
+        self.conn = conn;
+        self.recv_handle = recv_handle;
+        self.send_handle = send_handle;
+        self.host = host;
+        self.close_socket = close;
+        self.initialized = false;
+        self.reconnects += 1;
+        debug!("Reconnected with the connection {}", self.conn.id());
+
+        while self.outstanding_reqs < self.max_in_flight() && !self.request_queue.is_empty() {
+            try!(self.queue_next_request());
+        }
+        Ok(())
+    }
+
+    /// Establishes a new connection of the current generation, making as many attempts as the
+    /// retry policy allows. The error of the last attempt is returned if none of them succeed.
+    fn open_new_connection(&mut self) -> HttpResult<Connection> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let err = {
+                let reconnect = self.reconnect.as_mut().expect("The service reconnects");
+                match reconnect(self.generation) {
+                    Ok(connection) => return Ok(connection),
+                    Err(err) => err,
+                }
+            };
+            match self.retry_policy.should_retry(&err, attempt) {
+                Some(wait) => {
+                    debug!("Failed to reconnect ({}); retrying in {:?}", err, wait);
+                    thread::sleep(wait);
+                },
+                None => return Err(err),
             }
         }
     }
 
+    /// Shuts the socket of the current connection down, which stops its IO threads.
+    fn close(&mut self) {
+        (self.close_socket)();
+    }
+
+    /// Fails everyone waiting on the connection, which turned out not to be an HTTP/2 one: the
+    /// clients waiting for it to be initialized and the requests, whether queued or sent (as far
+    /// as their channels take errors), all get the bytes that the server sent instead.
+    fn fail_not_http2(&mut self, mismatch: &PrefaceMismatch) {
+        for tx in self.ready_waiters.drain(..) {
+            let _ = tx.send(Err(HttpError::NotHttp2(mismatch.clone())));
+        }
+        for (_, tx) in self.chans.drain() {
+            tx.fail(HttpError::NotHttp2(mismatch.clone()));
+        }
+        for req in self.request_queue.drain(..) {
+            req.tx.fail(HttpError::NotHttp2(mismatch.clone()));
+        }
+    }
+
     /// A private convenience method that performs the handling of the next received frame.
     ///
     /// It calls the underlying connection's `handle_next_frame` method and then inspects the
@@ -446,14 +926,23 @@ impl ClientService {
     fn handle_frame(&mut self) -> Result<(), ClientServiceErr> {
         // Handles the next frame...
         debug!("Handling next frame");
-        try!(self.conn.handle_next_frame(&mut self.recv_handle, &mut self.send_handle));
+        match self.conn.handle_next_frame(&mut self.recv_handle, &mut self.send_handle) {
+            Ok(()) => {},
+            Err(HttpError::Http11Required) => {
+                // The connection is going away, so none of the requests can be made over it.
+                self.fall_back_all();
+                return Err(ClientServiceErr::Http(HttpError::Http11Required));
+            },
+            Err(e) => return Err(e.into()),
+        }
+        self.notify_heads();
         // ...and then any connections that may have been closed in the meantime
         // are converted to responses and notifications sent to appropriate
         // channels.
         self.handle_closed();
         // At this point we try to queue another outstanding request (if the
         // limit has not been reached).
-        self.queue_next_request();
+        try!(self.queue_next_request());
 
         Ok(())
     }
@@ -461,19 +950,93 @@ impl ClientService {
     /// Internal helper method. Sends a request to the server based on the
     /// parameters given in the `AsyncRequest`. It blocks until the request is
     /// fully transmitted to the server.
-    fn send_request(&mut self, async_req: AsyncRequest) {
+    ///
+    /// Any error raised while sending the request is propagated.
+    fn send_request(&mut self, mut async_req: AsyncRequest) -> HttpResult<()> {
+        let trace = if self.propagate_traces {
+            let trace = TraceContext::from_headers(&async_req.headers)
+                                     .map(|parent| parent.child())
+                                     .unwrap_or_else(TraceContext::new);
+            trace.apply_to(&mut async_req.headers);
+            Some(trace)
+        } else {
+            None
+        };
+        // A request whose body is still being streamed cannot be retried with all of it.
+        let fallback_request = match self.http11_fallback {
+            Some(_) if async_req.upload.is_none() => Some(self.fallback_request(&async_req)),
+            _ => None,
+        };
+        let upload = async_req.upload;
+        let head = async_req.head.take();
+        let span = if tracing::is_enabled() {
+            let mut span = Span::stream(Role::Client, self.conn.id(), 0);
+            span.method = Some(async_req.method.clone());
+            span.path = Some(async_req.path.clone());
+            span.bytes_sent = async_req.body.as_ref().map_or(0, |body| body.len() as u64);
+            span.trace = trace;
+            Some(span)
+        } else {
+            None
+        };
         let (req, tx) = self.create_request(async_req);
 
         trace!("Sending new request...");
 
-        let stream_id = self.conn.start_request(req, &mut self.send_handle).ok().unwrap();
+        let stream_id = try!(self.conn.start_request(req, &mut self.send_handle));
         // The ID has been assigned to the stream, so attach it to the stream instance too.
         // TODO(mlalic): The `Stream` trait should grow an `on_id_assigned` method which can
         //               then be called by the session (i.e. the `ClientConnection` in this case).
-        self.conn.state.get_stream_mut(stream_id).unwrap().stream_id = Some(stream_id);
+        let stream = try!(self.conn.state.get_stream_mut(stream_id)
+                                         .ok_or(HttpError::UnknownStreamId));
+        stream.stream_id = Some(stream_id);
 
         self.chans.insert(stream_id, tx);
+        if let Some(mut span) = span {
+            span.stream_id = Some(stream_id);
+            self.spans.insert(stream_id, span);
+        }
+        if let Some(upload) = upload {
+            self.uploads.insert(upload, stream_id);
+        }
+        if let Some((id, head_tx)) = head {
+            self.tracked.insert(id, stream_id);
+            self.head_waiters.insert(stream_id, (id, head_tx));
+        }
+        if let Some(fallback_request) = fallback_request {
+            self.fallback_requests.insert(stream_id, fallback_request);
+        }
         self.outstanding_reqs += 1;
+
+        Ok(())
+    }
+
+    /// Returns the `FallbackRequest` that retries the given request over HTTP/1.1.
+    fn fallback_request(&self, async_req: &AsyncRequest) -> FallbackRequest {
+        FallbackRequest {
+            method: async_req.method.clone(),
+            path: async_req.path.clone(),
+            authority: async_req.authority.clone().unwrap_or_else(|| self.host.clone()),
+            headers: async_req.headers.clone(),
+            body: async_req.body.clone(),
+            tx: async_req.tx.clone(),
+        }
+    }
+
+    /// Hands all the requests, both the ones in flight and the queued ones, to the fallback (if
+    /// there is one), as the server requires HTTP/1.1 for the whole connection.
+    fn fall_back_all(&mut self) {
+        let queued: Vec<_> = self.request_queue.drain(..).collect();
+        let queued: Vec<_> = queued.iter().filter(|req| req.upload.is_none())
+                                          .map(|req| self.fallback_request(req))
+                                          .collect();
+        let in_flight: Vec<_> = self.fallback_requests.drain().map(|(_, req)| req).collect();
+        if let Some(ref mut fallback) = self.http11_fallback {
+            for request in in_flight.into_iter().chain(queued.into_iter()) {
+                debug!("Falling back to HTTP/1.1 for {:?}", request.path);
+                fallback(request);
+            }
+        }
     }
 
     /// Internal helper method. Creates a new `RequestStream` instance based on the
@@ -482,20 +1045,23 @@ impl ClientService {
     /// Also returns the sender end of the channel to which the response is to be transmitted,
     /// once received.
     fn create_request(&self, async_req: AsyncRequest)
-            -> (RequestStream<'static, 'static, DefaultStream>, Sender<StaticResponse>) {
+            -> (RequestStream<'static, 'static, DefaultStream>, ResponseSender) {
+        let authority = async_req.authority.unwrap_or_else(|| self.host.clone());
         let mut headers: Vec<Header> = Vec::new();
         headers.extend(vec![
             Header::new(b":method", async_req.method),
             Header::new(b":path", async_req.path),
-            Header::new(b":authority", self.host.clone()),
+            Header::new(b":authority", authority),
             Header::new(b":scheme", self.conn.scheme().as_bytes().to_vec()),
         ].into_iter());
         headers.extend(async_req.headers.into_iter());
 
         let mut stream = DefaultStream::new();
-        match async_req.body {
-            Some(body) => stream.set_full_data(body),
-            None => stream.close_local(),
+        match (async_req.body, async_req.upload) {
+            // The stream stays open for the rest of the body.
+            (body, Some(_)) => stream.push_data(&body.unwrap_or_default()),
+            (Some(body), None) => stream.set_full_data(body),
+            (None, None) => stream.close_local(),
         };
 
         (
@@ -507,28 +1073,154 @@ impl ClientService {
         )
     }
 
+    /// Adds the given chunk of the body of the request with the given upload ID to its stream,
+    /// or ends the body if there is no chunk.
+    ///
+    /// The chunks of a request that is still queued are collected into its body. The ones of a
+    /// request that failed (or that was reset by the server) are dropped.
+    fn handle_upload(&mut self, upload: usize, chunk: Option<Vec<u8>>) -> HttpResult<()> {
+        if let Some(req) = self.request_queue.iter_mut().find(|req| req.upload == Some(upload)) {
+            let body = req.body.get_or_insert_with(Vec::new);
+            match chunk {
+                Some(data) => body.extend(data),
+                None => req.upload = None,
+            }
+            return Ok(());
+        }
+        let stream_id = match self.uploads.get(&upload) {
+            Some(&stream_id) => stream_id,
+            None => return Ok(()),
+        };
+        let end = chunk.is_none();
+        if let (Some(span), Some(data)) = (self.spans.get_mut(&stream_id), chunk.as_ref()) {
+            span.bytes_sent += data.len() as u64;
+        }
+        match self.conn.state.get_stream_mut(stream_id) {
+            Some(stream) => match chunk {
+                Some(data) => stream.push_data(&data),
+                None => stream.finish_data(),
+            },
+            None => {
+                debug!("Dropping the body of the closed stream {}", stream_id);
+                self.uploads.remove(&upload);
+                return Ok(());
+            },
+        }
+        if end {
+            self.uploads.remove(&upload);
+        }
+        // Nothing else gets the data sent if all of the earlier data was already written out.
+        try!(self.conn.send_next_data(&mut self.send_handle));
+        Ok(())
+    }
+
     /// Internal helper method. Sends a response assembled from the given
     /// stream to the corresponding channel that is waiting for the response.
     ///
     /// The given `stream` instance is consumed by this method.
+    ///
+    /// If the stream was closed before any headers were received (e.g. it was reset by the
+    /// server), the channel is dropped without a response, which the waiting client observes as
+    /// a disconnected channel.
     fn send_response(&mut self, stream: DefaultStream) {
-        let stream_id = stream.stream_id.unwrap();
+        let stream_id = match stream.stream_id {
+            Some(stream_id) => stream_id,
+            None => {
+                // Every stream gets its ID assigned as soon as the request is sent.
+                debug!("Closed stream without an ID; ignoring");
+                return;
+            },
+        };
+        if let Some(mut span) = self.spans.remove(&stream_id) {
+            span.status = stream.headers.as_ref().and_then(|headers| {
+                ResponseHead::new(stream_id, headers.clone()).ok().map(|head| head.status)
+            });
+            span.bytes_received = stream.body.len() as u64;
+            span.finish();
+        }
+        self.tracked.retain(|_, &mut tracked_id| tracked_id != stream_id);
+        self.head_waiters.remove(&stream_id);
+        let fallback_request = self.fallback_requests.remove(&stream_id);
+        if stream.error_code == Some(ErrorCode::Http11Required) {
+            if let (Some(request), Some(fallback)) = (fallback_request,
+                                                      self.http11_fallback.as_mut()) {
+                debug!("Stream {} requires HTTP/1.1; falling back", stream_id);
+                self.chans.remove(&stream_id);
+                fallback(request);
+                return;
+            }
+        }
         match self.chans.remove(&stream_id) {
             None => {
-                // This should never happen, it means the session gave us
-                // a response that we didn't request.
-                panic!("Received a response for an unknown request!");
+                // Unless the request was cancelled, this should never happen, it means the
+                // session gave us a response that we didn't request.
+                debug!("Received a response for an unknown request! id={}", stream_id);
             },
             Some(tx) => {
-                let _ = tx.send(Response {
-                    stream_id: stream_id,
-                    headers: stream.headers.unwrap(),
-                    body: stream.body,
-                });
+                if let Some(e) = stream.malformed_headers {
+                    debug!("Stream {} got malformed headers: {}", stream_id, e);
+                    tx.fail(HttpError::MalformedHeaders(e));
+                    return;
+                }
+                match stream.headers {
+                    Some(headers) => {
+                        tx.send(Response {
+                            stream_id: stream_id,
+                            headers: headers,
+                            body: stream.body,
+                            trailers: stream.trailers.unwrap_or_default(),
+                        });
+                    },
+                    None if stream.error_code == Some(ErrorCode::Http11Required) => {
+                        tx.fail(HttpError::Http11Required);
+                    },
+                    None => debug!("Stream {} closed without a response", stream_id),
+                }
             }
         };
     }
 
+    /// Sends the IDs of the tracked requests whose response headers arrived to the channels
+    /// waiting for them.
+    fn notify_heads(&mut self) {
+        let arrived: Vec<StreamId> = {
+            let state = &self.conn.state;
+            self.head_waiters.keys()
+                             .filter(|&&stream_id| {
+                                 state.get_stream_ref(stream_id)
+                                      .map_or(false, |stream| stream.headers.is_some())
+                             })
+                             .cloned()
+                             .collect()
+        };
+        for stream_id in arrived {
+            if let Some((id, head_tx)) = self.head_waiters.remove(&stream_id) {
+                // The client may have stopped waiting for the headers; that's fine.
+                let _ = head_tx.send(id);
+            }
+        }
+    }
+
+    /// Cancels the tracked request with the given ID: a queued request is dropped, while the
+    /// stream of one in flight is reset with the CANCEL error code. Either way, the channel
+    /// waiting for its response is dropped. A request that already completed is left alone.
+    fn cancel(&mut self, id: RequestId) -> HttpResult<()> {
+        if let Some(pos) = self.request_queue.iter().position(|req| req.id() == Some(id)) {
+            debug!("Dropping the cancelled request {:?} from the queue", id);
+            self.request_queue.remove(pos);
+            return Ok(());
+        }
+        let stream_id = match self.tracked.get(&id) {
+            Some(&stream_id) => stream_id,
+            None => return Ok(()),
+        };
+        debug!("Cancelling the request {:?} on stream {}", id, stream_id);
+        self.chans.remove(&stream_id);
+        try!(self.conn.reset_stream(stream_id, ErrorCode::Cancel, &mut self.send_handle));
+        self.handle_closed();
+        self.queue_next_request()
+    }
+
     /// Internal helper method. Handles all closed streams by sending appropriate
     /// notifications to waiting channels.
     ///
@@ -546,19 +1238,142 @@ impl ClientService {
     /// client to the service and the service has not exceeded the limit of
     /// concurrent requests that it is allowed to issue, it sends a single
     /// new request to the server. Blocks until this request is sent.
-    fn queue_next_request(&mut self) {
-        if self.outstanding_reqs < self.limit {
+    ///
+    /// An expired connection gets no new requests; it is rotated once the last of its requests
+    /// completes instead.
+    fn queue_next_request(&mut self) -> HttpResult<()> {
+        if self.is_expired() {
+            return self.rotate_connection();
+        }
+        if self.outstanding_reqs < self.max_in_flight() {
             // Try to queue another request since we haven't gone over
             // the (arbitrary) limit.
             debug!("Not over the limit yet. Checking for more requests...");
             if self.request_queue.len() > 0 {
                 let async_req = self.request_queue.remove(0);
-                self.send_request(async_req);
+                try!(self.send_request(async_req));
             }
         }
+
+        Ok(())
     }
 }
 
+/// Establishes a new connection with the given connector and spawns the threads that perform its
+/// IO, which notify the given work queue of the frames that they read and write, under the given
+/// generation of the connection.
+///
+/// The connection uses the given configuration. Since the preface is already written to the
+/// stream, the settings of the config that differ from their defaults are queued for sending in
+/// an additional SETTINGS frame.
+///
+/// If the connection cannot be established (or its stream split into its read and write ends),
+/// the error is returned.
+fn open_connection<C, S>(connector: C,
+                         config: Http2Config,
+                         work_queue: Sender<WorkItem>,
+                         generation: u32)
+        -> HttpResult<Connection>
+        where C: HttpConnect<Stream=S>, S: TransportStream + Send + 'static {
+    // Use the provided connector to establish a network connection...
+    let ClientStream(stream, scheme, host) = try!(connector.connect());
+    // Keep a socket handle in order to shut it down once the service stops (or replaces the
+    // connection). This is required because if the service decides to stop (due to all clients
+    // disconnecting) while the socket is still open and the read thread waiting, it can happen
+    // that the read thread (and as such the socket itself) ends up waiting indefinitely (or well,
+    // until the server decides to close it), effectively leaking the socket and thread.
+    let mut sck = try!(stream.try_split());
+
+    // Manually split the stream into the write/read ends, so that we can...
+    let sender = try!(stream.try_split());
+    let mut writer_sck = try!(stream.try_split());
+    let receiver = stream;
+    // ...wrap them into the adapters...
+    let max_frame_size = config.max_frame_size;
+    let (mut recv_frame, recv_handle) = ChannelFrameReceiver::new(receiver, max_frame_size);
+    let (mut send_frame, send_handle) = ChannelFrameSender::new(sender);
+
+    // ...and pass the non-blocking/buffering ends into the `HttpConnect` instead of the
+    // blocking socket itself.
+    let send_settings = !config.settings().is_empty();
+    let conn = ClientConnection::with_connection(
+            HttpConnection::with_config(scheme, config),
+            DefaultSessionState::<ClientMarker, _>::new());
+    let mut connection = Connection {
+        conn: conn,
+        recv_handle: recv_handle,
+        send_handle: send_handle,
+        host: host.as_bytes().to_vec(),
+        close: Box::new(move || {
+            // If the shutdown fails, there's really nothing we can do to recover at this point,
+            // other than let it be known.
+            if let Err(e) = sck.close() {
+                debug!("Failed to shut down the socket: {}", e);
+            }
+        }),
+    };
+    if send_settings {
+        try!(connection.conn.send_settings(&mut connection.send_handle));
+    }
+
+    // Keep a handle to the work queue to notify the service of newly read frames, making it so
+    // that it never blocks on waiting for frames to read.
+    let read_notify = work_queue.clone();
+    let sender_work_queue = work_queue;
+
+    thread::spawn(move || {
+        while let Ok(_) = send_frame.send_next() {
+            // The service stopping means there is nobody left to notify.
+            if let Err(_) = sender_work_queue.send(WorkItem::SendData(generation)) {
+                break;
+            }
+        }
+        debug!("Sender thread halting");
+        // The frames are all written out once the service lets go of the connection (e.g. the
+        // GOAWAY that a rotated connection is closed with), so the socket can be shut down.
+        let _ = writer_sck.close();
+    });
+    thread::spawn(move || {
+        loop {
+            match recv_frame.read_next() {
+                Ok(_) => {
+                    if let Err(_) = read_notify.send(WorkItem::HandleFrame(generation)) {
+                        break;
+                    }
+                },
+                Err(HttpError::PeerClosed { .. }) => {
+                    debug!("The server closed the connection");
+                    break;
+                },
+                Err(HttpError::NotHttp2(mismatch)) => {
+                    // The error is buffered in place of the server's preface, so that the service
+                    // fails everyone waiting on the connection with it.
+                    debug!("Not an HTTP/2 server: {:?}", mismatch);
+                    recv_frame.fail(HttpError::NotHttp2(mismatch));
+                    let _ = read_notify.send(WorkItem::HandleFrame(generation));
+                    break;
+                },
+                Err(e @ HttpError::LocalConnectionError(_)) => {
+                    // The error is buffered in place of the frame, so the connection fails (and
+                    // tells the server why) once it handles it.
+                    debug!("Refused a frame: {}", e);
+                    let _ = read_notify.send(WorkItem::HandleFrame(generation));
+                    break;
+                },
+                Err(e) => {
+                    debug!("Failed to read a frame: {}", e);
+                    break;
+                },
+            }
+        }
+        debug!("Reader thread halting");
+        // The service may well be gone already.
+        let _ = read_notify.send(WorkItem::ConnectionLost(generation));
+    });
+
+    Ok(connection)
+}
+
 /// A struct representing an HTTP/2 client that receives responses to its
 /// requests asynchronously. Additionally, this client can be cloned and all
 /// clones can issue (concurrently) requests to the server, using the same
@@ -603,13 +1418,66 @@ pub struct Client {
 
 impl Clone for Client {
     fn clone(&self) -> Client {
-        self.sender.send(WorkItem::NewClient).unwrap();
+        // If the service has already stopped, the clone is simply unable to issue any requests,
+        // which its `request` method will report.
+        let _ = self.sender.send(WorkItem::NewClient);
         Client {
             sender: self.sender.clone(),
         }
     }
 }
 
+/// The sending end of the body of a request issued with `Client::request_streaming`.
+///
+/// The body ends once `finish` is called, or once the `BodySender` is dropped. The chunks are
+/// sent as they are given, each one in as few DATA frames as the server's limits allow, so
+/// writing the body through a `BufWriter` avoids sending lots of tiny frames.
+pub struct BodySender {
+    /// The ID under which the service knows the upload.
+    upload: usize,
+    /// The sender side of the service's work queue.
+    sender: Sender<WorkItem>,
+    /// Whether the end of the body was sent.
+    finished: bool,
+}
+
+impl BodySender {
+    /// Sends the given chunk of the body.
+    ///
+    /// An `HttpError::ConnectionClosed` error is returned if the client is gone.
+    pub fn send(&self, data: Vec<u8>) -> HttpResult<()> {
+        self.sender.send(WorkItem::RequestData(self.upload, Some(data)))
+                   .map_err(|_| HttpError::ConnectionClosed)
+    }
+
+    /// Ends the body.
+    pub fn finish(mut self) -> HttpResult<()> {
+        self.finished = true;
+        self.sender.send(WorkItem::RequestData(self.upload, None))
+                   .map_err(|_| HttpError::ConnectionClosed)
+    }
+}
+
+impl io::Write for BodySender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        try!(self.send(buf.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e)));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for BodySender {
+    fn drop(&mut self) {
+        if !self.finished {
+            // The client may be gone already.
+            let _ = self.sender.send(WorkItem::RequestData(self.upload, None));
+        }
+    }
+}
+
 impl Drop for Client {
     fn drop(&mut self) {
         let _ = self.sender.send(WorkItem::ClientLeft);
@@ -634,56 +1502,69 @@ impl Client {
     /// gracefully. Any error on the underlying HTTP/2 connection also causes
     /// the thread to exit.
     ///
-    /// If the HTTP/2 connection cannot be initialized returns `None`.
-    pub fn with_connector<C, S>(connector: C) -> Option<Client>
+    /// If the HTTP/2 connection cannot be initialized, the error that caused it is returned.
+    pub fn with_connector<C, S>(connector: C) -> HttpResult<Client>
             where C: HttpConnect<Stream=S>, S: TransportStream + Send + 'static {
-        // Use the provided connector to establish a network connection...
-        let client_stream = connector.connect().ok().unwrap();
-        // Keep a socket handle in order to shut it down once the service stops. This is required
-        // because if the service decides to stop (due to all clients disconnecting) while the
-        // socket is still open and the read thread waiting, it can happen that the read thread
-        // (and as such the socket itself) ends up waiting indefinitely (or well, until the server
-        // decides to close it), effectively leaking the socket and thread.
-        let mut sck = client_stream.0.try_split().unwrap();
-
-        let service = match ClientService::new(client_stream) {
-            Some(service) => service,
-            None => return None,
-        };
-        let Service(mut service, rx, mut recv_frame, mut send_frame) = service;
+        Client::with_connector_and_config(connector, Http2Config::default())
+    }
 
-        if let Err(_) = rx.send(WorkItem::NewClient) {
-            return None;
-        }
+    /// Creates a new HTTP/2 client, same as `with_connector`, but using the given configuration
+    /// for the underlying connection.
+    pub fn with_connector_and_config<C, S>(connector: C, config: Http2Config)
+            -> HttpResult<Client>
+            where C: HttpConnect<Stream=S>, S: TransportStream + Send + 'static {
+        let (tx, rx) = mpsc::channel();
+        let connection = try!(open_connection(connector, config, tx.clone(), 0));
+        Client::start(ClientService::new(connection, rx, None), tx)
+    }
 
-        // Keep a handle to the work queue to notify the service of newly read frames, making it so
-        // that it never blocks on waiting for frames to read.
-        let read_notify = rx.clone();
-        let sender_work_queue = rx.clone();
+    /// Creates a new HTTP/2 client, same as `with_connector_and_config`, except that the client
+    /// replaces its connection with a new one (obtained with a connector that the given closure
+    /// returns) whenever the connection fails or the server closes it, e.g. with a GOAWAY.
+    ///
+    /// The requests that are yet to be sent when the connection is replaced carry over to the new
+    /// connection transparently. The ones that were already sent fail (their channels disconnect
+    /// without a response), as there is no telling whether the server processed them.
+    ///
+    /// Only a connection that was fully set up is replaced: if a new connection fails before the
+    /// server's SETTINGS are received, the client stops, failing the requests still queued.
+    ///
+    /// If the config sets a `max_connection_age`, connections are also rotated once they reach
+    /// that age (e.g. so that the load spreads over the servers behind an L4 load balancer): the
+    /// client stops sending requests on the expired connection, waits for the ones in flight to
+    /// complete, closes it with a GOAWAY and continues on a new connection. The age is checked
+    /// whenever a request is about to be sent, so an idle connection is only rotated once the
+    /// next request is made.
+    pub fn with_reconnect<F, C, S>(mut connect: F, config: Http2Config) -> HttpResult<Client>
+            where F: FnMut() -> C + Send + 'static,
+                  C: HttpConnect<Stream=S>,
+                  S: TransportStream + Send + 'static {
+        let (tx, rx) = mpsc::channel();
+        let connection = try!(open_connection(connect(), config.clone(), tx.clone(), 0));
+        let max_connection_age = config.max_connection_age;
+        let notify = tx.clone();
+        let reconnect: Reconnect = Box::new(move |generation| {
+            open_connection(connect(), config.clone(), notify.clone(), generation)
+        });
+        let mut service = ClientService::new(connection, rx, Some(reconnect));
+        service.max_connection_age = max_connection_age;
+        Client::start(service, tx)
+    }
 
+    /// Starts the given service on a new thread, returning a client that queues work for it on
+    /// the given work queue.
+    fn start(mut service: ClientService, work_queue: Sender<WorkItem>) -> HttpResult<Client> {
+        if let Err(_) = work_queue.send(WorkItem::NewClient) {
+            return Err(HttpError::UnableToConnect);
+        }
         thread::spawn(move || {
             while let Ok(_) = service.run_once() {}
             debug!("Service thread halting");
-            // This is the one place where it's okay to unwrap, as if the shutdown fails, there's
-            // really nothing we can do to recover at this point...
-            // This forces the reader thread to stop, as the socket is no longer operational.
-            sck.close().unwrap();
-        });
-        thread::spawn(move || {
-            while let Ok(_) = send_frame.send_next() {
-                sender_work_queue.send(WorkItem::SendData).unwrap();
-            }
-            debug!("Sender thread halting");
-        });
-        thread::spawn(move || {
-            while let Ok(_) = recv_frame.read_next() {
-                read_notify.send(WorkItem::HandleFrame).unwrap();
-            }
-            debug!("Reader thread halting");
+            service.close();
         });
 
-        Some(Client {
-            sender: rx,
+        Ok(Client {
+            sender: work_queue,
         })
     }
 
@@ -714,18 +1595,132 @@ impl Client {
             headers: &[StaticHeader],
             body: Option<Vec<u8>>)
             -> Option<Receiver<StaticResponse>> {
-        let (resp_tx, resp_rx): (Sender<StaticResponse>, Receiver<StaticResponse>) =
-                mpsc::channel();
+        self.queue_request(None, method, path, headers, body, None)
+    }
+
+    /// Issues a new request to the server, same as `request`, except that the returned channel
+    /// receives the result of the request: either the response, or the error that the request
+    /// failed with, where it is specific to the request. That is an `HttpError::MalformedHeaders`
+    /// error if the server sent a malformed header block, and an `HttpError::Http11Required`
+    /// error if the server requires HTTP/1.1 for the request (unless the client has a fallback
+    /// that handles it, see `set_http11_fallback`). If the server turns out not to speak HTTP/2
+    /// at all, the request fails with an `HttpError::NotHttp2` error.
+    ///
+    /// Requests that fail for any other reason disconnect the channel, as with `request`.
+    pub fn request_checked(
+            &self,
+            method: &[u8],
+            path: &[u8],
+            headers: &[StaticHeader],
+            body: Option<Vec<u8>>)
+            -> Option<Receiver<HttpResult<StaticResponse>>> {
+        let (resp_tx, resp_rx) = mpsc::channel();
+        let resp_tx = ResponseSender::Result(resp_tx);
+        let async_req = AsyncRequest::new(None, method, path, headers, body, resp_tx);
+        match self.sender.send(WorkItem::Request(async_req)) {
+            Ok(_) => Some(resp_rx),
+            Err(_) => None,
+        }
+    }
+
+    /// Issues a new request to the server, same as `request`, except that the request's
+    /// `:authority` is the given one, instead of the host that the client is connected to.
+    ///
+    /// This allows a single connection to carry requests for several authorities, e.g. when the
+    /// client is used by a reverse proxy. The authority is sent as it is given.
+    pub fn request_with_authority(
+            &self,
+            authority: &[u8],
+            method: &[u8],
+            path: &[u8],
+            headers: &[StaticHeader],
+            body: Option<Vec<u8>>)
+            -> Option<Receiver<StaticResponse>> {
+        self.queue_request(Some(authority.to_vec()), method, path, headers, body, None)
+    }
+
+    /// Issues a new request to the server whose body is streamed: it is sent as it is handed to
+    /// the returned `BodySender`, rather than given all at once. This lets e.g. a proxy pass an
+    /// upload through to the server without buffering it. The `:authority` of the request is the
+    /// given one, if any, or else the host that the client is connected to.
+    ///
+    /// The response arrives on the returned channel, as with `request`.
+    pub fn request_streaming(
+            &self,
+            authority: Option<&[u8]>,
+            method: &[u8],
+            path: &[u8],
+            headers: &[StaticHeader])
+            -> Option<(BodySender, Receiver<StaticResponse>)> {
+        let upload = NEXT_UPLOAD.fetch_add(1, Ordering::Relaxed);
+        let authority = authority.map(|authority| authority.to_vec());
+        let rx = match self.queue_request(authority, method, path, headers, None, Some(upload)) {
+            Some(rx) => rx,
+            None => return None,
+        };
+        let body = BodySender {
+            upload: upload,
+            sender: self.sender.clone(),
+            finished: false,
+        };
+        Some((body, rx))
+    }
+
+    /// Issues a new request to the server, same as `request`, which can later be cancelled with
+    /// `cancel`, given the returned ID. The ID is also sent to the given channel as soon as the
+    /// response headers arrive (while the body may still be on its way), so that the caller can
+    /// tell a request whose response is slow to start from one that is being answered; if the
+    /// request fails before that, the channel is dropped instead.
+    ///
+    /// Returns `None` if the request could not be queued, same as `request`.
+    pub fn request_tracked(
+            &self,
+            method: &[u8],
+            path: &[u8],
+            headers: &[StaticHeader],
+            body: Option<Vec<u8>>,
+            on_head: Sender<RequestId>)
+            -> Option<(RequestId, Receiver<StaticResponse>)> {
+        let id = RequestId(NEXT_REQUEST.fetch_add(1, Ordering::Relaxed));
+        let (resp_tx, resp_rx) = mpsc::channel();
+        let resp_tx = ResponseSender::Response(resp_tx);
+        let mut async_req = AsyncRequest::new(None, method, path, headers, body, resp_tx);
+        async_req.head = Some((id, on_head));
+        match self.sender.send(WorkItem::Request(async_req)) {
+            Ok(_) => Some((id, resp_rx)),
+            Err(_) => None,
+        }
+    }
+
+    /// Cancels the request with the given ID, issued with `request_tracked` by this client (or
+    /// any of its clones): if it is still queued, it is never sent; if it is in flight, its
+    /// stream is reset with the CANCEL error code. Either way, its response channel disconnects
+    /// without a response. Cancelling a request that already completed has no effect.
+    ///
+    /// If the connection has already failed, an `HttpError::ConnectionClosed` error is returned.
+    pub fn cancel(&self, id: RequestId) -> HttpResult<()> {
+        self.sender.send(WorkItem::Cancel(id)).map_err(|_| HttpError::ConnectionClosed)
+    }
+
+    /// Queues a request for the service, returning the channel on which its response will
+    /// arrive, unless the service is gone.
+    fn queue_request(
+            &self,
+            authority: Option<Vec<u8>>,
+            method: &[u8],
+            path: &[u8],
+            headers: &[StaticHeader],
+            body: Option<Vec<u8>>,
+            upload: Option<usize>)
+            -> Option<Receiver<StaticResponse>> {
+        let (resp_tx, resp_rx) = mpsc::channel();
+        let resp_tx = ResponseSender::Response(resp_tx);
         // A send can only fail if the receiver is disconnected. If the send
         // fails here, it means that the service hit an error on the underlying
         // HTTP/2 connection and will never come alive again.
-        let res = self.sender.send(WorkItem::Request(AsyncRequest {
-            method: method.to_vec(),
-            path: path.to_vec(),
-            headers: headers.to_vec(),
-            body: body,
-            tx: resp_tx,
-        }));
+        let mut async_req = AsyncRequest::new(authority, method, path, headers, body, resp_tx);
+        async_req.upload = upload;
+        let res = self.sender.send(WorkItem::Request(async_req));
 
         match res {
             Ok(_) => Some(resp_rx),
@@ -733,6 +1728,87 @@ impl Client {
         }
     }
 
+    /// Blocks until the client's connection is fully set up, i.e. the TCP (and TLS, if used)
+    /// connection is established, the client preface sent and the server's SETTINGS received and
+    /// acknowledged.
+    ///
+    /// Requests can be issued without waiting for this, but calling it right after creating the
+    /// client (e.g. while an application starts up) makes sure that the first request does not
+    /// pay for the connection setup latency.
+    ///
+    /// If the connection cannot be initialized, an `HttpError::UnableToConnect` error is
+    /// returned, or an `HttpError::NotHttp2` error carrying what the server sent instead of its
+    /// preface, if it does not speak HTTP/2.
+    pub fn preconnect(&self) -> HttpResult<()> {
+        let (tx, rx) = mpsc::channel();
+        if let Err(_) = self.sender.send(WorkItem::AwaitReady(tx)) {
+            return Err(HttpError::UnableToConnect);
+        }
+        // The service drops the sender without signaling if it stops before being initialized,
+        // unless it found out that the server does not speak HTTP/2.
+        rx.recv().unwrap_or(Err(HttpError::UnableToConnect))
+    }
+
+    /// Sets the callback that is handed the requests that the server refuses to handle over
+    /// HTTP/2, requiring HTTP/1.1 instead (by resetting their streams, or closing the whole
+    /// connection, with the HTTP_1_1_REQUIRED error code).
+    ///
+    /// The callback runs on the client's background thread and can retry the request with an
+    /// HTTP/1.1 stack, delivering the response to the waiting client with
+    /// `FallbackRequest::respond`; it should not block for long, so retries are best done on
+    /// another thread. Without a fallback, such requests fail like any other failed request.
+    ///
+    /// The fallback applies to all clones of the `Client`, for the requests issued after it is
+    /// set. If the connection has already failed, an `HttpError::ConnectionClosed` error is
+    /// returned.
+    pub fn set_http11_fallback<F>(&self, fallback: F) -> HttpResult<()>
+            where F: FnMut(FallbackRequest) + Send + 'static {
+        self.sender.send(WorkItem::SetHttp11Fallback(Box::new(fallback)))
+                   .map_err(|_| HttpError::ConnectionClosed)
+    }
+
+    /// Turns the propagation of W3C trace contexts on or off (it is off by default).
+    ///
+    /// While it is on, every request carries a `traceparent` header (and a `tracestate` header,
+    /// if there is any state): a request whose headers already carry a trace context (e.g. the
+    /// `child` of the one extracted from a request that a server is handling) continues that
+    /// trace with a new parent ID, while any other request starts a new trace. The context is
+    /// attached to the request's span as well (see `http::tracing`).
+    ///
+    /// The setting applies to all clones of the `Client`, for the requests issued after it is
+    /// set. If the connection has already failed, an `HttpError::ConnectionClosed` error is
+    /// returned.
+    pub fn set_trace_propagation(&self, enabled: bool) -> HttpResult<()> {
+        self.sender.send(WorkItem::SetTracePropagation(enabled))
+                   .map_err(|_| HttpError::ConnectionClosed)
+    }
+
+    /// Sets the policy that decides whether (and when) establishing a new connection is retried
+    /// when it fails, for a client that reconnects (see `with_reconnect`). By default, it is not:
+    /// the client stops once it cannot reconnect, failing the requests still queued.
+    ///
+    /// The policy applies to all clones of the `Client`. If the connection has already failed, an
+    /// `HttpError::ConnectionClosed` error is returned.
+    pub fn set_retry_policy(&self, policy: Arc<RetryPolicy>) -> HttpResult<()> {
+        self.sender.send(WorkItem::SetRetryPolicy(policy))
+                   .map_err(|_| HttpError::ConnectionClosed)
+    }
+
+    /// Returns a snapshot of the number of requests in flight and queued on the client's
+    /// connection, along with the concurrency limits that apply to it. All clones of a `Client`
+    /// share the same stats.
+    ///
+    /// The call blocks until the background service processes the work queued before it.
+    ///
+    /// Returns `None` if the underlying HTTP/2 connection has failed.
+    pub fn stats(&self) -> Option<ClientStats> {
+        let (tx, rx) = mpsc::channel();
+        if let Err(_) = self.sender.send(WorkItem::Stats(tx)) {
+            return None;
+        }
+        rx.recv().ok()
+    }
+
     /// Issues a GET request to the server.
     ///
     /// A convenience wrapper around the `request` method that sets the correct
@@ -748,4 +1824,24 @@ impl Client {
             -> Option<Receiver<StaticResponse>> {
         self.request(b"POST", path, headers, Some(body))
     }
+
+    /// Issues a POST request to the server, carrying the given form as a `multipart/form-data`
+    /// body, along with the `content-type` header that announces its boundary.
+    ///
+    /// The body is streamed (see `request_streaming`): the calling thread writes the form out to
+    /// the client chunk by chunk, reading its file parts along the way, before returning the
+    /// channel where the `Response` will eventually be pushed.
+    ///
+    /// If a file part cannot be read, the error is returned and the body ends right away, without
+    /// the closing boundary, so that the server sees an incomplete form.
+    pub fn post_multipart(&self, path: &[u8], headers: &[StaticHeader], form: Multipart)
+            -> HttpResult<Receiver<StaticResponse>> {
+        let mut headers = headers.to_vec();
+        headers.push(Header::new(b"content-type".to_vec(), form.content_type()));
+        let (mut body, rx) = try!(self.request_streaming(None, b"POST", path, &headers)
+                                      .ok_or(HttpError::ConnectionClosed));
+        try!(form.write_to(&mut body));
+        try!(body.finish());
+        Ok(rx)
+    }
 }
@@ -0,0 +1,54 @@
+//! The module implements the `application/x-www-form-urlencoded` encoding of request bodies, as
+//! HTML forms submit them (see `SimpleClient::post_form`).
+
+/// The value of the `content-type` header of a request that carries a URL-encoded form.
+pub const FORM_CONTENT_TYPE: &'static [u8] = b"application/x-www-form-urlencoded";
+
+/// Encodes the given fields into an `application/x-www-form-urlencoded` body: the names and
+/// values are percent-encoded (with spaces turned into `+`), joined by `=` and the fields by `&`,
+/// in the given order.
+pub fn encode_form(fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (i, &(name, value)) in fields.iter().enumerate() {
+        if i > 0 {
+            body.push(b'&');
+        }
+        encode_component(name.as_bytes(), &mut body);
+        body.push(b'=');
+        encode_component(value.as_bytes(), &mut body);
+    }
+    body
+}
+
+/// Appends the percent-encoding of the given bytes to the buffer. Only the characters that the
+/// URL-encoded serializer leaves alone (alphanumerics and `*-._`) are appended as they are.
+fn encode_component(bytes: &[u8], buf: &mut Vec<u8>) {
+    const HEX: &'static [u8] = b"0123456789ABCDEF";
+    for &byte in bytes {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'*' | b'-' | b'.' | b'_' => buf.push(byte),
+            b' ' => buf.push(b'+'),
+            _ => {
+                buf.push(b'%');
+                buf.push(HEX[(byte >> 4) as usize]);
+                buf.push(HEX[(byte & 0xf) as usize]);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::encode_form;
+
+    #[test]
+    fn test_encode_form() {
+        assert_eq!(encode_form(&[]), b"".to_vec());
+        assert_eq!(encode_form(&[("name", "value")]), b"name=value".to_vec());
+        assert_eq!(encode_form(&[("q", "a b&c=d"), ("lang", "de"), ("empty", "")]),
+                   b"q=a+b%26c%3Dd&lang=de&empty=".to_vec());
+        // Anything beyond ASCII is encoded as its UTF-8 bytes.
+        assert_eq!(encode_form(&[("city", "Zürich"), ("x*-._~", "1+1")]),
+                   b"city=Z%C3%BCrich&x*-._%7E=1%2B1".to_vec());
+    }
+}
@@ -0,0 +1,117 @@
+//! The module implements the (de)serialization of JSON request and response bodies with
+//! `serde_json` (see `SimpleClient::post_json` and `SimpleClient::get_json`).
+//!
+//! The module is only available with the `json` feature.
+use std::error::Error;
+use std::fmt;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use http::{HttpError, HttpResult, Response};
+
+/// The value of the `content-type` header of a request that carries JSON, and of the `accept`
+/// header of one that expects JSON in return.
+pub const JSON_CONTENT_TYPE: &'static [u8] = b"application/json";
+
+/// The error returned when a JSON body cannot be produced or the response does not carry the
+/// expected JSON. It is wrapped in an `HttpError::Other`.
+#[derive(Debug)]
+pub enum JsonError {
+    /// The response has a status other than 2xx, so its body is not the expected value. The
+    /// status is included in the error.
+    UnexpectedStatus(u16),
+    /// The value could not be serialized or the body could not be deserialized.
+    Serde(serde_json::Error),
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JsonError::UnexpectedStatus(status) => write!(fmt, "Unexpected status: {}", status),
+            JsonError::Serde(ref err) => write!(fmt, "Invalid JSON: {}", err),
+        }
+    }
+}
+
+impl Error for JsonError {
+    fn description(&self) -> &str {
+        match *self {
+            JsonError::UnexpectedStatus(_) => "Unexpected status",
+            JsonError::Serde(_) => "Invalid JSON",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            JsonError::UnexpectedStatus(_) => None,
+            JsonError::Serde(ref err) => Some(err),
+        }
+    }
+}
+
+impl From<JsonError> for HttpError {
+    fn from(err: JsonError) -> HttpError {
+        HttpError::Other(Box::new(err))
+    }
+}
+
+/// Serializes the given value into a request body.
+pub fn to_body<T: Serialize>(value: &T) -> HttpResult<Vec<u8>> {
+    serde_json::to_vec(value).map_err(|err| JsonError::Serde(err).into())
+}
+
+/// Deserializes the body of the given response, provided that it has a 2xx status.
+pub fn from_response<T: DeserializeOwned>(response: &Response) -> HttpResult<T> {
+    let status = try!(response.status_code());
+    if !(200..300).contains(&status) {
+        return Err(JsonError::UnexpectedStatus(status).into());
+    }
+    serde_json::from_slice(&response.body).map_err(|err| JsonError::Serde(err).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use http::{HttpError, Response};
+    use super::{to_body, from_response, JsonError};
+
+    fn response(status: &[u8], body: &[u8]) -> Response<'static, 'static> {
+        Response::new(1, vec![(b":status".to_vec(), status.to_vec())], body.to_vec())
+    }
+
+    #[test]
+    fn test_json_body() {
+        let mut value = BTreeMap::new();
+        value.insert("name", vec![1, 2]);
+        assert_eq!(to_body(&value).unwrap(), b"{\"name\":[1,2]}".to_vec());
+
+        let parsed: BTreeMap<String, Vec<u32>> =
+            from_response(&response(b"200", b"{\"name\": [1, 2]}")).unwrap();
+        assert_eq!(parsed.get("name"), Some(&vec![1, 2]));
+    }
+
+    /// Tests that responses with a non-2xx status or a body that is not the expected JSON are
+    /// errors.
+    #[test]
+    fn test_json_response_errors() {
+        let kind = |err: HttpError| {
+            match err {
+                HttpError::Other(err) => {
+                    match err.downcast::<JsonError>().map(|err| *err) {
+                        Ok(JsonError::UnexpectedStatus(status)) => Some(status),
+                        Ok(JsonError::Serde(_)) => None,
+                        Err(err) => panic!("Unexpected error: {:?}", err),
+                    }
+                },
+                err => panic!("Unexpected error: {:?}", err),
+            }
+        };
+        let res = from_response::<Vec<u32>>(&response(b"404", b"[1]"));
+        assert_eq!(kind(res.unwrap_err()), Some(404));
+        let res = from_response::<Vec<u32>>(&response(b"200", b"{\"not\": \"a list\"}"));
+        assert_eq!(kind(res.unwrap_err()), None);
+    }
+}
@@ -3,8 +3,20 @@
 //! responses.
 
 pub use self::simple::SimpleClient;
-pub use self::async::Client;
+pub use self::async::{Client, ClientStats, FallbackRequest, BodySender, RequestId};
+pub use self::pool::{ClientPool, BalancingStrategy, RoundRobin, LeastLoaded};
+pub use self::retry::{RetryPolicy, NoRetry, ExponentialBackoff};
+pub use self::multipart::Multipart;
+pub use self::form::{encode_form, FORM_CONTENT_TYPE};
+#[cfg(feature="json")]
+pub use self::json::{JsonError, JSON_CONTENT_TYPE};
 
 mod simple;
 mod async;
+mod pool;
+mod retry;
+mod multipart;
+mod form;
+#[cfg(feature="json")]
+mod json;
 #[cfg(test)] mod tests;
@@ -0,0 +1,234 @@
+//! The module implements a builder of `multipart/form-data` request bodies (RFC 7578), made up of
+//! plain fields and of file parts.
+//!
+//! The contents of the file parts are read from their sources only as the body is written out
+//! (see `Multipart::write_to`), so a form can be streamed to the server (see
+//! `Client::post_multipart`) without ever being assembled in memory.
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// The size of the chunks in which the contents of the file parts are written out.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// The contents of a part.
+enum PartBody {
+    /// A plain field, held in memory.
+    Bytes(Vec<u8>),
+    /// A file, read from its source as the body is written out.
+    Reader(Box<Read + Send>),
+}
+
+/// A part of the form: its serialized headers and its contents.
+struct Part {
+    headers: Vec<u8>,
+    body: PartBody,
+}
+
+/// A `multipart/form-data` request body.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::io::Cursor;
+/// use solicit::client::Multipart;
+///
+/// let form = Multipart::new()
+///     .text("title", b"A report")
+///     .file("attachment", "report.csv", b"text/csv", Cursor::new(b"a,b\n1,2\n".to_vec()))
+///     .file_path("log", "/var/log/app.log").unwrap();
+/// let mut body = Vec::new();
+/// let content_type = form.content_type();
+/// form.write_to(&mut body).unwrap();
+/// ```
+pub struct Multipart {
+    boundary: String,
+    parts: Vec<Part>,
+}
+
+impl Multipart {
+    /// Creates a new, empty, form, separating its parts with a randomly generated boundary.
+    pub fn new() -> Multipart {
+        let random = RandomState::new();
+        let boundary = format!("solicit-{:016x}{:016x}",
+                               random.build_hasher().finish(),
+                               RandomState::new().build_hasher().finish());
+        Multipart::with_boundary(boundary)
+    }
+
+    /// Creates a new, empty, form, separating its parts with the given boundary. The boundary
+    /// must not occur in any of the parts.
+    pub fn with_boundary(boundary: String) -> Multipart {
+        Multipart {
+            boundary: boundary,
+            parts: Vec::new(),
+        }
+    }
+
+    /// Returns the boundary that separates the parts of the form.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// Returns the value of the `content-type` header of a request carrying the form, which
+    /// announces its boundary.
+    pub fn content_type(&self) -> Vec<u8> {
+        format!("multipart/form-data; boundary={}", self.boundary).into_bytes()
+    }
+
+    /// Adds a plain field with the given name and value to the form.
+    pub fn text(mut self, name: &str, value: &[u8]) -> Multipart {
+        let headers = format!("content-disposition: form-data; name=\"{}\"\r\n",
+                              escape(name));
+        self.parts.push(Part {
+            headers: headers.into_bytes(),
+            body: PartBody::Bytes(value.to_vec()),
+        });
+        self
+    }
+
+    /// Adds a file part with the given field name, file name and content type to the form. Its
+    /// contents are read from the given source only as the form is written out.
+    pub fn file<R>(mut self, name: &str, filename: &str, content_type: &[u8], source: R)
+            -> Multipart where R: Read + Send + 'static {
+        let mut headers = format!("content-disposition: form-data; name=\"{}\"; \
+                                   filename=\"{}\"\r\ncontent-type: ",
+                                  escape(name),
+                                  escape(filename)).into_bytes();
+        headers.extend_from_slice(content_type);
+        headers.extend_from_slice(b"\r\n");
+        self.parts.push(Part {
+            headers: headers,
+            body: PartBody::Reader(Box::new(source)),
+        });
+        self
+    }
+
+    /// Adds the file at the given path to the form, as an `application/octet-stream` part with
+    /// the given field name, named after the file. The file is opened right away, but read only
+    /// as the form is written out.
+    pub fn file_path<P: AsRef<Path>>(self, name: &str, path: P) -> io::Result<Multipart> {
+        let path = path.as_ref();
+        let file = try!(File::open(path));
+        let filename = path.file_name()
+                           .map(|filename| filename.to_string_lossy().into_owned())
+                           .unwrap_or_default();
+        Ok(self.file(name, &filename, b"application/octet-stream", file))
+    }
+
+    /// Writes the form out to the given writer, reading the file parts from their sources in
+    /// chunks along the way.
+    pub fn write_to<W: Write>(self, writer: &mut W) -> io::Result<()> {
+        let mut buf = vec![0; CHUNK_SIZE];
+        for part in self.parts {
+            try!(write!(writer, "--{}\r\n", self.boundary));
+            try!(writer.write_all(&part.headers));
+            try!(writer.write_all(b"\r\n"));
+            match part.body {
+                PartBody::Bytes(bytes) => try!(writer.write_all(&bytes)),
+                PartBody::Reader(mut source) => {
+                    loop {
+                        let read = match source.read(&mut buf) {
+                            Ok(0) => break,
+                            Ok(read) => read,
+                            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                            Err(e) => return Err(e),
+                        };
+                        try!(writer.write_all(&buf[..read]));
+                    }
+                },
+            }
+            try!(writer.write_all(b"\r\n"));
+        }
+        write!(writer, "--{}--\r\n", self.boundary)
+    }
+
+    /// Writes the whole form out into a buffer.
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        try!(self.write_to(&mut body));
+        Ok(body)
+    }
+}
+
+impl Default for Multipart {
+    fn default() -> Multipart {
+        Multipart::new()
+    }
+}
+
+impl fmt::Debug for Multipart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Multipart {{ boundary: {:?}, parts: {} }}", self.boundary, self.parts.len())
+    }
+}
+
+/// Escapes the given field or file name for a quoted `content-disposition` parameter, the way
+/// browsers do it: the quotes and line breaks are percent-encoded.
+fn escape(name: &str) -> String {
+    name.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor, Read};
+
+    use super::Multipart;
+
+    /// Tests that the form is written out with its parts separated by the boundary.
+    #[test]
+    fn test_multipart_body() {
+        let form = Multipart::with_boundary("XyZ".to_string())
+            .text("title", b"Hello")
+            .file("upload", "a \"b\".txt", b"text/plain", Cursor::new(b"file\ncontents".to_vec()));
+        assert_eq!(form.content_type(), b"multipart/form-data; boundary=XyZ".to_vec());
+
+        let body = form.into_bytes().unwrap();
+
+        let expected = b"--XyZ\r\n\
+                         content-disposition: form-data; name=\"title\"\r\n\
+                         \r\n\
+                         Hello\r\n\
+                         --XyZ\r\n\
+                         content-disposition: form-data; name=\"upload\"; \
+                         filename=\"a %22b%22.txt\"\r\n\
+                         content-type: text/plain\r\n\
+                         \r\n\
+                         file\ncontents\r\n\
+                         --XyZ--\r\n";
+        assert_eq!(String::from_utf8(body).unwrap(),
+                   String::from_utf8(expected.to_vec()).unwrap());
+    }
+
+    /// Tests that large file parts are read in chunks and that the errors of their sources are
+    /// propagated.
+    #[test]
+    fn test_multipart_file_source() {
+        let contents = vec![7; 20_000];
+        let form = Multipart::new().file("f", "f.bin", b"application/octet-stream",
+                                         Cursor::new(contents.clone()));
+        let boundary = form.boundary().to_string();
+        assert!(boundary.starts_with("solicit-"));
+        let body = form.into_bytes().unwrap();
+        assert!(body.windows(contents.len()).any(|window| window == &contents[..]));
+        assert!(body.ends_with(format!("\r\n--{}--\r\n", boundary).as_bytes()));
+
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::Other, "disk on fire"))
+            }
+        }
+        let form = Multipart::new().file("f", "f.bin", b"application/octet-stream", Failing);
+        assert_eq!(form.into_bytes().unwrap_err().kind(), io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_multipart_file_path() {
+        assert!(Multipart::new().file_path("f", "/this/file/does/not/exist").is_err());
+    }
+}
@@ -0,0 +1,512 @@
+//! The module contains a pool of `Client`s connected to the same authority, which opens more
+//! connections as the ones it has fill up and spreads the requests over them.
+
+use std::cmp;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Sender, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use http::{HttpError, HttpResult, StaticHeader, StaticResponse};
+use client::{Client, ClientStats, FallbackRequest, RequestId, RetryPolicy, NoRetry};
+
+/// The HTTP/1.1 fallback of a pool, shared by all of its connections.
+type SharedFallback = Arc<Mutex<Box<FnMut(FallbackRequest) + Send>>>;
+
+/// The trait is implemented by the policies that a `ClientPool` uses to pick the connection that
+/// a new request is sent on.
+pub trait BalancingStrategy: Send {
+    /// Returns the index of the connection that the next request should be sent on, given the
+    /// current load of each one of the candidate connections. There is always at least one
+    /// candidate.
+    fn pick(&mut self, candidates: &[ClientStats]) -> usize;
+}
+
+/// Sends the requests on each connection in turn.
+#[derive(Clone, Debug, Default)]
+pub struct RoundRobin {
+    next: usize,
+}
+
+impl BalancingStrategy for RoundRobin {
+    fn pick(&mut self, candidates: &[ClientStats]) -> usize {
+        let picked = self.next % candidates.len();
+        self.next = picked + 1;
+        picked
+    }
+}
+
+/// Sends each request on the connection with the fewest requests, relative to the number of
+/// requests that it can have in flight, so that connections to servers that allow more
+/// concurrent streams get proportionally more requests. Ties go to the first connection.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LeastLoaded;
+
+impl BalancingStrategy for LeastLoaded {
+    fn pick(&mut self, candidates: &[ClientStats]) -> usize {
+        let mut picked = 0;
+        for (i, stats) in candidates.iter().enumerate().skip(1) {
+            if stats.load() < candidates[picked].load() {
+                picked = i;
+            }
+        }
+        picked
+    }
+}
+
+/// A pool of `Client`s whose connections all go to the same authority.
+///
+/// The pool starts out with a single connection. A new one is only opened when a request is
+/// issued while all the connections are saturated (see `ClientStats::is_saturated`), e.g.
+/// because the server's SETTINGS_MAX_CONCURRENT_STREAMS is low, until the maximum number of
+/// connections is reached. The requests are spread over the connections that have room for
+/// them according to the pool's `BalancingStrategy` (`LeastLoaded` by default); once all of
+/// them are saturated and no more connections can be opened, the strategy picks among all of
+/// them and the request is queued by the picked client.
+///
+/// Connections that fail are dropped from the pool and replaced on demand.
+///
+/// The pool can also hedge GET requests (see `set_hedging`).
+pub struct ClientPool {
+    connect: Box<FnMut() -> HttpResult<Client> + Send>,
+    clients: Vec<Client>,
+    max_connections: usize,
+    strategy: Box<BalancingStrategy>,
+    /// How long a GET request waits for its response headers before it is hedged, if the pool
+    /// hedges requests.
+    hedging: Option<Duration>,
+    /// Decides whether opening a connection, or a hedged request that failed, is retried.
+    retry_policy: Arc<RetryPolicy>,
+    /// The callback that is handed the requests that need HTTP/1.1, if the pool has one.
+    http11_fallback: Option<SharedFallback>,
+}
+
+impl ClientPool {
+    /// Creates a new pool that opens its connections by invoking the given closure (e.g. one
+    /// that calls `Client::with_connector` with a new connector), opening the first one right
+    /// away.
+    ///
+    /// If the first connection cannot be opened, the error that caused it is returned.
+    pub fn new<F>(mut connect: F) -> HttpResult<ClientPool>
+            where F: FnMut() -> HttpResult<Client> + Send + 'static {
+        let client = try!(connect());
+        Ok(ClientPool {
+            connect: Box::new(connect),
+            clients: vec![client],
+            max_connections: 4,
+            strategy: Box::new(LeastLoaded),
+            hedging: None,
+            retry_policy: Arc::new(NoRetry),
+            http11_fallback: None,
+        })
+    }
+
+    /// Sets the maximum number of connections that the pool opens (4 by default). It is never
+    /// less than 1.
+    pub fn set_max_connections(&mut self, max: usize) {
+        self.max_connections = if max == 0 { 1 } else { max };
+    }
+
+    /// Returns the maximum number of connections that the pool opens.
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    /// Sets the strategy that picks the connection for each request.
+    pub fn set_balancing_strategy<S>(&mut self, strategy: S)
+            where S: BalancingStrategy + 'static {
+        self.strategy = Box::new(strategy);
+    }
+
+    /// Turns the hedging of GET requests on, with the given delay, or off (it is off by default).
+    ///
+    /// While it is on, a GET request whose response headers do not arrive within the delay is
+    /// issued once more, on another one of the pool's connections (a second connection is
+    /// opened for it, if the pool has only one and the limit allows it). Whichever of the two
+    /// responses starts arriving first is delivered, while the other request is cancelled with a
+    /// RST_STREAM (see `Client::cancel`). This trades some extra load on the servers for a lower
+    /// tail latency, e.g. when one of the servers behind a load balancer is slow. Only GET
+    /// requests without a body are hedged, as they are idempotent.
+    pub fn set_hedging(&mut self, delay: Option<Duration>) {
+        self.hedging = delay;
+    }
+
+    /// Returns the delay after which GET requests are hedged, if the pool hedges them.
+    pub fn hedging(&self) -> Option<Duration> {
+        self.hedging
+    }
+
+    /// Sets the policy that decides whether (and when) the pool retries opening a connection that
+    /// it could not open, as well as a hedged request that failed on all the connections that it
+    /// went out on (which the policy is given as an `HttpError::ConnectionClosed` error). By
+    /// default, nothing is retried.
+    ///
+    /// The retries of a connection block the request that needed it, while hedged requests are
+    /// retried in the background.
+    pub fn set_retry_policy(&mut self, policy: Arc<RetryPolicy>) {
+        self.retry_policy = policy;
+    }
+
+    /// Sets the callback that is handed the requests that the server refuses to handle over
+    /// HTTP/2, requiring HTTP/1.1 instead, on any of the pool's connections (see
+    /// `Client::set_http11_fallback`).
+    ///
+    /// The fallback is set on the current connections and on every connection that the pool
+    /// opens from then on, including the ones replacing failed connections. As the connections
+    /// share it, the callback is invoked by one of them at a time.
+    pub fn set_http11_fallback<F>(&mut self, fallback: F)
+            where F: FnMut(FallbackRequest) + Send + 'static {
+        let fallback: SharedFallback = Arc::new(Mutex::new(Box::new(fallback)));
+        for client in &self.clients {
+            set_shared_fallback(client, &fallback);
+        }
+        self.http11_fallback = Some(fallback);
+    }
+
+    /// Warms the pool up: opens connections until the pool has `n` of them (but no more than its
+    /// maximum) and waits for each one to be fully set up (see `Client::preconnect`), so that the
+    /// first requests do not pay for the connection setup latency. All of the pool's connections
+    /// go to the same authority, the one that its `connect` closure connects to.
+    ///
+    /// Connections that failed are dropped from the pool first. If a connection cannot be opened
+    /// or set up, the error is returned; the connections opened until then stay in the pool.
+    pub fn warm(&mut self, n: usize) -> HttpResult<()> {
+        self.clients.retain(|client| client.stats().is_some());
+        let target = cmp::min(n, self.max_connections);
+        while self.clients.len() < target {
+            let client = try!(self.open_connection());
+            self.clients.push(client);
+        }
+        for client in &self.clients[..target] {
+            try!(client.preconnect());
+        }
+        Ok(())
+    }
+
+    /// Returns the number of connections that the pool currently has.
+    pub fn connections(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Returns the load of each of the pool's connections that has not failed.
+    ///
+    /// The call blocks until the background service of each connection processes the work queued
+    /// before it (see `Client::stats`).
+    pub fn stats(&self) -> Vec<ClientStats> {
+        self.clients.iter().filter_map(|client| client.stats()).collect()
+    }
+
+    /// Issues a new request on one of the pool's connections, opening a new connection if needed,
+    /// same as `Client::request`.
+    ///
+    /// Returns `None` if the request could not be queued, i.e. when the picked connection failed
+    /// in the meantime, or when the pool has no connections left and cannot open a new one.
+    pub fn request(
+            &mut self,
+            method: &[u8],
+            path: &[u8],
+            headers: &[StaticHeader],
+            body: Option<Vec<u8>>)
+            -> Option<Receiver<StaticResponse>> {
+        let index = match self.pick_client() {
+            Some(index) => index,
+            None => return None,
+        };
+        match self.hedging {
+            Some(delay) if method == b"GET" && body.is_none() => {
+                self.hedged_request(index, delay, path, headers)
+            },
+            _ => self.clients[index].request(method, path, headers, body),
+        }
+    }
+
+    /// Issues a GET request on the client with the given index, hedging it on another client if
+    /// its response headers do not arrive within the given delay.
+    ///
+    /// A thread races the requests (see `HedgedRequest::run`) and passes the winner's response on
+    /// to the returned channel. If the request fails for good, the channel disconnects without a
+    /// response.
+    fn hedged_request(
+            &mut self,
+            index: usize,
+            delay: Duration,
+            path: &[u8],
+            headers: &[StaticHeader])
+            -> Option<Receiver<StaticResponse>> {
+        let (head_tx, head_rx) = mpsc::channel();
+        let primary = self.clients[index].clone();
+        let first = match primary.request_tracked(b"GET", path, headers, None, head_tx.clone()) {
+            Some(first) => first,
+            None => return None,
+        };
+        let backup = match self.pick_other_client(index) {
+            Some(backup) => backup,
+            // There is nothing to hedge the request on.
+            None => return Some(first.1),
+        };
+
+        let hedged = HedgedRequest {
+            path: path.to_vec(),
+            headers: headers.to_vec(),
+            delay: delay,
+            retry_policy: self.retry_policy.clone(),
+            clients: (primary, backup),
+        };
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            if let Some(response) = hedged.run(first, (head_tx, head_rx)) {
+                // The caller may have stopped waiting for the response; that's fine.
+                let _ = tx.send(response);
+            }
+        });
+        Some(rx)
+    }
+
+    /// Issues a GET request on one of the pool's connections.
+    pub fn get(&mut self, path: &[u8], headers: &[StaticHeader])
+            -> Option<Receiver<StaticResponse>> {
+        self.request(b"GET", path, headers, None)
+    }
+
+    /// Issues a POST request on one of the pool's connections.
+    pub fn post(&mut self, path: &[u8], headers: &[StaticHeader], body: Vec<u8>)
+            -> Option<Receiver<StaticResponse>> {
+        self.request(b"POST", path, headers, Some(body))
+    }
+
+    /// Returns the index of the client that the next request is to be issued on, opening a new
+    /// connection if all of the current ones are saturated (or failed) and the limit allows it.
+    fn pick_client(&mut self) -> Option<usize> {
+        // Drop the clients whose connections failed, as they can no longer issue requests.
+        let mut stats = Vec::with_capacity(self.clients.len());
+        let mut clients = Vec::with_capacity(self.clients.len());
+        for client in self.clients.drain(..) {
+            if let Some(client_stats) = client.stats() {
+                stats.push(client_stats);
+                clients.push(client);
+            }
+        }
+        self.clients = clients;
+
+        let available: Vec<usize> = (0..stats.len()).filter(|&i| !stats[i].is_saturated())
+                                                    .collect();
+        if available.is_empty() && self.clients.len() < self.max_connections {
+            match self.open_connection() {
+                Ok(client) => {
+                    debug!("Opened connection {} of the pool", self.clients.len() + 1);
+                    self.clients.push(client);
+                    return Some(self.clients.len() - 1);
+                },
+                Err(e) => debug!("Failed to open a new connection for the pool: {:?}", e),
+            }
+        }
+        if self.clients.is_empty() {
+            return None;
+        }
+
+        let index = if available.is_empty() {
+            self.strategy.pick(&stats)
+        } else {
+            let candidates: Vec<ClientStats> = available.iter().map(|&i| stats[i]).collect();
+            available[self.strategy.pick(&candidates)]
+        };
+        Some(index)
+    }
+
+    /// Returns a client, other than the one with the given index, that a request can be hedged
+    /// on, opening a second connection if the pool has only one and the limit allows it.
+    fn pick_other_client(&mut self, index: usize) -> Option<Client> {
+        if self.clients.len() == 1 && self.max_connections > 1 {
+            match self.open_connection() {
+                Ok(client) => {
+                    debug!("Opened a second connection of the pool for hedging");
+                    self.clients.push(client);
+                },
+                Err(e) => debug!("Failed to open a connection for hedging: {:?}", e),
+            }
+        }
+        let mut others = Vec::new();
+        let mut stats = Vec::new();
+        for (i, client) in self.clients.iter().enumerate().filter(|&(i, _)| i != index) {
+            if let Some(client_stats) = client.stats() {
+                others.push(i);
+                stats.push(client_stats);
+            }
+        }
+        if others.is_empty() {
+            return None;
+        }
+        let picked = others[self.strategy.pick(&stats)];
+        Some(self.clients[picked].clone())
+    }
+
+    /// Opens a new connection, making as many attempts as the retry policy allows. The error of
+    /// the last attempt is returned if none of them succeed.
+    fn open_connection(&mut self) -> HttpResult<Client> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let err = match (self.connect)() {
+                Ok(client) => {
+                    if let Some(ref fallback) = self.http11_fallback {
+                        set_shared_fallback(&client, fallback);
+                    }
+                    return Ok(client);
+                },
+                Err(err) => err,
+            };
+            match self.retry_policy.should_retry(&err, attempt) {
+                Some(wait) => {
+                    debug!("Failed to open a connection ({}); retrying in {:?}", err, wait);
+                    thread::sleep(wait);
+                },
+                None => return Err(err),
+            }
+        }
+    }
+}
+
+/// Sets the given shared fallback on the client. A client whose connection already failed is
+/// left as it is, as it is dropped from the pool anyway.
+fn set_shared_fallback(client: &Client, fallback: &SharedFallback) {
+    let fallback = fallback.clone();
+    let _ = client.set_http11_fallback(move |req| {
+        let mut fallback = fallback.lock().unwrap();
+        (&mut **fallback)(req)
+    });
+}
+
+/// A GET request that is hedged over two of the pool's clients.
+struct HedgedRequest {
+    path: Vec<u8>,
+    headers: Vec<StaticHeader>,
+    /// How long the request waits for its response headers before it is hedged.
+    delay: Duration,
+    retry_policy: Arc<RetryPolicy>,
+    /// The client that the request goes out on first and the one that it is hedged on.
+    clients: (Client, Client),
+}
+
+impl HedgedRequest {
+    /// Races the given request, already issued on the first client, against the one hedged on
+    /// the second client once the delay passes, returning the response of the one whose headers
+    /// arrive first, after cancelling the other. The IDs of the requests whose headers arrive are
+    /// sent to the given channel.
+    ///
+    /// If both requests fail, the request is retried, as long as the retry policy allows it,
+    /// with the roles of the two clients swapped.
+    fn run(mut self,
+           mut first: (RequestId, Receiver<StaticResponse>),
+           mut head: (Sender<RequestId>, Receiver<RequestId>))
+            -> Option<StaticResponse> {
+        let mut attempts = 0;
+        loop {
+            let (head_tx, head_rx) = head;
+            let mut requests = vec![(first.0, first.1, &self.clients.0)];
+            let winner = match head_rx.recv_timeout(self.delay) {
+                Ok(id) => Some(id),
+                Err(RecvTimeoutError::Timeout) => {
+                    debug!("No response headers after {:?}; hedging the request", self.delay);
+                    let backup = &self.clients.1;
+                    let hedged = backup.request_tracked(b"GET", &self.path, &self.headers, None,
+                                                        head_tx);
+                    if let Some((id, rx)) = hedged {
+                        requests.push((id, rx, backup));
+                    }
+                    // Only the clients hold on to the head channel now, so it disconnects once
+                    // all of the requests fail.
+                    head_rx.recv().ok()
+                },
+                Err(RecvTimeoutError::Disconnected) => None,
+            };
+            attempts += requests.len() as u32;
+            let mut winner_rx = None;
+            for (id, rx, client) in requests {
+                if Some(id) == winner {
+                    winner_rx = Some(rx);
+                } else {
+                    // The loser may have completed already, or its connection failed.
+                    let _ = client.cancel(id);
+                }
+            }
+            if let Some(rx) = winner_rx {
+                return rx.recv().ok();
+            }
+
+            let err = HttpError::ConnectionClosed;
+            let wait = match self.retry_policy.should_retry(&err, attempts) {
+                Some(wait) => wait,
+                None => return None,
+            };
+            debug!("The hedged request failed; retrying in {:?}", wait);
+            thread::sleep(wait);
+            {
+                let (ref mut primary, ref mut backup) = self.clients;
+                mem::swap(primary, backup);
+            }
+            head = mpsc::channel();
+            let retried = self.clients.0.request_tracked(b"GET", &self.path, &self.headers, None,
+                                                         head.0.clone());
+            first = match retried {
+                Some(first) => first,
+                None => return None,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use http::ConnectionId;
+    use http::connection::ConnectionActivity;
+    use client::ClientStats;
+    use super::{BalancingStrategy, RoundRobin, LeastLoaded};
+
+    /// Returns the stats of a client with the given number of requests in flight and queued,
+    /// and the given server limit to the concurrent streams.
+    fn stats(in_flight: u32, queued: usize, peer_max: Option<u32>) -> ClientStats {
+        ClientStats {
+            in_flight: in_flight,
+            queued: queued,
+            limit: 3,
+            peer_max_concurrent_streams: peer_max,
+            reconnects: 0,
+            activity: ConnectionActivity {
+                established_at: Instant::now(),
+                last_frame_received: None,
+                last_frame_sent: None,
+            },
+            connection_id: ConnectionId(1),
+        }
+    }
+
+    /// Tests that the round robin strategy cycles through the candidates, even as their number
+    /// changes.
+    #[test]
+    fn test_round_robin() {
+        let mut strategy = RoundRobin::default();
+        let candidates = vec![stats(0, 0, None), stats(2, 0, None), stats(1, 0, None)];
+        let picks: Vec<_> = (0..4).map(|_| strategy.pick(&candidates)).collect();
+        assert_eq!(picks, vec![0, 1, 2, 0]);
+        assert_eq!(strategy.pick(&candidates[..1]), 0);
+        assert_eq!(strategy.pick(&candidates[..2]), 1);
+    }
+
+    /// Tests that the least loaded strategy picks the connection with the lowest load relative
+    /// to how many requests it can have in flight.
+    #[test]
+    fn test_least_loaded() {
+        let mut strategy = LeastLoaded;
+        let candidates = vec![stats(2, 0, None), stats(1, 0, None), stats(1, 0, None)];
+        assert_eq!(strategy.pick(&candidates), 1);
+        // A connection that can only have a single stream open is fully loaded with one.
+        let candidates = vec![stats(1, 0, Some(1)), stats(2, 0, None)];
+        assert_eq!(strategy.pick(&candidates), 1);
+        // Queued requests count as load too.
+        let candidates = vec![stats(0, 2, None), stats(1, 0, None)];
+        assert_eq!(strategy.pick(&candidates), 1);
+    }
+}
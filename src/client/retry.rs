@@ -0,0 +1,151 @@
+//! The module contains the `RetryPolicy` trait, which decides whether an operation that failed is
+//! attempted again, and after how long, along with its implementations.
+//!
+//! A single policy (shared through an `Arc`) configures all the parts of the client that retry: a
+//! `Client` that reconnects retries establishing its new connections (see
+//! `Client::set_retry_policy`), while a `ClientPool` retries opening its connections, as well as
+//! the hedged requests that fail on all of the connections they went out on (see
+//! `ClientPool::set_retry_policy`).
+
+use std::cmp;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use http::HttpError;
+
+/// The trait is implemented by the policies that decide whether (and when) a failed operation is
+/// retried.
+pub trait RetryPolicy: Send + Sync {
+    /// Returns how long to wait before attempting the operation once more, given the error that
+    /// the last attempt failed with and the number of attempts made so far (1 after the first
+    /// one failed), or `None` if the operation is to be given up.
+    fn should_retry(&self, error: &HttpError, attempt: u32) -> Option<Duration>;
+}
+
+/// Never retries anything. This is the default policy of all the client components.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoRetry;
+
+impl RetryPolicy for NoRetry {
+    fn should_retry(&self, _error: &HttpError, _attempt: u32) -> Option<Duration> {
+        None
+    }
+}
+
+/// Retries up to a maximum number of attempts, waiting exponentially longer after each one: the
+/// wait after the n-th attempt is `initial_delay * 2^(n - 1)`, capped at `max_delay`, minus a
+/// random jitter of up to half of it, so that the clients that failed at the same time do not
+/// all retry in lockstep.
+///
+/// An `HttpError::Http11Required` is never retried, as the server is not going to switch back to
+/// HTTP/2.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ExponentialBackoff {
+    /// The wait after the first attempt, before the jitter is taken off.
+    pub initial_delay: Duration,
+    /// The longest wait between two attempts, before the jitter is taken off.
+    pub max_delay: Duration,
+    /// The number of attempts, including the first one, after which the operation is given up.
+    pub max_attempts: u32,
+}
+
+impl ExponentialBackoff {
+    /// Creates a new policy with the given delays, which makes at most the given number of
+    /// attempts.
+    pub fn new(initial_delay: Duration, max_delay: Duration, max_attempts: u32)
+            -> ExponentialBackoff {
+        ExponentialBackoff {
+            initial_delay: initial_delay,
+            max_delay: max_delay,
+            max_attempts: max_attempts,
+        }
+    }
+
+    /// Returns the wait after the given number of attempts, before the jitter is taken off.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt.saturating_sub(1)).unwrap_or(::std::u32::MAX);
+        match self.initial_delay.checked_mul(factor) {
+            Some(delay) => cmp::min(delay, self.max_delay),
+            None => self.max_delay,
+        }
+    }
+}
+
+impl Default for ExponentialBackoff {
+    /// Waits 100ms after the first attempt, up to 10s, making at most 5 attempts.
+    fn default() -> ExponentialBackoff {
+        ExponentialBackoff::new(Duration::from_millis(100), Duration::from_secs(10), 5)
+    }
+}
+
+impl RetryPolicy for ExponentialBackoff {
+    fn should_retry(&self, error: &HttpError, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        if let HttpError::Http11Required = *error {
+            return None;
+        }
+        let delay = self.delay(attempt);
+        Some(delay - jitter(delay / 2))
+    }
+}
+
+/// Returns a random duration that is not longer than the given one. The randomness comes from the
+/// randomly keyed hasher of the standard library, which is plenty for spreading retries out.
+fn jitter(max: Duration) -> Duration {
+    let max_nanos = max.as_secs().saturating_mul(1_000_000_000)
+                                 .saturating_add(max.subsec_nanos() as u64);
+    let random = RandomState::new().build_hasher().finish();
+    let nanos = random % max_nanos.saturating_add(1);
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use http::HttpError;
+    use super::{RetryPolicy, NoRetry, ExponentialBackoff};
+
+    #[test]
+    fn test_no_retry() {
+        assert_eq!(NoRetry.should_retry(&HttpError::UnableToConnect, 1), None);
+    }
+
+    /// Tests that the delays of the exponential backoff double with each attempt, up to the
+    /// maximum.
+    #[test]
+    fn test_exponential_backoff_delay() {
+        let policy = ExponentialBackoff::new(Duration::from_millis(100),
+                                             Duration::from_secs(1),
+                                             10);
+        let delays: Vec<_> = (1..6).map(|attempt| policy.delay(attempt)).collect();
+        assert_eq!(delays, vec![
+            Duration::from_millis(100),
+            Duration::from_millis(200),
+            Duration::from_millis(400),
+            Duration::from_millis(800),
+            Duration::from_secs(1),
+        ]);
+        // No overflow, however many attempts there are.
+        assert_eq!(policy.delay(100), Duration::from_secs(1));
+    }
+
+    /// Tests that the exponential backoff takes at most half of the delay off, and that it gives
+    /// up after the maximum number of attempts, or on errors that a retry cannot fix.
+    #[test]
+    fn test_exponential_backoff_should_retry() {
+        let policy = ExponentialBackoff::new(Duration::from_millis(100),
+                                             Duration::from_secs(1),
+                                             3);
+        for attempt in 1..3 {
+            let wait = policy.should_retry(&HttpError::UnableToConnect, attempt).unwrap();
+            assert!(wait <= policy.delay(attempt));
+            assert!(wait >= policy.delay(attempt) / 2);
+        }
+        assert_eq!(policy.should_retry(&HttpError::UnableToConnect, 3), None);
+        assert_eq!(policy.should_retry(&HttpError::Http11Required, 1), None);
+    }
+}
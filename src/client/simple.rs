@@ -1,6 +1,6 @@
 //! The module contains an implementation of a simple HTTP/2 client.
 
-use http::{StreamId, HttpResult, HttpError, Response, Header, HttpScheme};
+use http::{StreamId, HttpResult, HttpError, ErrorCode, Response, Header, HttpScheme};
 use http::transport::{TransportStream, TransportReceiveFrame};
 use http::connection::{HttpConnection, SendStatus};
 use http::session::{
@@ -11,6 +11,15 @@ use http::session::{
 };
 use http::session::Client as ClientMarker;
 use http::client::{ClientConnection, HttpConnect, RequestStream, ClientStream};
+use http::config::Http2Config;
+use http::checksum::{BodyHook, BodyCheck};
+use client::form::{encode_form, FORM_CONTENT_TYPE};
+#[cfg(feature="json")]
+use client::json::{self, JSON_CONTENT_TYPE};
+#[cfg(feature="json")]
+use serde::Serialize;
+#[cfg(feature="json")]
+use serde::de::DeserializeOwned;
 
 /// A struct implementing a simple HTTP/2 client.
 ///
@@ -118,9 +127,20 @@ impl<S> SimpleClient<S> where S: TransportStream {
     /// (by having the required protocol negotiation done and writing the client preface).
     pub fn with_stream(stream: S, host: String, scheme: HttpScheme)
             -> HttpResult<SimpleClient<S>> {
+        SimpleClient::with_stream_and_config(stream, host, scheme, Http2Config::default())
+    }
+
+    /// Creates a new `SimpleClient`, same as `with_stream`, but using the given configuration
+    /// for the connection.
+    ///
+    /// Since the preface is already written to the `stream`, the settings of the config that
+    /// differ from their defaults are sent in an additional SETTINGS frame.
+    pub fn with_stream_and_config(stream: S, host: String, scheme: HttpScheme, config: Http2Config)
+            -> HttpResult<SimpleClient<S>> {
         let state = DefaultSessionState::<ClientMarker, _>::new();
         let receiver = try!(stream.try_split());
-        let conn = HttpConnection::new(scheme);
+        let send_settings = !config.settings().is_empty();
+        let conn = HttpConnection::with_config(scheme, config);
         let mut client = SimpleClient {
             conn: ClientConnection::with_connection(conn, state),
             host: host.as_bytes().to_vec(),
@@ -128,6 +148,9 @@ impl<S> SimpleClient<S> where S: TransportStream {
             sender: stream,
         };
 
+        if send_settings {
+            try!(client.conn.send_settings(&mut client.sender));
+        }
         try!(client.init());
 
         Ok(client)
@@ -142,15 +165,23 @@ impl<S> SimpleClient<S> where S: TransportStream {
     /// Currently, it panics if the connector returns an error.
     pub fn with_connector<C>(connector: C) -> HttpResult<SimpleClient<S>>
             where C: HttpConnect<Stream=S> {
+        SimpleClient::with_connector_and_config(connector, Http2Config::default())
+    }
+
+    /// A convenience constructor that establishes the connection using the given connector, same
+    /// as `with_connector`, and configures it with the given `Http2Config`.
+    pub fn with_connector_and_config<C>(connector: C, config: Http2Config)
+            -> HttpResult<SimpleClient<S>>
+            where C: HttpConnect<Stream=S> {
         let ClientStream(stream, scheme, host) = try!(connector.connect());
-        SimpleClient::with_stream(stream, host, scheme)
+        SimpleClient::with_stream_and_config(stream, host, scheme, config)
     }
 
     /// Internal helper method that performs the initialization of the client's
     /// connection.
     #[inline]
     fn init(&mut self) -> HttpResult<()> {
-        self.conn.expect_settings(
+        self.conn.expect_preface(
             &mut TransportReceiveFrame::new(&mut self.receiver),
             &mut self.sender)
     }
@@ -161,7 +192,9 @@ impl<S> SimpleClient<S> where S: TransportStream {
     /// The request is described by the method, the path on which it should be
     /// invoked and the "real" headers that should be included. Clients should
     /// never put pseudo-headers in the `headers` parameter, as those are
-    /// automatically included based on metadata.
+    /// automatically included based on metadata. The headers may be of any
+    /// size (e.g. large signed tokens), as header blocks that do not fit in
+    /// a single frame are continued in CONTINUATION frames.
     ///
     /// # Returns
     ///
@@ -172,12 +205,30 @@ impl<S> SimpleClient<S> where S: TransportStream {
     /// Any IO errors are propagated.
     pub fn request(&mut self, method: &[u8], path: &[u8], extras: &[Header], body: Option<Vec<u8>>)
             -> HttpResult<StreamId> {
+        let host = self.host.clone();
+        self.request_with_authority(&host, method, path, extras, body)
+    }
+
+    /// Sends a request to the server, same as `request`, except that the request's `:authority`
+    /// is the given one, instead of the host that the client is connected to.
+    ///
+    /// This allows a single connection to carry requests for several authorities, e.g. when the
+    /// client is used by a reverse proxy. The authority is sent as it is given.
+    pub fn request_with_authority(&mut self,
+                                  authority: &[u8],
+                                  method: &[u8],
+                                  path: &[u8],
+                                  extras: &[Header],
+                                  body: Option<Vec<u8>>)
+                                  -> HttpResult<StreamId> {
         // Prepares the request stream
-        let stream = self.new_stream(method, path, extras, body);
+        let stream = self.new_stream(method, path, authority, extras, body);
         // Starts the request (i.e. sends out the headers)
         let stream_id = try!(self.conn.start_request(stream, &mut self.receiver));
         // TODO(mlalic): Remove when `Stream::on_id_assigned` is invoked by the session. 
-        self.conn.state.get_stream_mut(stream_id).unwrap().stream_id = Some(stream_id);
+        let stream = try!(self.conn.state.get_stream_mut(stream_id)
+                                         .ok_or(HttpError::UnknownStreamId));
+        stream.stream_id = Some(stream_id);
 
         // And now makes sure the data is sent out...
         // Note: Since for now there is no flow control, sending data will always continue
@@ -192,6 +243,23 @@ impl<S> SimpleClient<S> where S: TransportStream {
         Ok(stream_id)
     }
 
+    /// Sets the hook that processes the body of the response on the given stream as it is
+    /// received, e.g. to verify its digest (see `DefaultStream::set_body_hook`). Its result is
+    /// returned by `body_check` once the response is received.
+    ///
+    /// The hook needs to be set before the response is read, i.e. before `get_response`.
+    pub fn set_body_hook(&mut self, stream_id: StreamId, hook: Box<BodyHook>) -> HttpResult<()> {
+        let stream = try!(self.conn.state.get_stream_mut(stream_id)
+                                         .ok_or(HttpError::UnknownStreamId));
+        stream.set_body_hook(hook);
+        Ok(())
+    }
+
+    /// Returns the result of the body hook of the given stream, once its response is received.
+    pub fn body_check(&self, stream_id: StreamId) -> Option<BodyCheck> {
+        self.conn.state.get_stream_ref(stream_id).and_then(|stream| stream.body_check.clone())
+    }
+
     /// Gets the response for the stream with the given ID. If a valid stream ID
     /// is given, it blocks until a response is received.
     ///
@@ -201,6 +269,10 @@ impl<S> SimpleClient<S> where S: TransportStream {
     ///
     /// Any underlying IO errors are propagated. Errors in the HTTP/2 protocol
     /// also stop processing and are returned to the client.
+    ///
+    /// If the server requires the request to be made over HTTP/1.1 instead, an
+    /// `HttpError::Http11Required` error is returned. If the server sent a malformed header
+    /// block, an `HttpError::MalformedHeaders` error says why it is malformed.
     pub fn get_response(&mut self, stream_id: StreamId) -> HttpResult<Response<'static, 'static>> {
         match self.conn.state.get_stream_ref(stream_id) {
             None => return Err(HttpError::UnknownStreamId),
@@ -209,10 +281,21 @@ impl<S> SimpleClient<S> where S: TransportStream {
         loop {
             if let Some(stream) = self.conn.state.get_stream_ref(stream_id) {
                 if stream.is_closed() {
+                    if let Some(ref e) = stream.malformed_headers {
+                        return Err(HttpError::MalformedHeaders(e.clone()));
+                    }
+                    if stream.error_code == Some(ErrorCode::Http11Required) {
+                        return Err(HttpError::Http11Required);
+                    }
+                    // A stream can be closed (e.g. reset by the server) before any headers
+                    // arrive, in which case there is no valid response.
+                    let headers = try!(stream.headers.clone()
+                                                     .ok_or(HttpError::MalformedResponse));
                     return Ok(Response {
                         stream_id: stream_id,
-                        headers: stream.headers.clone().unwrap(),
+                        headers: headers,
                         body: stream.body.clone(),
+                        trailers: stream.trailers.clone().unwrap_or_default(),
                     });
                 }
             }
@@ -220,6 +303,48 @@ impl<S> SimpleClient<S> where S: TransportStream {
         }
     }
 
+    /// Gets the response headers for the stream with the given ID, blocking until they are
+    /// received, same as `get_response` does for the full response.
+    ///
+    /// If the response has a body that is still to come, the stream is reset with a RST_STREAM
+    /// frame carrying `ErrorCode::Cancel`, so that the server stops sending it; the returned
+    /// `Response` then has an empty body and no trailers. This allows probing the status code or
+    /// the metadata of a (large) resource without downloading it.
+    ///
+    /// # Returns
+    ///
+    /// The errors are the same as those of `get_response`.
+    pub fn get_response_headers(&mut self, stream_id: StreamId)
+            -> HttpResult<Response<'static, 'static>> {
+        match self.conn.state.get_stream_ref(stream_id) {
+            None => return Err(HttpError::UnknownStreamId),
+            Some(_) => {},
+        };
+        loop {
+            let (has_headers, closed) = match self.conn.state.get_stream_ref(stream_id) {
+                Some(stream) => (stream.headers.is_some(), stream.is_closed()),
+                None => return Err(HttpError::UnknownStreamId),
+            };
+            if closed {
+                return self.get_response(stream_id);
+            }
+            if has_headers {
+                debug!("Got the headers of stream {}, cancelling the body", stream_id);
+                try!(self.conn.reset_stream(stream_id, ErrorCode::Cancel, &mut self.sender));
+                return self.get_response(stream_id);
+            }
+            try!(self.handle_next_frame());
+        }
+    }
+
+    /// Performs a GET request on the given path, but only gets the response headers, cancelling
+    /// the response body (see `get_response_headers`).
+    pub fn get_headers(&mut self, path: &[u8], extra_headers: &[Header])
+            -> HttpResult<Response<'static, 'static>> {
+        let stream_id = try!(self.request(b"GET", path, extra_headers, None));
+        self.get_response_headers(stream_id)
+    }
+
     /// Performs a GET request on the given path. This is a shortcut method for
     /// calling `request` followed by `get_response` for the returned stream ID.
     pub fn get(&mut self, path: &[u8], extra_headers: &[Header])
@@ -235,6 +360,40 @@ impl<S> SimpleClient<S> where S: TransportStream {
         self.get_response(stream_id)
     }
 
+    /// Performs a POST request on the given path, submitting the given fields as a URL-encoded
+    /// form (see `encode_form`), as an HTML form would.
+    pub fn post_form(&mut self, path: &[u8], fields: &[(&str, &str)])
+            -> HttpResult<Response<'static, 'static>> {
+        let headers = [Header::new(&b"content-type"[..], FORM_CONTENT_TYPE)];
+        self.post(path, &headers, encode_form(fields))
+    }
+
+    /// Performs a POST request on the given path, with the given value serialized into a JSON
+    /// body. The response is returned as it is, since it need not be JSON.
+    ///
+    /// Only available with the `json` feature.
+    #[cfg(feature="json")]
+    pub fn post_json<T: Serialize>(&mut self, path: &[u8], value: &T)
+            -> HttpResult<Response<'static, 'static>> {
+        let body = try!(json::to_body(value));
+        let headers = [
+            Header::new(&b"content-type"[..], JSON_CONTENT_TYPE),
+            Header::new(b"accept", JSON_CONTENT_TYPE),
+        ];
+        self.post(path, &headers, body)
+    }
+
+    /// Performs a GET request on the given path and deserializes the JSON body of the response.
+    /// A response with a status other than 2xx is a `JsonError::UnexpectedStatus` error (wrapped
+    /// in an `HttpError::Other`), as is a body that is not the expected JSON a `JsonError::Serde`.
+    ///
+    /// Only available with the `json` feature.
+    #[cfg(feature="json")]
+    pub fn get_json<T: DeserializeOwned>(&mut self, path: &[u8]) -> HttpResult<T> {
+        let response = try!(self.get(path, &[Header::new(b"accept", JSON_CONTENT_TYPE)]));
+        json::from_response(&response)
+    }
+
     /// Internal helper method that prepares a new `RequestStream` instance based on the given
     /// request parameters.
     ///
@@ -244,6 +403,7 @@ impl<S> SimpleClient<S> where S: TransportStream {
             &self,
             method: &'v [u8],
             path: &'v [u8],
+            authority: &'v [u8],
             extras: &[Header<'n, 'v>],
             body: Option<Vec<u8>>)
             -> RequestStream<'n, 'v, DefaultStream> {
@@ -256,7 +416,7 @@ impl<S> SimpleClient<S> where S: TransportStream {
         let mut headers: Vec<Header> = vec![
             Header::new(b":method", method),
             Header::new(b":path", path),
-            Header::new(b":authority", self.host.clone()),
+            Header::new(b":authority", authority),
             Header::new(b":scheme", self.conn.scheme().as_bytes().to_vec()),
         ];
         // The clone is lightweight if the original Header was just borrowing something; it's a
@@ -103,4 +103,221 @@ mod async {
         let body = str::from_utf8(&res.body).unwrap();
         assert!(body.contains("Hello, World!"));
     }
+
+    #[test]
+    fn test_live_preconnect() {
+        let client = Client::with_connector(CleartextConnector::new("http2bin.org")).unwrap();
+
+        client.preconnect().unwrap();
+        // Once connected, subsequent calls return immediately.
+        client.preconnect().unwrap();
+    }
+
+    #[test]
+    fn test_live_stats() {
+        let host = "http2bin.org";
+        let client = Client::with_connector(CleartextConnector::new(host)).unwrap();
+
+        let res = client.get(b"/get", &[]).unwrap();
+        res.recv().unwrap();
+
+        let stats = client.stats().unwrap();
+        assert_eq!(stats.in_flight, 0);
+        assert_eq!(stats.queued, 0);
+    }
+}
+
+mod stub {
+    use hpack;
+
+    use http::{HttpError, HttpScheme, ErrorCode};
+    use http::connection::HttpFrame;
+    use http::frame::{SettingsFrame, HeadersFrame, HeadersFlag, DataFrame, DataFlag, Frame};
+    use http::checksum::{BodyCheck, DigestHook};
+    use http::validation::MalformedHeaders;
+    use http::tests::common::{StubTransportStream, build_stub_from_frames, parse_frames};
+    use client::SimpleClient;
+
+    /// Tests that a request sent with an explicit authority carries it, rather than the host that
+    /// the client is connected to.
+    #[test]
+    fn test_simple_request_with_authority() {
+        let server_preface = build_stub_from_frames(&vec![
+            HttpFrame::SettingsFrame(SettingsFrame::new()),
+        ]);
+        let stream = StubTransportStream::with_stub_content(&server_preface);
+        let mut client = SimpleClient::with_stream(stream.clone(),
+                                                   "proxy.lcl".to_string(),
+                                                   HttpScheme::Http).unwrap();
+
+        client.request(b"GET", b"/", &[], None).unwrap();
+        client.request_with_authority(b"backend.example.com:8080", b"GET", b"/", &[], None)
+              .unwrap();
+
+        let mut decoder = hpack::Decoder::new();
+        let authorities = parse_frames(&stream.get_written()).iter().filter_map(|raw| {
+            match HttpFrame::from_raw(raw) {
+                Ok(HttpFrame::HeadersFrame(frame)) => Some(frame),
+                _ => None,
+            }
+        }).map(|frame| {
+            let headers = decoder.decode(&frame.header_fragment()).unwrap();
+            headers.into_iter().find(|&(ref name, _)| name == b":authority").unwrap().1
+        }).collect::<Vec<_>>();
+        assert_eq!(authorities, vec![b"proxy.lcl".to_vec(), b"backend.example.com:8080".to_vec()]);
+    }
+
+    /// Tests that getting only the headers of a response cancels the stream once they arrive,
+    /// without reading the body that follows them.
+    #[test]
+    fn test_simple_get_headers() {
+        let fragment = hpack::Encoder::new().encode(vec![(&b":status"[..], &b"200"[..])]);
+        let mut headers = HeadersFrame::new(fragment, 1);
+        headers.set_flag(HeadersFlag::EndHeaders);
+        let server_preface = build_stub_from_frames(&vec![
+            HttpFrame::SettingsFrame(SettingsFrame::new()),
+            HttpFrame::HeadersFrame(headers),
+            HttpFrame::DataFrame(DataFrame::with_data(1, &b"a large body"[..])),
+        ]);
+        let stream = StubTransportStream::with_stub_content(&server_preface);
+        let mut client = SimpleClient::with_stream(stream.clone(),
+                                                   "example.com".to_string(),
+                                                   HttpScheme::Http).unwrap();
+
+        let response = client.get_headers(b"/large", &[]).unwrap();
+
+        assert_eq!(response.status_code().unwrap(), 200);
+        assert!(response.body.is_empty());
+        let resets = parse_frames(&stream.get_written()).iter().filter_map(|raw| {
+            match HttpFrame::from_raw(raw) {
+                Ok(HttpFrame::RstStreamFrame(frame)) => {
+                    Some((frame.get_stream_id(), frame.error_code()))
+                },
+                _ => None,
+            }
+        }).collect::<Vec<_>>();
+        assert_eq!(resets, vec![(1, ErrorCode::Cancel)]);
+    }
+
+    /// Tests that the result of a body hook is available once the response is received.
+    #[test]
+    fn test_simple_body_hook() {
+        let fragment = hpack::Encoder::new().encode(vec![(&b":status"[..], &b"200"[..])]);
+        let mut headers = HeadersFrame::new(fragment, 1);
+        headers.set_flag(HeadersFlag::EndHeaders);
+        let mut data = DataFrame::with_data(1, &b"hello"[..]);
+        data.set_flag(DataFlag::EndStream);
+        let server_preface = build_stub_from_frames(&vec![
+            HttpFrame::SettingsFrame(SettingsFrame::new()),
+            HttpFrame::HeadersFrame(headers),
+            HttpFrame::DataFrame(data),
+        ]);
+        let stream = StubTransportStream::with_stub_content(&server_preface);
+        let mut client = SimpleClient::with_stream(stream,
+                                                   "example.com".to_string(),
+                                                   HttpScheme::Http).unwrap();
+
+        let stream_id = client.request(b"GET", b"/", &[], None).unwrap();
+        client.set_body_hook(stream_id, Box::new(DigestHook::new())).unwrap();
+        assert_eq!(client.body_check(stream_id), None);
+        let response = client.get_response(stream_id).unwrap();
+
+        assert_eq!(response.body, b"hello".to_vec());
+        let digest = match client.body_check(stream_id) {
+            Some(BodyCheck::Computed(digest)) => digest,
+            check => panic!("Expected a computed digest, got {:?}", check),
+        };
+        assert_eq!(digest[..4], [0x2c, 0xf2, 0x4d, 0xba]);
+    }
+
+    /// Tests that a response with malformed headers fails with an error saying why.
+    #[test]
+    fn test_simple_malformed_response() {
+        let fragment = hpack::Encoder::new().encode(vec![(&b":status"[..], &b"2000"[..])]);
+        let mut headers = HeadersFrame::new(fragment, 1);
+        headers.set_flag(HeadersFlag::EndHeaders);
+        let server_preface = build_stub_from_frames(&vec![
+            HttpFrame::SettingsFrame(SettingsFrame::new()),
+            HttpFrame::HeadersFrame(headers),
+        ]);
+        let stream = StubTransportStream::with_stub_content(&server_preface);
+        let mut client = SimpleClient::with_stream(stream,
+                                                   "example.com".to_string(),
+                                                   HttpScheme::Http).unwrap();
+
+        let err = client.get(b"/", &[]).err().unwrap();
+
+        assert_eq!(err, HttpError::MalformedHeaders(MalformedHeaders::InvalidStatus));
+    }
+
+    /// Returns a stub server's frames that respond to the first request with the given body.
+    fn stub_response(body: &[u8]) -> Vec<u8> {
+        let fragment = hpack::Encoder::new().encode(vec![(&b":status"[..], &b"200"[..])]);
+        let mut headers = HeadersFrame::new(fragment, 1);
+        headers.set_flag(HeadersFlag::EndHeaders);
+        let mut data = DataFrame::with_data(1, body);
+        data.set_flag(DataFlag::EndStream);
+        build_stub_from_frames(&vec![
+            HttpFrame::SettingsFrame(SettingsFrame::new()),
+            HttpFrame::HeadersFrame(headers),
+            HttpFrame::DataFrame(data),
+        ])
+    }
+
+    /// Returns the headers and the body of the request that the client wrote out.
+    fn written_request(written: &[u8]) -> (Vec<(Vec<u8>, Vec<u8>)>, Vec<u8>) {
+        let mut headers = Vec::new();
+        let mut body = Vec::new();
+        for raw in parse_frames(written) {
+            match HttpFrame::from_raw(&raw) {
+                Ok(HttpFrame::HeadersFrame(frame)) => {
+                    headers = hpack::Decoder::new().decode(&frame.header_fragment()).unwrap();
+                },
+                Ok(HttpFrame::DataFrame(frame)) => body.extend_from_slice(&frame.data),
+                _ => {},
+            }
+        }
+        (headers, body)
+    }
+
+    /// Tests that a form is posted URL-encoded, with the matching content type.
+    #[test]
+    fn test_simple_post_form() {
+        let stream = StubTransportStream::with_stub_content(&stub_response(b"ok"));
+        let mut client = SimpleClient::with_stream(stream.clone(),
+                                                   "example.com".to_string(),
+                                                   HttpScheme::Http).unwrap();
+
+        let response = client.post_form(b"/login", &[("user", "jo"), ("pass", "a&b c")]).unwrap();
+
+        assert_eq!(response.body, b"ok".to_vec());
+        let (headers, body) = written_request(&stream.get_written());
+        assert!(headers.contains(&(b":method".to_vec(), b"POST".to_vec())));
+        assert!(headers.contains(&(b"content-type".to_vec(),
+                                   b"application/x-www-form-urlencoded".to_vec())));
+        assert_eq!(body, b"user=jo&pass=a%26b+c".to_vec());
+    }
+
+    /// Tests that the JSON helpers announce JSON and (de)serialize the bodies.
+    #[cfg(feature="json")]
+    #[test]
+    fn test_simple_json() {
+        let stream = StubTransportStream::with_stub_content(&stub_response(b"[1, 2, 3]"));
+        let mut client = SimpleClient::with_stream(stream.clone(),
+                                                   "example.com".to_string(),
+                                                   HttpScheme::Http).unwrap();
+        let numbers: Vec<u32> = client.get_json(b"/numbers").unwrap();
+        assert_eq!(numbers, vec![1, 2, 3]);
+        let (headers, _) = written_request(&stream.get_written());
+        assert!(headers.contains(&(b"accept".to_vec(), b"application/json".to_vec())));
+
+        let stream = StubTransportStream::with_stub_content(&stub_response(b"{}"));
+        let mut client = SimpleClient::with_stream(stream.clone(),
+                                                   "example.com".to_string(),
+                                                   HttpScheme::Http).unwrap();
+        client.post_json(b"/numbers", &vec![4, 5]).unwrap();
+        let (headers, body) = written_request(&stream.get_written());
+        assert!(headers.contains(&(b"content-type".to_vec(), b"application/json".to_vec())));
+        assert_eq!(body, b"[4,5]".to_vec());
+    }
 }
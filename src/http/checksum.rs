@@ -0,0 +1,338 @@
+//! The module defines the hooks that process the body of a response incrementally, as its chunks
+//! arrive, e.g. to compute a checksum of it or to verify it against the one that the response
+//! headers announced (see `DefaultStream::set_body_hook` and `DelegateStream::set_body_hook`).
+//!
+//! Once the full body is received, the hook's `BodyCheck` is stored alongside the response. The
+//! `DigestHook` computes the SHA-256 digest of the body, verifying it against the `digest` header
+//! (RFC 3230), if the response carries a `SHA-256` one.
+use http::Header;
+
+/// The result of a `BodyHook`, once the full body was processed.
+#[derive(Clone, PartialEq, Debug)]
+pub enum BodyCheck {
+    /// The hook computed the given value (e.g. a digest) over the body, but had nothing to
+    /// verify it against.
+    Computed(Vec<u8>),
+    /// The value computed over the body matched the expected one, given here.
+    Verified(Vec<u8>),
+    /// The value computed over the body did not match the expected one.
+    Mismatch {
+        /// The value that the response announced.
+        expected: Vec<u8>,
+        /// The value computed over the body that was received.
+        actual: Vec<u8>,
+    },
+}
+
+impl BodyCheck {
+    /// Returns whether the body failed the check.
+    pub fn is_mismatch(&self) -> bool {
+        match *self {
+            BodyCheck::Mismatch { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+/// The trait is implemented by the hooks that process the body of a response as it arrives.
+pub trait BodyHook: Send {
+    /// Invoked with the headers of the response, before any of its body.
+    ///
+    /// The default implementation ignores them.
+    fn on_headers(&mut self, _headers: &[Header]) {}
+    /// Invoked for every chunk of the body.
+    fn on_data(&mut self, data: &[u8]);
+    /// Invoked once the full body is received. The hook is not used afterwards.
+    fn finish(&mut self) -> BodyCheck;
+    /// Returns a copy of the hook in its current state, so that the streams that carry a hook
+    /// can be cloned.
+    fn box_clone(&self) -> Box<BodyHook>;
+}
+
+impl Clone for Box<BodyHook> {
+    fn clone(&self) -> Box<BodyHook> {
+        self.box_clone()
+    }
+}
+
+/// A `BodyHook` computing the SHA-256 digest of the body.
+///
+/// If the response carries a `digest` header with a `SHA-256` value, the digest of the body is
+/// verified against it; otherwise, the computed digest is returned as it is.
+#[derive(Clone, Default)]
+pub struct DigestHook {
+    sha256: Sha256,
+    expected: Option<Vec<u8>>,
+}
+
+impl DigestHook {
+    /// Creates a new `DigestHook`.
+    pub fn new() -> DigestHook {
+        DigestHook::default()
+    }
+}
+
+impl BodyHook for DigestHook {
+    fn on_headers(&mut self, headers: &[Header]) {
+        self.expected = headers.iter()
+                               .filter(|h| h.name() == b"digest")
+                               .filter_map(|h| expected_sha256(h.value()))
+                               .next();
+    }
+
+    fn on_data(&mut self, data: &[u8]) {
+        self.sha256.update(data);
+    }
+
+    fn finish(&mut self) -> BodyCheck {
+        let actual = self.sha256.clone().finish().to_vec();
+        match self.expected.take() {
+            Some(ref expected) if *expected == actual => BodyCheck::Verified(actual),
+            Some(expected) => BodyCheck::Mismatch { expected: expected, actual: actual },
+            None => BodyCheck::Computed(actual),
+        }
+    }
+
+    fn box_clone(&self) -> Box<BodyHook> {
+        Box::new(self.clone())
+    }
+}
+
+/// Extracts the SHA-256 digest from the value of a `digest` header (a comma-separated list of
+/// `algorithm=base64-value` pairs), if it has one.
+fn expected_sha256(value: &[u8]) -> Option<Vec<u8>> {
+    value.split(|&b| b == b',').filter_map(|pair| {
+        let eq = match pair.iter().position(|&b| b == b'=') {
+            Some(eq) => eq,
+            None => return None,
+        };
+        let algorithm = trim(&pair[..eq]);
+        if !algorithm.eq_ignore_ascii_case(b"sha-256") {
+            return None;
+        }
+        decode_base64(trim(&pair[eq + 1..]))
+    }).next()
+}
+
+/// Returns the given bytes without the leading and trailing whitespace.
+fn trim(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|&b| b != b' ' && b != b'\t').unwrap_or(bytes.len());
+    let end = bytes.iter().rposition(|&b| b != b' ' && b != b'\t').map_or(start, |pos| pos + 1);
+    &bytes[start..end]
+}
+
+/// Decodes the given (padded) base64, returning `None` if it is not valid.
+fn decode_base64(encoded: &[u8]) -> Option<Vec<u8>> {
+    if encoded.len() % 4 != 0 {
+        return None;
+    }
+    let unpadded = encoded.iter().rposition(|&b| b != b'=').map_or(0, |pos| pos + 1);
+    if encoded.len() - unpadded > 2 {
+        return None;
+    }
+    let mut decoded = Vec::with_capacity(encoded.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut nbits = 0;
+    for &b in &encoded[..unpadded] {
+        let value = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        bits = (bits << 6) | value as u32;
+        nbits += 6;
+        if nbits >= 8 {
+            nbits -= 8;
+            decoded.push((bits >> nbits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// The round constants of SHA-256.
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// The initial hash value of SHA-256.
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// An incremental SHA-256 hasher (FIPS 180-4).
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    /// The bytes of the current block that are yet to be processed.
+    block: [u8; 64],
+    block_len: usize,
+    /// The total number of bytes hashed.
+    len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Sha256 {
+        Sha256 {
+            state: H0,
+            block: [0; 64],
+            block_len: 0,
+            len: 0,
+        }
+    }
+}
+
+impl Sha256 {
+    /// Creates a new hasher.
+    pub fn new() -> Sha256 {
+        Sha256::default()
+    }
+
+    /// Hashes the given bytes.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let take = ::std::cmp::min(64 - self.block_len, data.len());
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+            if self.block_len == 64 {
+                let block = self.block;
+                self.compress(&block);
+                self.block_len = 0;
+            }
+        }
+    }
+
+    /// Returns the digest of all the bytes that were hashed.
+    pub fn finish(mut self) -> [u8; 32] {
+        let bit_len = self.len.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        let len_bytes = [
+            (bit_len >> 56) as u8, (bit_len >> 48) as u8, (bit_len >> 40) as u8,
+            (bit_len >> 32) as u8, (bit_len >> 24) as u8, (bit_len >> 16) as u8,
+            (bit_len >> 8) as u8, bit_len as u8,
+        ];
+        self.update(&len_bytes);
+
+        let mut digest = [0; 32];
+        for (i, word) in self.state.iter().enumerate() {
+            digest[4 * i] = (word >> 24) as u8;
+            digest[4 * i + 1] = (word >> 16) as u8;
+            digest[4 * i + 2] = (word >> 8) as u8;
+            digest[4 * i + 3] = *word as u8;
+        }
+        digest
+    }
+
+    /// Processes a single 64-byte block.
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = (block[4 * i] as u32) << 24 | (block[4 * i + 1] as u32) << 16 |
+                   (block[4 * i + 2] as u32) << 8 | block[4 * i + 3] as u32;
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let mut v = self.state;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7].wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+            v = [t1.wrapping_add(t2), v[0], v[1], v[2], v[3].wrapping_add(t1), v[4], v[5], v[6]];
+        }
+        for (state, value) in self.state.iter_mut().zip(v.iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BodyHook, BodyCheck, DigestHook, Sha256, decode_base64};
+    use http::Header;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Tests the hasher against the test vectors of FIPS 180-4, fed in chunks of varying sizes.
+    #[test]
+    fn test_sha256() {
+        assert_eq!(hex(&Sha256::new().finish()),
+                   "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        let mut sha = Sha256::new();
+        sha.update(b"a");
+        sha.update(b"bc");
+        assert_eq!(hex(&sha.finish()),
+                   "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        let mut sha = Sha256::new();
+        for chunk in b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq".chunks(7) {
+            sha.update(chunk);
+        }
+        assert_eq!(hex(&sha.finish()),
+                   "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+    }
+
+    #[test]
+    fn test_decode_base64() {
+        assert_eq!(decode_base64(b"aGVsbG8="), Some(b"hello".to_vec()));
+        assert_eq!(decode_base64(b"aGVsbG8h"), Some(b"hello!".to_vec()));
+        assert_eq!(decode_base64(b"aGVsbA=="), Some(b"hell".to_vec()));
+        assert_eq!(decode_base64(b""), Some(vec![]));
+        assert_eq!(decode_base64(b"aGVsbG8"), None);
+        assert_eq!(decode_base64(b"aGV*bG8="), None);
+    }
+
+    /// Tests that the digest hook verifies the body against the `digest` header, if there is one.
+    #[test]
+    fn test_digest_hook() {
+        // The SHA-256 digest of "hello".
+        let digest = b"sha-256=LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=".to_vec();
+        let mut hook = DigestHook::new();
+        hook.on_headers(&[Header::new(b":status", b"200"),
+                          Header::new(b"digest".to_vec(), b"md5=xyz==, ".to_vec())]);
+        hook.on_data(b"hel");
+        hook.on_data(b"lo");
+        let computed = match hook.finish() {
+            BodyCheck::Computed(computed) => computed,
+            check => panic!("Expected a computed digest, got {:?}", check),
+        };
+        assert_eq!(hex(&computed),
+                   "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+
+        let mut hook = DigestHook::new();
+        hook.on_headers(&[Header::new(b"digest".to_vec(), digest.clone())]);
+        hook.on_data(b"hello");
+        assert_eq!(hook.finish(), BodyCheck::Verified(computed.clone()));
+
+        let mut hook = DigestHook::new();
+        hook.on_headers(&[Header::new(b"digest".to_vec(), digest)]);
+        let mut copy = hook.box_clone();
+        hook.on_data(b"hello!");
+        let check = hook.finish();
+        assert!(check.is_mismatch());
+        // The copy does not see what the original hook processed.
+        copy.on_data(b"hello");
+        assert!(!copy.finish().is_mismatch());
+    }
+}
@@ -0,0 +1,438 @@
+//! The module implements streaming of responses to a `ResponseDelegate`.
+//!
+//! The `DelegateStream` hands each part of the response to its delegate as soon as it arrives:
+//! the head (borrowing the headers that were just decoded), the chunks of the body and the
+//! trailers. Nothing is buffered by the stream itself, so delegates that process the response as
+//! it arrives never pay for copies of it. Those that need a fully-owned `StaticResponse` can use
+//! the `ResponseCollector` delegate.
+use http::{Header, ErrorCode, StreamId, ConnectionId};
+use http::{ResponseHead, StaticResponse, Response, OwnedHeader};
+use http::session::{
+    Stream,
+    DefaultStream,
+    StreamState,
+    StreamDataChunk,
+    StreamDataError,
+};
+use http::validation::{MalformedHeaders, BodyLength};
+use http::checksum::{BodyHook, BodyCheck};
+
+/// The trait is implemented by types that process responses as they are received.
+pub trait ResponseDelegate {
+    /// Invoked once the head of the response is received.
+    fn on_head(&mut self, head: &ResponseHead);
+    /// Invoked for every chunk of the response body.
+    fn on_data(&mut self, data: &[u8]);
+    /// Invoked if the response carries trailers.
+    ///
+    /// The default implementation ignores them.
+    fn on_trailers(&mut self, _trailers: &[Header]) {}
+    /// Invoked with the result of the stream's body hook (see `DelegateStream::set_body_hook`)
+    /// once the full response is received, right before `on_end`.
+    ///
+    /// The default implementation ignores it.
+    fn on_body_check(&mut self, _check: &BodyCheck) {}
+    /// Invoked once the full response is received.
+    ///
+    /// The default implementation does nothing.
+    fn on_end(&mut self) {}
+    /// Invoked if the stream is reset before the full response is received, either by the peer
+    /// or because the response turned out to be malformed (with a `PROTOCOL_ERROR`).
+    ///
+    /// The default implementation does nothing.
+    fn on_reset(&mut self, _error_code: ErrorCode) {}
+    /// Invoked instead of `on_reset` if the stream is reset because the peer sent a malformed
+    /// header block, with the reason why it is malformed.
+    ///
+    /// The default implementation calls `on_reset` with a `PROTOCOL_ERROR`.
+    fn on_malformed_headers(&mut self, _error: &MalformedHeaders) {
+        self.on_reset(ErrorCode::ProtocolError);
+    }
+}
+
+/// A `Stream` that passes the response that it receives on to a `ResponseDelegate`.
+///
+/// The request body (if any) is sent in the same way as by the `DefaultStream`. The `stream_id`
+/// should be set once the connection assigns it (e.g. as returned by `start_request`); until then,
+/// the heads handed to the delegate carry a stream ID of 0. Likewise, setting the `connection_id`
+/// (e.g. to `ClientConnection::id`) attributes the heads to the connection.
+pub struct DelegateStream<D> where D: ResponseDelegate {
+    /// The ID of the stream, if already assigned by the connection.
+    pub stream_id: Option<StreamId>,
+    /// The ID of the connection that the stream belongs to, if set.
+    pub connection_id: Option<ConnectionId>,
+    delegate: D,
+    /// The hook that processes the body as it is received, if any.
+    body_hook: Option<Box<BodyHook>>,
+    /// Tracks the state and the outgoing data of the stream.
+    request: DefaultStream,
+    head_received: bool,
+    /// Whether the delegate was notified that the response is over (or reset).
+    finished: bool,
+}
+
+impl<D> DelegateStream<D> where D: ResponseDelegate {
+    /// Creates a new `DelegateStream` passing the response on to the given delegate.
+    pub fn new(delegate: D) -> DelegateStream<D> {
+        DelegateStream {
+            stream_id: None,
+            connection_id: None,
+            delegate: delegate,
+            body_hook: None,
+            request: DefaultStream::new(),
+            head_received: false,
+            finished: false,
+        }
+    }
+
+    /// Sets the body of the request.
+    pub fn set_full_data(&mut self, data: Vec<u8>) {
+        self.request.set_full_data(data);
+    }
+
+    /// Sets the hook that processes the body as it is received, e.g. to verify its digest. Its
+    /// result is handed to the delegate's `on_body_check` once the full response is received.
+    pub fn set_body_hook(&mut self, hook: Box<BodyHook>) {
+        self.body_hook = Some(hook);
+    }
+
+    /// Returns a reference to the delegate.
+    pub fn delegate(&self) -> &D {
+        &self.delegate
+    }
+
+    /// Returns the delegate, consuming the stream.
+    pub fn into_delegate(self) -> D {
+        self.delegate
+    }
+
+    /// Notifies the delegate that the stream is reset, unless it is already finished.
+    fn reset(&mut self, error_code: ErrorCode) {
+        if !self.finished {
+            self.finished = true;
+            self.delegate.on_reset(error_code);
+        }
+    }
+}
+
+impl<D> Stream for DelegateStream<D> where D: ResponseDelegate {
+    fn new_data_chunk(&mut self, data: &[u8]) {
+        if let Some(ref mut hook) = self.body_hook {
+            hook.on_data(data);
+        }
+        self.delegate.on_data(data);
+    }
+
+    fn set_headers<'n, 'v>(&mut self, headers: Vec<Header<'n, 'v>>) {
+        if self.head_received {
+            self.delegate.on_trailers(&headers);
+            return;
+        }
+        match ResponseHead::new(self.stream_id.unwrap_or(0), headers) {
+            Ok(mut head) => {
+                head.connection_id = self.connection_id;
+                self.head_received = true;
+                if let Some(ref mut hook) = self.body_hook {
+                    hook.on_headers(&head.headers);
+                }
+                self.delegate.on_head(&head);
+            },
+            Err(_) => {
+                // Only possible if the session did not validate the headers.
+                debug!("Response without a valid status; closing the stream");
+                self.finished = true;
+                self.close();
+            },
+        }
+    }
+
+    fn set_state(&mut self, state: StreamState) {
+        self.request.set_state(state);
+        if self.head_received && !self.finished && self.is_closed_remote() {
+            self.finished = true;
+            if let Some(mut hook) = self.body_hook.take() {
+                self.delegate.on_body_check(&hook.finish());
+            }
+            self.delegate.on_end();
+        }
+    }
+
+    fn on_rst_stream(&mut self, error_code: ErrorCode) {
+        self.reset(error_code);
+        self.close();
+    }
+
+    fn on_malformed_headers(&mut self, error: &MalformedHeaders) {
+        if !self.finished {
+            self.finished = true;
+            self.delegate.on_malformed_headers(error);
+        }
+    }
+
+    fn on_body_length_mismatch(&mut self, _length: &BodyLength) {
+        self.reset(ErrorCode::ProtocolError);
+    }
+
+    fn headers_received(&self) -> bool { self.head_received }
+
+    fn state(&self) -> StreamState { self.request.state() }
+
+    fn get_data_chunk(&mut self, buf: &mut [u8]) -> Result<StreamDataChunk, StreamDataError> {
+        let chunk = self.request.get_data_chunk(buf);
+        if let Ok(StreamDataChunk::Last(_)) = chunk {
+            // The `DefaultStream` closed itself, which needs to be reflected here as well.
+            let state = self.request.state();
+            self.set_state(state);
+        }
+        chunk
+    }
+}
+
+/// A `ResponseDelegate` that collects the full response, so that it can be turned into a
+/// `StaticResponse` once received.
+#[derive(Clone, Default)]
+pub struct ResponseCollector {
+    head: Option<ResponseHead<'static, 'static>>,
+    body: Vec<u8>,
+    trailers: Vec<Header<'static, 'static>>,
+    body_check: Option<BodyCheck>,
+    complete: bool,
+}
+
+impl ResponseCollector {
+    /// Creates a new `ResponseCollector`.
+    pub fn new() -> ResponseCollector {
+        ResponseCollector::default()
+    }
+
+    /// Returns whether the full response was received.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Returns the result of the stream's body hook, if it had one and the full response was
+    /// received.
+    pub fn body_check(&self) -> Option<&BodyCheck> {
+        self.body_check.as_ref()
+    }
+
+    /// Returns the collected response, if its head was received.
+    pub fn into_response(self) -> Option<StaticResponse> {
+        let body = self.body;
+        let trailers = self.trailers;
+        self.head.map(|head| Response::from_parts(head, body, trailers))
+    }
+}
+
+impl ResponseDelegate for ResponseCollector {
+    fn on_head(&mut self, head: &ResponseHead) {
+        self.head = Some(head.clone().into_owned());
+    }
+
+    fn on_data(&mut self, data: &[u8]) {
+        self.body.extend_from_slice(data);
+    }
+
+    fn on_trailers(&mut self, trailers: &[Header]) {
+        self.trailers = trailers.iter().map(|h| {
+            let owned: OwnedHeader = h.clone().into();
+            owned.into()
+        }).collect();
+    }
+
+    fn on_body_check(&mut self, check: &BodyCheck) {
+        self.body_check = Some(check.clone());
+    }
+
+    fn on_end(&mut self) {
+        self.complete = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DelegateStream, ResponseDelegate, ResponseCollector};
+    use http::{Header, ErrorCode, ResponseHead, ConnectionId};
+    use http::client::ClientSession;
+    use http::session::{Session, SessionState, DefaultSessionState, Stream, StreamDataChunk};
+    use http::session::Client as ClientMarker;
+    use http::tests::common::{build_mock_http_conn, MockSendFrame};
+    use http::checksum::{BodyCheck, DigestHook};
+    use http::validation::MalformedHeaders;
+
+    /// A delegate that records the calls it receives.
+    #[derive(Default)]
+    struct RecordingDelegate {
+        calls: Vec<String>,
+    }
+
+    impl ResponseDelegate for RecordingDelegate {
+        fn on_head(&mut self, head: &ResponseHead) {
+            match head.connection_id {
+                Some(id) => self.calls.push(format!("head {} on {}", head.status, id)),
+                None => self.calls.push(format!("head {}", head.status)),
+            }
+        }
+        fn on_data(&mut self, data: &[u8]) {
+            self.calls.push(format!("data {}", data.len()));
+        }
+        fn on_trailers(&mut self, trailers: &[Header]) {
+            self.calls.push(format!("trailers {}", trailers.len()));
+        }
+        fn on_end(&mut self) {
+            self.calls.push("end".to_string());
+        }
+        fn on_reset(&mut self, error_code: ErrorCode) {
+            self.calls.push(format!("reset {:?}", error_code));
+        }
+        fn on_malformed_headers(&mut self, error: &MalformedHeaders) {
+            self.calls.push(format!("malformed {}", error));
+        }
+    }
+
+    /// A delegate that only records resets, handling malformed headers as a reset by default.
+    #[derive(Default)]
+    struct ResetDelegate {
+        resets: Vec<ErrorCode>,
+    }
+
+    impl ResponseDelegate for ResetDelegate {
+        fn on_head(&mut self, _head: &ResponseHead) {}
+        fn on_data(&mut self, _data: &[u8]) {}
+        fn on_reset(&mut self, error_code: ErrorCode) {
+            self.resets.push(error_code);
+        }
+    }
+
+    /// Tests that the parts of the response are handed to the delegate as they arrive.
+    #[test]
+    fn test_delegate_stream_streams_response() {
+        let mut stream = DelegateStream::new(RecordingDelegate::default());
+        stream.stream_id = Some(1);
+        stream.close_local();
+
+        stream.set_headers(vec![Header::new(b":status", b"200")]);
+        stream.new_data_chunk(b"abc");
+        stream.new_data_chunk(b"de");
+        stream.set_headers(vec![Header::new(b"grpc-status".to_vec(), b"0".to_vec())]);
+        stream.close_remote();
+
+        assert!(stream.is_closed());
+        assert_eq!(stream.into_delegate().calls,
+                   vec!["head 200", "data 3", "data 2", "trailers 1", "end"]);
+    }
+
+    /// Tests that the heads handed to the delegate are attributed to the stream's connection.
+    #[test]
+    fn test_delegate_stream_connection_id() {
+        let mut stream = DelegateStream::new(RecordingDelegate::default());
+        stream.stream_id = Some(1);
+        stream.connection_id = Some(ConnectionId(3));
+
+        stream.set_headers(vec![Header::new(b":status", b"204")]);
+        assert_eq!(stream.into_delegate().calls, vec!["head 204 on conn-3"]);
+    }
+
+    /// Tests that a reset stream notifies the delegate only of the reset.
+    #[test]
+    fn test_delegate_stream_reset() {
+        let mut stream = DelegateStream::new(RecordingDelegate::default());
+        stream.set_headers(vec![Header::new(b":status", b"200")]);
+        stream.on_rst_stream(ErrorCode::Cancel);
+
+        assert!(stream.is_closed());
+        assert_eq!(stream.into_delegate().calls, vec!["head 200", "reset Cancel"]);
+    }
+
+    /// Tests that a response that the `ClientSession` finds malformed is reported to the delegate
+    /// along with the reason, or as a reset by delegates that do not handle it.
+    #[test]
+    fn test_delegate_stream_malformed_response() {
+        let mut state = DefaultSessionState::<ClientMarker, _>::new();
+        state.insert_outgoing(DelegateStream::new(RecordingDelegate::default()));
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        {
+            let mut session = ClientSession::new(&mut state, &mut sender);
+            let headers = vec![Header::new(b"x-no-status".to_vec(), b"1".to_vec())];
+            session.new_headers(1, headers, &mut conn).unwrap();
+        }
+
+        let stream = state.remove_stream(1).unwrap();
+        assert!(stream.is_closed());
+        assert_eq!(stream.into_delegate().calls, vec!["malformed Missing pseudo-header :status"]);
+
+        let mut state = DefaultSessionState::<ClientMarker, _>::new();
+        state.insert_outgoing(DelegateStream::new(ResetDelegate::default()));
+        {
+            let mut session = ClientSession::new(&mut state, &mut sender);
+            let headers = vec![Header::new(b"x-no-status".to_vec(), b"1".to_vec())];
+            session.new_headers(1, headers, &mut conn).unwrap();
+        }
+        let stream = state.remove_stream(1).unwrap();
+        assert_eq!(stream.into_delegate().resets, vec![ErrorCode::ProtocolError]);
+    }
+
+    /// Tests that the `DelegateStream` sends the request body like the `DefaultStream`.
+    #[test]
+    fn test_delegate_stream_request_body() {
+        let mut stream = DelegateStream::new(RecordingDelegate::default());
+        stream.set_full_data(vec![1, 2, 3]);
+        let mut buf = [0; 2];
+
+        assert_eq!(stream.get_data_chunk(&mut buf).ok().unwrap(), StreamDataChunk::Chunk(2));
+        assert_eq!(stream.get_data_chunk(&mut buf).ok().unwrap(), StreamDataChunk::Last(1));
+        assert!(stream.is_closed_local());
+        assert!(!stream.is_closed_remote());
+    }
+
+    /// Tests that the `ResponseCollector` turns the streamed response into a `StaticResponse`.
+    #[test]
+    fn test_response_collector() {
+        let mut stream = DelegateStream::new(ResponseCollector::new());
+        stream.stream_id = Some(3);
+        stream.set_headers(vec![
+            Header::new(b":status", b"404"),
+            Header::new(b"x-test".to_vec(), b"1".to_vec()),
+        ]);
+        stream.new_data_chunk(b"not found");
+        stream.close();
+
+        let collector = stream.into_delegate();
+        assert!(collector.is_complete());
+        let response = collector.into_response().unwrap();
+        assert_eq!(response.stream_id, 3);
+        assert_eq!(response.status_code().unwrap(), 404);
+        assert_eq!(response.headers.len(), 2);
+        assert_eq!(response.body, b"not found".to_vec());
+        assert!(response.trailers.is_empty());
+    }
+
+    /// Tests that the result of the body hook is handed to the delegate once the full response
+    /// is received, but not if the stream is reset.
+    #[test]
+    fn test_delegate_stream_body_hook() {
+        let mut stream = DelegateStream::new(ResponseCollector::new());
+        stream.set_body_hook(Box::new(DigestHook::new()));
+        stream.close_local();
+        stream.set_headers(vec![
+            Header::new(b":status", b"200"),
+            Header::new(b"digest".to_vec(),
+                        b"SHA-256=LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=".to_vec()),
+        ]);
+        stream.new_data_chunk(b"hel");
+        stream.new_data_chunk(b"lo");
+        stream.close_remote();
+        match stream.delegate().body_check() {
+            Some(&BodyCheck::Verified(_)) => {},
+            check => panic!("Expected a verified body, got {:?}", check),
+        }
+
+        let mut stream = DelegateStream::new(ResponseCollector::new());
+        stream.set_body_hook(Box::new(DigestHook::new()));
+        stream.set_headers(vec![Header::new(b":status", b"200")]);
+        stream.new_data_chunk(b"hel");
+        stream.on_rst_stream(ErrorCode::Cancel);
+        assert!(stream.delegate().body_check().is_none());
+    }
+}
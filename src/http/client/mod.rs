@@ -6,22 +6,45 @@ use std::io;
 use std::fmt;
 use std::error;
 
-use http::{HttpScheme, HttpResult, StreamId, Header, HttpError, ErrorCode};
+use http::{
+    ConnectionId,
+    HttpScheme,
+    HttpResult,
+    StreamId,
+    Header,
+    Request,
+    HttpError,
+    ErrorCode,
+    ConnectionError,
+    CLIENT_PREFACE,
+    INITIAL_STREAM_WINDOW_SIZE,
+};
 use http::transport::TransportStream;
 use http::frame::{SettingsFrame, HttpSetting, FrameIR};
 use http::connection::{
     SendFrame, ReceiveFrame,
     SendStatus,
     HttpConnection,
+    ConnectionActivity,
     EndStream,
 };
 use http::session::{
     Session,
-    Stream, DefaultStream,
+    Stream, StreamState, DefaultStream,
     DefaultSessionState, SessionState,
+    HeaderPhase, HeaderEvent,
 };
 use http::session::Client as ClientMarker;
 use http::priority::SimplePrioritizer;
+use http::flow_control::FlowControlStrategy;
+use http::rate_limit::RateLimit;
+use http::padding::PaddingStrategy;
+use http::tracing::Role;
+use http::validation;
+use http::config::Http2Config;
+use http::window;
+
+pub mod delegate;
 
 #[cfg(feature="tls")]
 pub mod tls;
@@ -35,17 +58,36 @@ pub mod tls;
 ///
 /// Any error raised by the underlying connection is propagated.
 pub fn write_preface<W: io::Write>(stream: &mut W) -> Result<(), io::Error> {
+    write_preface_with_config(stream, &Http2Config::default())
+}
+
+/// Returns the settings that the client preface written by `write_preface_with_config` carries.
+fn preface_settings(config: &Http2Config) -> Vec<HttpSetting> {
+    let mut settings = Vec::new();
+    if !config.enable_push {
+        settings.push(HttpSetting::EnablePush(0));
+    }
+    settings.extend(config.settings());
+    settings
+}
+
+/// Writes the client preface to the given stream, advertising the settings of the given
+/// `Http2Config` (along with disabling server push, unless the config enables it) in the client's
+/// SETTINGS frame.
+pub fn write_preface_with_config<W: io::Write>(stream: &mut W, config: &Http2Config)
+        -> Result<(), io::Error> {
     // The first part of the client preface is always this sequence of 24
     // raw octets.
-    let preface = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
-    try!(stream.write_all(preface));
+    try!(stream.write_all(CLIENT_PREFACE));
 
     // It is followed by the client's settings.
     // TODO: It doesn't really make sense to have the initial settings be sent here, outside of the
     //       HttpConnection/Session. This should be moved to the initialization of the session!
     let settings = {
         let mut frame = SettingsFrame::new();
-        frame.add_setting(HttpSetting::EnablePush(0));
+        for setting in preface_settings(config) {
+            frame.add_setting(setting);
+        }
         frame
     };
     let mut buf = io::Cursor::new(Vec::with_capacity(16));
@@ -187,6 +229,9 @@ pub struct ClientConnection<State=DefaultSessionState<ClientMarker, DefaultStrea
     /// The state of the session associated to this client connection. Maintains the status of the
     /// connection streams.
     pub state: State,
+    /// Set once an operation on the connection failed with a fatal error, after which the
+    /// connection refuses to do anything else.
+    poisoned: bool,
 }
 
 impl<State> ClientConnection<State>
@@ -195,12 +240,52 @@ impl<State> ClientConnection<State>
     /// for all its underlying HTTP/2 communication.
     ///
     /// The given `state` instance will handle the maintenance of the session's state.
-    pub fn with_connection(conn: HttpConnection, state: State)
+    ///
+    /// The client preface is assumed to be written (or about to be written, by `send_preface`)
+    /// with the settings of the connection's config, so those settings are expected to be
+    /// acknowledged by the server.
+    pub fn with_connection(mut conn: HttpConnection, state: State)
             -> ClientConnection<State> {
+        let settings = preface_settings(conn.config());
+        conn.settings_sent(&settings);
+        conn.set_role(Role::Client);
         ClientConnection {
             conn: conn,
             state: state,
+            poisoned: false,
+        }
+    }
+
+    /// Returns whether the connection can still be used, i.e. none of the operations on it failed
+    /// with a fatal error (see `HttpError::is_fatal`).
+    ///
+    /// Once the connection is no longer usable, all its operations fail right away with an
+    /// `HttpError::ConnectionClosed` error, without touching the underlying transport; a new
+    /// connection needs to be established for any further requests.
+    #[inline]
+    pub fn is_usable(&self) -> bool {
+        !self.poisoned
+    }
+
+    /// Returns an `HttpError::ConnectionClosed` error if the connection is no longer usable.
+    fn check_usable(&self) -> HttpResult<()> {
+        if self.poisoned {
+            Err(HttpError::ConnectionClosed)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Marks the connection as no longer usable if the given result of an operation is a fatal
+    /// error, passing the result through.
+    fn track<T>(&mut self, res: HttpResult<T>) -> HttpResult<T> {
+        if let Err(ref e) = res {
+            if e.is_fatal() && !self.poisoned {
+                debug!("Connection poisoned by a fatal error: {:?}", e);
+                self.poisoned = true;
+            }
         }
+        res
     }
 
     /// Returns the scheme of the underlying `HttpConnection`.
@@ -209,6 +294,63 @@ impl<State> ClientConnection<State>
         self.conn.scheme
     }
 
+    /// Returns the maximum number of concurrent streams that the server allows, if it announced a
+    /// limit.
+    #[inline]
+    pub fn peer_max_concurrent_streams(&self) -> Option<u32> {
+        self.conn.peer_max_concurrent_streams()
+    }
+
+    /// Returns when the connection was established and when it last received and sent a frame.
+    #[inline]
+    pub fn activity(&self) -> ConnectionActivity {
+        self.conn.activity()
+    }
+
+    /// Returns the ID of the underlying connection.
+    #[inline]
+    pub fn id(&self) -> ConnectionId {
+        self.conn.id()
+    }
+
+    /// Sends the client connection preface (the magic, followed by the client's SETTINGS frame)
+    /// onto the given stream.
+    ///
+    /// Usually, the preface is already sent by the `HttpConnect` implementation that establishes
+    /// the stream (using the `write_preface` function); the method is provided so that clients
+    /// that sequence the handshake themselves can perform it as an explicit step.
+    ///
+    /// The SETTINGS frame advertises the settings of the connection's `Http2Config`.
+    pub fn send_preface<W: io::Write>(&mut self, stream: &mut W) -> HttpResult<()> {
+        try!(self.check_usable());
+        let res = write_preface_with_config(stream, self.conn.config()).map_err(HttpError::from);
+        self.track(res)
+    }
+
+    /// Sends the settings of the connection's `Http2Config` to the server, in a new SETTINGS
+    /// frame.
+    ///
+    /// This is needed when the connection preface was written without taking the config into
+    /// account, e.g. by an `HttpConnect` implementation using `write_preface`.
+    pub fn send_settings<S: SendFrame>(&mut self, sender: &mut S) -> HttpResult<()> {
+        try!(self.check_usable());
+        let settings = self.conn.config().settings();
+        let res = self.conn.sender(sender).send_settings(&settings);
+        self.track(res)
+    }
+
+    /// Expects the server connection preface (i.e. a SETTINGS frame) to be the next frame
+    /// provided by the given frame receiver and handles it.
+    ///
+    /// If the server sent anything else, an error is returned.
+    pub fn expect_preface<Recv: ReceiveFrame, Sender: SendFrame>(
+            &mut self,
+            rx: &mut Recv,
+            tx: &mut Sender)
+            -> HttpResult<()> {
+        self.expect_settings(rx, tx)
+    }
+
     /// Handles the next frame provided by the given frame receiver and expects it to be a
     /// `SETTINGS` frame. If it is not, it returns an error.
     ///
@@ -220,33 +362,189 @@ impl<State> ClientConnection<State>
             rx: &mut Recv,
             tx: &mut Sender)
             -> HttpResult<()> {
-        let mut session = ClientSession::new(&mut self.state, tx);
-        self.conn.expect_settings(rx, &mut session)
+        try!(self.check_usable());
+        let res = {
+            let mut session = ClientSession::new(&mut self.state, tx);
+            self.conn.expect_settings(rx, &mut session)
+        };
+        self.track(res)
     }
 
     /// Starts a new request based on the given `RequestStream`.
     ///
+    /// The stream is assigned the next free stream ID, which is returned. If the connection ran
+    /// out of stream IDs, an `HttpError::InvalidStreamId` error is returned without sending
+    /// anything.
+    ///
+    /// The header list may be of any size: a header block larger than the server's maximum frame
+    /// size is sent as a HEADERS frame followed by as many CONTINUATION frames as it takes.
+    ///
     /// For now it does not perform any validation whether the given `RequestStream` is valid.
     pub fn start_request<S: SendFrame>(
             &mut self,
             req: RequestStream<State::Stream>,
             sender: &mut S) -> HttpResult<StreamId> {
+        try!(self.check_usable());
+        let end_stream = if req.stream.is_closed_local() { EndStream::Yes } else { EndStream::No };
+        let stream_id = try!(self.state.try_insert_outgoing(req.stream)
+                                       .map_err(HttpError::InvalidStreamId));
+        try!(self.send_request_headers(req.headers, stream_id, end_stream, sender));
+
+        Ok(stream_id)
+    }
+
+    /// Starts a new request based on the given `RequestStream`, same as `start_request`, except
+    /// that the request is sent on the stream with the given ID, instead of the next free one.
+    ///
+    /// Letting the connection pick the IDs is almost always what is wanted; the method is meant
+    /// for tests and other cases that need to control the IDs exactly (e.g. replaying a captured
+    /// session). The ID has to be one that the client may use for a new stream, i.e. odd and
+    /// higher than the IDs of all the requests started so far. If it is not, an
+    /// `HttpError::InvalidStreamId` error is returned without sending anything.
+    pub fn start_request_with_id<S: SendFrame>(
+            &mut self,
+            stream_id: StreamId,
+            req: RequestStream<State::Stream>,
+            sender: &mut S) -> HttpResult<()> {
+        try!(self.check_usable());
         let end_stream = if req.stream.is_closed_local() { EndStream::Yes } else { EndStream::No };
-        let stream_id = self.state.insert_outgoing(req.stream);
-        try!(self.conn.sender(sender).send_headers(req.headers, stream_id, end_stream));
+        try!(self.state.insert_outgoing_with_id(stream_id, req.stream)
+                       .map_err(HttpError::InvalidStreamId));
+        self.send_request_headers(req.headers, stream_id, end_stream, sender)
+    }
+
+    /// Sends the headers of a request on the given stream, which is already tracked by the
+    /// session state. If they cannot be sent, the stream is dropped from the state.
+    fn send_request_headers<S: SendFrame>(
+            &mut self,
+            headers: Vec<Header>,
+            stream_id: StreamId,
+            end_stream: EndStream,
+            sender: &mut S) -> HttpResult<()> {
+        let head = headers.iter().any(|h| h.name() == b":method" && h.value() == b"HEAD");
+        if head {
+            // The response to a HEAD request has no body, whatever its content-length says.
+            self.state.expect_body_length(stream_id, 0);
+        }
+        if let Err(e) = self.conn.sender(sender).send_headers(headers, stream_id, end_stream) {
+            // The request never started, so the stream is not tracked any longer.
+            self.state.remove_stream(stream_id);
+            return self.track(Err(e));
+        }
+
+        Ok(())
+    }
+
+    /// Starts a new request based on the given `RequestStream`, same as `start_request`, except
+    /// that the inbound flow control window of the new stream is set to `window_size`, instead of
+    /// the default initial window size.
+    ///
+    /// This allows the peer to send a large response body (e.g. a known-large download) without
+    /// waiting for window updates, while all other streams keep the default window. The window is
+    /// increased by sending a WINDOW_UPDATE frame right after the request headers. Note that the
+    /// peer remains limited by the connection-level flow control window as well.
+    ///
+    /// A `window_size` that is not larger than the default leaves the window as it is. One that
+    /// exceeds the maximum window size (2^31 - 1) results in an `HttpError::WindowSizeOverflow`,
+    /// without starting the request.
+    pub fn start_request_with_window<S: SendFrame>(
+            &mut self,
+            req: RequestStream<State::Stream>,
+            window_size: u32,
+            sender: &mut S) -> HttpResult<StreamId> {
+        if window_size > window::MAX_WINDOW_SIZE {
+            return Err(HttpError::WindowSizeOverflow);
+        }
+        let stream_id = try!(self.start_request(req, sender));
+        let initial = INITIAL_STREAM_WINDOW_SIZE as u32;
+        if let Some(increment) = window::increment_to(initial, window_size) {
+            let res = self.conn.sender(sender).send_window_update(stream_id, increment);
+            try!(self.track(res));
+        }
 
         Ok(stream_id)
     }
 
+    /// Sends a GOAWAY frame with the given error code to the server, signaling that the client
+    /// is done with the connection. Since the client accepts no streams, no stream initiated by
+    /// the server is to be processed any further.
+    pub fn send_goaway<S: SendFrame>(&mut self, error_code: ErrorCode, sender: &mut S)
+            -> HttpResult<()> {
+        try!(self.check_usable());
+        let res = self.conn.sender(sender).send_goaway(0, error_code);
+        self.track(res)
+    }
+
+    /// Resets the given stream with a RST_STREAM frame carrying the given error code (e.g.
+    /// `ErrorCode::Cancel` for a request whose response is no longer needed), closing the stream
+    /// locally, so that it is returned by the next `get_closed` call of the session state.
+    pub fn reset_stream<S: SendFrame>(
+            &mut self,
+            stream_id: StreamId,
+            error_code: ErrorCode,
+            sender: &mut S) -> HttpResult<()> {
+        try!(self.check_usable());
+        debug!("Resetting stream {} with {:?}", stream_id, error_code);
+        if let Some(stream) = self.state.get_stream_mut(stream_id) {
+            stream.close();
+        }
+        let res = self.conn.sender(sender).send_rst_stream(stream_id, error_code);
+        self.track(res)
+    }
+
+    /// Sends a WINDOW_UPDATE frame to the server, increasing the inbound flow control window of
+    /// the given stream (or of the connection, for stream 0) by the given increment.
+    ///
+    /// An invalid increment (0, or one exceeding the maximum window size of 2^31 - 1) results in
+    /// an `HttpError::InvalidWindowIncrement`, without sending anything.
+    pub fn send_window_update<S: SendFrame>(
+            &mut self,
+            stream_id: StreamId,
+            increment: u32,
+            sender: &mut S) -> HttpResult<()> {
+        try!(self.check_usable());
+        let res = self.conn.sender(sender).send_window_update(stream_id, increment);
+        self.track(res)
+    }
+
     /// Fully handles the next incoming frame provided by the given `ReceiveFrame` instance.
     /// Handling a frame may cause changes to the session state exposed by the `ClientConnection`.
+    ///
+    /// Any control frames that handling the frame required (e.g. a PING acknowledgement) are sent
+    /// right away.
     pub fn handle_next_frame<Recv: ReceiveFrame, Sender: SendFrame>(
             &mut self,
             rx: &mut Recv,
             tx: &mut Sender)
             -> HttpResult<()> {
-        let mut session = ClientSession::new(&mut self.state, tx);
-        self.conn.handle_next_frame(rx, &mut session)
+        try!(self.check_usable());
+        let res = {
+            let mut session = ClientSession::new(&mut self.state, tx);
+            self.conn.handle_next_frame(rx, &mut session)
+        };
+        try!(self.track(res));
+        let res = self.conn.sender(tx).send_pending_frames();
+        self.track(res)
+    }
+
+    /// Sets the strategy that the connection uses for managing its inbound flow control windows.
+    #[inline]
+    pub fn set_flow_control(&mut self, strategy: FlowControlStrategy) {
+        self.conn.set_flow_control(strategy)
+    }
+
+    /// Caps the bandwidth that the connection uses for sending DATA, connection-wide and/or per
+    /// stream. While the limit is exhausted, `send_next_data` returns `SendStatus::Throttled`.
+    #[inline]
+    pub fn set_rate_limit(&mut self, connection: Option<RateLimit>, per_stream: Option<RateLimit>) {
+        self.conn.set_rate_limit(connection, per_stream)
+    }
+
+    /// Pads the DATA frames sent on the given stream using the given strategy (or stops padding
+    /// them). See `HttpConnection::set_stream_padding`.
+    #[inline]
+    pub fn set_stream_padding(&mut self, stream_id: StreamId, strategy: Option<PaddingStrategy>) {
+        self.conn.set_stream_padding(stream_id, strategy)
     }
 
     /// Queues a new DATA frame onto the underlying `SendFrame`.
@@ -254,13 +552,66 @@ impl<State> ClientConnection<State>
     /// Currently, no prioritization of streams is taken into account and which stream's data is
     /// queued cannot be relied on.
     pub fn send_next_data<S: SendFrame>(&mut self, sender: &mut S) -> HttpResult<SendStatus> {
+        try!(self.check_usable());
         debug!("Sending next data...");
-        // A default "maximum" chunk size of 8 KiB is set on all data frames.
-        const MAX_CHUNK_SIZE: usize = 8 * 1024;
-        let mut buf = [0; MAX_CHUNK_SIZE];
+        // The chunks are as large as the server's maximum frame size and the connection's
+        // outbound flow control window allow.
+        let mut buf = vec![0; self.conn.next_chunk_size()];
 
+        let throttled = self.conn.throttled_streams();
         let mut prioritizer = SimplePrioritizer::new(&mut self.state, &mut buf);
-        self.conn.sender(sender).send_next_data(&mut prioritizer)
+        prioritizer.skip_streams(throttled);
+        let res = self.conn.sender(sender).send_next_data(&mut prioritizer);
+        self.track(res)
+    }
+}
+
+impl<State> ClientConnection<State>
+        where State: SessionState<Stream=DefaultStream> {
+    /// Starts the given request, with its body, on a new stream. The connection assigns the
+    /// stream's ID, which is returned; the `stream_id` of the request itself is ignored.
+    ///
+    /// Only the headers are sent right away, the body follows through `send_next_data`. The
+    /// response is received into the stream's `DefaultStream`, which is tracked by the session
+    /// state under the returned ID.
+    pub fn send_request<S: SendFrame>(&mut self, req: Request, sender: &mut S)
+            -> HttpResult<StreamId> {
+        let stream_id = try!(self.start_request(request_stream(req), sender));
+        if let Some(stream) = self.state.get_stream_mut(stream_id) {
+            stream.stream_id = Some(stream_id);
+        }
+        Ok(stream_id)
+    }
+
+    /// Starts the given request, same as `send_request`, except that it is sent on the stream
+    /// with the given ID. See `start_request_with_id` for when that is needed and which IDs are
+    /// allowed.
+    pub fn send_request_with_id<S: SendFrame>(
+            &mut self,
+            stream_id: StreamId,
+            req: Request,
+            sender: &mut S)
+            -> HttpResult<()> {
+        try!(self.start_request_with_id(stream_id, request_stream(req), sender));
+        if let Some(stream) = self.state.get_stream_mut(stream_id) {
+            stream.stream_id = Some(stream_id);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the `RequestStream` that sends the given request. A request without a body ends the
+/// stream along with its headers.
+fn request_stream<'n, 'v>(req: Request<'n, 'v>) -> RequestStream<'n, 'v, DefaultStream> {
+    let mut stream = DefaultStream::new();
+    if req.body.is_empty() {
+        stream.close_local();
+    } else {
+        stream.set_full_data(req.body);
+    }
+    RequestStream {
+        headers: req.headers,
+        stream: stream,
     }
 }
 
@@ -291,14 +642,71 @@ impl<'a, State, S> ClientSession<'a, State, S> where State: SessionState + 'a, S
             sender: sender,
         }
     }
+
+    /// Resets the given stream, whose body turned out not to match the declared length, after
+    /// notifying the stream.
+    fn body_length_mismatch(&mut self, stream_id: StreamId, conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        if let Some(length) = self.state.body_length(stream_id).cloned() {
+            debug!("Body length mismatch on stream {}: declared {}, received {}",
+                   stream_id, length.declared, length.received);
+            if let Some(stream) = self.state.get_stream_mut(stream_id) {
+                stream.on_body_length_mismatch(&length);
+            }
+        }
+        self.stream_error(stream_id, ErrorCode::ProtocolError, conn)
+    }
+
+    /// Moves the given stream to the header phase that follows the given event. A stream that
+    /// receives the event out of sequence (e.g. a second header block that cannot be trailers) is
+    /// reset with a PROTOCOL_ERROR instead.
+    ///
+    /// Returns whether the frame that caused the event should be handled any further.
+    fn advance_header_phase(&mut self, stream_id: StreamId, event: HeaderEvent,
+                            conn: &mut HttpConnection)
+            -> HttpResult<bool> {
+        // A stream that is already closed (e.g. because it was reset) is not checked again.
+        if self.state.get_stream_ref(stream_id).map_or(true, |stream| stream.is_closed()) {
+            return Ok(true);
+        }
+        let res = match self.state.header_phase(stream_id) {
+            Some(phase) => phase.transition(event).map(|next| *phase = next),
+            None => return Ok(true),
+        };
+        match res {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                debug!("Stream {}: {}", stream_id, e);
+                try!(self.stream_error(stream_id, ErrorCode::ProtocolError, conn));
+                Ok(false)
+            },
+        }
+    }
+}
+
+/// Returns the length of the body that should follow the given (valid) response headers, if it is
+/// known.
+///
+/// A `304 Not Modified` response never has a body, regardless of its `content-length`
+/// (section 8.1.2.6.).
+fn response_body_length(headers: &[Header]) -> Result<Option<u64>, validation::MalformedHeaders> {
+    let length = try!(validation::content_length(headers));
+    let not_modified = headers.iter().any(|h| h.name() == b":status" && h.value() == b"304");
+    Ok(if not_modified { Some(0) } else { length })
 }
 
 impl<'a, State, S> Session for ClientSession<'a, State, S>
         where State: SessionState + 'a,
               S: SendFrame + 'a {
-    fn new_data_chunk(&mut self, stream_id: StreamId, data: &[u8], _: &mut HttpConnection)
+    fn new_data_chunk(&mut self, stream_id: StreamId, data: &[u8], conn: &mut HttpConnection)
             -> HttpResult<()> {
         debug!("Data chunk for stream {}", stream_id);
+        if !try!(self.advance_header_phase(stream_id, HeaderEvent::Data, conn)) {
+            return Ok(());
+        }
+        if !self.state.body_length(stream_id).map_or(true, |l| l.data_received(data.len())) {
+            return self.body_length_mismatch(stream_id, conn);
+        }
         let mut stream = match self.state.get_stream_mut(stream_id) {
             None => {
                 debug!("Received a frame for an unknown stream!");
@@ -318,26 +726,79 @@ impl<'a, State, S> Session for ClientSession<'a, State, S>
             &mut self,
             stream_id: StreamId,
             headers: Vec<Header<'n, 'v>>,
-            _conn: &mut HttpConnection)
+            conn: &mut HttpConnection)
             -> HttpResult<()> {
         debug!("Headers for stream {}", stream_id);
-        let mut stream = match self.state.get_stream_mut(stream_id) {
-            None => {
-                debug!("Received a frame for an unknown stream!");
-                // TODO(mlalic): This means that the server's header is not associated to any
-                //               request made by the client nor any server-initiated stream (pushed)
-                return Ok(());
+        let informational = headers.iter().any(|h| {
+            h.name() == b":status" && h.value().starts_with(b"1")
+        });
+        let event = HeaderEvent::Headers { informational: informational };
+        // Where the phase is tracked, it tells trailers apart from a final response that follows
+        // informational ones.
+        let phase = self.state.header_phase(stream_id).map(|phase| *phase);
+        if !try!(self.advance_header_phase(stream_id, event, conn)) {
+            return Ok(());
+        }
+        let res = {
+            let mut stream = match self.state.get_stream_mut(stream_id) {
+                None => {
+                    debug!("Received a frame for an unknown stream!");
+                    // TODO(mlalic): This means that the server's header is not associated to any
+                    //               request made by the client nor any server-initiated stream
+                    //               (pushed)
+                    return Ok(());
+                },
+                Some(stream) => stream,
+            };
+            let res = if !conn.config().compliance.is_strict() {
+                Ok(None)
+            } else if phase.map_or(stream.headers_received(), |p| p == HeaderPhase::Data) {
+                validation::validate_trailers(&headers).map(|_| None)
+            } else {
+                validation::validate_response(&headers)
+                    .and_then(|_| response_body_length(&headers))
+            };
+            match res {
+                Ok(_) => {
+                    // Now let the stream handle the headers
+                    stream.set_headers(headers);
+                },
+                Err(ref e) => stream.on_malformed_headers(e),
+            };
+            res
+        };
+        match res {
+            Ok(Some(length)) => self.state.expect_body_length(stream_id, length),
+            Ok(None) => {},
+            Err(e) => {
+                debug!("Malformed headers on stream {}: {}", stream_id, e);
+                try!(self.stream_error(stream_id, ErrorCode::ProtocolError, conn));
             },
-            Some(stream) => stream,
         };
-        // Now let the stream handle the headers
-        stream.set_headers(headers);
         Ok(())
     }
 
-    fn end_of_stream(&mut self, stream_id: StreamId, _: &mut HttpConnection)
+    fn new_push_promise(&mut self, stream_id: StreamId, promised_stream_id: StreamId,
+                        conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        debug!("PUSH_PROMISE for stream {} on stream {}", promised_stream_id, stream_id);
+        // Once the server acknowledged that push is disabled, pushing is a protocol violation
+        // (section 6.6.)
+        if !conn.local_settings().acked().enable_push {
+            return Err(HttpError::LocalConnectionError(ErrorCode::ProtocolError));
+        }
+        Ok(())
+    }
+
+    fn end_of_stream(&mut self, stream_id: StreamId, conn: &mut HttpConnection)
             -> HttpResult<()> {
         debug!("End of stream {}", stream_id);
+        if !try!(self.advance_header_phase(stream_id, HeaderEvent::EndStream, conn)) {
+            return Ok(());
+        }
+        if !self.state.body_length(stream_id).map_or(true, |l| l.is_complete()) {
+            return self.body_length_mismatch(stream_id, conn);
+        }
         let mut stream = match self.state.get_stream_mut(stream_id) {
             None => {
                 debug!("Received a frame for an unknown stream!");
@@ -355,7 +816,9 @@ impl<'a, State, S> Session for ClientSession<'a, State, S>
     fn rst_stream(&mut self, stream_id: StreamId, error_code: ErrorCode, _: &mut HttpConnection)
             -> HttpResult<()> {
         debug!("RST_STREAM id={:?}, error={:?}", stream_id, error_code);
-        self.state.get_stream_mut(stream_id).map(|stream| stream.on_rst_stream(error_code));
+        if let Some(stream) = self.state.get_stream_mut(stream_id) {
+            stream.on_rst_stream(error_code);
+        }
         Ok(())
     }
 
@@ -364,41 +827,91 @@ impl<'a, State, S> Session for ClientSession<'a, State, S>
         debug!("Sending a SETTINGS ack");
         conn.sender(self.sender).send_settings_ack()
     }
+
+    fn on_goaway(&mut self, last_stream_id: StreamId, error_code: ErrorCode,
+                 debug_data: Option<&[u8]>, conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        debug!("GOAWAY last_stream_id={}, error={:?}", last_stream_id, error_code);
+        let err = match (error_code, debug_data) {
+            (ErrorCode::Http11Required, _) => return Err(HttpError::Http11Required),
+            (_, Some(data)) => ConnectionError::with_debug_data(error_code, data.to_vec()),
+            (_, None) => ConnectionError::new(error_code),
+        };
+        Err(HttpError::PeerConnectionError(err.on_connection(conn.id())))
+    }
+
+    fn stream_state(&self, stream_id: StreamId) -> Option<StreamState> {
+        self.state.stream_state(stream_id)
+    }
+
+    fn stream_error(&mut self, stream_id: StreamId, error_code: ErrorCode,
+                    conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        debug!("Resetting stream {} with {:?}", stream_id, error_code);
+        if let Some(stream) = self.state.get_stream_mut(stream_id) {
+            stream.close();
+        }
+        conn.sender(self.sender).send_rst_stream(stream_id, error_code)
+    }
+
+    fn connection_error(&mut self, error_code: ErrorCode, conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        debug!("Closing the connection with {:?}", error_code);
+        let last_stream_id = self.state.last_incoming_stream_id();
+        conn.sender(self.sender).send_goaway(last_stream_id, error_code)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::{
         ClientSession,
+        ClientConnection,
         write_preface,
+        preface_settings,
         RequestStream,
     };
 
-    use http::{Header, ErrorCode, HttpError};
+    use http::{Header, Request, ErrorCode, HttpError, HttpResult, HttpScheme, CLIENT_PREFACE};
+    use http::stream_id::StreamIdError;
+    use http::config::Http2Config;
+    use http::rate_limit::RateLimit;
     use http::tests::common::{
         TestStream,
         build_mock_client_conn,
         build_mock_http_conn,
         MockReceiveFrame,
         MockSendFrame,
+        parse_frames,
     };
     use http::frame::{
         SettingsFrame,
+        HttpSetting,
         DataFrame,
+        RstStreamFrame,
+        GoawayFrame,
+        HeadersFrame,
+        HeadersFlag,
+        ContinuationFrame,
+        ContinuationFlag,
         Frame,
         RawFrame,
+        pack_header,
     };
     use http::connection::{
         HttpFrame,
+        HttpConnection,
         SendStatus,
     };
     use http::session::{
         Session,
         SessionState,
         Stream,
+        DefaultStream,
         DefaultSessionState,
     };
     use http::session::Client as ClientMarker;
+    use http::validation::MalformedHeaders;
 
     /// Tests that a client connection is correctly initialized, by reading the
     /// server preface (i.e. a settings frame) as the first frame of the connection.
@@ -495,6 +1008,149 @@ mod tests {
         }
     }
 
+    /// Tests that the `ClientConnection` does not send more data than its rate limit allows.
+    #[test]
+    fn test_client_conn_send_next_data_rate_limited() {
+        let mut conn = build_mock_client_conn();
+        let mut sender = MockSendFrame::new();
+        // A bucket that holds 6 bytes and is practically never refilled.
+        conn.set_rate_limit(Some(RateLimit::new(1, 6)), None);
+        conn.state.insert_outgoing(prepare_stream(Some(vec![1; 10])));
+
+        let res = conn.send_next_data(&mut sender).unwrap();
+        assert_eq!(res, SendStatus::Sent);
+        // Only as much data as the bucket held was sent...
+        let frame = match HttpFrame::from_raw(&sender.sent[0]).unwrap() {
+            HttpFrame::DataFrame(frame) => frame,
+            _ => panic!("Expected a DATA frame"),
+        };
+        assert_eq!(frame.data, vec![1; 6]);
+        // ...and now the connection is throttled, even though the stream has more data.
+        let res = conn.send_next_data(&mut sender).unwrap();
+        assert_eq!(res, SendStatus::Throttled);
+        assert_eq!(sender.sent.len(), 1);
+
+        // Removing the limit allows the rest to go out.
+        conn.set_rate_limit(None, None);
+        let res = conn.send_next_data(&mut sender).unwrap();
+        assert_eq!(res, SendStatus::Sent);
+        assert_eq!(sender.sent.len(), 2);
+    }
+
+    /// Tests that the `ClientConnection` sends no more data than the connection's outbound flow
+    /// control window allows.
+    #[test]
+    fn test_client_conn_send_next_data_connection_window() {
+        let mut conn = build_mock_client_conn();
+        let mut sender = MockSendFrame::new();
+        conn.state.insert_outgoing(prepare_stream(Some(vec![1; 70_000])));
+
+        let mut sent = 0;
+        while let SendStatus::Sent = conn.send_next_data(&mut sender).unwrap() {
+            match HttpFrame::from_raw(sender.sent.last().unwrap()).unwrap() {
+                HttpFrame::DataFrame(frame) => sent += frame.data.len(),
+                _ => panic!("Expected a DATA frame"),
+            }
+        }
+        // The last frame was cut short to fit into what was left of the window.
+        assert_eq!(sent, 65_535);
+        assert_eq!(sender.sent.len(), 4);
+        assert_eq!(conn.send_next_data(&mut sender).unwrap(), SendStatus::Throttled);
+    }
+
+    /// Tests that the `ClientConnection::start_request_with_window` method sends a WINDOW_UPDATE
+    /// for the new stream if its window should be larger than the default.
+    #[test]
+    fn test_client_conn_start_request_with_window() {
+        fn get_request() -> RequestStream<'static, 'static, TestStream> {
+            RequestStream {
+                headers: vec![Header::new(b":method", b"GET")],
+                stream: prepare_stream(None),
+            }
+        }
+        {
+            let mut conn = build_mock_client_conn();
+            let mut sender = MockSendFrame::new();
+
+            let id = conn.start_request_with_window(get_request(), 10 * 1024 * 1024, &mut sender)
+                         .unwrap();
+
+            assert_eq!(id, 1);
+            assert_eq!(sender.sent.len(), 2);
+            match HttpFrame::from_raw(&sender.sent[1]).unwrap() {
+                HttpFrame::WindowUpdateFrame(ref frame) => {
+                    assert_eq!(frame.get_stream_id(), 1);
+                    assert_eq!(frame.increment(), 10 * 1024 * 1024 - 65_535);
+                },
+                _ => panic!("Expected a WINDOW_UPDATE frame"),
+            };
+        }
+        {
+            // Not larger than the default: only the headers are sent
+            let mut conn = build_mock_client_conn();
+            let mut sender = MockSendFrame::new();
+            conn.start_request_with_window(get_request(), 65_535, &mut sender).unwrap();
+            assert_eq!(sender.sent.len(), 1);
+        }
+        {
+            // Too large: the request is not started
+            let mut conn = build_mock_client_conn();
+            let mut sender = MockSendFrame::new();
+            let res = conn.start_request_with_window(get_request(), 0x80000000, &mut sender);
+            assert_eq!(res.err().unwrap(), HttpError::WindowSizeOverflow);
+            assert_eq!(sender.sent.len(), 0);
+            assert!(conn.state.get_stream_ref(1).is_none());
+        }
+    }
+
+    /// Tests that the `ClientConnection::send_window_update` method sends the frame, while an
+    /// invalid increment leaves the connection usable.
+    #[test]
+    fn test_client_conn_send_window_update() {
+        let mut conn = build_mock_client_conn();
+        let mut sender = MockSendFrame::new();
+
+        let res = conn.send_window_update(0, 0, &mut sender);
+        assert_eq!(res.err().unwrap(), HttpError::InvalidWindowIncrement(0));
+        assert!(conn.is_usable());
+        conn.send_window_update(0, 10, &mut sender).unwrap();
+
+        assert_eq!(sender.sent.len(), 1);
+        match HttpFrame::from_raw(&sender.sent[0]).unwrap() {
+            HttpFrame::WindowUpdateFrame(ref frame) => {
+                assert_eq!(frame.get_stream_id(), 0);
+                assert_eq!(frame.increment(), 10);
+            },
+            _ => panic!("Expected a WINDOW_UPDATE frame"),
+        };
+    }
+
+    /// Tests that the `ClientConnection::reset_stream` method sends a RST_STREAM for the stream
+    /// and closes it, so that it is reaped along with the other closed streams.
+    #[test]
+    fn test_client_conn_reset_stream() {
+        let mut conn = build_mock_client_conn();
+        let mut sender = MockSendFrame::new();
+        let req = RequestStream {
+            headers: vec![Header::new(b":method", b"GET")],
+            stream: prepare_stream(None),
+        };
+        let stream_id = conn.start_request(req, &mut sender).unwrap();
+
+        conn.reset_stream(stream_id, ErrorCode::Cancel, &mut sender).unwrap();
+
+        assert_eq!(sender.sent.len(), 2);
+        match HttpFrame::from_raw(&sender.sent[1]).unwrap() {
+            HttpFrame::RstStreamFrame(frame) => {
+                assert_eq!(frame.get_stream_id(), stream_id);
+                assert_eq!(frame.error_code(), ErrorCode::Cancel);
+            },
+            _ => panic!("Expected a RST_STREAM frame"),
+        };
+        assert_eq!(conn.state.get_closed().len(), 1);
+        assert!(conn.is_usable());
+    }
+
     /// Tests that the `ClientConnection::start_request` method correctly starts a new request.
     #[test]
     fn test_client_conn_start_request() {
@@ -552,6 +1208,114 @@ mod tests {
         }
     }
 
+    /// Tests that requests with a header block larger than the maximum frame size are sent,
+    /// with the block split across CONTINUATION frames.
+    #[test]
+    fn test_client_conn_start_request_large_headers() {
+        let mut conn = build_mock_client_conn();
+        let mut sender = MockSendFrame::new();
+        // E.g. a large signed token.
+        let token = vec![b'x'; 64 * 1024];
+
+        let stream = RequestStream {
+            headers: vec![
+                Header::new(b":method", b"GET"),
+                Header::new(b"authorization".to_vec(), token.clone()),
+            ],
+            stream: prepare_stream(None),
+        };
+        conn.start_request(stream, &mut sender).unwrap();
+
+        let frames: Vec<_> = sender.sent.iter().map(|raw| HttpFrame::from_raw(raw).unwrap())
+                                                .collect();
+        assert!(frames.len() > 1);
+        let mut block = Vec::new();
+        for (i, frame) in frames.iter().enumerate() {
+            let last = i == frames.len() - 1;
+            match *frame {
+                HttpFrame::HeadersFrame(ref frame) if i == 0 => {
+                    assert!(frame.is_end_of_stream());
+                    assert!(!frame.is_headers_end());
+                    block.extend_from_slice(frame.header_fragment());
+                },
+                HttpFrame::ContinuationFrame(ref frame) if i > 0 => {
+                    assert_eq!(frame.is_headers_end(), last);
+                    block.extend_from_slice(frame.header_fragment());
+                },
+                _ => panic!("Unexpected frame"),
+            }
+        }
+        let headers = hpack::Decoder::new().decode(&block).unwrap();
+        assert_eq!(headers[1], (b"authorization".to_vec(), token));
+    }
+
+    /// Tests that responses with a header block larger than the maximum frame size, which the
+    /// server continues in CONTINUATION frames, are received as a whole.
+    #[test]
+    fn test_client_conn_large_response_headers() {
+        let mut conn = ClientConnection::with_connection(
+            build_mock_http_conn(),
+            DefaultSessionState::<ClientMarker, DefaultStream>::new());
+        let mut sender = MockSendFrame::new();
+        let req = Request {
+            stream_id: 1,
+            headers: vec![Header::new(b":method", b"GET")],
+            body: vec![],
+        };
+        let stream_id = conn.send_request(req, &mut sender).unwrap();
+        let token = vec![b'x'; 64 * 1024];
+        let block = hpack::Encoder::new().encode(vec![
+            (&b":status"[..], &b"200"[..]),
+            (&b"x-token"[..], &token[..]),
+        ]);
+        // The block is split up the way the server would, given the default maximum frame size.
+        let mut fragments = block.chunks(16_384);
+        let mut headers = HeadersFrame::new(fragments.next().unwrap().to_vec(), stream_id);
+        headers.set_flag(HeadersFlag::EndStream);
+        let mut frames = vec![HttpFrame::HeadersFrame(headers)];
+        let fragments: Vec<_> = fragments.collect();
+        for (i, fragment) in fragments.iter().enumerate() {
+            let mut frame = ContinuationFrame::new(fragment.to_vec(), stream_id);
+            if i == fragments.len() - 1 {
+                frame.set_flag(ContinuationFlag::EndHeaders);
+            }
+            frames.push(HttpFrame::ContinuationFrame(frame));
+        }
+        let count = frames.len();
+        assert!(count > 2);
+        let mut rx = MockReceiveFrame::new(frames);
+
+        for _ in 0..count {
+            conn.handle_next_frame(&mut rx, &mut sender).unwrap();
+        }
+
+        let stream = conn.state.get_stream_ref(stream_id).unwrap();
+        assert!(stream.is_closed_remote());
+        let headers = stream.headers.as_ref().unwrap();
+        assert_eq!(headers[1], Header::new(b"x-token".to_vec(), token));
+    }
+
+    /// Tests that a request with an invalid `te` header is rejected before anything is sent and
+    /// that no stream is left behind for it.
+    #[test]
+    fn test_client_conn_start_request_invalid_te() {
+        let mut conn = build_mock_client_conn();
+        let mut sender = MockSendFrame::new();
+        let stream = RequestStream {
+            headers: vec![
+                Header::new(b":method", b"GET"),
+                Header::new(b"te".to_vec(), b"gzip".to_vec()),
+            ],
+            stream: prepare_stream(None),
+        };
+
+        let res = conn.start_request(stream, &mut sender);
+
+        assert_eq!(res.err().unwrap(), HttpError::MalformedHeaders(MalformedHeaders::InvalidTe));
+        assert!(sender.sent.is_empty());
+        assert!(conn.state.get_stream_ref(1).is_none());
+    }
+
     /// Tests that a `ClientSession` notifies the correct stream when the
     /// appropriate callback is invoked.
     ///
@@ -565,6 +1329,16 @@ mod tests {
         let mut conn = build_mock_http_conn();
         let mut sender = MockSendFrame::new();
 
+        // Headers first...
+        let headers = vec![
+            Header::new(b":status", b"200"),
+        ];
+        {
+            let mut session = ClientSession::new(&mut state, &mut sender);
+            session.new_headers(1, headers.clone(), &mut conn).unwrap();
+        }
+        assert_eq!(state.get_stream_ref(1).unwrap().headers.clone().unwrap(),
+                   headers);
         {
             // Registering some data to stream 1...
             let mut session = ClientSession::new(&mut state, &mut sender);
@@ -579,21 +1353,12 @@ mod tests {
         }
         // ...works.
         assert_eq!(state.get_stream_ref(1).unwrap().body, vec![1, 2, 3, 4]);
-        // Now headers?
-        let headers = vec![
-            Header::new(b":method", b"GET"),
-        ];
-        {
-            let mut session = ClientSession::new(&mut state, &mut sender);
-            session.new_headers(1, headers.clone(), &mut conn).unwrap();
-        }
-        assert_eq!(state.get_stream_ref(1).unwrap().headers.clone().unwrap(),
-                   headers);
         // Add another stream in the mix
         state.insert_outgoing(TestStream::new());
         {
             // and send it some data
             let mut session = ClientSession::new(&mut state, &mut sender);
+            session.new_headers(3, headers.clone(), &mut conn).unwrap();
             session.new_data_chunk(3, &[100], &mut conn).unwrap();
         }
         assert_eq!(state.get_stream_ref(3).unwrap().body, vec![100]);
@@ -633,6 +1398,244 @@ mod tests {
         assert!(state.get_stream_ref(1).map(|stream| stream.errors.len() == 0).unwrap());
     }
 
+    /// Tests that the `ClientSession` resets a stream whose response headers are malformed and
+    /// notifies the stream of the reason.
+    #[test]
+    fn test_client_session_malformed_response() {
+        let mut state = DefaultSessionState::<ClientMarker, TestStream>::new();
+        state.insert_outgoing(TestStream::new());
+        state.insert_outgoing(TestStream::new());
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        {
+            // A response that carries a request pseudo-header...
+            let mut session = ClientSession::new(&mut state, &mut sender);
+            let headers = vec![
+                Header::new(b":status", b"200"),
+                Header::new(b":path", b"/"),
+            ];
+            session.new_headers(1, headers, &mut conn).unwrap();
+        }
+        // ...is not passed on to the stream...
+        {
+            let stream = state.get_stream_ref(1).unwrap();
+            assert!(stream.headers.is_none());
+            assert_eq!(stream.malformed,
+                       vec![MalformedHeaders::UnexpectedPseudoHeader(b":path".to_vec())]);
+        }
+        // ...and the stream is reset with a PROTOCOL_ERROR.
+        assert_eq!(sender.sent.len(), 1);
+        match HttpFrame::from_raw(&sender.sent[0]).unwrap() {
+            HttpFrame::RstStreamFrame(frame) => {
+                assert_eq!(frame.get_stream_id(), 1);
+                assert_eq!(frame.error_code(), ErrorCode::ProtocolError);
+            },
+            _ => panic!("Expected a RST_STREAM frame"),
+        };
+        {
+            // A response without a `:status` is also rejected.
+            let mut session = ClientSession::new(&mut state, &mut sender);
+            let headers = vec![Header::new(b"server".to_vec(), b"x".to_vec())];
+            session.new_headers(3, headers, &mut conn).unwrap();
+        }
+        assert_eq!(state.get_stream_ref(3).unwrap().malformed,
+                   vec![MalformedHeaders::MissingPseudoHeader(":status")]);
+        assert_eq!(sender.sent.len(), 2);
+        state.insert_outgoing(TestStream::new());
+        {
+            // As is one with two `:status` pseudo-headers.
+            let mut session = ClientSession::new(&mut state, &mut sender);
+            let headers = vec![
+                Header::new(b":status", b"200"),
+                Header::new(b":status", b"302"),
+            ];
+            session.new_headers(5, headers, &mut conn).unwrap();
+        }
+        assert_eq!(state.get_stream_ref(5).unwrap().malformed,
+                   vec![MalformedHeaders::DuplicatePseudoHeader(b":status".to_vec())]);
+        assert_eq!(sender.sent.len(), 3);
+    }
+
+    /// Tests that the `ClientSession` resets streams whose response body does not match the
+    /// declared `content-length`.
+    #[test]
+    fn test_client_session_body_length_mismatch() {
+        let mut state = DefaultSessionState::<ClientMarker, TestStream>::new();
+        state.insert_outgoing(TestStream::new());
+        state.insert_outgoing(TestStream::new());
+        state.insert_outgoing(TestStream::new());
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        let headers = vec![
+            Header::new(b":status", b"200"),
+            Header::new(b"content-length".to_vec(), b"3".to_vec()),
+        ];
+        {
+            // Stream 1 gets the declared body...
+            let mut session = ClientSession::new(&mut state, &mut sender);
+            session.new_headers(1, headers.clone(), &mut conn).unwrap();
+            session.new_data_chunk(1, &[1, 2], &mut conn).unwrap();
+            session.new_data_chunk(1, &[3], &mut conn).unwrap();
+            session.end_of_stream(1, &mut conn).unwrap();
+        }
+        // ...which is fine.
+        assert!(sender.sent.is_empty());
+        assert_eq!(state.get_stream_ref(1).unwrap().length_mismatch, None);
+        {
+            // Stream 3 ends too early...
+            let mut session = ClientSession::new(&mut state, &mut sender);
+            session.new_headers(3, headers.clone(), &mut conn).unwrap();
+            session.new_data_chunk(3, &[1, 2], &mut conn).unwrap();
+            session.end_of_stream(3, &mut conn).unwrap();
+        }
+        // ...so it gets reset.
+        assert_eq!(state.get_stream_ref(3).unwrap().length_mismatch.map(|l| l.received),
+                   Some(2));
+        assert!(state.get_stream_ref(3).unwrap().is_closed());
+        assert_eq!(sender.sent.len(), 1);
+        match HttpFrame::from_raw(&sender.sent[0]).unwrap() {
+            HttpFrame::RstStreamFrame(frame) => {
+                assert_eq!(frame.get_stream_id(), 3);
+                assert_eq!(frame.error_code(), ErrorCode::ProtocolError);
+            },
+            _ => panic!("Expected a RST_STREAM frame"),
+        };
+        {
+            // Stream 5 sends too much data...
+            let mut session = ClientSession::new(&mut state, &mut sender);
+            session.new_headers(5, headers.clone(), &mut conn).unwrap();
+            session.new_data_chunk(5, &[1, 2], &mut conn).unwrap();
+            session.new_data_chunk(5, &[3, 4], &mut conn).unwrap();
+        }
+        // ...the excess never reaches the stream, which is reset right away.
+        assert_eq!(state.get_stream_ref(5).unwrap().body, vec![1, 2]);
+        assert_eq!(state.get_stream_ref(5).unwrap().length_mismatch.map(|l| l.received),
+                   Some(4));
+        assert_eq!(sender.sent.len(), 2);
+    }
+
+    /// Tests that an invalid `content-length` in a response makes it malformed.
+    #[test]
+    fn test_client_session_invalid_content_length() {
+        let mut state = DefaultSessionState::<ClientMarker, TestStream>::new();
+        state.insert_outgoing(TestStream::new());
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        {
+            let mut session = ClientSession::new(&mut state, &mut sender);
+            let headers = vec![
+                Header::new(b":status", b"200"),
+                Header::new(b"content-length".to_vec(), b"3".to_vec()),
+                Header::new(b"content-length".to_vec(), b"4".to_vec()),
+            ];
+            session.new_headers(1, headers, &mut conn).unwrap();
+        }
+        assert_eq!(state.get_stream_ref(1).unwrap().malformed,
+                   vec![MalformedHeaders::InvalidContentLength]);
+        assert_eq!(sender.sent.len(), 1);
+    }
+
+    /// Tests that responses to HEAD requests and `304` responses are expected to have no body,
+    /// regardless of their `content-length`.
+    #[test]
+    fn test_client_conn_head_response_body_length() {
+        let mut conn = build_mock_client_conn();
+        let mut sender = MockSendFrame::new();
+        let stream = RequestStream {
+            headers: vec![Header::new(b":method", b"HEAD")],
+            stream: prepare_stream(None),
+        };
+        let stream_id = conn.start_request(stream, &mut sender).unwrap();
+        assert_eq!(conn.state.body_length(stream_id).map(|l| l.declared), Some(0));
+
+        let mut state = DefaultSessionState::<ClientMarker, TestStream>::new();
+        state.insert_outgoing(TestStream::new());
+        let mut http_conn = build_mock_http_conn();
+        {
+            let mut session = ClientSession::new(&mut state, &mut sender);
+            let headers = vec![
+                Header::new(b":status", b"304"),
+                Header::new(b"content-length".to_vec(), b"100".to_vec()),
+            ];
+            session.new_headers(1, headers, &mut http_conn).unwrap();
+            session.end_of_stream(1, &mut http_conn).unwrap();
+        }
+        assert_eq!(state.get_stream_ref(1).unwrap().length_mismatch, None);
+        assert_eq!(sender.sent.len(), 1);
+    }
+
+    /// Tests that the `ClientSession` validates a second header block on a stream as trailers.
+    #[test]
+    fn test_client_session_trailers() {
+        let mut state = DefaultSessionState::<ClientMarker, TestStream>::new();
+        state.insert_outgoing(TestStream::new());
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        {
+            let mut session = ClientSession::new(&mut state, &mut sender);
+            session.new_headers(1, vec![Header::new(b":status", b"200")], &mut conn).unwrap();
+            let trailers = vec![Header::new(b"grpc-status".to_vec(), b"0".to_vec())];
+            session.new_headers(1, trailers, &mut conn).unwrap();
+        }
+        assert!(sender.sent.is_empty());
+        {
+            // A `:status` in the trailers makes them malformed.
+            state.insert_outgoing(TestStream::new());
+            let mut session = ClientSession::new(&mut state, &mut sender);
+            session.new_headers(3, vec![Header::new(b":status", b"200")], &mut conn).unwrap();
+            session.new_headers(3, vec![Header::new(b":status", b"200")], &mut conn).unwrap();
+        }
+        assert_eq!(state.get_stream_ref(3).unwrap().malformed,
+                   vec![MalformedHeaders::UnexpectedPseudoHeader(b":status".to_vec())]);
+        assert_eq!(sender.sent.len(), 1);
+    }
+
+    /// Tests that the `ClientSession` resets a stream that receives a header block after the
+    /// trailers, or a frame after the end of the stream.
+    #[test]
+    fn test_client_session_header_phase_violation() {
+        let mut state = DefaultSessionState::<ClientMarker, TestStream>::new();
+        state.insert_outgoing(TestStream::new());
+        state.insert_outgoing(TestStream::new());
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        {
+            let mut session = ClientSession::new(&mut state, &mut sender);
+            // Informational responses may precede the final one.
+            session.new_headers(1, vec![Header::new(b":status", b"100")], &mut conn).unwrap();
+            session.new_headers(1, vec![Header::new(b":status", b"200")], &mut conn).unwrap();
+            session.new_data_chunk(1, &[1, 2, 3], &mut conn).unwrap();
+            let trailers = vec![Header::new(b"grpc-status".to_vec(), b"0".to_vec())];
+            session.new_headers(1, trailers, &mut conn).unwrap();
+        }
+        assert!(sender.sent.is_empty());
+        {
+            // Nothing but the end of the stream may follow the trailers.
+            let mut session = ClientSession::new(&mut state, &mut sender);
+            let trailers = vec![Header::new(b"grpc-status".to_vec(), b"0".to_vec())];
+            session.new_headers(1, trailers, &mut conn).unwrap();
+        }
+        assert_eq!(sender.sent.len(), 1);
+        assert_eq!(state.get_stream_ref(1).unwrap().body, vec![1, 2, 3]);
+        assert!(state.get_stream_ref(1).unwrap().is_closed());
+        {
+            // A DATA frame before the response headers is just as much out of sequence.
+            let mut session = ClientSession::new(&mut state, &mut sender);
+            session.new_data_chunk(3, &[1], &mut conn).unwrap();
+        }
+        assert!(state.get_stream_ref(3).unwrap().body.is_empty());
+        assert_eq!(sender.sent.len(), 2);
+        for (raw, &id) in sender.sent.iter().zip([1, 3].iter()) {
+            match HttpFrame::from_raw(raw).unwrap() {
+                HttpFrame::RstStreamFrame(frame) => {
+                    assert_eq!(frame.get_stream_id(), id);
+                    assert_eq!(frame.error_code(), ErrorCode::ProtocolError);
+                },
+                _ => panic!("Expected a RST_STREAM frame"),
+            };
+        }
+    }
+
     /// Tests that the `ClientSession` signals the correct error to client code when told to go
     /// away by the peer.
     #[test]
@@ -647,6 +1650,7 @@ mod tests {
         if let Err(HttpError::PeerConnectionError(err)) = res {
             assert_eq!(err.error_code(), ErrorCode::ProtocolError);
             assert_eq!(err.debug_data(), None);
+            assert_eq!(err.connection_id(), Some(conn.id()));
         } else {
             panic!("Expected a PeerConnectionError");
         }
@@ -673,4 +1677,186 @@ mod tests {
         // ...which was not an ack, but our own settings.
         assert!(!frame.is_ack());
     }
+
+    /// Tests that the `ClientConnection` sends the same preface as the `write_preface` function.
+    #[test]
+    fn test_client_conn_send_preface() {
+        let mut conn = build_mock_client_conn();
+        let mut written: Vec<u8> = Vec::new();
+        let mut expected: Vec<u8> = Vec::new();
+
+        conn.send_preface(&mut written).unwrap();
+        write_preface(&mut expected).unwrap();
+
+        assert_eq!(written, expected);
+    }
+
+    /// Tests that the `ClientConnection` advertises the settings of its config in its preface.
+    #[test]
+    fn test_client_conn_send_preface_with_config() {
+        let config = Http2Config::new().initial_window_size(1 << 20);
+        let mut conn = ClientConnection::with_connection(
+            HttpConnection::with_config(HttpScheme::Http, config),
+            DefaultSessionState::<ClientMarker, TestStream>::new());
+        let mut written: Vec<u8> = Vec::new();
+
+        conn.send_preface(&mut written).unwrap();
+
+        let frames = parse_frames(&written[CLIENT_PREFACE.len()..]);
+        match HttpFrame::from_raw(&frames[0]).unwrap() {
+            HttpFrame::SettingsFrame(frame) => {
+                assert_eq!(frame.settings, vec![
+                    HttpSetting::EnablePush(0),
+                    HttpSetting::InitialWindowSize(1 << 20),
+                ]);
+            },
+            _ => panic!("Expected a SETTINGS frame"),
+        }
+        // The connection expects the server to acknowledge the settings of the preface.
+        assert_eq!(conn.conn.local_settings().pending_acks(), 1);
+        let advertised = conn.conn.local_settings().advertised();
+        assert!(!advertised.enable_push);
+        assert_eq!(advertised.initial_window_size, 1 << 20);
+    }
+
+    /// Tests that a server pushing once it acknowledged that push is disabled fails the
+    /// connection with a PROTOCOL_ERROR, while the pushes are ignored if push is enabled.
+    #[test]
+    fn test_client_conn_push_disabled() {
+        fn handle_push(config: Http2Config) -> (HttpResult<()>, MockSendFrame) {
+            let mut push_promise = pack_header(&(5, 0x5, 0x4, 1)).to_vec();
+            push_promise.extend_from_slice(&[0, 0, 0, 2, 0x82]);
+            let mut conn = ClientConnection::with_connection(
+                HttpConnection::with_config(HttpScheme::Http, config),
+                DefaultSessionState::<ClientMarker, TestStream>::new());
+            let mut sender = MockSendFrame::new();
+            let mut rx = MockReceiveFrame::new(vec![
+                HttpFrame::SettingsFrame(SettingsFrame::new_ack()),
+                HttpFrame::UnknownFrame(RawFrame::from(push_promise)),
+            ]);
+            conn.handle_next_frame(&mut rx, &mut sender).unwrap();
+            let res = conn.handle_next_frame(&mut rx, &mut sender);
+            (res, sender)
+        }
+
+        let (res, sender) = handle_push(Http2Config::new());
+        assert_eq!(res.err().unwrap(), HttpError::LocalConnectionError(ErrorCode::ProtocolError));
+        match HttpFrame::from_raw(&sender.sent[0]).unwrap() {
+            HttpFrame::GoawayFrame(frame) => {
+                assert_eq!(frame.error_code(), ErrorCode::ProtocolError);
+            },
+            _ => panic!("Expected a GOAWAY frame"),
+        }
+
+        let config = Http2Config::new().enable_push(true);
+        assert!(preface_settings(&config).is_empty());
+        let (res, sender) = handle_push(config);
+        assert!(res.is_ok());
+        assert!(sender.sent.is_empty());
+    }
+
+    /// Tests that the connection assigns the IDs of the requests sent by `send_request`, while
+    /// the explicitly chosen IDs of `send_request_with_id` are checked.
+    #[test]
+    fn test_client_conn_send_request() {
+        let request = |body: Vec<u8>| {
+            Request {
+                stream_id: 7,
+                headers: vec![Header::new(b":method", b"GET")],
+                body: body,
+            }
+        };
+        let mut conn = ClientConnection::with_connection(
+            build_mock_http_conn(),
+            DefaultSessionState::<ClientMarker, DefaultStream>::new());
+        let mut sender = MockSendFrame::new();
+
+        assert_eq!(conn.send_request(request(vec![]), &mut sender).unwrap(), 1);
+        conn.send_request_with_id(7, request(vec![1, 2, 3]), &mut sender).unwrap();
+        assert_eq!(conn.send_request_with_id(5, request(vec![]), &mut sender).err().unwrap(),
+                   HttpError::InvalidStreamId(StreamIdError::NotIncreasing(5)));
+        assert_eq!(conn.send_request(request(vec![]), &mut sender).unwrap(), 9);
+
+        let sent: Vec<_> = sender.sent.iter().map(|raw| {
+            match HttpFrame::from_raw(raw).unwrap() {
+                HttpFrame::HeadersFrame(frame) => {
+                    (frame.get_stream_id(), frame.is_end_of_stream())
+                },
+                _ => panic!("Expected a HEADERS frame"),
+            }
+        }).collect();
+        assert_eq!(sent, vec![(1, true), (7, false), (9, true)]);
+        assert!(conn.state.get_stream_ref(5).is_none());
+        assert_eq!(conn.state.get_stream_ref(7).unwrap().stream_id, Some(7));
+        // The body of the request follows the headers.
+        assert_eq!(conn.send_next_data(&mut sender).unwrap(), SendStatus::Sent);
+    }
+
+    /// Tests that the connection refuses any further operations once one of them failed with a
+    /// fatal error, while errors concerning a single request leave it usable.
+    #[test]
+    fn test_client_conn_poisoned() {
+        let request = || {
+            Request {
+                stream_id: 1,
+                headers: vec![Header::new(b":method", b"GET")],
+                body: vec![],
+            }
+        };
+        let mut conn = ClientConnection::with_connection(
+            build_mock_http_conn(),
+            DefaultSessionState::<ClientMarker, DefaultStream>::new());
+        let mut sender = MockSendFrame::new();
+        conn.send_request(request(), &mut sender).unwrap();
+        assert_eq!(conn.send_request_with_id(1, request(), &mut sender).err().unwrap(),
+                   HttpError::InvalidStreamId(StreamIdError::NotIncreasing(1)));
+        assert!(conn.is_usable());
+
+        // The peer goes away...
+        let mut rx = MockReceiveFrame::new(vec![]);
+        assert_eq!(conn.handle_next_frame(&mut rx, &mut sender).err().unwrap(),
+                   HttpError::PeerClosed { graceful: false });
+        assert!(!conn.is_usable());
+
+        // ...so nothing is sent or received any longer.
+        let mut sender = MockSendFrame::new();
+        let mut rx = MockReceiveFrame::new(vec![
+            HttpFrame::SettingsFrame(SettingsFrame::new_ack()),
+        ]);
+        assert_eq!(conn.send_request(request(), &mut sender).err().unwrap(),
+                   HttpError::ConnectionClosed);
+        assert_eq!(conn.send_next_data(&mut sender).err().unwrap(), HttpError::ConnectionClosed);
+        assert_eq!(conn.handle_next_frame(&mut rx, &mut sender).err().unwrap(),
+                   HttpError::ConnectionClosed);
+        assert!(sender.sent.is_empty());
+        assert_eq!(rx.recv_list.len(), 1);
+    }
+
+    /// Tests that the server asking for HTTP/1.1, for a single request or the whole connection,
+    /// is reported as such.
+    #[test]
+    fn test_client_conn_http11_required() {
+        let mut conn = ClientConnection::with_connection(
+            build_mock_http_conn(),
+            DefaultSessionState::<ClientMarker, DefaultStream>::new());
+        let mut sender = MockSendFrame::new();
+        let req = Request {
+            stream_id: 1,
+            headers: vec![Header::new(b":method", b"GET")],
+            body: vec![],
+        };
+        let stream_id = conn.send_request(req, &mut sender).unwrap();
+        let mut rx = MockReceiveFrame::new(vec![
+            HttpFrame::RstStreamFrame(RstStreamFrame::new(stream_id, ErrorCode::Http11Required)),
+            HttpFrame::GoawayFrame(GoawayFrame::new(stream_id, ErrorCode::Http11Required)),
+        ]);
+
+        conn.handle_next_frame(&mut rx, &mut sender).unwrap();
+        let stream = conn.state.get_stream_ref(stream_id).unwrap();
+        assert!(stream.is_closed());
+        assert_eq!(stream.error_code, Some(ErrorCode::Http11Required));
+
+        assert_eq!(conn.handle_next_frame(&mut rx, &mut sender).err().unwrap(),
+                   HttpError::Http11Required);
+    }
 }
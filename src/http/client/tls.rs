@@ -136,9 +136,31 @@ impl fmt::Debug for TlsConnectError {
     }
 }
 
+impl TlsConnectError {
+    /// Returns the application protocol that the server chose instead of HTTP/2, if the error is
+    /// due to the server not supporting HTTP/2 and it did choose some protocol (e.g.
+    /// `http/1.1`).
+    pub fn negotiated_protocol(&self) -> Option<&[u8]> {
+        match *self {
+            TlsConnectError::Http2NotSupported(ref stream) => stream.get_selected_npn_protocol(),
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for TlsConnectError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmt, "TLS HTTP/2 connect error: {}", (self as &error::Error).description())
+        try!(write!(fmt, "TLS HTTP/2 connect error: {}", (self as &error::Error).description()));
+        if let TlsConnectError::Http2NotSupported(_) = *self {
+            // Tell the user what the server went for instead.
+            match self.negotiated_protocol() {
+                Some(proto) => {
+                    try!(write!(fmt, " (negotiated {})", String::from_utf8_lossy(proto)));
+                },
+                None => try!(write!(fmt, " (no application protocol negotiated)")),
+            };
+        }
+        Ok(())
     }
 }
 
@@ -0,0 +1,341 @@
+//! The module defines the `Http2Config`, which gathers the parameters of an HTTP/2 connection
+//! that users can tune, so that the client and server implementations share a single way of
+//! configuring them.
+//!
+//! The config is built starting from the defaults and chaining the setters:
+//!
+//! ```rust
+//! use std::time::Duration;
+//! use solicit::http::config::Http2Config;
+//!
+//! let config = Http2Config::new()
+//!     .initial_window_size(1 << 20)
+//!     .max_concurrent_streams(Some(100))
+//!     .idle_timeout(Some(Duration::from_secs(60)));
+//! assert_eq!(config.initial_window_size, 1 << 20);
+//! ```
+use std::cmp;
+use std::time::Duration;
+use std::vec::Vec;
+
+use http::frame::HttpSetting;
+use http::{INITIAL_STREAM_WINDOW_SIZE, DEFAULT_MAX_FRAME_SIZE, MAX_MAX_FRAME_SIZE};
+use http::window;
+
+/// How strictly the received (and sent) header blocks are checked against the requirements that
+/// the HTTP/2 spec places on HTTP messages.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compliance {
+    /// Malformed messages (e.g. missing or duplicated pseudo-headers, a `content-length` that
+    /// does not match the body) cause the stream to be reset, as the spec requires.
+    Strict,
+    /// The messages are taken as they are. Useful for talking to peers that are known to be
+    /// slightly broken, but it is up to the user to cope with whatever those peers send.
+    Lenient,
+}
+
+impl Compliance {
+    /// Returns whether messages need to be validated.
+    #[inline]
+    pub fn is_strict(&self) -> bool {
+        *self == Compliance::Strict
+    }
+}
+
+/// The configuration of an HTTP/2 connection, shared by the client and server implementations.
+///
+/// The settings-related fields (window size, frame size, header list size and concurrent
+/// streams) describe what the local endpoint allows its peer to do; only those that differ from
+/// the protocol defaults are advertised (see `settings`).
+#[derive(Clone, PartialEq, Debug)]
+pub struct Http2Config {
+    /// The initial flow control window size of the streams that the peer sends data on.
+    pub initial_window_size: u32,
+    /// The largest frame payload that the peer is allowed to send.
+    pub max_frame_size: u32,
+    /// The maximum size of a header list that the peer is allowed to send, if limited.
+    pub max_header_list_size: Option<u32>,
+    /// The maximum number of streams that the peer is allowed to have open at once, if limited.
+    pub max_concurrent_streams: Option<u32>,
+    /// The maximum number of fields (pseudo-headers included) in the header block that opens a
+    /// request, which a server accepts, if limited. It is not advertised to the peer.
+    pub max_header_count: Option<usize>,
+    /// The maximum length of the `:path` of a request that a server accepts, if limited. It is
+    /// not advertised to the peer.
+    pub max_path_length: Option<usize>,
+    /// How long the connection can go without receiving any frames before a PING is sent to
+    /// check that the peer is still there, if at all.
+    pub keepalive: Option<Duration>,
+    /// How long the connection can go without receiving any frames while there are no active
+    /// streams, before it is closed.
+    pub idle_timeout: Option<Duration>,
+    /// How long the peer has to complete the connection preface exchange.
+    pub handshake_timeout: Option<Duration>,
+    /// How strictly the exchanged messages are checked.
+    pub compliance: Compliance,
+    /// Whether HPACK's dynamic table is used. If not, the headers that are sent are only encoded
+    /// using the static table and literals, while the peer is asked (by advertising a
+    /// SETTINGS_HEADER_TABLE_SIZE of 0) to do the same.
+    pub dynamic_header_table: bool,
+    /// The memory that the connection may hold on to (HPACK tables, stream buffers and queued
+    /// frames), in bytes, if limited. See the `memory` module for how it is enforced.
+    pub memory_budget: Option<usize>,
+    /// How long a stream may have its DATA held back by an exhausted outbound flow control
+    /// window (the connection's or the stream's own), i.e. by a peer that stopped reading, before
+    /// the stream is reset, if at all.
+    pub slow_consumer_timeout: Option<Duration>,
+    /// Whether the whole connection is closed (rather than just the stream) once a stream stays
+    /// blocked for longer than the `slow_consumer_timeout`.
+    pub close_slow_consumers: bool,
+    /// Whether the server is allowed to push streams. Only clients advertise it (by sending a
+    /// SETTINGS_ENABLE_PUSH of 0 unless it is enabled), and as pushed streams are not supported,
+    /// enabling it only means that the server's PUSH_PROMISE frames are ignored, rather than
+    /// failing the connection with a PROTOCOL_ERROR.
+    pub enable_push: bool,
+    /// How long a client that reconnects (see `client::Client::with_reconnect`) keeps using a
+    /// connection before gracefully replacing it with a new one, if at all.
+    pub max_connection_age: Option<Duration>,
+}
+
+impl Default for Http2Config {
+    fn default() -> Http2Config {
+        Http2Config {
+            initial_window_size: INITIAL_STREAM_WINDOW_SIZE as u32,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_header_list_size: None,
+            max_concurrent_streams: None,
+            max_header_count: None,
+            max_path_length: None,
+            keepalive: None,
+            idle_timeout: None,
+            handshake_timeout: None,
+            compliance: Compliance::Strict,
+            dynamic_header_table: true,
+            memory_budget: None,
+            slow_consumer_timeout: None,
+            close_slow_consumers: false,
+            enable_push: false,
+            max_connection_age: None,
+        }
+    }
+}
+
+impl Http2Config {
+    /// Creates a new `Http2Config` with the default values: the protocol defaults for all
+    /// settings, no timeouts and strict compliance.
+    pub fn new() -> Http2Config {
+        Http2Config::default()
+    }
+
+    /// Sets the initial window size of the streams, capped at the maximum allowed by the spec.
+    pub fn initial_window_size(mut self, size: u32) -> Http2Config {
+        self.initial_window_size = window::clamp(size);
+        self
+    }
+
+    /// Sets the maximum frame size, clamped to the range allowed by the spec.
+    pub fn max_frame_size(mut self, size: u32) -> Http2Config {
+        self.max_frame_size = cmp::max(cmp::min(size, MAX_MAX_FRAME_SIZE), DEFAULT_MAX_FRAME_SIZE);
+        self
+    }
+
+    /// Sets the maximum header list size.
+    pub fn max_header_list_size(mut self, size: Option<u32>) -> Http2Config {
+        self.max_header_list_size = size;
+        self
+    }
+
+    /// Sets the maximum number of concurrent streams.
+    pub fn max_concurrent_streams(mut self, max: Option<u32>) -> Http2Config {
+        self.max_concurrent_streams = max;
+        self
+    }
+
+    /// Sets the maximum number of header fields in a request.
+    pub fn max_header_count(mut self, max: Option<usize>) -> Http2Config {
+        self.max_header_count = max;
+        self
+    }
+
+    /// Sets the maximum length of the `:path` of a request.
+    pub fn max_path_length(mut self, max: Option<usize>) -> Http2Config {
+        self.max_path_length = max;
+        self
+    }
+
+    /// Sets the keepalive interval.
+    pub fn keepalive(mut self, interval: Option<Duration>) -> Http2Config {
+        self.keepalive = interval;
+        self
+    }
+
+    /// Sets the idle timeout.
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Http2Config {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Sets the handshake timeout.
+    pub fn handshake_timeout(mut self, timeout: Option<Duration>) -> Http2Config {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Sets the compliance mode.
+    pub fn compliance(mut self, compliance: Compliance) -> Http2Config {
+        self.compliance = compliance;
+        self
+    }
+
+    /// Sets whether HPACK's dynamic table is used, in both directions.
+    pub fn dynamic_header_table(mut self, enabled: bool) -> Http2Config {
+        self.dynamic_header_table = enabled;
+        self
+    }
+
+    /// Sets the memory budget of the connection.
+    pub fn memory_budget(mut self, budget: Option<usize>) -> Http2Config {
+        self.memory_budget = budget;
+        self
+    }
+
+    /// Sets the slow consumer timeout.
+    pub fn slow_consumer_timeout(mut self, timeout: Option<Duration>) -> Http2Config {
+        self.slow_consumer_timeout = timeout;
+        self
+    }
+
+    /// Sets whether the connection is closed when it has a slow consumer.
+    pub fn close_slow_consumers(mut self, close: bool) -> Http2Config {
+        self.close_slow_consumers = close;
+        self
+    }
+
+    /// Sets whether the server is allowed to push streams.
+    pub fn enable_push(mut self, enabled: bool) -> Http2Config {
+        self.enable_push = enabled;
+        self
+    }
+
+    /// Sets the maximum connection age.
+    pub fn max_connection_age(mut self, age: Option<Duration>) -> Http2Config {
+        self.max_connection_age = age;
+        self
+    }
+
+    /// Applies the given list of settings to the config, as if by the corresponding setters, e.g.
+    /// to advertise settings that are read from an application's own configuration.
+    ///
+    /// Since the config has no notion of a header table size other than the default, any
+    /// non-zero SETTINGS_HEADER_TABLE_SIZE keeps the dynamic table enabled, while 0 disables it.
+    /// Unknown settings are ignored.
+    pub fn with_settings(self, settings: &[HttpSetting]) -> Http2Config {
+        settings.iter().fold(self, |config, setting| {
+            match *setting {
+                HttpSetting::HeaderTableSize(size) => config.dynamic_header_table(size != 0),
+                HttpSetting::EnablePush(enabled) => config.enable_push(enabled != 0),
+                HttpSetting::MaxConcurrentStreams(max) => config.max_concurrent_streams(Some(max)),
+                HttpSetting::InitialWindowSize(size) => config.initial_window_size(size),
+                HttpSetting::MaxFrameSize(size) => config.max_frame_size(size),
+                HttpSetting::MaxHeaderListSize(size) => config.max_header_list_size(Some(size)),
+                HttpSetting::Unknown(..) => config,
+            }
+        })
+    }
+
+    /// Returns the settings that need to be advertised to the peer, i.e. the ones whose values
+    /// differ from the protocol defaults. The SETTINGS_ENABLE_PUSH is not included, since only
+    /// clients may advertise it.
+    pub fn settings(&self) -> Vec<HttpSetting> {
+        let mut settings = Vec::new();
+        if !self.dynamic_header_table {
+            settings.push(HttpSetting::HeaderTableSize(0));
+        }
+        if let Some(max) = self.max_concurrent_streams {
+            settings.push(HttpSetting::MaxConcurrentStreams(max));
+        }
+        if self.initial_window_size != INITIAL_STREAM_WINDOW_SIZE as u32 {
+            settings.push(HttpSetting::InitialWindowSize(self.initial_window_size));
+        }
+        if self.max_frame_size != DEFAULT_MAX_FRAME_SIZE {
+            settings.push(HttpSetting::MaxFrameSize(self.max_frame_size));
+        }
+        if let Some(size) = self.max_header_list_size {
+            settings.push(HttpSetting::MaxHeaderListSize(size));
+        }
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Http2Config, Compliance};
+    use http::frame::HttpSetting;
+    use std::time::Duration;
+
+    /// Tests that the default config advertises no settings.
+    #[test]
+    fn test_default_config_settings() {
+        let config = Http2Config::new();
+        assert_eq!(config, Http2Config::default());
+        assert_eq!(config.initial_window_size, 65_535);
+        assert_eq!(config.max_frame_size, 16_384);
+        assert_eq!(config.compliance, Compliance::Strict);
+        assert!(!config.enable_push);
+        assert!(config.settings().is_empty());
+    }
+
+    /// Tests that the settings that differ from the defaults are advertised.
+    #[test]
+    fn test_config_settings() {
+        let config = Http2Config::new()
+            .initial_window_size(1 << 20)
+            .max_frame_size(1 << 16)
+            .max_header_list_size(Some(8192))
+            .max_concurrent_streams(Some(10))
+            .keepalive(Some(Duration::from_secs(30)));
+
+        assert_eq!(config.settings(), vec![
+            HttpSetting::MaxConcurrentStreams(10),
+            HttpSetting::InitialWindowSize(1 << 20),
+            HttpSetting::MaxFrameSize(1 << 16),
+            HttpSetting::MaxHeaderListSize(8192),
+        ]);
+        assert_eq!(config.keepalive, Some(Duration::from_secs(30)));
+    }
+
+    /// Tests that a list of settings is applied to the config and advertised back as it is.
+    #[test]
+    fn test_config_with_settings() {
+        let settings = vec![
+            HttpSetting::HeaderTableSize(0),
+            HttpSetting::MaxConcurrentStreams(250),
+            HttpSetting::InitialWindowSize(1 << 20),
+            HttpSetting::MaxFrameSize(1 << 20),
+        ];
+        let config = Http2Config::new().with_settings(&settings);
+
+        assert_eq!(config.settings(), settings);
+        assert!(Http2Config::new().with_settings(&[HttpSetting::EnablePush(1)]).enable_push);
+    }
+
+    /// Tests that disabling the dynamic header table advertises a table size of 0.
+    #[test]
+    fn test_config_no_dynamic_header_table() {
+        let config = Http2Config::new().dynamic_header_table(false);
+
+        assert!(!config.dynamic_header_table);
+        assert_eq!(config.settings(), vec![HttpSetting::HeaderTableSize(0)]);
+    }
+
+    /// Tests that the values that the spec does not allow are clamped.
+    #[test]
+    fn test_config_clamps_values() {
+        let config = Http2Config::new().initial_window_size(0xffffffff).max_frame_size(100);
+        assert_eq!(config.initial_window_size, 0x7fffffff);
+        assert_eq!(config.max_frame_size, 16_384);
+
+        let config = Http2Config::new().max_frame_size(0xffffffff);
+        assert_eq!(config.max_frame_size, 0xffffff);
+    }
+}
@@ -17,33 +17,70 @@
 
 use std::borrow::Cow;
 use std::borrow::Borrow;
+use std::cmp;
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+use std::time::{Duration, Instant};
 
 use http::{
     Header,
     StreamId,
+    ConnectionId,
+    ErrorCode,
     HttpError,
     HttpResult,
     HttpScheme,
     WindowSize,
     INITIAL_CONNECTION_WINDOW_SIZE,
+    DEFAULT_HEADER_TABLE_SIZE,
 };
 use http::priority::DataPrioritizer;
-use http::session::Session;
+use http::memory::MemoryUsage;
+use http::soak::{Allocation, Resource};
+use http::tracing::{ConnectionTrace, Role};
+use http::flow_control::{self, FlowControlStrategy, AdaptiveWindow, StreamWindows};
+use http::rate_limit::{RateLimit, RateLimiter};
+use http::padding::{self, PaddingStrategy};
+use http::session::{Session, StreamState};
+use http::validation;
+use http::window;
+use http::config::Http2Config;
+use http::static_table::StaticTableEncoder;
+use http::settings::{SettingsState, LocalSettings};
 use http::frame::{
     Frame,
+    Flag,
     FrameIR,
+    FRAME_HEADER_LEN,
+    FrameBuilder,
+    FrameHeader,
+    FrameParseError,
     RawFrame,
     DataFrame,
     DataFlag,
     HeadersFrame,
     HeadersFlag,
+    StreamDependency,
+    PriorityFrame,
+    ContinuationFrame,
+    ContinuationFlag,
     SettingsFrame,
+    SettingsFlag,
+    HttpSetting,
     RstStreamFrame,
     GoawayFrame,
     WindowUpdateFrame,
+    PingFrame,
+    AltSvcFrame,
 };
+use http::frame::priority::{PRIORITY_FRAME_LEN, PRIORITY_FRAME_TYPE};
+use http::frame::window_update::WINDOW_UPDATE_FRAME_LEN;
 use hpack;
 
+/// The type of PUSH_PROMISE frames, which are not parsed into an `HttpFrame` of their own.
+const PUSH_PROMISE_FRAME_TYPE: u8 = 0x5;
+
 /// An enum representing all frame variants that can be returned by an `HttpConnection` can handle.
 ///
 /// The variants wrap the appropriate `Frame` implementation, except for the `UnknownFrame`
@@ -54,28 +91,60 @@ use hpack;
 pub enum HttpFrame<'a> {
     DataFrame(DataFrame<'a>),
     HeadersFrame(HeadersFrame<'a>),
+    PriorityFrame(PriorityFrame),
     RstStreamFrame(RstStreamFrame),
     SettingsFrame(SettingsFrame),
     GoawayFrame(GoawayFrame<'a>),
     WindowUpdateFrame(WindowUpdateFrame),
+    PingFrame(PingFrame),
+    ContinuationFrame(ContinuationFrame<'a>),
+    AltSvcFrame(AltSvcFrame<'a>),
     UnknownFrame(RawFrame<'a>),
 }
 
 impl<'a> HttpFrame<'a> {
     pub fn from_raw(raw_frame: &'a RawFrame) -> HttpResult<HttpFrame<'a>> {
         let frame = match raw_frame.header().1 {
-            0x0 => HttpFrame::DataFrame(try!(HttpFrame::parse_frame(&raw_frame))),
-            0x1 => HttpFrame::HeadersFrame(try!(HttpFrame::parse_frame(&raw_frame))),
+            0x0 => HttpFrame::DataFrame(try!(DataFrame::parse(&raw_frame)
+                                                       .map_err(HttpFrame::parse_error))),
+            0x1 => HttpFrame::HeadersFrame(try!(HeadersFrame::parse(&raw_frame)
+                                                       .map_err(HttpFrame::parse_error))),
+            0x2 => try!(HttpFrame::parse_priority_frame(&raw_frame)),
             0x3 => HttpFrame::RstStreamFrame(try!(HttpFrame::parse_frame(&raw_frame))),
-            0x4 => HttpFrame::SettingsFrame(try!(HttpFrame::parse_frame(&raw_frame))),
+            0x4 => HttpFrame::SettingsFrame(try!(HttpFrame::parse_settings_frame(&raw_frame))),
+            0x6 => HttpFrame::PingFrame(try!(HttpFrame::parse_frame(&raw_frame))),
             0x7 => HttpFrame::GoawayFrame(try!(HttpFrame::parse_frame(&raw_frame))),
-            0x8 => HttpFrame::WindowUpdateFrame(try!(HttpFrame::parse_frame(&raw_frame))),
+            0x8 => HttpFrame::WindowUpdateFrame(try!(HttpFrame::parse_window_update_frame(
+                        &raw_frame))),
+            0x9 => HttpFrame::ContinuationFrame(try!(HttpFrame::parse_frame(&raw_frame))),
+            // An ALTSVC frame that cannot be parsed is ignored, like any unknown frame.
+            0xa => match Frame::from_raw(&raw_frame) {
+                Some(frame) => HttpFrame::AltSvcFrame(frame),
+                None => HttpFrame::UnknownFrame(raw_frame.as_ref().into()),
+            },
             _ => HttpFrame::UnknownFrame(raw_frame.as_ref().into()),
         };
 
         Ok(frame)
     }
 
+    /// Returns the header of the frame.
+    pub fn header(&self) -> FrameHeader {
+        match *self {
+            HttpFrame::DataFrame(ref frame) => frame.get_header(),
+            HttpFrame::HeadersFrame(ref frame) => frame.get_header(),
+            HttpFrame::PriorityFrame(ref frame) => frame.get_header(),
+            HttpFrame::RstStreamFrame(ref frame) => frame.get_header(),
+            HttpFrame::SettingsFrame(ref frame) => frame.get_header(),
+            HttpFrame::GoawayFrame(ref frame) => frame.get_header(),
+            HttpFrame::WindowUpdateFrame(ref frame) => frame.get_header(),
+            HttpFrame::PingFrame(ref frame) => frame.get_header(),
+            HttpFrame::ContinuationFrame(ref frame) => frame.get_header(),
+            HttpFrame::AltSvcFrame(ref frame) => frame.get_header(),
+            HttpFrame::UnknownFrame(ref frame) => frame.header(),
+        }
+    }
+
     /// A helper method that parses the given `RawFrame` into the given `Frame`
     /// implementation.
     ///
@@ -90,6 +159,76 @@ impl<'a> HttpFrame<'a> {
         //       taken (e.g. responding with a PROTOCOL_ERROR).
         Frame::from_raw(&raw_frame).ok_or(HttpError::InvalidFrame)
     }
+
+    /// Maps the reason why a frame could not be parsed to the error that the connection needs to
+    /// report: the connection error that the spec requires, if any, or else an
+    /// `HttpError::InvalidFrame`.
+    fn parse_error(err: FrameParseError) -> HttpError {
+        match err.error_code() {
+            Some(error_code) => {
+                debug!("Invalid frame received: {:?}", err);
+                HttpError::LocalConnectionError(error_code)
+            },
+            None => HttpError::InvalidFrame,
+        }
+    }
+
+    /// Parses a SETTINGS frame. A SETTINGS ACK that carries a payload is a connection error of
+    /// type FRAME_SIZE_ERROR (section 6.5.), rather than merely an invalid frame.
+    fn parse_settings_frame(raw_frame: &'a RawFrame) -> HttpResult<SettingsFrame> {
+        let (len, _, flags, _) = raw_frame.header();
+        if flags & SettingsFlag::Ack.bitmask() != 0 && len != 0 {
+            debug!("SETTINGS ACK with a payload of {} bytes", len);
+            return Err(HttpError::LocalConnectionError(ErrorCode::FrameSizeError));
+        }
+        HttpFrame::parse_frame(raw_frame)
+    }
+
+    /// Parses a PRIORITY frame. One on stream 0 is a connection error of type PROTOCOL_ERROR,
+    /// while one of the wrong length is only a stream error of type FRAME_SIZE_ERROR
+    /// (section 6.3.), which is left to the connection: such a frame is kept as an
+    /// `UnknownFrame`.
+    fn parse_priority_frame(raw_frame: &'a RawFrame) -> HttpResult<HttpFrame<'a>> {
+        let (len, _, _, stream_id) = raw_frame.header();
+        if stream_id == 0 {
+            debug!("PRIORITY frame on stream 0");
+            return Err(HttpError::LocalConnectionError(ErrorCode::ProtocolError));
+        }
+        if len != PRIORITY_FRAME_LEN {
+            return Ok(HttpFrame::UnknownFrame(raw_frame.as_ref().into()));
+        }
+        Ok(HttpFrame::PriorityFrame(try!(HttpFrame::parse_frame(raw_frame))))
+    }
+
+    /// Parses a WINDOW_UPDATE frame. One with a length other than 4 octets is a connection error
+    /// of type FRAME_SIZE_ERROR (section 6.9.), rather than merely an invalid frame.
+    fn parse_window_update_frame(raw_frame: &'a RawFrame) -> HttpResult<WindowUpdateFrame> {
+        let (len, _, _, stream_id) = raw_frame.header();
+        if len != WINDOW_UPDATE_FRAME_LEN {
+            debug!("WINDOW_UPDATE frame of length {} on stream {}", len, stream_id);
+            return Err(HttpError::LocalConnectionError(ErrorCode::FrameSizeError));
+        }
+        HttpFrame::parse_frame(raw_frame)
+    }
+}
+
+/// Any `HttpFrame` can be serialized to its on-the-wire format.
+impl<'a> FrameIR for HttpFrame<'a> {
+    fn serialize_into<B: FrameBuilder>(self, b: &mut B) -> io::Result<()> {
+        match self {
+            HttpFrame::DataFrame(frame) => frame.serialize_into(b),
+            HttpFrame::HeadersFrame(frame) => frame.serialize_into(b),
+            HttpFrame::PriorityFrame(frame) => frame.serialize_into(b),
+            HttpFrame::RstStreamFrame(frame) => frame.serialize_into(b),
+            HttpFrame::SettingsFrame(frame) => frame.serialize_into(b),
+            HttpFrame::GoawayFrame(frame) => frame.serialize_into(b),
+            HttpFrame::WindowUpdateFrame(frame) => frame.serialize_into(b),
+            HttpFrame::PingFrame(frame) => frame.serialize_into(b),
+            HttpFrame::ContinuationFrame(frame) => frame.serialize_into(b),
+            HttpFrame::AltSvcFrame(frame) => frame.serialize_into(b),
+            HttpFrame::UnknownFrame(frame) => frame.serialize_into(b),
+        }
+    }
 }
 
 /// The enum represents the success status of the operation of sending a next data chunk on an
@@ -102,6 +241,60 @@ pub enum SendStatus {
     ///
     /// This is the case when none of the streams had any data to write.
     Nothing,
+    /// Indicates that nothing was sent because the connection's rate limit does not currently
+    /// allow any (more) data to be sent; sending should be retried later.
+    Throttled,
+}
+
+/// The stream-specific frames whose legality the `HttpConnection` checks against the state of the
+/// stream that they are received on.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum StreamFrame {
+    Data,
+    Headers,
+    RstStream,
+    WindowUpdate,
+}
+
+/// The kind of error that receiving a frame on a stream in a state that does not allow it is.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum StateViolation {
+    /// A stream error: the stream should be reset with the given error code.
+    Stream(ErrorCode),
+    /// A connection error: the connection should be closed with the given error code.
+    Connection(ErrorCode),
+}
+
+impl StreamFrame {
+    /// Checks whether the frame may be received on a stream in the given state, following
+    /// [section 5.1.](http://http2.github.io/http2-spec/#rfc.section.5.1) of the spec.
+    fn check_state(self, state: StreamState) -> Result<(), StateViolation> {
+        use self::StreamFrame::*;
+        use self::StateViolation::{Stream, Connection};
+
+        match (self, state) {
+            // Resetting an idle stream is a connection error; any other state accepts it.
+            (RstStream, StreamState::Idle) => Err(Connection(ErrorCode::ProtocolError)),
+            (RstStream, _) => Ok(()),
+
+            // The same goes for updating the window of an idle stream (section 5.1.); it may
+            // still arrive for a stream that was just closed, though.
+            (WindowUpdate, StreamState::Idle) => Err(Connection(ErrorCode::ProtocolError)),
+            (WindowUpdate, _) => Ok(()),
+
+            (Data, StreamState::Open) | (Data, StreamState::HalfClosedLocal) => Ok(()),
+            (Data, StreamState::HalfClosedRemote) | (Data, StreamState::Closed) => {
+                Err(Stream(ErrorCode::StreamClosed))
+            },
+            (Data, _) => Err(Connection(ErrorCode::ProtocolError)),
+
+            (Headers, StreamState::HalfClosedRemote) | (Headers, StreamState::Closed) => {
+                Err(Stream(ErrorCode::StreamClosed))
+            },
+            (Headers, StreamState::ReservedLocal) => Err(Connection(ErrorCode::ProtocolError)),
+            (Headers, _) => Ok(()),
+        }
+    }
 }
 
 /// The struct implements the HTTP/2 connection level logic.
@@ -119,13 +312,172 @@ pub struct HttpConnection {
     /// HPACK decoder used to decode incoming headers before passing them on to the session.
     decoder: hpack::Decoder<'static>,
     /// The HPACK encoder used to encode headers before sending them on this connection.
-    encoder: hpack::Encoder<'static>,
+    encoder: HeaderEncoder,
     /// Tracks the size of the outbound flow control window
     out_window_size: WindowSize,
+    /// Tracks the outbound flow control windows of the streams that we are sending data on.
+    stream_windows: StreamWindows,
     /// Tracks the size of the inbound flow control window
     in_window_size: WindowSize,
     /// The scheme of the connection
     pub scheme: HttpScheme,
+    /// Whether the peer has sent a GOAWAY frame on the connection.
+    goaway_received: bool,
+    /// The connection error that the peer caused, if any. Once set, no more frames are handled.
+    connection_error: Option<ErrorCode>,
+    /// The state of the adaptive flow control strategy, if it is used.
+    adaptive_window: Option<AdaptiveWindow>,
+    /// The increments of the inbound connection window that the adaptive strategy held back since
+    /// the window was frozen, if it is (see `freeze_in_window`).
+    frozen_in_window: Option<u32>,
+    /// Control frames that the connection needs to send in response to the frames it handled.
+    pending_frames: Vec<(ControlFrame, Allocation)>,
+    /// Limits the rate at which DATA is sent, if set.
+    rate_limiter: Option<RateLimiter>,
+    /// The strategies with which the DATA frames of the streams that have one are padded.
+    padding: HashMap<StreamId, PaddingStrategy>,
+    /// Our own settings, split into the ones that the peer acknowledged and the ones that it is
+    /// yet to acknowledge.
+    local_settings: LocalSettings,
+    /// The settings that the peer announced, which constrain what we may send it.
+    peer_settings: SettingsState,
+    /// The buffers that received header blocks are decoded into.
+    header_arena: HeaderArena,
+    /// The header block that is being received, while its HEADERS frame is followed by
+    /// CONTINUATION frames.
+    header_block: Option<HeaderBlock>,
+    /// The configuration of the connection.
+    config: Http2Config,
+    /// When the connection was established and when it last received and sent a frame.
+    activity: ConnectionActivity,
+    /// The ID of the connection, assigned when it is created.
+    id: ConnectionId,
+    /// Records the span of the connection once it is dropped (see `http::tracing`).
+    trace: ConnectionTrace,
+}
+
+/// When a connection was established and when it last received and sent a frame, e.g. for pools
+/// that close connections past a certain age, or ones that the peer has gone quiet on.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ConnectionActivity {
+    /// When the connection was created.
+    pub established_at: Instant,
+    /// When a frame was last received from the peer, if any was received yet.
+    pub last_frame_received: Option<Instant>,
+    /// When a frame was last sent to the peer, if any was sent yet.
+    pub last_frame_sent: Option<Instant>,
+}
+
+impl ConnectionActivity {
+    /// Creates the activity of a connection that was just established.
+    fn new() -> ConnectionActivity {
+        ConnectionActivity {
+            established_at: Instant::now(),
+            last_frame_received: None,
+            last_frame_sent: None,
+        }
+    }
+
+    /// Returns how long ago the connection was established.
+    pub fn age(&self) -> Duration {
+        self.established_at.elapsed()
+    }
+
+    /// Returns when the peer was last heard from: when its last frame was received, or when the
+    /// connection was established, if it sent nothing yet.
+    pub fn last_peer_activity(&self) -> Instant {
+        self.last_frame_received.unwrap_or(self.established_at)
+    }
+
+    /// Returns how long the peer has been quiet for, i.e. the time since `last_peer_activity`.
+    pub fn peer_idle_for(&self) -> Duration {
+        self.last_peer_activity().elapsed()
+    }
+}
+
+/// The buffers that header blocks are decoded into, reused from one block to the next.
+///
+/// Decoding a block into owned headers would allocate two buffers for every header (and most
+/// blocks carry a dozen or more of them). Instead, the names and values are copied back to back
+/// into a single buffer and the headers handed to the session borrow from it, so that decoding
+/// allocates nothing beyond the list of headers, once the buffers have grown to the size of the
+/// usual blocks.
+#[derive(Clone, Debug, Default)]
+struct HeaderArena {
+    /// The names and values of the decoded headers, back to back.
+    bytes: Vec<u8>,
+    /// Where the name and the value of each header end in `bytes`.
+    ends: Vec<(usize, usize)>,
+}
+
+impl HeaderArena {
+    /// Decodes the given header block into the arena, replacing its previous contents.
+    fn decode(&mut self, decoder: &mut hpack::Decoder, block: &[u8]) -> HttpResult<()> {
+        self.bytes.clear();
+        self.ends.clear();
+        let bytes = &mut self.bytes;
+        let ends = &mut self.ends;
+        decoder.decode_with_cb(block, |name, value| {
+            bytes.extend_from_slice(&name);
+            let name_end = bytes.len();
+            bytes.extend_from_slice(&value);
+            ends.push((name_end, bytes.len()));
+        }).map_err(HttpError::CompressionError)
+    }
+
+    /// Returns the decoded headers, borrowing their names and values from the arena.
+    fn headers(&self) -> Vec<Header> {
+        let mut start = 0;
+        self.ends.iter().map(|&(name_end, value_end)| {
+            let header = Header::new(&self.bytes[start..name_end],
+                                     &self.bytes[name_end..value_end]);
+            start = value_end;
+            header
+        }).collect()
+    }
+}
+
+/// A header block whose fragments are still being received.
+#[derive(Clone, Debug)]
+struct HeaderBlock {
+    stream_id: StreamId,
+    /// The fragments received so far, concatenated.
+    fragment: Vec<u8>,
+    /// Whether the HEADERS frame that started the block also ended the stream.
+    end_stream: bool,
+    /// The priority information that the HEADERS frame carried, if any.
+    stream_dep: Option<StreamDependency>,
+    /// The stream promised by the PUSH_PROMISE frame that started the block, if it was not
+    /// started by a HEADERS frame.
+    promised_stream_id: Option<StreamId>,
+    /// Accounts for the buffered header block in soak tests.
+    _allocation: Allocation,
+}
+
+/// The HPACK encoder of a connection, which uses the dynamic table unless the connection's config
+/// disables it.
+enum HeaderEncoder {
+    Dynamic(hpack::Encoder<'static>),
+    Static(StaticTableEncoder),
+}
+
+impl HeaderEncoder {
+    fn encode<'b, I>(&mut self, headers: I) -> Vec<u8>
+            where I: IntoIterator<Item=(&'b [u8], &'b [u8])> {
+        match *self {
+            HeaderEncoder::Dynamic(ref mut encoder) => encoder.encode(headers),
+            HeaderEncoder::Static(ref mut encoder) => encoder.encode(headers),
+        }
+    }
+}
+
+/// A control frame queued by the `HttpConnection` while handling received frames, to be sent by
+/// `HttpConnectionSender::send_pending_frames`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ControlFrame {
+    WindowUpdate(StreamId, u32),
+    Ping(u64),
+    PingAck(u64),
 }
 
 /// A trait that should be implemented by types that can provide the functionality
@@ -144,6 +496,28 @@ pub trait ReceiveFrame {
     /// Return a new `HttpFrame` instance. Unknown frames can be wrapped in the
     /// `HttpFrame::UnknownFrame` variant (i.e. their `RawFrame` representation).
     fn recv_frame(&mut self) -> HttpResult<HttpFrame>;
+
+    /// Returns the first frame that the peer sends on the connection, which is expected to be the
+    /// SETTINGS frame of its connection preface.
+    ///
+    /// Implementations that read raw bytes off of a transport can override it in order to detect
+    /// peers that do not speak HTTP/2 before attempting to read a whole frame, returning an
+    /// `HttpError::NotHttp2`.
+    ///
+    /// The default implementation simply receives the next frame.
+    fn recv_preface_frame(&mut self) -> HttpResult<HttpFrame> {
+        self.recv_frame()
+    }
+
+    /// Sets the largest frame payload that the peer may send, i.e. our SETTINGS_MAX_FRAME_SIZE.
+    /// The connection calls it before receiving each frame.
+    ///
+    /// Implementations that read raw bytes off of a transport should refuse a longer frame with
+    /// an `HttpError::LocalConnectionError(ErrorCode::FrameSizeError)`, before reading its
+    /// payload (see `transport::check_frame_size`).
+    ///
+    /// The default implementation does nothing.
+    fn set_max_frame_size(&mut self, _max_frame_size: u32) {}
 }
 
 /// The struct represents a chunk of data that should be sent to the peer on a particular stream.
@@ -215,7 +589,9 @@ impl<'a, S> HttpConnectionSender<'a, S> where S: SendFrame + 'a {
     /// If the frame is successfully written, returns a unit Ok (`Ok(())`).
     #[inline]
     fn send_frame<F: FrameIR>(&mut self, frame: F) -> HttpResult<()> {
-        self.sender.send_frame(frame)
+        try!(self.sender.send_frame(frame));
+        self.conn.activity.last_frame_sent = Some(Instant::now());
+        Ok(())
     }
 
     /// Sends a SETTINGS acknowledge frame to the peer.
@@ -223,6 +599,94 @@ impl<'a, S> HttpConnectionSender<'a, S> where S: SendFrame + 'a {
         self.send_frame(SettingsFrame::new_ack())
     }
 
+    /// Sends a SETTINGS frame carrying the given settings to the peer.
+    ///
+    /// The settings take effect (i.e. are reflected by the connection's `local_settings`) once
+    /// the peer acknowledges them.
+    pub fn send_settings(&mut self, settings: &[HttpSetting]) -> HttpResult<()> {
+        let mut frame = SettingsFrame::new();
+        for setting in settings.iter() {
+            frame.add_setting(*setting);
+        }
+        try!(self.send_frame(frame));
+        self.conn.local_settings.sent(settings);
+        Ok(())
+    }
+
+    /// Sends a PING frame carrying the given opaque data to the peer.
+    pub fn send_ping(&mut self, data: u64) -> HttpResult<()> {
+        self.send_frame(PingFrame::new(data))
+    }
+
+    /// Sends a GOAWAY frame to the peer, indicating that the connection is being shut down.
+    ///
+    /// # Parameters
+    ///
+    /// - `last_stream_id` - the highest numbered peer-initiated stream that was (or might still
+    ///   be) processed.
+    /// - `error_code` - the reason for closing the connection; `ErrorCode::NoError` signals a
+    ///   graceful shutdown.
+    pub fn send_goaway(&mut self, last_stream_id: StreamId, error_code: ErrorCode)
+            -> HttpResult<()> {
+        self.send_frame(GoawayFrame::new(last_stream_id, error_code))
+    }
+
+    /// Sends a WINDOW_UPDATE frame to the peer, increasing the inbound flow control window of the
+    /// given stream by the given increment. Stream 0 refers to the connection-level window, in
+    /// which case the connection's `in_window_size` grows by the increment as well.
+    ///
+    /// This is how applications replenish the windows under the manual flow control strategy.
+    ///
+    /// An increment of 0, or one that exceeds the maximum window size (2^31 - 1), results in an
+    /// `HttpError::InvalidWindowIncrement`, as does one that would grow the connection-level
+    /// window past the maximum. Nothing is sent to the peer in that case.
+    pub fn send_window_update(&mut self, stream_id: StreamId, increment: u32)
+            -> HttpResult<()> {
+        if increment == 0 || increment > window::MAX_WINDOW_SIZE {
+            return Err(HttpError::InvalidWindowIncrement(increment));
+        }
+        if stream_id != 0 {
+            return self.send_frame(WindowUpdateFrame::for_stream(stream_id, increment));
+        }
+        let mut in_window_size = self.conn.in_window_size;
+        if in_window_size.try_increase(increment).is_err() {
+            return Err(HttpError::InvalidWindowIncrement(increment));
+        }
+        try!(self.send_frame(WindowUpdateFrame::for_connection(increment)));
+        self.conn.in_window_size = in_window_size;
+        Ok(())
+    }
+
+    /// Sends all the control frames that the connection queued while handling received frames,
+    /// such as PING acknowledgements and the WINDOW_UPDATE frames of the adaptive flow control
+    /// strategy.
+    ///
+    /// Should be invoked after each handled frame.
+    pub fn send_pending_frames(&mut self) -> HttpResult<()> {
+        for (frame, _allocation) in self.conn.pending_frames.split_off(0) {
+            try!(match frame {
+                // The connection already accounted for the increments when queueing them.
+                ControlFrame::WindowUpdate(0, increment) => {
+                    self.send_frame(WindowUpdateFrame::for_connection(increment))
+                },
+                ControlFrame::WindowUpdate(stream_id, increment) => {
+                    self.send_frame(WindowUpdateFrame::for_stream(stream_id, increment))
+                },
+                ControlFrame::Ping(data) => self.send_frame(PingFrame::new(data)),
+                ControlFrame::PingAck(data) => self.send_frame(PingFrame::new_ack(data)),
+            });
+        }
+        Ok(())
+    }
+
+    /// Sends a RST_STREAM frame to the peer, resetting the given stream with the given error
+    /// code.
+    pub fn send_rst_stream(&mut self, stream_id: StreamId, error_code: ErrorCode)
+            -> HttpResult<()> {
+        self.conn.close_stream_out(stream_id);
+        self.send_frame(RstStreamFrame::new(stream_id, error_code))
+    }
+
     /// A helper function that inserts the frames required to send the given headers onto the
     /// `SendFrame` stream.
     ///
@@ -236,25 +700,74 @@ impl<'a, S> HttpConnectionSender<'a, S> where S: SendFrame + 'a {
     ///   performs no checks as to whether the stream is a valid identifier.
     /// - `end_stream` - whether the stream should be closed from the peer's side immediately
     ///   after sending the headers
+    ///
+    /// A header block with a `te` header carrying anything but `trailers` is rejected with an
+    /// `HttpError::MalformedHeaders`, without sending anything.
     pub fn send_headers<'n, 'v, H: Into<Vec<Header<'n, 'v>>>>(
             &mut self,
             headers: H,
             stream_id: StreamId,
             end_stream: EndStream)
             -> HttpResult<()> {
-        let headers_fragment = self.conn.encoder.encode(
-            headers.into().iter().map(|h| (h.name(), h.value())));
-        // For now, sending header fragments larger than 16kB is not supported
-        // (i.e. the encoded representation cannot be split into CONTINUATION
-        // frames).
-        let mut frame = HeadersFrame::new(headers_fragment, stream_id);
-        frame.set_flag(HeadersFlag::EndHeaders);
+        self.send_headers_with_priority(headers, stream_id, end_stream, None)
+    }
 
+    /// Sends the given headers, like `send_headers`, but also includes the given priority
+    /// information (the stream's dependency and weight) in the HEADERS frame, if any.
+    ///
+    /// This lets the peer prioritize the stream from the start, without a separate PRIORITY
+    /// frame.
+    pub fn send_headers_with_priority<'n, 'v, H: Into<Vec<Header<'n, 'v>>>>(
+            &mut self,
+            headers: H,
+            stream_id: StreamId,
+            end_stream: EndStream,
+            stream_dep: Option<StreamDependency>)
+            -> HttpResult<()> {
+        let headers = headers.into();
+        // The check needs to happen before the encoder sees the headers, since encoding them
+        // already changes its state.
+        if self.conn.config.compliance.is_strict() {
+            try!(validation::validate_te(&headers).map_err(HttpError::MalformedHeaders));
+        }
+        let headers_fragment = self.conn.encoder.encode(
+            headers.iter().map(|h| (h.name(), h.value())));
+        // Header blocks that do not fit into a single frame are split into a HEADERS frame,
+        // followed by as many CONTINUATION frames as required. The priority fields of the HEADERS
+        // frame count against the peer's maximum frame size too.
+        let max_len = self.conn.peer_settings.max_frame_size as usize;
+        let first_len = if stream_dep.is_some() { max_len - 5 } else { max_len };
+        let (first, rest) = headers_fragment.split_at(cmp::min(first_len, headers_fragment.len()));
+        let mut rest = rest.chunks(max_len).peekable();
+
+        let mut frame = match stream_dep {
+            Some(dep) => HeadersFrame::with_dependency(first.to_vec(), stream_id, dep),
+            None => HeadersFrame::new(first.to_vec(), stream_id),
+        };
+        if rest.peek().is_none() {
+            frame.set_flag(HeadersFlag::EndHeaders);
+        }
         if end_stream == EndStream::Yes {
             frame.set_flag(HeadersFlag::EndStream);
         }
+        try!(self.send_frame(frame));
+
+        while let Some(fragment) = rest.next() {
+            let mut frame = ContinuationFrame::new(fragment.to_vec(), stream_id);
+            if rest.peek().is_none() {
+                frame.set_flag(ContinuationFlag::EndHeaders);
+            }
+            try!(self.send_frame(frame));
+        }
 
-        self.send_frame(frame)
+        match end_stream {
+            EndStream::Yes => self.conn.close_stream_out(stream_id),
+            EndStream::No => {
+                let initial_size = self.conn.peer_settings.initial_window_size;
+                self.conn.stream_windows.open(stream_id, initial_size);
+            },
+        }
+        Ok(())
     }
 
     /// A helper function that inserts a frame representing the given data into the `SendFrame`
@@ -267,27 +780,56 @@ impl<'a, S> HttpConnectionSender<'a, S> where S: SendFrame + 'a {
         if end_stream == EndStream::Yes {
             frame.set_flag(DataFlag::EndStream);
         }
-        // Adjust the flow control window...
+        if let Some(pad_len) = self.conn.padding_for(stream_id, data.len()) {
+            frame.set_padding(pad_len);
+        }
+        // Adjust the flow control windows...
         try!(self.conn.decrease_out_window(frame.payload_len()));
+        self.conn.trace.data_sent(frame.payload_len());
         trace!("New OUT WINDOW size = {}", self.conn.out_window_size());
+        match end_stream {
+            EndStream::Yes => self.conn.close_stream_out(stream_id),
+            EndStream::No => self.conn.stream_windows.consume(stream_id, frame.payload_len()),
+        }
         // ...and now send it out.
         self.send_frame(frame)
     }
 
     /// Sends the chunk of data provided by the given `DataPrioritizer`.
     ///
+    /// The prioritizer should not provide chunks larger than the connection's outbound flow
+    /// control window allows (see `HttpConnection::out_window_available`). If the connection is
+    /// rate limited, the chunks should not be larger than `HttpConnection::send_budget` allows
+    /// either, nor should they be for the streams returned by `HttpConnection::throttled_streams`.
+    ///
     /// # Returns
     ///
-    /// Returns the status of the operation. If the provider does not currently have any data that
-    /// could be sent, returns `SendStatus::Nothing`. If any data is sent, returns
+    /// Returns the status of the operation. If the rate limit or the connection's outbound flow
+    /// control window does not currently allow sending any data, returns `SendStatus::Throttled`,
+    /// without asking the prioritizer for a chunk. If the provider does not currently have any
+    /// data that could be sent, returns `SendStatus::Nothing`. If any data is sent, returns
     /// `SendStatus::Sent`.
     pub fn send_next_data<P: DataPrioritizer>(&mut self, prioritizer: &mut P)
             -> HttpResult<SendStatus> {
+        if self.conn.out_window_available() == 0 {
+            trace!("The outbound connection window is exhausted, holding DATA back");
+            return Ok(SendStatus::Throttled);
+        }
+        if self.conn.send_budget(1) == 0 {
+            return Ok(SendStatus::Throttled);
+        }
         let chunk = try!(prioritizer.get_next_chunk());
         match chunk {
-            None => Ok(SendStatus::Nothing),
+            None if self.conn.throttled_streams().is_empty() => Ok(SendStatus::Nothing),
+            None => Ok(SendStatus::Throttled),
             Some(chunk) => {
+                let stream_id = chunk.stream_id;
+                let len = chunk.data.len() as u32;
+                let end_stream = chunk.end_stream == EndStream::Yes;
                 try!(self.send_data(chunk));
+                if let Some(ref mut limiter) = self.conn.rate_limiter {
+                    limiter.data_sent(stream_id, len, end_stream);
+                }
                 Ok(SendStatus::Sent)
             },
         }
@@ -298,15 +840,48 @@ impl HttpConnection {
     /// Creates a new `HttpConnection` that will use the given sender
     /// for writing frames.
     pub fn new(scheme: HttpScheme) -> HttpConnection {
+        HttpConnection::with_config(scheme, Http2Config::default())
+    }
+
+    /// Creates a new `HttpConnection` that uses the given configuration.
+    pub fn with_config(scheme: HttpScheme, config: Http2Config) -> HttpConnection {
+        let encoder = if config.dynamic_header_table {
+            HeaderEncoder::Dynamic(hpack::Encoder::new())
+        } else {
+            HeaderEncoder::Static(StaticTableEncoder::new())
+        };
+        let id = ConnectionId::next();
         HttpConnection {
             scheme: scheme,
             decoder: hpack::Decoder::new(),
-            encoder: hpack::Encoder::new(),
+            encoder: encoder,
             in_window_size: WindowSize::new(INITIAL_CONNECTION_WINDOW_SIZE),
             out_window_size: WindowSize::new(INITIAL_CONNECTION_WINDOW_SIZE),
+            stream_windows: StreamWindows::new(),
+            goaway_received: false,
+            connection_error: None,
+            adaptive_window: None,
+            frozen_in_window: None,
+            pending_frames: Vec::new(),
+            rate_limiter: None,
+            padding: HashMap::new(),
+            local_settings: LocalSettings::new(),
+            peer_settings: SettingsState::new(),
+            header_arena: HeaderArena::default(),
+            header_block: None,
+            config: config,
+            activity: ConnectionActivity::new(),
+            id: id,
+            trace: ConnectionTrace::new(id),
         }
     }
 
+    /// Returns the configuration of the connection.
+    #[inline]
+    pub fn config(&self) -> &Http2Config {
+        &self.config
+    }
+
     /// Creates a new `HttpConnectionSender` instance that will use the given `SendFrame` instance
     /// to send the frames that it prepares. This is a convenience struct so that clients do not
     /// have to pass the same `sender` reference to multiple send methods.
@@ -344,6 +919,50 @@ impl HttpConnection {
         }
     }
 
+    /// Sends the given frame to the peer using the given `SendFrame`, as is.
+    ///
+    /// This is an escape hatch for advanced uses and test harnesses, which need to send frames
+    /// that the higher-level send methods cannot produce, e.g. hand-crafted SETTINGS or frames of
+    /// extension types (as `HttpFrame::UnknownFrame`). As such, the connection performs only
+    /// minimal validation: frames that have to be associated to a stream must not be sent on
+    /// stream 0 and no frame may exceed the maximum frame size allowed by the peer. Otherwise, an
+    /// `HttpError::InvalidFrame` is returned and nothing is sent.
+    ///
+    /// The outbound flow control window is adjusted for DATA frames, but the frames are not
+    /// otherwise reflected in the state of the connection. In particular, the header fragments
+    /// of HEADERS and CONTINUATION frames are sent without involving the connection's HPACK
+    /// encoder.
+    pub fn send_frame<S: SendFrame>(&mut self, frame: HttpFrame, sender: &mut S)
+            -> HttpResult<()> {
+        let (len, _, _, stream_id) = frame.header();
+        let stream_bound = match frame {
+            HttpFrame::DataFrame(_) |
+            HttpFrame::HeadersFrame(_) |
+            HttpFrame::RstStreamFrame(_) |
+            HttpFrame::ContinuationFrame(_) => true,
+            _ => false,
+        };
+        if stream_bound && stream_id == 0 {
+            debug!("Refusing to send a stream-bound frame on stream 0");
+            return Err(HttpError::InvalidFrame);
+        }
+        if len > self.peer_settings.max_frame_size {
+            debug!("Refusing to send a frame with a payload of {} octets", len);
+            return Err(HttpError::InvalidFrame);
+        }
+        if let HttpFrame::DataFrame(_) = frame {
+            try!(self.decrease_out_window(len));
+        }
+        self.sender(sender).send_frame(frame)
+    }
+
+    /// Returns the number of octets that the outbound flow control window currently allows to be
+    /// sent, i.e. its size if it is positive and 0 otherwise.
+    #[inline]
+    pub fn out_window_available(&self) -> u32 {
+        self.out_window_size.available()
+    }
+
     /// Returns the current size of the inbound flow control window (i.e. the number of octets that
     /// the connection will accept and the peer will send at most, unless the window is updated).
     pub fn in_window_size(&self) -> i32 {
@@ -355,6 +974,199 @@ impl HttpConnection {
         self.out_window_size.size()
     }
 
+    /// Returns the current size of the outbound flow control window of the given stream, if we
+    /// are sending data on it (i.e. its headers were sent without ending it, and it was neither
+    /// ended by us nor reset since).
+    pub fn stream_out_window_size(&self, stream_id: StreamId) -> Option<i32> {
+        self.stream_windows.size(stream_id)
+    }
+
+    /// Returns whether the peer has sent a GOAWAY frame, i.e. whether it has started shutting the
+    /// connection down.
+    pub fn goaway_received(&self) -> bool {
+        self.goaway_received
+    }
+
+    /// Sets the strategy that the connection uses for managing its inbound flow control windows.
+    ///
+    /// The control frames that the strategy requires are queued while handling frames and need
+    /// to be sent by `HttpConnectionSender::send_pending_frames`.
+    pub fn set_flow_control(&mut self, strategy: FlowControlStrategy) {
+        self.adaptive_window = match strategy {
+            FlowControlStrategy::Manual => None,
+            FlowControlStrategy::Adaptive { max_window } => Some(AdaptiveWindow::new(max_window)),
+        };
+    }
+
+    /// Limits the rate at which the connection sends DATA to the given connection-wide and
+    /// per-stream limits. Passing `None` for both removes any limits.
+    pub fn set_rate_limit(&mut self, connection: Option<RateLimit>, per_stream: Option<RateLimit>) {
+        self.rate_limiter = match (connection, per_stream) {
+            (None, None) => None,
+            (connection, per_stream) => Some(RateLimiter::new(connection, per_stream)),
+        };
+    }
+
+    /// Pads the DATA frames that are sent on the given stream from now on using the given
+    /// strategy, or stops padding them, if `None` is given. The strategy is dropped once our side
+    /// of the stream ends.
+    pub fn set_stream_padding(&mut self, stream_id: StreamId, strategy: Option<PaddingStrategy>) {
+        match strategy {
+            Some(strategy) => { self.padding.insert(stream_id, strategy); },
+            None => { self.padding.remove(&stream_id); },
+        }
+    }
+
+    /// Returns the largest chunk of data that can be sent right now: as much as a single DATA
+    /// frame can carry, but no more than the connection's outbound flow control window and its
+    /// rate limit allow. It is 0 while either of them does not allow sending anything.
+    pub fn next_chunk_size(&mut self) -> usize {
+        let max = cmp::min(self.max_data_chunk_size(), self.out_window_available() as usize);
+        self.send_budget(max)
+    }
+
+    /// Returns how many bytes of data the connection's rate limit allows it to send right now,
+    /// but at most `max`.
+    pub fn send_budget(&mut self, max: usize) -> usize {
+        match self.rate_limiter {
+            Some(ref mut limiter) => limiter.connection_budget(max),
+            None => max,
+        }
+    }
+
+    /// Returns the streams that may not send any data right now, because they exceeded the
+    /// per-stream rate limit.
+    pub fn throttled_streams(&mut self) -> Vec<StreamId> {
+        match self.rate_limiter {
+            Some(ref mut limiter) => limiter.throttled_streams(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the maximum number of concurrent streams that the peer allows to be opened, if it
+    /// announced a limit.
+    pub fn peer_max_concurrent_streams(&self) -> Option<u32> {
+        self.peer_settings.max_concurrent_streams
+    }
+
+    /// Returns the settings that the peer announced, i.e. the ones that apply to what is sent to
+    /// the peer.
+    #[inline]
+    pub fn peer_settings(&self) -> &SettingsState {
+        &self.peer_settings
+    }
+
+    /// Returns the largest chunk of data that a single DATA frame sent to the peer can carry,
+    /// i.e. the peer's SETTINGS_MAX_FRAME_SIZE. It grows whenever the peer raises the setting.
+    #[inline]
+    pub fn max_data_chunk_size(&self) -> usize {
+        self.peer_settings.max_frame_size as usize
+    }
+
+    /// Returns our own settings, i.e. the ones that apply to what the peer sends. Only the ones
+    /// that the peer acknowledged are in effect.
+    #[inline]
+    pub fn local_settings(&self) -> &LocalSettings {
+        &self.local_settings
+    }
+
+    /// Records that a SETTINGS frame carrying the given settings was sent to the peer without
+    /// going through the connection, e.g. as a part of the client preface written by
+    /// `write_preface`.
+    pub fn settings_sent(&mut self, settings: &[HttpSetting]) {
+        self.local_settings.sent(settings);
+    }
+
+    /// Returns the state of the adaptive flow control strategy, if the connection uses it.
+    pub fn adaptive_window(&self) -> Option<&AdaptiveWindow> {
+        self.adaptive_window.as_ref()
+    }
+
+    /// Stops the adaptive flow control strategy from replenishing the inbound connection-level
+    /// window, so that the peer cannot send more DATA than what is left of it (e.g. while the
+    /// server sheds load). The windows of the streams are still replenished.
+    ///
+    /// The increments that are held back are sent once the window is thawed by `thaw_in_window`.
+    pub fn freeze_in_window(&mut self) {
+        if self.frozen_in_window.is_none() {
+            self.frozen_in_window = Some(0);
+        }
+    }
+
+    /// Lets the adaptive flow control strategy replenish the inbound connection-level window
+    /// again, queuing a WINDOW_UPDATE with the increments that were held back while it was
+    /// frozen.
+    pub fn thaw_in_window(&mut self) -> HttpResult<()> {
+        match self.frozen_in_window.take() {
+            Some(increment) if increment > 0 => {
+                try!(self.in_window_size.try_increase(increment)
+                                        .map_err(|_| HttpError::WindowSizeOverflow));
+                self.queue_control_frame(ControlFrame::WindowUpdate(0, increment));
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    /// Returns whether the connection has queued control frames that still need to be sent.
+    pub fn has_pending_frames(&self) -> bool {
+        !self.pending_frames.is_empty()
+    }
+
+    /// Queues the given control frame, to be sent by `HttpConnectionSender::send_pending_frames`.
+    fn queue_control_frame(&mut self, frame: ControlFrame) {
+        self.pending_frames.push((frame, Allocation::new(Resource::Frame)));
+    }
+
+    /// Returns the memory that the connection itself holds on to: the HPACK dynamic tables, the
+    /// queued control frames and the fragments of a header block that is being received. The
+    /// usage of the streams is up to the session.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        // The peer may keep using the table size that is in effect until it acknowledges a
+        // smaller one.
+        let decoder_table = cmp::max(self.local_settings.acked().header_table_size,
+                                     self.local_settings.advertised().header_table_size);
+        // The encoder's table is limited by the size that the peer allows, but it never grows
+        // past the default size either.
+        let encoder_table = match self.encoder {
+            HeaderEncoder::Dynamic(_) => {
+                cmp::min(self.peer_settings.header_table_size, DEFAULT_HEADER_TABLE_SIZE) as usize
+            },
+            HeaderEncoder::Static(_) => 0,
+        };
+        // None of the control frames has a payload longer than 8 bytes.
+        let control_frames = self.pending_frames.len() * (FRAME_HEADER_LEN + 8);
+        let header_block = self.header_block.as_ref().map_or(0, |block| block.fragment.len());
+        MemoryUsage {
+            header_tables: decoder_table as usize + encoder_table,
+            streams: 0,
+            queued: control_frames + header_block,
+        }
+    }
+
+    /// Returns when the connection was established and when it last received and sent a frame.
+    pub fn activity(&self) -> ConnectionActivity {
+        self.activity
+    }
+
+    /// Returns the ID of the connection, which is unique among all connections of the process.
+    pub fn id(&self) -> ConnectionId {
+        self.id
+    }
+
+    /// Sets the side of the connection that the connection's span is recorded for (see
+    /// `http::tracing`). Client and server connections set it on their own.
+    pub fn set_role(&mut self, role: Role) {
+        self.trace.set_role(role);
+    }
+
+    /// Returns the error code of the connection error that the peer caused (e.g. by sending a
+    /// header block that could not be decoded), if any. Once the connection has failed with such
+    /// an error, it refuses to handle any further frames.
+    pub fn connection_error(&self) -> Option<ErrorCode> {
+        self.connection_error
+    }
+
     /// The method processes the next frame provided by the given `ReceiveFrame` instance, expecting
     /// it to be a SETTINGS frame.
     /// Additionally, the frame cannot be an ACK settings frame, but rather it should contain the
@@ -366,12 +1178,14 @@ impl HttpConnection {
     ///
     /// If the received frame is not a SETTINGS frame, an `HttpError::UnableToConnect` variant is
     /// returned. (TODO: Change this variant's name, as it is a byproduct of this method's legacy)
+    /// If the `ReceiveFrame` can tell that the peer is not speaking HTTP/2 at all, the
+    /// `HttpError::NotHttp2` error that it returns is propagated.
     pub fn expect_settings<Recv: ReceiveFrame, Sess: Session>(
             &mut self,
             rx: &mut Recv,
             session: &mut Sess)
             -> HttpResult<()> {
-        let frame = rx.recv_frame();
+        let frame = rx.recv_preface_frame();
         match frame {
             Ok(HttpFrame::SettingsFrame(ref settings)) if !settings.is_ack() => {
                 debug!("Correctly received a SETTINGS frame from the server");
@@ -381,8 +1195,7 @@ impl HttpConnection {
             // Already an error -- propagate that.
             Err(e) => return Err(e),
         };
-        try!(self.handle_frame(frame.unwrap(), session));
-        Ok(())
+        self.handle_frame(frame.unwrap(), session)
     }
 
     /// Handles the next frame incoming on the given `ReceiveFrame` instance.
@@ -394,26 +1207,79 @@ impl HttpConnection {
     ///
     /// If the handling is successful, a unit `Ok` is returned; all HTTP and IO errors are
     /// propagated.
+    ///
+    /// If the peer closed the connection, an `HttpError::PeerClosed` error is returned, which
+    /// indicates a graceful close iff the peer had previously sent a GOAWAY frame.
+    ///
+    /// If handling the frame results in a connection error (e.g. the header block could not be
+    /// decoded), the session is notified (so that it can send a GOAWAY frame) and the error is
+    /// returned. Any further calls fail with an `HttpError::LocalConnectionError`, without
+    /// reading any more frames.
     pub fn handle_next_frame<Recv: ReceiveFrame, Sess: Session>(
             &mut self,
             rx: &mut Recv,
             session: &mut Sess)
             -> HttpResult<()> {
+        if let Some(error_code) = self.connection_error {
+            return Err(HttpError::LocalConnectionError(error_code));
+        }
         debug!("Waiting for frame...");
+        rx.set_max_frame_size(self.local_settings.max_receivable_frame_size());
         let frame = match rx.recv_frame() {
             Ok(frame) => frame,
+            Err(HttpError::PeerClosed { .. }) => {
+                debug!("{}: The peer closed the connection", self.id);
+                return Err(HttpError::PeerClosed { graceful: self.goaway_received });
+            },
             Err(e) => {
-                debug!("Encountered an HTTP/2 error, stopping.");
-                return Err(e);
+                debug!("{}: Encountered an HTTP/2 error, stopping.", self.id);
+                // A frame that could not be parsed may amount to a connection error.
+                return self.check_connection_error(Err(e), session);
             },
         };
 
         self.handle_frame(frame, session)
     }
 
-    /// Private helper method that actually handles a received frame.
+    /// Private helper method that handles a received frame, failing the connection if the frame
+    /// causes a connection error.
     fn handle_frame<Sess: Session>(&mut self, frame: HttpFrame, session: &mut Sess)
             -> HttpResult<()> {
+        self.activity.last_frame_received = Some(Instant::now());
+        let res = self.dispatch_frame(frame, session);
+        self.check_connection_error(res, session)
+    }
+
+    /// Private helper method that fails the connection if the given result of handling a frame
+    /// is a connection error, notifying the session so that it can send a GOAWAY frame.
+    fn check_connection_error<Sess: Session>(&mut self, res: HttpResult<()>, session: &mut Sess)
+            -> HttpResult<()> {
+        if let Err(ref e) = res {
+            if let Some(error_code) = e.connection_error_code() {
+                debug!("{}: Connection error {:?}: {}", self.id, error_code, e);
+                self.connection_error = Some(error_code);
+                try!(session.connection_error(error_code, self));
+            }
+        }
+        res
+    }
+
+    /// Private helper method that actually handles a received frame.
+    fn dispatch_frame<Sess: Session>(&mut self, frame: HttpFrame, session: &mut Sess)
+            -> HttpResult<()> {
+        if let Some(stream_id) = self.header_block.as_ref().map(|block| block.stream_id) {
+            // A header block must be sent as a contiguous sequence of frames (section 4.3.).
+            return match frame {
+                HttpFrame::ContinuationFrame(ref frame) if frame.get_stream_id() == stream_id => {
+                    debug!("CONTINUATION frame received");
+                    self.handle_continuation_frame(frame, session)
+                },
+                _ => {
+                    debug!("Frame interleaved with the header block of stream {}", stream_id);
+                    Err(HttpError::LocalConnectionError(ErrorCode::ProtocolError))
+                },
+            };
+        }
         match frame {
             HttpFrame::DataFrame(frame) => {
                 debug!("Data frame received");
@@ -423,6 +1289,10 @@ impl HttpConnection {
                 debug!("Headers frame received");
                 self.handle_headers_frame(frame, session)
             },
+            HttpFrame::PriorityFrame(frame) => {
+                debug!("PRIORITY frame received");
+                self.handle_priority_frame(frame, session)
+            },
             HttpFrame::RstStreamFrame(frame) => {
                 debug!("RST_STREAM frame received");
                 self.handle_rst_stream_frame(frame, session)
@@ -432,16 +1302,44 @@ impl HttpConnection {
                 self.handle_settings_frame::<Sess>(frame, session)
             },
             HttpFrame::GoawayFrame(frame) => {
-                debug!("GOAWAY frame received");
+                debug!("{}: GOAWAY frame received", self.id);
+                self.goaway_received = true;
                 session.on_goaway(
                     frame.last_stream_id(),
                     frame.error_code(),
                     frame.debug_data(),
                     self)
             },
-            HttpFrame::WindowUpdateFrame(_) => {
+            HttpFrame::WindowUpdateFrame(frame) => {
                 debug!("WINDOW_UPDATE frame received");
-                Ok(())
+                self.handle_window_update_frame(frame, session)
+            },
+            HttpFrame::PingFrame(frame) => {
+                debug!("PING frame received");
+                self.handle_ping_frame(frame)
+            },
+            HttpFrame::ContinuationFrame(_) => {
+                debug!("CONTINUATION frame received without a preceding HEADERS frame");
+                Err(HttpError::LocalConnectionError(ErrorCode::ProtocolError))
+            },
+            HttpFrame::AltSvcFrame(frame) => {
+                debug!("ALTSVC frame received");
+                if frame.is_ignored() {
+                    debug!("Ignoring ALTSVC frame on stream {} with origin {:?}",
+                           frame.get_stream_id(), frame.origin());
+                    return Ok(());
+                }
+                session.on_alt_svc(frame.get_stream_id(), frame.origin(), frame.field_value(), self)
+            },
+            HttpFrame::UnknownFrame(ref frame) if frame.header().1 == PRIORITY_FRAME_TYPE => {
+                let stream_id = frame.header().3;
+                debug!("PRIORITY frame of invalid length {} on stream {}",
+                       frame.header().0, stream_id);
+                session.stream_error(stream_id, ErrorCode::FrameSizeError, self)
+            },
+            HttpFrame::UnknownFrame(ref frame) if frame.header().1 == PUSH_PROMISE_FRAME_TYPE => {
+                debug!("PUSH_PROMISE frame received");
+                self.handle_push_promise_frame(frame, session)
             },
             HttpFrame::UnknownFrame(frame) => {
                 debug!("Unknown frame received; raw = {:?}", frame);
@@ -458,7 +1356,32 @@ impl HttpConnection {
             -> HttpResult<()> {
         try!(self.decrease_in_window(frame.payload_len()));
         trace!("New IN WINDOW size = {}", self.in_window_size());
+        self.trace.data_received(frame.payload_len());
+        if !try!(self.check_stream_state(StreamFrame::Data, frame.get_stream_id(), session)) {
+            return Ok(());
+        }
         try!(session.new_data_chunk(frame.get_stream_id(), &frame.data, self));
+        if let Some(ref mut adaptive) = self.adaptive_window {
+            let mut updates = Vec::new();
+            let ping = adaptive.data_received(frame.get_stream_id(),
+                                              frame.payload_len(),
+                                              frame.is_set(DataFlag::EndStream),
+                                              &mut updates);
+            for (stream_id, increment) in updates {
+                if stream_id == 0 {
+                    if let Some(ref mut held_back) = self.frozen_in_window {
+                        *held_back = held_back.saturating_add(increment);
+                        continue;
+                    }
+                    try!(self.in_window_size.try_increase(increment)
+                                            .map_err(|_| HttpError::WindowSizeOverflow));
+                }
+                self.queue_control_frame(ControlFrame::WindowUpdate(stream_id, increment));
+            }
+            if let Some(data) = ping {
+                self.queue_control_frame(ControlFrame::Ping(data));
+            }
+        }
         // TODO(mlalic): Should the connection separately signal the decrease in the flow control
         //               window? For now, it is expected that the data callback is enough, as the
         //               session would be able to inspect the new window size there (and know that
@@ -473,62 +1396,351 @@ impl HttpConnection {
     }
 
     /// Private helper method that handles a received `HeadersFrame`.
+    ///
+    /// If the frame does not end the header block, the fragment is kept until the
+    /// CONTINUATION frames that complete it are received.
     fn handle_headers_frame<Sess: Session>(&mut self, frame: HeadersFrame, session: &mut Sess)
             -> HttpResult<()> {
-        let headers = try!(self.decoder.decode(&frame.header_fragment())
-                                       .map_err(|e| HttpError::CompressionError(e)));
-        let headers = headers.into_iter().map(|h| h.into()).collect();
-        try!(session.new_headers(frame.get_stream_id(), headers, self));
-
-        if frame.is_end_of_stream() {
-            debug!("End of stream {}", frame.get_stream_id());
-            try!(session.end_of_stream(frame.get_stream_id(), self));
+        if !frame.is_headers_end() {
+            self.header_block = Some(HeaderBlock {
+                stream_id: frame.get_stream_id(),
+                fragment: frame.header_fragment().to_vec(),
+                end_stream: frame.is_end_of_stream(),
+                stream_dep: frame.stream_dep.clone(),
+                promised_stream_id: None,
+                _allocation: Allocation::new(Resource::Buffer),
+            });
+            return Ok(());
         }
+        self.handle_header_block(frame.get_stream_id(),
+                                 frame.header_fragment(),
+                                 frame.is_end_of_stream(),
+                                 frame.stream_dep.clone(),
+                                 session)
+    }
 
-        Ok(())
+    /// Private helper method that handles a received PUSH_PROMISE frame, which is only passed on
+    /// to the session, as pushed streams are not supported.
+    ///
+    /// Like the one of a HEADERS frame, the header block of the frame may continue in
+    /// CONTINUATION frames, in which case it is kept until they complete it.
+    fn handle_push_promise_frame<Sess: Session>(&mut self, frame: &RawFrame, session: &mut Sess)
+            -> HttpResult<()> {
+        let (_, _, flags, stream_id) = frame.header();
+        let payload = frame.payload();
+        // Strip the padding, if the PADDED flag is set.
+        let payload = if flags & 0x8 != 0 {
+            let pad_len = match payload.first() {
+                Some(&pad_len) => pad_len as usize,
+                None => return Err(HttpError::InvalidFrame),
+            };
+            if pad_len >= payload.len() {
+                debug!("PUSH_PROMISE frame with {} bytes of padding", pad_len);
+                return Err(HttpError::LocalConnectionError(ErrorCode::ProtocolError));
+            }
+            &payload[1..payload.len() - pad_len]
+        } else {
+            payload
+        };
+        if payload.len() < 4 {
+            return Err(HttpError::InvalidFrame);
+        }
+        let promised_stream_id =
+            payload[..4].iter().fold(0, |id, &b| (id << 8) | b as u32) & !0x80000000;
+        let fragment = &payload[4..];
+        if flags & HeadersFlag::EndHeaders.bitmask() == 0 {
+            self.header_block = Some(HeaderBlock {
+                stream_id: stream_id,
+                fragment: fragment.to_vec(),
+                end_stream: false,
+                stream_dep: None,
+                promised_stream_id: Some(promised_stream_id),
+                _allocation: Allocation::new(Resource::Buffer),
+            });
+            return Ok(());
+        }
+        self.handle_push_promise_block(stream_id, promised_stream_id, fragment, session)
     }
 
-    /// Private helper method that handles a received `RstStreamFrame`
-    #[inline]
-    fn handle_rst_stream_frame<Sess: Session>(
+    /// Private helper method that handles the complete header block of a PUSH_PROMISE frame.
+    ///
+    /// The promised request's headers are not passed on, but they are still decoded, in order to
+    /// keep the decoder context in sync with the peer's encoder.
+    fn handle_push_promise_block<Sess: Session>(
             &mut self,
-            frame: RstStreamFrame,
+            stream_id: StreamId,
+            promised_stream_id: StreamId,
+            fragment: &[u8],
             session: &mut Sess)
             -> HttpResult<()> {
+        try!(self.header_arena.decode(&mut self.decoder, fragment));
+        session.new_push_promise(stream_id, promised_stream_id, self)
+    }
+
+    /// Private helper method that handles a received `ContinuationFrame` that continues the
+    /// header block currently being received.
+    fn handle_continuation_frame<Sess: Session>(
+            &mut self,
+            frame: &ContinuationFrame,
+            session: &mut Sess)
+            -> HttpResult<()> {
+        let mut block = match self.header_block.take() {
+            Some(block) => block,
+            None => return Err(HttpError::LocalConnectionError(ErrorCode::ProtocolError)),
+        };
+        block.fragment.extend_from_slice(frame.header_fragment());
+        if !frame.is_headers_end() {
+            self.header_block = Some(block);
+            return Ok(());
+        }
+        if let Some(promised_stream_id) = block.promised_stream_id {
+            return self.handle_push_promise_block(block.stream_id,
+                                                  promised_stream_id,
+                                                  &block.fragment,
+                                                  session);
+        }
+        self.handle_header_block(block.stream_id,
+                                 &block.fragment,
+                                 block.end_stream,
+                                 block.stream_dep,
+                                 session)
+    }
+
+    /// Private helper method that handles a complete header block received on the given stream.
+    ///
+    /// The priority information of the block's HEADERS frame, if any, is passed to the session
+    /// before the headers themselves. A stream that depends on itself is a stream error.
+    fn handle_header_block<Sess: Session>(
+            &mut self,
+            stream_id: StreamId,
+            fragment: &[u8],
+            end_stream: bool,
+            stream_dep: Option<StreamDependency>,
+            session: &mut Sess)
+            -> HttpResult<()> {
+        // The arena is taken out of the connection while the headers borrow from it, since the
+        // connection itself is lent to the session along with them.
+        let mut arena = mem::replace(&mut self.header_arena, HeaderArena::default());
+        let res = self.handle_decoded_header_block(
+            stream_id, fragment, end_stream, stream_dep, &mut arena, session);
+        self.header_arena = arena;
+        res
+    }
+
+    /// Decodes the given header block into the given arena and handles it.
+    fn handle_decoded_header_block<Sess: Session>(
+            &mut self,
+            stream_id: StreamId,
+            fragment: &[u8],
+            end_stream: bool,
+            stream_dep: Option<StreamDependency>,
+            arena: &mut HeaderArena,
+            session: &mut Sess)
+            -> HttpResult<()> {
+        // The header block is decoded regardless of whether the frame is allowed on the stream,
+        // in order to keep the decoder context in sync with the peer's encoder.
+        try!(arena.decode(&mut self.decoder, fragment));
+        if !try!(self.check_stream_state(StreamFrame::Headers, stream_id, session)) {
+            return Ok(());
+        }
+        if let Some(dep) = stream_dep {
+            if dep.stream_id == stream_id {
+                debug!("Stream {} depends on itself", stream_id);
+                return session.stream_error(stream_id, ErrorCode::ProtocolError, self);
+            }
+            try!(session.new_priority(stream_id, dep, self));
+        }
+        try!(session.new_headers(stream_id, arena.headers(), self));
+
+        if end_stream {
+            debug!("End of stream {}", stream_id);
+            try!(session.end_of_stream(stream_id, self));
+        }
+
+        Ok(())
+    }
+
+    /// Private helper method that handles a received `RstStreamFrame`
+    #[inline]
+    fn handle_rst_stream_frame<Sess: Session>(
+            &mut self,
+            frame: RstStreamFrame,
+            session: &mut Sess)
+            -> HttpResult<()> {
+        self.close_stream_out(frame.get_stream_id());
+        if !try!(self.check_stream_state(StreamFrame::RstStream, frame.get_stream_id(), session)) {
+            return Ok(());
+        }
         session.rst_stream(frame.get_stream_id(), frame.error_code(), self)
     }
 
+    /// Private helper method that handles a received `PriorityFrame`.
+    ///
+    /// PRIORITY frames can be received in any state of a stream (section 6.3.), so the new
+    /// priority is handed to the session without checking the state first. A stream that depends
+    /// on itself is a stream error of type PROTOCOL_ERROR (section 5.3.1.).
+    fn handle_priority_frame<Sess: Session>(&mut self, frame: PriorityFrame, session: &mut Sess)
+            -> HttpResult<()> {
+        let stream_id = frame.get_stream_id();
+        if frame.depends_on() == stream_id {
+            debug!("Stream {} depends on itself", stream_id);
+            return session.stream_error(stream_id, ErrorCode::ProtocolError, self);
+        }
+        session.new_priority(stream_id, frame.dependency().clone(), self)
+    }
+
     /// Private helper method that handles a received `SettingsFrame`.
+    ///
+    /// A setting with a value out of its bounds is a connection error (section 6.5.2.), in which
+    /// case none of the frame's settings are applied. So is an ACK when none of the SETTINGS
+    /// frames that we sent are waiting for one.
     fn handle_settings_frame<Sess: Session>(&mut self, frame: SettingsFrame, session: &mut Sess)
             -> HttpResult<()> {
         if !frame.is_ack() {
-            // TODO: Actually handle the settings change before sending out the ACK
-            //       sending out the ACK.
             trace!("New settings frame {:#?}", frame);
+            for setting in frame.settings.iter() {
+                if let Err(error_code) = setting.validate() {
+                    debug!("Invalid setting received: {:?}", setting);
+                    return Err(HttpError::LocalConnectionError(error_code));
+                }
+            }
+            let old_window = self.peer_settings.initial_window_size;
+            self.peer_settings.apply_all(&frame.settings);
+            let new_window = self.peer_settings.initial_window_size;
+            if old_window != new_window {
+                try!(self.stream_windows.initial_window_size_changed(old_window, new_window)
+                                        .map_err(HttpError::LocalConnectionError));
+            }
             try!(session.new_settings(frame.settings, self));
+        } else if !self.local_settings.acked_by_peer() {
+            debug!("Received a SETTINGS ACK, but no SETTINGS are waiting for one");
+            return Err(HttpError::LocalConnectionError(ErrorCode::ProtocolError));
         }
 
         Ok(())
     }
 
+    /// Private helper method that handles a received `WindowUpdateFrame`.
+    ///
+    /// Updates on stream 0 increase the connection-level outbound window, while all others are
+    /// passed on to the session as stream-level updates.
+    ///
+    /// An increment of 0 is a PROTOCOL_ERROR and one that overflows the window a
+    /// FLOW_CONTROL_ERROR (section 6.9.); a connection error for updates on stream 0 and a stream
+    /// error (of which the session is notified, so that it resets the stream) otherwise.
+    fn handle_window_update_frame<Sess: Session>(
+            &mut self,
+            frame: WindowUpdateFrame,
+            session: &mut Sess)
+            -> HttpResult<()> {
+        let increment = frame.increment();
+        match frame.get_stream_id() {
+            0 => {
+                match flow_control::apply_window_update(&mut self.out_window_size, increment) {
+                    Ok(()) => {},
+                    Err(ErrorCode::FlowControlError) => return Err(HttpError::WindowSizeOverflow),
+                    Err(error_code) => return Err(HttpError::LocalConnectionError(error_code)),
+                }
+                trace!("New OUT WINDOW size = {}", self.out_window_size());
+                session.connection_window_update(increment, self)
+            },
+            stream_id => {
+                if !try!(self.check_stream_state(StreamFrame::WindowUpdate, stream_id, session)) {
+                    return Ok(());
+                }
+                if let Err(error_code) = self.stream_windows.window_update(stream_id, increment) {
+                    debug!("Invalid WINDOW_UPDATE on stream {}: {:?}", stream_id, error_code);
+                    return session.stream_error(stream_id, error_code, self);
+                }
+                session.window_update(stream_id, increment, self)
+            },
+        }
+    }
+
+    /// Private helper method that handles a received `PingFrame`.
+    ///
+    /// PINGs are acknowledged (by queuing the ACK), while ACKs are passed on to the adaptive flow
+    /// control strategy, if any.
+    fn handle_ping_frame(&mut self, frame: PingFrame) -> HttpResult<()> {
+        if !frame.is_ack() {
+            self.queue_control_frame(ControlFrame::PingAck(frame.opaque_data()));
+        } else if let Some(ref mut adaptive) = self.adaptive_window {
+            adaptive.ping_acked(frame.opaque_data());
+        }
+        Ok(())
+    }
+
+    /// Internal helper method that checks whether a frame of the given kind is allowed to be
+    /// received on the given stream, based on the stream state reported by the session.
+    ///
+    /// Returns `Ok(true)` if the frame should be handled and `Ok(false)` if it should be discarded
+    /// because it is a stream error (the session is notified of the error). Connection errors
+    /// are returned as an `HttpError::LocalConnectionError`.
+    fn check_stream_state<Sess: Session>(
+            &mut self,
+            frame: StreamFrame,
+            stream_id: StreamId,
+            session: &mut Sess)
+            -> HttpResult<bool> {
+        let state = match session.stream_state(stream_id) {
+            Some(state) => state,
+            None => return Ok(true),
+        };
+        match frame.check_state(state) {
+            Ok(()) => Ok(true),
+            Err(StateViolation::Stream(error_code)) => {
+                debug!("{:?} frame on stream {} in state {:?}", frame, stream_id, state);
+                try!(session.stream_error(stream_id, error_code, self));
+                Ok(false)
+            },
+            Err(StateViolation::Connection(error_code)) => {
+                debug!("{:?} frame on stream {} in state {:?}", frame, stream_id, state);
+                Err(HttpError::LocalConnectionError(error_code))
+            },
+        }
+    }
+
     /// Internal helper method that decreases the outbound flow control window size.
     fn decrease_out_window(&mut self, size: u32) -> HttpResult<()> {
-        // The size by which we decrease the window must be at most 2^31 - 1. We should be able to
-        // reach here only after sending a DATA frame, whose payload also cannot be larger than
-        // that, but we assert it just in case.
-        debug_assert!(size < 0x80000000);
-        self.out_window_size.try_decrease(size as i32)
+        // The payload of a DATA frame cannot be anywhere near 2^31 - 1, but should it ever be,
+        // consuming the window fails instead of the size wrapping around.
+        self.out_window_size.try_consume(size)
                             .map_err(|_| HttpError::WindowSizeOverflow)
     }
 
+    /// Internal helper method that returns the length of the padding for a DATA frame that
+    /// carries `data_len` octets on the given stream, if the stream's frames are to be padded.
+    ///
+    /// The padding is shortened so that the frame fits within the peer's maximum frame size and
+    /// the outbound flow control windows that the data leaves room in.
+    fn padding_for(&mut self, stream_id: StreamId, data_len: usize) -> Option<u8> {
+        let requested = match self.padding.get_mut(&stream_id) {
+            Some(strategy) => strategy.padding_for(data_len),
+            None => return None,
+        };
+        let mut max_payload = cmp::min(self.peer_settings.max_frame_size,
+                                       self.out_window_size.available()) as usize;
+        if let Some(size) = self.stream_windows.size(stream_id) {
+            max_payload = cmp::min(max_payload, cmp::max(size, 0) as usize);
+        }
+        padding::fit_padding(requested, data_len, max_payload)
+    }
+
+    /// Internal helper method that stops tracking what is sent on the given stream, once our
+    /// side of it ended (or it was reset).
+    fn close_stream_out(&mut self, stream_id: StreamId) {
+        self.stream_windows.close(stream_id);
+        self.padding.remove(&stream_id);
+        if let Some(ref mut limiter) = self.rate_limiter {
+            limiter.remove_stream(stream_id);
+        }
+    }
+
     /// Internal helper method that decreases the inbound flow control window size.
     fn decrease_in_window(&mut self, size: u32) -> HttpResult<()> {
-        // The size by which we decrease the window must be at most 2^31 - 1. We should be able to
-        // reach here only after receiving a DATA frame, which would have been validated when
-        // parsed from the raw frame to have the correct payload size, but we assert it just in
-        // case.
-        debug_assert!(size < 0x80000000);
-        self.in_window_size.try_decrease(size as i32)
+        // The payload length of a received DATA frame is validated against the maximum frame
+        // size, but consuming the window is checked regardless.
+        self.in_window_size.try_consume(size)
                            .map_err(|_| HttpError::WindowSizeOverflow)
     }
 }
@@ -549,21 +1761,36 @@ mod tests {
 
     use http::tests::common::{
         build_mock_http_conn,
+        serialize_frame,
         StubDataPrioritizer,
+        StubTransportStream,
         TestSession,
         MockReceiveFrame,
         MockSendFrame,
     };
     use http::frame::{
-        Frame, DataFrame, HeadersFrame,
+        Frame, DataFrame, DataFlag, HeadersFrame, HeadersFlag, StreamDependency,
+        PriorityFrame,
+        ContinuationFrame, ContinuationFlag,
+        AltSvcFrame,
         RstStreamFrame,
         GoawayFrame,
         SettingsFrame,
+        WindowUpdateFrame,
+        HttpSetting,
+        PingFrame,
         pack_header,
         RawFrame,
         FrameIR,
     };
-    use http::{HttpResult, HttpScheme, Header, OwnedHeader, ErrorCode};
+    use http::flow_control::FlowControlStrategy;
+    use http::padding::PaddingStrategy;
+    use http::rate_limit::RateLimit;
+    use http::config::Http2Config;
+    use http::{HttpResult, HttpError, HttpScheme, Header, OwnedHeader, ErrorCode, StreamId};
+    use http::session::StreamState;
+    use http::transport::TransportReceiveFrame;
+    use http::validation::MalformedHeaders;
     use hpack;
 
     /// A helper function that performs a `send_frame` operation on the given
@@ -579,12 +1806,24 @@ mod tests {
             HttpFrame::SettingsFrame(frame) => conn.sender(sender).send_frame(frame),
             HttpFrame::RstStreamFrame(frame) => conn.sender(sender).send_frame(frame),
             HttpFrame::HeadersFrame(frame) => conn.sender(sender).send_frame(frame),
+            HttpFrame::PriorityFrame(frame) => conn.sender(sender).send_frame(frame),
             HttpFrame::GoawayFrame(frame) => conn.sender(sender).send_frame(frame),
             HttpFrame::WindowUpdateFrame(frame) => conn.sender(sender).send_frame(frame),
+            HttpFrame::PingFrame(frame) => conn.sender(sender).send_frame(frame),
+            HttpFrame::ContinuationFrame(frame) => conn.sender(sender).send_frame(frame),
+            HttpFrame::AltSvcFrame(frame) => conn.sender(sender).send_frame(frame),
             HttpFrame::UnknownFrame(_) => Ok(()),
         }
     }
 
+    /// Returns a HEADERS frame that carries the whole header block (i.e. has the END_HEADERS flag
+    /// set).
+    fn complete_headers<'a>(fragment: Vec<u8>, stream_id: StreamId) -> HeadersFrame<'a> {
+        let mut frame = HeadersFrame::new(fragment, stream_id);
+        frame.set_flag(HeadersFlag::EndHeaders);
+        frame
+    }
+
     /// Tests that the `HttpFrame::from_raw` method correctly recognizes the frame
     /// type from the header and returns the corresponding variant.
     #[test]
@@ -605,11 +1844,22 @@ mod tests {
             _ => false,
         });
 
+        assert!(match HttpFrame::from_raw(&to_raw(ContinuationFrame::new(vec![], 1))) {
+            Ok(HttpFrame::ContinuationFrame(_)) => true,
+            _ => false,
+        });
+
         assert!(match HttpFrame::from_raw(&to_raw(SettingsFrame::new())) {
             Ok(HttpFrame::SettingsFrame(_)) => true,
             _ => false,
         });
 
+        let priority = PriorityFrame::new(3, StreamDependency::new(1, 10, false));
+        assert!(match HttpFrame::from_raw(&to_raw(priority)) {
+            Ok(HttpFrame::PriorityFrame(_)) => true,
+            _ => false,
+        });
+
         let unknown_frame = RawFrame::from({
             let mut buf: Vec<u8> = Vec::new();
             // Frame type 10 with a payload of length 1 on stream 1
@@ -689,6 +1939,44 @@ mod tests {
         assert_eq!(conn.out_window_size(), expected_window);
     }
 
+    /// Tests that no more DATA is sent once the connection's outbound flow control window is
+    /// exhausted, until a WINDOW_UPDATE on stream 0 replenishes it.
+    #[test]
+    fn test_send_next_data_connection_window() {
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        let chunks = vec![
+            vec![0; 16_384],
+            vec![0; 16_384],
+            vec![0; 16_384],
+            vec![0; 16_383],
+            vec![1, 2, 3],
+        ];
+        let mut prioritizer = StubDataPrioritizer::new(chunks);
+        for _ in 0..4 {
+            assert_eq!(SendStatus::Sent,
+                       conn.sender(&mut sender).send_next_data(&mut prioritizer).unwrap());
+        }
+        assert_eq!(conn.out_window_size(), 0);
+        assert_eq!(conn.next_chunk_size(), 0);
+
+        // The window is exhausted: the last chunk is not even taken from the prioritizer.
+        assert_eq!(SendStatus::Throttled,
+                   conn.sender(&mut sender).send_next_data(&mut prioritizer).unwrap());
+        assert_eq!(sender.sent.len(), 4);
+        assert_eq!(prioritizer.chunks.len(), 1);
+
+        let frames = vec![HttpFrame::WindowUpdateFrame(WindowUpdateFrame::for_connection(10))];
+        let mut frame_provider = MockReceiveFrame::new(frames);
+        conn.handle_next_frame(&mut frame_provider, &mut TestSession::new()).unwrap();
+        assert_eq!(conn.next_chunk_size(), 10);
+
+        assert_eq!(SendStatus::Sent,
+                   conn.sender(&mut sender).send_next_data(&mut prioritizer).unwrap());
+        assert_eq!(conn.out_window_size(), 7);
+        assert_eq!(sender.sent.len(), 5);
+    }
+
     /// Tests that multiple frames are correctly written to the stream.
     #[test]
     fn test_write_multiple_frames() {
@@ -709,6 +1997,122 @@ mod tests {
         expect_frame_list(expected, sender.sent);
     }
 
+    /// Tests that `HttpConnection::send_frame` sends arbitrary frames as they are, including
+    /// frames of unknown types.
+    #[test]
+    fn test_http_conn_send_frame() {
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        let mut settings = SettingsFrame::new();
+        settings.add_setting(HttpSetting::InitialWindowSize(0));
+        settings.add_setting(HttpSetting::InitialWindowSize(100));
+        let unknown = RawFrame::from({
+            let mut buf = pack_header(&(3, 0xfa, 0x1, 1)).to_vec();
+            buf.extend(vec![1, 2, 3]);
+            buf
+        });
+        let frames = vec![
+            HttpFrame::SettingsFrame(settings),
+            HttpFrame::UnknownFrame(unknown),
+        ];
+
+        for frame in frames.iter() {
+            conn.send_frame(frame.clone(), &mut sender).unwrap();
+        }
+
+        let sent: Vec<_> = sender.sent.iter().map(|raw| HttpFrame::from_raw(raw).unwrap())
+                                             .collect();
+        assert_eq!(sent, frames);
+    }
+
+    /// Tests that DATA frames sent through `HttpConnection::send_frame` still consume the
+    /// outbound flow control window.
+    #[test]
+    fn test_http_conn_send_frame_data_window() {
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        let window = conn.out_window_size();
+
+        let frame = DataFrame::with_data(1, &b"123"[..]);
+        conn.send_frame(HttpFrame::DataFrame(frame), &mut sender).unwrap();
+
+        assert_eq!(conn.out_window_size(), window - 3);
+        assert_eq!(sender.sent.len(), 1);
+    }
+
+    /// Tests that `HttpConnection::send_frame` refuses frames that could never be valid.
+    #[test]
+    fn test_http_conn_send_frame_invalid() {
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+
+        // A stream-bound frame on stream 0...
+        let res = conn.send_frame(HttpFrame::RstStreamFrame(RstStreamFrame::new(0,
+                                                                                ErrorCode::Cancel)),
+                                  &mut sender);
+        assert_eq!(res.err().unwrap(), HttpError::InvalidFrame);
+        // ...and a frame that exceeds the maximum frame size.
+        let frame = DataFrame::with_data(1, vec![0; 16385]);
+        let res = conn.send_frame(HttpFrame::DataFrame(frame), &mut sender);
+        assert_eq!(res.err().unwrap(), HttpError::InvalidFrame);
+
+        assert!(sender.sent.is_empty());
+        assert_eq!(conn.out_window_size(), 65535);
+    }
+
+    /// Tests that `HttpConnection::send_headers` refuses to send a `te` header with anything but
+    /// `trailers`, without touching the encoder.
+    #[test]
+    fn test_send_headers_invalid_te() {
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        let headers = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b"te".to_vec(), b"gzip".to_vec()),
+        ];
+
+        let res = conn.sender(&mut sender).send_headers(headers, 1, EndStream::Yes);
+
+        assert_eq!(res.err().unwrap(), HttpError::MalformedHeaders(MalformedHeaders::InvalidTe));
+        assert!(sender.sent.is_empty());
+        // The next header block is still encoded with a fresh encoder state.
+        let headers = vec![Header::new(b"te".to_vec(), b"trailers".to_vec())];
+        conn.sender(&mut sender).send_headers(headers, 1, EndStream::Yes).unwrap();
+        let frame = match HttpFrame::from_raw(&sender.sent[0]).unwrap() {
+            HttpFrame::HeadersFrame(frame) => frame,
+            _ => panic!("Headers frame not sent"),
+        };
+        assert_eq!(hpack::Decoder::new().decode(frame.header_fragment()).unwrap(),
+                   vec![(b"te".to_vec(), b"trailers".to_vec())]);
+    }
+
+    /// Tests that a connection whose config disables the dynamic header table encodes the same
+    /// headers the same way every time, after shrinking the peer's table to 0 in the first block.
+    #[test]
+    fn test_send_headers_no_dynamic_table() {
+        let config = Http2Config::new().dynamic_header_table(false);
+        let mut conn = HttpConnection::with_config(HttpScheme::Http, config);
+        let mut sender = MockSendFrame::new();
+        let headers = || vec![Header::new(b"x-custom", b"value")];
+
+        conn.sender(&mut sender).send_headers(headers(), 1, EndStream::Yes).unwrap();
+        conn.sender(&mut sender).send_headers(headers(), 3, EndStream::Yes).unwrap();
+
+        let fragments: Vec<Vec<u8>> = sender.sent.iter().map(|raw| {
+            match HttpFrame::from_raw(raw).unwrap() {
+                HttpFrame::HeadersFrame(frame) => frame.header_fragment().to_vec(),
+                _ => panic!("Headers frame not sent"),
+            }
+        }).collect();
+        assert_eq!(fragments[0][0], 0x20);
+        assert_eq!(&fragments[0][1..], &fragments[1][..]);
+        let mut decoder = hpack::Decoder::new();
+        for fragment in &fragments {
+            assert_eq!(decoder.decode(fragment).unwrap(),
+                       vec![(b"x-custom".to_vec(), b"value".to_vec())]);
+        }
+    }
+
     /// Tests that `HttpConnection::send_headers` correctly sends the given headers when they can
     /// fit into a single frame's payload.
     #[test]
@@ -860,12 +2264,64 @@ mod tests {
         }
     }
 
+    /// Tests that the DATA frames sent on a stream are padded according to its strategy, that
+    /// the padding counts against the flow control windows and that it is shortened to keep
+    /// the frames within the maximum frame size.
+    #[test]
+    fn test_send_data_padded() {
+        fn sent_data_frame(sender: &mut MockSendFrame) -> (Vec<u8>, u32, bool) {
+            let raw = sender.sent.remove(0);
+            match HttpFrame::from_raw(&raw).unwrap() {
+                HttpFrame::DataFrame(frame) => {
+                    (frame.data.to_vec(), frame.payload_len(), frame.is_set(DataFlag::Padded))
+                },
+                _ => panic!("Data frame not sent"),
+            }
+        }
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        conn.sender(&mut sender).send_headers(vec![Header::new(b":method", b"POST")],
+                                              1,
+                                              EndStream::No).unwrap();
+        sender.sent.clear();
+        conn.set_stream_padding(1, Some(PaddingStrategy::Fixed(10)));
+
+        conn.sender(&mut sender).send_data(DataChunk::new_borrowed(&b"1234"[..], 1, EndStream::No))
+                                .unwrap();
+        assert_eq!(sent_data_frame(&mut sender), (b"1234".to_vec(), 15, true));
+        assert_eq!(conn.out_window_size(), 65_535 - 15);
+        assert_eq!(conn.stream_out_window_size(1), Some(65_535 - 15));
+
+        // Only as much padding as still fits into the maximum frame size...
+        let data = vec![0; 16_380];
+        conn.sender(&mut sender).send_data(DataChunk::new_borrowed(&data[..], 1, EndStream::No))
+                                .unwrap();
+        assert_eq!(sent_data_frame(&mut sender), (data.clone(), 16_384, true));
+
+        // ...and none at all, when not even the padding length does.
+        let data = vec![0; 16_384];
+        conn.sender(&mut sender).send_data(DataChunk::new_borrowed(&data[..], 1, EndStream::No))
+                                .unwrap();
+        assert_eq!(sent_data_frame(&mut sender), (data, 16_384, false));
+
+        // The strategy is dropped along with the stream.
+        conn.set_stream_padding(1, Some(PaddingStrategy::Callback(Box::new(|len| len as u8))));
+        conn.sender(&mut sender).send_data(DataChunk::new_borrowed(&b"12"[..], 1, EndStream::Yes))
+                                .unwrap();
+        assert_eq!(sent_data_frame(&mut sender), (b"12".to_vec(), 5, true));
+        assert!(conn.padding.is_empty());
+        // Streams without a strategy are not padded.
+        conn.sender(&mut sender).send_data(DataChunk::new_borrowed(&b"12"[..], 3, EndStream::Yes))
+                                .unwrap();
+        assert_eq!(sent_data_frame(&mut sender), (b"12".to_vec(), 2, false));
+    }
+
     /// Tests that the `HttpConnection` correctly notifies the session on a
     /// new headers frame, with no continuation.
     #[test]
     fn test_http_conn_notifies_session_header() {
         let frames: Vec<HttpFrame> = vec![
-            HttpFrame::HeadersFrame(HeadersFrame::new(vec![], 1)),
+            HttpFrame::HeadersFrame(complete_headers(vec![], 1)),
         ];
         let mut conn = HttpConnection::new(HttpScheme::Http);
         let mut session = TestSession::new();
@@ -909,7 +2365,7 @@ mod tests {
     fn test_http_conn_session_gets_headers_data_values() {
         let expected_headers = vec![(b":method".to_vec(), b"GET".to_vec())];
         let frames: Vec<HttpFrame> = vec![
-            HttpFrame::HeadersFrame(HeadersFrame::new(
+            HttpFrame::HeadersFrame(complete_headers(
                     hpack::Encoder::new().encode(
                         expected_headers.iter().map(|h| (&h.0[..], &h.1[..]))),
                     1)),
@@ -934,6 +2390,36 @@ mod tests {
         assert_eq!(session.curr_header, 1);
     }
 
+    /// Tests that the headers of consecutive blocks are decoded correctly, even though they are
+    /// decoded into the same buffers, including the ones that refer to the dynamic table.
+    #[test]
+    fn test_http_conn_headers_decoded_across_blocks() {
+        let first = vec![
+            (b":method".to_vec(), b"GET".to_vec()),
+            (b"x-custom".to_vec(), b"a fairly long value".to_vec()),
+        ];
+        let second = vec![
+            (b"x-custom".to_vec(), b"a fairly long value".to_vec()),
+            (b"x-other".to_vec(), b"v".to_vec()),
+            (b":method".to_vec(), b"GET".to_vec()),
+        ];
+        let mut encoder = hpack::Encoder::new();
+        let frames: Vec<HttpFrame> = vec![
+            HttpFrame::HeadersFrame(complete_headers(
+                    encoder.encode(first.iter().map(|h| (&h.0[..], &h.1[..]))), 1)),
+            HttpFrame::HeadersFrame(complete_headers(
+                    encoder.encode(second.iter().map(|h| (&h.0[..], &h.1[..]))), 3)),
+        ];
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        let mut session = TestSession::new_verify(vec![first, second], vec![]);
+        let mut frame_provider = MockReceiveFrame::new(frames);
+
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+
+        assert_eq!(session.curr_header, 2);
+    }
+
     /// Tests that the `HttpConnection` correctly notifies the session when a stream is reset.
     #[test]
     fn test_conn_rst_stream() {
@@ -951,6 +2437,31 @@ mod tests {
         assert_eq!(session.rst_streams[0], 1);
     }
 
+    /// Tests that the per-stream rate limit buckets of the streams that get reset, by the peer or
+    /// by us, are dropped.
+    #[test]
+    fn test_conn_rst_stream_drops_rate_limit() {
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        conn.set_rate_limit(None, Some(RateLimit::new(1, 10)));
+        for &stream_id in [1, 3].iter() {
+            conn.rate_limiter.as_mut().unwrap().data_sent(stream_id, 100, false);
+        }
+        let mut throttled = conn.throttled_streams();
+        throttled.sort();
+        assert_eq!(throttled, vec![1, 3]);
+
+        let mut session = TestSession::new();
+        let mut frame_provider = MockReceiveFrame::new(vec![
+            HttpFrame::RstStreamFrame(RstStreamFrame::new(1, ErrorCode::Cancel)),
+        ]);
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        assert_eq!(conn.throttled_streams(), vec![3]);
+
+        let mut sender = MockSendFrame::new();
+        conn.sender(&mut sender).send_rst_stream(3, ErrorCode::Cancel).unwrap();
+        assert!(conn.throttled_streams().is_empty());
+    }
+
     /// Tests that the `HttpConnection` correctly notifies the session when it receives a GOAWAY
     /// frame.
     #[test]
@@ -971,6 +2482,471 @@ mod tests {
         assert_eq!(session.rst_streams.len(), 0);
     }
 
+    /// Tests that the `HttpConnection` correctly notifies the session when it receives
+    /// WINDOW_UPDATE frames and that connection-level updates increase the outbound window.
+    #[test]
+    fn test_conn_window_update() {
+        let frames = vec![
+            HttpFrame::WindowUpdateFrame(WindowUpdateFrame::for_stream(1, 10)),
+            HttpFrame::WindowUpdateFrame(WindowUpdateFrame::for_connection(100)),
+        ];
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        let mut session = TestSession::new();
+        let mut frame_provider = MockReceiveFrame::new(frames);
+
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        assert_eq!(session.window_updates, vec![(1, 10)]);
+        // The stream-level update does not affect the connection window
+        assert_eq!(conn.out_window_size(), 65_535);
+
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        assert_eq!(session.window_updates, vec![(1, 10), (0, 100)]);
+        assert_eq!(conn.out_window_size(), 65_535 + 100);
+    }
+
+    /// Tests that a connection-level WINDOW_UPDATE that would overflow the outbound window results
+    /// in an error.
+    #[test]
+    fn test_conn_window_update_overflow() {
+        let frames = vec![
+            HttpFrame::WindowUpdateFrame(WindowUpdateFrame::for_connection(0x7fffffff)),
+        ];
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        let mut session = TestSession::new();
+        let mut frame_provider = MockReceiveFrame::new(frames);
+
+        let res = conn.handle_next_frame(&mut frame_provider, &mut session);
+
+        assert!(match res {
+            Err(HttpError::WindowSizeOverflow) => true,
+            _ => false,
+        });
+        assert_eq!(session.window_updates.len(), 0);
+    }
+
+    /// Tests that a WINDOW_UPDATE with an increment of 0 is a connection error on stream 0 and a
+    /// stream error on any other stream (section 6.9.).
+    #[test]
+    fn test_conn_window_update_zero_increment() {
+        let frames = vec![
+            HttpFrame::WindowUpdateFrame(WindowUpdateFrame::for_stream(1, 0)),
+            HttpFrame::WindowUpdateFrame(WindowUpdateFrame::for_connection(0)),
+        ];
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        let mut session = TestSession::new();
+        let mut frame_provider = MockReceiveFrame::new(frames);
+
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        assert_eq!(session.stream_errors, vec![(1, ErrorCode::ProtocolError)]);
+        assert!(session.connection_errors.is_empty());
+
+        let err = conn.handle_next_frame(&mut frame_provider, &mut session).unwrap_err();
+        assert_eq!(err, HttpError::LocalConnectionError(ErrorCode::ProtocolError));
+        assert_eq!(session.connection_errors, vec![ErrorCode::ProtocolError]);
+        assert_eq!(session.window_updates.len(), 0);
+        assert_eq!(conn.out_window_size(), 65_535);
+    }
+
+    /// Tests that the outbound windows of the streams that we send data on are tracked, with a
+    /// WINDOW_UPDATE that overflows one of them being a stream error, and a change of the initial
+    /// window size that overflows one of them a connection error.
+    #[test]
+    fn test_conn_stream_window_update_overflow() {
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        let mut sender = MockSendFrame::new();
+        {
+            let mut sender = conn.sender(&mut sender);
+            sender.send_headers(vec![Header::new(b":method", b"POST")], 1, EndStream::No).unwrap();
+            sender.send_headers(vec![Header::new(b":method", b"POST")], 3, EndStream::No).unwrap();
+            sender.send_headers(vec![Header::new(b":method", b"GET")], 5, EndStream::Yes).unwrap();
+            sender.send_data(DataChunk::new_borrowed(&b"hello"[..], 1, EndStream::No)).unwrap();
+        }
+        assert_eq!(conn.stream_out_window_size(1), Some(65_530));
+        assert_eq!(conn.stream_out_window_size(3), Some(65_535));
+        assert_eq!(conn.stream_out_window_size(5), None);
+
+        let mut settings = SettingsFrame::new();
+        settings.add_setting(HttpSetting::InitialWindowSize(0x7fffffff));
+        let frames = vec![
+            HttpFrame::WindowUpdateFrame(WindowUpdateFrame::for_stream(1, 10)),
+            HttpFrame::WindowUpdateFrame(WindowUpdateFrame::for_stream(3, 0x7fffffff)),
+            // The stream ended on our side, so its window is no longer tracked.
+            HttpFrame::WindowUpdateFrame(WindowUpdateFrame::for_stream(5, 0x7fffffff)),
+            HttpFrame::SettingsFrame(settings),
+        ];
+        let mut session = TestSession::new();
+        let mut frame_provider = MockReceiveFrame::new(frames);
+
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        assert_eq!(conn.stream_out_window_size(1), Some(65_540));
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        assert_eq!(session.stream_errors, vec![(3, ErrorCode::FlowControlError)]);
+        assert_eq!(conn.stream_out_window_size(3), None);
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        assert_eq!(session.window_updates, vec![(1, 10), (5, 0x7fffffff)]);
+
+        // Stream 1 has grown past the initial window, so the new setting overflows it.
+        let err = conn.handle_next_frame(&mut frame_provider, &mut session).unwrap_err();
+        assert_eq!(err, HttpError::LocalConnectionError(ErrorCode::FlowControlError));
+        assert_eq!(session.connection_errors, vec![ErrorCode::FlowControlError]);
+    }
+
+    /// Tests that a WINDOW_UPDATE frame whose length is not 4 is a FRAME_SIZE_ERROR that closes
+    /// the connection, whichever stream it is on.
+    #[test]
+    fn test_conn_window_update_invalid_length() {
+        for &stream_id in &[0, 1] {
+            let mut raw = pack_header(&(5, 0x8, 0, stream_id)).to_vec();
+            raw.extend_from_slice(&[0, 0, 0, 10, 0]);
+            let mut stream = StubTransportStream::with_stub_content(&raw);
+            let mut conn = HttpConnection::new(HttpScheme::Http);
+            let mut session = TestSession::new();
+
+            let err = conn.handle_next_frame(&mut TransportReceiveFrame::new(&mut stream),
+                                             &mut session).unwrap_err();
+            assert_eq!(err, HttpError::LocalConnectionError(ErrorCode::FrameSizeError));
+            assert_eq!(session.connection_errors, vec![ErrorCode::FrameSizeError]);
+            assert_eq!(session.window_updates.len(), 0);
+            assert_eq!(conn.out_window_size(), 65_535);
+        }
+    }
+
+    /// Tests that a frame longer than our SETTINGS_MAX_FRAME_SIZE is a FRAME_SIZE_ERROR that
+    /// closes the connection, while a larger maximum that we advertised applies right away.
+    #[test]
+    fn test_conn_frame_too_large() {
+        let serialized = serialize_frame(&DataFrame::with_data(1, vec![0; 20_000]));
+        {
+            let mut stream = StubTransportStream::with_stub_content(&serialized);
+            let mut conn = HttpConnection::new(HttpScheme::Http);
+            let mut session = TestSession::new();
+
+            let err = conn.handle_next_frame(&mut TransportReceiveFrame::new(&mut stream),
+                                             &mut session).unwrap_err();
+            assert_eq!(err, HttpError::LocalConnectionError(ErrorCode::FrameSizeError));
+            assert_eq!(session.connection_errors, vec![ErrorCode::FrameSizeError]);
+            assert_eq!(session.curr_chunk, 0);
+        }
+        {
+            let mut stream = StubTransportStream::with_stub_content(&serialized);
+            let mut conn = HttpConnection::new(HttpScheme::Http);
+            // The peer may use the new maximum before it acknowledges it.
+            conn.settings_sent(&[HttpSetting::MaxFrameSize(32_768)]);
+            let mut session = TestSession::new();
+
+            conn.handle_next_frame(&mut TransportReceiveFrame::new(&mut stream), &mut session)
+                .unwrap();
+            assert_eq!(session.curr_chunk, 1);
+            assert!(session.connection_errors.is_empty());
+        }
+    }
+
+    /// Tests that the connection keeps track of when it last received and sent a frame.
+    #[test]
+    fn test_conn_activity() {
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        let activity = conn.activity();
+        assert_eq!(activity.last_frame_received, None);
+        assert_eq!(activity.last_frame_sent, None);
+        assert_eq!(activity.last_peer_activity(), activity.established_at);
+
+        let mut sender = MockSendFrame::new();
+        conn.sender(&mut sender).send_ping(1).unwrap();
+        let sent = conn.activity().last_frame_sent.unwrap();
+        assert!(sent >= activity.established_at);
+        assert_eq!(conn.activity().last_frame_received, None);
+
+        let frames = vec![HttpFrame::PingFrame(PingFrame::new_ack(1))];
+        let mut session = TestSession::new();
+        conn.handle_next_frame(&mut MockReceiveFrame::new(frames), &mut session).unwrap();
+        let activity = conn.activity();
+        let received = activity.last_frame_received.unwrap();
+        assert!(received >= sent);
+        assert_eq!(activity.last_peer_activity(), received);
+        assert_eq!(activity.last_frame_sent, Some(sent));
+    }
+
+    /// Tests that each connection is assigned its own ID, which stays the same for its lifetime.
+    #[test]
+    fn test_conn_id() {
+        let first = HttpConnection::new(HttpScheme::Http);
+        let second = HttpConnection::new(HttpScheme::Http);
+        assert!(first.id() != second.id());
+        assert_eq!(first.id(), first.id());
+    }
+
+    /// Tests that WINDOW_UPDATE frames can be sent manually, growing the connection-level window
+    /// along with them, and that invalid increments are rejected without sending anything.
+    #[test]
+    fn test_conn_send_window_update() {
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        let mut sender = MockSendFrame::new();
+
+        conn.sender(&mut sender).send_window_update(1, 100).unwrap();
+        conn.sender(&mut sender).send_window_update(0, 1000).unwrap();
+
+        assert_eq!(conn.in_window_size(), 65_535 + 1000);
+        let sent: Vec<_> = sender.sent.iter().map(|raw| {
+            match HttpFrame::from_raw(raw).unwrap() {
+                HttpFrame::WindowUpdateFrame(frame) => (frame.get_stream_id(), frame.increment()),
+                _ => panic!("Expected a WINDOW_UPDATE frame"),
+            }
+        }).collect();
+        assert_eq!(sent, vec![(1, 100), (0, 1000)]);
+
+        for &(stream_id, increment) in &[(1, 0), (0, 0), (1, 0x80000000), (0, 0x7fffffff)] {
+            let res = conn.sender(&mut sender).send_window_update(stream_id, increment);
+            assert_eq!(res.err().unwrap(), HttpError::InvalidWindowIncrement(increment));
+        }
+        // The largest increment is fine for a stream, whose window is tracked by the application.
+        conn.sender(&mut sender).send_window_update(3, 0x7fffffff).unwrap();
+        assert_eq!(sender.sent.len(), 3);
+        assert_eq!(conn.in_window_size(), 65_535 + 1000);
+    }
+
+    /// Tests that the connection reports the peer closing the connection, distinguishing between
+    /// a close that was preceded by a GOAWAY frame and one that was not.
+    #[test]
+    fn test_conn_peer_closed() {
+        {
+            let frames = vec![HttpFrame::DataFrame(DataFrame::new(1))];
+            let mut conn = HttpConnection::new(HttpScheme::Http);
+            let mut session = TestSession::new();
+            let mut frame_provider = MockReceiveFrame::new(frames);
+            conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+
+            let res = conn.handle_next_frame(&mut frame_provider, &mut session);
+
+            assert_eq!(res.err().unwrap(), HttpError::PeerClosed { graceful: false });
+        }
+        {
+            let frames = vec![
+                HttpFrame::GoawayFrame(GoawayFrame::new(1, ErrorCode::NoError)),
+            ];
+            let mut conn = HttpConnection::new(HttpScheme::Http);
+            let mut session = TestSession::new();
+            let mut frame_provider = MockReceiveFrame::new(frames);
+            conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+            assert!(conn.goaway_received());
+
+            let res = conn.handle_next_frame(&mut frame_provider, &mut session);
+
+            assert_eq!(res.err().unwrap(), HttpError::PeerClosed { graceful: true });
+        }
+    }
+
+    /// Tests that frames that are not allowed in the state of their stream are not passed on to
+    /// the session, but are reported as stream or connection errors, as appropriate.
+    #[test]
+    fn test_conn_frame_stream_state() {
+        fn handle(frame: HttpFrame, state: StreamState) -> (HttpResult<()>, TestSession) {
+            let mut conn = HttpConnection::new(HttpScheme::Http);
+            let mut session = TestSession::new();
+            session.stream_states.insert(1, state);
+            let mut frame_provider = MockReceiveFrame::new(vec![frame]);
+            let res = conn.handle_next_frame(&mut frame_provider, &mut session);
+            (res, session)
+        }
+        fn headers() -> HttpFrame<'static> {
+            let data = hpack::Encoder::new().encode(vec![(&b":method"[..], &b"GET"[..])]);
+            HttpFrame::HeadersFrame(complete_headers(data, 1))
+        }
+        let data = || HttpFrame::DataFrame(DataFrame::with_data(1, vec![1, 2, 3]));
+        let rst = || HttpFrame::RstStreamFrame(RstStreamFrame::new(1, ErrorCode::Cancel));
+        let window_update = || HttpFrame::WindowUpdateFrame(WindowUpdateFrame::for_stream(1, 10));
+
+        {
+            // DATA on an idle stream is a connection error
+            let (res, session) = handle(data(), StreamState::Idle);
+            assert_eq!(res.err().unwrap(),
+                       HttpError::LocalConnectionError(ErrorCode::ProtocolError));
+            assert_eq!(session.curr_chunk, 0);
+        }
+        {
+            // DATA on a stream that the peer already closed is a stream error
+            let (res, session) = handle(data(), StreamState::HalfClosedRemote);
+            assert!(res.is_ok());
+            assert_eq!(session.curr_chunk, 0);
+            assert_eq!(session.stream_errors, vec![(1, ErrorCode::StreamClosed)]);
+        }
+        {
+            // ...and so are HEADERS
+            let (res, session) = handle(headers(), StreamState::HalfClosedRemote);
+            assert!(res.is_ok());
+            assert_eq!(session.curr_header, 0);
+            assert_eq!(session.stream_errors, vec![(1, ErrorCode::StreamClosed)]);
+        }
+        {
+            // Resetting an idle stream is a connection error
+            let (res, session) = handle(rst(), StreamState::Idle);
+            assert_eq!(res.err().unwrap(),
+                       HttpError::LocalConnectionError(ErrorCode::ProtocolError));
+            assert_eq!(session.rst_streams.len(), 0);
+        }
+        {
+            // ...and so is updating its window
+            let (res, session) = handle(window_update(), StreamState::Idle);
+            assert_eq!(res.err().unwrap(),
+                       HttpError::LocalConnectionError(ErrorCode::ProtocolError));
+            assert_eq!(session.window_updates.len(), 0);
+        }
+        {
+            // Allowed frames are handled as usual
+            let (res, session) = handle(data(), StreamState::HalfClosedLocal);
+            assert!(res.is_ok());
+            assert_eq!(session.curr_chunk, 1);
+            let (res, session) = handle(headers(), StreamState::Idle);
+            assert!(res.is_ok());
+            assert_eq!(session.curr_header, 1);
+            let (res, session) = handle(rst(), StreamState::Closed);
+            assert!(res.is_ok());
+            assert_eq!(session.rst_streams, vec![1]);
+            let (res, session) = handle(window_update(), StreamState::Closed);
+            assert!(res.is_ok());
+            assert_eq!(session.window_updates, vec![(1, 10)]);
+            assert_eq!(session.stream_errors.len(), 0);
+        }
+    }
+
+    /// Tests that a header block that cannot be decoded fails the connection with a compression
+    /// error, after which no more frames are handled.
+    #[test]
+    fn test_conn_header_decode_error() {
+        use std::error::Error;
+
+        let frames = vec![
+            // Index 70 is in neither the static nor the (empty) dynamic table.
+            HttpFrame::HeadersFrame(complete_headers(vec![0x80 | 70], 1)),
+            HttpFrame::DataFrame(DataFrame::new(1)),
+        ];
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        let mut session = TestSession::new();
+        let mut frame_provider = MockReceiveFrame::new(frames);
+
+        let err = conn.handle_next_frame(&mut frame_provider, &mut session).err().unwrap();
+
+        assert_eq!(err.connection_error_code(), Some(ErrorCode::CompressionError));
+        assert!(err.description().contains("index"));
+        assert_eq!(conn.connection_error(), Some(ErrorCode::CompressionError));
+        assert_eq!(session.connection_errors, vec![ErrorCode::CompressionError]);
+        assert_eq!(session.curr_header, 0);
+        // No further frames are handled...
+        let err = conn.handle_next_frame(&mut frame_provider, &mut session).err().unwrap();
+        assert_eq!(err, HttpError::LocalConnectionError(ErrorCode::CompressionError));
+        assert_eq!(frame_provider.recv_list.len(), 1);
+        assert_eq!(session.curr_chunk, 0);
+        // ...and the session is not notified again.
+        assert_eq!(session.connection_errors.len(), 1);
+    }
+
+    /// Tests that the connection acknowledges received PING frames.
+    #[test]
+    fn test_conn_ping() {
+        let frames = vec![
+            HttpFrame::PingFrame(PingFrame::new(42)),
+            HttpFrame::PingFrame(PingFrame::new_ack(1)),
+        ];
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        let mut session = TestSession::new();
+        let mut frame_provider = MockReceiveFrame::new(frames);
+        let mut sender = MockSendFrame::new();
+
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        assert!(conn.has_pending_frames());
+        conn.sender(&mut sender).send_pending_frames().unwrap();
+        assert!(!conn.has_pending_frames());
+        // An ACK does not get acknowledged
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        conn.sender(&mut sender).send_pending_frames().unwrap();
+
+        assert_eq!(sender.sent.len(), 1);
+        assert_eq!(HttpFrame::from_raw(&sender.sent[0]).unwrap(),
+                   HttpFrame::PingFrame(PingFrame::new_ack(42)));
+    }
+
+    /// Tests that with the adaptive flow control strategy the connection replenishes the inbound
+    /// windows and probes the BDP, growing the windows when the link fills them.
+    #[test]
+    fn test_conn_adaptive_flow_control() {
+        let frames = vec![
+            HttpFrame::DataFrame(DataFrame::with_data(1, vec![0; 50_000])),
+            HttpFrame::PingFrame(PingFrame::new_ack(0)),
+        ];
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        conn.set_flow_control(FlowControlStrategy::Adaptive { max_window: 1_000_000 });
+        let mut session = TestSession::new();
+        let mut frame_provider = MockReceiveFrame::new(frames);
+        let mut sender = MockSendFrame::new();
+
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        conn.sender(&mut sender).send_pending_frames().unwrap();
+
+        let sent: Vec<_> = sender.sent.iter().map(|raw| HttpFrame::from_raw(raw).unwrap())
+                                             .collect();
+        assert_eq!(sent, vec![
+            HttpFrame::WindowUpdateFrame(WindowUpdateFrame::for_connection(50_000)),
+            HttpFrame::WindowUpdateFrame(WindowUpdateFrame::for_stream(1, 50_000)),
+            HttpFrame::PingFrame(PingFrame::new(0)),
+        ]);
+        // The connection-level update is reflected in the inbound window.
+        assert_eq!(conn.in_window_size(), 65_535);
+
+        // The probe's ACK shows that the window is too small for the link...
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        assert_eq!(conn.adaptive_window().unwrap().window_size(), 100_000);
+        assert!(!conn.has_pending_frames());
+    }
+
+    /// Tests that the memory usage counts the encoder's HPACK table at the size that the peer
+    /// allows it to take up.
+    #[test]
+    fn test_conn_memory_usage_encoder_table() {
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        assert_eq!(conn.memory_usage().header_tables, 2 * 4_096);
+
+        let mut settings = SettingsFrame::new();
+        settings.add_setting(HttpSetting::HeaderTableSize(256));
+        let mut frame_provider = MockReceiveFrame::new(vec![HttpFrame::SettingsFrame(settings)]);
+        conn.handle_next_frame(&mut frame_provider, &mut TestSession::new()).unwrap();
+        assert_eq!(conn.memory_usage().header_tables, 4_096 + 256);
+    }
+
+    /// Tests that a frozen inbound connection window is not replenished by the adaptive flow
+    /// control strategy until it is thawed, while the windows of the streams still are.
+    #[test]
+    fn test_conn_frozen_in_window() {
+        let frames = vec![
+            HttpFrame::DataFrame(DataFrame::with_data(1, vec![0; 40_000])),
+            HttpFrame::DataFrame(DataFrame::with_data(3, vec![0; 20_000])),
+        ];
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        conn.set_flow_control(FlowControlStrategy::Adaptive { max_window: 65_535 });
+        conn.freeze_in_window();
+        let mut session = TestSession::new();
+        let mut frame_provider = MockReceiveFrame::new(frames);
+        let mut sender = MockSendFrame::new();
+
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        conn.sender(&mut sender).send_pending_frames().unwrap();
+        assert_eq!(conn.in_window_size(), 65_535 - 60_000);
+        let sent: Vec<_> = sender.sent.iter().map(|raw| HttpFrame::from_raw(raw).unwrap())
+                                             .collect();
+        assert!(sent.iter().all(|frame| match *frame {
+            HttpFrame::WindowUpdateFrame(ref frame) => frame.get_stream_id() != 0,
+            _ => true,
+        }));
+        assert!(!sent.is_empty());
+
+        let mut sender = MockSendFrame::new();
+        conn.thaw_in_window().unwrap();
+        conn.sender(&mut sender).send_pending_frames().unwrap();
+        // The strategy only replenished the window for the first 40 000 bytes.
+        assert_eq!(conn.in_window_size(), 65_535 - 20_000);
+        assert_eq!(HttpFrame::from_raw(&sender.sent[0]).unwrap(),
+                   HttpFrame::WindowUpdateFrame(WindowUpdateFrame::for_connection(40_000)));
+    }
+
     /// Tests that the connection flow control windows have the correct size when the
     /// HttpConnection is just created.
     #[test]
@@ -1005,4 +2981,487 @@ mod tests {
             assert!(conn.expect_settings(&mut frame_provider, &mut TestSession::new()).is_err());
         }
     }
+
+    /// Tests that the connection keeps track of the peer's limit on concurrent streams.
+    #[test]
+    fn test_conn_peer_max_concurrent_streams() {
+        let mut settings = SettingsFrame::new();
+        settings.add_setting(HttpSetting::MaxConcurrentStreams(10));
+        let frames = vec![
+            HttpFrame::SettingsFrame(SettingsFrame::new()),
+            HttpFrame::SettingsFrame(settings),
+        ];
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        let mut frame_provider = MockReceiveFrame::new(frames);
+        let mut session = TestSession::new();
+
+        conn.expect_settings(&mut frame_provider, &mut session).unwrap();
+        // No limit was announced yet.
+        assert_eq!(conn.peer_max_concurrent_streams(), None);
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        assert_eq!(conn.peer_max_concurrent_streams(), Some(10));
+    }
+
+    /// Tests that a setting with a value out of its bounds is a connection error with the
+    /// appropriate error code, while unknown settings are ignored.
+    #[test]
+    fn test_conn_invalid_settings() {
+        let cases = vec![
+            (HttpSetting::EnablePush(2), Some(ErrorCode::ProtocolError)),
+            (HttpSetting::InitialWindowSize(1 << 31), Some(ErrorCode::FlowControlError)),
+            (HttpSetting::MaxFrameSize(16_383), Some(ErrorCode::ProtocolError)),
+            (HttpSetting::MaxFrameSize(1 << 24), Some(ErrorCode::ProtocolError)),
+            (HttpSetting::Unknown(0x99, 7), None),
+        ];
+        for (setting, expected) in cases {
+            let mut frame = SettingsFrame::new();
+            frame.add_setting(HttpSetting::MaxConcurrentStreams(10));
+            frame.add_setting(setting);
+            let mut conn = HttpConnection::new(HttpScheme::Http);
+            let mut frame_provider = MockReceiveFrame::new(vec![HttpFrame::SettingsFrame(frame)]);
+            let mut session = TestSession::new();
+
+            let res = conn.handle_next_frame(&mut frame_provider, &mut session);
+            match expected {
+                Some(error_code) => {
+                    assert_eq!(res.unwrap_err(), HttpError::LocalConnectionError(error_code));
+                    assert_eq!(session.connection_errors, vec![error_code]);
+                    // None of the frame's settings took effect.
+                    assert_eq!(conn.peer_max_concurrent_streams(), None);
+                },
+                None => {
+                    res.unwrap();
+                    assert_eq!(conn.peer_max_concurrent_streams(), Some(10));
+                },
+            }
+        }
+    }
+
+    /// Tests that a DATA frame whose padding length is not less than its payload length is a
+    /// connection error of type PROTOCOL_ERROR (h2spec 6.1/3), while a merely malformed frame is
+    /// reported as invalid.
+    #[test]
+    fn test_conn_data_invalid_padding() {
+        fn receive(raw: Vec<u8>) -> (HttpError, TestSession) {
+            let mut stream = StubTransportStream::with_stub_content(&raw);
+            let mut conn = HttpConnection::new(HttpScheme::Http);
+            let mut session = TestSession::new();
+            let err = conn.handle_next_frame(&mut TransportReceiveFrame::new(&mut stream),
+                                             &mut session).unwrap_err();
+            (err, session)
+        }
+        for &pad_len in &[5, 6] {
+            let mut raw = pack_header(&(5, 0x0, 0x8 | 0x1, 1)).to_vec();
+            raw.extend_from_slice(&[pad_len, 0, 0, 0, 0]);
+            let (err, session) = receive(raw);
+            assert_eq!(err, HttpError::LocalConnectionError(ErrorCode::ProtocolError));
+            assert_eq!(session.connection_errors, vec![ErrorCode::ProtocolError]);
+            assert_eq!(session.curr_chunk, 0);
+        }
+
+        // A DATA frame on stream 0 is a PROTOCOL_ERROR as well.
+        let mut raw = pack_header(&(1, 0x0, 0, 0)).to_vec();
+        raw.push(0);
+        let (err, _) = receive(raw);
+        assert_eq!(err, HttpError::LocalConnectionError(ErrorCode::ProtocolError));
+    }
+
+    /// Tests that a SETTINGS ACK with a payload is a FRAME_SIZE_ERROR and that an ACK without any
+    /// SETTINGS waiting for one is a PROTOCOL_ERROR, both closing the connection.
+    #[test]
+    fn test_conn_invalid_settings_ack() {
+        {
+            let mut raw = pack_header(&(6, 0x4, 0x1, 0)).to_vec();
+            raw.extend_from_slice(&[0, 3, 0, 0, 0, 10]);
+            let mut stream = StubTransportStream::with_stub_content(&raw);
+            let mut conn = HttpConnection::new(HttpScheme::Http);
+            conn.settings_sent(&[]);
+            let mut session = TestSession::new();
+
+            let err = conn.handle_next_frame(&mut TransportReceiveFrame::new(&mut stream),
+                                             &mut session).unwrap_err();
+            assert_eq!(err, HttpError::LocalConnectionError(ErrorCode::FrameSizeError));
+            assert_eq!(session.connection_errors, vec![ErrorCode::FrameSizeError]);
+            // The ACK did not count.
+            assert_eq!(conn.local_settings().pending_acks(), 1);
+        }
+        {
+            let frames = vec![
+                HttpFrame::SettingsFrame(SettingsFrame::new_ack()),
+                HttpFrame::SettingsFrame(SettingsFrame::new_ack()),
+            ];
+            let mut conn = HttpConnection::new(HttpScheme::Http);
+            conn.settings_sent(&[HttpSetting::MaxConcurrentStreams(1)]);
+            let mut frame_provider = MockReceiveFrame::new(frames);
+            let mut session = TestSession::new();
+
+            conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+            assert_eq!(conn.local_settings().acked().max_concurrent_streams, Some(1));
+            let err = conn.handle_next_frame(&mut frame_provider, &mut session).unwrap_err();
+            assert_eq!(err, HttpError::LocalConnectionError(ErrorCode::ProtocolError));
+            assert_eq!(session.connection_errors, vec![ErrorCode::ProtocolError]);
+        }
+    }
+
+    /// Tests that the settings of the peer and our own are tracked separately, ours taking effect
+    /// only once acknowledged.
+    #[test]
+    fn test_conn_settings_per_direction() {
+        let mut peer = SettingsFrame::new();
+        peer.add_setting(HttpSetting::MaxFrameSize(32_768));
+        let frames = vec![
+            HttpFrame::SettingsFrame(peer),
+            HttpFrame::SettingsFrame(SettingsFrame::new_ack()),
+        ];
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        let mut frame_provider = MockReceiveFrame::new(frames);
+        let mut session = TestSession::new();
+        let mut sender = MockSendFrame::new();
+
+        conn.sender(&mut sender).send_settings(&[HttpSetting::InitialWindowSize(1 << 20)]).unwrap();
+        assert_eq!(conn.local_settings().pending_acks(), 1);
+        assert_eq!(conn.local_settings().advertised().initial_window_size, 1 << 20);
+
+        conn.expect_settings(&mut frame_provider, &mut session).unwrap();
+        assert_eq!(conn.peer_settings().max_frame_size, 32_768);
+        // The peer's settings do not affect ours...
+        assert_eq!(conn.local_settings().acked().max_frame_size, 16_384);
+        assert_eq!(conn.local_settings().acked().initial_window_size, 65_535);
+        // ...which apply once the peer acknowledges them.
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        assert_eq!(conn.local_settings().pending_acks(), 0);
+        assert_eq!(conn.local_settings().acked().initial_window_size, 1 << 20);
+        assert_eq!(conn.peer_settings().initial_window_size, 65_535);
+    }
+
+    /// Tests that a header block that does not fit into a single frame is sent as a HEADERS
+    /// frame followed by CONTINUATION frames, and that the receiving end reassembles it.
+    #[test]
+    fn test_conn_large_header_block() {
+        let big_value = vec![b'a'; 70 * 1024];
+        let headers = vec![
+            Header::new(b":method".to_vec(), b"GET".to_vec()),
+            Header::new(b"authorization".to_vec(), big_value.clone()),
+        ];
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        conn.sender(&mut sender).send_headers(headers.clone(), 1, EndStream::Yes).unwrap();
+
+        // 70 KiB of headers need 5 frames of at most 16 KiB.
+        assert_eq!(sender.sent.len(), 5);
+        let frames: Vec<_> = sender.sent.iter().map(|raw| {
+            assert!(raw.header().0 <= 16_384);
+            HttpFrame::from_raw(raw).unwrap()
+        }).collect();
+        match frames[0] {
+            HttpFrame::HeadersFrame(ref frame) => {
+                assert!(!frame.is_headers_end());
+                assert!(frame.is_end_of_stream());
+            },
+            _ => panic!("Expected a HEADERS frame"),
+        }
+        for (i, frame) in frames[1..].iter().enumerate() {
+            match *frame {
+                HttpFrame::ContinuationFrame(ref frame) => {
+                    assert_eq!(frame.get_stream_id(), 1);
+                    assert_eq!(frame.is_headers_end(), i == 3);
+                },
+                _ => panic!("Expected a CONTINUATION frame"),
+            }
+        }
+
+        // The receiving end reassembles the header block before passing it on to the session.
+        let expected: Vec<OwnedHeader> = headers.into_iter().map(|h| h.into()).collect();
+        let mut session = TestSession::new_verify(vec![expected], vec![]);
+        let mut receiver = HttpConnection::new(HttpScheme::Http);
+        let mut frame_provider = MockReceiveFrame::new(frames);
+        for _ in 0..4 {
+            receiver.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+            assert_eq!(session.curr_header, 0);
+        }
+        receiver.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        assert_eq!(session.curr_header, 1);
+    }
+
+    /// Tests that a large header block sent along with priority information leaves room for the
+    /// priority fields in the HEADERS frame, so that no frame exceeds the peer's maximum size.
+    #[test]
+    fn test_conn_large_header_block_with_priority() {
+        let headers = vec![
+            Header::new(b":method".to_vec(), b"GET".to_vec()),
+            Header::new(b"cookie".to_vec(), vec![b'c'; 20 * 1024]),
+        ];
+        let dep = StreamDependency::new(0, 15, false);
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        conn.sender(&mut sender).send_headers_with_priority(
+            headers.clone(), 1, EndStream::No, Some(dep.clone())).unwrap();
+
+        assert_eq!(sender.sent.len(), 2);
+        assert_eq!(sender.sent[0].header().0, 16_384);
+        assert!(sender.sent[1].header().0 <= 16_384);
+        let frames: Vec<_> = sender.sent.iter().map(|raw| HttpFrame::from_raw(raw).unwrap())
+                                              .collect();
+        match frames[0] {
+            HttpFrame::HeadersFrame(ref frame) => {
+                assert_eq!(frame.stream_dep, Some(dep.clone()));
+                assert!(!frame.is_headers_end());
+            },
+            _ => panic!("Expected a HEADERS frame"),
+        }
+
+        let expected: Vec<OwnedHeader> = headers.into_iter().map(|h| h.into()).collect();
+        let mut session = TestSession::new_verify(vec![expected], vec![]);
+        let mut receiver = HttpConnection::new(HttpScheme::Http);
+        let mut frame_provider = MockReceiveFrame::new(frames);
+        receiver.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        receiver.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        assert_eq!(session.curr_header, 1);
+        assert_eq!(session.priorities, vec![(1, dep)]);
+    }
+
+    /// Tests that the priority information given to `send_headers_with_priority` is sent in the
+    /// HEADERS frame and passed on to the receiving session ahead of the headers.
+    #[test]
+    fn test_conn_headers_with_priority() {
+        let dep = StreamDependency::new(3, 200, true);
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        conn.sender(&mut sender).send_headers_with_priority(
+            vec![Header::new(b":method", b"GET")], 5, EndStream::Yes, Some(dep.clone())).unwrap();
+        let frame = match HttpFrame::from_raw(&sender.sent[0]).unwrap() {
+            HttpFrame::HeadersFrame(frame) => frame,
+            _ => panic!("Expected a HEADERS frame"),
+        };
+        assert_eq!(frame.stream_dep, Some(dep.clone()));
+
+        let mut session = TestSession::new();
+        let mut receiver = HttpConnection::new(HttpScheme::Http);
+        let mut frame_provider = MockReceiveFrame::new(vec![HttpFrame::HeadersFrame(frame)]);
+        receiver.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+
+        assert_eq!(session.priorities, vec![(5, dep)]);
+        assert_eq!(session.curr_header, 1);
+    }
+
+    /// Tests that a HEADERS frame making its stream depend on itself is a stream error.
+    #[test]
+    fn test_conn_headers_self_dependency() {
+        let fragment = hpack::Encoder::new().encode(vec![(&b":method"[..], &b"GET"[..])]);
+        let mut frame = HeadersFrame::with_dependency(fragment,
+                                                      1,
+                                                      StreamDependency::new(1, 0, false));
+        frame.set_flag(HeadersFlag::EndHeaders);
+        let mut session = TestSession::new();
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        let mut frame_provider = MockReceiveFrame::new(vec![HttpFrame::HeadersFrame(frame)]);
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+
+        assert_eq!(session.stream_errors, vec![(1, ErrorCode::ProtocolError)]);
+        assert!(session.priorities.is_empty());
+        assert_eq!(session.curr_header, 0);
+    }
+
+    /// Tests that the padding and the stream dependency of a received HEADERS frame are stripped
+    /// from its header block, the dependency being passed on to the session, and that a frame
+    /// whose padding is too long is a connection error of type PROTOCOL_ERROR (h2spec 6.2/2).
+    #[test]
+    fn test_conn_headers_padded_with_priority() {
+        fn receive(raw: Vec<u8>) -> (HttpResult<()>, TestSession) {
+            let mut stream = StubTransportStream::with_stub_content(&raw);
+            let mut conn = HttpConnection::new(HttpScheme::Http);
+            let mut session = TestSession::new();
+            let res = conn.handle_next_frame(&mut TransportReceiveFrame::new(&mut stream),
+                                             &mut session);
+            (res, session)
+        }
+        let dep = StreamDependency::new(0, 31, true);
+        let fragment = hpack::Encoder::new().encode(vec![(&b":method"[..], &b"GET"[..])]);
+        let mut frame = HeadersFrame::with_dependency(fragment, 1, dep.clone());
+        frame.set_flag(HeadersFlag::EndHeaders);
+        frame.set_padding(7);
+        let (res, session) = receive(serialize_frame(&frame));
+        res.unwrap();
+        assert_eq!(session.priorities, vec![(1, dep)]);
+        assert_eq!(session.curr_header, 1);
+
+        let mut raw = pack_header(&(5, 0x1, 0x8 | 0x4, 1)).to_vec();
+        raw.extend_from_slice(&[5, 0x82, 0, 0, 0]);
+        let (res, session) = receive(raw);
+        assert_eq!(res.unwrap_err(), HttpError::LocalConnectionError(ErrorCode::ProtocolError));
+        assert_eq!(session.connection_errors, vec![ErrorCode::ProtocolError]);
+        assert_eq!(session.curr_header, 0);
+    }
+
+    /// Tests that PRIORITY frames are passed on to the session regardless of the state of their
+    /// stream, that a stream depending on itself or a frame of the wrong length is a stream error
+    /// and that one on stream 0 is a connection error.
+    #[test]
+    fn test_conn_priority() {
+        fn raw_priority(stream_id: StreamId, payload: &[u8]) -> RawFrame<'static> {
+            let mut raw = pack_header(&(payload.len() as u32, 0x2, 0, stream_id)).to_vec();
+            raw.extend_from_slice(payload);
+            RawFrame::from(raw)
+        }
+        let dep = StreamDependency::new(1, 20, true);
+        let too_short = raw_priority(9, &[0, 0, 0, 1]);
+        let mut session = TestSession::new();
+        session.stream_states.insert(3, StreamState::Closed);
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        let mut frame_provider = MockReceiveFrame::new(vec![
+            // An idle stream...
+            HttpFrame::PriorityFrame(PriorityFrame::new(5, dep.clone())),
+            // ...a closed one...
+            HttpFrame::PriorityFrame(PriorityFrame::new(3, dep.clone())),
+            // ...one that depends on itself...
+            HttpFrame::PriorityFrame(PriorityFrame::new(7, StreamDependency::new(7, 1, false))),
+            // ...and one that is too short.
+            HttpFrame::from_raw(&too_short).unwrap(),
+        ]);
+        for _ in 0..4 {
+            conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        }
+        assert_eq!(session.priorities, vec![(5, dep.clone()), (3, dep)]);
+        assert_eq!(session.stream_errors,
+                   vec![(7, ErrorCode::ProtocolError), (9, ErrorCode::FrameSizeError)]);
+        assert!(session.connection_errors.is_empty());
+
+        match HttpFrame::from_raw(&raw_priority(0, &[0, 0, 0, 1, 20])) {
+            Err(HttpError::LocalConnectionError(ErrorCode::ProtocolError)) => {},
+            other => panic!("Expected a PROTOCOL_ERROR, got {:?}", other),
+        }
+    }
+
+    /// Tests that the PUSH_PROMISE frames are passed on to the session, with or without padding,
+    /// and that a frame too short to carry the promised stream ID is invalid.
+    #[test]
+    fn test_conn_push_promise() {
+        fn push_promise(flags: u8, stream_id: StreamId, payload: &[u8]) -> HttpFrame<'static> {
+            let mut raw = pack_header(&(payload.len() as u32, 0x5, flags, stream_id)).to_vec();
+            raw.extend_from_slice(payload);
+            HttpFrame::UnknownFrame(RawFrame::from(raw))
+        }
+        let mut session = TestSession::new();
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        let mut frame_provider = MockReceiveFrame::new(vec![
+            push_promise(0x4 | 0x8, 1, &[2, 0, 0, 0, 4, 0x82, 0, 0]),
+            push_promise(0x4, 3, &[0x80, 0, 0, 2, 0x82]),
+            push_promise(0x4, 5, &[0, 0, 6]),
+        ]);
+
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        assert_eq!(conn.handle_next_frame(&mut frame_provider, &mut session).err().unwrap(),
+                   HttpError::InvalidFrame);
+        assert_eq!(session.push_promises, vec![(1, 4), (3, 2)]);
+    }
+
+    /// Tests that the ALTSVC frames are passed on to the session, except for the ones that the
+    /// RFC requires to be ignored and the malformed ones.
+    #[test]
+    fn test_conn_alt_svc() {
+        let malformed = {
+            let mut raw = pack_header(&(3, 0xa, 0, 0)).to_vec();
+            raw.extend_from_slice(&[0, 5, b'a']);
+            RawFrame::from(raw)
+        };
+        let mut session = TestSession::new();
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        let mut frame_provider = MockReceiveFrame::new(vec![
+            HttpFrame::AltSvcFrame(AltSvcFrame::for_origin(b"example.com", b"h2=\":8000\"")),
+            HttpFrame::AltSvcFrame(AltSvcFrame::for_stream(1, b"clear")),
+            // No origin on stream 0...
+            HttpFrame::AltSvcFrame(AltSvcFrame::for_origin(b"", b"clear")),
+            // ...and a malformed frame.
+            HttpFrame::from_raw(&malformed).unwrap(),
+        ]);
+        for _ in 0..4 {
+            conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        }
+        assert_eq!(session.alt_svcs, vec![
+            (0, b"example.com".to_vec(), b"h2=\":8000\"".to_vec()),
+            (1, vec![], b"clear".to_vec()),
+        ]);
+    }
+
+    /// Tests that the header block of a PUSH_PROMISE frame can continue in CONTINUATION frames and
+    /// that it is decoded once complete, keeping the decoder in sync for the header blocks that
+    /// follow it.
+    #[test]
+    fn test_conn_push_promise_continuation() {
+        let mut encoder = hpack::Encoder::new();
+        let promised = encoder.encode(vec![(&b":path"[..], &b"/pushed"[..]),
+                                           (&b"x-pushed"[..], &b"yes"[..])]);
+        // The same header again, which the encoder now takes from its dynamic table.
+        let response = encoder.encode(vec![(&b"x-pushed"[..], &b"yes"[..])]);
+        assert!(response.len() < 3);
+        let mut payload = vec![0, 0, 0, 2];
+        payload.extend_from_slice(&promised[..3]);
+        let mut raw = pack_header(&(payload.len() as u32, 0x5, 0, 1)).to_vec();
+        raw.extend(payload);
+        let mut continuation = ContinuationFrame::new(promised[3..].to_vec(), 1);
+        continuation.set_flag(ContinuationFlag::EndHeaders);
+
+        let expected = vec![(b"x-pushed".to_vec(), b"yes".to_vec())];
+        let mut session = TestSession::new_verify(vec![expected], vec![]);
+        let mut conn = HttpConnection::new(HttpScheme::Http);
+        let mut frame_provider = MockReceiveFrame::new(vec![
+            HttpFrame::UnknownFrame(RawFrame::from(raw)),
+            HttpFrame::ContinuationFrame(continuation),
+            HttpFrame::HeadersFrame(complete_headers(response, 1)),
+        ]);
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        assert!(session.push_promises.is_empty());
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        assert_eq!(session.push_promises, vec![(1, 2)]);
+        conn.handle_next_frame(&mut frame_provider, &mut session).unwrap();
+        assert_eq!(session.curr_header, 1);
+    }
+
+    /// Tests that any frame other than a CONTINUATION on the same stream received in the middle
+    /// of a header block, as well as a CONTINUATION frame outside of a header block, is a
+    /// connection error.
+    #[test]
+    fn test_conn_continuation_protocol_error() {
+        fn handle(frames: Vec<HttpFrame>) -> (HttpResult<()>, TestSession) {
+            let mut conn = HttpConnection::new(HttpScheme::Http);
+            let mut session = TestSession::new();
+            let mut frame_provider = MockReceiveFrame::new(frames);
+            let mut res = Ok(());
+            while !frame_provider.recv_list.is_empty() && res.is_ok() {
+                res = conn.handle_next_frame(&mut frame_provider, &mut session);
+            }
+            (res, session)
+        }
+        let protocol_error = HttpError::LocalConnectionError(ErrorCode::ProtocolError);
+
+        {
+            // A DATA frame interleaved with the header block
+            let (res, session) = handle(vec![
+                HttpFrame::HeadersFrame(HeadersFrame::new(vec![], 1)),
+                HttpFrame::DataFrame(DataFrame::new(1)),
+            ]);
+            assert_eq!(res.err().unwrap(), protocol_error);
+            assert_eq!(session.connection_errors, vec![ErrorCode::ProtocolError]);
+            assert_eq!(session.curr_header, 0);
+        }
+        {
+            // A CONTINUATION on a different stream
+            let mut continuation = ContinuationFrame::new(vec![], 3);
+            continuation.set_flag(ContinuationFlag::EndHeaders);
+            let (res, session) = handle(vec![
+                HttpFrame::HeadersFrame(HeadersFrame::new(vec![], 1)),
+                HttpFrame::ContinuationFrame(continuation),
+            ]);
+            assert_eq!(res.err().unwrap(), protocol_error);
+            assert_eq!(session.curr_header, 0);
+        }
+        {
+            // A CONTINUATION without a preceding HEADERS frame
+            let (res, _) = handle(vec![
+                HttpFrame::ContinuationFrame(ContinuationFrame::new(vec![], 1)),
+            ]);
+            assert_eq!(res.err().unwrap(), protocol_error);
+        }
+    }
 }
@@ -0,0 +1,225 @@
+//! The module implements an in-memory duplex transport, which connects two endpoints within the
+//! same process without any sockets.
+//!
+//! The two ends of a `pipe` are `TransportStream`s: whatever is written to one end can be read
+//! from the other. Reads block until the peer writes something, the pipe is closed, or the read
+//! timeout elapses. The timeouts are measured in virtual time, which only passes when
+//! `DuplexStream::advance_time` is called, so that tests can exercise timeouts (such as the idle
+//! timeout and keepalive of a server) deterministically and without sleeping.
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard, Condvar};
+use std::time::Duration;
+
+use http::transport::TransportStream;
+
+/// The state shared by both ends of a pipe.
+#[derive(Default)]
+struct Pipe {
+    /// The bytes that are yet to be read by each end.
+    buffers: [VecDeque<u8>; 2],
+    /// The read timeouts of the two ends.
+    timeouts: [Option<Duration>; 2],
+    /// Whether each end is blocked in a read, waiting for bytes.
+    blocked: [bool; 2],
+    /// Whether the pipe was closed, by either end.
+    closed: bool,
+    /// The virtual time that has passed since the pipe was created.
+    now: Duration,
+}
+
+/// One end of an in-memory duplex pipe (see `pipe`).
+///
+/// Handles created by `try_split` refer to the same end. Closing any handle (of either end)
+/// closes the whole pipe: reads then return an end of file once the remaining bytes are read,
+/// while writes fail with a `BrokenPipe` error.
+pub struct DuplexStream {
+    shared: Arc<(Mutex<Pipe>, Condvar)>,
+    /// The index of the end, which is also the index of the buffer that it reads from.
+    side: usize,
+}
+
+/// Creates a new in-memory duplex pipe, returning its two ends.
+pub fn pipe() -> (DuplexStream, DuplexStream) {
+    let shared = Arc::new((Mutex::new(Pipe::default()), Condvar::new()));
+    let first = DuplexStream { shared: shared.clone(), side: 0 };
+    let second = DuplexStream { shared: shared, side: 1 };
+    (first, second)
+}
+
+impl DuplexStream {
+    fn lock(&self) -> MutexGuard<Pipe> {
+        // A panic while the lock is held cannot leave the pipe inconsistent.
+        self.shared.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Advances the virtual time of the pipe by the given duration, timing out the reads (of
+    /// either end) whose timeout elapses in the meantime.
+    pub fn advance_time(&self, duration: Duration) {
+        self.lock().now += duration;
+        self.shared.1.notify_all();
+    }
+
+    /// Blocks until the other end of the pipe is blocked in a read, i.e. until the peer has
+    /// processed everything sent to it so far and waits for more.
+    ///
+    /// This makes it possible to advance the virtual time only once the peer is idle, so that
+    /// its read times out deterministically.
+    pub fn wait_for_peer_read(&self) {
+        let mut pipe = self.lock();
+        while !pipe.blocked[1 - self.side] {
+            pipe = self.shared.1.wait(pipe).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Returns the virtual time that has passed since the pipe was created.
+    pub fn elapsed(&self) -> Duration {
+        self.lock().now
+    }
+
+    /// Returns whether the pipe is closed.
+    pub fn is_closed(&self) -> bool {
+        self.lock().closed
+    }
+}
+
+impl Read for DuplexStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut pipe = self.lock();
+        let deadline = pipe.timeouts[self.side].map(|timeout| pipe.now + timeout);
+        loop {
+            if !pipe.buffers[self.side].is_empty() {
+                let len = cmp::min(buf.len(), pipe.buffers[self.side].len());
+                for (dst, src) in buf.iter_mut().zip(pipe.buffers[self.side].drain(..len)) {
+                    *dst = src;
+                }
+                return Ok(len);
+            }
+            if pipe.closed {
+                return Ok(0);
+            }
+            if deadline.map_or(false, |deadline| pipe.now >= deadline) {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "Read timed out"));
+            }
+            pipe.blocked[self.side] = true;
+            self.shared.1.notify_all();
+            pipe = self.shared.1.wait(pipe).unwrap_or_else(|e| e.into_inner());
+            pipe.blocked[self.side] = false;
+        }
+    }
+}
+
+impl Write for DuplexStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        {
+            let mut pipe = self.lock();
+            if pipe.closed {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "The pipe is closed"));
+            }
+            pipe.buffers[1 - self.side].extend(buf.iter().cloned());
+        }
+        self.shared.1.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl TransportStream for DuplexStream {
+    fn try_split(&self) -> Result<DuplexStream, io::Error> {
+        Ok(DuplexStream {
+            shared: self.shared.clone(),
+            side: self.side,
+        })
+    }
+
+    fn close(&mut self) -> Result<(), io::Error> {
+        self.lock().closed = true;
+        self.shared.1.notify_all();
+        Ok(())
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> Result<(), io::Error> {
+        self.lock().timeouts[self.side] = dur;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::pipe;
+    use http::transport::TransportStream;
+
+    /// Tests that the bytes written to one end are read from the other, in order.
+    #[test]
+    fn test_pipe_read_write() {
+        let (mut a, mut b) = pipe();
+        a.write_all(b"ping").unwrap();
+        b.write_all(b"pong").unwrap();
+
+        let mut buf = [0; 3];
+        assert_eq!(b.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"pin");
+        assert_eq!(b.read(&mut buf).unwrap(), 1);
+        assert_eq!(&buf[..1], b"g");
+        let mut buf = [0; 10];
+        assert_eq!(a.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"pong");
+    }
+
+    /// Tests that a blocked read returns once the peer writes.
+    #[test]
+    fn test_pipe_blocking_read() {
+        let (mut a, mut b) = pipe();
+        let reader = thread::spawn(move || {
+            let mut buf = [0; 5];
+            Read::read_exact(&mut b, &mut buf).unwrap();
+            buf
+        });
+        a.write_all(b"hello").unwrap();
+
+        assert_eq!(&reader.join().unwrap(), b"hello");
+    }
+
+    /// Tests that reads time out only once enough virtual time passes.
+    #[test]
+    fn test_pipe_virtual_timeout() {
+        let (a, mut b) = pipe();
+        b.set_read_timeout(Some(Duration::from_secs(30))).unwrap();
+        let reader = thread::spawn(move || {
+            let mut buf = [0; 1];
+            b.read(&mut buf).unwrap_err().kind()
+        });
+        a.wait_for_peer_read();
+        a.advance_time(Duration::from_secs(10));
+        a.advance_time(Duration::from_secs(20));
+
+        assert_eq!(reader.join().unwrap(), io::ErrorKind::TimedOut);
+        assert_eq!(a.elapsed(), Duration::from_secs(30));
+    }
+
+    /// Tests that closing the pipe ends reads with an end of file, after the buffered bytes, and
+    /// fails any further writes.
+    #[test]
+    fn test_pipe_close() {
+        let (mut a, mut b) = pipe();
+        a.write_all(b"x").unwrap();
+        a.try_split().unwrap().close().unwrap();
+
+        let mut buf = [0; 2];
+        assert_eq!(b.read(&mut buf).unwrap(), 1);
+        assert_eq!(b.read(&mut buf).unwrap(), 0);
+        assert!(b.write(b"y").is_err());
+        assert!(a.is_closed());
+    }
+}
@@ -0,0 +1,446 @@
+//! The module implements a `TransportStream` wrapper that injects faults into the bytes flowing
+//! through it, so that the robustness of connections and of the client and server loops can be
+//! tested without a real (and unreliable) network.
+//!
+//! A `FaultyTransport` is scripted with a list of `Fault`s, which can be given upfront or added
+//! while the transport is in use: writes can be cut short, reads delayed, the connection dropped
+//! once a number of bytes went through it, and single bytes of chosen frames corrupted. Handles
+//! created by `try_split` share the script and the byte counts, so that a client or server that
+//! splits its transport still sees the faults exactly once.
+//!
+//! ```rust
+//! use solicit::http::duplex;
+//! use solicit::http::fault::{FaultyTransport, Fault};
+//! use solicit::http::transport::Direction;
+//!
+//! let (client, _server) = duplex::pipe();
+//! // Flip the type of the second frame that the client sends.
+//! let client = FaultyTransport::new(client)
+//!     .with_preface(Direction::Write)
+//!     .with_fault(Fault::CorruptFrame {
+//!         direction: Direction::Write,
+//!         frame: 1,
+//!         offset: 3,
+//!         mask: 0xff,
+//!     });
+//! # let _ = client;
+//! ```
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use http::CLIENT_PREFACE;
+use http::frame::{FRAME_HEADER_LEN, unpack_header};
+use http::transport::{TransportStream, Direction};
+
+/// A fault that a `FaultyTransport` injects.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Fault {
+    /// Every write accepts at most the given number of bytes, forcing the writer to deal with
+    /// short writes.
+    TruncateWrites(usize),
+    /// Every read sleeps for the given duration before reading anything.
+    DelayReads(Duration),
+    /// The connection is dropped once the given number of bytes went through it in the given
+    /// direction. The transport is closed and any further reads or writes fail with a
+    /// `ConnectionReset` error.
+    DropAfter(Direction, usize),
+    /// The byte at the given offset of a frame is XORed with the mask. Frames are counted from
+    /// zero, in the given direction, and the offset counts from the start of the frame header.
+    CorruptFrame {
+        direction: Direction,
+        frame: usize,
+        offset: usize,
+        mask: u8,
+    },
+}
+
+/// Follows the frame boundaries of the bytes flowing in one direction.
+#[derive(Default)]
+struct FrameTracker {
+    /// The number of bytes of the connection preface that are yet to go through.
+    preface_left: usize,
+    /// The header of the current frame, as far as it went through.
+    header: [u8; FRAME_HEADER_LEN],
+    /// The offset of the next byte within the current frame.
+    offset: usize,
+    /// The full length of the current frame, once its header went through.
+    frame_len: usize,
+    /// The index of the current frame.
+    frame: usize,
+}
+
+impl FrameTracker {
+    /// Moves the tracker past the given bytes, applying the corruptions that hit any of them.
+    ///
+    /// The frame boundaries are always taken from the original bytes, so that corrupting the
+    /// length of a frame does not shift the frames that the later corruptions refer to.
+    fn track(&mut self, bytes: &mut [u8], corruptions: &[(usize, usize, u8)]) {
+        for byte in bytes.iter_mut() {
+            if self.preface_left > 0 {
+                self.preface_left -= 1;
+                continue;
+            }
+            if self.offset < FRAME_HEADER_LEN {
+                self.header[self.offset] = *byte;
+            }
+            for &(frame, offset, mask) in corruptions {
+                if frame == self.frame && offset == self.offset {
+                    *byte ^= mask;
+                }
+            }
+            self.offset += 1;
+            if self.offset == FRAME_HEADER_LEN {
+                let (len, _, _, _) = unpack_header(&self.header);
+                self.frame_len = FRAME_HEADER_LEN + len as usize;
+            }
+            if self.offset >= FRAME_HEADER_LEN && self.offset == self.frame_len {
+                self.frame += 1;
+                self.offset = 0;
+            }
+        }
+    }
+}
+
+/// The state shared by all handles of a `FaultyTransport`.
+#[derive(Default)]
+struct Script {
+    faults: Vec<Fault>,
+    /// The number of bytes that went through in each direction (read, then written).
+    bytes: [usize; 2],
+    /// The frame trackers of each direction.
+    frames: [FrameTracker; 2],
+    /// Whether the connection was dropped.
+    dropped: bool,
+}
+
+/// Returns the index of the given direction into the per-direction state.
+fn index(direction: Direction) -> usize {
+    match direction {
+        Direction::Read => 0,
+        Direction::Write => 1,
+    }
+}
+
+impl Script {
+    /// Returns the number of bytes that may still go through in the given direction before the
+    /// connection is dropped.
+    fn remaining(&self, direction: Direction) -> Option<usize> {
+        let done = self.bytes[index(direction)];
+        self.faults.iter().filter_map(|fault| {
+            match *fault {
+                Fault::DropAfter(dir, limit) if dir == direction => {
+                    Some(limit.saturating_sub(done))
+                },
+                _ => None,
+            }
+        }).min()
+    }
+
+    /// Returns the largest number of bytes that a single write may accept.
+    fn max_write(&self) -> Option<usize> {
+        self.faults.iter().filter_map(|fault| {
+            match *fault {
+                Fault::TruncateWrites(max) => Some(max),
+                _ => None,
+            }
+        }).min()
+    }
+
+    /// Returns the total delay of every read.
+    fn read_delay(&self) -> Duration {
+        self.faults.iter().fold(Duration::from_secs(0), |total, fault| {
+            match *fault {
+                Fault::DelayReads(delay) => total + delay,
+                _ => total,
+            }
+        })
+    }
+
+    /// Moves the given direction past the bytes, corrupting them as scripted.
+    fn track(&mut self, direction: Direction, bytes: &mut [u8]) {
+        let corruptions: Vec<_> = self.faults.iter().filter_map(|fault| {
+            match *fault {
+                Fault::CorruptFrame { direction: dir, frame, offset, mask } if dir == direction => {
+                    Some((frame, offset, mask))
+                },
+                _ => None,
+            }
+        }).collect();
+        self.frames[index(direction)].track(bytes, &corruptions);
+        self.bytes[index(direction)] += bytes.len();
+    }
+}
+
+/// A `TransportStream` that wraps another one and injects the scripted `Fault`s into the bytes
+/// that flow through it.
+pub struct FaultyTransport<T> where T: TransportStream {
+    inner: T,
+    script: Arc<Mutex<Script>>,
+}
+
+impl<T> FaultyTransport<T> where T: TransportStream {
+    /// Wraps the given transport, initially without any faults.
+    pub fn new(inner: T) -> FaultyTransport<T> {
+        FaultyTransport {
+            inner: inner,
+            script: Arc::new(Mutex::new(Script::default())),
+        }
+    }
+
+    /// Adds the given fault to the script.
+    pub fn with_fault(self, fault: Fault) -> FaultyTransport<T> {
+        self.add_fault(fault);
+        self
+    }
+
+    /// Declares that the bytes in the given direction start with the client connection preface,
+    /// which is then not counted as a frame by `Fault::CorruptFrame`. This is the case for the
+    /// bytes that a client writes and that a server reads.
+    pub fn with_preface(self, direction: Direction) -> FaultyTransport<T> {
+        self.lock().frames[index(direction)].preface_left = CLIENT_PREFACE.len();
+        self
+    }
+
+    /// Adds the given fault to the script, taking effect from the next read or write on any
+    /// handle of the transport.
+    pub fn add_fault(&self, fault: Fault) {
+        self.lock().faults.push(fault);
+    }
+
+    /// Removes all faults from the script. A connection that was already dropped stays so.
+    pub fn clear_faults(&self) {
+        self.lock().faults.clear();
+    }
+
+    /// Returns the number of bytes that went through the transport in the given direction.
+    pub fn byte_count(&self, direction: Direction) -> usize {
+        self.lock().bytes[index(direction)]
+    }
+
+    /// Returns whether the connection was dropped by a `Fault::DropAfter`.
+    pub fn is_dropped(&self) -> bool {
+        self.lock().dropped
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    fn lock(&self) -> MutexGuard<Script> {
+        self.script.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Drops the connection, if it has not been already.
+    fn drop_connection(&mut self) {
+        let mut script = self.script.lock().unwrap_or_else(|e| e.into_inner());
+        if !script.dropped {
+            debug!("Injecting a dropped connection");
+            script.dropped = true;
+            let _ = self.inner.close();
+        }
+    }
+}
+
+/// The error that all IO on a dropped connection fails with.
+fn dropped_error() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionReset, "The connection was dropped")
+}
+
+impl<T> Read for FaultyTransport<T> where T: TransportStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (delay, remaining) = {
+            let script = self.lock();
+            if script.dropped {
+                return Err(dropped_error());
+            }
+            (script.read_delay(), script.remaining(Direction::Read))
+        };
+        if delay > Duration::from_secs(0) {
+            thread::sleep(delay);
+        }
+        let len = cmp::min(buf.len(), remaining.unwrap_or(buf.len()));
+        if len == 0 && !buf.is_empty() {
+            self.drop_connection();
+            return Err(dropped_error());
+        }
+
+        let read = try!(self.inner.read(&mut buf[..len]));
+        self.lock().track(Direction::Read, &mut buf[..read]);
+        if remaining == Some(read) && read > 0 {
+            self.drop_connection();
+        }
+        Ok(read)
+    }
+}
+
+impl<T> Write for FaultyTransport<T> where T: TransportStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut bytes = {
+            let mut script = self.lock();
+            if script.dropped {
+                return Err(dropped_error());
+            }
+            let mut len = buf.len();
+            if let Some(max) = script.max_write() {
+                len = cmp::min(len, max);
+            }
+            if let Some(remaining) = script.remaining(Direction::Write) {
+                len = cmp::min(len, remaining);
+            }
+            let mut bytes = buf[..len].to_vec();
+            script.track(Direction::Write, &mut bytes);
+            bytes
+        };
+        if bytes.is_empty() && !buf.is_empty() {
+            self.drop_connection();
+            return Err(dropped_error());
+        }
+
+        // The bytes were already counted, so they need to be written out in full.
+        try!(self.inner.write_all(&bytes));
+        if self.lock().remaining(Direction::Write) == Some(0) {
+            self.drop_connection();
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.lock().dropped {
+            return Err(dropped_error());
+        }
+        self.inner.flush()
+    }
+}
+
+impl<T> TransportStream for FaultyTransport<T> where T: TransportStream {
+    fn try_split(&self) -> Result<FaultyTransport<T>, io::Error> {
+        Ok(FaultyTransport {
+            inner: try!(self.inner.try_split()),
+            script: self.script.clone(),
+        })
+    }
+
+    fn close(&mut self) -> Result<(), io::Error> {
+        self.inner.close()
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> Result<(), io::Error> {
+        self.inner.set_read_timeout(dur)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::time::{Duration, Instant};
+
+    use super::{FaultyTransport, Fault};
+    use http::CLIENT_PREFACE;
+    use http::duplex::{pipe, DuplexStream};
+    use http::frame::{PingFrame, RawFrame};
+    use http::connection::SendFrame;
+    use http::transport::{TransportStream, Direction};
+    use http::tests::common::{serialize_frame, parse_frames};
+
+    /// Reads everything that is buffered on the given end of a pipe, after closing it.
+    fn read_rest(mut stream: DuplexStream) -> Vec<u8> {
+        stream.close().unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    /// Tests that writes are cut short, while every byte still makes it through.
+    #[test]
+    fn test_faulty_truncate_writes() {
+        let (a, b) = pipe();
+        let mut a = FaultyTransport::new(a).with_fault(Fault::TruncateWrites(3));
+
+        assert_eq!(a.write(b"hello").unwrap(), 3);
+        a.write_all(b"lo world").unwrap();
+
+        assert_eq!(a.byte_count(Direction::Write), 11);
+        assert_eq!(read_rest(b), b"hello world".to_vec());
+    }
+
+    /// Tests that reads are delayed.
+    #[test]
+    fn test_faulty_delay_reads() {
+        let (mut a, b) = pipe();
+        let delay = Duration::from_millis(20);
+        let mut b = FaultyTransport::new(b).with_fault(Fault::DelayReads(delay));
+        a.write_all(b"x").unwrap();
+
+        let start = Instant::now();
+        let mut buf = [0; 1];
+        assert_eq!(b.read(&mut buf).unwrap(), 1);
+        assert!(start.elapsed() >= delay);
+    }
+
+    /// Tests that the connection is dropped once the given number of bytes were written, failing
+    /// any further IO on all handles, while the peer sees the connection closed.
+    #[test]
+    fn test_faulty_drop_after_write() {
+        let (a, b) = pipe();
+        let mut a = FaultyTransport::new(a).with_fault(Fault::DropAfter(Direction::Write, 4));
+        let mut other = a.try_split().unwrap();
+
+        let err = a.write_all(b"abcdefgh").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert!(a.is_dropped());
+        assert!(other.write(b"x").is_err());
+        assert!(other.read(&mut [0; 1]).is_err());
+        assert!(a.get_ref().is_closed());
+        assert_eq!(read_rest(b), b"abcd".to_vec());
+    }
+
+    /// Tests that the connection is dropped once the given number of bytes were read.
+    #[test]
+    fn test_faulty_drop_after_read() {
+        let (mut a, b) = pipe();
+        let mut b = FaultyTransport::new(b).with_fault(Fault::DropAfter(Direction::Read, 2));
+        a.write_all(b"abc").unwrap();
+
+        let mut buf = [0; 3];
+        assert_eq!(b.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ab");
+        assert_eq!(b.read(&mut buf).unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+    }
+
+    /// Tests that only the scripted byte of the scripted frame is corrupted, not counting the
+    /// preface as a frame.
+    #[test]
+    fn test_faulty_corrupt_frame() {
+        let (a, b) = pipe();
+        let mut a = FaultyTransport::new(a)
+            .with_preface(Direction::Write)
+            .with_fault(Fault::CorruptFrame {
+                direction: Direction::Write,
+                frame: 1,
+                offset: 9,
+                mask: 0xff,
+            });
+        let first = PingFrame::new(1);
+        let second = PingFrame::new(2);
+
+        a.write_all(CLIENT_PREFACE).unwrap();
+        // The frames are written byte by byte, to check that the boundaries are followed
+        // across writes.
+        a.add_fault(Fault::TruncateWrites(1));
+        a.send_frame(first.clone()).unwrap();
+        a.send_frame(second.clone()).unwrap();
+
+        let written = read_rest(b);
+        assert_eq!(&written[..CLIENT_PREFACE.len()], CLIENT_PREFACE);
+        let frames = parse_frames(&written[CLIENT_PREFACE.len()..]);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0], RawFrame::from(serialize_frame(&first)));
+        let mut expected = serialize_frame(&second);
+        expected[9] ^= 0xff;
+        assert_eq!(frames[1], RawFrame::from(expected));
+    }
+}
@@ -0,0 +1,530 @@
+//! The module contains the strategies that an `HttpConnection` can use to manage its inbound flow
+//! control windows, i.e. how much data the peer is allowed to send before it needs to wait for a
+//! WINDOW_UPDATE.
+//!
+//! By default (`FlowControlStrategy::Manual`) the connection never increases the inbound windows
+//! on its own. With `FlowControlStrategy::Adaptive`, the windows are replenished as data is
+//! received and grown according to an estimate of the bandwidth-delay product (BDP) of the link,
+//! obtained by timing PING round trips, much like gRPC's BDP probing. This way, links with a high
+//! latency and a high bandwidth are not throttled by the 64 KiB default windows.
+//!
+//! The module also contains the checks that the WINDOW_UPDATE frames received from the peer are
+//! subject to (`apply_window_update`), along with the outbound windows of the streams that they
+//! apply to (`StreamWindows`).
+use std::cmp;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use http::{StreamId, ErrorCode, WindowSize, INITIAL_STREAM_WINDOW_SIZE};
+use http::window;
+
+/// The largest window that the adaptive strategy grows the windows to by default (16 MiB).
+pub const DEFAULT_MAX_ADAPTIVE_WINDOW: u32 = 16 * 1024 * 1024;
+
+/// The strategies for managing the inbound flow control windows of a connection.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FlowControlStrategy {
+    /// The connection does not send any WINDOW_UPDATE frames on its own; it is up to the
+    /// application to do so.
+    Manual,
+    /// The connection replenishes the windows of the connection and its streams once half of the
+    /// window is consumed and grows them based on the estimated bandwidth-delay product, up to
+    /// the given maximum window size.
+    Adaptive { max_window: u32 },
+}
+
+/// Applies the increment of a received WINDOW_UPDATE frame to the given outbound window,
+/// following section 6.9.
+///
+/// An increment of 0 is answered with a PROTOCOL_ERROR and one that would take the window past
+/// 2^31 - 1 with a FLOW_CONTROL_ERROR; the window is left as it was in both cases. It is up to
+/// the caller to apply the error code at the right scope: as a connection error for updates of
+/// the connection window (stream 0) and as a stream error for all others.
+pub fn apply_window_update(window: &mut WindowSize, increment: u32) -> Result<(), ErrorCode> {
+    if increment == 0 {
+        return Err(ErrorCode::ProtocolError);
+    }
+    window.try_increase(increment).map_err(|_| ErrorCode::FlowControlError)
+}
+
+/// The outbound flow control windows of the streams that we are sending data on.
+///
+/// A stream's window is tracked from the moment its headers are sent without ending it, until
+/// our side of the stream ends or the stream is reset. Its size starts at the peer's
+/// SETTINGS_INITIAL_WINDOW_SIZE, follows the changes of that setting (section 6.9.2.), is
+/// consumed by the DATA sent on the stream and grows with the peer's WINDOW_UPDATE frames.
+#[derive(Clone, Debug, Default)]
+pub struct StreamWindows {
+    windows: HashMap<StreamId, WindowSize>,
+}
+
+impl StreamWindows {
+    /// Creates a new `StreamWindows` that does not track any streams.
+    pub fn new() -> StreamWindows {
+        StreamWindows::default()
+    }
+
+    /// Starts tracking the window of the given stream, with the given initial size, unless it is
+    /// already tracked.
+    pub fn open(&mut self, stream_id: StreamId, initial_size: u32) {
+        self.windows.entry(stream_id)
+                    .or_insert_with(|| WindowSize::new(window::clamp(initial_size) as i32));
+    }
+
+    /// Stops tracking the window of the given stream.
+    pub fn close(&mut self, stream_id: StreamId) {
+        self.windows.remove(&stream_id);
+    }
+
+    /// Returns the current size of the given stream's window, if it is tracked.
+    pub fn size(&self, stream_id: StreamId) -> Option<i32> {
+        self.windows.get(&stream_id).map(|window| window.size())
+    }
+
+    /// Accounts for `len` bytes of DATA sent on the given stream.
+    ///
+    /// The window may go negative (e.g. if the peer shrank the windows after the data was
+    /// handed to the connection); it is not up to this tracker to hold the data back. (A window
+    /// that would underflow is left as it is, since the peer would have failed the stream long
+    /// before.)
+    pub fn consume(&mut self, stream_id: StreamId, len: u32) {
+        if let Some(window) = self.windows.get_mut(&stream_id) {
+            let _ = window.try_consume(len);
+        }
+    }
+
+    /// Applies the increment of a WINDOW_UPDATE received on the given stream, as by
+    /// `apply_window_update`. The windows of streams that are not tracked (e.g. because our side
+    /// of them already ended) are not affected, but the increment is still checked for being 0.
+    ///
+    /// On error, the stream is no longer tracked, as it is expected to be reset.
+    pub fn window_update(&mut self, stream_id: StreamId, increment: u32)
+            -> Result<(), ErrorCode> {
+        let res = match self.windows.get_mut(&stream_id) {
+            Some(window) => apply_window_update(window, increment),
+            None if increment == 0 => Err(ErrorCode::ProtocolError),
+            None => Ok(()),
+        };
+        if res.is_err() {
+            self.close(stream_id);
+        }
+        res
+    }
+
+    /// Adjusts all tracked windows to a change of the peer's SETTINGS_INITIAL_WINDOW_SIZE from
+    /// `old` to `new` (section 6.9.2.).
+    ///
+    /// If that takes any window past 2^31 - 1, a FLOW_CONTROL_ERROR is returned, which is a
+    /// connection error.
+    pub fn initial_window_size_changed(&mut self, old: u32, new: u32) -> Result<(), ErrorCode> {
+        for window in self.windows.values_mut() {
+            try!(window.try_adjust_initial(old, new).map_err(|_| ErrorCode::FlowControlError));
+        }
+        Ok(())
+    }
+}
+
+/// Estimates the bandwidth-delay product of the link to the peer.
+///
+/// A PING is sent along with the first DATA frame received after the previous probe completed;
+/// the amount of data received until the PING is acknowledged approximates the amount of data
+/// that the link can hold, i.e. how large the windows need to be in order for the peer to never
+/// block on flow control.
+#[derive(Clone, Debug)]
+pub struct BdpEstimator {
+    /// The opaque data of the PING in flight and the time it was sent at, if any.
+    ping: Option<(u64, Instant)>,
+    /// The number of bytes received since the PING in flight was sent.
+    bytes: u32,
+    /// The opaque data to use for the next PING.
+    next_ping: u64,
+    /// The largest BDP measured so far.
+    bdp: u32,
+    /// The round trip time measured by the most recent probe.
+    rtt: Option<Duration>,
+}
+
+impl BdpEstimator {
+    /// Creates a new `BdpEstimator` with no measurements.
+    pub fn new() -> BdpEstimator {
+        BdpEstimator {
+            ping: None,
+            bytes: 0,
+            next_ping: 0,
+            bdp: 0,
+            rtt: None,
+        }
+    }
+
+    /// Accounts for `len` bytes of received data.
+    ///
+    /// Returns the opaque data of a PING that should be sent to the peer if a new probe starts.
+    pub fn data_received(&mut self, len: u32) -> Option<u64> {
+        self.bytes = self.bytes.saturating_add(len);
+        if self.ping.is_some() {
+            return None;
+        }
+        let data = self.next_ping;
+        self.next_ping = self.next_ping.wrapping_add(1);
+        self.ping = Some((data, Instant::now()));
+        Some(data)
+    }
+
+    /// Completes the probe if the given PING acknowledgement belongs to it.
+    ///
+    /// Returns the BDP that the probe measured, or `None` if the ACK is for some other PING.
+    pub fn ping_acked(&mut self, data: u64) -> Option<u32> {
+        let sent_at = match self.ping {
+            Some((pending, sent_at)) if pending == data => sent_at,
+            _ => return None,
+        };
+        self.ping = None;
+        self.rtt = Some(sent_at.elapsed());
+        let bdp = self.bytes;
+        self.bytes = 0;
+        self.bdp = cmp::max(self.bdp, bdp);
+        Some(bdp)
+    }
+
+    /// Returns whether a PING sent by the estimator is yet to be acknowledged.
+    #[inline]
+    pub fn is_probing(&self) -> bool {
+        self.ping.is_some()
+    }
+
+    /// Returns the largest bandwidth-delay product (in bytes) measured so far.
+    #[inline]
+    pub fn bdp(&self) -> u32 {
+        self.bdp
+    }
+
+    /// Returns the round trip time measured by the most recent probe, if any.
+    #[inline]
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+}
+
+/// Tracks how much of a window was consumed since it was last replenished.
+#[derive(Clone, Copy, Debug)]
+struct Consumption {
+    /// The bytes received since the last WINDOW_UPDATE.
+    consumed: u32,
+    /// The window size that the peer was granted by the last WINDOW_UPDATE.
+    granted: u32,
+}
+
+impl Consumption {
+    fn new(granted: u32) -> Consumption {
+        Consumption { consumed: 0, granted: granted }
+    }
+
+    /// Accounts for `len` received bytes, returning the increment that should be sent in a
+    /// WINDOW_UPDATE in order to restore the window to `window`, once at least half of it is used.
+    ///
+    /// "Half" is measured against the window that the peer was granted, if smaller: a window that
+    /// grew since the last WINDOW_UPDATE cannot be half used before the peer learns about it.
+    fn consume(&mut self, len: u32, window: u32) -> Option<u32> {
+        self.consumed = self.consumed.saturating_add(len);
+        if self.consumed < cmp::min(window, self.granted) / 2 {
+            return None;
+        }
+        let increment = window::saturating_add(self.consumed, window.saturating_sub(self.granted));
+        self.consumed = 0;
+        self.granted = window;
+        Some(increment)
+    }
+}
+
+/// The state of the adaptive flow control strategy of a connection.
+///
+/// The same window size is used for the connection and all its streams; it starts at the spec
+/// default and is doubled whenever a probe finds that the BDP is close to it (at least 2/3 of the
+/// window), up to the maximum.
+#[derive(Clone, Debug)]
+pub struct AdaptiveWindow {
+    estimator: BdpEstimator,
+    window: u32,
+    max_window: u32,
+    connection: Consumption,
+    streams: HashMap<StreamId, Consumption>,
+}
+
+impl AdaptiveWindow {
+    /// Creates a new `AdaptiveWindow` which grows the windows up to `max_window` bytes.
+    pub fn new(max_window: u32) -> AdaptiveWindow {
+        let initial = INITIAL_STREAM_WINDOW_SIZE as u32;
+        AdaptiveWindow {
+            estimator: BdpEstimator::new(),
+            window: initial,
+            max_window: cmp::max(window::clamp(max_window), initial),
+            connection: Consumption::new(initial),
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Returns the current target size of the windows.
+    #[inline]
+    pub fn window_size(&self) -> u32 {
+        self.window
+    }
+
+    /// Returns the underlying BDP estimator.
+    #[inline]
+    pub fn estimator(&self) -> &BdpEstimator {
+        &self.estimator
+    }
+
+    /// Accounts for a DATA frame with a payload of `len` bytes received on the given stream.
+    ///
+    /// The WINDOW_UPDATE increments that should be sent are pushed onto `updates` as
+    /// `(stream_id, increment)` pairs (stream 0 being the connection). If a new BDP probe starts,
+    /// returns the opaque data of the PING that should be sent.
+    pub fn data_received(&mut self,
+                         stream_id: StreamId,
+                         len: u32,
+                         end_stream: bool,
+                         updates: &mut Vec<(StreamId, u32)>)
+            -> Option<u64> {
+        let window = self.window;
+        if let Some(increment) = self.connection.consume(len, window) {
+            updates.push((0, increment));
+        }
+        if end_stream {
+            // No more data can arrive on the stream, so its window is irrelevant.
+            self.streams.remove(&stream_id);
+        } else {
+            let stream = self.streams.entry(stream_id)
+                                     .or_insert(Consumption::new(INITIAL_STREAM_WINDOW_SIZE as u32));
+            if let Some(increment) = stream.consume(len, window) {
+                updates.push((stream_id, increment));
+            }
+        }
+
+        if self.window < self.max_window && len > 0 {
+            self.estimator.data_received(len)
+        } else {
+            None
+        }
+    }
+
+    /// Handles the acknowledgement of a PING, growing the window if it completes a probe that
+    /// found the BDP to be close to the current window size.
+    ///
+    /// Returns whether the ACK belonged to a BDP probe.
+    pub fn ping_acked(&mut self, data: u64) -> bool {
+        let bdp = match self.estimator.ping_acked(data) {
+            Some(bdp) => bdp as u64,
+            None => return false,
+        };
+        if bdp * 3 >= (self.window as u64) * 2 {
+            let grown = cmp::min(bdp * 2, self.max_window as u64) as u32;
+            if grown > self.window {
+                debug!("Growing the flow control windows to {} (BDP = {})", grown, bdp);
+                self.window = grown;
+            }
+        }
+        true
+    }
+
+    /// Stops tracking the window of the given stream (e.g. once it is reset).
+    pub fn remove_stream(&mut self, stream_id: StreamId) {
+        self.streams.remove(&stream_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BdpEstimator, AdaptiveWindow, StreamWindows, apply_window_update};
+    use http::{ErrorCode, WindowSize};
+
+    /// Tests that the `BdpEstimator` measures the amount of data received during a PING round
+    /// trip.
+    #[test]
+    fn test_bdp_estimator() {
+        let mut estimator = BdpEstimator::new();
+
+        // The first chunk of data starts a probe...
+        let ping = estimator.data_received(100).unwrap();
+        assert!(estimator.is_probing());
+        // ...but the following ones do not, while it is in flight.
+        assert!(estimator.data_received(200).is_none());
+        assert!(estimator.data_received(300).is_none());
+        // An ACK for some other PING is ignored.
+        assert!(estimator.ping_acked(ping + 1).is_none());
+
+        assert_eq!(estimator.ping_acked(ping), Some(600));
+        assert!(!estimator.is_probing());
+        assert!(estimator.rtt().is_some());
+        assert_eq!(estimator.bdp(), 600);
+
+        // A new probe uses a different PING.
+        let next = estimator.data_received(10).unwrap();
+        assert!(next != ping);
+        assert_eq!(estimator.ping_acked(next), Some(10));
+        // The largest measurement is kept.
+        assert_eq!(estimator.bdp(), 600);
+    }
+
+    /// Tests that the `AdaptiveWindow` replenishes the connection and stream windows once half of
+    /// them is consumed.
+    #[test]
+    fn test_adaptive_window_replenish() {
+        let mut window = AdaptiveWindow::new(65_535);
+        let mut updates = Vec::new();
+
+        window.data_received(1, 30_000, false, &mut updates);
+        assert_eq!(updates, vec![]);
+        window.data_received(3, 30_000, false, &mut updates);
+        // The connection window is now more than half used, the stream windows are not.
+        assert_eq!(updates, vec![(0, 60_000)]);
+        updates.clear();
+
+        window.data_received(1, 10_000, false, &mut updates);
+        assert_eq!(updates, vec![(1, 40_000)]);
+        updates.clear();
+
+        // Finished streams are no longer tracked, so only the connection window is replenished.
+        window.data_received(3, 30_000, true, &mut updates);
+        assert_eq!(updates, vec![(0, 40_000)]);
+    }
+
+    /// Tests that the `AdaptiveWindow` grows the windows when the measured BDP is close to the
+    /// window size, and that the growth is reflected in the next window updates.
+    #[test]
+    fn test_adaptive_window_growth() {
+        let mut window = AdaptiveWindow::new(200_000);
+        let mut updates = Vec::new();
+
+        let ping = window.data_received(1, 20_000, false, &mut updates).unwrap();
+        window.data_received(1, 30_000, false, &mut updates);
+        assert_eq!(updates, vec![(0, 50_000), (1, 50_000)]);
+        updates.clear();
+        // 50 000 bytes per round trip is more than 2/3 of the window...
+        assert!(window.ping_acked(ping));
+        assert_eq!(window.window_size(), 100_000);
+
+        // ...so the next updates also grant the additional window.
+        let ping = window.data_received(1, 50_000, false, &mut updates).unwrap();
+        assert_eq!(updates, vec![(0, 50_000 + 100_000 - 65_535),
+                                 (1, 50_000 + 100_000 - 65_535)]);
+
+        // Growth is capped at the maximum.
+        window.data_received(1, 100_000, false, &mut updates);
+        assert!(window.ping_acked(ping));
+        assert_eq!(window.window_size(), 200_000);
+        // No more probes once the maximum is reached.
+        assert!(window.data_received(1, 100, false, &mut updates).is_none());
+    }
+
+    /// Tests that a stream opened after the windows grew is replenished once half of the default
+    /// window that it starts with is used, growing its window as well.
+    #[test]
+    fn test_adaptive_window_new_stream_after_growth() {
+        let mut window = AdaptiveWindow::new(1_000_000);
+        let mut updates = Vec::new();
+        let ping = window.data_received(1, 10_000, false, &mut updates).unwrap();
+        window.data_received(1, 150_000, false, &mut updates);
+        assert!(window.ping_acked(ping));
+        assert_eq!(window.window_size(), 320_000);
+        updates.clear();
+
+        // The new stream can only send the 65 535 bytes of the default window...
+        let mut stream_updates = Vec::new();
+        for &len in &[16_384, 16_384, 16_384, 16_383] {
+            window.data_received(3, len, false, &mut updates);
+            stream_updates.extend(updates.drain(..).filter(|&(id, _)| id == 3));
+        }
+
+        // ...and gets the rest of the grown window after the first half of it.
+        assert_eq!(stream_updates, vec![(3, 32_768 + 320_000 - 65_535)]);
+    }
+
+    /// Tests that the `AdaptiveWindow` does not grow the windows when the link does not fill them.
+    #[test]
+    fn test_adaptive_window_no_growth() {
+        let mut window = AdaptiveWindow::new(1_000_000);
+        let mut updates = Vec::new();
+
+        let ping = window.data_received(1, 1_000, false, &mut updates).unwrap();
+        assert!(window.ping_acked(ping));
+        assert_eq!(window.window_size(), 65_535);
+        // Unrelated PING ACKs are not consumed.
+        assert!(!window.ping_acked(ping));
+    }
+
+    /// Tests that a WINDOW_UPDATE increment of 0 is a PROTOCOL_ERROR and one that overflows the
+    /// window a FLOW_CONTROL_ERROR, neither of which changes the window.
+    #[test]
+    fn test_apply_window_update() {
+        let mut window = WindowSize::new(65_535);
+        assert_eq!(apply_window_update(&mut window, 0), Err(ErrorCode::ProtocolError));
+        assert_eq!(window.size(), 65_535);
+        assert_eq!(apply_window_update(&mut window, 1), Ok(()));
+        assert_eq!(window.size(), 65_536);
+        assert_eq!(apply_window_update(&mut window, 0x7fffffff - 65_536), Ok(()));
+        assert_eq!(window.size(), 0x7fffffff);
+        assert_eq!(apply_window_update(&mut window, 1), Err(ErrorCode::FlowControlError));
+        assert_eq!(window.size(), 0x7fffffff);
+
+        // A negative window can take the largest increment...
+        let mut window = WindowSize::new(-100);
+        assert_eq!(apply_window_update(&mut window, 0x7fffffff), Ok(()));
+        assert_eq!(window.size(), 0x7fffffff - 100);
+        assert_eq!(apply_window_update(&mut window, 101), Err(ErrorCode::FlowControlError));
+        // ...but never an increment that does not fit into 31 bits.
+        let mut window = WindowSize::new(i32::min_value());
+        assert_eq!(apply_window_update(&mut window, 0x80000000), Err(ErrorCode::FlowControlError));
+    }
+
+    /// Tests that the stream windows are opened at the given size, consumed by the data sent and
+    /// grown by window updates, and that invalid updates stop the tracking of the stream.
+    #[test]
+    fn test_stream_windows_updates() {
+        let mut windows = StreamWindows::new();
+        windows.open(1, 100);
+        windows.open(3, 65_535);
+        // Opening a tracked stream again does not reset its window.
+        windows.consume(1, 40);
+        windows.open(1, 100);
+        assert_eq!(windows.size(1), Some(60));
+
+        assert_eq!(windows.window_update(1, 10), Ok(()));
+        assert_eq!(windows.size(1), Some(70));
+        assert_eq!(windows.window_update(1, 0), Err(ErrorCode::ProtocolError));
+        assert_eq!(windows.size(1), None);
+        assert_eq!(windows.window_update(3, 0x7fffffff), Err(ErrorCode::FlowControlError));
+        assert_eq!(windows.size(3), None);
+
+        // Updates of streams that are not tracked are only checked for being 0.
+        assert_eq!(windows.window_update(5, 0x7fffffff), Ok(()));
+        assert_eq!(windows.window_update(5, 0), Err(ErrorCode::ProtocolError));
+        assert_eq!(windows.size(5), None);
+        windows.consume(5, 10);
+        assert_eq!(windows.size(5), None);
+
+        windows.open(7, 10);
+        windows.close(7);
+        assert_eq!(windows.size(7), None);
+    }
+
+    /// Tests that a change of the initial window size adjusts all tracked windows, failing with a
+    /// FLOW_CONTROL_ERROR if any of them would overflow.
+    #[test]
+    fn test_stream_windows_initial_size_changed() {
+        let mut windows = StreamWindows::new();
+        windows.open(1, 65_535);
+        windows.open(3, 65_535);
+        windows.consume(3, 65_535);
+
+        assert_eq!(windows.initial_window_size_changed(65_535, 0), Ok(()));
+        assert_eq!(windows.size(1), Some(0));
+        assert_eq!(windows.size(3), Some(-65_535));
+        assert_eq!(windows.initial_window_size_changed(0, 0x7fffffff), Ok(()));
+        assert_eq!(windows.size(1), Some(0x7fffffff));
+
+        assert_eq!(windows.window_update(3, 100), Ok(()));
+        assert_eq!(windows.initial_window_size_changed(0x7fffffff - 1, 0x7fffffff),
+                   Err(ErrorCode::FlowControlError));
+    }
+}
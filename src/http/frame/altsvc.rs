@@ -0,0 +1,192 @@
+//! Implements the `ALTSVC` HTTP/2 frame, defined by RFC 7838, section 4.
+
+use std::io;
+
+use http::StreamId;
+use http::frame::{
+    Frame,
+    FrameIR,
+    FrameBuilder,
+    FrameHeader,
+    RawFrame,
+    NoFlag,
+};
+
+/// The minimum size for the `ALTSVC` frame payload: the length of the origin is required.
+pub const ALTSVC_MIN_FRAME_LEN: u32 = 2;
+/// The frame type of the `ALTSVC` frame.
+pub const ALTSVC_FRAME_TYPE: u8 = 0xa;
+
+/// The struct represents the `ALTSVC` HTTP/2 frame, with which a server advertises alternative
+/// services (e.g. other hosts or protocols) that the same resources can be accessed through.
+///
+/// On stream 0, the frame carries the origin that the alternatives apply to; on any other stream,
+/// they apply to the origin of that stream's request and the frame carries no origin.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AltSvcFrame<'a> {
+    stream_id: StreamId,
+    origin: &'a [u8],
+    field_value: &'a [u8],
+    flags: u8,
+}
+
+impl<'a> AltSvcFrame<'a> {
+    /// Creates a new `ALTSVC` frame for the given origin, to be sent on stream 0, carrying the
+    /// given `Alt-Svc` field value (e.g. `h2="alt.example.com:443"; ma=3600`).
+    pub fn for_origin(origin: &'a [u8], field_value: &'a [u8]) -> AltSvcFrame<'a> {
+        AltSvcFrame {
+            stream_id: 0,
+            origin: origin,
+            field_value: field_value,
+            flags: 0,
+        }
+    }
+
+    /// Creates a new `ALTSVC` frame for the origin of the request on the given stream, carrying
+    /// the given `Alt-Svc` field value.
+    pub fn for_stream(stream_id: StreamId, field_value: &'a [u8]) -> AltSvcFrame<'a> {
+        AltSvcFrame {
+            stream_id: stream_id,
+            origin: &[],
+            field_value: field_value,
+            flags: 0,
+        }
+    }
+
+    /// Returns the origin that the frame carries (empty if it carries none).
+    pub fn origin(&self) -> &[u8] {
+        self.origin
+    }
+
+    /// Returns the value of the `Alt-Svc` field that the frame carries.
+    pub fn field_value(&self) -> &[u8] {
+        self.field_value
+    }
+
+    /// Returns whether the frame is to be ignored by its recipient (RFC 7838, section 4): one on
+    /// stream 0 must carry an origin, while one on any other stream must not.
+    pub fn is_ignored(&self) -> bool {
+        (self.stream_id == 0) == self.origin.is_empty()
+    }
+
+    /// Returns the total length of the frame's payload.
+    pub fn payload_len(&self) -> u32 {
+        ALTSVC_MIN_FRAME_LEN + self.origin.len() as u32 + self.field_value.len() as u32
+    }
+}
+
+impl<'a> Frame<'a> for AltSvcFrame<'a> {
+    type FlagType = NoFlag;
+
+    fn from_raw(raw_frame: &'a RawFrame<'a>) -> Option<Self> {
+        let (payload_len, frame_type, flags, stream_id) = raw_frame.header();
+        if payload_len < ALTSVC_MIN_FRAME_LEN {
+            return None;
+        }
+        if frame_type != ALTSVC_FRAME_TYPE {
+            return None;
+        }
+        let payload = raw_frame.payload();
+        if payload.len() != payload_len as usize {
+            return None;
+        }
+
+        let origin_len = ((payload[0] as usize) << 8) | payload[1] as usize;
+        let rest = &payload[ALTSVC_MIN_FRAME_LEN as usize..];
+        if origin_len > rest.len() {
+            return None;
+        }
+
+        Some(AltSvcFrame {
+            stream_id: stream_id,
+            origin: &rest[..origin_len],
+            field_value: &rest[origin_len..],
+            flags: flags,
+        })
+    }
+
+    fn is_set(&self, _: NoFlag) -> bool { false }
+    fn get_stream_id(&self) -> StreamId { self.stream_id }
+    fn get_header(&self) -> FrameHeader {
+        (self.payload_len(), ALTSVC_FRAME_TYPE, self.flags, self.stream_id)
+    }
+}
+
+impl<'a> FrameIR for AltSvcFrame<'a> {
+    fn serialize_into<B: FrameBuilder>(self, builder: &mut B) -> io::Result<()> {
+        try!(builder.write_header(self.get_header()));
+        let origin_len = self.origin.len();
+        try!(builder.write_all(&[(origin_len >> 8) as u8, origin_len as u8]));
+        try!(builder.write_all(self.origin));
+        try!(builder.write_all(self.field_value));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AltSvcFrame;
+
+    use http::tests::common::{serialize_frame, raw_frame_from_parts};
+    use http::frame::Frame;
+
+    #[test]
+    fn test_parse_valid_with_origin() {
+        let mut payload = vec![0, 11];
+        payload.extend_from_slice(b"example.comh2=\":8000\"");
+        let raw = raw_frame_from_parts((payload.len() as u32, 0xa, 0, 0), payload);
+        let frame = AltSvcFrame::from_raw(&raw).expect("Expected successful parse");
+        assert_eq!(frame.origin(), b"example.com");
+        assert_eq!(frame.field_value(), b"h2=\":8000\"");
+        assert!(!frame.is_ignored());
+    }
+
+    #[test]
+    fn test_parse_valid_on_stream() {
+        let mut payload = vec![0, 0];
+        payload.extend_from_slice(b"h2=\":8000\"");
+        let raw = raw_frame_from_parts((payload.len() as u32, 0xa, 0, 3), payload);
+        let frame = AltSvcFrame::from_raw(&raw).expect("Expected successful parse");
+        assert_eq!(frame.get_stream_id(), 3);
+        assert_eq!(frame.origin(), b"");
+        assert_eq!(frame.field_value(), b"h2=\":8000\"");
+        assert!(!frame.is_ignored());
+    }
+
+    #[test]
+    fn test_ignored() {
+        assert!(AltSvcFrame::for_origin(b"", b"clear").is_ignored());
+        let mut frame = AltSvcFrame::for_stream(1, b"clear");
+        frame.origin = b"example.com";
+        assert!(frame.is_ignored());
+    }
+
+    #[test]
+    fn test_parse_invalid_payload() {
+        // Too short to carry the origin length...
+        let raw = raw_frame_from_parts((1, 0xa, 0, 0), vec![0]);
+        assert!(AltSvcFrame::from_raw(&raw).is_none());
+        // ...and an origin longer than the payload.
+        let raw = raw_frame_from_parts((4, 0xa, 0, 0), vec![0, 3, b'a', b'b']);
+        assert!(AltSvcFrame::from_raw(&raw).is_none());
+    }
+
+    #[test]
+    fn test_parse_invalid_type() {
+        let raw = raw_frame_from_parts((2, 0x7, 0, 0), vec![0, 0]);
+        assert!(AltSvcFrame::from_raw(&raw).is_none());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let frame = AltSvcFrame::for_origin(b"a.io", b"clear");
+        let mut expected = vec![0, 0, 11, 0xa, 0, 0, 0, 0, 0, 0, 4];
+        expected.extend_from_slice(b"a.ioclear");
+        assert_eq!(serialize_frame(&frame), expected);
+
+        let frame = AltSvcFrame::for_stream(5, b"clear");
+        let mut expected = vec![0, 0, 7, 0xa, 0, 0, 0, 0, 5, 0, 0];
+        expected.extend_from_slice(b"clear");
+        assert_eq!(serialize_frame(&frame), expected);
+    }
+}
@@ -1,6 +1,7 @@
 //! Defines the `FrameBuilder` trait and some default implementations of the trait.
 
 use std::io;
+use std::vec::Vec;
 use http::frame::{
     FrameHeader,
     pack_header,
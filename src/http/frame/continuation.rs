@@ -0,0 +1,163 @@
+//! The module contains the implementation of the `CONTINUATION` frame and associated flags.
+
+use std::io;
+use std::borrow::Cow;
+use std::vec::Vec;
+
+use http::StreamId;
+use http::frame::{
+    FrameBuilder,
+    FrameIR,
+    Flag,
+    Frame,
+    FrameHeader,
+    RawFrame,
+};
+
+/// The frame type of the `CONTINUATION` frame.
+pub const CONTINUATION_FRAME_TYPE: u8 = 0x9;
+
+/// An enum representing the flags that a `ContinuationFrame` can have.
+/// The integer representation associated to each variant is that flag's
+/// bitmask.
+///
+/// HTTP/2 spec, section 6.10.
+#[derive(Clone, PartialEq, Debug, Copy)]
+pub enum ContinuationFlag {
+    EndHeaders = 0x4,
+}
+
+impl Flag for ContinuationFlag {
+    #[inline]
+    fn bitmask(&self) -> u8 {
+        *self as u8
+    }
+}
+
+/// A struct representing the CONTINUATION frames of HTTP/2, as defined in the HTTP/2 spec,
+/// section 6.10.
+///
+/// The frames carry the rest of a header block fragment that did not fit into the HEADERS frame
+/// that started it.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ContinuationFrame<'a> {
+    /// The header fragment bytes stored within the frame.
+    header_fragment: Cow<'a, [u8]>,
+    /// The ID of the stream with which this frame is associated
+    pub stream_id: StreamId,
+    /// The set of flags for the frame, packed into a single byte.
+    flags: u8,
+}
+
+impl<'a> ContinuationFrame<'a> {
+    /// Creates a new `ContinuationFrame` with the given header fragment and stream ID. No flags
+    /// are set.
+    pub fn new(fragment: Vec<u8>, stream_id: StreamId) -> ContinuationFrame<'a> {
+        ContinuationFrame {
+            header_fragment: Cow::Owned(fragment),
+            stream_id: stream_id,
+            flags: 0,
+        }
+    }
+
+    /// Returns whether this frame ends the header block. If not, more CONTINUATION frames MUST
+    /// follow it.
+    pub fn is_headers_end(&self) -> bool {
+        self.is_set(ContinuationFlag::EndHeaders)
+    }
+
+    pub fn header_fragment(&self) -> &[u8] { &self.header_fragment }
+
+    /// Sets the given flag for the frame.
+    pub fn set_flag(&mut self, flag: ContinuationFlag) {
+        self.flags |= flag.bitmask();
+    }
+}
+
+impl<'a> Frame<'a> for ContinuationFrame<'a> {
+    type FlagType = ContinuationFlag;
+
+    /// Creates a new `ContinuationFrame` with the given `RawFrame` (i.e. header and payload), if
+    /// possible.
+    ///
+    /// # Returns
+    ///
+    /// `None` if a valid `ContinuationFrame` cannot be constructed from the given `RawFrame`. The
+    /// stream ID *must not* be 0.
+    fn from_raw(raw_frame: &'a RawFrame) -> Option<ContinuationFrame<'a>> {
+        let (len, frame_type, flags, stream_id) = raw_frame.header();
+        if frame_type != CONTINUATION_FRAME_TYPE {
+            return None;
+        }
+        if (len as usize) != raw_frame.payload().len() {
+            return None;
+        }
+        if stream_id == 0 {
+            return None;
+        }
+
+        Some(ContinuationFrame {
+            header_fragment: Cow::Borrowed(raw_frame.payload()),
+            stream_id: stream_id,
+            flags: flags,
+        })
+    }
+
+    fn is_set(&self, flag: ContinuationFlag) -> bool {
+        (self.flags & flag.bitmask()) != 0
+    }
+
+    fn get_stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+
+    fn get_header(&self) -> FrameHeader {
+        (self.header_fragment.len() as u32, CONTINUATION_FRAME_TYPE, self.flags, self.stream_id)
+    }
+}
+
+impl<'a> FrameIR for ContinuationFrame<'a> {
+    fn serialize_into<B: FrameBuilder>(self, b: &mut B) -> io::Result<()> {
+        try!(b.write_header(self.get_header()));
+        try!(b.write_all(&self.header_fragment));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ContinuationFrame, ContinuationFlag};
+    use http::tests::common::{raw_frame_from_parts, serialize_frame};
+    use http::frame::Frame;
+
+    /// Tests that a CONTINUATION frame is correctly parsed.
+    #[test]
+    fn test_continuation_frame_parse() {
+        let raw = raw_frame_from_parts((3, 0x9, 0x4, 1), b"123".to_vec());
+        let frame: ContinuationFrame = Frame::from_raw(&raw).unwrap();
+
+        assert_eq!(frame.header_fragment(), &b"123"[..]);
+        assert_eq!(frame.get_stream_id(), 1);
+        assert!(frame.is_headers_end());
+    }
+
+    /// Tests that CONTINUATION frames on stream 0 are rejected.
+    #[test]
+    fn test_continuation_frame_parse_stream_zero() {
+        let raw = raw_frame_from_parts((3, 0x9, 0, 0), b"123".to_vec());
+        let frame: Option<ContinuationFrame> = Frame::from_raw(&raw);
+
+        assert!(frame.is_none());
+    }
+
+    /// Tests that a CONTINUATION frame is correctly serialized.
+    #[test]
+    fn test_continuation_frame_serialize() {
+        let mut frame = ContinuationFrame::new(b"123".to_vec(), 3);
+        assert!(!frame.is_headers_end());
+        frame.set_flag(ContinuationFlag::EndHeaders);
+
+        let expected: Vec<u8> = raw_frame_from_parts((3, 0x9, 0x4, 3), b"123".to_vec()).into();
+        assert_eq!(serialize_frame(&frame), expected);
+    }
+}
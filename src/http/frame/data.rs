@@ -2,6 +2,7 @@
 
 use std::io;
 use std::borrow::Cow;
+use std::vec::Vec;
 use http::StreamId;
 use http::frame::{
     FrameBuilder,
@@ -10,6 +11,7 @@ use http::frame::{
     Frame,
     FrameHeader,
     RawFrame,
+    FrameParseError,
     parse_padded_payload,
 };
 
@@ -156,30 +158,30 @@ impl<'a> DataFrame<'a> {
     pub fn set_flag(&mut self, flag: DataFlag) {
         self.flags |= flag.bitmask();
     }
-}
-
-impl<'a> Frame<'a> for DataFrame<'a> {
-    type FlagType = DataFlag;
 
     /// Creates a new `DataFrame` from the given `RawFrame` (i.e. header and
-    /// payload), if possible.  Returns `None` if a valid `DataFrame` cannot be
-    /// constructed from the given `RawFrame`.
-    fn from_raw(raw_frame: &'a RawFrame<'a>) -> Option<DataFrame<'a>> {
+    /// payload), returning the reason why it is invalid if it cannot be
+    /// constructed.
+    ///
+    /// A frame on stream 0 and a padded frame whose padding length is not less
+    /// than its payload length (section 6.1.) are reported as such, since they
+    /// are connection errors of type PROTOCOL_ERROR.
+    pub fn parse(raw_frame: &'a RawFrame<'a>) -> Result<DataFrame<'a>, FrameParseError> {
         // Unpack the header
         let (len, frame_type, flags, stream_id) = raw_frame.header();
         // Check that the frame type is correct for this frame implementation
         if frame_type != 0x0 {
-            return None;
+            return Err(FrameParseError::Malformed);
         }
         // Check that the length given in the header matches the payload
         // length; if not, something went wrong and we do not consider this a
         // valid frame.
         if (len as usize) != raw_frame.payload().len() {
-            return None;
+            return Err(FrameParseError::Malformed);
         }
         // A DATA frame cannot be associated to the connection itself.
         if stream_id == 0x0 {
-            return None;
+            return Err(FrameParseError::StreamIdZero);
         }
         // No validation is required for the flags, since according to the spec,
         // unknown flags MUST be ignored.
@@ -187,27 +189,29 @@ impl<'a> Frame<'a> for DataFrame<'a> {
         // the payload.
         let padded = (flags & DataFlag::Padded.bitmask()) != 0;
         match DataFrame::parse_payload(raw_frame.payload(), padded) {
-            Some((data, Some(padding_len))) => {
-                // The data got extracted (from a padded frame)
-                Some(DataFrame {
+            Some((data, padding_len)) => {
+                Ok(DataFrame {
                     stream_id: stream_id,
                     flags: flags,
                     data: Cow::Borrowed(data),
-                    padding_len: Some(padding_len),
+                    padding_len: padding_len,
                 })
             },
-            Some((data, None)) => {
-                // The data got extracted (from a no-padding frame)
-                Some(DataFrame {
-                    stream_id: stream_id,
-                    flags: flags,
-                    data: Cow::Borrowed(data),
-                    padding_len: None,
-                })
-            },
-            None => None,
+            None => Err(FrameParseError::InvalidPadding),
         }
     }
+}
+
+impl<'a> Frame<'a> for DataFrame<'a> {
+    type FlagType = DataFlag;
+
+    /// Creates a new `DataFrame` from the given `RawFrame` (i.e. header and
+    /// payload), if possible.  Returns `None` if a valid `DataFrame` cannot be
+    /// constructed from the given `RawFrame` (see `DataFrame::parse` for the
+    /// reason why).
+    fn from_raw(raw_frame: &'a RawFrame<'a>) -> Option<DataFrame<'a>> {
+        DataFrame::parse(raw_frame).ok()
+    }
 
     /// Tests if the given flag is set for the frame.
     fn is_set(&self, flag: DataFlag) -> bool {
@@ -243,6 +247,7 @@ impl<'a> FrameIR for DataFrame<'a> {
 #[cfg(test)]
 mod tests {
     use super::{DataFlag, DataFrame};
+    use http::frame::FrameParseError;
     use http::frame::tests::{build_padded_frame_payload};
     use http::tests::common::{raw_frame_from_parts, serialize_frame};
     use http::frame::{pack_header, Frame};
@@ -333,6 +338,33 @@ mod tests {
         assert!(frame.is_none())
     }
 
+    /// Tests that `DataFrame::parse` reports why a frame is invalid, including
+    /// the invalid padding lengths of h2spec's test cases for section 6.1.
+    #[test]
+    fn test_data_frame_parse_errors() {
+        fn parse_error(header: (u32, u8, u8, u32), payload: Vec<u8>) -> FrameParseError {
+            let raw = raw_frame_from_parts(header, payload);
+            DataFrame::parse(&raw).err().unwrap()
+        }
+        // The padding length equals the payload length...
+        assert_eq!(parse_error((5, 0, 0x8, 1), vec![5, 0, 0, 0, 0]),
+                   FrameParseError::InvalidPadding);
+        // ...or exceeds it (h2spec 6.1/3, with END_STREAM set as well).
+        assert_eq!(parse_error((5, 0, 0x9, 1), vec![6, 0, 0, 0, 0]),
+                   FrameParseError::InvalidPadding);
+        // There is no room for the padding length.
+        assert_eq!(parse_error((0, 0, 0x8, 1), vec![]), FrameParseError::InvalidPadding);
+        // A DATA frame on stream 0 (h2spec 6.1/2).
+        assert_eq!(parse_error((4, 0, 0, 0), b"asdf".to_vec()), FrameParseError::StreamIdZero);
+        assert_eq!(parse_error((4, 1, 0, 1), b"asdf".to_vec()), FrameParseError::Malformed);
+
+        // The largest padding that still fits.
+        let raw = raw_frame_from_parts((5, 0, 0x8, 1), vec![4, 0, 0, 0, 0]);
+        let frame = DataFrame::parse(&raw).unwrap();
+        assert_eq!(&frame.data[..], &b""[..]);
+        assert_eq!(frame.padding_len, Some(4));
+    }
+
     /// Tests that if a frame that should be parsed has a stream ID of 0, it is
     /// not considered a valid DATA frame.
     #[test]
@@ -360,7 +392,7 @@ mod tests {
         let frame: DataFrame = Frame::from_raw(&raw).unwrap();
 
         // The frame correctly returns the data -- i.e. an empty array?
-        assert_eq!(&frame.data[..], &[][..]);
+        assert_eq!(&frame.data[..], &b""[..]);
         // ...and the headers?
         assert_eq!(frame.get_header(), header);
     }
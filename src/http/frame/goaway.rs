@@ -39,7 +39,20 @@ impl<'a> GoawayFrame<'a> {
         }
     }
 
+    /// Create a new `GOAWAY` frame that will use the given `raw_error_code` for its payload and
+    /// has no debug data.
+    pub fn with_raw_error_code(last_stream_id: StreamId, raw_error_code: u32) -> Self {
+        GoawayFrame {
+            last_stream_id: last_stream_id,
+            raw_error_code: raw_error_code,
+            debug_data: None,
+            flags: 0,
+        }
+    }
+
     /// Create a new `GOAWAY` frame with the given parts.
+    ///
+    /// Empty debug data is the same as none at all, as the two cannot be told apart on the wire.
     pub fn with_debug_data(
             last_stream_id: StreamId,
             raw_error: u32,
@@ -48,7 +61,7 @@ impl<'a> GoawayFrame<'a> {
         GoawayFrame {
             last_stream_id: last_stream_id,
             raw_error_code: raw_error,
-            debug_data: Some(debug_data),
+            debug_data: if debug_data.is_empty() { None } else { Some(debug_data) },
             flags: 0,
         }
     }
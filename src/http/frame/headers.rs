@@ -2,6 +2,7 @@
 
 use std::io;
 use std::borrow::Cow;
+use std::vec::Vec;
 
 use http::StreamId;
 use http::frame::{
@@ -11,6 +12,7 @@ use http::frame::{
     Frame,
     FrameHeader,
     RawFrame,
+    FrameParseError,
     parse_padded_payload,
 };
 
@@ -210,38 +212,32 @@ impl<'a> HeadersFrame<'a> {
     pub fn set_flag(&mut self, flag: HeadersFlag) {
         self.flags |= flag.bitmask();
     }
-}
 
-impl<'a> Frame<'a> for HeadersFrame<'a> {
-    /// The type that represents the flags that the particular `Frame` can take.
-    /// This makes sure that only valid `Flag`s are used with each `Frame`.
-    type FlagType = HeadersFlag;
-
-    /// Creates a new `HeadersFrame` with the given `RawFrame` (i.e. header and
-    /// payload), if possible.
-    ///
-    /// # Returns
-    ///
-    /// `None` if a valid `HeadersFrame` cannot be constructed from the given
-    /// `RawFrame`. The stream ID *must not* be 0.
+    /// Creates a new `HeadersFrame` from the given `RawFrame` (i.e. header and
+    /// payload), returning the reason why it is invalid if it cannot be
+    /// constructed.
     ///
-    /// Otherwise, returns a newly constructed `HeadersFrame`.
-    fn from_raw(raw_frame: &'a RawFrame) -> Option<HeadersFrame<'a>> {
+    /// As with DATA frames, a frame on stream 0 and a padded frame whose
+    /// padding length is not less than its payload length (section 6.2.) are
+    /// reported as such, since they are connection errors of type
+    /// PROTOCOL_ERROR. The padding and the stream dependency (if the PRIORITY
+    /// flag is set) are stripped from the header block fragment.
+    pub fn parse(raw_frame: &'a RawFrame) -> Result<HeadersFrame<'a>, FrameParseError> {
         // Unpack the header
         let (len, frame_type, flags, stream_id) = raw_frame.header();
         // Check that the frame type is correct for this frame implementation
         if frame_type != 0x1 {
-            return None;
+            return Err(FrameParseError::Malformed);
         }
         // Check that the length given in the header matches the payload
         // length; if not, something went wrong and we do not consider this a
         // valid frame.
         if (len as usize) != raw_frame.payload().len() {
-            return None;
+            return Err(FrameParseError::Malformed);
         }
         // Check that the HEADERS frame is not associated to stream 0
         if stream_id == 0 {
-            return None;
+            return Err(FrameParseError::StreamIdZero);
         }
 
         // First, we get a slice containing the actual payload, depending on if
@@ -250,7 +246,7 @@ impl<'a> Frame<'a> for HeadersFrame<'a> {
         let (actual, pad_len) = if padded {
             match parse_padded_payload(&raw_frame.payload()) {
                 Some((data, pad_len)) => (data, Some(pad_len)),
-                None => return None,
+                None => return Err(FrameParseError::InvalidPadding),
             }
         } else {
             (raw_frame.payload(), None)
@@ -260,12 +256,16 @@ impl<'a> Frame<'a> for HeadersFrame<'a> {
         // the appropriate flag is set.
         let priority = (flags & HeadersFlag::Priority.bitmask()) != 0;
         let (data, stream_dep) = if priority {
+            // The stream dependency takes up 5 bytes, which the payload has to have room for.
+            if actual.len() < 5 {
+                return Err(FrameParseError::Malformed);
+            }
             (&actual[5..], Some(StreamDependency::parse(&actual[..5])))
         } else {
             (actual, None)
         };
 
-        Some(HeadersFrame {
+        Ok(HeadersFrame {
             header_fragment: Cow::Borrowed(data),
             stream_id: stream_id,
             stream_dep: stream_dep,
@@ -273,6 +273,20 @@ impl<'a> Frame<'a> for HeadersFrame<'a> {
             flags: flags,
         })
     }
+}
+
+impl<'a> Frame<'a> for HeadersFrame<'a> {
+    /// The type that represents the flags that the particular `Frame` can take.
+    /// This makes sure that only valid `Flag`s are used with each `Frame`.
+    type FlagType = HeadersFlag;
+
+    /// Creates a new `HeadersFrame` with the given `RawFrame` (i.e. header and
+    /// payload), if possible. Returns `None` if a valid `HeadersFrame` cannot be
+    /// constructed from the given `RawFrame` (see `HeadersFrame::parse` for the
+    /// reason why).
+    fn from_raw(raw_frame: &'a RawFrame) -> Option<HeadersFrame<'a>> {
+        HeadersFrame::parse(raw_frame).ok()
+    }
 
     /// Tests if the given flag is set for the frame.
     fn is_set(&self, flag: HeadersFlag) -> bool {
@@ -323,7 +337,7 @@ mod tests {
     use super::{HeadersFrame, HeadersFlag, StreamDependency};
     use http::frame::tests::{build_padded_frame_payload};
     use http::tests::common::{raw_frame_from_parts, serialize_frame};
-    use http::frame::{pack_header, Frame};
+    use http::frame::{pack_header, Frame, FrameParseError};
 
     /// Tests that a stream dependency structure can be correctly parsed by the
     /// `StreamDependency::parse` method.
@@ -504,6 +518,54 @@ mod tests {
         assert!(frame.is_none());
     }
 
+    /// Tests that a HEADERS frame with the PRIORITY flag, but a payload too short for the stream
+    /// dependency, is considered invalid.
+    #[test]
+    fn test_headers_frame_parse_truncated_priority() {
+        let payload = vec![0, 0, 0, 1];
+        let header = (payload.len() as u32, 0x1, 0x20, 1);
+
+        let raw = raw_frame_from_parts(header, payload);
+        let frame: Option<HeadersFrame> = Frame::from_raw(&raw);
+
+        assert!(frame.is_none());
+    }
+
+    /// Tests that `HeadersFrame::parse` reports why a frame is invalid, with the
+    /// padding length counted from the start of the payload, i.e. including
+    /// the stream dependency of a frame with the PRIORITY flag.
+    #[test]
+    fn test_headers_frame_parse_errors() {
+        fn parse_error(header: (u32, u8, u8, u32), payload: Vec<u8>) -> FrameParseError {
+            let raw = raw_frame_from_parts(header, payload);
+            HeadersFrame::parse(&raw).err().unwrap()
+        }
+        // The padding length equals the payload length...
+        assert_eq!(parse_error((5, 0x1, 0x8, 1), vec![5, 0, 0, 0, 0]),
+                   FrameParseError::InvalidPadding);
+        // ...or exceeds it (h2spec 6.2/2).
+        assert_eq!(parse_error((5, 0x1, 0x8 | 0x4, 1), vec![6, 0, 0, 0, 0]),
+                   FrameParseError::InvalidPadding);
+        // There is no room for the padding length.
+        assert_eq!(parse_error((0, 0x1, 0x8, 1), vec![]), FrameParseError::InvalidPadding);
+        // A HEADERS frame on stream 0 (h2spec 6.2/3).
+        assert_eq!(parse_error((3, 0x1, 0x4, 0), b"123".to_vec()), FrameParseError::StreamIdZero);
+        // The padding leaves no room for the stream dependency.
+        assert_eq!(parse_error((6, 0x1, 0x8 | 0x20, 1), vec![1, 0, 0, 0, 1, 0]),
+                   FrameParseError::Malformed);
+
+        // The padding that leaves exactly the stream dependency.
+        let dep = StreamDependency::new(1, 15, true);
+        let mut payload = vec![3];
+        payload.extend_from_slice(&dep.serialize());
+        payload.extend_from_slice(&[0, 0, 0]);
+        let raw = raw_frame_from_parts((9, 0x1, 0x8 | 0x20, 3), payload);
+        let frame = HeadersFrame::parse(&raw).unwrap();
+        assert_eq!(frame.header_fragment(), &b""[..]);
+        assert_eq!(frame.stream_dep, Some(dep));
+        assert_eq!(frame.padding_len, Some(3));
+    }
+
     /// Tests that the `HeadersFrame::parse` method considers any frame with
     /// a frame ID other than 1 in the frame header invalid.
     #[test]
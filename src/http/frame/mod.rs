@@ -2,9 +2,10 @@
 
 use std::io;
 use std::mem;
-use std::borrow::Cow;
+use std::sync::Arc;
+use std::vec::Vec;
 
-use http::StreamId;
+use http::{StreamId, ErrorCode};
 
 /// A helper macro that unpacks a sequence of 4 bytes found in the buffer with
 /// the given identifier, starting at the given offset, into the given integer
@@ -44,22 +45,61 @@ pub const FRAME_HEADER_LEN: usize = 9;
 pub mod builder;
 pub mod data;
 pub mod headers;
+pub mod priority;
+pub mod continuation;
 pub mod rst_stream;
 pub mod settings;
 pub mod goaway;
 pub mod window_update;
+pub mod ping;
+pub mod altsvc;
 
 pub use self::builder::FrameBuilder;
 
 /// Rexports related to the `DATA` frame.
 pub use self::data::{DataFlag, DataFrame};
 /// Rexports related to the `HEADERS` frame.
-pub use self::headers::{HeadersFlag, HeadersFrame};
+pub use self::headers::{HeadersFlag, HeadersFrame, StreamDependency};
+pub use self::priority::PriorityFrame;
+/// Rexports related to the `CONTINUATION` frame.
+pub use self::continuation::{ContinuationFlag, ContinuationFrame};
 pub use self::rst_stream::RstStreamFrame;
 /// Rexports related to the `SETTINGS` frame.
 pub use self::settings::{SettingsFlag, SettingsFrame, HttpSetting};
 pub use self::goaway::GoawayFrame;
 pub use self::window_update::WindowUpdateFrame;
+/// Rexports related to the `PING` frame.
+pub use self::ping::{PingFlag, PingFrame};
+pub use self::altsvc::AltSvcFrame;
+
+/// The reasons for which a raw frame cannot be parsed into its `Frame` implementation.
+///
+/// Unlike the `None` that `Frame::from_raw` gives, they allow the connection to tell the frames
+/// that the spec requires to be treated as a particular connection error apart from the ones
+/// that are merely malformed.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FrameParseError {
+    /// The frame is not of the expected type or its header does not match its payload.
+    Malformed,
+    /// The frame is associated to stream 0, while it needs to be associated to a stream.
+    StreamIdZero,
+    /// The frame is padded, but the padding is at least as long as the payload (or the payload
+    /// cannot even hold the padding length).
+    InvalidPadding,
+}
+
+impl FrameParseError {
+    /// Returns the error code of the connection error that receiving such a frame amounts to, if
+    /// the spec defines one: the invalid padding and stream ID of DATA and HEADERS frames are
+    /// PROTOCOL_ERRORs (sections 6.1. and 6.2.).
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        match *self {
+            FrameParseError::Malformed => None,
+            FrameParseError::StreamIdZero |
+            FrameParseError::InvalidPadding => Some(ErrorCode::ProtocolError),
+        }
+    }
+}
 
 /// An alias for the 9-byte buffer that each HTTP/2 frame header must be stored
 /// in.
@@ -141,6 +181,16 @@ pub trait FrameIR {
     fn serialize_into<B: FrameBuilder>(self, builder: &mut B) -> io::Result<()>;
 }
 
+/// Serializes the given frame into a new buffer holding its on-the-wire representation.
+///
+/// Builds without the `std` feature have no `FrameBuilder` of their own to pass to
+/// `FrameIR::serialize_into`, so this is how they serialize frames.
+pub fn serialize_frame<F: FrameIR>(frame: F) -> Vec<u8> {
+    let mut buf = io::Cursor::new(Vec::new());
+    frame.serialize_into(&mut buf).ok().expect("Writing to a buffer never fails");
+    buf.into_inner()
+}
+
 /// A trait that all HTTP/2 frame header flags need to implement.
 pub trait Flag {
     /// Returns a bit mask that represents the flag.
@@ -191,13 +241,44 @@ pub trait Frame<'a>: Sized {
 /// It does not try to interpret the payload bytes, nor do any validation in
 /// terms of its validity based on the frame type given in the header.
 /// It is simply a wrapper around the two parts of an HTTP/2 frame.
-#[derive(PartialEq)]
+///
+/// The bytes can be borrowed, owned or shared. A shared `RawFrame` (see `RawFrame::into_shared`)
+/// is reference-counted, making it cheap to clone, so that the frame can be stashed away for
+/// deferred processing without copying it.
 #[derive(Debug)]
 #[derive(Clone)]
 pub struct RawFrame<'a> {
     /// The raw frame representation, including both the raw header representation
     /// (in the first 9 bytes), followed by the raw payload representation.
-    raw_content: Cow<'a, [u8]>,
+    raw_content: RawBuffer<'a>,
+}
+
+/// The buffer backing a `RawFrame`.
+#[derive(Debug)]
+#[derive(Clone)]
+enum RawBuffer<'a> {
+    Borrowed(&'a [u8]),
+    Owned(Vec<u8>),
+    Shared(Arc<[u8]>),
+}
+
+impl<'a> RawBuffer<'a> {
+    #[inline]
+    fn as_slice(&self) -> &[u8] {
+        match *self {
+            RawBuffer::Borrowed(buf) => buf,
+            RawBuffer::Owned(ref buf) => &buf[..],
+            RawBuffer::Shared(ref buf) => &buf[..],
+        }
+    }
+}
+
+/// Two `RawFrame`s are equal iff they are made up of the same bytes, regardless of how the bytes
+/// are stored.
+impl<'a, 'b> PartialEq<RawFrame<'b>> for RawFrame<'a> {
+    fn eq(&self, other: &RawFrame<'b>) -> bool {
+        self.as_ref() == other.as_ref()
+    }
 }
 
 impl<'a> RawFrame<'a> {
@@ -261,46 +342,94 @@ impl<'a> RawFrame<'a> {
     /// payload.
     #[inline]
     pub fn len(&self) -> usize {
-        self.raw_content.len()
+        self.as_ref().len()
     }
 
     /// Returns a `Vec` of bytes representing the serialized (on-the-wire)
     /// representation of this raw frame.
     pub fn serialize(&self) -> Vec<u8> {
-        self.raw_content.clone().into_owned()
+        self.as_ref().to_vec()
     }
 
     /// Returns a `FrameHeader` instance corresponding to the headers of the
     /// `RawFrame`.
     pub fn header(&self) -> FrameHeader {
+        let raw = self.as_ref();
         unpack_header(unsafe {
-            assert!(self.raw_content.len() >= 9);
+            assert!(raw.len() >= 9);
             // We just asserted that this transmute is safe.
-            mem::transmute(self.raw_content.as_ptr())
+            mem::transmute(raw.as_ptr())
         })
     }
 
     /// Returns a slice representing the payload of the `RawFrame`.
     pub fn payload(&self) -> &[u8] {
-        &self.raw_content[9..]
+        &self.as_ref()[9..]
+    }
+
+    /// Converts the `RawFrame` into one backed by a reference-counted buffer, which is no longer
+    /// tied to the lifetime of the original buffer and can be cloned without copying the bytes.
+    ///
+    /// Borrowed and owned frames are copied (respectively, moved) into a new shared buffer once;
+    /// frames that are already shared are returned as they are.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use solicit::http::frame::RawFrame;
+    ///
+    /// let buf = vec![0, 0, 1, 0, 0, 0, 0, 0, 1, 1];
+    /// let shared = RawFrame::parse(&buf[..]).unwrap().into_shared();
+    /// let stashed = vec![shared.clone(), shared.clone()];
+    /// assert_eq!(stashed[0], shared);
+    /// assert_eq!(stashed[1].as_ref(), &buf[..]);
+    /// ```
+    pub fn into_shared(self) -> RawFrame<'static> {
+        let shared: Arc<[u8]> = match self.raw_content {
+            RawBuffer::Borrowed(buf) => buf.to_vec().into(),
+            RawBuffer::Owned(buf) => buf.into(),
+            RawBuffer::Shared(buf) => buf,
+        };
+        RawFrame { raw_content: RawBuffer::Shared(shared) }
+    }
+
+    /// Returns whether the `RawFrame` is backed by a reference-counted (shared) buffer.
+    #[inline]
+    pub fn is_shared(&self) -> bool {
+        match self.raw_content {
+            RawBuffer::Shared(_) => true,
+            _ => false,
+        }
     }
 }
 
 impl<'a> Into<Vec<u8>> for RawFrame<'a> {
-    fn into(self) -> Vec<u8> { self.raw_content.into_owned() }
+    fn into(self) -> Vec<u8> {
+        match self.raw_content {
+            RawBuffer::Owned(buf) => buf,
+            other => other.as_slice().to_vec(),
+        }
+    }
 }
 impl<'a> AsRef<[u8]> for RawFrame<'a> {
-    fn as_ref(&self) -> &[u8] { self.raw_content.as_ref() }
+    fn as_ref(&self) -> &[u8] { self.raw_content.as_slice() }
 }
 /// Provide a conversion from a `Vec`.
 ///
 /// This conversion is unchecked and could cause the resulting `RawFrame` to be an
 /// invalid HTTP/2 frame.
 impl<'a> From<Vec<u8>> for RawFrame<'a> {
-    fn from(raw: Vec<u8>) -> RawFrame<'a> { RawFrame { raw_content: Cow::Owned(raw) } }
+    fn from(raw: Vec<u8>) -> RawFrame<'a> { RawFrame { raw_content: RawBuffer::Owned(raw) } }
 }
 impl<'a> From<&'a [u8]> for RawFrame<'a> {
-    fn from(raw: &'a [u8]) -> RawFrame<'a> { RawFrame { raw_content: Cow::Borrowed(raw) } }
+    fn from(raw: &'a [u8]) -> RawFrame<'a> { RawFrame { raw_content: RawBuffer::Borrowed(raw) } }
+}
+/// Provide a conversion from a shared buffer, without copying it.
+///
+/// This conversion is unchecked and could cause the resulting `RawFrame` to be an
+/// invalid HTTP/2 frame.
+impl<'a> From<Arc<[u8]>> for RawFrame<'a> {
+    fn from(raw: Arc<[u8]>) -> RawFrame<'a> { RawFrame { raw_content: RawBuffer::Shared(raw) } }
 }
 
 /// `RawFrame`s can be serialized to an on-the-wire format.
@@ -318,6 +447,7 @@ mod tests {
         pack_header,
         RawFrame,
         FrameIR,
+        serialize_frame,
     };
     use std::io;
 
@@ -504,7 +634,7 @@ mod tests {
         }
         // Completely empty buffer
         {
-            assert_eq!(RawFrame::from(vec![]).serialize(), &[]);
+            assert_eq!(RawFrame::from(vec![]).serialize(), &b""[..]);
         }
     }
 
@@ -570,9 +700,10 @@ mod tests {
         let raw: RawFrame = buf.clone().into();
 
         let mut serialized = io::Cursor::new(Vec::new());
-        raw.serialize_into(&mut serialized).unwrap();
+        raw.clone().serialize_into(&mut serialized).unwrap();
 
         assert_eq!(serialized.into_inner(), buf);
+        assert_eq!(serialize_frame(raw), buf);
     }
 
     /// Tests the `len` method of the `RawFrame`.
@@ -596,4 +727,30 @@ mod tests {
             assert_eq!(buf.len(), frame.len());
         }
     }
+
+    /// Tests that a `RawFrame` can be converted into a shared one, which compares equal to the
+    /// original and whose clones share the same buffer.
+    #[test]
+    fn test_raw_frame_into_shared() {
+        let buf = vec![0, 0, 3, 0x1, 0, 0, 0, 0, 1, 1, 2, 3];
+        let frame = RawFrame::parse(&buf[..]).unwrap();
+        assert!(!frame.is_shared());
+
+        let shared = frame.clone().into_shared();
+        assert!(shared.is_shared());
+        assert_eq!(shared, frame);
+        assert_eq!(shared.header(), frame.header());
+        assert_eq!(shared.payload(), &[1, 2, 3]);
+
+        let stashed = shared.clone();
+        // The clone points to the very same bytes...
+        assert_eq!(stashed.as_ref().as_ptr(), shared.as_ref().as_ptr());
+        // ...and converting it again does not copy them either.
+        let again = stashed.into_shared();
+        assert_eq!(again.as_ref().as_ptr(), shared.as_ref().as_ptr());
+        // An owned frame can be converted as well.
+        let owned: RawFrame = buf.clone().into();
+        let vec: Vec<u8> = owned.into_shared().into();
+        assert_eq!(vec, buf);
+    }
 }
@@ -0,0 +1,171 @@
+//! Implements the `PING` HTTP/2 frame.
+
+use std::io;
+
+use http::StreamId;
+use http::frame::{
+    Frame,
+    FrameIR,
+    FrameBuilder,
+    FrameHeader,
+    RawFrame,
+    Flag,
+};
+
+/// The total allowed size for the `PING` frame payload.
+pub const PING_FRAME_LEN: u32 = 8;
+/// The frame type of the `PING` frame.
+pub const PING_FRAME_TYPE: u8 = 0x6;
+
+/// An enum representing the flags that a `PingFrame` can have.
+/// The integer representation associated to each variant is that flag's
+/// bitmask.
+///
+/// HTTP/2 spec, section 6.7.
+#[derive(Clone, PartialEq, Debug, Copy)]
+pub enum PingFlag {
+    Ack = 0x1,
+}
+
+impl Flag for PingFlag {
+    #[inline]
+    fn bitmask(&self) -> u8 {
+        *self as u8
+    }
+}
+
+/// The struct represents the `PING` HTTP/2 frame.
+///
+/// The 8 octets of opaque data that the frame carries are represented as a network-endian `u64`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PingFrame {
+    opaque_data: u64,
+    flags: u8,
+}
+
+impl PingFrame {
+    /// Creates a new `PingFrame` carrying the given opaque data.
+    pub fn new(opaque_data: u64) -> PingFrame {
+        PingFrame {
+            opaque_data: opaque_data,
+            flags: 0,
+        }
+    }
+
+    /// Creates a new `PingFrame` that acknowledges a received `PING` carrying the given opaque
+    /// data.
+    pub fn new_ack(opaque_data: u64) -> PingFrame {
+        PingFrame {
+            opaque_data: opaque_data,
+            flags: PingFlag::Ack.bitmask(),
+        }
+    }
+
+    /// Returns whether the frame is an acknowledgement of a previously sent `PING`.
+    #[inline]
+    pub fn is_ack(&self) -> bool {
+        self.is_set(PingFlag::Ack)
+    }
+
+    /// Returns the opaque data carried by the frame.
+    #[inline]
+    pub fn opaque_data(&self) -> u64 {
+        self.opaque_data
+    }
+}
+
+impl<'a> Frame<'a> for PingFrame {
+    type FlagType = PingFlag;
+
+    fn from_raw(raw_frame: &'a RawFrame<'a>) -> Option<Self> {
+        let (payload_len, frame_type, flags, stream_id) = raw_frame.header();
+        if payload_len != PING_FRAME_LEN {
+            return None;
+        }
+        if frame_type != PING_FRAME_TYPE {
+            return None;
+        }
+        if stream_id != 0x0 {
+            return None;
+        }
+
+        let payload = raw_frame.payload();
+        let high = unpack_octets_4!(payload, 0, u64);
+        let low = unpack_octets_4!(payload, 4, u64);
+
+        Some(PingFrame {
+            opaque_data: (high << 32) | low,
+            flags: flags,
+        })
+    }
+
+    fn is_set(&self, flag: PingFlag) -> bool {
+        (flag.bitmask() & self.flags) != 0
+    }
+    fn get_stream_id(&self) -> StreamId { 0 }
+    fn get_header(&self) -> FrameHeader {
+        (PING_FRAME_LEN, PING_FRAME_TYPE, self.flags, 0)
+    }
+}
+
+impl FrameIR for PingFrame {
+    fn serialize_into<B: FrameBuilder>(self, builder: &mut B) -> io::Result<()> {
+        try!(builder.write_header(self.get_header()));
+        try!(builder.write_u32((self.opaque_data >> 32) as u32));
+        try!(builder.write_u32(self.opaque_data as u32));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PingFrame;
+
+    use http::tests::common::{serialize_frame, raw_frame_from_parts};
+    use http::frame::Frame;
+
+    #[test]
+    fn test_parse_valid() {
+        let raw = raw_frame_from_parts((8, 0x6, 0, 0), vec![0, 0, 0, 1, 0, 0, 0, 2]);
+        let frame = PingFrame::from_raw(&raw).expect("expected valid PING");
+        assert_eq!(frame.opaque_data(), (1 << 32) | 2);
+        assert!(!frame.is_ack());
+        assert_eq!(frame.get_stream_id(), 0);
+    }
+
+    #[test]
+    fn test_parse_valid_ack() {
+        let raw = raw_frame_from_parts((8, 0x6, 1, 0), vec![0xff; 8]);
+        let frame = PingFrame::from_raw(&raw).expect("expected valid PING");
+        assert_eq!(frame.opaque_data(), 0xffffffffffffffff);
+        assert!(frame.is_ack());
+    }
+
+    #[test]
+    fn test_parse_invalid_stream_id() {
+        let raw = raw_frame_from_parts((8, 0x6, 0, 1), vec![0; 8]);
+        assert!(PingFrame::from_raw(&raw).is_none());
+    }
+
+    #[test]
+    fn test_parse_invalid_payload_size() {
+        let raw = raw_frame_from_parts((4, 0x6, 0, 0), vec![0; 4]);
+        assert!(PingFrame::from_raw(&raw).is_none());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let frame = PingFrame::new(0x0102030405060708);
+        let expected: Vec<u8> = raw_frame_from_parts((8, 0x6, 0, 0),
+                                                     vec![1, 2, 3, 4, 5, 6, 7, 8]).into();
+        assert_eq!(expected, serialize_frame(&frame));
+    }
+
+    #[test]
+    fn test_serialize_ack() {
+        let frame = PingFrame::new_ack(1);
+        let expected: Vec<u8> = raw_frame_from_parts((8, 0x6, 1, 0),
+                                                     vec![0, 0, 0, 0, 0, 0, 0, 1]).into();
+        assert_eq!(expected, serialize_frame(&frame));
+    }
+}
@@ -0,0 +1,179 @@
+//! The module contains the implementation of the `PRIORITY` frame.
+use std::io;
+
+use http::StreamId;
+use http::frame::{
+    Frame,
+    FrameIR,
+    FrameBuilder,
+    FrameHeader,
+    RawFrame,
+    NoFlag,
+    StreamDependency,
+};
+
+/// The total allowed size for the `PRIORITY` frame payload.
+pub const PRIORITY_FRAME_LEN: u32 = 5;
+/// The frame type of the `PRIORITY` frame.
+pub const PRIORITY_FRAME_TYPE: u8 = 0x2;
+
+/// The struct represents the `PRIORITY` HTTP/2 frame, which (re)prioritizes a stream in any of
+/// its states (HTTP/2 spec, section 6.3.).
+#[derive(Clone, Debug, PartialEq)]
+pub struct PriorityFrame {
+    stream_id: StreamId,
+    dependency: StreamDependency,
+    flags: u8,
+}
+
+impl PriorityFrame {
+    /// Constructs a new `PriorityFrame` that gives the given stream the given dependency.
+    pub fn new(stream_id: StreamId, dependency: StreamDependency) -> PriorityFrame {
+        PriorityFrame {
+            stream_id: stream_id,
+            dependency: dependency,
+            flags: 0,
+        }
+    }
+
+    /// Returns the full dependency that the frame carries.
+    pub fn dependency(&self) -> &StreamDependency {
+        &self.dependency
+    }
+
+    /// Returns the ID of the stream that the frame's stream depends on.
+    pub fn depends_on(&self) -> StreamId {
+        self.dependency.stream_id
+    }
+
+    /// Returns the weight of the stream, in the range [0, 255] (see `StreamDependency`).
+    pub fn weight(&self) -> u8 {
+        self.dependency.weight
+    }
+
+    /// Returns whether the dependency is exclusive.
+    pub fn is_exclusive(&self) -> bool {
+        self.dependency.is_exclusive
+    }
+}
+
+impl<'a> Frame<'a> for PriorityFrame {
+    type FlagType = NoFlag;
+
+    fn from_raw(raw_frame: &'a RawFrame<'a>) -> Option<Self> {
+        let (payload_len, frame_type, flags, stream_id) = raw_frame.header();
+        if payload_len != PRIORITY_FRAME_LEN {
+            return None;
+        }
+        if frame_type != PRIORITY_FRAME_TYPE {
+            return None;
+        }
+        if stream_id == 0x0 {
+            return None;
+        }
+        if raw_frame.payload().len() != PRIORITY_FRAME_LEN as usize {
+            return None;
+        }
+
+        Some(PriorityFrame {
+            stream_id: stream_id,
+            dependency: StreamDependency::parse(raw_frame.payload()),
+            flags: flags,
+        })
+    }
+
+    fn is_set(&self, _: NoFlag) -> bool { false }
+    fn get_stream_id(&self) -> StreamId { self.stream_id }
+    fn get_header(&self) -> FrameHeader {
+        (PRIORITY_FRAME_LEN, PRIORITY_FRAME_TYPE, self.flags, self.stream_id)
+    }
+}
+
+impl FrameIR for PriorityFrame {
+    fn serialize_into<B: FrameBuilder>(self, builder: &mut B) -> io::Result<()> {
+        try!(builder.write_header(self.get_header()));
+        try!(builder.write_all(&self.dependency.serialize()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PriorityFrame;
+
+    use http::tests::common::serialize_frame;
+    use http::frame::{
+        pack_header,
+        FrameHeader,
+        Frame,
+        StreamDependency,
+    };
+
+    /// A helper function that creates a new Vec containing the serialized representation of the
+    /// given `FrameHeader` followed by the raw provided payload.
+    fn prepare_frame_bytes(header: FrameHeader, payload: Vec<u8>) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend(pack_header(&header).to_vec());
+        buf.extend(payload);
+        buf
+    }
+
+    #[test]
+    fn test_parse_valid() {
+        let raw = prepare_frame_bytes((5, 0x2, 0, 3), vec![0, 0, 0, 1, 15]);
+        let frame = PriorityFrame::from_raw(&raw.into()).expect("Valid frame expected");
+        assert_eq!(frame.get_stream_id(), 3);
+        assert_eq!(frame.depends_on(), 1);
+        assert_eq!(frame.weight(), 15);
+        assert!(!frame.is_exclusive());
+    }
+
+    #[test]
+    fn test_parse_exclusive() {
+        let raw = prepare_frame_bytes((5, 0x2, 0, 3), vec![0x80, 0, 0, 1, 255]);
+        let frame = PriorityFrame::from_raw(&raw.into()).expect("Valid frame expected");
+        assert_eq!(frame.depends_on(), 1);
+        assert_eq!(frame.weight(), 255);
+        assert!(frame.is_exclusive());
+        assert_eq!(frame.dependency(), &StreamDependency::new(1, 255, true));
+    }
+
+    #[test]
+    fn test_parse_valid_with_unknown_flags() {
+        let raw = prepare_frame_bytes((5, 0x2, 0x80, 3), vec![0, 0, 0, 1, 15]);
+        let frame = PriorityFrame::from_raw(&raw.into()).expect("Valid frame expected");
+        assert_eq!(frame.depends_on(), 1);
+        assert_eq!(frame.get_header().2, 0x80);
+    }
+
+    #[test]
+    fn test_parse_invalid_stream_id() {
+        let raw = prepare_frame_bytes((5, 0x2, 0, 0), vec![0, 0, 0, 1, 15]);
+        assert!(PriorityFrame::from_raw(&raw.into()).is_none());
+    }
+
+    #[test]
+    fn test_parse_invalid_payload_size() {
+        let raw = prepare_frame_bytes((4, 0x2, 0, 3), vec![0, 0, 0, 1]);
+        assert!(PriorityFrame::from_raw(&raw.into()).is_none());
+        let raw = prepare_frame_bytes((6, 0x2, 0, 3), vec![0, 0, 0, 1, 15, 0]);
+        assert!(PriorityFrame::from_raw(&raw.into()).is_none());
+    }
+
+    #[test]
+    fn test_parse_invalid_type() {
+        let raw = prepare_frame_bytes((5, 0x3, 0, 3), vec![0, 0, 0, 1, 15]);
+        assert!(PriorityFrame::from_raw(&raw.into()).is_none());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let frame = PriorityFrame::new(5, StreamDependency::new(3, 7, true));
+        let raw = serialize_frame(&frame);
+        assert_eq!(raw, prepare_frame_bytes((5, 0x2, 0, 5), vec![0x80, 0, 0, 3, 7]));
+
+        let frame = PriorityFrame::new(5, StreamDependency::new(0, 15, false));
+        let raw = serialize_frame(&frame);
+        assert_eq!(raw, prepare_frame_bytes((5, 0x2, 0, 5), vec![0, 0, 0, 0, 15]));
+    }
+}
@@ -1,7 +1,9 @@
 //! The module contains the implementation of the `SETTINGS` frame and associated flags.
 
 use std::io;
-use http::StreamId;
+use std::vec::Vec;
+use http::{StreamId, ErrorCode, DEFAULT_MAX_FRAME_SIZE, MAX_MAX_FRAME_SIZE};
+use http::window::MAX_WINDOW_SIZE;
 use http::frame::{
     FrameBuilder,
     FrameIR,
@@ -12,7 +14,8 @@ use http::frame::{
 };
 
 /// An enum that lists all valid settings that can be sent in a SETTINGS
-/// frame.
+/// frame, along with an `Unknown` variant for the ones that the spec does not
+/// define (e.g. the settings of extensions).
 ///
 /// Each setting has a value that is a 32 bit unsigned integer (6.5.1.).
 #[derive(Clone)]
@@ -26,21 +29,25 @@ pub enum HttpSetting {
     InitialWindowSize(u32),
     MaxFrameSize(u32),
     MaxHeaderListSize(u32),
+    /// A setting with an identifier that is not defined by the spec. The receiver MUST ignore
+    /// such settings (6.5.2.), but they are kept in the frame, so that it can be serialized
+    /// exactly as it was received.
+    Unknown(u16, u32),
 }
 
 impl HttpSetting {
     /// Creates a new `HttpSetting` with the correct variant corresponding to
     /// the given setting id, based on the settings IDs defined in section
-    /// 6.5.2.
-    pub fn from_id(id: u16, val: u32) -> Option<HttpSetting> {
+    /// 6.5.2. Any other id gives an `HttpSetting::Unknown`.
+    pub fn from_id(id: u16, val: u32) -> HttpSetting {
         match id {
-            1 => Some(HttpSetting::HeaderTableSize(val)),
-            2 => Some(HttpSetting::EnablePush(val)),
-            3 => Some(HttpSetting::MaxConcurrentStreams(val)),
-            4 => Some(HttpSetting::InitialWindowSize(val)),
-            5 => Some(HttpSetting::MaxFrameSize(val)),
-            6 => Some(HttpSetting::MaxHeaderListSize(val)),
-            _ => None,
+            1 => HttpSetting::HeaderTableSize(val),
+            2 => HttpSetting::EnablePush(val),
+            3 => HttpSetting::MaxConcurrentStreams(val),
+            4 => HttpSetting::InitialWindowSize(val),
+            5 => HttpSetting::MaxFrameSize(val),
+            6 => HttpSetting::MaxHeaderListSize(val),
+            _ => HttpSetting::Unknown(id, val),
         }
     }
 
@@ -54,7 +61,7 @@ impl HttpSetting {
     /// # Panics
     ///
     /// If given a buffer shorter than 6 bytes, the function will panic.
-    fn parse_setting(raw_setting: &[u8]) -> Option<HttpSetting> {
+    fn parse_setting(raw_setting: &[u8]) -> HttpSetting {
         let id: u16 = ((raw_setting[0] as u16) << 8) | (raw_setting[1] as u16);
         let val: u32 = unpack_octets_4!(raw_setting, 2, u32);
 
@@ -71,6 +78,7 @@ impl HttpSetting {
             &HttpSetting::InitialWindowSize(_) => 4,
             &HttpSetting::MaxFrameSize(_) => 5,
             &HttpSetting::MaxHeaderListSize(_) => 6,
+            &HttpSetting::Unknown(id, _) => id,
         }
     }
 
@@ -83,6 +91,25 @@ impl HttpSetting {
             &HttpSetting::InitialWindowSize(ref val) => val.clone(),
             &HttpSetting::MaxFrameSize(ref val) => val.clone(),
             &HttpSetting::MaxHeaderListSize(ref val) => val.clone(),
+            &HttpSetting::Unknown(_, ref val) => val.clone(),
+        }
+    }
+
+    /// Checks that the value of the setting is within the bounds that section 6.5.2. places on
+    /// it. If it is not, the error code of the connection error that the peer needs to be sent
+    /// is returned: a FLOW_CONTROL_ERROR for a SETTINGS_INITIAL_WINDOW_SIZE above 2^31 - 1 and a
+    /// PROTOCOL_ERROR for any other value out of bounds.
+    pub fn validate(&self) -> Result<(), ErrorCode> {
+        match *self {
+            HttpSetting::EnablePush(val) if val > 1 => Err(ErrorCode::ProtocolError),
+            HttpSetting::InitialWindowSize(val) if val > MAX_WINDOW_SIZE => {
+                Err(ErrorCode::FlowControlError)
+            },
+            HttpSetting::MaxFrameSize(val)
+                    if val < DEFAULT_MAX_FRAME_SIZE || val > MAX_MAX_FRAME_SIZE => {
+                Err(ErrorCode::ProtocolError)
+            },
+            _ => Ok(()),
         }
     }
 
@@ -135,9 +162,10 @@ impl Flag for SettingsFlag {
 /// - Payload length not a multiple of 6
 /// - Stream ID not zero (SETTINGS frames MUST be associated to stream 0)
 ///
-/// What is *not* treated as an error (for now) are settings values out of
-/// allowed bounds such as a EnablePush being set to something other than 0 or
-/// 1.
+/// What is *not* treated as an error are settings values out of allowed
+/// bounds, such as EnablePush being set to something other than 0 or 1, since
+/// those are connection errors rather than malformed frames. They can be
+/// checked with `HttpSetting::validate`.
 #[derive(PartialEq)]
 #[derive(Debug)]
 #[derive(Clone)]
@@ -199,7 +227,8 @@ impl SettingsFrame {
     ///
     /// A `Vec` of settings that are set by the given payload.
     ///
-    /// Any unknown setting is ignored, as per the HTTP/2 spec requirement.
+    /// Any unknown setting is returned as an `HttpSetting::Unknown`, for the
+    /// receiver to ignore, as per the HTTP/2 spec requirement.
     ///
     /// If the frame is invalid (i.e. the length of the payload is not a
     /// multiple of 6) it returns `None`.
@@ -210,9 +239,7 @@ impl SettingsFrame {
 
         // Iterates through chunks of the raw payload of size 6 bytes and
         // parses each of them into an `HttpSetting`
-        Some(payload.chunks(6).filter_map(|chunk| {
-            HttpSetting::parse_setting(chunk)
-        }).collect())
+        Some(payload.chunks(6).map(HttpSetting::parse_setting).collect())
     }
 
     /// Sets the given flag for the frame.
@@ -310,6 +337,7 @@ impl FrameIR for SettingsFrame {
 #[cfg(test)]
 mod tests {
     use super::{HttpSetting, SettingsFrame};
+    use http::ErrorCode;
     use http::tests::common::{raw_frame_from_parts, serialize_frame};
     use http::frame::{pack_header, Frame};
 
@@ -387,7 +415,7 @@ mod tests {
 
     /// Tests that a `SettingsFrame` correctly handles a SETTING frame with no
     /// ACK and an unknown setting within the frame. The unknown setting is
-    /// simply ignored.
+    /// kept as an `HttpSetting::Unknown`.
     #[test]
     fn test_settings_frame_parse_no_ack_unknown_setting() {
         let settings = vec![
@@ -407,13 +435,33 @@ mod tests {
         let raw = raw_frame_from_parts(header.clone(), payload.to_vec());
         let frame: SettingsFrame = Frame::from_raw(&raw).unwrap();
 
-        // All the settings are returned twice, but the unkown isn't found in
-        // the returned Vec. For now, we ignore the unknown setting fully, not
-        // exposing it in any way to any other higher-level clients.
-        assert_eq!(frame.settings.len(), 4);
+        // All the settings are returned twice, with the unknown one in between.
+        assert_eq!(frame.settings.len(), 5);
         assert_eq!(&frame.settings[0..2], &settings[..]);
-        assert_eq!(&frame.settings[2..], &settings[..]);
+        assert_eq!(frame.settings[2], HttpSetting::Unknown(10, 0));
+        assert_eq!(&frame.settings[3..], &settings[..]);
         assert!(!frame.is_ack());
+        // The unknown setting is serialized back unchanged.
+        assert_eq!(serialize_frame(&frame), raw.as_ref().to_vec());
+    }
+
+    /// Tests that the values of settings are checked against their bounds.
+    #[test]
+    fn test_setting_validate() {
+        assert_eq!(HttpSetting::EnablePush(1).validate(), Ok(()));
+        assert_eq!(HttpSetting::EnablePush(2).validate(), Err(ErrorCode::ProtocolError));
+        assert_eq!(HttpSetting::InitialWindowSize(0x7fffffff).validate(), Ok(()));
+        assert_eq!(HttpSetting::InitialWindowSize(0x80000000).validate(),
+                   Err(ErrorCode::FlowControlError));
+        assert_eq!(HttpSetting::MaxFrameSize(16_384).validate(), Ok(()));
+        assert_eq!(HttpSetting::MaxFrameSize(0xffffff).validate(), Ok(()));
+        assert_eq!(HttpSetting::MaxFrameSize(16_383).validate(), Err(ErrorCode::ProtocolError));
+        assert_eq!(HttpSetting::MaxFrameSize(0x1000000).validate(),
+                   Err(ErrorCode::ProtocolError));
+        assert_eq!(HttpSetting::MaxConcurrentStreams(0).validate(), Ok(()));
+        assert_eq!(HttpSetting::Unknown(7, 0xffffffff).validate(), Ok(()));
+        assert_eq!(HttpSetting::from_id(7, 1), HttpSetting::Unknown(7, 1));
+        assert_eq!(HttpSetting::Unknown(7, 1).get_id(), 7);
     }
 
     /// Tests that a `SettingsFrame` correctly handles a SETTINGS frame with an
@@ -543,42 +591,42 @@ mod tests {
         {
             let buf = [0, 1, 0, 0, 1, 0];
 
-            let setting = HttpSetting::parse_setting(&buf).unwrap();
+            let setting = HttpSetting::parse_setting(&buf);
 
             assert_eq!(setting, HttpSetting::HeaderTableSize(1 << 8));
         }
         {
             let buf = [0, 2, 0, 0, 0, 1];
 
-            let setting = HttpSetting::parse_setting(&buf).unwrap();
+            let setting = HttpSetting::parse_setting(&buf);
 
             assert_eq!(setting, HttpSetting::EnablePush(1));
         }
         {
             let buf = [0, 3, 0, 0, 0, 0];
 
-            let setting = HttpSetting::parse_setting(&buf).unwrap();
+            let setting = HttpSetting::parse_setting(&buf);
 
             assert_eq!(setting, HttpSetting::MaxConcurrentStreams(0));
         }
         {
             let buf = [0, 4, 0, 0, 0, 1];
 
-            let setting = HttpSetting::parse_setting(&buf).unwrap();
+            let setting = HttpSetting::parse_setting(&buf);
 
             assert_eq!(setting, HttpSetting::InitialWindowSize(1));
         }
         {
             let buf = [0, 5, 0, 0, 0, 255];
 
-            let setting = HttpSetting::parse_setting(&buf).unwrap();
+            let setting = HttpSetting::parse_setting(&buf);
 
             assert_eq!(setting, HttpSetting::MaxFrameSize((1 << 8) - 1));
         }
         {
             let buf = [0, 6, 0, 0, 0, 255];
 
-            let setting = HttpSetting::parse_setting(&buf).unwrap();
+            let setting = HttpSetting::parse_setting(&buf);
 
             assert_eq!(setting, HttpSetting::MaxHeaderListSize((1 << 8) - 1));
         }
@@ -587,14 +635,14 @@ mod tests {
 
             let setting = HttpSetting::parse_setting(&buf);
 
-            assert!(setting.is_none());
+            assert_eq!(setting, HttpSetting::Unknown(7, 255));
         }
         {
             let buf = [0, 0, 0, 0, 0, 255];
 
             let setting = HttpSetting::parse_setting(&buf);
 
-            assert!(setting.is_none());
+            assert_eq!(setting, HttpSetting::Unknown(0, 255));
         }
     }
 
@@ -0,0 +1,136 @@
+//! The module implements the tracking of the header phase of an HTTP/2 stream, i.e. where in the
+//! sequence of header blocks and DATA frames that make up a message the stream currently is, as
+//! described in [section 8.1.](http://http2.github.io/http2-spec/#rfc.section.8.1) of the spec.
+//!
+//! A message consists of any number of informational (1xx) header blocks, a single header block
+//! that starts the message, any number of DATA frames and an optional trailer block. A peer that
+//! deviates from the sequence (e.g. sending a second non-trailer header block or anything after
+//! the end of the stream) produces a malformed message.
+//!
+//! Like the stream state machine, the header phase is independent of the way in which frames are
+//! exchanged.
+use std::error::Error;
+use std::fmt;
+
+/// The enum represents all the phases that the message of an HTTP/2 stream can be found in.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HeaderPhase {
+    /// The header block that starts the message is yet to be received. Informational header
+    /// blocks keep the stream in this phase.
+    Initial,
+    /// The header block that starts the message was received; the body follows.
+    Data,
+    /// A trailer block was received, after which the stream must end.
+    Trailers,
+    /// The stream ended; nothing is allowed to follow.
+    Done,
+}
+
+/// The enum represents the events that drive a stream's message through the header phases.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HeaderEvent {
+    /// A complete header block was received. `informational` indicates that it carried a 1xx
+    /// response status.
+    Headers { informational: bool },
+    /// A header block that is explicitly meant to carry trailers, as when sending them. Unlike
+    /// with `Headers`, the block can never start the message.
+    Trailers,
+    /// A DATA frame was received.
+    Data,
+    /// A frame with the END_STREAM flag was received.
+    EndStream,
+}
+
+/// The error returned when an event is not allowed to happen in a stream's current header phase.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct HeaderPhaseError {
+    /// The phase the stream was in.
+    pub phase: HeaderPhase,
+    /// The event that is illegal in that phase.
+    pub event: HeaderEvent,
+}
+
+impl fmt::Display for HeaderPhaseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "HeaderPhaseError: {:?} in phase {:?}", self.event, self.phase)
+    }
+}
+
+impl Error for HeaderPhaseError {
+    fn description(&self) -> &str {
+        "Illegal header phase transition"
+    }
+}
+
+impl HeaderPhase {
+    /// Returns the phase that follows this one once the given event happens, or an error if the
+    /// event is not allowed in this phase.
+    pub fn transition(self, event: HeaderEvent) -> Result<HeaderPhase, HeaderPhaseError> {
+        use self::HeaderPhase::*;
+
+        let next = match (self, event) {
+            (Initial, HeaderEvent::Headers { informational: true }) => Some(Initial),
+            (Initial, HeaderEvent::Headers { informational: false }) => Some(Data),
+
+            (Data, HeaderEvent::Data) => Some(Data),
+            // A header block following the one that started the message can only carry trailers,
+            // which is why it must never be informational.
+            (Data, HeaderEvent::Headers { informational: false }) => Some(Trailers),
+            (Data, HeaderEvent::Trailers) => Some(Trailers),
+
+            (Data, HeaderEvent::EndStream) | (Trailers, HeaderEvent::EndStream) => Some(Done),
+
+            _ => None,
+        };
+
+        next.ok_or(HeaderPhaseError { phase: self, event: event })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HeaderEvent, HeaderPhaseError};
+
+    /// Tests that the `HeaderPhase` transitions follow the sequence of a message.
+    #[test]
+    fn test_header_phase_transitions() {
+        use super::HeaderPhase::*;
+
+        let informational = HeaderEvent::Headers { informational: true };
+        let headers = HeaderEvent::Headers { informational: false };
+        // A response preceded by a 100 Continue, with a body and trailers.
+        assert_eq!(Initial.transition(informational), Ok(Initial));
+        assert_eq!(Initial.transition(headers), Ok(Data));
+        assert_eq!(Data.transition(HeaderEvent::Data), Ok(Data));
+        assert_eq!(Data.transition(headers), Ok(Trailers));
+        assert_eq!(Data.transition(HeaderEvent::Trailers), Ok(Trailers));
+        assert_eq!(Trailers.transition(HeaderEvent::EndStream), Ok(Done));
+        // A message without trailers.
+        assert_eq!(Data.transition(HeaderEvent::EndStream), Ok(Done));
+    }
+
+    /// Tests that out of order events are rejected by the `HeaderPhase` transitions.
+    #[test]
+    fn test_header_phase_illegal_transitions() {
+        use super::HeaderPhase::*;
+
+        let informational = HeaderEvent::Headers { informational: true };
+        let headers = HeaderEvent::Headers { informational: false };
+        let illegal = [
+            (Initial, HeaderEvent::Data),
+            (Initial, HeaderEvent::EndStream),
+            (Initial, HeaderEvent::Trailers),
+            (Data, informational),
+            (Trailers, headers),
+            (Trailers, HeaderEvent::Data),
+            (Trailers, HeaderEvent::Trailers),
+            (Done, headers),
+            (Done, HeaderEvent::Data),
+            (Done, HeaderEvent::EndStream),
+        ];
+        for &(phase, event) in illegal.iter() {
+            assert_eq!(phase.transition(event),
+                       Err(HeaderPhaseError { phase: phase, event: event }));
+        }
+    }
+}
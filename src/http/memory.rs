@@ -0,0 +1,96 @@
+//! The module implements the accounting behind the memory budget of a connection.
+//!
+//! The memory that a peer can make a connection hold on to is spread over several places: the
+//! HPACK dynamic tables, the buffers of the streams (received bodies that are yet to be consumed
+//! and responses that are yet to be sent) and the frames queued for sending. A `MemoryUsage`
+//! gathers all of them, so that a single limit (`Http2Config::memory_budget`) can bound the
+//! worst-case memory of a connection.
+//!
+//! The usage is compared against the budget to get the `MemoryPressure` of the connection, which
+//! determines how much load it needs to shed:
+//!
+//! - within the budget, nothing is done;
+//! - over the budget, new streams are refused and the flow control windows of the streams are
+//!   shrunk to nothing, so that the peer stops adding to the usage while it goes back down;
+//! - over twice the budget, the peer is clearly not backing off, so the connection is closed.
+
+/// A breakdown of the memory that a connection holds on to, in bytes.
+///
+/// The figures are estimates of the payloads, not counting the bookkeeping around them, so they
+/// are only meant to be compared against a budget.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct MemoryUsage {
+    /// The (largest) size of the HPACK dynamic tables of both directions.
+    pub header_tables: usize,
+    /// The data buffered by the streams, as reported by `Stream::buffered_len`.
+    pub streams: usize,
+    /// The frames and header blocks that are waiting to be sent or to be completed.
+    pub queued: usize,
+}
+
+impl MemoryUsage {
+    /// Returns the total usage.
+    #[inline]
+    pub fn total(&self) -> usize {
+        self.header_tables + self.streams + self.queued
+    }
+}
+
+/// How close a connection is to exhausting its memory budget.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum MemoryPressure {
+    /// The usage is within the budget.
+    Normal,
+    /// The usage is over the budget: no new streams are accepted and the peer is not allowed to
+    /// send any more DATA on the streams, until the usage is back within the budget.
+    Shedding,
+    /// The usage is over twice the budget: the connection needs to be closed.
+    Exhausted,
+}
+
+impl MemoryPressure {
+    /// Returns the pressure that the given usage puts on a connection with the given budget. A
+    /// connection without a budget is never under any pressure.
+    pub fn of(usage: &MemoryUsage, budget: Option<usize>) -> MemoryPressure {
+        let budget = match budget {
+            Some(budget) => budget,
+            None => return MemoryPressure::Normal,
+        };
+        let total = usage.total();
+        if total <= budget {
+            MemoryPressure::Normal
+        } else if total <= budget.saturating_mul(2) {
+            MemoryPressure::Shedding
+        } else {
+            MemoryPressure::Exhausted
+        }
+    }
+}
+
+/// Returns the estimated size of the given header list: the lengths of the names and values,
+/// along with the 32 bytes that HPACK accounts for each entry (see section 4.1 of RFC 7541).
+pub fn header_list_size<'a, I>(headers: I) -> usize
+        where I: IntoIterator<Item=(&'a [u8], &'a [u8])> {
+    headers.into_iter().map(|(name, value)| name.len() + value.len() + 32).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryUsage, MemoryPressure, header_list_size};
+
+    /// Tests that the pressure grows as the usage goes over the budget and then twice over it.
+    #[test]
+    fn test_memory_pressure() {
+        let usage = |total: usize| {
+            MemoryUsage { header_tables: 100, streams: total - 150, queued: 50 }
+        };
+
+        assert_eq!(usage(1000).total(), 1000);
+        assert_eq!(MemoryPressure::of(&usage(1_000_000), None), MemoryPressure::Normal);
+        assert_eq!(MemoryPressure::of(&usage(1000), Some(1000)), MemoryPressure::Normal);
+        assert_eq!(MemoryPressure::of(&usage(1001), Some(1000)), MemoryPressure::Shedding);
+        assert_eq!(MemoryPressure::of(&usage(2000), Some(1000)), MemoryPressure::Shedding);
+        assert_eq!(MemoryPressure::of(&usage(2001), Some(1000)), MemoryPressure::Exhausted);
+        assert_eq!(header_list_size(vec![(&b"ab"[..], &b"c"[..])]), 35);
+    }
+}
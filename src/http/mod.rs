@@ -1,168 +1,132 @@
 //! The module implements the framing layer of HTTP/2 and exposes an API for using it.
+//!
+//! Without the `std` feature, only the layers that do not depend on any IO are available: the
+//! frames (`frame`), the settings (`settings` and `config`) and the stream state machine
+//! (`stream_state` and `header_phase`), along with the basic types defined here. Frames are then
+//! serialized into the minimal `io::Cursor<Vec<u8>>` stand-in that the crate provides in place of
+//! `std::io`.
+#[cfg(feature="std")]
 use std::io;
 use std::fmt;
-use std::borrow::Cow;
 use std::convert::From;
 use std::error::Error;
+use std::vec::Vec;
 
-use hpack::decoder::DecoderError;
+#[cfg(feature="std")]
+use hpack::decoder::{DecoderError, StringDecodingError};
+
+#[cfg(feature="std")]
+use self::validation::MalformedHeaders;
+#[cfg(feature="std")]
+use self::stream_id::StreamIdError;
+#[cfg(feature="std")]
+use self::header_phase::HeaderPhaseError;
 
 pub mod frame;
+pub mod config;
+pub mod settings;
+pub mod stream_state;
+pub mod header_phase;
+pub mod window;
+#[cfg(feature="std")]
+pub mod stream_id;
+#[cfg(feature="std")]
 pub mod transport;
+#[cfg(feature="std")]
+pub mod duplex;
+#[cfg(feature="std")]
+pub mod fault;
+#[cfg(feature="std")]
+pub mod recording;
+#[cfg(feature="std")]
 pub mod connection;
+#[cfg(feature="std")]
 pub mod session;
+#[cfg(feature="std")]
 pub mod priority;
-
+#[cfg(feature="std")]
+pub mod scheduler;
+#[cfg(feature="std")]
+pub mod static_table;
+#[cfg(feature="std")]
+pub mod flow_control;
+#[cfg(feature="std")]
+pub mod memory;
+#[cfg(feature="std")]
+pub mod soak;
+#[cfg(feature="std")]
+pub mod tracing;
+#[cfg(feature="std")]
+pub mod rate_limit;
+#[cfg(feature="std")]
+pub mod padding;
+#[cfg(feature="std")]
+pub mod validation;
+#[cfg(feature="std")]
+pub mod checksum;
+#[cfg(feature="std")]
+pub mod semantics;
+
+#[cfg(feature="std")]
 pub mod client;
+#[cfg(feature="std")]
 pub mod server;
 
+pub use self::window::WindowSize;
+
 /// The initial size of the connections' flow control window.
 pub const INITIAL_CONNECTION_WINDOW_SIZE: i32 = 65_535;
+/// The initial size of the streams' flow control windows, unless changed by the
+/// SETTINGS_INITIAL_WINDOW_SIZE setting.
+pub const INITIAL_STREAM_WINDOW_SIZE: i32 = 65_535;
+/// The maximum frame payload size that can be sent, unless the peer allows larger frames with the
+/// SETTINGS_MAX_FRAME_SIZE setting.
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16_384;
+/// The largest frame payload size that can be allowed by SETTINGS_MAX_FRAME_SIZE (2^24 - 1).
+pub const MAX_MAX_FRAME_SIZE: u32 = 0xffffff;
+/// The size of the HPACK dynamic tables, unless changed by the SETTINGS_HEADER_TABLE_SIZE
+/// setting.
+pub const DEFAULT_HEADER_TABLE_SIZE: u32 = 4_096;
+
+#[cfg(feature="std")]
+pub use self::semantics::{
+    Header,
+    HeaderPart,
+    OwnedHeader,
+    StaticHeader,
+    PseudoHeaders,
+    Request,
+    Response,
+    ResponseHead,
+    StaticResponse,
+    Method,
+    StatusCode,
+};
 
 /// An alias for the type that represents the ID of an HTTP/2 stream
 pub type StreamId = u32;
-/// An alias for the type that represents an HTTP/2 header where both the name and the value is
-/// owned.
-pub type OwnedHeader = (Vec<u8>, Vec<u8>);
-
-/// A convenience struct representing a part of a header (either the name or the value) that can be
-/// either an owned or a borrowed byte sequence.
-pub struct HeaderPart<'a>(Cow<'a, [u8]>);
-impl<'a> From<Vec<u8>> for HeaderPart<'a> {
-    fn from(vec: Vec<u8>) -> HeaderPart<'a> {
-        HeaderPart(Cow::Owned(vec))
-    }
-}
-
-impl<'a> From<&'a [u8]> for HeaderPart<'a> {
-    fn from(buf: &'a [u8]) -> HeaderPart<'a> {
-        HeaderPart(Cow::Borrowed(buf))
-    }
-}
-
-impl<'a> From<Cow<'a, [u8]>> for HeaderPart<'a> {
-    fn from(cow: Cow<'a, [u8]>) -> HeaderPart<'a> {
-        HeaderPart(cow)
-    }
-}
-
-macro_rules! from_static_size_array {
-    ($N:expr) => (
-        impl<'a> From<&'a [u8; $N]> for HeaderPart<'a> {
-            fn from(buf: &'a [u8; $N]) -> HeaderPart<'a> {
-                buf[..].into()
-            }
-        }
-    );
-}
-
-macro_rules! impl_from_static_size_array {
-    ($($N:expr,)+) => {
-        $(
-            from_static_size_array!($N);
-        )+
-    }
-}
 
-impl_from_static_size_array!(
-    0,
-    1,
-    2,
-    3,
-    4,
-    5,
-    6,
-    7,
-    8,
-    9,
-    10,
-);
-
-impl<'n, 'v> PartialEq<Header<'n, 'v>> for OwnedHeader {
-    fn eq(&self, other: &Header<'n, 'v>) -> bool {
-        &self.0[..] == other.name() && &self.1[..] == other.value()
-    }
-}
-
-impl<'n, 'v> PartialEq<OwnedHeader> for Header<'n, 'v> {
-    fn eq(&self, other: &OwnedHeader) -> bool {
-        &other.0[..] == self.name() && &other.1[..] == self.value()
-    }
-}
-
-/// Represents an HTTP/2 header. Allows both the name and the value to be either an owned or a
-/// borrowed byte sequence.
-///
-/// # Examples
-///
-/// A new `Header` can be created by providing an owned or borrowed name, as well as value:
+/// The ID of an HTTP/2 connection, unique among all connections created by the process.
 ///
-/// ```rust
-/// use solicit::http::Header;
-/// // Name and value both borrowed (static) slices.
-/// let h1 = Header::new(b":method", b"GET");
-/// assert_eq!(h1.name(), &b":method"[..]);
-/// assert_eq!(h1.value(), &b"GET"[..]);
-/// // A borrowed slice with a scope-bound lifetime as the value; static name
-/// {
-///     let value = vec![1];
-///     let header = Header::new(&b"x-test-head"[..], &value[..]);
-///     assert_eq!(header.name(), &b"x-test-head"[..]);
-///     assert_eq!(header.value(), &[1][..]);
-/// }
-/// // An owned value, static name
-/// {
-///     let value = vec![1];
-///     let header = Header::new(&b"x-test-head"[..], value);
-///     assert_eq!(header.name(), &b"x-test-head"[..]);
-///     assert_eq!(header.value(), &[1][..]);
-/// }
-/// // An owned name, as well as value
-/// {
-///     let value = vec![1];
-///     let name = b"x-test-head".to_vec();
-///     let header = Header::new(name, value);
-///     assert_eq!(header.name(), &b"x-test-head"[..]);
-///     assert_eq!(header.value(), &[1][..]);
-/// }
-/// ```
-#[derive(Debug, Clone, PartialEq)]
-pub struct Header<'n, 'v> {
-    name: Cow<'n, [u8]>,
-    value: Cow<'v, [u8]>,
-}
-
-/// A type alias for a `Header` where both the name, as well as the value must have a `'static`
-/// lifetime if it is borrowed. Owned parts are allowed.
-pub type StaticHeader = Header<'static, 'static>;
-
-impl<'n, 'v> Header<'n, 'v> {
-    /// Creates a new `Header` with the given name and value.
-    ///
-    /// The name and value need to be convertible into a `HeaderPart`.
-    pub fn new<N: Into<HeaderPart<'n>>, V: Into<HeaderPart<'v>>>(name: N, value: V)
-            -> Header<'n, 'v> {
-        Header {
-            name: name.into().0,
-            value: value.into().0,
-        }
+/// Each `HttpConnection` is assigned a new ID when it is created, right as the connection preface
+/// exchange starts, so that applications juggling several connections can attribute errors, log
+/// lines and stats to a particular one of them.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct ConnectionId(pub u64);
+
+#[cfg(feature="std")]
+impl ConnectionId {
+    /// Returns a new ID, different from all the ones returned before.
+    pub fn next() -> ConnectionId {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+        ConnectionId(NEXT_ID.fetch_add(1, Ordering::Relaxed) as u64)
     }
-
-    /// Return a borrowed representation of the `Header` name.
-    pub fn name(&self) -> &[u8] { &self.name }
-    /// Return a borrowed representation of the `Header` value.
-    pub fn value(&self) -> &[u8] { &self.value }
 }
 
-impl<'n, 'v> Into<OwnedHeader> for Header<'n, 'v> {
-    fn into(self) -> OwnedHeader {
-        (self.name.into_owned(), self.value.into_owned())
-    }
-}
-
-impl<'n, 'v> Into<Header<'n, 'v>> for OwnedHeader {
-    fn into(self) -> Header<'n, 'v> {
-        Header::new(self.0, self.1)
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "conn-{}", self.0)
     }
 }
 
@@ -179,6 +143,10 @@ pub const ALPN_PROTOCOLS: &'static [&'static [u8]] = &[
     b"h2-14",
 ];
 
+/// The sequence of octets that every client connection preface starts with (i.e. the connection
+/// "magic"). It is followed by the client's SETTINGS frame.
+pub const CLIENT_PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
 /// The enum represents an error code that are used in `RST_STREAM` and `GOAWAY` frames.
 /// These are defined in [Section 7](http://http2.github.io/http2-spec/#ErrorCodes) of the HTTP/2
 /// spec.
@@ -221,10 +189,11 @@ pub enum ErrorCode {
     Http11Required = 0xd,
 }
 
-impl From<u32> for ErrorCode {
-    /// Converts the given `u32` number to the appropriate `ErrorCode` variant.
-    fn from(code: u32) -> ErrorCode {
-        match code {
+impl ErrorCode {
+    /// Returns the variant for the given numeric error code, or `None` if the code is not one of
+    /// those defined by the spec.
+    pub fn from_u32(code: u32) -> Option<ErrorCode> {
+        Some(match code {
             0x0 => ErrorCode::NoError,
             0x1 => ErrorCode::ProtocolError,
             0x2 => ErrorCode::InternalError,
@@ -239,13 +208,47 @@ impl From<u32> for ErrorCode {
             0xb => ErrorCode::EnhanceYourCalm,
             0xc => ErrorCode::InadequateSecurity,
             0xd => ErrorCode::Http11Required,
-            // According to the spec, unknown error codes MAY be treated as equivalent to
-            // INTERNAL_ERROR.
-            _ => ErrorCode::InternalError,
+            _ => return None,
+        })
+    }
+
+    /// Returns the name of the error code, as the spec spells it (e.g. `PROTOCOL_ERROR`).
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ErrorCode::NoError => "NO_ERROR",
+            ErrorCode::ProtocolError => "PROTOCOL_ERROR",
+            ErrorCode::InternalError => "INTERNAL_ERROR",
+            ErrorCode::FlowControlError => "FLOW_CONTROL_ERROR",
+            ErrorCode::SettingsTimeout => "SETTINGS_TIMEOUT",
+            ErrorCode::StreamClosed => "STREAM_CLOSED",
+            ErrorCode::FrameSizeError => "FRAME_SIZE_ERROR",
+            ErrorCode::RefusedStream => "REFUSED_STREAM",
+            ErrorCode::Cancel => "CANCEL",
+            ErrorCode::CompressionError => "COMPRESSION_ERROR",
+            ErrorCode::ConnectError => "CONNECT_ERROR",
+            ErrorCode::EnhanceYourCalm => "ENHANCE_YOUR_CALM",
+            ErrorCode::InadequateSecurity => "INADEQUATE_SECURITY",
+            ErrorCode::Http11Required => "HTTP_1_1_REQUIRED",
         }
     }
 }
 
+impl From<u32> for ErrorCode {
+    /// Converts the given `u32` number to the appropriate `ErrorCode` variant.
+    fn from(code: u32) -> ErrorCode {
+        // According to the spec, unknown error codes MAY be treated as equivalent to
+        // INTERNAL_ERROR.
+        ErrorCode::from_u32(code).unwrap_or(ErrorCode::InternalError)
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    /// Shows the name of the error code along with its value, e.g. `PROTOCOL_ERROR (0x1)`.
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{} (0x{:x})", self.name(), *self as u32)
+    }
+}
+
 impl AsRef<str> for ErrorCode {
     fn as_ref(&self) -> &str {
         match *self {
@@ -279,6 +282,7 @@ impl Into<u32> for ErrorCode {
 pub struct ConnectionError {
     error_code: ErrorCode,
     debug_data: Option<Vec<u8>>,
+    connection_id: Option<ConnectionId>,
 }
 
 impl ConnectionError {
@@ -287,6 +291,7 @@ impl ConnectionError {
         ConnectionError {
             error_code: error_code,
             debug_data: None,
+            connection_id: None,
         }
     }
     /// Creates a new `ConnectionError` with the given associated debug data.
@@ -294,8 +299,14 @@ impl ConnectionError {
         ConnectionError {
             error_code: error_code,
             debug_data: Some(debug_data),
+            connection_id: None,
         }
     }
+    /// Attributes the error to the connection with the given ID.
+    pub fn on_connection(mut self, connection_id: ConnectionId) -> ConnectionError {
+        self.connection_id = Some(connection_id);
+        self
+    }
 
     /// The error code of the underlying error.
     pub fn error_code(&self) -> ErrorCode {
@@ -309,11 +320,22 @@ impl ConnectionError {
     pub fn debug_str(&self) -> Option<&str> {
         self.debug_data().and_then(|data| ::std::str::from_utf8(data).ok())
     }
+    /// The ID of the connection that the error arose on, if it is known.
+    pub fn connection_id(&self) -> Option<ConnectionId> {
+        self.connection_id
+    }
 }
 
 impl fmt::Display for ConnectionError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmt, "ConnectionError: {}", self.description())
+        try!(match self.debug_str() {
+            Some(debug) => write!(fmt, "ConnectionError: {}: {}", self.error_code, debug),
+            None => write!(fmt, "ConnectionError: {}", self.error_code),
+        });
+        match self.connection_id {
+            Some(id) => write!(fmt, " (on {})", id),
+            None => Ok(()),
+        }
     }
 }
 
@@ -327,6 +349,7 @@ impl Error for ConnectionError {
 
 /// An enum representing errors that can arise when performing operations involving an HTTP/2
 /// connection.
+#[cfg(feature="std")]
 #[derive(Debug)]
 pub enum HttpError {
     /// The underlying IO layer raised an error
@@ -345,34 +368,123 @@ pub enum HttpError {
     UnknownStreamId,
     UnableToConnect,
     MalformedResponse,
+    /// The connection was closed by the local peer (with a GOAWAY frame) since it was idle for
+    /// longer than the configured timeout.
+    ConnectionIdle,
+    /// The connection was closed by the local peer (with a GOAWAY frame) since the memory that it
+    /// held on to grew past twice its budget.
+    MemoryBudgetExceeded,
+    /// The connection was closed by the local peer (with a GOAWAY frame) since the peer kept a
+    /// stream blocked on flow control for longer than the configured timeout, i.e. it stopped
+    /// reading.
+    SlowConsumer,
+    /// The peer closed the underlying transport stream (i.e. an EOF was reached where a new
+    /// frame was expected). The close is considered graceful if the peer sent a GOAWAY frame
+    /// before closing the connection.
+    PeerClosed { graceful: bool },
+    /// The peer did not complete the connection preface within the allotted time.
+    HandshakeTimeout,
+    /// The peer sent an invalid connection preface (i.e. not the HTTP/2 client magic).
+    InvalidPreface,
+    /// The local peer detected a connection error of the given type caused by the peer (e.g. a
+    /// frame that is not allowed in the current state of its stream). The connection cannot be
+    /// used any further and should be terminated with a GOAWAY frame carrying the error code.
+    LocalConnectionError(ErrorCode),
+    /// The local peer attempted to send a malformed header block. Nothing was sent to the peer.
+    MalformedHeaders(MalformedHeaders),
+    /// The local peer attempted to initiate a stream with an ID that it cannot use (or it ran out
+    /// of stream IDs). Nothing was sent to the peer.
+    InvalidStreamId(StreamIdError),
+    /// The local peer attempted to send a WINDOW_UPDATE frame with the given increment, which is
+    /// either 0 or would take the window past the maximum size (2^31 - 1). Nothing was sent to
+    /// the peer.
+    InvalidWindowIncrement(u32),
+    /// The local peer attempted to send a header block or DATA out of sequence on a stream (e.g.
+    /// trailers on a stream that it already ended). Nothing was sent to the peer.
+    InvalidHeaderPhase(HeaderPhaseError),
+    /// The peer did not start the connection with an HTTP/2 preface, most likely because it does
+    /// not speak HTTP/2 at all (e.g. an HTTP/1.1 server). The bytes that it sent instead are
+    /// included in the error.
+    NotHttp2(PrefaceMismatch),
+    /// The connection failed with a fatal error earlier on, so it cannot be used any further.
+    ConnectionClosed,
+    /// The server requires HTTP/1.1 to be used instead of HTTP/2, which it signaled with the
+    /// HTTP_1_1_REQUIRED error code, either for a single request (in a RST_STREAM frame) or for
+    /// the whole connection (in a GOAWAY frame).
+    Http11Required,
     Other(Box<Error + Send + Sync>),
 }
 
 /// Implement the trait that allows us to automatically convert `io::Error`s
 /// into an `HttpError` by wrapping the given `io::Error` into an `HttpError::IoError` variant.
+#[cfg(feature="std")]
 impl From<io::Error> for HttpError {
     fn from(err: io::Error) -> HttpError {
         HttpError::IoError(err)
     }
 }
 
+#[cfg(feature="std")]
 impl fmt::Display for HttpError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmt, "HTTP/2 Error: {}", self.description())
+        match *self {
+            HttpError::NotHttp2(ref mismatch) => write!(fmt, "HTTP/2 Error: {}", mismatch),
+            HttpError::PeerConnectionError(ref err) => write!(fmt, "HTTP/2 Error: {}", err),
+            HttpError::LocalConnectionError(code) => {
+                write!(fmt, "HTTP/2 Error: Connection error {}", code)
+            },
+            _ => write!(fmt, "HTTP/2 Error: {}", self.description()),
+        }
     }
 }
 
+#[cfg(feature="std")]
 impl Error for HttpError {
     fn description(&self) -> &str {
         match *self {
             HttpError::IoError(_) => "Encountered an IO error",
             HttpError::InvalidFrame => "Encountered an invalid HTTP/2 frame",
             HttpError::PeerConnectionError(ref err) => err.description(),
-            HttpError::CompressionError(_) => "Encountered an error with HPACK compression",
+            HttpError::CompressionError(ref e) => match *e {
+                DecoderError::HeaderIndexOutOfBounds => {
+                    "HPACK: a header field referenced an index outside of the header table"
+                },
+                DecoderError::IntegerDecodingError(_) => {
+                    "HPACK: a header field contained an invalid integer"
+                },
+                DecoderError::StringDecodingError(StringDecodingError::NotEnoughOctets) => {
+                    "HPACK: a header field contained a truncated string literal"
+                },
+                DecoderError::StringDecodingError(StringDecodingError::HuffmanDecoderError(_)) => {
+                    "HPACK: a header field contained an invalid Huffman-encoded string literal"
+                },
+                DecoderError::InvalidMaxDynamicSize => {
+                    "HPACK: a dynamic table size update exceeded the allowed maximum"
+                },
+            },
             HttpError::WindowSizeOverflow => "The connection flow control window overflowed",
             HttpError::UnknownStreamId => "Attempted an operation with an unknown HTTP/2 stream ID",
             HttpError::UnableToConnect => "An error attempting to establish an HTTP/2 connection",
             HttpError::MalformedResponse => "The received response was malformed",
+            HttpError::ConnectionIdle => "The connection was closed after being idle for too long",
+            HttpError::MemoryBudgetExceeded => {
+                "The connection was closed for exceeding its memory budget"
+            },
+            HttpError::SlowConsumer => "The connection was closed since the peer stopped reading",
+            HttpError::PeerClosed { graceful: true } => "The peer closed the connection gracefully",
+            HttpError::PeerClosed { graceful: false } => "The peer closed the connection abruptly",
+            HttpError::HandshakeTimeout => "The peer did not send the connection preface in time",
+            HttpError::InvalidPreface => "The peer sent an invalid connection preface",
+            HttpError::LocalConnectionError(ref code) => code.as_ref(),
+            HttpError::MalformedHeaders(ref e) => e.description(),
+            HttpError::InvalidStreamId(ref e) => e.description(),
+            HttpError::InvalidWindowIncrement(_) => {
+                "Attempted to send a WINDOW_UPDATE frame with an invalid increment"
+            },
+            HttpError::InvalidHeaderPhase(ref e) => e.description(),
+            HttpError::NotHttp2(_) => "The peer does not appear to speak HTTP/2",
+            HttpError::ConnectionClosed => "The connection is closed after an earlier error",
+            HttpError::Http11Required => "The server requires HTTP/1.1 to be used",
             HttpError::Other(_) => "An unknown error",
         }
     }
@@ -382,12 +494,51 @@ impl Error for HttpError {
             HttpError::Other(ref e) => Some(&**e),
             HttpError::IoError(ref e) => Some(e),
             HttpError::PeerConnectionError(ref e) => Some(e),
+            HttpError::MalformedHeaders(ref e) => Some(e),
+            HttpError::InvalidStreamId(ref e) => Some(e),
+            HttpError::InvalidHeaderPhase(ref e) => Some(e),
             _ => None,
         }
     }
 }
 
+#[cfg(feature="std")]
+impl HttpError {
+    /// Returns the error code that the local peer needs to close the connection with (i.e. send
+    /// in a GOAWAY frame) if the error is a connection error caused by the peer, as defined in
+    /// [section 5.4.1.](http://http2.github.io/http2-spec/#rfc.section.5.4.1) of the spec.
+    ///
+    /// Returns `None` for all other errors (e.g. IO errors, or the peer closing the connection).
+    pub fn connection_error_code(&self) -> Option<ErrorCode> {
+        match *self {
+            HttpError::CompressionError(_) => Some(ErrorCode::CompressionError),
+            HttpError::WindowSizeOverflow => Some(ErrorCode::FlowControlError),
+            HttpError::LocalConnectionError(error_code) => Some(error_code),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the connection cannot be used any further after the error.
+    ///
+    /// Errors that concern a single operation, after which nothing (or only a stream reset) was
+    /// sent to the peer, leave the connection usable; all others mean that the transport is broken
+    /// or that one of the peers is closing the connection.
+    pub fn is_fatal(&self) -> bool {
+        match *self {
+            HttpError::UnknownStreamId |
+            HttpError::MalformedResponse |
+            HttpError::MalformedHeaders(_) |
+            HttpError::InvalidStreamId(_) |
+            HttpError::InvalidWindowIncrement(_) |
+            HttpError::InvalidHeaderPhase(_) |
+            HttpError::Other(_) => false,
+            _ => true,
+        }
+    }
+}
+
 /// Implementation of the `PartialEq` trait as a convenience for tests.
+#[cfg(feature="std")]
 #[cfg(test)]
 impl PartialEq for HttpError {
     fn eq(&self, other: &HttpError) -> bool {
@@ -399,9 +550,32 @@ impl PartialEq for HttpError {
             (&HttpError::CompressionError(ref e1), &HttpError::CompressionError(ref e2)) => {
                 e1 == e2
             },
+            (&HttpError::WindowSizeOverflow, &HttpError::WindowSizeOverflow) => true,
             (&HttpError::UnknownStreamId, &HttpError::UnknownStreamId) => true,
             (&HttpError::UnableToConnect, &HttpError::UnableToConnect) => true,
             (&HttpError::MalformedResponse, &HttpError::MalformedResponse) => true,
+            (&HttpError::ConnectionIdle, &HttpError::ConnectionIdle) => true,
+            (&HttpError::MemoryBudgetExceeded, &HttpError::MemoryBudgetExceeded) => true,
+            (&HttpError::SlowConsumer, &HttpError::SlowConsumer) => true,
+            (&HttpError::PeerClosed { graceful: g1 }, &HttpError::PeerClosed { graceful: g2 }) => {
+                g1 == g2
+            },
+            (&HttpError::HandshakeTimeout, &HttpError::HandshakeTimeout) => true,
+            (&HttpError::InvalidPreface, &HttpError::InvalidPreface) => true,
+            (&HttpError::LocalConnectionError(c1), &HttpError::LocalConnectionError(c2)) => {
+                c1 == c2
+            },
+            (&HttpError::MalformedHeaders(ref e1), &HttpError::MalformedHeaders(ref e2)) => {
+                e1 == e2
+            },
+            (&HttpError::InvalidStreamId(e1), &HttpError::InvalidStreamId(e2)) => e1 == e2,
+            (&HttpError::InvalidWindowIncrement(i1), &HttpError::InvalidWindowIncrement(i2)) => {
+                i1 == i2
+            },
+            (&HttpError::InvalidHeaderPhase(e1), &HttpError::InvalidHeaderPhase(e2)) => e1 == e2,
+            (&HttpError::NotHttp2(ref m1), &HttpError::NotHttp2(ref m2)) => m1 == m2,
+            (&HttpError::ConnectionClosed, &HttpError::ConnectionClosed) => true,
+            (&HttpError::Http11Required, &HttpError::Http11Required) => true,
             (&HttpError::Other(ref e1), &HttpError::Other(ref e2)) => {
                 e1.description() == e2.description()
             },
@@ -410,100 +584,44 @@ impl PartialEq for HttpError {
     }
 }
 
-/// A convenience `Result` type that has the `HttpError` type as the error
-/// type and a generic Ok result type.
-pub type HttpResult<T> = Result<T, HttpError>;
-
-/// The struct represents the size of a flow control window.
+/// The bytes that a peer sent at the start of a connection in place of the HTTP/2 preface.
 ///
-/// It exposes methods that allow the manipulation of window sizes, such that they can never
-/// overflow the spec-mandated upper bound.
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub struct WindowSize(i32);
-impl WindowSize {
-    /// Tries to increase the window size by the given delta. If the WindowSize would overflow the
-    /// maximum allowed value (2^31 - 1), returns an error case. If the increase succeeds, returns
-    /// `Ok`.
-    ///
-    /// # Examples
-    ///
-    /// ```rust
-    /// use solicit::http::WindowSize;
-    ///
-    /// let mut window_size = WindowSize::new(65_535);
-    /// assert_eq!(window_size.size(), 65_535);
-    /// // An increase within the bounds...
-    /// assert!(window_size.try_increase(100).is_ok());
-    /// assert_eq!(window_size.size(), 65_635);
-    /// // An increase that would overflow
-    /// assert!(window_size.try_increase(0x7fffffff).is_err());
-    /// assert_eq!(window_size.size(), 65_635);
-    /// ```
-    pub fn try_increase(&mut self, delta: u32) -> Result<(), ()> {
-        // Someone's provided a delta that would definitely overflow the window size.
-        if delta > 0x7fffffff {
-            return Err(())
-        }
-        // Now it is safe to cast the delta to the `i32`.
-        match self.0.checked_add(delta as i32) {
-            None => {
-                // When the add overflows, we will have went over the maximum allowed size of the
-                // window size...
-                Err(())
-            },
-            Some(next_val) => {
-                // The addition didn't overflow, so the next window size is in the range allowed by
-                // the spec.
-                self.0 = next_val;
-                Ok(())
-            }
-        }
-    }
+/// They are kept around purely for diagnostic purposes: more often than not, they make it
+/// immediately obvious what the peer is actually speaking, e.g. an HTTP/1.1 status line.
+#[cfg(feature="std")]
+#[derive(Clone, PartialEq, Debug)]
+pub struct PrefaceMismatch {
+    /// The first bytes received from the peer.
+    pub received: Vec<u8>,
+}
 
-    /// Tries to decrease the size of the window by the given delta.
-    ///
-    /// There are situations where the window size should legitimately be allowed to become
-    /// negative, so the only situation where the result is an error is if the window size would
-    /// underflow, as this would definitely cause the peers to lose sync.
-    ///
-    /// # Example
-    ///
-    /// ```rust
-    /// use solicit::http::WindowSize;
-    ///
-    /// let mut window_size = WindowSize::new(65_535);
-    /// assert_eq!(window_size.size(), 65_535);
-    /// // A decrease...
-    /// assert!(window_size.try_decrease(100).is_ok());
-    /// assert_eq!(window_size.size(), 65_435);
-    /// // A decrease that does not underflow
-    /// assert!(window_size.try_decrease(0x7fffffff).is_ok());
-    /// assert_eq!(window_size.size(), -2147418212);
-    /// // A decrease that *would* underflow
-    /// assert!(window_size.try_decrease(0x7fffffff).is_err());
-    /// assert_eq!(window_size.size(), -2147418212);
-    /// ```
-    pub fn try_decrease(&mut self, delta: i32) -> Result<(), ()> {
-        match self.0.checked_sub(delta) {
-            Some(new) => {
-                self.0 = new;
-                Ok(())
-            },
-            None => Err(()),
-        }
+#[cfg(feature="std")]
+impl PrefaceMismatch {
+    /// Returns whether the peer responded with an HTTP/1.x status line.
+    pub fn is_http1(&self) -> bool {
+        self.received.starts_with(b"HTTP/1.")
     }
+}
 
-    /// Creates a new `WindowSize` with the given initial size.
-    pub fn new(size: i32) -> WindowSize {
-        WindowSize(size)
+#[cfg(feature="std")]
+impl fmt::Display for PrefaceMismatch {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let received = String::from_utf8_lossy(&self.received);
+        if self.is_http1() {
+            write!(fmt, "The peer responded with HTTP/1.x instead of HTTP/2: \"{}\"",
+                   received.escape_default())
+        } else {
+            write!(fmt, "The peer did not send an HTTP/2 preface, received: \"{}\"",
+                   received.escape_default())
+        }
     }
-    /// Returns the current size of the window.
-    ///
-    /// The size is actually allowed to become negative (for instance if the peer changes its
-    /// intial window size in the settings); therefore, the return is an `i32`.
-    pub fn size(&self) -> i32 { self.0 }
 }
 
+/// A convenience `Result` type that has the `HttpError` type as the error
+/// type and a generic Ok result type.
+#[cfg(feature="std")]
+pub type HttpResult<T> = Result<T, HttpError>;
+
 /// An enum representing the two possible HTTP schemes.
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum HttpScheme {
@@ -524,90 +642,5 @@ impl HttpScheme {
     }
 }
 
-/// A struct representing the full raw response received on an HTTP/2 connection.
-///
-/// The full body of the response is included, regardless how large it may be.
-/// The headers contain both the meta-headers, as well as the actual headers.
-#[derive(Clone)]
-pub struct Response<'n, 'v> {
-    /// The ID of the stream to which the response is associated. HTTP/1.1 does
-    /// not really have an equivalent to this.
-    pub stream_id: StreamId,
-    /// Exposes *all* the raw response headers, including the meta-headers.
-    /// (For now the only meta header allowed in HTTP/2 responses is the
-    /// `:status`.)
-    pub headers: Vec<Header<'n, 'v>>,
-    /// The full body of the response as an uninterpreted sequence of bytes.
-    pub body: Vec<u8>,
-}
-
-/// A type alias for a `Response` where all headers' names and values must have a `'static`
-/// lifetime if they are borrowed. This means that the parts can also be owned.
-pub type StaticResponse = Response<'static, 'static>;
-
-impl<'n, 'v> Response<'n, 'v> {
-    /// Creates a new `Response` with all the components already provided.
-    pub fn new(stream_id: StreamId, headers: Vec<OwnedHeader>, body: Vec<u8>)
-            -> Response<'n, 'v> {
-        Response {
-            stream_id: stream_id,
-            headers: headers.into_iter().map(|h| Header::new(h.0, h.1)).collect(),
-            body: body,
-        }
-    }
-
-    /// Gets the response status code from the pseudo-header. If the response
-    /// does not contain the response as the first pseuo-header, an error is
-    /// returned as such a response is malformed.
-    pub fn status_code(&self) -> HttpResult<u16> {
-        // Since pseudo-headers MUST be found before any regular header fields
-        // and the *only* pseudo-header defined for responses is the `:status`
-        // field, the `:status` MUST be the first header; otherwise, the
-        // response is malformed.
-        if self.headers.len() < 1 {
-            return Err(HttpError::MalformedResponse)
-        }
-        if &self.headers[0].name[..] != &b":status"[..] {
-            Err(HttpError::MalformedResponse)
-        } else {
-            Ok(try!(Response::parse_status_code(&self.headers[0].value)))
-        }
-    }
-
-    /// A helper function that parses a given buffer as a status code and
-    /// returns it as a `u16`, if it is valid.
-    fn parse_status_code(buf: &[u8]) -> HttpResult<u16> {
-        // "The status-code element is a three-digit integer code [...]"
-        if buf.len() != 3 {
-            return Err(HttpError::MalformedResponse);
-        }
-
-        // "There are five values for the first digit"
-        if buf[0] < b'1' || buf[0] > b'5' {
-            return Err(HttpError::MalformedResponse);
-        }
-
-        // The rest of them just have to be digits
-        if buf[1] < b'0' || buf[1] > b'9' || buf[2] < b'0' || buf[2] > b'9' {
-            return Err(HttpError::MalformedResponse);
-        }
-
-        // Finally, we can merge them into an integer
-        Ok(100 * ((buf[0] - b'0') as u16) +
-           10 * ((buf[1] - b'0') as u16) +
-           1 * ((buf[2] - b'0') as u16))
-    }
-}
-
-/// A struct representing a full HTTP/2 request, along with the full body, as a
-/// sequence of bytes.
-#[derive(Clone)]
-pub struct Request<'n, 'v> {
-    pub stream_id: u32,
-    pub headers: Vec<Header<'n, 'v>>,
-    pub body: Vec<u8>,
-}
-
-
 #[cfg(test)]
 pub mod tests;
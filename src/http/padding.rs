@@ -0,0 +1,74 @@
+//! The module defines the strategies with which an `HttpConnection` pads the DATA frames that it
+//! sends on a stream (see `HttpConnection::set_stream_padding`), e.g. to make it harder for an
+//! observer of the (encrypted) traffic to infer the size of the transferred resources.
+//!
+//! The padding counts against the flow control windows just like the data does. Whatever length
+//! a strategy asks for, the connection shortens the padding so that the frame still fits within
+//! the peer's maximum frame size and the outbound flow control windows; if not even the byte that
+//! carries the padding length fits, the frame is sent without padding.
+use std::fmt;
+
+/// Decides how many octets of padding are added to each DATA frame sent on a stream.
+pub enum PaddingStrategy {
+    /// Every frame is padded with the given number of octets.
+    Fixed(u8),
+    /// The callback is given the length of the data that a frame carries and returns the number
+    /// of octets that the frame is padded with, e.g. to round the length up to a multiple of a
+    /// block size.
+    Callback(Box<FnMut(usize) -> u8 + Send>),
+}
+
+impl PaddingStrategy {
+    /// Returns the number of octets of padding that the strategy asks for, for a frame carrying
+    /// `data_len` octets of data.
+    pub fn padding_for(&mut self, data_len: usize) -> u8 {
+        match *self {
+            PaddingStrategy::Fixed(pad_len) => pad_len,
+            PaddingStrategy::Callback(ref mut callback) => callback(data_len),
+        }
+    }
+}
+
+impl fmt::Debug for PaddingStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PaddingStrategy::Fixed(pad_len) => write!(f, "Fixed({})", pad_len),
+            PaddingStrategy::Callback(_) => write!(f, "Callback(..)"),
+        }
+    }
+}
+
+/// Returns the number of octets of padding to add to a frame carrying `data_len` octets of data,
+/// when the strategy asks for `requested` octets and the frame's whole payload may be at most
+/// `max_payload` octets long. `None` means that the frame cannot be padded at all.
+pub fn fit_padding(requested: u8, data_len: usize, max_payload: usize) -> Option<u8> {
+    // The padded payload also carries the octet with the padding length.
+    let room = match max_payload.checked_sub(data_len + 1) {
+        Some(room) => room,
+        None => return None,
+    };
+    Some(if room < requested as usize { room as u8 } else { requested })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PaddingStrategy, fit_padding};
+
+    #[test]
+    fn test_padding_strategy() {
+        assert_eq!(PaddingStrategy::Fixed(10).padding_for(100), 10);
+        // Pads the data to a multiple of 16 octets.
+        let mut strategy = PaddingStrategy::Callback(Box::new(|len| ((16 - len % 16) % 16) as u8));
+        assert_eq!(strategy.padding_for(5), 11);
+        assert_eq!(strategy.padding_for(32), 0);
+    }
+
+    #[test]
+    fn test_fit_padding() {
+        assert_eq!(fit_padding(10, 5, 100), Some(10));
+        assert_eq!(fit_padding(10, 5, 10), Some(4));
+        assert_eq!(fit_padding(10, 5, 6), Some(0));
+        assert_eq!(fit_padding(10, 5, 5), None);
+        assert_eq!(fit_padding(10, 5, 0), None);
+    }
+}
@@ -5,7 +5,10 @@
 //! have control over exactly *when* the data is sent. This is left up to the particular client
 //! implementations to trigger.
 
-use http::{HttpResult, HttpError};
+use std::cmp;
+use std::collections::HashMap;
+
+use http::{HttpResult, HttpError, StreamId};
 use http::connection::{
     DataChunk,
     EndStream,
@@ -33,6 +36,10 @@ pub struct SimplePrioritizer<'a, 'b, State> where State: SessionState + 'a {
     state: &'a mut State,
     /// The buffer into which the prioritizer can place the stream data chunk
     buf: &'b mut [u8],
+    /// The streams whose data should not be sent for now
+    skipped: Vec<StreamId>,
+    /// The most data that may be sent on each of the streams found here
+    limits: HashMap<StreamId, usize>,
 }
 
 impl<'a, 'b, State> SimplePrioritizer<'a, 'b, State> where State: SessionState +'a {
@@ -42,16 +49,39 @@ impl<'a, 'b, State> SimplePrioritizer<'a, 'b, State> where State: SessionState +
         SimplePrioritizer {
             state: state,
             buf: buf,
+            skipped: Vec::new(),
+            limits: HashMap::new(),
         }
     }
+
+    /// Makes the prioritizer pass over the given streams, e.g. because they are rate limited.
+    pub fn skip_streams(&mut self, streams: Vec<StreamId>) {
+        self.skipped = streams;
+    }
+
+    /// Limits the size of the chunks of the given streams, e.g. to their flow control windows.
+    /// Streams whose limit is 0 are passed over.
+    pub fn limit_streams(&mut self, limits: HashMap<StreamId, usize>) {
+        self.limits = limits;
+    }
 }
 
 impl<'a, 'b, State> DataPrioritizer for SimplePrioritizer<'a, 'b, State>
         where State: SessionState +'a {
     fn get_next_chunk(&mut self) -> HttpResult<Option<DataChunk>> {
         // Returns the data of the first stream that has data to be written.
-        for (stream_id, stream) in self.state.iter().filter(|&(_, ref s)| !s.is_closed_local()) {
-            let res = stream.get_data_chunk(self.buf);
+        let skipped = &self.skipped;
+        let limits = &self.limits;
+        let streams = self.state.iter().filter(|&(id, ref s)| {
+            !s.is_closed_local() && !skipped.contains(id)
+        });
+        for (stream_id, stream) in streams {
+            let limit = limits.get(stream_id).cloned().unwrap_or(self.buf.len());
+            let limit = cmp::min(limit, self.buf.len());
+            if limit == 0 {
+                continue;
+            }
+            let res = stream.get_data_chunk(&mut self.buf[..limit]);
             match res {
                 Ok(StreamDataChunk::Last(total)) => {
                     return Ok(Some(DataChunk::new_borrowed(
@@ -171,5 +201,46 @@ mod tests {
             let mut prioritizer = SimplePrioritizer::new(&mut state, &mut buf);
             assert!(prioritizer.get_next_chunk().unwrap().is_none());
         }
+        {
+            // Skipped streams
+            let mut buf = [0; 10];
+            let mut state = prepare_state();
+            let mut ids = Vec::new();
+            for i in 0..2 {
+                let mut stream = TestStream::new();
+                stream.set_outgoing(vec![i]);
+                ids.push(state.insert_outgoing(stream));
+            }
+            let mut prioritizer = SimplePrioritizer::new(&mut state, &mut buf);
+            prioritizer.skip_streams(vec![ids[0]]);
+
+            {
+                let chunk = prioritizer.get_next_chunk().unwrap().unwrap();
+                assert_eq!(chunk.stream_id, ids[1]);
+                assert_eq!(chunk.data, vec![1]);
+            }
+
+            // The skipped stream still has data, but it is not returned.
+            assert!(prioritizer.get_next_chunk().unwrap().is_none());
+        }
+        {
+            // Limited streams
+            let mut buf = [0; 10];
+            let mut state = prepare_state();
+            let mut ids = Vec::new();
+            for _ in 0..2 {
+                let mut stream = TestStream::new();
+                stream.set_outgoing(vec![1, 2, 3]);
+                ids.push(state.insert_outgoing(stream));
+            }
+            let mut prioritizer = SimplePrioritizer::new(&mut state, &mut buf);
+            prioritizer.limit_streams(vec![(ids[0], 0), (ids[1], 2)].into_iter().collect());
+
+            {
+                let chunk = prioritizer.get_next_chunk().unwrap().unwrap();
+                assert_eq!(chunk.stream_id, ids[1]);
+                assert_eq!(chunk.data, vec![1, 2]);
+            }
+        }
     }
 }
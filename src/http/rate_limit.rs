@@ -0,0 +1,198 @@
+//! The module implements token-bucket rate limiting of the DATA that an `HttpConnection` sends.
+//!
+//! A `RateLimiter` can cap the bandwidth of the whole connection, of each of its streams, or
+//! both. The connection-wide bucket is consulted before a chunk of data is taken from the
+//! streams, so the connection never exceeds its budget; the size of a chunk is only known once
+//! it is taken from a stream, so a stream's bucket can go into debt by (at most) one chunk, in
+//! which case the stream is skipped until the debt is repaid.
+use std::cmp;
+use std::collections::HashMap;
+use std::time::Instant;
+
+use http::StreamId;
+
+/// The parameters of a token bucket.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RateLimit {
+    /// The sustained rate, in bytes per second.
+    pub bytes_per_second: u32,
+    /// The number of bytes that can be sent in a single burst, after a period of inactivity.
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// Creates a new `RateLimit` with the given rate and burst size, both in bytes.
+    pub fn new(bytes_per_second: u32, burst: u32) -> RateLimit {
+        RateLimit {
+            bytes_per_second: bytes_per_second,
+            burst: burst,
+        }
+    }
+}
+
+/// A token bucket: holds up to `burst` tokens (bytes) and is refilled at a constant rate.
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    /// The available tokens. Negative if more was sent than the bucket allowed.
+    tokens: f64,
+    /// When the tokens were last refilled.
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a new, full, `TokenBucket`.
+    pub fn new(limit: RateLimit) -> TokenBucket {
+        TokenBucket {
+            limit: limit,
+            tokens: limit.burst as f64,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Returns the whole number of bytes that can currently be sent without exceeding the limit.
+    pub fn available(&mut self) -> u32 {
+        self.refill(Instant::now());
+        if self.tokens < 1.0 { 0 } else { self.tokens as u32 }
+    }
+
+    /// Takes the tokens for `len` sent bytes from the bucket, possibly going into debt.
+    pub fn consume(&mut self, len: u32) {
+        self.tokens -= len as f64;
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now <= self.refilled_at {
+            return;
+        }
+        let elapsed = now - self.refilled_at;
+        let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+        let refilled = self.tokens + elapsed * self.limit.bytes_per_second as f64;
+        self.tokens = refilled.min(self.limit.burst as f64);
+        self.refilled_at = now;
+    }
+}
+
+/// Limits the rate at which a connection sends DATA, connection-wide and/or per stream.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    connection: Option<TokenBucket>,
+    per_stream: Option<RateLimit>,
+    streams: HashMap<StreamId, TokenBucket>,
+}
+
+impl RateLimiter {
+    /// Creates a new `RateLimiter` that applies the given connection-wide and per-stream limits.
+    pub fn new(connection: Option<RateLimit>, per_stream: Option<RateLimit>) -> RateLimiter {
+        RateLimiter {
+            connection: connection.map(TokenBucket::new),
+            per_stream: per_stream,
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Returns how many bytes the connection may send right now, but at most `max`.
+    pub fn connection_budget(&mut self, max: usize) -> usize {
+        match self.connection {
+            Some(ref mut bucket) => cmp::min(max, bucket.available() as usize),
+            None => max,
+        }
+    }
+
+    /// Returns the streams that are not allowed to send data right now, as they exceeded their
+    /// own limit.
+    pub fn throttled_streams(&mut self) -> Vec<StreamId> {
+        self.streams.iter_mut()
+                    .filter_map(|(&id, bucket)| if bucket.available() == 0 { Some(id) } else { None })
+                    .collect()
+    }
+
+    /// Accounts for `len` bytes of data sent on the given stream.
+    pub fn data_sent(&mut self, stream_id: StreamId, len: u32, end_stream: bool) {
+        if let Some(ref mut bucket) = self.connection {
+            bucket.consume(len);
+        }
+        if end_stream {
+            self.streams.remove(&stream_id);
+        } else if let Some(limit) = self.per_stream {
+            self.streams.entry(stream_id)
+                        .or_insert_with(|| TokenBucket::new(limit))
+                        .consume(len);
+        }
+    }
+
+    /// Drops the bucket of the given stream, which no longer sends any data (e.g. as it was
+    /// reset), so that it is not kept around (or reported as throttled) any longer.
+    pub fn remove_stream(&mut self, stream_id: StreamId) {
+        self.streams.remove(&stream_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{RateLimit, RateLimiter, TokenBucket};
+
+    /// Tests that a `TokenBucket` is refilled at the configured rate, up to the burst size.
+    #[test]
+    fn test_token_bucket_refill() {
+        let mut bucket = TokenBucket::new(RateLimit::new(1000, 500));
+        let start = bucket.refilled_at;
+        assert_eq!(bucket.tokens, 500.0);
+
+        bucket.consume(800);
+        assert_eq!(bucket.tokens, -300.0);
+        bucket.refill(start + Duration::from_millis(500));
+        assert_eq!(bucket.tokens, 200.0);
+        // Never more than the burst.
+        bucket.refill(start + Duration::from_secs(10));
+        assert_eq!(bucket.tokens, 500.0);
+        // Going back in time does nothing.
+        bucket.refill(start);
+        assert_eq!(bucket.tokens, 500.0);
+    }
+
+    /// Tests that the connection budget is capped by the connection-wide bucket.
+    #[test]
+    fn test_rate_limiter_connection_budget() {
+        let mut limiter = RateLimiter::new(Some(RateLimit::new(1, 100)), None);
+        assert_eq!(limiter.connection_budget(8192), 100);
+        assert_eq!(limiter.connection_budget(10), 10);
+        limiter.data_sent(1, 100, false);
+        assert_eq!(limiter.connection_budget(8192), 0);
+        // Without a per-stream limit, streams are never throttled.
+        assert!(limiter.throttled_streams().is_empty());
+
+        let mut unlimited = RateLimiter::new(None, None);
+        assert_eq!(unlimited.connection_budget(8192), 8192);
+    }
+
+    /// Tests that streams that exceed their limit are throttled, without affecting the others.
+    #[test]
+    fn test_rate_limiter_per_stream() {
+        let mut limiter = RateLimiter::new(None, Some(RateLimit::new(1, 10)));
+        limiter.data_sent(1, 5, false);
+        assert!(limiter.throttled_streams().is_empty());
+        limiter.data_sent(1, 100, false);
+        limiter.data_sent(3, 5, false);
+        assert_eq!(limiter.throttled_streams(), vec![1]);
+        // The buckets of ended streams are dropped.
+        limiter.data_sent(1, 0, true);
+        assert!(limiter.throttled_streams().is_empty());
+    }
+
+    /// Tests that the buckets of removed streams are dropped, without affecting the others.
+    #[test]
+    fn test_rate_limiter_remove_stream() {
+        let mut limiter = RateLimiter::new(None, Some(RateLimit::new(1, 10)));
+        limiter.data_sent(1, 100, false);
+        limiter.data_sent(3, 100, false);
+        assert_eq!(limiter.streams.len(), 2);
+
+        limiter.remove_stream(1);
+        assert_eq!(limiter.throttled_streams(), vec![3]);
+        limiter.remove_stream(5);
+        assert_eq!(limiter.streams.len(), 1);
+    }
+}
@@ -0,0 +1,533 @@
+//! The module implements a `TransportStream` wrapper that records all the bytes flowing through
+//! it, so that the traffic of a connection can be inspected after the fact, e.g. attached to a bug
+//! report.
+//!
+//! A `RecordingTransport` keeps every chunk of bytes that it reads or writes, along with the time
+//! at which it went through, relative to the creation of the transport. The recording can be
+//! exported in two forms:
+//!
+//! - a raw binary log (`write_raw_log`), which keeps everything and can be read back with
+//!   `read_raw_log`, e.g. to replay it, and
+//! - a text transcript (`transcript`), which decodes the bytes frame by frame, including the
+//!   header blocks, so that the conversation can be read without any other tools.
+//!
+//! In the transcript, `>` marks the frames that were written (sent to the peer) and `<` the ones
+//! that were read (received from it). Each frame is stamped with the time at which its last byte
+//! went through.
+use std::fmt::Write as FmtWrite;
+use std::mem;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use hpack;
+
+use http::CLIENT_PREFACE;
+use http::connection::HttpFrame;
+use http::frame::{RawFrame, FRAME_HEADER_LEN, unpack_header};
+use http::transport::{TransportStream, Direction};
+
+/// The bytes that every raw log starts with.
+pub const RAW_LOG_MAGIC: &'static [u8] = b"SOLICIT-LOG/1\n";
+
+/// A chunk of bytes that went through a `RecordingTransport` in a single read or write.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Record {
+    /// Whether the bytes were read or written.
+    pub direction: Direction,
+    /// The time at which the bytes went through, relative to the start of the recording.
+    pub time: Duration,
+    /// The bytes themselves.
+    pub bytes: Vec<u8>,
+}
+
+/// The recording shared by all handles of a `RecordingTransport`.
+struct Log {
+    start: Instant,
+    records: Vec<Record>,
+}
+
+/// A `TransportStream` that wraps another one and records every byte that flows through it, in
+/// both directions.
+///
+/// Handles created by `try_split` share the recording, so that it keeps the order in which the
+/// bytes went through, no matter which handle read or wrote them.
+pub struct RecordingTransport<T> where T: TransportStream {
+    inner: T,
+    log: Arc<Mutex<Log>>,
+}
+
+impl<T> RecordingTransport<T> where T: TransportStream {
+    /// Wraps the given transport, starting the recording.
+    pub fn new(inner: T) -> RecordingTransport<T> {
+        RecordingTransport {
+            inner: inner,
+            log: Arc::new(Mutex::new(Log {
+                start: Instant::now(),
+                records: Vec::new(),
+            })),
+        }
+    }
+
+    /// Returns a copy of everything that was recorded so far.
+    pub fn records(&self) -> Vec<Record> {
+        self.lock().records.clone()
+    }
+
+    /// Writes everything that was recorded so far to the given writer, as a raw binary log.
+    pub fn write_raw_log<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write_raw_log(&self.lock().records, out)
+    }
+
+    /// Returns a text transcript of everything that was recorded so far (see `transcript`).
+    pub fn transcript(&self) -> String {
+        transcript(&self.lock().records)
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    fn lock(&self) -> MutexGuard<Log> {
+        self.log.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Records the given bytes as having just gone through in the given direction.
+    fn record(&self, direction: Direction, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let mut log = self.lock();
+        let time = log.start.elapsed();
+        log.records.push(Record {
+            direction: direction,
+            time: time,
+            bytes: bytes.to_vec(),
+        });
+    }
+}
+
+impl<T> Read for RecordingTransport<T> where T: TransportStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = try!(self.inner.read(buf));
+        self.record(Direction::Read, &buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<T> Write for RecordingTransport<T> where T: TransportStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = try!(self.inner.write(buf));
+        self.record(Direction::Write, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T> TransportStream for RecordingTransport<T> where T: TransportStream {
+    fn try_split(&self) -> Result<RecordingTransport<T>, io::Error> {
+        Ok(RecordingTransport {
+            inner: try!(self.inner.try_split()),
+            log: self.log.clone(),
+        })
+    }
+
+    fn close(&mut self) -> Result<(), io::Error> {
+        self.inner.close()
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> Result<(), io::Error> {
+        self.inner.set_read_timeout(dur)
+    }
+}
+
+/// Writes the given records to the writer as a raw binary log.
+///
+/// The log starts with `RAW_LOG_MAGIC`, followed by the records, each one made up of its
+/// direction (a single byte: 0 for reads, 1 for writes), its time in microseconds (8 bytes), the
+/// number of bytes (4 bytes) and the bytes themselves. All integers are big endian.
+pub fn write_raw_log<W: Write>(records: &[Record], out: &mut W) -> io::Result<()> {
+    try!(out.write_all(RAW_LOG_MAGIC));
+    for record in records {
+        let direction = match record.direction {
+            Direction::Read => 0u8,
+            Direction::Write => 1u8,
+        };
+        let micros = record.time.as_secs() * 1_000_000 +
+                     (record.time.subsec_nanos() / 1000) as u64;
+        let len = record.bytes.len() as u64;
+        let mut header = vec![direction];
+        header.extend((0..8).rev().map(|i| (micros >> (8 * i)) as u8));
+        header.extend((0..4).rev().map(|i| (len >> (8 * i)) as u8));
+        try!(out.write_all(&header));
+        try!(out.write_all(&record.bytes));
+    }
+    Ok(())
+}
+
+/// Reads back the records of a raw binary log, as written by `write_raw_log`.
+pub fn read_raw_log<R: Read>(input: &mut R) -> io::Result<Vec<Record>> {
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let mut buf = Vec::new();
+    try!(input.read_to_end(&mut buf));
+    if !buf.starts_with(RAW_LOG_MAGIC) {
+        return Err(invalid("Not a raw log"));
+    }
+
+    let mut records = Vec::new();
+    let mut rest = &buf[RAW_LOG_MAGIC.len()..];
+    while !rest.is_empty() {
+        if rest.len() < 13 {
+            return Err(invalid("Truncated record"));
+        }
+        let direction = match rest[0] {
+            0 => Direction::Read,
+            1 => Direction::Write,
+            _ => return Err(invalid("Invalid direction")),
+        };
+        let micros = rest[1..9].iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+        let len = rest[9..13].iter().fold(0usize, |acc, &b| (acc << 8) | b as usize);
+        rest = &rest[13..];
+        if rest.len() < len {
+            return Err(invalid("Truncated record"));
+        }
+        records.push(Record {
+            direction: direction,
+            time: Duration::new(micros / 1_000_000, (micros % 1_000_000) as u32 * 1000),
+            bytes: rest[..len].to_vec(),
+        });
+        rest = &rest[len..];
+    }
+    Ok(records)
+}
+
+/// The state of decoding the bytes of one direction into frames.
+struct FrameDecoder {
+    /// The bytes that do not make up a full frame yet.
+    buf: Vec<u8>,
+    /// Whether it is known by now if the bytes start with the connection preface.
+    preface_checked: bool,
+    /// The HPACK decoder for the header blocks flowing in this direction.
+    hpack: hpack::Decoder<'static>,
+    /// The fragments of the header block that is being received.
+    block: Vec<u8>,
+}
+
+impl FrameDecoder {
+    fn new() -> FrameDecoder {
+        FrameDecoder {
+            buf: Vec::new(),
+            preface_checked: false,
+            hpack: hpack::Decoder::new(),
+            block: Vec::new(),
+        }
+    }
+
+    /// Appends the transcript lines of all frames completed by the buffered bytes.
+    fn decode(&mut self, prefix: &str, out: &mut String) {
+        if !self.preface_checked {
+            if self.buf.len() < CLIENT_PREFACE.len() && CLIENT_PREFACE.starts_with(&self.buf) {
+                // It is too early to tell.
+                return;
+            }
+            self.preface_checked = true;
+            if self.buf.starts_with(CLIENT_PREFACE) {
+                self.buf.drain(..CLIENT_PREFACE.len());
+                let _ = writeln!(out, "{} PREFACE", prefix);
+            }
+        }
+
+        loop {
+            if self.buf.len() < FRAME_HEADER_LEN {
+                return;
+            }
+            let mut header = [0; FRAME_HEADER_LEN];
+            header.copy_from_slice(&self.buf[..FRAME_HEADER_LEN]);
+            let (len, _, _, _) = unpack_header(&header);
+            let frame_len = FRAME_HEADER_LEN + len as usize;
+            if self.buf.len() < frame_len {
+                return;
+            }
+            let raw: RawFrame = self.buf.drain(..frame_len).collect::<Vec<_>>().into();
+            self.describe(&raw, prefix, out);
+        }
+    }
+
+    /// Returns the buffered header block if it is complete.
+    fn take_block(&mut self, is_complete: bool) -> Option<Vec<u8>> {
+        if is_complete {
+            Some(mem::replace(&mut self.block, Vec::new()))
+        } else {
+            None
+        }
+    }
+
+    /// Appends the transcript lines of a single frame.
+    fn describe(&mut self, raw: &RawFrame, prefix: &str, out: &mut String) {
+        let (len, frame_type, flags, stream_id) = raw.header();
+        let name = match frame_type {
+            0x0 => "DATA".to_string(),
+            0x1 => "HEADERS".to_string(),
+            0x2 => "PRIORITY".to_string(),
+            0x3 => "RST_STREAM".to_string(),
+            0x4 => "SETTINGS".to_string(),
+            0x5 => "PUSH_PROMISE".to_string(),
+            0x6 => "PING".to_string(),
+            0x7 => "GOAWAY".to_string(),
+            0x8 => "WINDOW_UPDATE".to_string(),
+            0x9 => "CONTINUATION".to_string(),
+            0xa => "ALTSVC".to_string(),
+            other => format!("UNKNOWN(0x{:x})", other),
+        };
+        let _ = write!(out, "{} {} stream={} flags=0x{:x} length={}",
+                       prefix, name, stream_id, flags, len);
+
+        let frame = match HttpFrame::from_raw(raw) {
+            Ok(frame) => frame,
+            Err(_) => {
+                let _ = writeln!(out, " (malformed)");
+                return;
+            },
+        };
+        let block = match frame {
+            HttpFrame::HeadersFrame(ref frame) => {
+                self.block = frame.header_fragment().to_vec();
+                self.take_block(frame.is_headers_end())
+            },
+            HttpFrame::ContinuationFrame(ref frame) => {
+                self.block.extend_from_slice(frame.header_fragment());
+                self.take_block(frame.is_headers_end())
+            },
+            HttpFrame::SettingsFrame(ref frame) => {
+                if frame.is_ack() {
+                    let _ = write!(out, " ack");
+                } else {
+                    let _ = write!(out, " {:?}", frame.settings);
+                }
+                None
+            },
+            HttpFrame::RstStreamFrame(ref frame) => {
+                let _ = write!(out, " error={:?}", frame.error_code());
+                None
+            },
+            HttpFrame::PingFrame(ref frame) => {
+                let ack = if frame.is_ack() { " ack" } else { "" };
+                let _ = write!(out, "{} opaque=0x{:016x}", ack, frame.opaque_data());
+                None
+            },
+            HttpFrame::GoawayFrame(ref frame) => {
+                let _ = write!(out, " last_stream={} error={:?}",
+                               frame.last_stream_id(), frame.error_code());
+                if let Some(data) = frame.debug_data() {
+                    let _ = write!(out, " debug={:?}", String::from_utf8_lossy(data));
+                }
+                None
+            },
+            HttpFrame::WindowUpdateFrame(ref frame) => {
+                let _ = write!(out, " increment={}", frame.increment());
+                None
+            },
+            HttpFrame::AltSvcFrame(ref frame) => {
+                let _ = write!(out, " origin={:?} value={:?}",
+                               String::from_utf8_lossy(frame.origin()),
+                               String::from_utf8_lossy(frame.field_value()));
+                None
+            },
+            HttpFrame::PriorityFrame(ref frame) => {
+                let exclusive = if frame.is_exclusive() { " exclusive" } else { "" };
+                let _ = write!(out, " depends_on={} weight={}{}",
+                               frame.depends_on(), frame.weight(), exclusive);
+                None
+            },
+            HttpFrame::DataFrame(_) | HttpFrame::UnknownFrame(_) => None,
+        };
+        let _ = writeln!(out, "");
+
+        if let Some(block) = block {
+            match self.hpack.decode(&block) {
+                Ok(headers) => {
+                    for (name, value) in headers {
+                        let _ = writeln!(out, "    {}: {}",
+                                         String::from_utf8_lossy(&name),
+                                         String::from_utf8_lossy(&value));
+                    }
+                },
+                Err(e) => {
+                    let _ = writeln!(out, "    (undecodable header block: {:?})", e);
+                },
+            }
+        }
+    }
+}
+
+/// Decodes the given records into a text transcript, with a line for each frame (and for the
+/// connection preface) and a line for each header of every header block.
+///
+/// ```text
+/// [   0.000102] > PREFACE
+/// [   0.000102] > SETTINGS stream=0 flags=0x0 length=0 []
+/// [   0.000131] > HEADERS stream=1 flags=0x5 length=17
+///     :method: GET
+///     :path: /
+/// [   0.001530] < SETTINGS stream=0 flags=0x1 length=0 ack
+/// ```
+///
+/// Frames that are still incomplete at the end of the recording are noted as such.
+pub fn transcript(records: &[Record]) -> String {
+    let mut decoders = [FrameDecoder::new(), FrameDecoder::new()];
+    let mut out = String::new();
+    let mut last_time = Duration::from_secs(0);
+    for record in records {
+        let (index, marker) = match record.direction {
+            Direction::Read => (0, '<'),
+            Direction::Write => (1, '>'),
+        };
+        let prefix = format!("[{:>4}.{:06}] {}",
+                             record.time.as_secs(), record.time.subsec_nanos() / 1000, marker);
+        decoders[index].buf.extend_from_slice(&record.bytes);
+        decoders[index].decode(&prefix, &mut out);
+        last_time = record.time;
+    }
+
+    for (decoder, marker) in decoders.iter().zip(&['<', '>']) {
+        if !decoder.buf.is_empty() {
+            let _ = writeln!(out, "[{:>4}.{:06}] {} ({} bytes of an incomplete frame)",
+                             last_time.as_secs(), last_time.subsec_nanos() / 1000, marker,
+                             decoder.buf.len());
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::time::Duration;
+
+    use hpack;
+
+    use super::{RecordingTransport, Record, transcript, write_raw_log, read_raw_log};
+    use http::CLIENT_PREFACE;
+    use http::duplex::pipe;
+    use http::frame::{
+        SettingsFrame,
+        HttpSetting,
+        HeadersFrame,
+        HeadersFlag,
+        ContinuationFrame,
+        ContinuationFlag,
+        PingFrame,
+    };
+    use http::transport::Direction;
+    use http::tests::common::serialize_frame;
+
+    /// Returns the transcript without the timestamps, which depend on the timing of the test.
+    fn strip_times(transcript: &str) -> Vec<String> {
+        transcript.lines().map(|line| {
+            if line.starts_with('[') {
+                line[line.find(']').unwrap() + 2..].to_string()
+            } else {
+                line.to_string()
+            }
+        }).collect()
+    }
+
+    /// Tests that the bytes going through the transport in both directions are recorded in order
+    /// and decoded into a transcript.
+    #[test]
+    fn test_recording_transcript() {
+        let (a, mut b) = pipe();
+        let mut a = RecordingTransport::new(a);
+        let mut settings = SettingsFrame::new();
+        settings.add_setting(HttpSetting::MaxConcurrentStreams(10));
+        let fragment = hpack::Encoder::new().encode(vec![
+            (&b":method"[..], &b"GET"[..]),
+            (&b":path"[..], &b"/"[..]),
+        ]);
+        let mut headers = HeadersFrame::new(fragment, 1);
+        headers.set_flag(HeadersFlag::EndHeaders);
+        headers.set_flag(HeadersFlag::EndStream);
+
+        a.write_all(CLIENT_PREFACE).unwrap();
+        a.write_all(&serialize_frame(&settings)).unwrap();
+        a.write_all(&serialize_frame(&headers)).unwrap();
+        b.write_all(&serialize_frame(&SettingsFrame::new_ack())).unwrap();
+        let mut buf = [0; 9];
+        Read::read_exact(&mut a, &mut buf).unwrap();
+
+        let records = a.records();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[3].direction, Direction::Read);
+        assert!(records[0].time <= records[3].time);
+        assert_eq!(strip_times(&a.transcript()), vec![
+            "> PREFACE",
+            "> SETTINGS stream=0 flags=0x0 length=6 [MaxConcurrentStreams(10)]",
+            "> HEADERS stream=1 flags=0x5 length=2",
+            "    :method: GET",
+            "    :path: /",
+            "< SETTINGS stream=0 flags=0x1 length=0 ack",
+        ]);
+    }
+
+    /// Tests that header blocks are decoded across CONTINUATION frames, that frames split over
+    /// several records are stamped with the time of their last byte, and that incomplete frames
+    /// are noted.
+    #[test]
+    fn test_transcript_fragments() {
+        let mut encoder = hpack::Encoder::new();
+        let block = encoder.encode(vec![(&b"x-long"[..], &b"value"[..])]);
+        let headers = serialize_frame(&HeadersFrame::new(block[..3].to_vec(), 3));
+        let mut continuation = ContinuationFrame::new(block[3..].to_vec(), 3);
+        continuation.set_flag(ContinuationFlag::EndHeaders);
+        let continuation = serialize_frame(&continuation);
+        let ping = serialize_frame(&PingFrame::new(0xab));
+        let record = |direction, millis, bytes: &[u8]| {
+            Record {
+                direction: direction,
+                time: Duration::from_millis(millis),
+                bytes: bytes.to_vec(),
+            }
+        };
+
+        let records = vec![
+            record(Direction::Read, 1, &headers),
+            record(Direction::Read, 2, &continuation[..4]),
+            record(Direction::Write, 1500, &ping[..5]),
+            record(Direction::Read, 2003, &continuation[4..]),
+        ];
+
+        assert_eq!(transcript(&records), "\
+[   0.001000] < HEADERS stream=3 flags=0x0 length=3
+[   2.003000] < CONTINUATION stream=3 flags=0x4 length=11
+    x-long: value
+[   2.003000] > (5 bytes of an incomplete frame)
+");
+    }
+
+    /// Tests that raw logs are read back as they were written, and that anything else is rejected.
+    #[test]
+    fn test_raw_log_round_trip() {
+        let records = vec![
+            Record {
+                direction: Direction::Write,
+                time: Duration::new(1, 234_000),
+                bytes: b"hello".to_vec(),
+            },
+            Record {
+                direction: Direction::Read,
+                time: Duration::new(70_000, 1_000),
+                bytes: vec![0; 300],
+            },
+        ];
+        let mut log = Vec::new();
+        write_raw_log(&records, &mut log).unwrap();
+
+        assert_eq!(read_raw_log(&mut &log[..]).unwrap(), records);
+        assert!(read_raw_log(&mut &log[..log.len() - 1]).is_err());
+        assert!(read_raw_log(&mut &b"not a log"[..]).is_err());
+    }
+}
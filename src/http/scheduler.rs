@@ -0,0 +1,528 @@
+//! The module implements a scheduler that decides the order in which the frames of all streams
+//! are sent on a connection.
+//!
+//! Writing header blocks as soon as they are ready, while DATA goes through a `DataPrioritizer`,
+//! means that the two kinds of frames are never weighed against each other: a burst of responses
+//! gets its headers out ahead of everything else, while the DATA of an urgent stream waits
+//! behind them. The `Scheduler` instead queues the header blocks and emits one frame at a time,
+//! either a queued header block or a chunk of DATA, sharing the connection between the streams in
+//! proportion to their weights.
+//!
+//! Header blocks are not subject to flow control, so they are never held back by an exhausted
+//! flow control window or rate limit, whereas DATA is only sent within the rate limit and both
+//! the connection's and the stream's own flow control windows.
+//!
+//! Trailers are the exception to header blocks going out first: they are held back until the
+//! last chunk of the stream's DATA is sent, and end the stream in its place.
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+
+use http::{StreamId, Header, HttpResult, HttpError};
+use http::connection::{
+    DataChunk,
+    EndStream,
+    HttpConnection,
+    SendFrame,
+    SendStatus,
+};
+use http::memory::header_list_size;
+use http::soak::{Allocation, Resource};
+use http::priority::DataPrioritizer;
+use http::session::{
+    SessionState,
+    Stream,
+    StreamDataChunk,
+    StreamDataError,
+};
+
+/// The weight of streams that were not given one explicitly, as defined by the spec for streams
+/// without any priority information (i.e. 16, in the form that `set_weight` takes).
+pub const DEFAULT_WEIGHT: u8 = 15;
+
+/// The number of streams that are not open yet (e.g. streams that PRIORITY frames were sent for
+/// ahead of their requests) whose weights are remembered.
+const MAX_IDLE_WEIGHTS: usize = 100;
+
+/// A header block that is waiting to be sent.
+#[derive(Clone, Debug)]
+struct QueuedHeaders {
+    stream_id: StreamId,
+    headers: Vec<Header<'static, 'static>>,
+    end_stream: EndStream,
+    /// Accounts for the buffered header block in soak tests.
+    _allocation: Allocation,
+}
+
+/// Queues header blocks and emits them, interleaved with the DATA of the streams of a session,
+/// according to the weights of the streams.
+///
+/// The streams are served by weighted fair queueing: every stream has a virtual time, which
+/// advances by the size of each frame that the stream sends, divided by the stream's weight. On
+/// every call to `send_next`, the stream with the lowest virtual time that has something to send
+/// gets to send a single frame: its oldest queued header block, if any, or else the next chunk of
+/// its DATA. A stream with twice the weight of another thus gets to send twice as many bytes, but
+/// no stream is starved. On a tie, the streams with queued header blocks go first, and then the
+/// ones with lower IDs. Streams that had nothing to send do not build up credit for a later burst.
+///
+/// A stream's DATA is never sent while it still has a header block queued, so that a response's
+/// body can never overtake its headers.
+#[derive(Clone, Debug, Default)]
+pub struct Scheduler {
+    headers: VecDeque<QueuedHeaders>,
+    /// The trailers of the streams, waiting for the streams' DATA to be sent.
+    trailers: HashMap<StreamId, QueuedHeaders>,
+    weights: HashMap<StreamId, u8>,
+    /// The virtual times of the streams.
+    passes: HashMap<StreamId, u64>,
+    /// The virtual time of the stream that was served last, which streams start out from.
+    virtual_time: u64,
+    /// The highest IDs of even and odd streams that have had anything to send so far; the streams
+    /// above them are not open yet.
+    newest: [StreamId; 2],
+    /// The streams that the last `send_next` held back because of a flow control window.
+    flow_blocked: Vec<StreamId>,
+}
+
+impl Scheduler {
+    /// Creates a new `Scheduler` with nothing queued.
+    pub fn new() -> Scheduler {
+        Scheduler::default()
+    }
+
+    /// Queues the given header block, to be sent on the given stream once it is its turn.
+    pub fn queue_headers(&mut self,
+                         headers: Vec<Header<'static, 'static>>,
+                         stream_id: StreamId,
+                         end_stream: EndStream) {
+        self.headers.push_back(QueuedHeaders {
+            stream_id: stream_id,
+            headers: headers,
+            end_stream: end_stream,
+            _allocation: Allocation::new(Resource::Buffer),
+        });
+    }
+
+    /// Queues the given trailers, to be sent on the given stream once all of its DATA is. The
+    /// trailers end the stream, so the last DATA frame is sent without the END_STREAM flag.
+    ///
+    /// Trailers that are queued again for the same stream replace the earlier ones.
+    pub fn queue_trailers(&mut self, trailers: Vec<Header<'static, 'static>>, stream_id: StreamId) {
+        self.trailers.insert(stream_id, QueuedHeaders {
+            stream_id: stream_id,
+            headers: trailers,
+            end_stream: EndStream::Yes,
+            _allocation: Allocation::new(Resource::Buffer),
+        });
+    }
+
+    /// Returns whether the given stream has trailers that are yet to be sent.
+    pub fn has_queued_trailers(&self, stream_id: StreamId) -> bool {
+        self.trailers.contains_key(&stream_id)
+    }
+
+    /// Sets the weight of the given stream, which may also be one that is not open yet. Streams
+    /// with higher weights get a larger share of the connection.
+    ///
+    /// Like the `weight` of a `StreamDependency`, the value is one less than the actual weight,
+    /// so that the full range of weights, [1, 256], fits into a `u8`.
+    pub fn set_weight(&mut self, stream_id: StreamId, weight: u8) {
+        self.weights.insert(stream_id, weight);
+    }
+
+    /// Returns the weight of the given stream.
+    pub fn weight(&self, stream_id: StreamId) -> u8 {
+        self.weights.get(&stream_id).cloned().unwrap_or(DEFAULT_WEIGHT)
+    }
+
+    /// Returns whether the given stream has any header blocks that are yet to be sent.
+    pub fn has_queued_headers(&self, stream_id: StreamId) -> bool {
+        self.headers.iter().any(|block| block.stream_id == stream_id)
+    }
+
+    /// Returns the estimated size of the header blocks that are queued (see
+    /// `memory::header_list_size`).
+    pub fn queued_len(&self) -> usize {
+        self.headers.iter().chain(self.trailers.values()).map(block_size).sum()
+    }
+
+    /// Returns the IDs of the streams that have header blocks queued. Their DATA may not be sent
+    /// until the header blocks are.
+    pub fn streams_with_queued_headers(&self) -> Vec<StreamId> {
+        let mut ids: Vec<_> = self.headers.iter().map(|block| block.stream_id).collect();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    /// Returns the IDs of the streams whose DATA the last call to `send_next` held back because
+    /// either the connection's or the stream's own outbound flow control window was exhausted.
+    /// Streams that still have a header block queued are not included, since they would not be
+    /// sending DATA anyway.
+    pub fn flow_blocked_streams(&self) -> &[StreamId] {
+        &self.flow_blocked
+    }
+
+    /// Sends the next frame that is due: either a queued header block or a DATA frame carrying a
+    /// chunk of a stream's outgoing data, read into the given buffer. The size of the buffer is
+    /// the largest DATA payload that is sent.
+    ///
+    /// # Returns
+    ///
+    /// `SendStatus::Sent` if a frame was sent. If nothing could be sent, but some streams were
+    /// held back by the rate limit or an outbound flow control window, returns
+    /// `SendStatus::Throttled`; otherwise, `SendStatus::Nothing`.
+    pub fn send_next<State, S>(&mut self,
+                               state: &mut State,
+                               conn: &mut HttpConnection,
+                               sender: &mut S,
+                               buf: &mut [u8])
+                               -> HttpResult<SendStatus>
+            where State: SessionState, S: SendFrame {
+        let candidates = self.candidates(state);
+        let throttled = conn.throttled_streams();
+        let window = conn.out_window_available() as usize;
+        let budget = cmp::min(conn.send_budget(buf.len()), window);
+        let mut held_back = false;
+
+        let queued = self.streams_with_queued_headers();
+        self.flow_blocked = candidates.iter()
+                                      .cloned()
+                                      .filter(|id| !queued.contains(id))
+                                      .filter(|&id| window == 0 || stream_window(conn, id) == 0)
+                                      .collect();
+        self.flow_blocked.sort();
+
+        for stream_id in candidates {
+            if let Some(pos) = self.headers.iter().position(|block| block.stream_id == stream_id) {
+                let block = self.headers.remove(pos).unwrap();
+                trace!("Scheduling the headers of stream {}", stream_id);
+                self.charge(stream_id, block_size(&block));
+                try!(conn.sender(sender).send_headers(block.headers, stream_id, block.end_stream));
+                return Ok(SendStatus::Sent);
+            }
+            let budget = cmp::min(budget, stream_window(conn, stream_id));
+            if budget == 0 || throttled.contains(&stream_id) {
+                held_back = true;
+                continue;
+            }
+            let stream = match state.get_stream_mut(stream_id) {
+                Some(stream) => stream,
+                None => continue,
+            };
+            let (len, end_stream) = match stream.get_data_chunk(&mut buf[..budget]) {
+                Ok(StreamDataChunk::Chunk(len)) => (len, EndStream::No),
+                Ok(StreamDataChunk::Last(len)) if self.trailers.contains_key(&stream_id) => {
+                    // The trailers are next in line for the stream and end it instead.
+                    let block = self.trailers.remove(&stream_id).unwrap();
+                    if len == 0 {
+                        trace!("Scheduling the trailers of stream {}", stream_id);
+                        self.charge(stream_id, block_size(&block));
+                        try!(conn.sender(sender).send_headers(block.headers, stream_id,
+                                                              EndStream::Yes));
+                        return Ok(SendStatus::Sent);
+                    }
+                    self.headers.push_front(block);
+                    (len, EndStream::No)
+                },
+                Ok(StreamDataChunk::Last(len)) => (len, EndStream::Yes),
+                Ok(StreamDataChunk::Unavailable) => continue,
+                Err(StreamDataError::Closed) => {
+                    stream.close_local();
+                    continue;
+                },
+                Err(StreamDataError::Other(e)) => return Err(HttpError::Other(e)),
+            };
+            trace!("Scheduling {} bytes of data on stream {}", len, stream_id);
+            self.charge(stream_id, len);
+            let chunk = DataChunk::new_borrowed(&buf[..len], stream_id, end_stream);
+            return conn.sender(sender).send_next_data(&mut ScheduledChunk(Some(chunk)));
+        }
+
+        if held_back {
+            Ok(SendStatus::Throttled)
+        } else {
+            Ok(SendStatus::Nothing)
+        }
+    }
+
+    /// Advances the virtual time of the given stream for a frame of the given size that it is
+    /// sending, inversely proportional to the stream's weight.
+    fn charge(&mut self, stream_id: StreamId, size: usize) {
+        let weight = self.weight(stream_id) as u64 + 1;
+        let virtual_time = self.virtual_time;
+        let pass = self.passes.entry(stream_id).or_insert(virtual_time);
+        self.virtual_time = *pass;
+        *pass += size as u64 * 256 / weight;
+    }
+
+    /// Returns the IDs of all streams that might have something to send, in the order in which
+    /// they should be served. The weights and virtual times of streams that are done are dropped
+    /// along the way.
+    fn candidates<State: SessionState>(&mut self, state: &mut State) -> Vec<StreamId> {
+        let mut ids: Vec<StreamId> = state.iter()
+                                          .filter(|&(_, ref s)| !s.is_closed_local())
+                                          .map(|(id, _)| *id)
+                                          .collect();
+        ids.extend(self.headers.iter().map(|block| block.stream_id));
+        ids.sort();
+        ids.dedup();
+
+        for &id in &ids {
+            let newest = &mut self.newest[(id % 2) as usize];
+            *newest = cmp::max(*newest, id);
+        }
+        // The weights of streams that are not open yet are kept for when they are, but only for
+        // so many of them.
+        let newest = self.newest;
+        self.weights.retain(|&id, _| {
+            ids.binary_search(&id).is_ok() || id > newest[(id % 2) as usize]
+        });
+        let mut idle: Vec<StreamId> = self.weights.keys()
+                                                  .cloned()
+                                                  .filter(|id| ids.binary_search(id).is_err())
+                                                  .collect();
+        if idle.len() > MAX_IDLE_WEIGHTS {
+            idle.sort();
+            for id in &idle[MAX_IDLE_WEIGHTS..] {
+                self.weights.remove(id);
+            }
+        }
+        // Trailers of streams that can no longer send anything (e.g. because they were reset)
+        // are never going to be sent.
+        self.trailers.retain(|id, _| ids.binary_search(id).is_ok());
+        self.passes.retain(|id, _| ids.binary_search(id).is_ok());
+        let virtual_time = self.virtual_time;
+        for &id in &ids {
+            let pass = self.passes.entry(id).or_insert(virtual_time);
+            *pass = cmp::max(*pass, virtual_time);
+        }
+
+        let queued = self.streams_with_queued_headers();
+        let passes = &self.passes;
+        ids.sort_by_key(|id| (passes[id], queued.binary_search(id).is_err(), *id));
+        ids
+    }
+}
+
+/// Returns the number of bytes of DATA that the given stream's outbound flow control window
+/// allows to be sent. Streams whose windows are not tracked (yet) are not limited by them.
+fn stream_window(conn: &HttpConnection, stream_id: StreamId) -> usize {
+    match conn.stream_out_window_size(stream_id) {
+        Some(size) => cmp::max(size, 0) as usize,
+        None => ::std::usize::MAX,
+    }
+}
+
+/// Returns the estimated size of the given header block, which is what it costs the stream in
+/// terms of its share of the connection.
+fn block_size(block: &QueuedHeaders) -> usize {
+    header_list_size(block.headers.iter().map(|h| (h.name(), h.value())))
+}
+
+/// A `DataPrioritizer` that provides the single chunk that the `Scheduler` picked.
+struct ScheduledChunk<'a>(Option<DataChunk<'a>>);
+
+impl<'a> DataPrioritizer for ScheduledChunk<'a> {
+    fn get_next_chunk(&mut self) -> HttpResult<Option<DataChunk>> {
+        Ok(self.0.take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Scheduler, DEFAULT_WEIGHT};
+    use http::Header;
+    use http::connection::{EndStream, HttpFrame, SendStatus};
+    use http::frame::{Frame, SettingsFrame, HttpSetting};
+    use http::session::{DefaultSessionState, SessionState};
+    use http::session::Server as ServerMarker;
+    use http::tests::common::{TestStream, TestSession, build_mock_http_conn, MockSendFrame};
+    use http::tests::common::MockReceiveFrame;
+
+    /// Returns the stream ID and the type of each frame that was sent.
+    fn sent_frames(sender: &MockSendFrame) -> Vec<(u32, &'static str)> {
+        sender.sent.iter().map(|raw| {
+            match HttpFrame::from_raw(raw).unwrap() {
+                HttpFrame::HeadersFrame(frame) => (frame.get_stream_id(), "headers"),
+                HttpFrame::DataFrame(frame) => (frame.get_stream_id(), "data"),
+                _ => panic!("Unexpected frame"),
+            }
+        }).collect()
+    }
+
+    /// Tests that headers and DATA are interleaved according to the weights of the streams and
+    /// that a stream's DATA never overtakes its headers.
+    ///
+    /// Both streams start out at the same virtual time, so both header blocks go first, but the
+    /// heavier stream 3 is charged a lot less for its headers and gets to send its DATA first.
+    #[test]
+    fn test_scheduler_order() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        for &id in &[1, 3] {
+            let mut stream = TestStream::new();
+            stream.set_outgoing(vec![1, 2, 3]);
+            state.insert_incoming(id, stream).unwrap();
+        }
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        let mut scheduler = Scheduler::new();
+        scheduler.queue_headers(vec![Header::new(b":status", b"200")], 1, EndStream::No);
+        scheduler.queue_headers(vec![Header::new(b":status", b"200")], 3, EndStream::No);
+        scheduler.set_weight(3, 200);
+        assert_eq!(scheduler.weight(1), DEFAULT_WEIGHT);
+        assert_eq!(scheduler.streams_with_queued_headers(), vec![1, 3]);
+        assert_eq!(scheduler.queued_len(), 2 * (7 + 3 + 32));
+
+        let mut buf = [0; 2];
+        loop {
+            let res = scheduler.send_next(&mut state, &mut conn, &mut sender, &mut buf).unwrap();
+            if res == SendStatus::Nothing {
+                break;
+            }
+        }
+
+        assert_eq!(sent_frames(&sender), vec![
+            (1, "headers"), (3, "headers"),
+            (3, "data"), (3, "data"),
+            (1, "data"), (1, "data"),
+        ]);
+        assert!(!scheduler.has_queued_headers(1));
+        assert_eq!(scheduler.queued_len(), 0);
+        // The weight is forgotten once the stream has nothing more to send.
+        assert_eq!(scheduler.weight(3), DEFAULT_WEIGHT);
+    }
+
+    /// Tests that header blocks are still sent while DATA is held back by the connection's
+    /// outbound flow control window.
+    #[test]
+    fn test_scheduler_headers_not_flow_controlled() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        // Exhaust the connection's outbound window.
+        {
+            let mut exhausting = TestStream::new();
+            exhausting.set_outgoing(vec![0; 65_535]);
+            state.insert_incoming(3, exhausting).unwrap();
+            let mut buf = vec![0; 65_535];
+            let mut scheduler = Scheduler::new();
+            scheduler.send_next(&mut state, &mut conn, &mut sender, &mut buf).unwrap();
+            assert_eq!(conn.out_window_size(), 0);
+        }
+        let mut stream = TestStream::new();
+        stream.set_outgoing(vec![1, 2, 3]);
+        state.insert_incoming(5, stream).unwrap();
+        let mut scheduler = Scheduler::new();
+        scheduler.queue_headers(vec![Header::new(b":status", b"200")], 7, EndStream::Yes);
+        let mut buf = [0; 10];
+
+        let res = scheduler.send_next(&mut state, &mut conn, &mut sender, &mut buf).unwrap();
+        assert_eq!(res, SendStatus::Sent);
+        let res = scheduler.send_next(&mut state, &mut conn, &mut sender, &mut buf).unwrap();
+        assert_eq!(res, SendStatus::Throttled);
+        assert_eq!(sent_frames(&sender), vec![(3, "data"), (7, "headers")]);
+        assert_eq!(scheduler.flow_blocked_streams(), &[5]);
+    }
+
+    /// Tests that trailers wait for all of the stream's DATA and end the stream in place of the
+    /// last DATA frame.
+    #[test]
+    fn test_scheduler_trailers() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        let mut stream = TestStream::new();
+        stream.set_outgoing(vec![1, 2, 3, 4, 5]);
+        state.insert_incoming(1, stream).unwrap();
+        // A stream without any DATA sends the trailers right away.
+        let mut stream = TestStream::new();
+        stream.set_outgoing(vec![]);
+        state.insert_incoming(3, stream).unwrap();
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        let mut scheduler = Scheduler::new();
+        scheduler.queue_headers(vec![Header::new(b":status", b"200")], 1, EndStream::No);
+        scheduler.queue_trailers(vec![Header::new(b"grpc-status".to_vec(), b"0".to_vec())], 1);
+        scheduler.queue_trailers(vec![Header::new(b"grpc-status".to_vec(), b"0".to_vec())], 3);
+        assert!(scheduler.has_queued_trailers(1));
+        let mut buf = [0; 3];
+
+        while scheduler.send_next(&mut state, &mut conn, &mut sender, &mut buf).unwrap() ==
+                SendStatus::Sent {}
+
+        assert_eq!(sent_frames(&sender), vec![
+            (1, "headers"), (3, "headers"), (1, "data"), (1, "data"), (1, "headers"),
+        ]);
+        let end_stream: Vec<_> = sender.sent.iter().map(|raw| {
+            match HttpFrame::from_raw(raw).unwrap() {
+                HttpFrame::HeadersFrame(frame) => frame.is_end_of_stream(),
+                HttpFrame::DataFrame(frame) => frame.is_end_of_stream(),
+                _ => panic!("Unexpected frame"),
+            }
+        }).collect();
+        assert_eq!(end_stream, vec![false, true, false, false, true]);
+        assert!(!scheduler.has_queued_trailers(1));
+        assert_eq!(scheduler.queued_len(), 0);
+    }
+
+    /// Tests that the streams share the connection in proportion to their weights, without the
+    /// lighter ones being starved, and that the weight of a stream that is not open yet is kept.
+    #[test]
+    fn test_scheduler_fairness() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        for &id in &[1, 3] {
+            let mut stream = TestStream::new();
+            stream.set_outgoing(vec![0; 1000]);
+            state.insert_incoming(id, stream).unwrap();
+        }
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        let mut scheduler = Scheduler::new();
+        scheduler.set_weight(1, 255);
+        scheduler.set_weight(3, 63);
+        // As if a PRIORITY frame were received ahead of the stream's request.
+        scheduler.set_weight(5, 127);
+        let mut buf = [0; 10];
+
+        for _ in 0..50 {
+            scheduler.send_next(&mut state, &mut conn, &mut sender, &mut buf).unwrap();
+        }
+
+        let sent = sent_frames(&sender);
+        assert_eq!(sent.iter().filter(|&&(id, _)| id == 1).count(), 40);
+        assert_eq!(sent.iter().filter(|&&(id, _)| id == 3).count(), 10);
+        assert_eq!(scheduler.weight(5), 127);
+    }
+
+    /// Tests that a stream's DATA is held back by the stream's own flow control window, while the
+    /// other streams keep sending theirs.
+    #[test]
+    fn test_scheduler_stream_window() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        let mut stream = TestStream::new();
+        stream.set_outgoing(vec![1, 2, 3, 4, 5, 6]);
+        state.insert_incoming(1, stream).unwrap();
+        let mut stream = TestStream::new();
+        stream.set_outgoing(vec![1, 2]);
+        state.insert_incoming(3, stream).unwrap();
+        let mut conn = build_mock_http_conn();
+        let mut settings = SettingsFrame::new();
+        settings.add_setting(HttpSetting::InitialWindowSize(4));
+        let mut frame_provider = MockReceiveFrame::new(vec![HttpFrame::SettingsFrame(settings)]);
+        conn.handle_next_frame(&mut frame_provider, &mut TestSession::new()).unwrap();
+        let mut sender = MockSendFrame::new();
+        let mut scheduler = Scheduler::new();
+        scheduler.queue_headers(vec![Header::new(b":status", b"200")], 1, EndStream::No);
+        scheduler.queue_headers(vec![Header::new(b":status", b"200")], 3, EndStream::No);
+        let mut buf = [0; 10];
+
+        while scheduler.send_next(&mut state, &mut conn, &mut sender, &mut buf).unwrap() ==
+                SendStatus::Sent {}
+
+        assert_eq!(sent_frames(&sender), vec![
+            (1, "headers"), (3, "headers"), (1, "data"), (3, "data"),
+        ]);
+        let lens: Vec<_> = sender.sent.iter().map(|raw| raw.payload().len()).collect();
+        assert_eq!(&lens[2..], &[4, 2]);
+        assert_eq!(conn.stream_out_window_size(1), Some(0));
+        assert_eq!(scheduler.flow_blocked_streams(), &[1]);
+    }
+}
@@ -0,0 +1,399 @@
+//! Conversions between solicit's HTTP semantics types and those of the `http` crate.
+//!
+//! Requests and responses are converted by mapping the pseudo-headers to the parts of the `http`
+//! crate's types that they correspond to (e.g. the `:method`, `:scheme`, `:authority` and `:path`
+//! to the request's method and URI) and back. Stream IDs have no counterpart in the `http` crate,
+//! so the converted requests and responses have a stream ID of 0, which needs to be set before
+//! they are sent.
+//!
+//! The module is only available with the `http-types` feature.
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+use http_types;
+use http_types::header::{HeaderMap, HeaderName, HeaderValue};
+
+use http::semantics::{
+    Header,
+    StaticHeader,
+    Method,
+    StatusCode,
+    PseudoHeaders,
+    Request,
+    Response,
+};
+
+/// The headers that are specific to HTTP/1.x connections, which must not appear in HTTP/2
+/// messages (section 8.1.2.2.).
+const CONNECTION_HEADERS: &'static [&'static str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// The error returned when a value cannot be represented by the types on the other side of a
+/// conversion.
+#[derive(Clone, PartialEq, Debug)]
+pub enum InteropError {
+    /// The method name is not a valid token.
+    InvalidMethod(Vec<u8>),
+    /// The status code is outside of the range that HTTP defines.
+    InvalidStatus(u16),
+    /// The header name is not valid.
+    InvalidHeaderName(Vec<u8>),
+    /// The header value contains characters that are not allowed.
+    InvalidHeaderValue(Vec<u8>),
+    /// The request's `:scheme`, `:authority` and `:path` do not make up a valid URI.
+    InvalidUri,
+    /// The pseudo-header with the given name is missing.
+    MissingPseudoHeader(&'static str),
+}
+
+impl fmt::Display for InteropError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InteropError::InvalidMethod(ref name) => {
+                write!(fmt, "Invalid method: {:?}", String::from_utf8_lossy(name))
+            },
+            InteropError::InvalidStatus(status) => write!(fmt, "Invalid status: {}", status),
+            InteropError::InvalidHeaderName(ref name) => {
+                write!(fmt, "Invalid header name: {:?}", String::from_utf8_lossy(name))
+            },
+            InteropError::InvalidHeaderValue(ref value) => {
+                write!(fmt, "Invalid header value: {:?}", String::from_utf8_lossy(value))
+            },
+            InteropError::InvalidUri => write!(fmt, "{}", self.description()),
+            InteropError::MissingPseudoHeader(name) => {
+                write!(fmt, "Missing pseudo-header: {}", name)
+            },
+        }
+    }
+}
+
+impl Error for InteropError {
+    fn description(&self) -> &str {
+        match *self {
+            InteropError::InvalidMethod(_) => "Invalid method",
+            InteropError::InvalidStatus(_) => "Invalid status",
+            InteropError::InvalidHeaderName(_) => "Invalid header name",
+            InteropError::InvalidHeaderValue(_) => "Invalid header value",
+            InteropError::InvalidUri => "Invalid request URI",
+            InteropError::MissingPseudoHeader(_) => "Missing pseudo-header",
+        }
+    }
+}
+
+impl<'a> From<&'a http_types::Method> for Method {
+    fn from(method: &'a http_types::Method) -> Method {
+        Method::from(method.as_str().as_bytes())
+    }
+}
+
+impl From<http_types::Method> for Method {
+    fn from(method: http_types::Method) -> Method {
+        Method::from(&method)
+    }
+}
+
+impl TryFrom<Method> for http_types::Method {
+    type Error = InteropError;
+
+    fn try_from(method: Method) -> Result<http_types::Method, InteropError> {
+        http_types::Method::from_bytes(method.as_bytes())
+            .map_err(|_| InteropError::InvalidMethod(method.as_bytes().to_vec()))
+    }
+}
+
+impl From<StatusCode> for http_types::StatusCode {
+    fn from(status: StatusCode) -> http_types::StatusCode {
+        // All codes between 100 and 599 are valid for the `http` crate as well.
+        http_types::StatusCode::from_u16(status.as_u16()).unwrap()
+    }
+}
+
+impl TryFrom<http_types::StatusCode> for StatusCode {
+    type Error = InteropError;
+
+    fn try_from(status: http_types::StatusCode) -> Result<StatusCode, InteropError> {
+        StatusCode::from_u16(status.as_u16())
+            .ok_or(InteropError::InvalidStatus(status.as_u16()))
+    }
+}
+
+/// Builds a `HeaderMap` out of the given headers, skipping any pseudo-headers.
+///
+/// Fails if any of the headers cannot be represented in a `HeaderMap`.
+pub fn to_header_map(headers: &[Header]) -> Result<HeaderMap, InteropError> {
+    let mut map = HeaderMap::with_capacity(headers.len());
+    for header in headers.iter().filter(|h| !h.name().starts_with(b":")) {
+        let name = try!(HeaderName::from_bytes(header.name())
+            .map_err(|_| InteropError::InvalidHeaderName(header.name().to_vec())));
+        let value = try!(HeaderValue::from_bytes(header.value())
+            .map_err(|_| InteropError::InvalidHeaderValue(header.value().to_vec())));
+        map.append(name, value);
+    }
+    Ok(map)
+}
+
+/// Returns the headers found in the given `HeaderMap`, in the order in which they were added.
+///
+/// The names in a `HeaderMap` are always lowercase, as HTTP/2 requires.
+pub fn from_header_map(map: &HeaderMap) -> Vec<StaticHeader> {
+    map.iter().map(|(name, value)| {
+        Header::new(name.as_str().as_bytes().to_vec(), value.as_bytes().to_vec())
+    }).collect()
+}
+
+/// Returns the regular headers of the given `HeaderMap`, skipping the ones that HTTP/2 does not
+/// allow.
+fn regular_headers(map: &HeaderMap) -> Vec<StaticHeader> {
+    from_header_map(map).into_iter()
+                        .filter(|h| !CONNECTION_HEADERS.iter().any(|n| h.name() == n.as_bytes()))
+                        .collect()
+}
+
+impl From<http_types::Request<Vec<u8>>> for Request<'static, 'static> {
+    /// Converts the request, placing its method and the parts of its URI into the
+    /// corresponding pseudo-headers. Without an authority in the URI, the `host` header is used
+    /// as the `:authority` instead. Headers specific to HTTP/1.x connections are dropped.
+    fn from(request: http_types::Request<Vec<u8>>) -> Request<'static, 'static> {
+        let (parts, body) = request.into_parts();
+        let mut headers = vec![Header::new(b":method", parts.method.as_str().as_bytes().to_vec())];
+        let is_connect = parts.method == http_types::Method::CONNECT;
+        if !is_connect {
+            let scheme = parts.uri.scheme_str().unwrap_or("https");
+            headers.push(Header::new(b":scheme", scheme.as_bytes().to_vec()));
+        }
+        let mut regular = regular_headers(&parts.headers);
+        let authority = match parts.uri.authority() {
+            Some(authority) => Some(authority.as_str().as_bytes().to_vec()),
+            None => {
+                regular.iter().position(|h| h.name() == b"host")
+                       .map(|i| regular.remove(i).value().to_vec())
+            },
+        };
+        if let Some(authority) = authority {
+            headers.push(Header::new(&b":authority"[..], authority));
+        }
+        if !is_connect {
+            let path = parts.uri.path_and_query().map_or("/", |p| p.as_str());
+            headers.push(Header::new(b":path", path.as_bytes().to_vec()));
+        }
+        headers.extend(regular);
+
+        Request {
+            stream_id: 0,
+            headers: headers,
+            body: body,
+        }
+    }
+}
+
+impl<'n, 'v> TryFrom<Request<'n, 'v>> for http_types::Request<Vec<u8>> {
+    type Error = InteropError;
+
+    /// Converts the request, building its URI out of the `:scheme`, `:authority` and `:path`
+    /// pseudo-headers.
+    fn try_from(request: Request<'n, 'v>) -> Result<http_types::Request<Vec<u8>>, InteropError> {
+        let pseudo = PseudoHeaders::parse(&request.headers);
+        let method = try!(pseudo.method.ok_or(InteropError::MissingPseudoHeader(":method")));
+        let method = try!(http_types::Method::try_from(Method::from(&method[..])));
+
+        let mut uri = http_types::Uri::builder();
+        if let Some(scheme) = pseudo.scheme {
+            uri = uri.scheme(&scheme[..]);
+        }
+        if let Some(authority) = pseudo.authority {
+            uri = uri.authority(&authority[..]);
+        }
+        if let Some(path) = pseudo.path {
+            uri = uri.path_and_query(&path[..]);
+        }
+        let uri = try!(uri.build().map_err(|_| InteropError::InvalidUri));
+
+        let mut converted = http_types::Request::new(request.body);
+        *converted.method_mut() = method;
+        *converted.uri_mut() = uri;
+        *converted.headers_mut() = try!(to_header_map(&request.headers));
+        Ok(converted)
+    }
+}
+
+impl From<http_types::Response<Vec<u8>>> for Response<'static, 'static> {
+    /// Converts the response, placing its status into the `:status` pseudo-header. Headers
+    /// specific to HTTP/1.x connections are dropped.
+    fn from(response: http_types::Response<Vec<u8>>) -> Response<'static, 'static> {
+        let (parts, body) = response.into_parts();
+        let status = parts.status.as_str().as_bytes().to_vec();
+        let mut headers = vec![Header::new(b":status", status)];
+        headers.extend(regular_headers(&parts.headers));
+
+        Response {
+            stream_id: 0,
+            headers: headers,
+            body: body,
+            trailers: Vec::new(),
+        }
+    }
+}
+
+impl<'n, 'v> TryFrom<Response<'n, 'v>> for http_types::Response<Vec<u8>> {
+    type Error = InteropError;
+
+    /// Converts the response, taking its status from the `:status` pseudo-header.
+    ///
+    /// The `http` crate's responses have no place for trailers, so they are not carried over.
+    fn try_from(response: Response<'n, 'v>)
+            -> Result<http_types::Response<Vec<u8>>, InteropError> {
+        let status = try!(response.status().map_err(|_| {
+            InteropError::MissingPseudoHeader(":status")
+        }));
+
+        let mut converted = http_types::Response::new(response.body);
+        *converted.status_mut() = status.into();
+        *converted.headers_mut() = try!(to_header_map(&response.headers));
+        Ok(converted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use http_types;
+
+    use super::{InteropError, to_header_map, from_header_map};
+    use http::semantics::{Header, Method, StatusCode, Request, Response};
+
+    /// Tests the conversions of methods in both directions.
+    #[test]
+    fn test_method_conversions() {
+        assert_eq!(Method::from(http_types::Method::GET), Method::Get);
+        assert_eq!(Method::from(http_types::Method::from_bytes(b"PROPFIND").unwrap()),
+                   Method::Extension(b"PROPFIND".to_vec()));
+
+        assert_eq!(http_types::Method::try_from(Method::Post).unwrap(), http_types::Method::POST);
+        assert_eq!(http_types::Method::try_from(Method::Extension(b"BAD METHOD".to_vec())),
+                   Err(InteropError::InvalidMethod(b"BAD METHOD".to_vec())));
+    }
+
+    /// Tests the conversions of status codes in both directions.
+    #[test]
+    fn test_status_conversions() {
+        let status = StatusCode::from_u16(404).unwrap();
+        assert_eq!(http_types::StatusCode::from(status), http_types::StatusCode::NOT_FOUND);
+        assert_eq!(StatusCode::try_from(http_types::StatusCode::OK).unwrap().as_u16(), 200);
+        assert_eq!(StatusCode::try_from(http_types::StatusCode::from_u16(999).unwrap()),
+                   Err(InteropError::InvalidStatus(999)));
+    }
+
+    /// Tests that headers are converted to a `HeaderMap` without their pseudo-headers, and back.
+    #[test]
+    fn test_header_map_conversions() {
+        let headers = vec![
+            Header::new(b":status", b"200"),
+            Header::new(b"x-a".to_vec(), b"1".to_vec()),
+            Header::new(b"x-a".to_vec(), b"2".to_vec()),
+            Header::new(b"x-b".to_vec(), b"3".to_vec()),
+        ];
+
+        let map = to_header_map(&headers).unwrap();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get_all("x-a").iter().count(), 2);
+        assert_eq!(from_header_map(&map), headers[1..].to_vec());
+
+        let invalid = vec![Header::new(b"x-a".to_vec(), b"\n".to_vec())];
+        assert_eq!(to_header_map(&invalid).err().unwrap(),
+                   InteropError::InvalidHeaderValue(b"\n".to_vec()));
+    }
+
+    /// Tests that an `http` crate request is converted into one with the pseudo-headers.
+    #[test]
+    fn test_request_from_http() {
+        let request = http_types::Request::builder()
+            .method("POST")
+            .uri("http://example.com/upload?x=1")
+            .header("content-type", "text/plain")
+            .header("connection", "keep-alive")
+            .body(b"hi".to_vec())
+            .unwrap();
+
+        let request = Request::from(request);
+        assert_eq!(request.stream_id, 0);
+        assert_eq!(request.headers, vec![
+            Header::new(b":method", b"POST"),
+            Header::new(b":scheme", b"http"),
+            Header::new(&b":authority"[..], &b"example.com"[..]),
+            Header::new(b":path", &b"/upload?x=1"[..]),
+            Header::new(&b"content-type"[..], &b"text/plain"[..]),
+        ]);
+        assert_eq!(request.body, b"hi".to_vec());
+
+        // Without an authority in the URI, the `host` header takes its place.
+        let request = http_types::Request::builder()
+            .uri("/")
+            .header("host", "example.com")
+            .body(vec![])
+            .unwrap();
+        assert_eq!(Request::from(request).headers, vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(&b":authority"[..], &b"example.com"[..]),
+            Header::new(b":path", b"/"),
+        ]);
+    }
+
+    /// Tests that a request is converted into an `http` crate request, and that one without a
+    /// `:method` cannot be.
+    #[test]
+    fn test_request_into_http() {
+        let request = Request {
+            stream_id: 1,
+            headers: vec![
+                Header::new(b":method", b"GET"),
+                Header::new(b":scheme", b"https"),
+                Header::new(&b":authority"[..], &b"example.com"[..]),
+                Header::new(b":path", b"/a?b=c"),
+                Header::new(b"accept", b"*/*"),
+            ],
+            body: vec![],
+        };
+
+        let converted = http_types::Request::try_from(request).unwrap();
+        assert_eq!(converted.method(), &http_types::Method::GET);
+        assert_eq!(converted.uri().to_string(), "https://example.com/a?b=c");
+        assert_eq!(converted.headers().len(), 1);
+        assert_eq!(converted.headers()["accept"], "*/*");
+
+        let request = Request { stream_id: 1, headers: vec![], body: vec![] };
+        assert_eq!(http_types::Request::try_from(request).err().unwrap(),
+                   InteropError::MissingPseudoHeader(":method"));
+    }
+
+    /// Tests the conversions of responses in both directions.
+    #[test]
+    fn test_response_conversions() {
+        let response = http_types::Response::builder()
+            .status(404)
+            .header("content-length", "3")
+            .body(b"abc".to_vec())
+            .unwrap();
+
+        let response = Response::from(response);
+        assert_eq!(response.status_code().unwrap(), 404);
+        assert_eq!(response.headers[1], Header::new(&b"content-length"[..], b"3"));
+        assert_eq!(response.body, b"abc".to_vec());
+
+        let converted = http_types::Response::try_from(response).unwrap();
+        assert_eq!(converted.status(), http_types::StatusCode::NOT_FOUND);
+        assert_eq!(converted.headers()["content-length"], "3");
+        assert_eq!(converted.body(), &b"abc".to_vec());
+
+        let response = Response::new(1, vec![], vec![]);
+        assert_eq!(http_types::Response::try_from(response).err().unwrap(),
+                   InteropError::MissingPseudoHeader(":status"));
+    }
+}
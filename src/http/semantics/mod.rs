@@ -0,0 +1,601 @@
+//! The module implements the HTTP semantics that solicit exposes -- headers, requests and
+//! responses, along with their methods and status codes -- independently of how they are framed
+//! on an HTTP/2 connection.
+//!
+//! All the types are re-exported from the `solicit::http` module. With the `http-types` feature
+//! enabled, they can be converted to and from the types of the `http` crate (see the `interop`
+//! module).
+use std::borrow::Cow;
+use std::fmt;
+
+use http::{StreamId, ConnectionId, HttpError, HttpResult};
+
+#[cfg(feature="http-types")]
+pub mod interop;
+
+/// An alias for the type that represents an HTTP/2 header where both the name and the value is
+/// owned.
+pub type OwnedHeader = (Vec<u8>, Vec<u8>);
+
+/// A convenience struct representing a part of a header (either the name or the value) that can be
+/// either an owned or a borrowed byte sequence.
+pub struct HeaderPart<'a>(Cow<'a, [u8]>);
+impl<'a> From<Vec<u8>> for HeaderPart<'a> {
+    fn from(vec: Vec<u8>) -> HeaderPart<'a> {
+        HeaderPart(Cow::Owned(vec))
+    }
+}
+
+impl<'a> From<&'a [u8]> for HeaderPart<'a> {
+    fn from(buf: &'a [u8]) -> HeaderPart<'a> {
+        HeaderPart(Cow::Borrowed(buf))
+    }
+}
+
+impl<'a> From<Cow<'a, [u8]>> for HeaderPart<'a> {
+    fn from(cow: Cow<'a, [u8]>) -> HeaderPart<'a> {
+        HeaderPart(cow)
+    }
+}
+
+macro_rules! from_static_size_array {
+    ($N:expr) => (
+        impl<'a> From<&'a [u8; $N]> for HeaderPart<'a> {
+            fn from(buf: &'a [u8; $N]) -> HeaderPart<'a> {
+                buf[..].into()
+            }
+        }
+    );
+}
+
+macro_rules! impl_from_static_size_array {
+    ($($N:expr,)+) => {
+        $(
+            from_static_size_array!($N);
+        )+
+    }
+}
+
+impl_from_static_size_array!(
+    0,
+    1,
+    2,
+    3,
+    4,
+    5,
+    6,
+    7,
+    8,
+    9,
+    10,
+);
+
+impl<'n, 'v> PartialEq<Header<'n, 'v>> for OwnedHeader {
+    fn eq(&self, other: &Header<'n, 'v>) -> bool {
+        &self.0[..] == other.name() && &self.1[..] == other.value()
+    }
+}
+
+impl<'n, 'v> PartialEq<OwnedHeader> for Header<'n, 'v> {
+    fn eq(&self, other: &OwnedHeader) -> bool {
+        &other.0[..] == self.name() && &other.1[..] == self.value()
+    }
+}
+
+/// Represents an HTTP/2 header. Allows both the name and the value to be either an owned or a
+/// borrowed byte sequence.
+///
+/// # Examples
+///
+/// A new `Header` can be created by providing an owned or borrowed name, as well as value:
+///
+/// ```rust
+/// use solicit::http::Header;
+/// // Name and value both borrowed (static) slices.
+/// let h1 = Header::new(b":method", b"GET");
+/// assert_eq!(h1.name(), &b":method"[..]);
+/// assert_eq!(h1.value(), &b"GET"[..]);
+/// // A borrowed slice with a scope-bound lifetime as the value; static name
+/// {
+///     let value = vec![1];
+///     let header = Header::new(&b"x-test-head"[..], &value[..]);
+///     assert_eq!(header.name(), &b"x-test-head"[..]);
+///     assert_eq!(header.value(), &[1][..]);
+/// }
+/// // An owned value, static name
+/// {
+///     let value = vec![1];
+///     let header = Header::new(&b"x-test-head"[..], value);
+///     assert_eq!(header.name(), &b"x-test-head"[..]);
+///     assert_eq!(header.value(), &[1][..]);
+/// }
+/// // An owned name, as well as value
+/// {
+///     let value = vec![1];
+///     let name = b"x-test-head".to_vec();
+///     let header = Header::new(name, value);
+///     assert_eq!(header.name(), &b"x-test-head"[..]);
+///     assert_eq!(header.value(), &[1][..]);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header<'n, 'v> {
+    name: Cow<'n, [u8]>,
+    value: Cow<'v, [u8]>,
+}
+
+/// A type alias for a `Header` where both the name, as well as the value must have a `'static`
+/// lifetime if it is borrowed. Owned parts are allowed.
+pub type StaticHeader = Header<'static, 'static>;
+
+impl<'n, 'v> Header<'n, 'v> {
+    /// Creates a new `Header` with the given name and value.
+    ///
+    /// The name and value need to be convertible into a `HeaderPart`.
+    pub fn new<N: Into<HeaderPart<'n>>, V: Into<HeaderPart<'v>>>(name: N, value: V)
+            -> Header<'n, 'v> {
+        Header {
+            name: name.into().0,
+            value: value.into().0,
+        }
+    }
+
+    /// Return a borrowed representation of the `Header` name.
+    pub fn name(&self) -> &[u8] { &self.name }
+    /// Return a borrowed representation of the `Header` value.
+    pub fn value(&self) -> &[u8] { &self.value }
+}
+
+impl<'n, 'v> Into<OwnedHeader> for Header<'n, 'v> {
+    fn into(self) -> OwnedHeader {
+        (self.name.into_owned(), self.value.into_owned())
+    }
+}
+
+impl<'n, 'v> Into<Header<'n, 'v>> for OwnedHeader {
+    fn into(self) -> Header<'n, 'v> {
+        Header::new(self.0, self.1)
+    }
+}
+
+/// The pseudo-header fields of a received header block, extracted once so that they do not need
+/// to be searched for in the header list whenever they are needed.
+///
+/// Responses only carry the `:status`; the rest are found in requests.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct PseudoHeaders {
+    /// The response status code. `None` if there was no (valid) `:status`.
+    pub status: Option<u16>,
+    pub method: Option<Vec<u8>>,
+    pub path: Option<Vec<u8>>,
+    pub authority: Option<Vec<u8>>,
+    pub scheme: Option<Vec<u8>>,
+}
+
+impl PseudoHeaders {
+    /// Extracts the pseudo-headers from the given header list.
+    ///
+    /// Since pseudo-headers MUST precede all regular header fields, only the leading ones are
+    /// considered. Unknown pseudo-headers are ignored.
+    pub fn parse(headers: &[Header]) -> PseudoHeaders {
+        let mut pseudo = PseudoHeaders::default();
+        for header in headers.iter().take_while(|h| h.name().starts_with(b":")) {
+            let value = || Some(header.value().to_vec());
+            match header.name() {
+                b":status" => pseudo.status = Response::parse_status_code(header.value()).ok(),
+                b":method" => pseudo.method = value(),
+                b":path" => pseudo.path = value(),
+                b":authority" => pseudo.authority = value(),
+                b":scheme" => pseudo.scheme = value(),
+                _ => {},
+            }
+        }
+        pseudo
+    }
+}
+
+/// A struct representing the full raw response received on an HTTP/2 connection.
+///
+/// The full body of the response is included, regardless how large it may be.
+/// The headers contain both the meta-headers, as well as the actual headers.
+#[derive(Clone)]
+pub struct Response<'n, 'v> {
+    /// The ID of the stream to which the response is associated. HTTP/1.1 does
+    /// not really have an equivalent to this.
+    pub stream_id: StreamId,
+    /// Exposes *all* the raw response headers, including the meta-headers.
+    /// (For now the only meta header allowed in HTTP/2 responses is the
+    /// `:status`.)
+    pub headers: Vec<Header<'n, 'v>>,
+    /// The full body of the response as an uninterpreted sequence of bytes.
+    pub body: Vec<u8>,
+    /// The trailer headers received after the body, if any (e.g. the `grpc-status`).
+    ///
+    /// The `SimpleServer` sends the trailers of the responses that its handler returns after
+    /// their body, except for responses that keep their stream open, whose trailers are sent
+    /// using `OpenStream::send_trailers` instead.
+    pub trailers: Vec<Header<'n, 'v>>,
+}
+
+/// A type alias for a `Response` where all headers' names and values must have a `'static`
+/// lifetime if they are borrowed. This means that the parts can also be owned.
+pub type StaticResponse = Response<'static, 'static>;
+
+/// The head of a response: its status and headers, without the body.
+///
+/// The headers can borrow their names and values, so that the head of a response can be handed
+/// out as soon as it is received, without copying the headers (e.g. to a `ResponseDelegate`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResponseHead<'n, 'v> {
+    /// The ID of the stream on which the response was received.
+    pub stream_id: StreamId,
+    /// The status code of the response.
+    pub status: u16,
+    /// All the headers of the response, including the `:status` pseudo-header.
+    pub headers: Vec<Header<'n, 'v>>,
+    /// The ID of the connection on which the response was received, if known.
+    pub connection_id: Option<ConnectionId>,
+}
+
+impl<'n, 'v> ResponseHead<'n, 'v> {
+    /// Creates a new `ResponseHead` from the given headers, extracting the status code from
+    /// them. If the headers do not start with a valid `:status`, a `MalformedResponse` error is
+    /// returned.
+    pub fn new(stream_id: StreamId, headers: Vec<Header<'n, 'v>>)
+            -> HttpResult<ResponseHead<'n, 'v>> {
+        let status = match headers.first() {
+            Some(header) if header.name() == b":status" => {
+                try!(Response::parse_status_code(header.value()))
+            },
+            _ => return Err(HttpError::MalformedResponse),
+        };
+        Ok(ResponseHead {
+            stream_id: stream_id,
+            status: status,
+            headers: headers,
+            connection_id: None,
+        })
+    }
+
+    /// Returns the value of the (first) header with the given name, if there is one.
+    pub fn header(&self, name: &[u8]) -> Option<&[u8]> {
+        self.headers.iter().find(|h| h.name() == name).map(|h| h.value())
+    }
+
+    /// Returns a copy of the head that owns all its headers.
+    pub fn into_owned(self) -> ResponseHead<'static, 'static> {
+        ResponseHead {
+            stream_id: self.stream_id,
+            status: self.status,
+            headers: self.headers.into_iter().map(|h| {
+                let owned: OwnedHeader = h.into();
+                owned.into()
+            }).collect(),
+            connection_id: self.connection_id,
+        }
+    }
+}
+
+impl<'n, 'v> Response<'n, 'v> {
+    /// Creates a new `Response` from its head, body and trailers.
+    pub fn from_parts(head: ResponseHead<'n, 'v>, body: Vec<u8>, trailers: Vec<Header<'n, 'v>>)
+            -> Response<'n, 'v> {
+        Response {
+            stream_id: head.stream_id,
+            headers: head.headers,
+            body: body,
+            trailers: trailers,
+        }
+    }
+
+    /// Returns the head of the response, borrowing its headers.
+    ///
+    /// If the headers do not start with a valid `:status`, a `MalformedResponse` error is
+    /// returned.
+    pub fn head(&self) -> HttpResult<ResponseHead> {
+        let headers = self.headers.iter().map(|h| Header::new(h.name(), h.value())).collect();
+        ResponseHead::new(self.stream_id, headers)
+    }
+
+    /// Creates a new `Response` with all the components already provided.
+    pub fn new(stream_id: StreamId, headers: Vec<OwnedHeader>, body: Vec<u8>)
+            -> Response<'n, 'v> {
+        Response {
+            stream_id: stream_id,
+            headers: headers.into_iter().map(|h| Header::new(h.0, h.1)).collect(),
+            body: body,
+            trailers: Vec::new(),
+        }
+    }
+
+    /// Returns the value of the trailer with the given name, if the response has such a trailer.
+    pub fn trailer(&self, name: &[u8]) -> Option<&[u8]> {
+        self.trailers.iter().find(|h| h.name() == name).map(|h| h.value())
+    }
+
+    /// Returns whether the stream should be closed by the HEADERS frame that starts the response.
+    ///
+    /// This is the case for responses without a body (such as `204 No Content` or `304 Not
+    /// Modified` responses), as there is no need to send an empty DATA frame only so that it can
+    /// carry the END_STREAM flag. Responses with trailers end the stream with the trailers
+    /// instead.
+    pub fn ends_stream_on_headers(&self) -> bool {
+        self.body.is_empty() && self.trailers.is_empty()
+    }
+
+    /// Returns the `StatusCode` of the response, failing in the same way as `status_code`.
+    pub fn status(&self) -> HttpResult<StatusCode> {
+        self.status_code().map(StatusCode)
+    }
+
+    /// Gets the response status code from the pseudo-header. If the response
+    /// does not contain the response as the first pseuo-header, an error is
+    /// returned as such a response is malformed.
+    pub fn status_code(&self) -> HttpResult<u16> {
+        // Since pseudo-headers MUST be found before any regular header fields
+        // and the *only* pseudo-header defined for responses is the `:status`
+        // field, the `:status` MUST be the first header; otherwise, the
+        // response is malformed.
+        if self.headers.len() < 1 {
+            return Err(HttpError::MalformedResponse)
+        }
+        if &self.headers[0].name[..] != &b":status"[..] {
+            Err(HttpError::MalformedResponse)
+        } else {
+            Ok(try!(Response::parse_status_code(&self.headers[0].value)))
+        }
+    }
+
+    /// A helper function that parses a given buffer as a status code and
+    /// returns it as a `u16`, if it is valid.
+    fn parse_status_code(buf: &[u8]) -> HttpResult<u16> {
+        // "The status-code element is a three-digit integer code [...]"
+        if buf.len() != 3 {
+            return Err(HttpError::MalformedResponse);
+        }
+
+        // "There are five values for the first digit"
+        if buf[0] < b'1' || buf[0] > b'5' {
+            return Err(HttpError::MalformedResponse);
+        }
+
+        // The rest of them just have to be digits
+        if buf[1] < b'0' || buf[1] > b'9' || buf[2] < b'0' || buf[2] > b'9' {
+            return Err(HttpError::MalformedResponse);
+        }
+
+        // Finally, we can merge them into an integer
+        Ok(100 * ((buf[0] - b'0') as u16) +
+           10 * ((buf[1] - b'0') as u16) +
+           1 * ((buf[2] - b'0') as u16))
+    }
+}
+
+/// A struct representing a full HTTP/2 request, along with the full body, as a
+/// sequence of bytes.
+#[derive(Clone)]
+pub struct Request<'n, 'v> {
+    /// The ID of the stream that the request was received on. It is ignored when sending a
+    /// request, as the connection assigns the stream IDs (see `ClientConnection::send_request`).
+    pub stream_id: u32,
+    pub headers: Vec<Header<'n, 'v>>,
+    pub body: Vec<u8>,
+}
+
+impl<'n, 'v> Request<'n, 'v> {
+    /// Returns the method of the request, if it has a `:method` pseudo-header.
+    pub fn method(&self) -> Option<Method> {
+        PseudoHeaders::parse(&self.headers).method.map(|method| Method::from(&method[..]))
+    }
+}
+
+/// An HTTP request method.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+    /// Any other method, holding its raw name.
+    Extension(Vec<u8>),
+}
+
+impl Method {
+    /// Returns the name of the method, as found in the `:method` pseudo-header.
+    pub fn as_bytes(&self) -> &[u8] {
+        match *self {
+            Method::Get => b"GET",
+            Method::Head => b"HEAD",
+            Method::Post => b"POST",
+            Method::Put => b"PUT",
+            Method::Delete => b"DELETE",
+            Method::Connect => b"CONNECT",
+            Method::Options => b"OPTIONS",
+            Method::Trace => b"TRACE",
+            Method::Patch => b"PATCH",
+            Method::Extension(ref name) => name,
+        }
+    }
+}
+
+impl<'a> From<&'a [u8]> for Method {
+    fn from(name: &'a [u8]) -> Method {
+        match name {
+            b"GET" => Method::Get,
+            b"HEAD" => Method::Head,
+            b"POST" => Method::Post,
+            b"PUT" => Method::Put,
+            b"DELETE" => Method::Delete,
+            b"CONNECT" => Method::Connect,
+            b"OPTIONS" => Method::Options,
+            b"TRACE" => Method::Trace,
+            b"PATCH" => Method::Patch,
+            _ => Method::Extension(name.to_vec()),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", String::from_utf8_lossy(self.as_bytes()))
+    }
+}
+
+/// An HTTP response status code, i.e. a three-digit number between 100 and 599.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct StatusCode(u16);
+
+impl StatusCode {
+    /// Returns the `StatusCode` with the given numeric value, if it is a valid status code.
+    pub fn from_u16(code: u16) -> Option<StatusCode> {
+        if code >= 100 && code < 600 {
+            Some(StatusCode(code))
+        } else {
+            None
+        }
+    }
+
+    /// Parses the given value of a `:status` pseudo-header.
+    pub fn parse(buf: &[u8]) -> Option<StatusCode> {
+        Response::parse_status_code(buf).ok().map(StatusCode)
+    }
+
+    /// Returns the numeric value of the status code.
+    #[inline]
+    pub fn as_u16(&self) -> u16 {
+        self.0
+    }
+
+    /// Returns whether the status code is informational (1xx).
+    pub fn is_informational(&self) -> bool { self.0 / 100 == 1 }
+    /// Returns whether the status code signals success (2xx).
+    pub fn is_success(&self) -> bool { self.0 / 100 == 2 }
+    /// Returns whether the status code is a redirection (3xx).
+    pub fn is_redirection(&self) -> bool { self.0 / 100 == 3 }
+    /// Returns whether the status code signals a client error (4xx).
+    pub fn is_client_error(&self) -> bool { self.0 / 100 == 4 }
+    /// Returns whether the status code signals a server error (5xx).
+    pub fn is_server_error(&self) -> bool { self.0 / 100 == 5 }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::{Method, StatusCode, Request, Header, OwnedHeader};
+
+    fn _assert_is_static(_: Header<'static, 'static>) {}
+
+    #[test]
+    fn test_owned_to_header_is_static_lifetime() {
+        let owned = (vec![1u8], vec![2u8]);
+        _assert_is_static(owned.into());
+    }
+
+    #[test]
+    fn test_header_from_static_slices_lifetime() {
+        let header = Header::new(b":method", b"GET");
+        _assert_is_static(header);
+    }
+
+    #[test]
+    fn test_header_to_owned_header() {
+        let header = Header::new(b":method", b"GET");
+        let (name, value): OwnedHeader = header.into();
+
+        assert_eq!(name, b":method".to_vec());
+        assert_eq!(value, b"GET".to_vec());
+    }
+
+    #[test]
+    fn test_partial_eq_of_headers() {
+        let fully_static = Header::new(b":method", b"GET");
+        let static_name = Header::new(b":method", b"GET".to_vec());
+        let other = Header::new(b":path", b"/");
+
+        assert!(fully_static == static_name);
+        assert!(fully_static != other);
+        assert!(static_name != other);
+    }
+
+    #[test]
+    fn test_partial_eq_to_owned_header() {
+        let fully_static = Header::new(b":method", b"GET");
+        let owned: OwnedHeader = fully_static.clone().into();
+
+        assert!(fully_static == owned);
+    }
+
+    #[test]
+    fn test_clone_keeps_borrows() {
+        let header = Header::new(b":method", b"GET");
+        let clone = header.clone();
+
+        match clone.name {
+            Cow::Owned(_) => panic!("Expected a borrowed name"),
+            _ => {},
+        };
+        match clone.value {
+            Cow::Owned(_) => panic!("Expected a borrowed value"),
+            _ => {},
+        };
+    }
+
+    /// Tests that methods are parsed from and turned back into their names.
+    #[test]
+    fn test_method_names() {
+        assert_eq!(Method::from(&b"GET"[..]), Method::Get);
+        assert_eq!(Method::from(&b"PATCH"[..]), Method::Patch);
+        assert_eq!(Method::from(&b"PROPFIND"[..]), Method::Extension(b"PROPFIND".to_vec()));
+        // Method names are case-sensitive.
+        assert_eq!(Method::from(&b"get"[..]), Method::Extension(b"get".to_vec()));
+
+        assert_eq!(Method::Connect.as_bytes(), b"CONNECT");
+        assert_eq!(Method::Extension(b"PROPFIND".to_vec()).as_bytes(), b"PROPFIND");
+        assert_eq!(Method::Delete.to_string(), "DELETE");
+    }
+
+    /// Tests the construction and classification of status codes.
+    #[test]
+    fn test_status_code() {
+        assert_eq!(StatusCode::from_u16(99), None);
+        assert_eq!(StatusCode::from_u16(600), None);
+        assert_eq!(StatusCode::parse(b"404"), StatusCode::from_u16(404));
+        assert_eq!(StatusCode::parse(b"4040"), None);
+        assert_eq!(StatusCode::parse(b"600"), None);
+
+        let ok = StatusCode::from_u16(200).unwrap();
+        assert!(ok.is_success());
+        assert!(!ok.is_client_error());
+        assert_eq!(ok.as_u16(), 200);
+        assert_eq!(ok.to_string(), "200");
+        assert!(StatusCode::from_u16(101).unwrap().is_informational());
+        assert!(StatusCode::from_u16(302).unwrap().is_redirection());
+        assert!(StatusCode::from_u16(503).unwrap().is_server_error());
+    }
+
+    /// Tests that the method of a `Request` is taken from its `:method` pseudo-header.
+    #[test]
+    fn test_request_method() {
+        let request = Request {
+            stream_id: 1,
+            headers: vec![Header::new(b":method", b"POST"), Header::new(b":path", b"/")],
+            body: vec![],
+        };
+        assert_eq!(request.method(), Some(Method::Post));
+
+        let request = Request { stream_id: 1, headers: vec![], body: vec![] };
+        assert_eq!(request.method(), None);
+    }
+}
@@ -1,28 +1,49 @@
 //! The module contains a number of reusable components for implementing the server side of an
 //! HTTP/2 connection.
 
+use std::cmp;
+use std::io;
+use std::collections::HashMap;
+use std::time::Instant;
+
 use http::{
+    ConnectionId,
     StreamId,
     Header,
     HttpResult,
+    HttpError,
     HttpScheme,
     ErrorCode,
+    CLIENT_PREFACE,
 };
-use http::frame::{HttpSetting};
+use http::frame::{HttpSetting, StreamDependency};
 use http::connection::{
     SendFrame, ReceiveFrame,
-    HttpConnection, EndStream,
+    HttpConnection, EndStream, ConnectionActivity,
     SendStatus,
 };
 use http::session::{
     Session,
     SessionState,
     Stream,
+    StreamState,
     DefaultStream,
     DefaultSessionState,
+    HeaderPhase,
+    HeaderEvent,
+    HeaderPhaseError,
 };
 use http::session::Server as ServerMarker;
 use http::priority::SimplePrioritizer;
+use http::scheduler::Scheduler;
+use http::memory::{MemoryUsage, MemoryPressure};
+use http::flow_control::FlowControlStrategy;
+use http::rate_limit::RateLimit;
+use http::padding::PaddingStrategy;
+use http::tracing::Role;
+use http::settings::SettingsState;
+use http::config::Http2Config;
+use http::validation::{self, LimitExceeded};
 
 /// The `ServerSession` requires an instance of a type that implements this trait in order to
 /// create a new `Stream` instance once it detects that a client has initiated a new stream. The
@@ -35,6 +56,11 @@ pub trait StreamFactory {
     fn create(&mut self, id: StreamId) -> Self::Stream;
 }
 
+/// A callback that is invoked whenever the client sends a SETTINGS frame (see
+/// `ServerConnection::set_settings_hook`). It receives the settings carried by the frame and the
+/// resulting state of all of the client's settings.
+pub type SettingsHook = Box<FnMut(&[HttpSetting], &SettingsState) + Send>;
+
 /// An implementation of the `Session` trait for a server-side HTTP/2 connection.
 pub struct ServerSession<'a, State, F, S>
         where State: SessionState + 'a,
@@ -43,6 +69,10 @@ pub struct ServerSession<'a, State, F, S>
     state: &'a mut State,
     factory: &'a mut F,
     sender: &'a mut S,
+    settings_hook: Option<&'a mut SettingsHook>,
+    scheduler: Option<&'a mut Scheduler>,
+    /// Whether streams that the client initiates are refused, e.g. to shed load.
+    refuse_new_streams: bool,
 }
 
 impl<'a, State, F, S> ServerSession<'a, State, F, S>
@@ -56,17 +86,119 @@ impl<'a, State, F, S> ServerSession<'a, State, F, S>
             state: state,
             factory: factory,
             sender: sender,
+            settings_hook: None,
+            scheduler: None,
+            refuse_new_streams: false,
         }
     }
+
+    /// Sets the `Scheduler` that the session passes the weights of the streams on to, as the
+    /// client gives them in the HEADERS frames opening the streams.
+    #[inline]
+    pub fn with_scheduler(mut self, scheduler: &'a mut Scheduler)
+            -> ServerSession<'a, State, F, S> {
+        self.scheduler = Some(scheduler);
+        self
+    }
+
+    /// Sets whether the session refuses (with a REFUSED_STREAM error) any streams that the client
+    /// initiates, as it does while the connection is over its memory budget.
+    #[inline]
+    pub fn refusing_new_streams(mut self, refuse: bool) -> ServerSession<'a, State, F, S> {
+        self.refuse_new_streams = refuse;
+        self
+    }
+
+    /// Sets the callback that the session notifies of the client's SETTINGS frames.
+    #[inline]
+    pub fn with_settings_hook(mut self, hook: Option<&'a mut SettingsHook>)
+            -> ServerSession<'a, State, F, S> {
+        self.settings_hook = hook;
+        self
+    }
+
+    /// Resets the given stream, whose body turned out not to match the declared length, after
+    /// notifying the stream.
+    fn body_length_mismatch(&mut self, stream_id: StreamId, conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        if let Some(length) = self.state.body_length(stream_id).cloned() {
+            debug!("Body length mismatch on stream {}: declared {}, received {}",
+                   stream_id, length.declared, length.received);
+            if let Some(stream) = self.state.get_stream_mut(stream_id) {
+                stream.on_body_length_mismatch(&length);
+            }
+        }
+        self.stream_error(stream_id, ErrorCode::ProtocolError, conn)
+    }
+
+    /// Rejects the request on the given stream, which exceeds one of the configured limits, with
+    /// a response carrying the status code for the limit (see `LimitExceeded::status`), closing
+    /// the stream. It is also reset with NO_ERROR, so that the client stops sending the rest of
+    /// the request (section 8.1.).
+    fn reject_request(&mut self, stream_id: StreamId, limit: LimitExceeded,
+                      conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        debug!("Rejecting the request on stream {}: {}", stream_id, limit);
+        if let Some(stream) = self.state.get_stream_mut(stream_id) {
+            stream.close();
+        }
+        let headers = vec![Header::new(b":status", limit.status())];
+        try!(conn.sender(self.sender).send_headers(headers, stream_id, EndStream::Yes));
+        conn.sender(self.sender).send_rst_stream(stream_id, ErrorCode::NoError)
+    }
+
+    /// Moves the given stream to the header phase that follows the given event. A stream that
+    /// receives the event out of sequence (e.g. a second header block that cannot be trailers) is
+    /// reset with a PROTOCOL_ERROR instead.
+    ///
+    /// Returns whether the frame that caused the event should be handled any further.
+    fn advance_header_phase(&mut self, stream_id: StreamId, event: HeaderEvent,
+                            conn: &mut HttpConnection)
+            -> HttpResult<bool> {
+        // A stream that is already closed (e.g. because it was reset) is not checked again.
+        if self.state.get_stream_ref(stream_id).map_or(true, |stream| stream.is_closed()) {
+            return Ok(true);
+        }
+        let res = match self.state.header_phase(stream_id) {
+            Some(phase) => phase.transition(event).map(|next| *phase = next),
+            None => return Ok(true),
+        };
+        match res {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                debug!("Stream {}: {}", stream_id, e);
+                try!(self.stream_error(stream_id, ErrorCode::ProtocolError, conn));
+                Ok(false)
+            },
+        }
+    }
+}
+
+/// Returns the header phase events that sending the given response headers amounts to.
+fn response_events(headers: &[Header], end_stream: EndStream) -> Vec<HeaderEvent> {
+    let informational = headers.iter().any(|h| {
+        h.name() == b":status" && h.value().starts_with(b"1")
+    });
+    let mut events = vec![HeaderEvent::Headers { informational: informational }];
+    if end_stream == EndStream::Yes {
+        events.push(HeaderEvent::EndStream);
+    }
+    events
 }
 
 impl<'a, State, F, S> Session for ServerSession<'a, State, F, S>
         where State: SessionState + 'a,
               S: SendFrame + 'a,
               F: StreamFactory<Stream=State::Stream> + 'a {
-    fn new_data_chunk(&mut self, stream_id: StreamId, data: &[u8], _: &mut HttpConnection)
+    fn new_data_chunk(&mut self, stream_id: StreamId, data: &[u8], conn: &mut HttpConnection)
             -> HttpResult<()> {
         debug!("Data chunk for stream {}", stream_id);
+        if !try!(self.advance_header_phase(stream_id, HeaderEvent::Data, conn)) {
+            return Ok(());
+        }
+        if !self.state.body_length(stream_id).map_or(true, |l| l.data_received(data.len())) {
+            return self.body_length_mismatch(stream_id, conn);
+        }
         let mut stream = match self.state.get_stream_mut(stream_id) {
             None => {
                 debug!("Received a frame for an unknown stream!");
@@ -83,29 +215,125 @@ impl<'a, State, F, S> Session for ServerSession<'a, State, F, S>
             &mut self,
             stream_id: StreamId,
             headers: Vec<Header<'n, 'v>>,
-            _conn: &mut HttpConnection)
+            conn: &mut HttpConnection)
             -> HttpResult<()> {
         debug!("Headers for stream {}", stream_id);
-        match self.state.get_stream_mut(stream_id) {
+        // Requests never carry informational headers.
+        let event = HeaderEvent::Headers { informational: false };
+        if !try!(self.advance_header_phase(stream_id, event, conn)) {
+            return Ok(());
+        }
+        let strict = conn.config().compliance.is_strict();
+        let res = match self.state.get_stream_mut(stream_id) {
             Some(stream) => {
                 // This'd correspond to having received trailers...
-                stream.set_headers(headers);
+                let res = if !strict {
+                    Ok(None)
+                } else if stream.headers_received() {
+                    validation::validate_trailers(&headers).map(|_| None)
+                } else {
+                    validation::validate_request_headers(&headers)
+                        .and_then(|_| validation::content_length(&headers))
+                };
+                match res {
+                    Ok(_) => stream.set_headers(headers),
+                    Err(ref e) => stream.on_malformed_headers(e),
+                };
+                res
+            },
+            None if stream_id % 2 == 0 => {
+                // Clients may only initiate odd-numbered streams (section 5.1.1.)
+                debug!("Client initiated an even-numbered stream {}", stream_id);
+                if strict {
+                    return Err(HttpError::LocalConnectionError(ErrorCode::ProtocolError));
+                }
                 return Ok(());
             },
-            None => {},
+            None => {
+                // New stream initiated by the client
+                let mut stream = self.factory.create(stream_id);
+                let limits = validation::check_limits(&headers,
+                                                      conn.config().max_header_count,
+                                                      conn.config().max_path_length);
+                let res = if !strict || limits.is_err() {
+                    Ok(None)
+                } else {
+                    validation::validate_request_headers(&headers)
+                        .and_then(|_| validation::content_length(&headers))
+                };
+                match (&limits, &res) {
+                    (&Err(ref limit), _) => stream.on_limit_exceeded(limit),
+                    (_, &Ok(_)) => stream.set_headers(headers),
+                    (_, &Err(ref e)) => stream.on_malformed_headers(e),
+                };
+                let refused = match conn.config().max_concurrent_streams {
+                    Some(max) => {
+                        let open = self.state.iter().filter(|&(_, ref s)| !s.is_closed()).count();
+                        open >= max as usize
+                    },
+                    None => false,
+                };
+                let _ = self.state.insert_incoming(stream_id, stream);
+                // The headers that opened the stream started the request.
+                if let Some(phase) = self.state.header_phase(stream_id) {
+                    *phase = HeaderPhase::Data;
+                }
+                if refused {
+                    debug!("Refusing stream {}: too many concurrent streams", stream_id);
+                    return self.stream_error(stream_id, ErrorCode::RefusedStream, conn);
+                }
+                if self.refuse_new_streams {
+                    debug!("Refusing stream {}: shedding load", stream_id);
+                    return self.stream_error(stream_id, ErrorCode::RefusedStream, conn);
+                }
+                if let Err(limit) = limits {
+                    return self.reject_request(stream_id, limit, conn);
+                }
+                res
+            },
         };
-        // New stream initiated by the client
-        let mut stream = self.factory.create(stream_id);
-        stream.set_headers(headers);
-        // TODO(mlalic): Once the `Session` trait is able to signal connection failure, handle
-        //               the error case here and return the corresponding protocol error.
-        let _ = self.state.insert_incoming(stream_id, stream);
+        match res {
+            Ok(Some(length)) => self.state.expect_body_length(stream_id, length),
+            Ok(None) => {},
+            Err(e) => {
+                debug!("Malformed headers on stream {}: {}", stream_id, e);
+                try!(self.stream_error(stream_id, ErrorCode::ProtocolError, conn));
+            },
+        };
+        Ok(())
+    }
+
+    fn new_priority(&mut self, stream_id: StreamId, dependency: StreamDependency,
+                    _conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        debug!("Priority for stream {}: {:?}", stream_id, dependency);
+        if let Some(ref mut scheduler) = self.scheduler {
+            scheduler.set_weight(stream_id, dependency.weight);
+        }
         Ok(())
     }
 
-    fn end_of_stream(&mut self, stream_id: StreamId, _: &mut HttpConnection)
+    fn new_push_promise(&mut self, stream_id: StreamId, promised_stream_id: StreamId,
+                        conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        // Clients must never push (section 8.2.)
+        debug!("Client sent a PUSH_PROMISE for stream {} on stream {}",
+               promised_stream_id, stream_id);
+        if conn.config().compliance.is_strict() {
+            return Err(HttpError::LocalConnectionError(ErrorCode::ProtocolError));
+        }
+        Ok(())
+    }
+
+    fn end_of_stream(&mut self, stream_id: StreamId, conn: &mut HttpConnection)
             -> HttpResult<()> {
         debug!("End of stream {}", stream_id);
+        if !try!(self.advance_header_phase(stream_id, HeaderEvent::EndStream, conn)) {
+            return Ok(());
+        }
+        if !self.state.body_length(stream_id).map_or(true, |l| l.is_complete()) {
+            return self.body_length_mismatch(stream_id, conn);
+        }
         let mut stream = match self.state.get_stream_mut(stream_id) {
             None => {
                 debug!("Received a frame for an unknown stream!");
@@ -120,14 +348,41 @@ impl<'a, State, F, S> Session for ServerSession<'a, State, F, S>
     fn rst_stream(&mut self, stream_id: StreamId, error_code: ErrorCode, _: &mut HttpConnection)
             -> HttpResult<()> {
         debug!("RST_STREAM id={:?}, error={:?}", stream_id, error_code);
-        self.state.get_stream_mut(stream_id).map(|stream| stream.on_rst_stream(error_code));
+        if let Some(stream) = self.state.get_stream_mut(stream_id) {
+            stream.on_rst_stream(error_code);
+        }
         Ok(())
     }
 
-    fn new_settings(&mut self, _settings: Vec<HttpSetting>, conn: &mut HttpConnection)
+    fn new_settings(&mut self, settings: Vec<HttpSetting>, conn: &mut HttpConnection)
             -> HttpResult<()> {
         debug!("Sending a SETTINGS ack");
-        conn.sender(self.sender).send_settings_ack()
+        try!(conn.sender(self.sender).send_settings_ack());
+        if let Some(ref mut hook) = self.settings_hook {
+            hook(&settings, conn.peer_settings());
+        }
+        Ok(())
+    }
+
+    fn stream_state(&self, stream_id: StreamId) -> Option<StreamState> {
+        self.state.stream_state(stream_id)
+    }
+
+    fn stream_error(&mut self, stream_id: StreamId, error_code: ErrorCode,
+                    conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        debug!("Resetting stream {} with {:?}", stream_id, error_code);
+        if let Some(stream) = self.state.get_stream_mut(stream_id) {
+            stream.close();
+        }
+        conn.sender(self.sender).send_rst_stream(stream_id, error_code)
+    }
+
+    fn connection_error(&mut self, error_code: ErrorCode, conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        debug!("Closing the connection with {:?}", error_code);
+        let last_stream_id = self.state.last_incoming_stream_id();
+        conn.sender(self.sender).send_goaway(last_stream_id, error_code)
     }
 }
 
@@ -145,6 +400,16 @@ pub struct ServerConnection<F, State=DefaultSessionState<ServerMarker, DefaultSt
     /// Creates `Stream` instances for client-initiated streams. This allows the client of the
     /// `ServerConnection` to implement custom handling of a newly initiated stream.
     factory: F,
+    /// Notified of the SETTINGS frames that the client sends.
+    settings_hook: Option<SettingsHook>,
+    /// Holds the queued responses' headers, which are sent along with the streams' DATA by
+    /// `send_next`.
+    scheduler: Scheduler,
+    /// Whether the connection is over its memory budget and shedding load.
+    shedding: bool,
+    /// When each of the streams whose DATA is held back by the outbound flow control window got
+    /// blocked.
+    blocked_since: HashMap<StreamId, Instant>,
 }
 
 impl<F, State> ServerConnection<F, State>
@@ -153,12 +418,17 @@ impl<F, State> ServerConnection<F, State>
     /// underlying HTTP/2 communication. The `state` and `factory` represent, respectively, the
     /// initial state of the connection and an instance of the `StreamFactory` type (allowing the
     /// client to handle newly created streams).
-    pub fn with_connection(conn: HttpConnection, state: State, factory: F)
+    pub fn with_connection(mut conn: HttpConnection, state: State, factory: F)
             -> ServerConnection<F, State> {
+        conn.set_role(Role::Server);
         ServerConnection {
             conn: conn,
             state: state,
             factory: factory,
+            settings_hook: None,
+            scheduler: Scheduler::new(),
+            shedding: false,
+            blocked_since: HashMap::new(),
         }
     }
 
@@ -168,10 +438,70 @@ impl<F, State> ServerConnection<F, State>
         self.conn.scheme
     }
 
+    /// Reads the connection preface magic that the client sends when it first connects and
+    /// validates it.
+    ///
+    /// This is the first step of the server side of the preface exchange. The magic is followed
+    /// by the client's SETTINGS frame, which should be processed by `expect_settings`, once the
+    /// server has sent its own preface (see `send_preface`).
+    ///
+    /// The steps are exposed separately so that servers doing their own handshake sequencing (or
+    /// test harnesses) can interleave their own logic between them.
+    ///
+    /// # Returns
+    ///
+    /// If the client sent anything other than the expected magic, an
+    /// `HttpError::InvalidPreface` error is returned. IO errors are propagated.
+    pub fn expect_preface<R: io::Read>(&mut self, stream: &mut R) -> HttpResult<()> {
+        self.continue_preface(stream, &mut Vec::with_capacity(CLIENT_PREFACE.len()))
+    }
+
+    /// Reads the connection preface magic, same as `expect_preface`, except that the bytes of the
+    /// magic that were read are kept in the given buffer.
+    ///
+    /// When a read fails (e.g. with an `io::ErrorKind::WouldBlock` error on a non-blocking
+    /// stream), the error is returned as is, with none of the bytes read so far lost: a
+    /// following call with the same buffer picks up where the failed one left off, like a
+    /// `FrameReader` does for frames.
+    pub fn continue_preface<R: io::Read>(&mut self, stream: &mut R, received: &mut Vec<u8>)
+            -> HttpResult<()> {
+        while received.len() < CLIENT_PREFACE.len() {
+            let filled = received.len();
+            received.resize(CLIENT_PREFACE.len(), 0);
+            let res = stream.read(&mut received[filled..]);
+            let read = *res.as_ref().unwrap_or(&0);
+            received.truncate(filled + read);
+            match res {
+                Ok(0) => {
+                    return Err(HttpError::from(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                                              "Incomplete client preface")));
+                },
+                Ok(_) => {},
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(HttpError::from(e)),
+            }
+        }
+        if &received[..] != CLIENT_PREFACE {
+            return Err(HttpError::InvalidPreface);
+        }
+        debug!("Received the client preface");
+
+        Ok(())
+    }
+
+    /// Sends the server connection preface to the client, i.e. a SETTINGS frame with the
+    /// server's settings.
+    pub fn send_preface<S: SendFrame>(&mut self, sender: &mut S) -> HttpResult<()> {
+        self.send_settings(sender)
+    }
+
     /// Send the current settings associated to the `ServerConnection` to the client.
+    ///
+    /// Only the settings of the connection's `Http2Config` that differ from their defaults are
+    /// sent.
     pub fn send_settings<S: SendFrame>(&mut self, sender: &mut S) -> HttpResult<()> {
-        // TODO: `HttpConnection` should provide a better API for sending settings.
-        self.conn.sender(sender).send_settings_ack()
+        let settings = self.conn.config().settings();
+        self.conn.sender(sender).send_settings(&settings)
     }
 
     /// Handles the next frame on the given `ReceiveFrame` instance and expects it to be a
@@ -181,20 +511,173 @@ impl<F, State> ServerConnection<F, State>
             rx: &mut Recv,
             tx: &mut Sender)
             -> HttpResult<()> {
-        let mut session = ServerSession::new(&mut self.state, &mut self.factory, tx);
+        let mut session = ServerSession::new(&mut self.state, &mut self.factory, tx)
+            .with_settings_hook(self.settings_hook.as_mut())
+            .with_scheduler(&mut self.scheduler);
         self.conn.expect_settings(rx, &mut session)
     }
 
     /// Fully handles the next frame provided by the given `ReceiveFrame` instance.
     /// Handling the frame can cause the session state of the `ServerConnection` to update.
-    #[inline]
+    ///
+    /// Any control frames that handling the frame required (e.g. a PING acknowledgement) are sent
+    /// right away. A client that pushes (sends a PUSH_PROMISE) or initiates an even-numbered
+    /// stream fails the connection with a PROTOCOL_ERROR, unless the compliance is lenient.
+    ///
+    /// Before the frame is handled, the connection's memory budget (if any) is enforced: while
+    /// the connection is over it, new streams are refused and the flow control windows of the
+    /// streams are shrunk to nothing. Once the usage is over twice the budget, a GOAWAY with an
+    /// ENHANCE_YOUR_CALM error is sent and an `HttpError::MemoryBudgetExceeded` error returned.
     pub fn handle_next_frame<Recv: ReceiveFrame, Sender: SendFrame>(
             &mut self,
             rx: &mut Recv,
             tx: &mut Sender)
             -> HttpResult<()> {
-        let mut session = ServerSession::new(&mut self.state, &mut self.factory, tx);
-        self.conn.handle_next_frame(rx, &mut session)
+        let pressure = try!(self.enforce_memory_budget(tx));
+        {
+            let mut session = ServerSession::new(&mut self.state, &mut self.factory, tx)
+                .with_settings_hook(self.settings_hook.as_mut())
+                .with_scheduler(&mut self.scheduler)
+                .refusing_new_streams(pressure != MemoryPressure::Normal);
+            try!(self.conn.handle_next_frame(rx, &mut session));
+        }
+        self.conn.sender(tx).send_pending_frames()
+    }
+
+    /// Returns the memory that the connection holds on to, including the buffers of its streams
+    /// and the queued response headers.
+    pub fn memory_usage(&mut self) -> MemoryUsage {
+        let mut usage = self.conn.memory_usage();
+        usage.streams = self.state.iter().map(|(_, stream)| stream.buffered_len()).sum();
+        usage.queued += self.scheduler.queued_len();
+        usage
+    }
+
+    /// Returns when the connection was established and when it last received and sent a frame.
+    #[inline]
+    pub fn activity(&self) -> ConnectionActivity {
+        self.conn.activity()
+    }
+
+    /// Returns the ID of the underlying connection.
+    #[inline]
+    pub fn id(&self) -> ConnectionId {
+        self.conn.id()
+    }
+
+    /// Sheds load according to the current memory pressure on the connection (see the `memory`
+    /// module), which is returned.
+    fn enforce_memory_budget<S: SendFrame>(&mut self, sender: &mut S)
+            -> HttpResult<MemoryPressure> {
+        let budget = self.conn.config().memory_budget;
+        if budget.is_none() {
+            return Ok(MemoryPressure::Normal);
+        }
+        let usage = self.memory_usage();
+        let pressure = MemoryPressure::of(&usage, budget);
+        match pressure {
+            MemoryPressure::Exhausted => {
+                debug!("Memory budget exhausted: {:?}", usage);
+                let last_stream_id = self.state.last_incoming_stream_id();
+                try!(self.conn.sender(sender).send_goaway(last_stream_id,
+                                                          ErrorCode::EnhanceYourCalm));
+                return Err(HttpError::MemoryBudgetExceeded);
+            },
+            MemoryPressure::Shedding if !self.shedding => {
+                debug!("Over the memory budget, shedding load: {:?}", usage);
+                // A zero initial window size applies to the open streams as well, so the client
+                // cannot send any more DATA on them, while the connection window is no longer
+                // replenished either.
+                self.shedding = true;
+                self.conn.freeze_in_window();
+                try!(self.conn.sender(sender).send_settings(&[HttpSetting::InitialWindowSize(0)]));
+            },
+            MemoryPressure::Normal if self.shedding => {
+                debug!("Back within the memory budget");
+                self.shedding = false;
+                try!(self.conn.thaw_in_window());
+                let size = self.conn.config().initial_window_size;
+                let settings = [HttpSetting::InitialWindowSize(size)];
+                try!(self.conn.sender(sender).send_settings(&settings));
+            },
+            _ => {},
+        }
+        Ok(pressure)
+    }
+
+    /// Sets a callback that is invoked whenever the client sends a SETTINGS frame, once the new
+    /// settings are applied and acknowledged. This includes the SETTINGS frame of the client's
+    /// preface, as well as any later updates, e.g. when the client disables push or shrinks its
+    /// flow control windows mid-connection.
+    pub fn set_settings_hook<H>(&mut self, hook: H)
+            where H: FnMut(&[HttpSetting], &SettingsState) + Send + 'static {
+        self.settings_hook = Some(Box::new(hook));
+    }
+
+    /// Returns the client's settings that are currently in effect.
+    #[inline]
+    pub fn peer_settings(&self) -> &SettingsState {
+        self.conn.peer_settings()
+    }
+
+    /// Returns the configuration of the connection, whose settings the server advertises.
+    #[inline]
+    pub fn config(&self) -> &Http2Config {
+        self.conn.config()
+    }
+
+    /// Sets the strategy that the connection uses for managing its inbound flow control windows.
+    #[inline]
+    pub fn set_flow_control(&mut self, strategy: FlowControlStrategy) {
+        self.conn.set_flow_control(strategy)
+    }
+
+    /// Caps the bandwidth that the connection uses for sending DATA, connection-wide and/or per
+    /// stream. While the limit is exhausted, `send_next_data` returns `SendStatus::Throttled`.
+    #[inline]
+    pub fn set_rate_limit(&mut self, connection: Option<RateLimit>, per_stream: Option<RateLimit>) {
+        self.conn.set_rate_limit(connection, per_stream)
+    }
+
+    /// Pads the DATA frames sent on the given stream using the given strategy (or stops padding
+    /// them). See `HttpConnection::set_stream_padding`.
+    #[inline]
+    pub fn set_stream_padding(&mut self, stream_id: StreamId, strategy: Option<PaddingStrategy>) {
+        self.conn.set_stream_padding(stream_id, strategy)
+    }
+
+    /// Sends a PING frame with the given opaque data to the client, e.g. to check that it is
+    /// still responsive.
+    #[inline]
+    pub fn send_ping<S: SendFrame>(&mut self, data: u64, sender: &mut S) -> HttpResult<()> {
+        self.conn.sender(sender).send_ping(data)
+    }
+
+    /// Sends a GOAWAY frame with the given error code to the client, signaling that no streams
+    /// after `last_stream_id` will be processed.
+    #[inline]
+    pub fn send_goaway<S: SendFrame>(
+            &mut self,
+            last_stream_id: StreamId,
+            error_code: ErrorCode,
+            sender: &mut S)
+            -> HttpResult<()> {
+        self.conn.sender(sender).send_goaway(last_stream_id, error_code)
+    }
+
+    /// Sends a WINDOW_UPDATE frame to the client, increasing the inbound flow control window of
+    /// the given stream (or of the connection, for stream 0) by the given increment.
+    ///
+    /// An invalid increment (0, or one exceeding the maximum window size of 2^31 - 1) results in
+    /// an `HttpError::InvalidWindowIncrement`, without sending anything.
+    #[inline]
+    pub fn send_window_update<S: SendFrame>(
+            &mut self,
+            stream_id: StreamId,
+            increment: u32,
+            sender: &mut S)
+            -> HttpResult<()> {
+        self.conn.sender(sender).send_window_update(stream_id, increment)
     }
 
     /// Starts a response on the stream with the given ID by sending the given headers.
@@ -202,6 +685,10 @@ impl<F, State> ServerConnection<F, State>
     /// The body of the response is assumed to be provided by the `Stream` instance stored within
     /// the connection's state. (The body does not have to be ready when this method is called, as
     /// long as the `Stream` instance knows how to provide it to the connection later on.)
+    ///
+    /// Informational (1xx) headers can be sent any number of times before the final headers of
+    /// the response. Headers that are out of sequence (e.g. a second final header block) result
+    /// in an `HttpError::InvalidHeaderPhase`, without sending anything.
     #[inline]
     pub fn start_response<'n, 'v, S: SendFrame>(
             &mut self,
@@ -210,24 +697,179 @@ impl<F, State> ServerConnection<F, State>
             end_stream: EndStream,
             sender: &mut S)
             -> HttpResult<()> {
+        try!(self.advance_response_start(stream_id, &headers, end_stream));
         self.conn.sender(sender).send_headers(
             headers,
             stream_id,
             end_stream)
     }
 
+    /// Queues the headers of a response on the stream with the given ID, to be sent by
+    /// `send_next` once it is the stream's turn.
+    ///
+    /// Unlike with `start_response`, the headers are weighed against the DATA of all other
+    /// streams, so that a burst of responses does not hold back the body of a more urgent one.
+    /// The headers are checked against the response's header phase in the same way, though.
+    #[inline]
+    pub fn queue_response(
+            &mut self,
+            headers: Vec<Header<'static, 'static>>,
+            stream_id: StreamId,
+            end_stream: EndStream)
+            -> HttpResult<()> {
+        try!(self.advance_response_start(stream_id, &headers, end_stream));
+        self.scheduler.queue_headers(headers, stream_id, end_stream);
+        Ok(())
+    }
+
+    /// Queues the trailers of the response on the stream with the given ID. They are sent by
+    /// `send_next` once all of the stream's DATA is, ending the stream.
+    ///
+    /// The response needs to have been started (with its final headers) and not yet ended;
+    /// otherwise an `HttpError::InvalidHeaderPhase` is returned.
+    pub fn queue_trailers(&mut self, trailers: Vec<Header<'static, 'static>>, stream_id: StreamId)
+            -> HttpResult<()> {
+        let events = [HeaderEvent::Trailers, HeaderEvent::EndStream];
+        try!(self.advance_response_phase(stream_id, &events));
+        self.scheduler.queue_trailers(trailers, stream_id);
+        Ok(())
+    }
+
+    /// Moves the response on the given stream through the header phases that follow the given
+    /// events, e.g. to account for DATA that is about to be sent on the stream.
+    ///
+    /// If any of the events is out of sequence, an `HttpError::InvalidHeaderPhase` is returned
+    /// and the phase is left unchanged. Streams that the session state does not track are not
+    /// checked.
+    pub fn advance_response_phase(&mut self, stream_id: StreamId, events: &[HeaderEvent])
+            -> HttpResult<()> {
+        let mut next = match self.response_phase(stream_id) {
+            Some(phase) => phase,
+            None => return Ok(()),
+        };
+        for &event in events {
+            next = try!(next.transition(event).map_err(HttpError::InvalidHeaderPhase));
+        }
+        if let Some(phase) = self.state.sent_header_phase(stream_id) {
+            *phase = next;
+        }
+        Ok(())
+    }
+
+    /// Returns the header phase of the response on the given stream, if the stream is tracked.
+    fn response_phase(&mut self, stream_id: StreamId) -> Option<HeaderPhase> {
+        // A stream that was already closed on our end has sent everything it ever will, whatever
+        // the tracked phase.
+        let closed = self.state.get_stream_ref(stream_id).map_or(false, |s| s.is_closed_local());
+        self.state.sent_header_phase(stream_id)
+                  .map(|&mut phase| if closed { HeaderPhase::Done } else { phase })
+    }
+
+    /// Advances the response on the given stream past the given headers that start it (or that
+    /// are informational). Such headers are never taken for trailers, which need to be sent
+    /// explicitly (see `queue_trailers`).
+    fn advance_response_start(&mut self, stream_id: StreamId, headers: &[Header],
+                              end_stream: EndStream)
+            -> HttpResult<()> {
+        let events = response_events(headers, end_stream);
+        match self.response_phase(stream_id) {
+            Some(phase) if phase != HeaderPhase::Initial => {
+                Err(HttpError::InvalidHeaderPhase(HeaderPhaseError {
+                    phase: phase,
+                    event: events[0],
+                }))
+            },
+            _ => self.advance_response_phase(stream_id, &events),
+        }
+    }
+
+    /// Sets the weight of the given stream, which determines how soon `send_next` serves it
+    /// relative to the other streams.
+    #[inline]
+    pub fn set_stream_weight(&mut self, stream_id: StreamId, weight: u8) {
+        self.scheduler.set_weight(stream_id, weight)
+    }
+
+    /// Sends the next frame that is due: either the headers of a response queued by
+    /// `queue_response` or a DATA frame of one of the streams, whichever the stream weights
+    /// favor. DATA is only sent within the rate limit and the outbound flow control windows of
+    /// both the connection and the stream.
+    ///
+    /// If the `Http2Config` sets a `slow_consumer_timeout`, the streams whose DATA stays held back
+    /// by the flow control window for longer than it are reset with a CANCEL error, after being
+    /// notified by `Stream::on_slow_consumer`. If the config also asks for `close_slow_consumers`,
+    /// a GOAWAY with an ENHANCE_YOUR_CALM error is sent as well, and an
+    /// `HttpError::SlowConsumer` error returned.
+    pub fn send_next<S: SendFrame>(&mut self, sender: &mut S) -> HttpResult<SendStatus> {
+        let mut buf = vec![0; self.conn.max_data_chunk_size()];
+        let status = try!(self.scheduler.send_next(&mut self.state, &mut self.conn, sender,
+                                                   &mut buf));
+        try!(self.reset_slow_consumers(sender));
+        Ok(status)
+    }
+
+    /// Resets the streams that were blocked on flow control for longer than the configured
+    /// timeout (and closes the connection, if configured to do so).
+    fn reset_slow_consumers<S: SendFrame>(&mut self, sender: &mut S) -> HttpResult<()> {
+        let timeout = match self.conn.config().slow_consumer_timeout {
+            Some(timeout) => timeout,
+            None => return Ok(()),
+        };
+        let now = Instant::now();
+        let blocked = self.scheduler.flow_blocked_streams();
+        self.blocked_since.retain(|stream_id, _| blocked.contains(stream_id));
+        for &stream_id in blocked {
+            self.blocked_since.entry(stream_id).or_insert(now);
+        }
+
+        let mut slow: Vec<_> = self.blocked_since.iter()
+                                                 .map(|(&id, &since)| (id, now - since))
+                                                 .filter(|&(_, blocked_for)| blocked_for >= timeout)
+                                                 .collect();
+        if slow.is_empty() {
+            return Ok(());
+        }
+        slow.sort();
+        for (stream_id, blocked_for) in slow {
+            debug!("Stream {} blocked on flow control for {:?}, resetting it",
+                   stream_id, blocked_for);
+            self.blocked_since.remove(&stream_id);
+            if let Some(stream) = self.state.get_stream_mut(stream_id) {
+                stream.on_slow_consumer(blocked_for);
+                stream.close();
+            }
+            try!(self.conn.sender(sender).send_rst_stream(stream_id, ErrorCode::Cancel));
+        }
+        if self.conn.config().close_slow_consumers {
+            let last_stream_id = self.state.last_incoming_stream_id();
+            try!(self.conn.sender(sender).send_goaway(last_stream_id,
+                                                      ErrorCode::EnhanceYourCalm));
+            return Err(HttpError::SlowConsumer);
+        }
+        Ok(())
+    }
+
     /// Queues a new DATA frame onto the underlying `SendFrame`.
     ///
     /// Currently, no prioritization of streams is taken into account and which stream's data is
-    /// queued cannot be relied on.
+    /// queued cannot be relied on. Streams whose response headers are still queued (see
+    /// `queue_response`) are skipped.
     pub fn send_next_data<S: SendFrame>(&mut self, sender: &mut S) -> HttpResult<SendStatus> {
         debug!("Sending next data...");
-        // A default "maximum" chunk size of 8 KiB is set on all data frames.
-        const MAX_CHUNK_SIZE: usize = 8 * 1024;
-        let mut buf = [0; MAX_CHUNK_SIZE];
+        // The chunks are as large as the client's maximum frame size and the connection's
+        // outbound flow control window allow.
+        let mut buf = vec![0; self.conn.next_chunk_size()];
+        let mut throttled = self.conn.throttled_streams();
+        throttled.extend(self.scheduler.streams_with_queued_headers());
+        // The chunks of each stream are also limited by the stream's own flow control window.
+        let conn = &self.conn;
+        let limits = self.state.iter().filter_map(|(&id, _)| {
+            conn.stream_out_window_size(id).map(|size| (id, cmp::max(size, 0) as usize))
+        }).collect();
 
-        // TODO: Additionally account for the flow control windows.
         let mut prioritizer = SimplePrioritizer::new(&mut self.state, &mut buf);
+        prioritizer.skip_streams(throttled);
+        prioritizer.limit_streams(limits);
 
         self.conn.sender(sender).send_next_data(&mut prioritizer)
     }
@@ -235,18 +877,42 @@ impl<F, State> ServerConnection<F, State>
 
 #[cfg(test)]
 mod tests {
-    use super::ServerSession;
+    use super::{ServerSession, ServerConnection};
+
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
 
     use http::tests::common::{TestStream, TestStreamFactory, build_mock_http_conn, MockSendFrame};
+    use http::tests::common::{MockReceiveFrame, TestSession};
 
-    use http::{Header, ErrorCode, HttpError};
+    use http::{Header, ErrorCode, HttpError, HttpScheme};
+    use http::config::{Http2Config, Compliance};
+    use http::connection::{HttpFrame, HttpConnection, EndStream, SendStatus};
+    use http::frame::{
+        Frame,
+        RawFrame,
+        DataFrame,
+        HeadersFrame,
+        pack_header,
+        HeadersFlag,
+        SettingsFrame,
+        HttpSetting,
+        StreamDependency,
+        WindowUpdateFrame,
+    };
+    use http::scheduler::{Scheduler, DEFAULT_WEIGHT};
     use http::session::{
         DefaultSessionState,
         SessionState,
         Stream,
+        StreamState,
         Session,
+        HeaderPhase,
+        HeaderEvent,
+        HeaderPhaseError,
     };
     use http::session::Server as ServerMarker;
+    use http::validation::MalformedHeaders;
 
     /// Tests that the `ServerSession` correctly manages the stream state.
     #[test]
@@ -306,6 +972,259 @@ mod tests {
         assert!(!state.get_stream_ref(3).unwrap().is_closed_remote());
     }
 
+    /// Tests that the `ServerSession` resets a stream whose request contains a duplicated
+    /// pseudo-header, without ever exposing the request headers to the stream.
+    #[test]
+    fn test_server_session_duplicate_pseudo_header() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        let headers = vec![
+            Header::new(b":method".to_vec(), b"GET".to_vec()),
+            Header::new(b":scheme".to_vec(), b"http".to_vec()),
+            Header::new(b":path".to_vec(), b"/".to_vec()),
+            Header::new(b":path".to_vec(), b"/admin".to_vec()),
+        ];
+        {
+            let mut factory = TestStreamFactory;
+            let mut session = ServerSession::new(&mut state, &mut factory, &mut sender);
+            session.new_headers(1, headers, &mut conn).unwrap();
+        }
+        {
+            let stream = state.get_stream_ref(1).unwrap();
+            assert!(stream.headers.is_none());
+            assert!(stream.is_closed());
+            assert_eq!(stream.malformed,
+                       vec![MalformedHeaders::DuplicatePseudoHeader(b":path".to_vec())]);
+        }
+        assert_eq!(sender.sent.len(), 1);
+        match HttpFrame::from_raw(&sender.sent[0]).unwrap() {
+            HttpFrame::RstStreamFrame(frame) => {
+                assert_eq!(frame.get_stream_id(), 1);
+                assert_eq!(frame.error_code(), ErrorCode::ProtocolError);
+            },
+            _ => panic!("Expected a RST_STREAM frame"),
+        };
+    }
+
+    /// Tests that the `ServerSession` accepts malformed requests as they are when the connection
+    /// is configured for lenient compliance.
+    #[test]
+    fn test_server_session_lenient_compliance() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        let config = Http2Config::new().compliance(Compliance::Lenient);
+        let mut conn = HttpConnection::with_config(HttpScheme::Http, config);
+        let mut sender = MockSendFrame::new();
+        let headers = vec![
+            Header::new(b":method".to_vec(), b"GET".to_vec()),
+            Header::new(b":path".to_vec(), b"/".to_vec()),
+            Header::new(b":path".to_vec(), b"/admin".to_vec()),
+        ];
+        {
+            let mut factory = TestStreamFactory;
+            let mut session = ServerSession::new(&mut state, &mut factory, &mut sender);
+            session.new_headers(1, headers.clone(), &mut conn).unwrap();
+        }
+        let stream = state.get_stream_ref(1).unwrap();
+        assert_eq!(stream.headers.clone().unwrap(), headers);
+        assert!(stream.malformed.is_empty());
+        assert_eq!(sender.sent.len(), 0);
+    }
+
+    /// Tests that the `ServerSession` refuses streams beyond the configured maximum number of
+    /// concurrent streams.
+    #[test]
+    fn test_server_session_max_concurrent_streams() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        let config = Http2Config::new().max_concurrent_streams(Some(1));
+        let mut conn = HttpConnection::with_config(HttpScheme::Http, config);
+        let mut sender = MockSendFrame::new();
+        let headers = vec![
+            Header::new(b":method".to_vec(), b"GET".to_vec()),
+            Header::new(b":scheme".to_vec(), b"http".to_vec()),
+            Header::new(b":path".to_vec(), b"/".to_vec()),
+        ];
+        {
+            let mut factory = TestStreamFactory;
+            let mut session = ServerSession::new(&mut state, &mut factory, &mut sender);
+            session.new_headers(1, headers.clone(), &mut conn).unwrap();
+            session.new_headers(3, headers.clone(), &mut conn).unwrap();
+        }
+        assert!(!state.get_stream_ref(1).unwrap().is_closed());
+        assert!(state.get_stream_ref(3).unwrap().is_closed());
+        assert_eq!(sender.sent.len(), 1);
+        match HttpFrame::from_raw(&sender.sent[0]).unwrap() {
+            HttpFrame::RstStreamFrame(frame) => {
+                assert_eq!(frame.get_stream_id(), 3);
+                assert_eq!(frame.error_code(), ErrorCode::RefusedStream);
+            },
+            _ => panic!("Expected a RST_STREAM frame"),
+        };
+
+        // Once the first stream is closed, new streams are accepted again.
+        state.get_stream_mut(1).unwrap().close();
+        {
+            let mut factory = TestStreamFactory;
+            let mut session = ServerSession::new(&mut state, &mut factory, &mut sender);
+            session.new_headers(5, headers, &mut conn).unwrap();
+        }
+        assert!(!state.get_stream_ref(5).unwrap().is_closed());
+        assert_eq!(sender.sent.len(), 1);
+    }
+
+    /// Tests that the `ServerSession` rejects requests with more header fields, or a longer
+    /// `:path`, than the config allows, responding with a 431 or a 414 and resetting the stream.
+    #[test]
+    fn test_server_session_request_limits() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        let config = Http2Config::new().max_header_count(Some(4)).max_path_length(Some(8));
+        let mut conn = HttpConnection::with_config(HttpScheme::Http, config);
+        let mut sender = MockSendFrame::new();
+        let request = |path: &[u8], extra: usize| {
+            let mut headers = vec![
+                Header::new(b":method".to_vec(), b"GET".to_vec()),
+                Header::new(b":scheme".to_vec(), b"http".to_vec()),
+                Header::new(b":path".to_vec(), path.to_vec()),
+            ];
+            for i in 0..extra {
+                headers.push(Header::new(format!("x-{}", i).into_bytes(), b"1".to_vec()));
+            }
+            headers
+        };
+        {
+            let mut factory = TestStreamFactory;
+            let mut session = ServerSession::new(&mut state, &mut factory, &mut sender);
+            session.new_headers(1, request(b"/ok", 1), &mut conn).unwrap();
+            session.new_headers(3, request(b"/ok", 2), &mut conn).unwrap();
+            session.new_headers(5, request(b"/too-long", 0), &mut conn).unwrap();
+        }
+
+        assert!(state.get_stream_ref(1).unwrap().headers.is_some());
+        for &stream_id in &[3, 5] {
+            let stream = state.get_stream_ref(stream_id).unwrap();
+            assert!(stream.headers.is_none());
+            assert!(stream.is_closed());
+        }
+        assert_eq!(sender.sent.len(), 4);
+        let mut decoder = hpack::Decoder::new();
+        for (i, &(stream_id, status)) in [(3, b"431"), (5, b"414")].iter().enumerate() {
+            match HttpFrame::from_raw(&sender.sent[2 * i]).unwrap() {
+                HttpFrame::HeadersFrame(frame) => {
+                    assert_eq!(frame.get_stream_id(), stream_id);
+                    assert!(frame.is_end_of_stream());
+                    let headers = decoder.decode(frame.header_fragment()).unwrap();
+                    assert_eq!(headers, vec![(b":status".to_vec(), status.to_vec())]);
+                },
+                _ => panic!("Expected a HEADERS frame"),
+            };
+            match HttpFrame::from_raw(&sender.sent[2 * i + 1]).unwrap() {
+                HttpFrame::RstStreamFrame(frame) => {
+                    assert_eq!(frame.get_stream_id(), stream_id);
+                    assert_eq!(frame.error_code(), ErrorCode::NoError);
+                },
+                _ => panic!("Expected a RST_STREAM frame"),
+            };
+        }
+    }
+
+    /// Tests that the `ServerSession` resets a stream whose request carries a `te` header with
+    /// something other than `trailers`.
+    #[test]
+    fn test_server_session_invalid_te() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        let headers = vec![
+            Header::new(b":method".to_vec(), b"GET".to_vec()),
+            Header::new(b":scheme".to_vec(), b"http".to_vec()),
+            Header::new(b":path".to_vec(), b"/".to_vec()),
+            Header::new(b"te".to_vec(), b"chunked".to_vec()),
+        ];
+        {
+            let mut factory = TestStreamFactory;
+            let mut session = ServerSession::new(&mut state, &mut factory, &mut sender);
+            session.new_headers(1, headers, &mut conn).unwrap();
+        }
+        assert_eq!(state.get_stream_ref(1).unwrap().malformed,
+                   vec![MalformedHeaders::InvalidTe]);
+        assert_eq!(sender.sent.len(), 1);
+    }
+
+    /// Tests that the `ServerSession` resets a stream whose request body is shorter than the
+    /// declared `content-length`.
+    #[test]
+    fn test_server_session_body_length_mismatch() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        let headers = vec![
+            Header::new(b":method".to_vec(), b"POST".to_vec()),
+            Header::new(b":scheme".to_vec(), b"http".to_vec()),
+            Header::new(b":path".to_vec(), b"/".to_vec()),
+            Header::new(b"content-length".to_vec(), b"10".to_vec()),
+        ];
+        {
+            let mut factory = TestStreamFactory;
+            let mut session = ServerSession::new(&mut state, &mut factory, &mut sender);
+            session.new_headers(1, headers, &mut conn).unwrap();
+            session.new_data_chunk(1, &[1, 2, 3], &mut conn).unwrap();
+            session.end_of_stream(1, &mut conn).unwrap();
+        }
+        {
+            let stream = state.get_stream_ref(1).unwrap();
+            assert!(stream.is_closed());
+            assert_eq!(stream.length_mismatch.map(|l| (l.declared, l.received)), Some((10, 3)));
+        }
+        assert_eq!(sender.sent.len(), 1);
+        match HttpFrame::from_raw(&sender.sent[0]).unwrap() {
+            HttpFrame::RstStreamFrame(frame) => {
+                assert_eq!(frame.get_stream_id(), 1);
+                assert_eq!(frame.error_code(), ErrorCode::ProtocolError);
+            },
+            _ => panic!("Expected a RST_STREAM frame"),
+        };
+    }
+
+    /// Tests that the `ServerSession` resets a stream that receives a second header block that
+    /// cannot be trailers, or a header block after the end of the stream.
+    #[test]
+    fn test_server_session_header_phase_violation() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        let headers = vec![
+            Header::new(b":method".to_vec(), b"POST".to_vec()),
+            Header::new(b":scheme".to_vec(), b"http".to_vec()),
+            Header::new(b":path".to_vec(), b"/".to_vec()),
+        ];
+        let trailers = vec![Header::new(b"x-checksum".to_vec(), b"1".to_vec())];
+        {
+            let mut factory = TestStreamFactory;
+            let mut session = ServerSession::new(&mut state, &mut factory, &mut sender);
+            // Trailers without the END_STREAM flag, followed by yet another header block.
+            session.new_headers(1, headers.clone(), &mut conn).unwrap();
+            session.new_data_chunk(1, &[1, 2, 3], &mut conn).unwrap();
+            session.new_headers(1, trailers.clone(), &mut conn).unwrap();
+            session.new_headers(1, trailers.clone(), &mut conn).unwrap();
+            // A complete request, followed by a header block after the end of the stream.
+            session.new_headers(3, headers.clone(), &mut conn).unwrap();
+            session.end_of_stream(3, &mut conn).unwrap();
+            session.new_headers(3, trailers.clone(), &mut conn).unwrap();
+        }
+        assert!(state.get_stream_ref(1).unwrap().is_closed());
+        assert!(state.get_stream_ref(3).unwrap().is_closed());
+        assert_eq!(sender.sent.len(), 2);
+        for (raw, &id) in sender.sent.iter().zip([1, 3].iter()) {
+            match HttpFrame::from_raw(raw).unwrap() {
+                HttpFrame::RstStreamFrame(frame) => {
+                    assert_eq!(frame.get_stream_id(), id);
+                    assert_eq!(frame.error_code(), ErrorCode::ProtocolError);
+                },
+                _ => panic!("Expected a RST_STREAM frame"),
+            };
+        }
+    }
+
     #[test]
     fn test_server_session_rst_stream() {
         let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
@@ -326,6 +1245,53 @@ mod tests {
         assert!(state.get_stream_ref(5).map(|stream| stream.errors.len() == 0).unwrap());
     }
 
+    /// Tests that the `ServerSession` closes the connection with a GOAWAY frame when the peer
+    /// causes a connection error.
+    #[test]
+    fn test_server_session_connection_error() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        state.insert_incoming(3, TestStream::new()).unwrap();
+        {
+            let mut factory = TestStreamFactory;
+            let mut session = ServerSession::new(&mut state, &mut factory, &mut sender);
+            session.connection_error(ErrorCode::CompressionError, &mut conn).unwrap();
+        }
+
+        assert_eq!(sender.sent.len(), 1);
+        let frame = match HttpFrame::from_raw(&sender.sent[0]).unwrap() {
+            HttpFrame::GoawayFrame(frame) => frame,
+            _ => panic!("Expected a GOAWAY frame"),
+        };
+        assert_eq!(frame.last_stream_id(), 3);
+        assert_eq!(frame.error_code(), ErrorCode::CompressionError);
+    }
+
+    /// Tests that the `ServerSession` resets a stream on which the peer caused a stream error.
+    #[test]
+    fn test_server_session_stream_error() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        state.insert_incoming(1, TestStream::new()).unwrap();
+        {
+            let mut factory = TestStreamFactory;
+            let mut session = ServerSession::new(&mut state, &mut factory, &mut sender);
+            assert_eq!(session.stream_state(1), Some(StreamState::Open));
+            session.stream_error(1, ErrorCode::StreamClosed, &mut conn).unwrap();
+        }
+
+        assert!(state.get_stream_ref(1).unwrap().is_closed());
+        assert_eq!(sender.sent.len(), 1);
+        let frame = match HttpFrame::from_raw(&sender.sent[0]).unwrap() {
+            HttpFrame::RstStreamFrame(frame) => frame,
+            _ => panic!("Expected a RST_STREAM frame"),
+        };
+        assert_eq!(frame.get_stream_id(), 1);
+        assert_eq!(frame.error_code(), ErrorCode::StreamClosed);
+    }
+
     /// When the server session is told to goaway by the client, we signal an error to the client
     /// code, surfacing the underlying reason.
     #[test]
@@ -345,4 +1311,450 @@ mod tests {
             panic!("Expected a PeerConnectionError");
         }
     }
+
+    /// Tests that the `ServerConnection` validates the client's preface magic.
+    #[test]
+    fn test_server_conn_expect_preface() {
+        let mut conn = ServerConnection::with_connection(
+            build_mock_http_conn(),
+            DefaultSessionState::<ServerMarker, TestStream>::new(),
+            TestStreamFactory);
+        {
+            let mut stream: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+            assert!(conn.expect_preface(&mut stream).is_ok());
+        }
+        {
+            let mut stream: &[u8] = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+            assert_eq!(conn.expect_preface(&mut stream).err().unwrap(),
+                       HttpError::InvalidPreface);
+        }
+        {
+            let mut stream: &[u8] = b"PRI * HTTP/2.0";
+            assert!(match conn.expect_preface(&mut stream) {
+                Err(HttpError::IoError(_)) => true,
+                _ => false,
+            });
+        }
+    }
+
+    /// Tests that the server preface is a (non-ACK) SETTINGS frame.
+    #[test]
+    fn test_server_conn_send_preface() {
+        let mut conn = ServerConnection::with_connection(
+            build_mock_http_conn(),
+            DefaultSessionState::<ServerMarker, TestStream>::new(),
+            TestStreamFactory);
+        let mut sender = MockSendFrame::new();
+
+        conn.send_preface(&mut sender).unwrap();
+
+        assert_eq!(sender.sent.len(), 1);
+        match HttpFrame::from_raw(&sender.sent[0]).unwrap() {
+            HttpFrame::SettingsFrame(frame) => assert!(!frame.is_ack()),
+            _ => panic!("Expected a SETTINGS frame"),
+        }
+    }
+
+    /// Tests that the weights that the client gives its streams are passed on to the scheduler.
+    #[test]
+    fn test_server_session_priority() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        let mut conn = build_mock_http_conn();
+        let mut sender = MockSendFrame::new();
+        let mut scheduler = Scheduler::new();
+        {
+            let mut factory = TestStreamFactory;
+            let mut session = ServerSession::new(&mut state, &mut factory, &mut sender)
+                .with_scheduler(&mut scheduler);
+            session.new_priority(1, StreamDependency::new(0, 255, false), &mut conn).unwrap();
+        }
+
+        assert_eq!(scheduler.weight(1), 255);
+        assert_eq!(scheduler.weight(3), DEFAULT_WEIGHT);
+    }
+
+    /// Tests that queued response headers are sent by `send_next` ahead of the stream's DATA,
+    /// which `send_next_data` does not send until then.
+    #[test]
+    fn test_server_conn_queue_response() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        let mut stream = TestStream::new();
+        stream.set_outgoing(vec![1, 2, 3]);
+        state.insert_incoming(1, stream).unwrap();
+        let mut conn = ServerConnection::with_connection(build_mock_http_conn(),
+                                                         state,
+                                                         TestStreamFactory);
+        let mut sender = MockSendFrame::new();
+        conn.queue_response(vec![Header::new(b":status", b"200")], 1, EndStream::No).unwrap();
+
+        assert_eq!(conn.send_next_data(&mut sender).unwrap(), SendStatus::Nothing);
+        assert_eq!(conn.send_next(&mut sender).unwrap(), SendStatus::Sent);
+        assert_eq!(conn.send_next(&mut sender).unwrap(), SendStatus::Sent);
+        assert_eq!(conn.send_next(&mut sender).unwrap(), SendStatus::Nothing);
+
+        assert_eq!(sender.sent.len(), 2);
+        match HttpFrame::from_raw(&sender.sent[0]).unwrap() {
+            HttpFrame::HeadersFrame(frame) => assert!(!frame.is_end_of_stream()),
+            _ => panic!("Expected a HEADERS frame"),
+        }
+        match HttpFrame::from_raw(&sender.sent[1]).unwrap() {
+            HttpFrame::DataFrame(frame) => assert_eq!(frame.data, vec![1, 2, 3]),
+            _ => panic!("Expected a DATA frame"),
+        }
+    }
+
+    /// Tests that the DATA frames are as large as the client's maximum frame size allows, growing
+    /// once the client raises it.
+    #[test]
+    fn test_server_conn_data_chunk_size() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        let mut stream = TestStream::new();
+        stream.set_outgoing(vec![0; 50_000]);
+        state.insert_incoming(1, stream).unwrap();
+        let mut conn = ServerConnection::with_connection(build_mock_http_conn(),
+                                                         state,
+                                                         TestStreamFactory);
+        let mut sender = MockSendFrame::new();
+        let data_len = |raw: &RawFrame| {
+            match HttpFrame::from_raw(raw).unwrap() {
+                HttpFrame::DataFrame(frame) => frame.data.len(),
+                _ => panic!("Expected a DATA frame"),
+            }
+        };
+
+        assert_eq!(conn.send_next_data(&mut sender).unwrap(), SendStatus::Sent);
+        assert_eq!(data_len(&sender.sent[0]), 16_384);
+
+        let mut settings = SettingsFrame::new();
+        settings.add_setting(HttpSetting::MaxFrameSize(32_768));
+        let mut rx = MockReceiveFrame::new(vec![HttpFrame::SettingsFrame(settings)]);
+        conn.handle_next_frame(&mut rx, &mut sender).unwrap();
+        assert_eq!(conn.send_next_data(&mut sender).unwrap(), SendStatus::Sent);
+        // The SETTINGS ACK is sent in between.
+        assert_eq!(sender.sent.len(), 3);
+        assert_eq!(data_len(&sender.sent[2]), 32_768);
+    }
+
+    /// Tests that the headers and trailers of a response are checked against its header phase
+    /// before they are queued.
+    #[test]
+    fn test_server_conn_response_phases() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        let mut stream = TestStream::new();
+        stream.set_outgoing(vec![1, 2, 3]);
+        state.insert_incoming(1, stream).unwrap();
+        state.insert_incoming(3, TestStream::new()).unwrap();
+        let mut conn = ServerConnection::with_connection(build_mock_http_conn(),
+                                                         state,
+                                                         TestStreamFactory);
+        let mut sender = MockSendFrame::new();
+        let trailers = vec![Header::new(b"grpc-status".to_vec(), b"0".to_vec())];
+
+        // Trailers cannot come before the response has started...
+        let res = conn.queue_trailers(trailers.clone(), 1);
+        assert_eq!(res.err().unwrap(), HttpError::InvalidHeaderPhase(HeaderPhaseError {
+            phase: HeaderPhase::Initial,
+            event: HeaderEvent::Trailers,
+        }));
+        // ...but any number of informational responses can.
+        conn.queue_response(vec![Header::new(b":status", b"100")], 1, EndStream::No).unwrap();
+        conn.queue_response(vec![Header::new(b":status", b"200")], 1, EndStream::No).unwrap();
+        // The final headers can only be sent once.
+        let res = conn.queue_response(vec![Header::new(b":status", b"200")], 1, EndStream::No);
+        assert!(res.is_err());
+        conn.queue_trailers(trailers.clone(), 1).unwrap();
+        assert_eq!(conn.state.sent_header_phase(1).cloned(), Some(HeaderPhase::Done));
+        assert!(conn.queue_trailers(trailers.clone(), 1).is_err());
+        // An informational response cannot end the stream.
+        let res = conn.start_response(vec![Header::new(b":status", b"103")], 3, EndStream::Yes,
+                                      &mut sender);
+        assert!(res.is_err());
+        assert!(sender.sent.is_empty());
+
+        while let SendStatus::Sent = conn.send_next(&mut sender).unwrap() {}
+        assert_eq!(sender.sent.len(), 4);
+        match HttpFrame::from_raw(&sender.sent[3]).unwrap() {
+            HttpFrame::HeadersFrame(frame) => assert!(frame.is_end_of_stream()),
+            _ => panic!("Expected a HEADERS frame"),
+        }
+    }
+
+    /// Tests that invalid WINDOW_UPDATE frames make the server reset the stream they are on, or,
+    /// on stream 0, close the connection with a GOAWAY.
+    #[test]
+    fn test_server_conn_invalid_window_update() {
+        // The request is not validated, so that its headers do not reset the stream already.
+        let config = Http2Config::new().compliance(Compliance::Lenient);
+        let mut conn = ServerConnection::with_connection(
+            HttpConnection::with_config(HttpScheme::Http, config),
+            DefaultSessionState::<ServerMarker, TestStream>::new(),
+            TestStreamFactory);
+        let mut sender = MockSendFrame::new();
+        let mut headers = HeadersFrame::new(vec![0x82], 1);
+        headers.set_flag(HeadersFlag::EndHeaders);
+        let mut rx = MockReceiveFrame::new(vec![
+            HttpFrame::HeadersFrame(headers),
+            HttpFrame::WindowUpdateFrame(WindowUpdateFrame::for_stream(1, 0)),
+            HttpFrame::WindowUpdateFrame(WindowUpdateFrame::for_connection(0x7fffffff)),
+        ]);
+
+        conn.handle_next_frame(&mut rx, &mut sender).unwrap();
+        // A zero increment is a stream error...
+        conn.handle_next_frame(&mut rx, &mut sender).unwrap();
+        assert_eq!(sender.sent.len(), 1);
+        match HttpFrame::from_raw(&sender.sent[0]).unwrap() {
+            HttpFrame::RstStreamFrame(frame) => {
+                assert_eq!((frame.get_stream_id(), frame.error_code()),
+                           (1, ErrorCode::ProtocolError));
+            },
+            _ => panic!("Expected a RST_STREAM frame"),
+        }
+        // ...while overflowing the connection window is a connection error.
+        assert!(conn.handle_next_frame(&mut rx, &mut sender).is_err());
+        assert_eq!(sender.sent.len(), 2);
+        match HttpFrame::from_raw(&sender.sent[1]).unwrap() {
+            HttpFrame::GoawayFrame(frame) => {
+                assert_eq!(frame.error_code(), ErrorCode::FlowControlError);
+            },
+            _ => panic!("Expected a GOAWAY frame"),
+        }
+    }
+
+    /// Tests that the settings hook is notified of the client's SETTINGS frames, after they are
+    /// applied and acknowledged.
+    #[test]
+    fn test_server_conn_settings_hook() {
+        let mut conn = ServerConnection::with_connection(
+            build_mock_http_conn(),
+            DefaultSessionState::<ServerMarker, TestStream>::new(),
+            TestStreamFactory);
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        {
+            let changes = changes.clone();
+            conn.set_settings_hook(move |settings, state| {
+                changes.lock().unwrap().push((settings.to_vec(), state.enable_push));
+            });
+        }
+        let mut sender = MockSendFrame::new();
+        let mut update = SettingsFrame::new();
+        update.add_setting(HttpSetting::EnablePush(0));
+        let mut rx = MockReceiveFrame::new(vec![
+            HttpFrame::SettingsFrame(SettingsFrame::new()),
+            HttpFrame::SettingsFrame(update),
+        ]);
+
+        conn.expect_settings(&mut rx, &mut sender).unwrap();
+        conn.handle_next_frame(&mut rx, &mut sender).unwrap();
+
+        assert_eq!(*changes.lock().unwrap(), vec![
+            (vec![], true),
+            (vec![HttpSetting::EnablePush(0)], false),
+        ]);
+        assert!(!conn.peer_settings().enable_push);
+        assert_eq!(sender.sent.len(), 2);
+    }
+
+    /// Tests that a connection over its memory budget refuses new streams and shrinks the
+    /// windows, restores them once back within the budget and closes the connection once over
+    /// twice the budget.
+    #[test]
+    fn test_server_conn_memory_budget() {
+        // The HPACK tables alone take up 8 KiB. The requests are not validated, so that only the
+        // budget gets in the way.
+        let config = Http2Config::new()
+            .memory_budget(Some(8192 + 100))
+            .compliance(Compliance::Lenient);
+        let mut conn = ServerConnection::with_connection(
+            HttpConnection::with_config(HttpScheme::Http, config),
+            DefaultSessionState::<ServerMarker, TestStream>::new(),
+            TestStreamFactory);
+        let mut sender = MockSendFrame::new();
+        let headers = |id| {
+            let mut frame = HeadersFrame::new(vec![0x82], id);
+            frame.set_flag(HeadersFlag::EndHeaders);
+            HttpFrame::HeadersFrame(frame)
+        };
+        let data = |len| HttpFrame::DataFrame(DataFrame::with_data(1, vec![0; len]));
+        let mut rx = MockReceiveFrame::new(vec![
+            headers(1), data(150), headers(3), data(100), data(9000), data(1),
+        ]);
+        let initial_window_size = |raw: &RawFrame| {
+            match HttpFrame::from_raw(raw).unwrap() {
+                HttpFrame::SettingsFrame(frame) => frame.settings,
+                _ => panic!("Expected a SETTINGS frame"),
+            }
+        };
+
+        conn.handle_next_frame(&mut rx, &mut sender).unwrap();
+        conn.handle_next_frame(&mut rx, &mut sender).unwrap();
+        assert!(sender.sent.is_empty());
+        assert_eq!(conn.memory_usage().streams, 150);
+        // Over the budget: the windows are shrunk and the new stream is refused.
+        conn.handle_next_frame(&mut rx, &mut sender).unwrap();
+        assert_eq!(sender.sent.len(), 2);
+        assert_eq!(initial_window_size(&sender.sent[0]),
+                   vec![HttpSetting::InitialWindowSize(0)]);
+        match HttpFrame::from_raw(&sender.sent[1]).unwrap() {
+            HttpFrame::RstStreamFrame(frame) => {
+                assert_eq!((frame.get_stream_id(), frame.error_code()),
+                           (3, ErrorCode::RefusedStream));
+            },
+            _ => panic!("Expected a RST_STREAM frame"),
+        }
+        // Once the body is consumed, the windows are restored.
+        conn.state.get_stream_mut(1).unwrap().body.clear();
+        conn.handle_next_frame(&mut rx, &mut sender).unwrap();
+        assert_eq!(initial_window_size(&sender.sent[2]),
+                   vec![HttpSetting::InitialWindowSize(65_535)]);
+        // Way over the budget, the connection is closed.
+        conn.handle_next_frame(&mut rx, &mut sender).unwrap();
+        assert_eq!(conn.handle_next_frame(&mut rx, &mut sender).err().unwrap(),
+                   HttpError::MemoryBudgetExceeded);
+        assert_eq!(sender.sent.len(), 4);
+        match HttpFrame::from_raw(&sender.sent[3]).unwrap() {
+            HttpFrame::GoawayFrame(frame) => {
+                assert_eq!(frame.error_code(), ErrorCode::EnhanceYourCalm);
+            },
+            _ => panic!("Expected a GOAWAY frame"),
+        }
+    }
+
+    /// Tests that a stream whose DATA is held back by the flow control window for longer than the
+    /// slow consumer timeout is reset, along with the whole connection if so configured.
+    #[test]
+    fn test_server_conn_slow_consumer() {
+        let new_conn = |close| {
+            let config = Http2Config::new()
+                .slow_consumer_timeout(Some(Duration::from_secs(0)))
+                .close_slow_consumers(close);
+            let mut conn = ServerConnection::with_connection(
+                HttpConnection::with_config(HttpScheme::Http, config),
+                DefaultSessionState::<ServerMarker, TestStream>::new(),
+                TestStreamFactory);
+            let mut stream = TestStream::new();
+            stream.set_outgoing(vec![0; 70_000]);
+            conn.state.insert_incoming(1, stream).unwrap();
+            conn
+        };
+
+        {
+            let mut conn = new_conn(false);
+            let mut sender = MockSendFrame::new();
+            // The peer never replenishes the window, so the stream is reset once the window is
+            // exhausted.
+            while conn.send_next(&mut sender).unwrap() == SendStatus::Sent {}
+            assert!(conn.state.get_stream_ref(1).unwrap().blocked_for.is_some());
+            assert!(conn.state.get_stream_ref(1).unwrap().is_closed());
+            match HttpFrame::from_raw(sender.sent.last().unwrap()).unwrap() {
+                HttpFrame::RstStreamFrame(frame) => {
+                    assert_eq!((frame.get_stream_id(), frame.error_code()),
+                               (1, ErrorCode::Cancel));
+                },
+                _ => panic!("Expected a RST_STREAM frame"),
+            }
+            assert_eq!(conn.send_next(&mut sender).unwrap(), SendStatus::Nothing);
+        }
+        {
+            let mut conn = new_conn(true);
+            let mut sender = MockSendFrame::new();
+            let res = loop {
+                match conn.send_next(&mut sender) {
+                    Ok(SendStatus::Sent) => {},
+                    res => break res,
+                }
+            };
+            assert_eq!(res.err().unwrap(), HttpError::SlowConsumer);
+            match HttpFrame::from_raw(sender.sent.last().unwrap()).unwrap() {
+                HttpFrame::GoawayFrame(frame) => {
+                    assert_eq!(frame.error_code(), ErrorCode::EnhanceYourCalm);
+                },
+                _ => panic!("Expected a GOAWAY frame"),
+            }
+        }
+    }
+
+    /// Tests that a stream counts as a slow consumer once its own flow control window is
+    /// exhausted, even while the connection's window is not, and that the other streams are not
+    /// affected.
+    #[test]
+    fn test_server_conn_slow_consumer_stream_window() {
+        let config = Http2Config::new().slow_consumer_timeout(Some(Duration::from_secs(0)));
+        let mut conn = ServerConnection::with_connection(
+            HttpConnection::with_config(HttpScheme::Http, config),
+            DefaultSessionState::<ServerMarker, TestStream>::new(),
+            TestStreamFactory);
+        let mut settings = SettingsFrame::new();
+        settings.add_setting(HttpSetting::InitialWindowSize(10));
+        let mut rx = MockReceiveFrame::new(vec![HttpFrame::SettingsFrame(settings)]);
+        conn.conn.handle_next_frame(&mut rx, &mut TestSession::new()).unwrap();
+        for &(id, len) in &[(1, 20), (3, 5)] {
+            let mut stream = TestStream::new();
+            stream.set_outgoing(vec![0; len]);
+            conn.state.insert_incoming(id, stream).unwrap();
+            let headers = vec![Header::new(b":status", b"200")];
+            conn.queue_response(headers, id, EndStream::No).unwrap();
+        }
+        let mut sender = MockSendFrame::new();
+
+        while conn.send_next(&mut sender).unwrap() == SendStatus::Sent {}
+
+        assert!(conn.conn.out_window_available() > 0);
+        assert!(conn.state.get_stream_ref(1).unwrap().blocked_for.is_some());
+        assert!(conn.state.get_stream_ref(1).unwrap().is_closed());
+        assert!(conn.state.get_stream_ref(3).map_or(true, |s| s.blocked_for.is_none()));
+        let resets: Vec<_> = sender.sent.iter().filter_map(|raw| {
+            match HttpFrame::from_raw(raw).unwrap() {
+                HttpFrame::RstStreamFrame(frame) => Some(frame.get_stream_id()),
+                _ => None,
+            }
+        }).collect();
+        assert_eq!(resets, vec![1]);
+    }
+
+    /// Tests that a client pushing or initiating an even-numbered stream fails the connection
+    /// with a PROTOCOL_ERROR, unless the compliance is lenient, in which case the frames are
+    /// ignored.
+    #[test]
+    fn test_server_conn_rejects_client_push() {
+        let push_promise = || {
+            let mut raw = pack_header(&(5, 0x5, 0x4, 1)).to_vec();
+            raw.extend_from_slice(&[0, 0, 0, 2, 0x82]);
+            HttpFrame::UnknownFrame(RawFrame::from(raw))
+        };
+        let even_headers = || {
+            let mut frame = HeadersFrame::new(vec![0x82], 2);
+            frame.set_flag(HeadersFlag::EndHeaders);
+            HttpFrame::HeadersFrame(frame)
+        };
+        let handle = |frame, compliance| {
+            let config = Http2Config::new().compliance(compliance);
+            let mut conn = ServerConnection::with_connection(
+                HttpConnection::with_config(HttpScheme::Http, config),
+                DefaultSessionState::<ServerMarker, TestStream>::new(),
+                TestStreamFactory);
+            let mut sender = MockSendFrame::new();
+            let mut rx = MockReceiveFrame::new(vec![frame]);
+            let res = conn.handle_next_frame(&mut rx, &mut sender);
+            assert_eq!(conn.state.iter().count(), 0);
+            (res, sender)
+        };
+
+        for frame in vec![push_promise(), even_headers()] {
+            let (res, sender) = handle(frame, Compliance::Strict);
+            assert_eq!(res.err().unwrap(),
+                       HttpError::LocalConnectionError(ErrorCode::ProtocolError));
+            assert_eq!(sender.sent.len(), 1);
+            match HttpFrame::from_raw(&sender.sent[0]).unwrap() {
+                HttpFrame::GoawayFrame(frame) => {
+                    assert_eq!(frame.error_code(), ErrorCode::ProtocolError);
+                },
+                _ => panic!("Expected a GOAWAY frame"),
+            }
+        }
+        for frame in vec![push_promise(), even_headers()] {
+            let (res, sender) = handle(frame, Compliance::Lenient);
+            assert!(res.is_ok());
+            assert!(sender.sent.is_empty());
+        }
+    }
 }
@@ -9,10 +9,19 @@ use std::error::Error;
 use std::io::Read;
 use std::io::Cursor;
 use std::iter::FromIterator;
+use std::time::Duration;
 use http::{StreamId, OwnedHeader, Header, HttpResult, ErrorCode, HttpError, ConnectionError};
-use http::frame::HttpSetting;
+use http::PseudoHeaders;
+use http::validation::{MalformedHeaders, BodyLength, LimitExceeded};
+use http::checksum::{BodyHook, BodyCheck};
+use http::stream_id::{StreamIdAllocator, StreamIdError};
+use http::soak::{Allocation, Resource};
+use http::frame::{HttpSetting, StreamDependency};
 use http::connection::{HttpConnection};
 
+pub use http::stream_state::{StreamState, StreamEvent, StreamStateError};
+pub use http::header_phase::{HeaderPhase, HeaderEvent, HeaderPhaseError};
+
 /// A trait that defines the interface between an `HttpConnection` and the higher-levels that use
 /// it. Essentially, it allows the `HttpConnection` to pass information onto those higher levels
 /// through a well-defined interface.
@@ -34,6 +43,41 @@ pub trait Session {
             headers: Vec<Header<'n, 'v>>,
             conn: &mut HttpConnection)
             -> HttpResult<()>;
+    /// Notifies the `Session` of the priority information that the peer included in the HEADERS
+    /// frame that opened (or continued) the given stream, in which case it is invoked right
+    /// before `new_headers` is invoked with the frame's headers, or sent in a PRIORITY frame. The
+    /// latter can concern a stream in any state, including one that is idle or already closed.
+    ///
+    /// The default implementation ignores the priority.
+    fn new_priority(&mut self, _stream_id: StreamId, _dependency: StreamDependency,
+                    _conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        Ok(())
+    }
+    /// Notifies the `Session` that the peer advertised alternative services in an ALTSVC frame
+    /// (RFC 7838), given the frame's `Alt-Svc` field value, e.g. `h2="alt.example.com:443"`.
+    ///
+    /// On stream 0, the alternatives apply to the given origin; on any other stream, the origin
+    /// is empty and they apply to the origin of the stream's request. Frames that the RFC
+    /// requires to be ignored are not passed on.
+    ///
+    /// The default implementation ignores the alternatives, which is what servers should do.
+    fn on_alt_svc(&mut self, _stream_id: StreamId, _origin: &[u8], _field_value: &[u8],
+                  _conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        Ok(())
+    }
+    /// Notifies the `Session` that the peer sent a PUSH_PROMISE frame on the given stream,
+    /// reserving the given promised stream.
+    ///
+    /// Server push is not supported, so the default implementation ignores the promise. Sessions
+    /// for which a push is a protocol violation (e.g. on the server side) should return an
+    /// `HttpError::LocalConnectionError` with a PROTOCOL_ERROR.
+    fn new_push_promise(&mut self, _stream_id: StreamId, _promised_stream_id: StreamId,
+                        _conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        Ok(())
+    }
     /// Notifies the `Session` that a particular stream got closed by the peer.
     fn end_of_stream(&mut self, stream_id: StreamId, conn: &mut HttpConnection)
             -> HttpResult<()>;
@@ -59,12 +103,77 @@ pub trait Session {
             _last_stream_id: StreamId,
             error_code: ErrorCode,
             debug_data: Option<&[u8]>,
-            _conn: &mut HttpConnection)
+            conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        let err = match debug_data {
+            Some(data) => ConnectionError::with_debug_data(error_code, data.to_vec()),
+            None => ConnectionError::new(error_code),
+        };
+        Err(HttpError::PeerConnectionError(err.on_connection(conn.id())))
+    }
+
+    /// Notifies the `Session` that the peer has increased the outbound flow control window of a
+    /// particular stream by the given increment.
+    ///
+    /// Producers of streamed bodies that were blocked on the stream's flow control window can use
+    /// this as the signal to resume producing data.
+    ///
+    /// The default implementation ignores the update.
+    fn window_update(&mut self, _stream_id: StreamId, _increment: u32, _conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        Ok(())
+    }
+
+    /// Notifies the `Session` that the peer has increased the connection-level outbound flow
+    /// control window by the given increment.
+    ///
+    /// By the time the callback is invoked, the new window size is already reflected by the
+    /// `HttpConnection::out_window_size` method of the given connection.
+    ///
+    /// The default implementation ignores the update.
+    fn connection_window_update(&mut self, _increment: u32, _conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        Ok(())
+    }
+
+    /// Returns the current state of the stream with the given ID, as far as the session is
+    /// concerned. Streams that the session never saw are expected to be reported as `Idle` and
+    /// those that it no longer tracks as `Closed`.
+    ///
+    /// The `HttpConnection` uses the state to check whether a received frame is allowed on its
+    /// stream before handing it to the session.
+    ///
+    /// The default implementation returns `None`, which disables the checks.
+    fn stream_state(&self, _stream_id: StreamId) -> Option<StreamState> {
+        None
+    }
+
+    /// Notifies the `Session` that the peer sent a frame that is a stream error of the given type
+    /// on the given stream (e.g. DATA on a stream that the peer already closed). The frame itself
+    /// is discarded by the connection.
+    ///
+    /// Sessions are expected to reset the stream by sending a RST_STREAM frame with the given
+    /// error code.
+    ///
+    /// The default implementation ignores the error.
+    fn stream_error(&mut self, _stream_id: StreamId, _error_code: ErrorCode,
+                    _conn: &mut HttpConnection)
+            -> HttpResult<()> {
+        Ok(())
+    }
+
+    /// Notifies the `Session` that the peer caused a connection error of the given type (e.g. it
+    /// sent a header block that could not be decoded). The connection does not handle any
+    /// further frames after this.
+    ///
+    /// Sessions are expected to close the connection by sending a GOAWAY frame with the given
+    /// error code.
+    ///
+    /// The default implementation ignores the error (the error that caused it is still returned
+    /// by the connection).
+    fn connection_error(&mut self, _error_code: ErrorCode, _conn: &mut HttpConnection)
             -> HttpResult<()> {
-        Err(HttpError::PeerConnectionError(ConnectionError {
-            error_code: error_code,
-            debug_data: debug_data.map(|data| data.to_vec()),
-        }))
+        Ok(())
     }
 }
 
@@ -98,6 +207,19 @@ pub trait SessionState {
     /// Inserts the given `Stream` into the session's state, starting to track it.
     /// The `SessionState` should assign it the next available outgoing stream ID.
     fn insert_outgoing(&mut self, stream: Self::Stream) -> StreamId;
+    /// Inserts the given `Stream` into the session's state, like `insert_outgoing`, unless there
+    /// are no stream IDs left to assign to it.
+    ///
+    /// The default implementation never fails, leaving it up to `insert_outgoing` to cope with
+    /// running out of IDs.
+    fn try_insert_outgoing(&mut self, stream: Self::Stream) -> Result<StreamId, StreamIdError> {
+        Ok(self.insert_outgoing(stream))
+    }
+    /// Inserts the given `Stream` into the session's state as an outgoing stream with the given
+    /// ID, instead of the next available one. The ID must be one that the next available ID could
+    /// skip ahead to, i.e. of the right parity and higher than all outgoing IDs used so far.
+    fn insert_outgoing_with_id(&mut self, stream_id: StreamId, stream: Self::Stream)
+            -> Result<(), StreamIdError>;
     /// Inserts the given `Stream` into the session's state, considering it an incoming
     /// stream.
     /// TODO(mlalic): Allow the exact error to propagate out.
@@ -115,6 +237,56 @@ pub trait SessionState {
     /// Returns an iterator over the streams currently found in the session.
     fn iter(&mut self) -> StreamIter<Self::Stream>;
 
+    /// Returns the highest ID of a stream initiated by the peer that the session state has seen,
+    /// i.e. the ID that should be sent in a GOAWAY frame.
+    ///
+    /// The default implementation returns 0, as if the peer had not initiated any streams.
+    fn last_incoming_stream_id(&self) -> StreamId {
+        0
+    }
+
+    /// Returns the state of the stream with the given ID.
+    ///
+    /// The default implementation only knows about the streams that are currently tracked and
+    /// returns `None` for all others. Implementations that keep track of the stream IDs that
+    /// were already used should report those as `Closed` and the rest as `Idle`.
+    fn stream_state(&self, stream_id: StreamId) -> Option<StreamState> {
+        self.get_stream_ref(stream_id).map(|stream| stream.state())
+    }
+
+    /// Starts checking that the body received on the given stream is exactly `length` octets
+    /// long, e.g. because the peer declared it in a `content-length` header. If the length of the
+    /// stream's body is already expected to be something, that expectation is kept.
+    ///
+    /// The default implementation does not keep track of body lengths.
+    fn expect_body_length(&mut self, _stream_id: StreamId, _length: u64) {}
+
+    /// Returns the tracked body length of the given stream, if the session state expects the body
+    /// to be of a particular length.
+    ///
+    /// The default implementation returns `None`, which disables the checks.
+    fn body_length(&mut self, _stream_id: StreamId) -> Option<&mut BodyLength> {
+        None
+    }
+
+    /// Returns the header phase of the given stream, i.e. how far the message received on it got,
+    /// if the session state tracks the stream. Streams start out in the `HeaderPhase::Initial`
+    /// phase.
+    ///
+    /// The default implementation returns `None`, which disables the checks.
+    fn header_phase(&mut self, _stream_id: StreamId) -> Option<&mut HeaderPhase> {
+        None
+    }
+
+    /// Returns the header phase of the message that the local peer sends on the given stream,
+    /// if the session state tracks the stream. It is the counterpart of `header_phase`, used to
+    /// reject illegal sequences before anything is sent.
+    ///
+    /// The default implementation returns `None`, which disables the checks.
+    fn sent_header_phase(&mut self, _stream_id: StreamId) -> Option<&mut HeaderPhase> {
+        None
+    }
+
     /// Returns all streams that are closed and tracked by the session state.
     ///
     /// The streams are moved out of the session state.
@@ -163,8 +335,16 @@ impl Parity {
 pub struct DefaultSessionState<T, S> where S: Stream {
     /// All streams that the session state is currently aware of.
     streams: HashMap<StreamId, S>,
-    /// The next available ID for outgoing streams.
-    next_stream_id: StreamId,
+    /// The expected body lengths of the streams that declared them.
+    body_lengths: HashMap<StreamId, BodyLength>,
+    /// The header phases of the streams that received any frames.
+    header_phases: HashMap<StreamId, HeaderPhase>,
+    /// The header phases of the messages sent on the streams.
+    sent_header_phases: HashMap<StreamId, HeaderPhase>,
+    /// Assigns the IDs of outgoing streams.
+    outgoing_ids: StreamIdAllocator,
+    /// The highest ID of an incoming stream seen so far (0 if none).
+    last_incoming_stream_id: StreamId,
     /// The parity bit for outgoing connections. Client-initiated connections must always be
     /// odd-numbered, while server-initiated ones should be even. Therefore, the parity bit
     /// is `Odd` for clients' session state and `Even` for servers'.
@@ -189,7 +369,11 @@ impl<S> DefaultSessionState<Client, S> where S: Stream {
     pub fn new() -> DefaultSessionState<Client, S> {
         DefaultSessionState {
             streams: HashMap::new(),
-            next_stream_id: 1,
+            body_lengths: HashMap::new(),
+            header_phases: HashMap::new(),
+            sent_header_phases: HashMap::new(),
+            outgoing_ids: StreamIdAllocator::for_client(),
+            last_incoming_stream_id: 0,
             outgoing_parity: Parity::Odd,
             _server_or_client: PhantomData,
         }
@@ -201,7 +385,11 @@ impl<S> DefaultSessionState<Server, S> where S: Stream {
     pub fn new() -> DefaultSessionState<Server, S> {
         DefaultSessionState {
             streams: HashMap::new(),
-            next_stream_id: 2,
+            body_lengths: HashMap::new(),
+            header_phases: HashMap::new(),
+            sent_header_phases: HashMap::new(),
+            outgoing_ids: StreamIdAllocator::for_server(),
+            last_incoming_stream_id: 0,
             outgoing_parity: Parity::Even,
             _server_or_client: PhantomData,
         }
@@ -224,11 +412,24 @@ pub fn default_server_state<S: Stream>() -> DefaultSessionState<Server, S> {
 impl<T, S> SessionState for DefaultSessionState<T, S> where S: Stream {
     type Stream = S;
 
+    /// # Panics
+    ///
+    /// Panics if the session ran out of stream IDs (see `try_insert_outgoing`).
     fn insert_outgoing(&mut self, stream: Self::Stream) -> StreamId {
-        let id = self.next_stream_id;
+        self.try_insert_outgoing(stream).expect("Ran out of stream IDs")
+    }
+
+    fn try_insert_outgoing(&mut self, stream: Self::Stream) -> Result<StreamId, StreamIdError> {
+        let id = try!(self.outgoing_ids.allocate());
         self.streams.insert(id, stream);
-        self.next_stream_id += 2;
-        id
+        Ok(id)
+    }
+
+    fn insert_outgoing_with_id(&mut self, stream_id: StreamId, stream: Self::Stream)
+            -> Result<(), StreamIdError> {
+        try!(self.outgoing_ids.reserve(stream_id));
+        self.streams.insert(stream_id, stream);
+        Ok(())
     }
 
     fn insert_incoming(&mut self, stream_id: StreamId, stream: Self::Stream) -> Result<(), ()> {
@@ -237,6 +438,9 @@ impl<T, S> SessionState for DefaultSessionState<T, S> where S: Stream {
             true => {
                 // TODO(mlalic): Assert that the stream IDs are monotonically increasing!
                 self.streams.insert(stream_id, stream);
+                if stream_id > self.last_incoming_stream_id {
+                    self.last_incoming_stream_id = stream_id;
+                }
                 Ok(())
             },
         }
@@ -246,6 +450,24 @@ impl<T, S> SessionState for DefaultSessionState<T, S> where S: Stream {
     fn get_stream_ref(&self, stream_id: StreamId) -> Option<&Self::Stream> {
         self.streams.get(&stream_id)
     }
+
+    #[inline]
+    fn last_incoming_stream_id(&self) -> StreamId {
+        self.last_incoming_stream_id
+    }
+
+    fn stream_state(&self, stream_id: StreamId) -> Option<StreamState> {
+        if let Some(stream) = self.streams.get(&stream_id) {
+            return Some(stream.state());
+        }
+        // Untracked streams with an ID that was already used must have been closed.
+        let used = if self.validate_incoming_parity(stream_id) {
+            stream_id <= self.last_incoming_stream_id
+        } else {
+            self.outgoing_ids.is_used(stream_id)
+        };
+        Some(if used { StreamState::Closed } else { StreamState::Idle })
+    }
     #[inline]
     fn get_stream_mut(&mut self, stream_id: StreamId) -> Option<&mut Self::Stream> {
         self.streams.get_mut(&stream_id)
@@ -253,29 +475,41 @@ impl<T, S> SessionState for DefaultSessionState<T, S> where S: Stream {
 
     #[inline]
     fn remove_stream(&mut self, stream_id: StreamId) -> Option<Self::Stream> {
+        self.body_lengths.remove(&stream_id);
+        self.header_phases.remove(&stream_id);
+        self.sent_header_phases.remove(&stream_id);
         self.streams.remove(&stream_id)
     }
 
+    fn expect_body_length(&mut self, stream_id: StreamId, length: u64) {
+        self.body_lengths.entry(stream_id).or_insert_with(|| BodyLength::new(length));
+    }
+
+    #[inline]
+    fn body_length(&mut self, stream_id: StreamId) -> Option<&mut BodyLength> {
+        self.body_lengths.get_mut(&stream_id)
+    }
+
+    fn header_phase(&mut self, stream_id: StreamId) -> Option<&mut HeaderPhase> {
+        if !self.streams.contains_key(&stream_id) {
+            return None;
+        }
+        Some(self.header_phases.entry(stream_id).or_insert(HeaderPhase::Initial))
+    }
+
+    fn sent_header_phase(&mut self, stream_id: StreamId) -> Option<&mut HeaderPhase> {
+        if !self.streams.contains_key(&stream_id) {
+            return None;
+        }
+        Some(self.sent_header_phases.entry(stream_id).or_insert(HeaderPhase::Initial))
+    }
+
     #[inline]
     fn iter(&mut self) -> StreamIter<S> {
         StreamIter(Box::new(self.streams.iter_mut()))
     }
 }
 
-/// The enum represents all the states that an HTTP/2 stream can be found in.
-///
-/// Corresponds to [section 5.1.](http://http2.github.io/http2-spec/#rfc.section.5.1) of the spec.
-#[derive(Clone, Copy, PartialEq, Debug)]
-pub enum StreamState {
-    Idle,
-    ReservedLocal,
-    ReservedRemote,
-    Open,
-    HalfClosedRemote,
-    HalfClosedLocal,
-    Closed,
-}
-
 /// The enum represents errors that can be returned from the `Stream::get_data_chunk` method.
 #[derive(Debug)]
 pub enum StreamDataError {
@@ -327,6 +561,46 @@ pub trait Stream {
         self.close();
     }
 
+    /// Returns whether a header block was already received on the stream, meaning that any
+    /// further header block carries trailers.
+    ///
+    /// The default implementation returns `false`, i.e. every header block is validated as the
+    /// one that starts a request or response.
+    fn headers_received(&self) -> bool { false }
+
+    /// Invoked when the peer sent a malformed header block on the stream. The headers are not
+    /// passed on to the stream and it gets reset with a PROTOCOL_ERROR.
+    ///
+    /// The default implementation does nothing.
+    fn on_malformed_headers(&mut self, _error: &MalformedHeaders) {}
+
+    /// Invoked when the request that the peer opened the stream with exceeds one of the limits of
+    /// the `Http2Config` (e.g. `max_header_count`). The headers are not passed on to the stream,
+    /// which gets closed right after the request is rejected.
+    ///
+    /// The default implementation does nothing.
+    fn on_limit_exceeded(&mut self, _limit: &LimitExceeded) {}
+
+    /// Invoked when the length of the body that the peer sent on the stream does not match the
+    /// `content-length` that it declared. The stream gets reset with a PROTOCOL_ERROR.
+    ///
+    /// The default implementation does nothing.
+    fn on_body_length_mismatch(&mut self, _length: &BodyLength) {}
+
+    /// Invoked when the stream's outgoing data was held back by the peer's flow control window
+    /// for longer than the `Http2Config::slow_consumer_timeout`, i.e. the peer stopped reading.
+    /// The stream gets reset with a CANCEL error right after.
+    ///
+    /// The default implementation does nothing.
+    fn on_slow_consumer(&mut self, _blocked_for: Duration) {}
+
+    /// Returns the number of bytes that the stream holds on to, i.e. the received data that is
+    /// yet to be consumed and the outgoing data that is yet to be sent. It counts towards the
+    /// memory budget of the connection (see `Http2Config::memory_budget`).
+    ///
+    /// The default implementation returns 0, i.e. the stream's buffers are not accounted for.
+    fn buffered_len(&self) -> usize { 0 }
+
     /// Places the next data chunk that should be written onto the stream into the given buffer.
     ///
     /// # Returns
@@ -345,28 +619,34 @@ pub trait Stream {
     /// Returns the current state of the stream.
     fn state(&self) -> StreamState;
 
+    /// Moves the stream to the state that follows its current one once the given event happens.
+    ///
+    /// If the event is not allowed in the current state, the state is left unchanged and an error
+    /// is returned.
+    fn transition(&mut self, event: StreamEvent) -> Result<StreamState, StreamStateError> {
+        let next = try!(self.state().transition(event));
+        self.set_state(next);
+        Ok(next)
+    }
+
     /// Transitions the stream state to closed. After this, the stream is considered to be closed
     /// for any further reads or writes.
     fn close(&mut self) { self.set_state(StreamState::Closed); }
     /// Updates the `Stream` status to indicate that it is closed locally.
     ///
-    /// If the stream is closed on the remote end, then it is fully closed after this call.
+    /// If the stream is closed on the remote end, then it is fully closed after this call. A
+    /// stream that cannot be closed locally in its current state (e.g. because it already is)
+    /// is left unchanged.
     fn close_local(&mut self) {
-        let next = match self.state() {
-            StreamState::HalfClosedRemote => StreamState::Closed,
-            _ => StreamState::HalfClosedLocal,
-        };
-        self.set_state(next);
+        let _ = self.transition(StreamEvent::SendEndStream);
     }
     /// Updates the `Stream` status to indicate that it is closed on the remote peer's side.
     ///
-    /// If the stream is also locally closed, then it is fully closed after this call.
+    /// If the stream is also locally closed, then it is fully closed after this call. A stream
+    /// that cannot be closed remotely in its current state (e.g. because it already is) is left
+    /// unchanged.
     fn close_remote(&mut self) {
-        let next = match self.state() {
-            StreamState::HalfClosedLocal => StreamState::Closed,
-            _ => StreamState::HalfClosedRemote,
-        };
-        self.set_state(next);
+        let _ = self.transition(StreamEvent::RecvEndStream);
     }
     /// Returns whether the stream is closed.
     ///
@@ -398,13 +678,31 @@ pub struct DefaultStream {
     pub stream_id: Option<StreamId>,
     /// The headers associated with the stream (i.e. the response headers)
     pub headers: Option<Vec<Header<'static, 'static>>>,
+    /// The pseudo-headers found in `headers`, extracted when the headers are set.
+    pub pseudo_headers: PseudoHeaders,
     /// The body of the stream (i.e. the response body)
     pub body: Vec<u8>,
+    /// The trailers, i.e. the header block received after the body, if any.
+    pub trailers: Option<Vec<Header<'static, 'static>>>,
+    /// The error code with which the peer reset the stream, if it did.
+    pub error_code: Option<ErrorCode>,
+    /// Why a header block that the peer sent on the stream was malformed, if one was. The stream
+    /// is reset with a PROTOCOL_ERROR in that case.
+    pub malformed_headers: Option<MalformedHeaders>,
     /// The current stream state.
     pub state: StreamState,
     /// The outgoing data associated to the stream. The `Cursor` points into the `Vec` at the
     /// position where the data has been sent out.
     data: Option<Cursor<Vec<u8>>>,
+    /// Whether more outgoing data may still follow the data that is already associated to the
+    /// stream, in which case the stream stays open once all of it is sent.
+    open_ended: bool,
+    /// The result of the body hook, once the full body was received.
+    pub body_check: Option<BodyCheck>,
+    /// The hook that processes the body as it is received, if any.
+    body_hook: Option<Box<BodyHook>>,
+    /// Accounts for the stream in soak tests.
+    _allocation: Allocation,
 }
 
 impl DefaultStream {
@@ -413,9 +711,17 @@ impl DefaultStream {
         DefaultStream {
             stream_id: None,
             headers: None,
+            pseudo_headers: PseudoHeaders::default(),
             body: Vec::new(),
+            trailers: None,
+            error_code: None,
+            malformed_headers: None,
             state: StreamState::Open,
             data: None,
+            open_ended: false,
+            body_check: None,
+            body_hook: None,
+            _allocation: Allocation::new(Resource::Stream),
         }
     }
 
@@ -424,9 +730,17 @@ impl DefaultStream {
         DefaultStream {
             stream_id: Some(stream_id),
             headers: None,
+            pseudo_headers: PseudoHeaders::default(),
             body: Vec::new(),
+            trailers: None,
+            error_code: None,
+            malformed_headers: None,
             state: StreamState::Open,
             data: None,
+            open_ended: false,
+            body_check: None,
+            body_hook: None,
+            _allocation: Allocation::new(Resource::Stream),
         }
     }
 
@@ -436,21 +750,100 @@ impl DefaultStream {
     #[inline]
     pub fn set_full_data(&mut self, data: Vec<u8>) {
         self.data = Some(Cursor::new(data));
+        self.open_ended = false;
+    }
+
+    /// Adds the given data to the outgoing data of the stream. Unlike with `set_full_data`, the
+    /// stream stays open once all the data is sent, so that more can be pushed later on.
+    ///
+    /// The stream is ended by `finish_data`.
+    pub fn push_data(&mut self, data: &[u8]) {
+        self.open_ended = true;
+        match self.data {
+            // The data that has already been sent is dropped, instead of letting the buffer grow
+            // for as long as the stream is open.
+            Some(ref mut buf) if (buf.position() as usize) < buf.get_ref().len() => {
+                buf.get_mut().extend_from_slice(data);
+                return;
+            },
+            _ => {},
+        }
+        self.data = Some(Cursor::new(data.to_vec()));
+    }
+
+    /// Returns whether any outgoing data was associated to the stream (by `set_full_data`,
+    /// `push_data` or `finish_data`), whether or not it is sent by now.
+    pub fn has_outgoing_data(&self) -> bool {
+        self.data.is_some()
+    }
+
+    /// Marks that no more data will be pushed to the stream, so that it ends once all of the
+    /// previously pushed data is sent.
+    pub fn finish_data(&mut self) {
+        self.open_ended = false;
+        if self.data.is_none() {
+            self.data = Some(Cursor::new(Vec::new()));
+        }
+    }
+
+    /// Sets the hook that processes the body as it is received, e.g. to verify its digest. Its
+    /// result is stored in `body_check` once the stream is closed after the headers were
+    /// received (normally, by the peer ending it), unless the peer reset the stream.
+    ///
+    /// The hook needs to be set before the headers are received.
+    pub fn set_body_hook(&mut self, hook: Box<BodyHook>) {
+        self.body_hook = Some(hook);
     }
 }
 
 impl Stream for DefaultStream {
     fn new_data_chunk(&mut self, data: &[u8]) {
+        if let Some(ref mut hook) = self.body_hook {
+            hook.on_data(data);
+        }
         self.body.extend(data.to_vec().into_iter());
     }
 
     fn set_headers<'n, 'v>(&mut self, headers: Vec<Header<'n, 'v>>) {
-        self.headers = Some(headers.into_iter().map(|h| {
+        let headers = headers.into_iter().map(|h| {
             let owned: OwnedHeader = h.into();
             owned.into()
-        }).collect());
+        }).collect();
+        if self.headers.is_some() {
+            // A header block following the headers carries the trailers.
+            self.trailers = Some(headers);
+        } else {
+            if let Some(ref mut hook) = self.body_hook {
+                hook.on_headers(&headers);
+            }
+            self.pseudo_headers = PseudoHeaders::parse(&headers);
+            self.headers = Some(headers);
+        }
+    }
+    fn set_state(&mut self, state: StreamState) {
+        self.state = state;
+        if self.is_closed_remote() && self.error_code.is_none() && self.headers.is_some() {
+            if let Some(mut hook) = self.body_hook.take() {
+                self.body_check = Some(hook.finish());
+            }
+        }
+    }
+
+    fn on_rst_stream(&mut self, error_code: ErrorCode) {
+        self.error_code = Some(error_code);
+        self.close();
+    }
+
+    fn headers_received(&self) -> bool { self.headers.is_some() }
+
+    fn on_malformed_headers(&mut self, error: &MalformedHeaders) {
+        self.malformed_headers = Some(error.clone());
+    }
+
+    fn buffered_len(&self) -> usize {
+        let unsent = self.data.as_ref().map_or(0, |d| d.get_ref().len() - d.position() as usize);
+        self.body.len() + unsent
     }
-    fn set_state(&mut self, state: StreamState) { self.state = state; }
 
     fn state(&self) -> StreamState { self.state }
 
@@ -465,10 +858,14 @@ impl Stream for DefaultStream {
                 // For the `Vec`-backed reader, this should never fail, so unwrapping is
                 // fine.
                 let read = d.read(buf).unwrap();
-                if (d.position() as usize) == d.get_ref().len() {
+                if (d.position() as usize) < d.get_ref().len() {
+                    StreamDataChunk::Chunk(read)
+                } else if !self.open_ended {
                     StreamDataChunk::Last(read)
-                } else {
+                } else if read > 0 {
                     StreamDataChunk::Chunk(read)
+                } else {
+                    StreamDataChunk::Unavailable
                 }
             }
         };
@@ -489,12 +886,15 @@ mod tests {
         DefaultSessionState,
         DefaultStream,
         StreamDataChunk, StreamDataError,
+        StreamState, StreamEvent,
         SessionState,
         Parity,
     };
     use super::Client as ClientMarker;
     use super::Server as ServerMarker;
-    use http::ErrorCode;
+    use http::{ErrorCode, Header};
+    use http::stream_id::StreamIdError;
+    use http::checksum::DigestHook;
     use http::tests::common::TestStream;
 
     /// Checks that the `Parity` struct indeed works as advertised.
@@ -517,6 +917,14 @@ mod tests {
         // ...while incoming are only allowed to be even-numbered.
         assert!(state.insert_incoming(2, TestStream::new()).is_ok());
         assert!(state.insert_incoming(3, TestStream::new()).is_err());
+        // Explicitly chosen IDs may skip ahead, but not go back.
+        assert_eq!(state.insert_outgoing_with_id(3, TestStream::new()),
+                   Err(StreamIdError::NotIncreasing(3)));
+        assert_eq!(state.insert_outgoing_with_id(4, TestStream::new()),
+                   Err(StreamIdError::WrongParity(4)));
+        assert_eq!(state.insert_outgoing_with_id(9, TestStream::new()), Ok(()));
+        assert!(state.get_stream_ref(9).is_some());
+        assert_eq!(state.insert_outgoing(TestStream::new()), 11);
     }
 
     /// Tests that the `DefaultSessionState` when instantiated in server-mode correctly assigns
@@ -602,6 +1010,108 @@ mod tests {
         }
     }
 
+    /// Tests that the `DefaultSessionState` keeps track of the expected body lengths of its
+    /// streams.
+    #[test]
+    fn test_default_session_state_body_length() {
+        let mut state = DefaultSessionState::<ClientMarker, TestStream>::new();
+        let id = state.insert_outgoing(TestStream::new());
+        assert!(state.body_length(id).is_none());
+
+        state.expect_body_length(id, 0);
+        // The first expectation is kept.
+        state.expect_body_length(id, 10);
+        assert_eq!(state.body_length(id).map(|l| l.declared), Some(0));
+
+        // Removing the stream also stops tracking its body length.
+        state.remove_stream(id);
+        assert!(state.body_length(id).is_none());
+    }
+
+    /// Tests that the `DefaultStream` extracts the pseudo-headers when its headers are set.
+    #[test]
+    fn test_default_stream_pseudo_headers() {
+        let mut stream = DefaultStream::new();
+        assert_eq!(stream.pseudo_headers.status, None);
+
+        stream.set_headers(vec![
+            Header::new(b":status", b"200"),
+            Header::new(b"content-type".to_vec(), b"text/plain".to_vec()),
+        ]);
+
+        assert_eq!(stream.pseudo_headers.status, Some(200));
+        assert_eq!(stream.pseudo_headers.method, None);
+        assert_eq!(stream.headers.as_ref().unwrap().len(), 2);
+    }
+
+    /// Tests that the `DefaultStream` keeps a second header block as the trailers, without
+    /// touching the headers.
+    #[test]
+    fn test_default_stream_trailers() {
+        let mut stream = DefaultStream::new();
+        stream.set_headers(vec![Header::new(b":status", b"200")]);
+        assert!(stream.trailers.is_none());
+
+        stream.set_headers(vec![Header::new(b"grpc-status".to_vec(), b"0".to_vec())]);
+
+        assert_eq!(stream.pseudo_headers.status, Some(200));
+        assert_eq!(stream.headers.unwrap(), vec![Header::new(b":status", b"200")]);
+        assert_eq!(stream.trailers.unwrap(),
+                   vec![Header::new(b"grpc-status".to_vec(), b"0".to_vec())]);
+    }
+
+    /// Tests that the body hook of a `DefaultStream` sees the headers and the body, and that its
+    /// result is stored once the stream is closed, unless the peer reset it.
+    #[test]
+    fn test_default_stream_body_hook() {
+        let mut stream = DefaultStream::new();
+        stream.set_body_hook(Box::new(DigestHook::new()));
+        stream.close_local();
+        stream.set_headers(vec![
+            Header::new(b":status", b"200"),
+            Header::new(b"digest".to_vec(),
+                        b"sha-256=LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=".to_vec()),
+        ]);
+        stream.new_data_chunk(b"hello!");
+        assert!(stream.body_check.is_none());
+        stream.close_remote();
+        assert!(stream.body_check.unwrap().is_mismatch());
+
+        let mut stream = DefaultStream::new();
+        stream.set_body_hook(Box::new(DigestHook::new()));
+        stream.set_headers(vec![Header::new(b":status", b"200")]);
+        stream.new_data_chunk(b"hel");
+        stream.on_rst_stream(ErrorCode::InternalError);
+        assert!(stream.body_check.is_none());
+    }
+
+    /// Tests that data pushed to a `DefaultStream` is sent without ending the stream, until
+    /// `finish_data` is called.
+    #[test]
+    fn test_default_stream_push_data() {
+        let mut buf = [0; 2];
+        let mut stream = DefaultStream::new();
+        stream.push_data(b"abc");
+        stream.new_data_chunk(b"xy");
+        assert_eq!(stream.buffered_len(), 5);
+
+        assert_eq!(stream.get_data_chunk(&mut buf).ok().unwrap(), StreamDataChunk::Chunk(2));
+        assert_eq!(stream.buffered_len(), 3);
+        assert_eq!(stream.get_data_chunk(&mut buf).ok().unwrap(), StreamDataChunk::Chunk(1));
+        assert_eq!(stream.get_data_chunk(&mut buf).ok().unwrap(), StreamDataChunk::Unavailable);
+        assert!(!stream.is_closed_local());
+
+        stream.push_data(b"d");
+        stream.push_data(b"e");
+        assert_eq!(stream.get_data_chunk(&mut buf).ok().unwrap(), StreamDataChunk::Chunk(2));
+        assert_eq!(&buf, b"de");
+
+        // Once finished without any data left, the stream is ended with an empty chunk.
+        stream.finish_data();
+        assert_eq!(stream.get_data_chunk(&mut buf).ok().unwrap(), StreamDataChunk::Last(0));
+        assert!(stream.is_closed_local());
+    }
+
     /// Tests that the `DefaultStream` provides the correct data when its `get_data_chunk` method
     /// is called.
     #[test]
@@ -702,4 +1212,46 @@ mod tests {
             _ => false,
         });
     }
+
+    /// Tests that a `Stream` is moved through the states by its `transition` method and that it
+    /// stays put on illegal moves.
+    #[test]
+    fn test_stream_transition() {
+        let mut stream = DefaultStream::new();
+        stream.set_state(StreamState::Idle);
+
+        assert_eq!(stream.transition(StreamEvent::RecvHeaders), Ok(StreamState::Open));
+        assert_eq!(stream.state(), StreamState::Open);
+        stream.close_remote();
+        assert_eq!(stream.state(), StreamState::HalfClosedRemote);
+        // Can't be closed remotely twice
+        assert!(stream.transition(StreamEvent::RecvEndStream).is_err());
+        stream.close_remote();
+        assert_eq!(stream.state(), StreamState::HalfClosedRemote);
+        stream.close_local();
+        assert!(stream.is_closed());
+        // Closing an already closed stream keeps it closed
+        stream.close_local();
+        assert!(stream.is_closed());
+    }
+
+    /// Tests that the `DefaultSessionState` reports the state of both tracked and untracked
+    /// streams.
+    #[test]
+    fn test_default_session_state_stream_state() {
+        let mut state = DefaultSessionState::<ServerMarker, TestStream>::new();
+        state.insert_incoming(3, TestStream::new()).unwrap();
+        assert_eq!(state.insert_outgoing(TestStream::new()), 2);
+
+        assert_eq!(state.stream_state(3), Some(StreamState::Open));
+        assert_eq!(state.stream_state(2), Some(StreamState::Open));
+        // Skipped or reaped stream IDs are closed...
+        assert_eq!(state.stream_state(1), Some(StreamState::Closed));
+        state.get_stream_mut(3).unwrap().close();
+        state.get_closed();
+        assert_eq!(state.stream_state(3), Some(StreamState::Closed));
+        // ...while the ones not yet used are idle.
+        assert_eq!(state.stream_state(5), Some(StreamState::Idle));
+        assert_eq!(state.stream_state(4), Some(StreamState::Idle));
+    }
 }
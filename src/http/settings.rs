@@ -0,0 +1,213 @@
+//! The module implements tracking of the values of the HTTP/2 settings (section 6.5.2.) that are
+//! in effect on a connection.
+//!
+//! Each direction of a connection has its own settings: the ones that we advertise constrain what
+//! the peer may send us, while the peer's constrain what we may send it. Our own settings only
+//! take effect once the peer acknowledges them, so they are tracked separately from the ones that
+//! were sent, but are not acknowledged yet.
+use std::cmp;
+use std::collections::VecDeque;
+use std::vec::Vec;
+
+use http::frame::HttpSetting;
+use http::{INITIAL_STREAM_WINDOW_SIZE, DEFAULT_MAX_FRAME_SIZE, DEFAULT_HEADER_TABLE_SIZE};
+
+/// The values of all settings of one side of a connection.
+///
+/// Settings that are unlimited by default are `None` until a value is given.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SettingsState {
+    /// The size of the HPACK dynamic table that the other side's encoder may use.
+    pub header_table_size: u32,
+    /// Whether server push is allowed.
+    pub enable_push: bool,
+    /// The maximum number of concurrent streams that the other side may open.
+    pub max_concurrent_streams: Option<u32>,
+    /// The initial flow control window size of the streams.
+    pub initial_window_size: u32,
+    /// The largest frame payload that the other side may send.
+    pub max_frame_size: u32,
+    /// The maximum size of a header list that the other side may send.
+    pub max_header_list_size: Option<u32>,
+}
+
+impl Default for SettingsState {
+    fn default() -> SettingsState {
+        SettingsState {
+            header_table_size: DEFAULT_HEADER_TABLE_SIZE,
+            enable_push: true,
+            max_concurrent_streams: None,
+            initial_window_size: INITIAL_STREAM_WINDOW_SIZE as u32,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_header_list_size: None,
+        }
+    }
+}
+
+impl SettingsState {
+    /// Creates a new `SettingsState` with all settings at their initial values, as defined by the
+    /// spec.
+    pub fn new() -> SettingsState {
+        SettingsState::default()
+    }
+
+    /// Updates the state with the value of the given setting. Unknown settings are ignored
+    /// (section 6.5.2.).
+    pub fn apply(&mut self, setting: HttpSetting) {
+        match setting {
+            HttpSetting::HeaderTableSize(size) => self.header_table_size = size,
+            HttpSetting::EnablePush(enabled) => self.enable_push = enabled != 0,
+            HttpSetting::MaxConcurrentStreams(max) => self.max_concurrent_streams = Some(max),
+            HttpSetting::InitialWindowSize(size) => self.initial_window_size = size,
+            HttpSetting::MaxFrameSize(size) => self.max_frame_size = size,
+            HttpSetting::MaxHeaderListSize(size) => self.max_header_list_size = Some(size),
+            HttpSetting::Unknown(..) => {},
+        }
+    }
+
+    /// Updates the state with the values of all given settings, in order.
+    pub fn apply_all(&mut self, settings: &[HttpSetting]) {
+        for setting in settings {
+            self.apply(*setting);
+        }
+    }
+}
+
+/// Tracks our own settings: those that the peer acknowledged (and are thus in effect) and those
+/// that were sent, but not acknowledged yet.
+#[derive(Clone, Debug, Default)]
+pub struct LocalSettings {
+    acked: SettingsState,
+    /// The settings of each SETTINGS frame that is still waiting for an ACK, in the order in
+    /// which they were sent.
+    pending: VecDeque<Vec<HttpSetting>>,
+}
+
+impl LocalSettings {
+    /// Creates a new `LocalSettings` with no settings sent yet.
+    pub fn new() -> LocalSettings {
+        LocalSettings::default()
+    }
+
+    /// Returns the settings that the peer acknowledged.
+    #[inline]
+    pub fn acked(&self) -> &SettingsState {
+        &self.acked
+    }
+
+    /// Returns the settings that will be in effect once all sent SETTINGS frames are
+    /// acknowledged.
+    pub fn advertised(&self) -> SettingsState {
+        let mut state = self.acked;
+        for settings in &self.pending {
+            state.apply_all(settings);
+        }
+        state
+    }
+
+    /// Returns the largest frame payload that the peer may send right now. Until it acknowledges
+    /// a change of SETTINGS_MAX_FRAME_SIZE, the peer may be using either value, so the largest
+    /// of the acknowledged and all the pending ones applies.
+    pub fn max_receivable_frame_size(&self) -> u32 {
+        let mut state = self.acked;
+        let mut max = state.max_frame_size;
+        for settings in &self.pending {
+            state.apply_all(settings);
+            max = cmp::max(max, state.max_frame_size);
+        }
+        max
+    }
+
+    /// Returns the number of SETTINGS frames that the peer has not acknowledged yet.
+    #[inline]
+    pub fn pending_acks(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Records that a SETTINGS frame with the given settings was sent.
+    pub fn sent(&mut self, settings: &[HttpSetting]) {
+        self.pending.push_back(settings.to_vec());
+    }
+
+    /// Applies the settings of the oldest unacknowledged SETTINGS frame, as the peer has just
+    /// acknowledged it.
+    ///
+    /// Returns `false` if there was no SETTINGS frame waiting for an ACK.
+    pub fn acked_by_peer(&mut self) -> bool {
+        match self.pending.pop_front() {
+            Some(settings) => {
+                self.acked.apply_all(&settings);
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SettingsState, LocalSettings};
+    use http::frame::HttpSetting;
+
+    /// Tests that the `SettingsState` starts out with the spec defaults and applies new values.
+    #[test]
+    fn test_settings_state_apply() {
+        let mut state = SettingsState::new();
+        assert_eq!(state.header_table_size, 4_096);
+        assert!(state.enable_push);
+        assert_eq!(state.max_concurrent_streams, None);
+        assert_eq!(state.initial_window_size, 65_535);
+        assert_eq!(state.max_frame_size, 16_384);
+        assert_eq!(state.max_header_list_size, None);
+
+        state.apply_all(&[
+            HttpSetting::EnablePush(0),
+            HttpSetting::MaxConcurrentStreams(100),
+            HttpSetting::MaxFrameSize(32_768),
+            HttpSetting::MaxConcurrentStreams(10),
+        ]);
+        assert!(!state.enable_push);
+        // The last value wins.
+        assert_eq!(state.max_concurrent_streams, Some(10));
+        assert_eq!(state.max_frame_size, 32_768);
+    }
+
+    /// Tests that our own settings only take effect once acknowledged, in the order in which they
+    /// were sent.
+    #[test]
+    fn test_local_settings_acks() {
+        let mut local = LocalSettings::new();
+        assert!(!local.acked_by_peer());
+
+        local.sent(&[HttpSetting::EnablePush(0)]);
+        local.sent(&[HttpSetting::InitialWindowSize(1 << 20)]);
+        assert_eq!(local.pending_acks(), 2);
+        assert!(local.acked().enable_push);
+        assert!(!local.advertised().enable_push);
+        assert_eq!(local.advertised().initial_window_size, 1 << 20);
+
+        assert!(local.acked_by_peer());
+        assert!(!local.acked().enable_push);
+        assert_eq!(local.acked().initial_window_size, 65_535);
+
+        assert!(local.acked_by_peer());
+        assert_eq!(local.acked().initial_window_size, 1 << 20);
+        assert_eq!(local.pending_acks(), 0);
+    }
+
+    /// Tests that the peer may send frames as large as any SETTINGS_MAX_FRAME_SIZE that we sent,
+    /// until it acknowledges a smaller one.
+    #[test]
+    fn test_local_settings_max_receivable_frame_size() {
+        let mut local = LocalSettings::new();
+        assert_eq!(local.max_receivable_frame_size(), 16_384);
+
+        local.sent(&[HttpSetting::MaxFrameSize(1 << 20)]);
+        local.sent(&[HttpSetting::MaxFrameSize(32_768)]);
+        assert_eq!(local.max_receivable_frame_size(), 1 << 20);
+        local.acked_by_peer();
+        assert_eq!(local.max_receivable_frame_size(), 1 << 20);
+        local.acked_by_peer();
+        assert_eq!(local.max_receivable_frame_size(), 32_768);
+    }
+}
@@ -0,0 +1,196 @@
+//! The module implements the accounting of the resources that connections allocate, meant for
+//! soak tests that push a very large number of requests through the library in order to detect
+//! leaks and unbounded growth.
+//!
+//! Every tracked resource holds an `Allocation`, which counts the resource as outstanding for as
+//! long as it is alive. Once all the connections of a process are gone (or quiescent), nothing
+//! should be outstanding any longer, which is what `debug_assert_balanced` checks.
+//!
+//! The accounting is only performed when the crate is built with the `soak` feature. Without it,
+//! an `Allocation` is a zero-sized token, nothing is ever counted as outstanding and the checks
+//! pass trivially, so that the accounting costs nothing outside of soak tests.
+//!
+//! Tracked are:
+//!
+//! - the `DefaultStream`s, which are retained by the session state until they are removed;
+//! - the header blocks buffered by a connection, i.e. the ones waiting in a `Scheduler` to be
+//!   sent and the one whose CONTINUATION frames are yet to be received;
+//! - the control frames (PING acknowledgements, WINDOW_UPDATEs...) that a connection queued for
+//!   sending.
+#[cfg(feature="soak")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The kinds of resources that are accounted for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Resource {
+    /// A stream (i.e. a `DefaultStream`).
+    Stream,
+    /// A buffered header block.
+    Buffer,
+    /// A queued control frame.
+    Frame,
+}
+
+impl Resource {
+    /// The index of the resource's counter.
+    #[cfg(feature="soak")]
+    fn index(&self) -> usize {
+        match *self {
+            Resource::Stream => 0,
+            Resource::Buffer => 1,
+            Resource::Frame => 2,
+        }
+    }
+}
+
+/// The number of outstanding resources of each kind, process-wide.
+#[cfg(feature="soak")]
+static OUTSTANDING: [AtomicUsize; 3] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// A token that counts a resource as outstanding until it is dropped. Cloning the token accounts
+/// for another resource of the same kind, as it goes along with a clone of the resource.
+#[derive(Debug)]
+pub struct Allocation {
+    #[cfg(feature="soak")]
+    resource: Resource,
+}
+
+impl Allocation {
+    /// Accounts for a new resource of the given kind.
+    #[cfg(feature="soak")]
+    pub fn new(resource: Resource) -> Allocation {
+        OUTSTANDING[resource.index()].fetch_add(1, Ordering::SeqCst);
+        Allocation { resource: resource }
+    }
+
+    /// Accounts for a new resource of the given kind.
+    #[cfg(not(feature="soak"))]
+    #[inline]
+    pub fn new(_resource: Resource) -> Allocation {
+        Allocation {}
+    }
+}
+
+impl Clone for Allocation {
+    #[cfg(feature="soak")]
+    fn clone(&self) -> Allocation {
+        Allocation::new(self.resource)
+    }
+
+    #[cfg(not(feature="soak"))]
+    #[inline]
+    fn clone(&self) -> Allocation {
+        Allocation {}
+    }
+}
+
+#[cfg(feature="soak")]
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        OUTSTANDING[self.resource.index()].fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A snapshot of the number of outstanding resources.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Outstanding {
+    pub streams: usize,
+    pub buffers: usize,
+    pub frames: usize,
+}
+
+impl Outstanding {
+    /// Returns whether nothing is outstanding.
+    #[inline]
+    pub fn is_balanced(&self) -> bool {
+        *self == Outstanding::default()
+    }
+}
+
+/// Returns the number of resources that are currently outstanding, process-wide. Without the
+/// `soak` feature, nothing is ever outstanding.
+#[cfg(feature="soak")]
+pub fn outstanding() -> Outstanding {
+    let count = |resource: Resource| OUTSTANDING[resource.index()].load(Ordering::SeqCst);
+    Outstanding {
+        streams: count(Resource::Stream),
+        buffers: count(Resource::Buffer),
+        frames: count(Resource::Frame),
+    }
+}
+
+/// Returns the number of resources that are currently outstanding, process-wide. Without the
+/// `soak` feature, nothing is ever outstanding.
+#[cfg(not(feature="soak"))]
+#[inline]
+pub fn outstanding() -> Outstanding {
+    Outstanding::default()
+}
+
+/// Asserts (in debug builds) that no resources are outstanding, i.e. that everything that the
+/// connections allocated was released again.
+///
+/// Since the accounting is process-wide, the check is only meaningful at a point where all
+/// connections are known to be gone (or to have dropped all their streams and flushed
+/// everything that they queued).
+///
+/// # Panics
+///
+/// Panics if any resources are outstanding, reporting how many of each kind.
+pub fn debug_assert_balanced() {
+    let outstanding = outstanding();
+    debug_assert!(outstanding.is_balanced(), "Unbalanced resources: {:?}", outstanding);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+
+    use super::{Allocation, Resource, debug_assert_balanced, outstanding};
+
+    /// Tests that allocations are counted while they are alive, when the accounting is enabled.
+    ///
+    /// Other tests running in parallel allocate as well, so only lower bounds can be checked.
+    #[test]
+    fn test_allocation_accounting() {
+        let stream = Allocation::new(Resource::Stream);
+        let frames = vec![Allocation::new(Resource::Frame); 3];
+
+        let during = outstanding();
+        if cfg!(feature="soak") {
+            assert!(during.streams >= 1);
+            assert!(during.frames >= 3);
+        } else {
+            assert!(during.is_balanced());
+        }
+        drop(stream);
+        drop(frames);
+    }
+
+    /// Tests that the balance check reports an allocation that is never released.
+    ///
+    /// The leak stays counted for the rest of the process, which is fine as long as no other
+    /// test of the crate checks the balance.
+    #[cfg(all(feature="soak", debug_assertions))]
+    #[test]
+    #[should_panic(expected="Unbalanced resources")]
+    fn test_leak_unbalanced() {
+        mem::forget(Allocation::new(Resource::Buffer));
+
+        debug_assert_balanced();
+    }
+
+    /// Tests that without the accounting, leaked allocations go unnoticed.
+    #[cfg(not(feature="soak"))]
+    #[test]
+    fn test_leak_not_accounted() {
+        mem::forget(Allocation::new(Resource::Buffer));
+
+        assert_eq!(outstanding().buffers, 0);
+        debug_assert_balanced();
+    }
+}
@@ -0,0 +1,175 @@
+//! The module implements an HPACK encoder that never touches the dynamic table.
+//!
+//! Every header is encoded either as an index into the static table (when both its name and value
+//! are found there) or as a literal that is not indexed, reusing the static table for the name
+//! when possible. The first header block that the encoder produces starts with a dynamic table
+//! size update to 0, which tells the peer's decoder that it can drop its dynamic table
+//! altogether.
+//!
+//! Without a dynamic table, neither side keeps any state for the header blocks sent on the
+//! connection, which bounds the memory spent on HPACK and avoids the compression-based attacks
+//! (such as CRIME and HPACK bombs) that a shared compression context allows, at the cost of
+//! larger header blocks.
+use std::io::Write;
+
+use hpack::encoder::encode_integer_into;
+
+/// The static table, as defined by the HPACK spec (Appendix A). The index of an entry is its
+/// position in the table, plus one.
+static STATIC_TABLE: &'static [(&'static [u8], &'static [u8])] = &[
+    (b":authority", b""),
+    (b":method", b"GET"),
+    (b":method", b"POST"),
+    (b":path", b"/"),
+    (b":path", b"/index.html"),
+    (b":scheme", b"http"),
+    (b":scheme", b"https"),
+    (b":status", b"200"),
+    (b":status", b"204"),
+    (b":status", b"206"),
+    (b":status", b"304"),
+    (b":status", b"400"),
+    (b":status", b"404"),
+    (b":status", b"500"),
+    (b"accept-charset", b""),
+    (b"accept-encoding", b"gzip, deflate"),
+    (b"accept-language", b""),
+    (b"accept-ranges", b""),
+    (b"accept", b""),
+    (b"access-control-allow-origin", b""),
+    (b"age", b""),
+    (b"allow", b""),
+    (b"authorization", b""),
+    (b"cache-control", b""),
+    (b"content-disposition", b""),
+    (b"content-encoding", b""),
+    (b"content-language", b""),
+    (b"content-length", b""),
+    (b"content-location", b""),
+    (b"content-range", b""),
+    (b"content-type", b""),
+    (b"cookie", b""),
+    (b"date", b""),
+    (b"etag", b""),
+    (b"expect", b""),
+    (b"expires", b""),
+    (b"from", b""),
+    (b"host", b""),
+    (b"if-match", b""),
+    (b"if-modified-since", b""),
+    (b"if-none-match", b""),
+    (b"if-range", b""),
+    (b"if-unmodified-since", b""),
+    (b"last-modified", b""),
+    (b"link", b""),
+    (b"location", b""),
+    (b"max-forwards", b""),
+    (b"proxy-authenticate", b""),
+    (b"proxy-authorization", b""),
+    (b"range", b""),
+    (b"referer", b""),
+    (b"refresh", b""),
+    (b"retry-after", b""),
+    (b"server", b""),
+    (b"set-cookie", b""),
+    (b"strict-transport-security", b""),
+    (b"transfer-encoding", b""),
+    (b"user-agent", b""),
+    (b"vary", b""),
+    (b"via", b""),
+    (b"www-authenticate", b""),
+];
+
+/// An HPACK encoder that only uses the static table and literals.
+#[derive(Clone, Debug, Default)]
+pub struct StaticTableEncoder {
+    /// Whether the dynamic table size update was already sent.
+    size_update_sent: bool,
+}
+
+impl StaticTableEncoder {
+    /// Creates a new `StaticTableEncoder`, which is yet to encode its first header block.
+    pub fn new() -> StaticTableEncoder {
+        StaticTableEncoder::default()
+    }
+
+    /// Encodes the given headers into a header block.
+    pub fn encode<'b, I>(&mut self, headers: I) -> Vec<u8>
+            where I: IntoIterator<Item=(&'b [u8], &'b [u8])> {
+        let mut buf = Vec::new();
+        if !self.size_update_sent {
+            // A dynamic table size update (001xxxxx) to a size of 0.
+            buf.push(0x20);
+            self.size_update_sent = true;
+        }
+        for (name, value) in headers {
+            encode_header(name, value, &mut buf);
+        }
+        buf
+    }
+}
+
+/// Appends the representation of a single header to the buffer.
+fn encode_header(name: &[u8], value: &[u8], buf: &mut Vec<u8>) {
+    let full = STATIC_TABLE.iter().position(|&(n, v)| n == name && v == value);
+    if let Some(index) = full {
+        // An indexed header field (1xxxxxxx).
+        encode_integer_into(index + 1, 7, 0x80, buf).unwrap();
+        return;
+    }
+
+    // A literal header field without indexing (0000xxxx), whose name is either indexed or a
+    // literal itself (with an index of 0).
+    match STATIC_TABLE.iter().position(|&(n, _)| n == name) {
+        Some(index) => encode_integer_into(index + 1, 4, 0, buf).unwrap(),
+        None => {
+            buf.push(0);
+            encode_string(name, buf);
+        },
+    }
+    encode_string(value, buf);
+}
+
+/// Appends the given string literal to the buffer, without Huffman encoding.
+fn encode_string(octets: &[u8], buf: &mut Vec<u8>) {
+    encode_integer_into(octets.len(), 7, 0, buf).unwrap();
+    buf.write_all(octets).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use hpack;
+
+    use super::StaticTableEncoder;
+
+    /// Tests that headers are encoded using the static table and literals only, so that a decoder
+    /// ends up with nothing in its dynamic table.
+    #[test]
+    fn test_static_table_encoder() {
+        let mut encoder = StaticTableEncoder::new();
+        let headers = vec![
+            (&b":method"[..], &b"GET"[..]),
+            (&b":path"[..], &b"/search"[..]),
+            (&b"x-custom"[..], &b"value"[..]),
+        ];
+
+        let first = encoder.encode(headers.clone());
+        let second = encoder.encode(headers.clone());
+
+        let mut expected = vec![0x82, 0x04, 7];
+        expected.extend_from_slice(b"/search");
+        expected.extend_from_slice(&[0x00, 8]);
+        expected.extend_from_slice(b"x-custom");
+        expected.push(5);
+        expected.extend_from_slice(b"value");
+        // Only the first block updates the size of the dynamic table.
+        assert_eq!(first[0], 0x20);
+        assert_eq!(&first[1..], &expected[..]);
+        assert_eq!(second, expected);
+
+        let mut decoder = hpack::Decoder::new();
+        let owned: Vec<_> = headers.iter().map(|&(n, v)| (n.to_vec(), v.to_vec())).collect();
+        assert_eq!(decoder.decode(&first).unwrap(), owned);
+        assert_eq!(decoder.decode(&second).unwrap(), owned);
+    }
+}
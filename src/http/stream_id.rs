@@ -0,0 +1,154 @@
+//! The module implements the assignment of IDs to the streams that the local endpoint initiates.
+//!
+//! Stream IDs have to follow the rules of [section 5.1.1.](http://http2.github.io/http2-spec/#rfc.section.5.1.1)
+//! of the spec: clients initiate odd-numbered streams and servers even-numbered (pushed) ones,
+//! each new stream must have a higher ID than all the streams that the endpoint initiated before
+//! it, and IDs cannot go past 2^31 - 1. Getting any of those wrong is a connection error on the
+//! peer's side, so the `StreamIdAllocator` takes care of handing out the IDs instead of the users
+//! picking them.
+use std::error::Error;
+use std::fmt;
+
+use http::StreamId;
+
+/// The largest stream ID allowed by the spec (2^31 - 1).
+pub const MAX_STREAM_ID: StreamId = 0x7fffffff;
+
+/// The reasons for which a stream ID cannot be used for a new locally initiated stream.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StreamIdError {
+    /// The ID has the parity of the streams that the peer initiates (or is 0).
+    WrongParity(StreamId),
+    /// The ID is not higher than the IDs of the streams that were already initiated.
+    NotIncreasing(StreamId),
+    /// All the stream IDs are used up; a new connection is needed for any further streams.
+    Exhausted,
+}
+
+impl fmt::Display for StreamIdError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StreamIdError::WrongParity(id) => {
+                write!(fmt, "Stream ID {} cannot be initiated by the local endpoint", id)
+            },
+            StreamIdError::NotIncreasing(id) => {
+                write!(fmt, "Stream ID {} is not higher than the previously used ones", id)
+            },
+            StreamIdError::Exhausted => write!(fmt, "{}", self.description()),
+        }
+    }
+}
+
+impl Error for StreamIdError {
+    fn description(&self) -> &str {
+        match *self {
+            StreamIdError::WrongParity(_) => "Stream ID with the wrong parity",
+            StreamIdError::NotIncreasing(_) => "Stream ID lower than a previously used one",
+            StreamIdError::Exhausted => "All stream IDs of the connection are used up",
+        }
+    }
+}
+
+/// Hands out the IDs of the streams that the local endpoint initiates: odd ones for clients and
+/// even ones for servers, in increasing order.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct StreamIdAllocator {
+    /// The ID that the next stream gets, if it does not exceed `MAX_STREAM_ID`.
+    next: u64,
+}
+
+impl StreamIdAllocator {
+    /// Creates an allocator for the streams that a client initiates, starting at 1.
+    pub fn for_client() -> StreamIdAllocator {
+        StreamIdAllocator { next: 1 }
+    }
+
+    /// Creates an allocator for the streams that a server initiates (i.e. pushes), starting at 2.
+    pub fn for_server() -> StreamIdAllocator {
+        StreamIdAllocator { next: 2 }
+    }
+
+    /// Returns the ID that `allocate` is going to hand out next, if there are any left.
+    pub fn next_id(&self) -> Option<StreamId> {
+        if self.next <= MAX_STREAM_ID as u64 {
+            Some(self.next as StreamId)
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether the given ID belongs to a stream that the local endpoint initiates, i.e.
+    /// whether it has the right parity.
+    #[inline]
+    pub fn is_local(&self, stream_id: StreamId) -> bool {
+        stream_id != 0 && stream_id as u64 % 2 == self.next % 2
+    }
+
+    /// Returns whether the given locally initiated stream ID was already handed out.
+    pub fn is_used(&self, stream_id: StreamId) -> bool {
+        self.is_local(stream_id) && (stream_id as u64) < self.next
+    }
+
+    /// Hands out the ID of a new stream.
+    pub fn allocate(&mut self) -> Result<StreamId, StreamIdError> {
+        let id = try!(self.next_id().ok_or(StreamIdError::Exhausted));
+        self.next += 2;
+        Ok(id)
+    }
+
+    /// Uses the given ID for a new stream, instead of the one that `allocate` would hand out,
+    /// skipping all the IDs in between.
+    ///
+    /// The ID must have the right parity and be higher than all the IDs handed out so far.
+    pub fn reserve(&mut self, stream_id: StreamId) -> Result<(), StreamIdError> {
+        if !self.is_local(stream_id) {
+            return Err(StreamIdError::WrongParity(stream_id));
+        }
+        if stream_id > MAX_STREAM_ID {
+            return Err(StreamIdError::Exhausted);
+        }
+        if (stream_id as u64) < self.next {
+            return Err(StreamIdError::NotIncreasing(stream_id));
+        }
+        self.next = stream_id as u64 + 2;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StreamIdAllocator, StreamIdError, MAX_STREAM_ID};
+
+    /// Tests that the IDs are handed out with the right parity, in increasing order, and that
+    /// explicitly chosen IDs are checked against the ones already used.
+    #[test]
+    fn test_stream_id_allocator() {
+        let mut client = StreamIdAllocator::for_client();
+        assert_eq!(client.allocate(), Ok(1));
+        assert_eq!(client.allocate(), Ok(3));
+        assert!(client.is_used(3));
+        assert!(!client.is_used(5));
+        assert!(!client.is_local(2));
+        assert_eq!(client.reserve(4), Err(StreamIdError::WrongParity(4)));
+        assert_eq!(client.reserve(3), Err(StreamIdError::NotIncreasing(3)));
+        assert_eq!(client.reserve(9), Ok(()));
+        assert_eq!(client.allocate(), Ok(11));
+
+        let mut server = StreamIdAllocator::for_server();
+        assert_eq!(server.next_id(), Some(2));
+        assert_eq!(server.allocate(), Ok(2));
+        assert!(!server.is_local(0));
+        assert_eq!(server.reserve(0), Err(StreamIdError::WrongParity(0)));
+    }
+
+    /// Tests that no IDs are handed out past the largest one allowed.
+    #[test]
+    fn test_stream_id_allocator_exhausted() {
+        let mut client = StreamIdAllocator::for_client();
+        client.reserve(MAX_STREAM_ID - 2).unwrap();
+        assert_eq!(client.allocate(), Ok(MAX_STREAM_ID));
+        assert_eq!(client.next_id(), None);
+        assert_eq!(client.allocate(), Err(StreamIdError::Exhausted));
+        assert_eq!(client.reserve(MAX_STREAM_ID + 2), Err(StreamIdError::Exhausted));
+    }
+}
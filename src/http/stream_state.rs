@@ -0,0 +1,172 @@
+//! The module implements the state machine of HTTP/2 streams, as defined by the stream lifecycle
+//! of [section 5.1.](http://http2.github.io/http2-spec/#rfc.section.5.1) of the spec.
+//!
+//! The state machine is independent of the way in which frames are exchanged, so it is available
+//! without the `std` feature as well.
+use std::error::Error;
+use std::fmt;
+
+/// The enum represents all the states that an HTTP/2 stream can be found in.
+///
+/// Corresponds to [section 5.1.](http://http2.github.io/http2-spec/#rfc.section.5.1) of the spec.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StreamState {
+    Idle,
+    ReservedLocal,
+    ReservedRemote,
+    Open,
+    HalfClosedRemote,
+    HalfClosedLocal,
+    Closed,
+}
+
+/// The enum represents the events that drive a stream through the states of the stream lifecycle
+/// diagram found in [section 5.1.](http://http2.github.io/http2-spec/#rfc.section.5.1) of the
+/// spec.
+///
+/// A frame carrying the END_STREAM flag corresponds to two events: the one for the frame type
+/// itself, followed by the matching `EndStream` event.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StreamEvent {
+    /// A HEADERS frame was sent on the stream.
+    SendHeaders,
+    /// A HEADERS frame was received on the stream.
+    RecvHeaders,
+    /// A PUSH_PROMISE frame reserving the stream was sent.
+    SendPushPromise,
+    /// A PUSH_PROMISE frame reserving the stream was received.
+    RecvPushPromise,
+    /// A frame with the END_STREAM flag was sent on the stream.
+    SendEndStream,
+    /// A frame with the END_STREAM flag was received on the stream.
+    RecvEndStream,
+    /// A RST_STREAM frame was sent on the stream.
+    SendReset,
+    /// A RST_STREAM frame was received on the stream.
+    RecvReset,
+}
+
+/// The error returned when an event is not allowed to happen to a stream in its current state.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StreamStateError {
+    /// The state the stream was in.
+    pub state: StreamState,
+    /// The event that is illegal in that state.
+    pub event: StreamEvent,
+}
+
+impl fmt::Display for StreamStateError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "StreamStateError: {:?} in state {:?}", self.event, self.state)
+    }
+}
+
+impl Error for StreamStateError {
+    fn description(&self) -> &str {
+        "Illegal stream state transition"
+    }
+}
+
+impl StreamState {
+    /// Returns the state that a stream in this state moves to once the given event happens,
+    /// or an error if the event is not allowed in this state.
+    pub fn transition(self, event: StreamEvent) -> Result<StreamState, StreamStateError> {
+        use self::StreamState::*;
+        use self::StreamEvent::*;
+
+        let next = match (self, event) {
+            // Resetting a stream closes it from any state, except for idle streams, which cannot
+            // be reset.
+            (Idle, SendReset) | (Idle, RecvReset) => None,
+            (_, SendReset) | (_, RecvReset) => Some(Closed),
+
+            (Idle, SendHeaders) | (Idle, RecvHeaders) => Some(Open),
+            (Idle, SendPushPromise) => Some(ReservedLocal),
+            (Idle, RecvPushPromise) => Some(ReservedRemote),
+
+            (ReservedLocal, SendHeaders) => Some(HalfClosedRemote),
+            (ReservedRemote, RecvHeaders) => Some(HalfClosedLocal),
+
+            // Further HEADERS (i.e. trailers) are allowed in whichever direction is still open.
+            (Open, SendHeaders) | (Open, RecvHeaders) => Some(Open),
+            (Open, SendEndStream) => Some(HalfClosedLocal),
+            (Open, RecvEndStream) => Some(HalfClosedRemote),
+
+            (HalfClosedLocal, RecvHeaders) => Some(HalfClosedLocal),
+            (HalfClosedLocal, RecvEndStream) => Some(Closed),
+
+            (HalfClosedRemote, SendHeaders) => Some(HalfClosedRemote),
+            (HalfClosedRemote, SendEndStream) => Some(Closed),
+
+            _ => None,
+        };
+
+        next.ok_or(StreamStateError { state: self, event: event })
+    }
+
+    /// Returns whether the given event is allowed to happen to a stream in this state.
+    pub fn allows(self, event: StreamEvent) -> bool {
+        self.transition(event).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StreamStateError;
+
+    /// Tests that the `StreamState` transitions follow the stream lifecycle of the spec.
+    #[test]
+    fn test_stream_state_transitions() {
+        use super::StreamState::*;
+        use super::StreamEvent::*;
+
+        // A request with a body, followed by a response with a body.
+        assert_eq!(Idle.transition(SendHeaders), Ok(Open));
+        assert_eq!(Open.transition(SendEndStream), Ok(HalfClosedLocal));
+        assert_eq!(HalfClosedLocal.transition(RecvHeaders), Ok(HalfClosedLocal));
+        assert_eq!(HalfClosedLocal.transition(RecvEndStream), Ok(Closed));
+        // The server's view of the same exchange.
+        assert_eq!(Idle.transition(RecvHeaders), Ok(Open));
+        assert_eq!(Open.transition(RecvEndStream), Ok(HalfClosedRemote));
+        assert_eq!(HalfClosedRemote.transition(SendHeaders), Ok(HalfClosedRemote));
+        assert_eq!(HalfClosedRemote.transition(SendEndStream), Ok(Closed));
+        // Pushed streams
+        assert_eq!(Idle.transition(SendPushPromise), Ok(ReservedLocal));
+        assert_eq!(ReservedLocal.transition(SendHeaders), Ok(HalfClosedRemote));
+        assert_eq!(Idle.transition(RecvPushPromise), Ok(ReservedRemote));
+        assert_eq!(ReservedRemote.transition(RecvHeaders), Ok(HalfClosedLocal));
+        // Resets close any stream that isn't idle.
+        for &state in &[ReservedLocal, ReservedRemote, Open, HalfClosedLocal, HalfClosedRemote,
+                        Closed] {
+            assert_eq!(state.transition(SendReset), Ok(Closed));
+            assert_eq!(state.transition(RecvReset), Ok(Closed));
+        }
+    }
+
+    /// Tests that illegal events are rejected by the `StreamState` transitions.
+    #[test]
+    fn test_stream_state_illegal_transitions() {
+        use super::StreamState::*;
+        use super::StreamEvent::*;
+
+        let illegal = [
+            (Idle, SendEndStream),
+            (Idle, RecvEndStream),
+            (Idle, RecvReset),
+            (ReservedLocal, RecvHeaders),
+            (ReservedRemote, SendHeaders),
+            (HalfClosedLocal, SendHeaders),
+            (HalfClosedLocal, SendEndStream),
+            (HalfClosedRemote, RecvHeaders),
+            (HalfClosedRemote, RecvEndStream),
+            (Closed, SendHeaders),
+            (Closed, RecvEndStream),
+            (Open, RecvPushPromise),
+        ];
+        for &(state, event) in illegal.iter() {
+            assert_eq!(state.transition(event),
+                       Err(StreamStateError { state: state, event: event }));
+            assert!(!state.allows(event));
+        }
+    }
+}
@@ -5,16 +5,19 @@ use std::rc::Rc;
 use std::cell::{RefCell, Cell};
 use std::borrow::Cow;
 use std::io::{Cursor, Read, Write};
+use std::time::Duration;
+use std::collections::HashMap;
 
 use http::{
     HttpResult,
+    HttpError,
     HttpScheme,
     StreamId,
     Header,
     OwnedHeader,
     ErrorCode,
 };
-use http::frame::{RawFrame, FrameIR, FrameHeader, pack_header, HttpSetting};
+use http::frame::{RawFrame, FrameIR, FrameHeader, pack_header, HttpSetting, StreamDependency};
 use http::session::{
     Session,
     DefaultSessionState,
@@ -36,6 +39,7 @@ use http::connection::{
 };
 use http::client::ClientConnection;
 use http::server::StreamFactory;
+use http::validation::{MalformedHeaders, BodyLength};
 
 /// Creates a new `RawFrame` from two separate parts: the header and the payload.
 /// Useful for tests that need to create frames, since they can easily specify the header and the
@@ -49,9 +53,60 @@ pub fn raw_frame_from_parts<'a>(header: FrameHeader, payload: Vec<u8>) -> RawFra
 
 /// Serializes the given frame into a newly allocated vector (without consuming the frame).
 pub fn serialize_frame<F: FrameIR + Clone>(frame: &F) -> Vec<u8> {
-    let mut buf = io::Cursor::new(Vec::new());
-    frame.clone().serialize_into(&mut buf).ok().expect("Expected the serialization to succeed");
-    buf.into_inner()
+    ::http::frame::serialize_frame(frame.clone())
+}
+
+/// Splits the given buffer into the (owned) raw frames that it contains. Any trailing bytes that
+/// do not make up a full frame are ignored.
+pub fn parse_frames(buf: &[u8]) -> Vec<RawFrame<'static>> {
+    let mut frames = Vec::new();
+    let mut pos = 0;
+    while let Some(raw) = RawFrame::parse(&buf[pos..]) {
+        pos += raw.len();
+        frames.push(raw.serialize().into());
+    }
+    frames
+}
+
+/// The number of random cases that the property tests check.
+pub const PROPERTY_CASES: usize = 10_000;
+
+/// A deterministic pseudo-random number generator (xorshift64*) for property tests, so that any
+/// failing case can be reproduced from the seed of the test.
+pub struct Rng(u64);
+
+impl Rng {
+    /// Creates a generator with the given seed.
+    pub fn new(seed: u64) -> Rng {
+        // The all-zero state is the one state that xorshift never leaves.
+        Rng(if seed == 0 { 0x9e3779b97f4a7c15 } else { seed })
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    /// Returns a number in `0..n`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Returns a `u32` that is likely to be at (or close to) one of the boundaries that matter
+    /// for the protocol: 0, 2^31 - 1 and 2^32 - 1.
+    pub fn edgy_u32(&mut self) -> u32 {
+        match self.below(4) {
+            0 => [0, 1, 0x7ffffffe, 0x7fffffff, 0x80000000, 0xffffffff][self.below(6) as usize],
+            1 => self.below(0x20000) as u32,
+            _ => self.next_u32(),
+        }
+    }
 }
 
 /// A mock `SendFrame` implementation that simply saves all frames that it is to send to a `Vec`.
@@ -76,6 +131,8 @@ impl SendFrame for MockSendFrame {
 }
 
 /// A mock `ReceiveFrame` implementation that simply serves the frames from a `Vec`.
+///
+/// Once all frames are served, it behaves as if the peer closed the connection.
 pub struct MockReceiveFrame<'a> {
     pub recv_list: Vec<HttpFrame<'a>>,
 }
@@ -93,7 +150,7 @@ impl<'a> ReceiveFrame for MockReceiveFrame<'a> {
         if self.recv_list.len() != 0 {
             Ok(self.recv_list.remove(0))
         } else {
-            Err(io::Error::new(io::ErrorKind::Other, "End of Frame List").into())
+            Err(HttpError::PeerClosed { graceful: false })
         }
     }
 }
@@ -117,11 +174,16 @@ pub fn build_mock_http_conn() -> MockHttpConnection {
 ///
 /// It is possible to "close" the stream (both ends at once) so that
 /// aftwerwards any read or write attempt returns an `io::Error`;
+///
+/// If a read timeout is set, reading once the predefined bytes are exhausted
+/// returns a `WouldBlock` error (as if the timeout had elapsed), instead of
+/// an EOF.
 #[derive(Clone)]
 pub struct StubTransportStream {
     reader: Rc<RefCell<Cursor<Vec<u8>>>>,
     writer: Rc<RefCell<Cursor<Vec<u8>>>>,
     closed: Rc<Cell<bool>>,
+    read_timeout: Rc<Cell<Option<Duration>>>,
 }
 
 impl StubTransportStream {
@@ -132,9 +194,16 @@ impl StubTransportStream {
             reader: Rc::new(RefCell::new(Cursor::new(stub.to_vec()))),
             writer: Rc::new(RefCell::new(Cursor::new(Vec::new()))),
             closed: Rc::new(Cell::new(false)),
+            read_timeout: Rc::new(Cell::new(None)),
         }
     }
 
+    /// Appends the given bytes to the ones that are read from the stream, as
+    /// if the peer sent them after the ones that were already read.
+    pub fn append_content(&self, bytes: &[u8]) {
+        self.reader.borrow_mut().get_mut().extend_from_slice(bytes);
+    }
+
     /// Returns a slice representing the bytes already written to the
     /// stream.
     pub fn get_written(&self) -> Vec<u8> {
@@ -145,9 +214,13 @@ impl StubTransportStream {
 impl io::Read for StubTransportStream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         if self.closed.get() {
-            Err(io::Error::new(io::ErrorKind::Other, "Closed"))
+            return Err(io::Error::new(io::ErrorKind::Other, "Closed"));
+        }
+        let read = try!(self.reader.borrow_mut().read(buf));
+        if read == 0 && buf.len() != 0 && self.read_timeout.get().is_some() {
+            Err(io::Error::new(io::ErrorKind::WouldBlock, "Timed out"))
         } else {
-            self.reader.borrow_mut().read(buf)
+            Ok(read)
         }
     }
 }
@@ -177,6 +250,11 @@ impl TransportStream for StubTransportStream {
         self.closed.set(true);
         Ok(())
     }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.read_timeout.set(dur);
+        Ok(())
+    }
 }
 
 /// A helper function that builds a buffer of bytes from the given `Vec` of
@@ -187,10 +265,14 @@ pub fn build_stub_from_frames(frames: &Vec<HttpFrame>) -> Vec<u8> {
         let serialized = match *frame {
             HttpFrame::DataFrame(ref frame) => serialize_frame(frame),
             HttpFrame::HeadersFrame(ref frame) => serialize_frame(frame),
+            HttpFrame::PriorityFrame(ref frame) => serialize_frame(frame),
             HttpFrame::RstStreamFrame(ref frame) => serialize_frame(frame),
             HttpFrame::SettingsFrame(ref frame) => serialize_frame(frame),
             HttpFrame::GoawayFrame(ref frame) => serialize_frame(frame),
             HttpFrame::WindowUpdateFrame(ref frame) => serialize_frame(frame),
+            HttpFrame::PingFrame(ref frame) => serialize_frame(frame),
+            HttpFrame::ContinuationFrame(ref frame) => serialize_frame(frame),
+            HttpFrame::AltSvcFrame(ref frame) => serialize_frame(frame),
             HttpFrame::UnknownFrame(ref frame) => serialize_frame(frame),
         };
         buf.extend(serialized.into_iter());
@@ -269,6 +351,22 @@ pub struct TestSession {
     pub rst_streams: Vec<StreamId>,
     /// All the goaway error codes received.
     pub goaways: Vec<ErrorCode>,
+    /// All the window updates received, as (stream id, increment) pairs. Connection-level updates
+    /// are recorded with a stream id of 0.
+    pub window_updates: Vec<(StreamId, u32)>,
+    /// The states reported for streams. If a stream is not found in the map, no state is
+    /// reported for it, i.e. the connection does not check frames against its state.
+    pub stream_states: HashMap<StreamId, StreamState>,
+    /// All the stream errors signaled by the connection.
+    pub stream_errors: Vec<(StreamId, ErrorCode)>,
+    /// All the connection errors signaled by the connection.
+    pub connection_errors: Vec<ErrorCode>,
+    /// The priority information received with HEADERS and PRIORITY frames.
+    pub priorities: Vec<(StreamId, StreamDependency)>,
+    /// The PUSH_PROMISE frames received, as (stream id, promised stream id) pairs.
+    pub push_promises: Vec<(StreamId, StreamId)>,
+    /// The ALTSVC frames received, as (stream id, origin, field value) triples.
+    pub alt_svcs: Vec<(StreamId, Vec<u8>, Vec<u8>)>,
 }
 
 impl TestSession {
@@ -283,6 +381,13 @@ impl TestSession {
             curr_chunk: 0,
             rst_streams: Vec::new(),
             goaways: Vec::new(),
+            window_updates: Vec::new(),
+            stream_states: HashMap::new(),
+            stream_errors: Vec::new(),
+            connection_errors: Vec::new(),
+            priorities: Vec::new(),
+            push_promises: Vec::new(),
+            alt_svcs: Vec::new(),
         }
     }
 
@@ -299,6 +404,13 @@ impl TestSession {
                 curr_chunk: 0,
                 rst_streams: Vec::new(),
                 goaways: Vec::new(),
+                window_updates: Vec::new(),
+                stream_states: HashMap::new(),
+                stream_errors: Vec::new(),
+                connection_errors: Vec::new(),
+                priorities: Vec::new(),
+                push_promises: Vec::new(),
+                alt_svcs: Vec::new(),
             }
         }
 }
@@ -326,6 +438,27 @@ impl Session for TestSession {
         Ok(())
     }
 
+    fn new_priority(&mut self, stream_id: StreamId, dependency: StreamDependency,
+                    _: &mut HttpConnection)
+            -> HttpResult<()> {
+        self.priorities.push((stream_id, dependency));
+        Ok(())
+    }
+
+    fn new_push_promise(&mut self, stream_id: StreamId, promised_stream_id: StreamId,
+                        _: &mut HttpConnection)
+            -> HttpResult<()> {
+        self.push_promises.push((stream_id, promised_stream_id));
+        Ok(())
+    }
+
+    fn on_alt_svc(&mut self, stream_id: StreamId, origin: &[u8], field_value: &[u8],
+                  _: &mut HttpConnection)
+            -> HttpResult<()> {
+        self.alt_svcs.push((stream_id, origin.to_vec(), field_value.to_vec()));
+        Ok(())
+    }
+
     fn end_of_stream(&mut self, _: StreamId, _: &mut HttpConnection)
             -> HttpResult<()> {
         Ok(())
@@ -352,6 +485,34 @@ impl Session for TestSession {
         self.goaways.push(error_code);
         Ok(())
     }
+
+    fn window_update(&mut self, stream_id: StreamId, increment: u32, _: &mut HttpConnection)
+            -> HttpResult<()> {
+        self.window_updates.push((stream_id, increment));
+        Ok(())
+    }
+
+    fn connection_window_update(&mut self, increment: u32, _: &mut HttpConnection)
+            -> HttpResult<()> {
+        self.window_updates.push((0, increment));
+        Ok(())
+    }
+
+    fn stream_state(&self, stream_id: StreamId) -> Option<StreamState> {
+        self.stream_states.get(&stream_id).cloned()
+    }
+
+    fn stream_error(&mut self, stream_id: StreamId, error_code: ErrorCode, _: &mut HttpConnection)
+            -> HttpResult<()> {
+        self.stream_errors.push((stream_id, error_code));
+        Ok(())
+    }
+
+    fn connection_error(&mut self, error_code: ErrorCode, _: &mut HttpConnection)
+            -> HttpResult<()> {
+        self.connection_errors.push(error_code);
+        Ok(())
+    }
 }
 
 /// A stream that can be used for testing purposes.
@@ -361,6 +522,9 @@ pub struct TestStream {
     pub state: StreamState,
     pub outgoing: Option<Cursor<Vec<u8>>>,
     pub errors: Vec<ErrorCode>,
+    pub malformed: Vec<MalformedHeaders>,
+    pub length_mismatch: Option<BodyLength>,
+    pub blocked_for: Option<Duration>,
 }
 
 impl TestStream {
@@ -371,6 +535,9 @@ impl TestStream {
             state: StreamState::Open,
             outgoing: None,
             errors: Vec::new(),
+            malformed: Vec::new(),
+            length_mismatch: None,
+            blocked_for: None,
         }
     }
 
@@ -394,6 +561,17 @@ impl Stream for TestStream {
         self.errors.push(error);
         self.close();
     }
+    fn headers_received(&self) -> bool { self.headers.is_some() }
+    fn on_malformed_headers(&mut self, error: &MalformedHeaders) {
+        self.malformed.push(error.clone());
+    }
+    fn on_body_length_mismatch(&mut self, length: &BodyLength) {
+        self.length_mismatch = Some(*length);
+    }
+    fn buffered_len(&self) -> usize { self.body.len() }
+    fn on_slow_consumer(&mut self, blocked_for: Duration) {
+        self.blocked_for = Some(blocked_for);
+    }
     fn get_data_chunk(&mut self, buf: &mut [u8]) -> Result<StreamDataChunk, StreamDataError> {
         if self.is_closed_local() {
             return Err(StreamDataError::Closed);
@@ -0,0 +1,247 @@
+//! Replays captured HTTP/2 sessions through an `HttpConnection`.
+//!
+//! Each fixture in `tests/fixtures/` holds the raw bytes that a real client (curl, nghttp2, a
+//! browser...) sent to a server, starting with the connection preface. `replay` feeds them to a
+//! server-side `HttpConnection` and returns the events that it raised on its session, so that
+//! tests can assert on how the connection interprets real-world traffic. A regression test for an
+//! interop bug then only needs a capture of the offending session and the events it should yield.
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use http::{StreamId, Header, HttpResult, HttpError, HttpScheme, ErrorCode, CLIENT_PREFACE};
+use http::connection::HttpConnection;
+use http::frame::{HttpSetting, StreamDependency};
+use http::session::Session;
+use http::transport::TransportReceiveFrame;
+use http::tests::common::StubTransportStream;
+
+/// An event that the `HttpConnection` raised on its session while replaying a fixture.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Event {
+    Settings(Vec<HttpSetting>),
+    Headers(StreamId, Vec<(Vec<u8>, Vec<u8>)>),
+    Priority(StreamId, StreamDependency),
+    Data(StreamId, Vec<u8>),
+    EndOfStream(StreamId),
+    RstStream(StreamId, ErrorCode),
+    WindowUpdate(StreamId, u32),
+    Goaway(StreamId, ErrorCode),
+    StreamError(StreamId, ErrorCode),
+    ConnectionError(ErrorCode),
+}
+
+/// A `Session` that records every event raised on it. Connection-level window updates are
+/// recorded with a stream ID of 0.
+#[derive(Default)]
+pub struct RecordingSession {
+    pub events: Vec<Event>,
+}
+
+impl Session for RecordingSession {
+    fn new_data_chunk(&mut self, stream_id: StreamId, data: &[u8], _: &mut HttpConnection)
+            -> HttpResult<()> {
+        self.events.push(Event::Data(stream_id, data.to_vec()));
+        Ok(())
+    }
+
+    fn new_headers<'n, 'v>(
+            &mut self,
+            stream_id: StreamId,
+            headers: Vec<Header<'n, 'v>>,
+            _: &mut HttpConnection)
+            -> HttpResult<()> {
+        let headers = headers.iter().map(|h| (h.name().to_vec(), h.value().to_vec())).collect();
+        self.events.push(Event::Headers(stream_id, headers));
+        Ok(())
+    }
+
+    fn new_priority(&mut self, stream_id: StreamId, dependency: StreamDependency,
+                    _: &mut HttpConnection)
+            -> HttpResult<()> {
+        self.events.push(Event::Priority(stream_id, dependency));
+        Ok(())
+    }
+
+    fn end_of_stream(&mut self, stream_id: StreamId, _: &mut HttpConnection) -> HttpResult<()> {
+        self.events.push(Event::EndOfStream(stream_id));
+        Ok(())
+    }
+
+    fn rst_stream(&mut self, stream_id: StreamId, error_code: ErrorCode, _: &mut HttpConnection)
+            -> HttpResult<()> {
+        self.events.push(Event::RstStream(stream_id, error_code));
+        Ok(())
+    }
+
+    fn new_settings(&mut self, settings: Vec<HttpSetting>, _: &mut HttpConnection)
+            -> HttpResult<()> {
+        self.events.push(Event::Settings(settings));
+        Ok(())
+    }
+
+    fn on_goaway(
+            &mut self,
+            last_stream_id: StreamId,
+            error_code: ErrorCode,
+            _: Option<&[u8]>,
+            _: &mut HttpConnection)
+            -> HttpResult<()> {
+        self.events.push(Event::Goaway(last_stream_id, error_code));
+        Ok(())
+    }
+
+    fn window_update(&mut self, stream_id: StreamId, increment: u32, _: &mut HttpConnection)
+            -> HttpResult<()> {
+        self.events.push(Event::WindowUpdate(stream_id, increment));
+        Ok(())
+    }
+
+    fn connection_window_update(&mut self, increment: u32, _: &mut HttpConnection)
+            -> HttpResult<()> {
+        self.events.push(Event::WindowUpdate(0, increment));
+        Ok(())
+    }
+
+    fn stream_error(&mut self, stream_id: StreamId, error_code: ErrorCode, _: &mut HttpConnection)
+            -> HttpResult<()> {
+        self.events.push(Event::StreamError(stream_id, error_code));
+        Ok(())
+    }
+
+    fn connection_error(&mut self, error_code: ErrorCode, _: &mut HttpConnection)
+            -> HttpResult<()> {
+        self.events.push(Event::ConnectionError(error_code));
+        Ok(())
+    }
+}
+
+/// Reads the fixture with the given file name from `tests/fixtures/`.
+pub fn load(name: &str) -> Vec<u8> {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("tests");
+    path.push("fixtures");
+    path.push(name);
+    let mut buf = Vec::new();
+    File::open(&path).and_then(|mut file| file.read_to_end(&mut buf))
+                     .unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+    buf
+}
+
+/// Replays the fixture with the given file name through a new server-side `HttpConnection`,
+/// returning the events that it raised, along with the error that stopped the connection, if
+/// any other than the end of the fixture.
+pub fn replay(name: &str) -> (Vec<Event>, Option<HttpError>) {
+    let bytes = load(name);
+    assert!(bytes.starts_with(CLIENT_PREFACE), "{} does not start with the preface", name);
+    let mut stream = StubTransportStream::with_stub_content(&bytes[CLIENT_PREFACE.len()..]);
+    let mut conn = HttpConnection::new(HttpScheme::Http);
+    // The fixtures were captured against a server whose SETTINGS frame the client acknowledges.
+    conn.settings_sent(&[]);
+    let mut session = RecordingSession::default();
+
+    let error = loop {
+        let mut rx = TransportReceiveFrame::new(&mut stream);
+        match conn.handle_next_frame(&mut rx, &mut session) {
+            Ok(()) => {},
+            // The fixture is exhausted.
+            Err(HttpError::PeerClosed { .. }) => break None,
+            Err(e) => break Some(e),
+        }
+    };
+    (session.events, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, replay};
+    use http::frame::HttpSetting;
+
+    /// Returns the given header list as owned pairs.
+    fn headers(list: &[(&str, &str)]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        list.iter().map(|&(n, v)| (n.as_bytes().to_vec(), v.as_bytes().to_vec())).collect()
+    }
+
+    /// The initial settings and window update that curl 7.88 (nghttp2 1.52) sends.
+    fn curl_preamble() -> Vec<Event> {
+        vec![
+            Event::Settings(vec![
+                HttpSetting::MaxConcurrentStreams(100),
+                HttpSetting::InitialWindowSize(33_554_432),
+                HttpSetting::EnablePush(0),
+            ]),
+            Event::WindowUpdate(0, 33_488_897),
+        ]
+    }
+
+    /// A GET request from curl, with `--http2-prior-knowledge`.
+    #[test]
+    fn test_fixture_curl_get() {
+        let (events, error) = replay("curl_get.bin");
+
+        assert!(error.is_none(), "{:?}", error);
+        let mut expected = curl_preamble();
+        expected.push(Event::Headers(1, headers(&[
+            (":method", "GET"),
+            (":path", "/index.html"),
+            (":scheme", "http"),
+            (":authority", "127.0.0.1:18081"),
+            ("user-agent", "curl/7.88.1"),
+            ("accept", "text/html"),
+        ])));
+        expected.push(Event::EndOfStream(1));
+        // The ACK of the capturing server's SETTINGS raises no event.
+        assert_eq!(events, expected);
+    }
+
+    /// A form POST from curl, whose body follows in a separate DATA frame.
+    #[test]
+    fn test_fixture_curl_post() {
+        let (events, error) = replay("curl_post.bin");
+
+        assert!(error.is_none(), "{:?}", error);
+        let mut expected = curl_preamble();
+        expected.push(Event::Headers(1, headers(&[
+            (":method", "POST"),
+            (":path", "/submit"),
+            (":scheme", "http"),
+            (":authority", "127.0.0.1:18082"),
+            ("user-agent", "curl/7.88.1"),
+            ("accept", "*/*"),
+            ("content-length", "22"),
+            ("content-type", "application/x-www-form-urlencoded"),
+        ])));
+        expected.push(Event::Data(1, b"name=solicit&lang=rust".to_vec()));
+        expected.push(Event::EndOfStream(1));
+        assert_eq!(events, expected);
+    }
+
+    /// A GET request from nghttp (nghttp2 1.67), which announces that it does not use the RFC 7540
+    /// priority scheme (a setting that the connection does not know and skips) and sends the
+    /// RFC 9218 `priority` header instead.
+    #[test]
+    fn test_fixture_nghttp_get() {
+        let (events, error) = replay("nghttp_get.bin");
+
+        assert!(error.is_none(), "{:?}", error);
+        assert_eq!(events, vec![
+            Event::Settings(vec![
+                HttpSetting::MaxConcurrentStreams(100),
+                HttpSetting::InitialWindowSize(65_535),
+                // SETTINGS_NO_RFC7540_PRIORITIES (RFC 9218)
+                HttpSetting::Unknown(9, 1),
+            ]),
+            Event::Headers(1, headers(&[
+                (":method", "GET"),
+                (":path", "/"),
+                (":scheme", "http"),
+                (":authority", "127.0.0.1:18083"),
+                ("priority", "u=3"),
+                ("accept", "*/*"),
+                ("accept-encoding", "gzip, deflate"),
+                ("user-agent", "nghttp2/1.67.1"),
+            ])),
+            Event::EndOfStream(1),
+        ]);
+    }
+}
@@ -1,10 +1,15 @@
 #[cfg(test)]
 pub mod common;
+#[cfg(test)]
+pub mod fixtures;
+#[cfg(test)]
+mod properties;
 
 /// Tests for the structs defined in the root of the `solicit::http` module.
 #[cfg(test)]
 mod root_tests {
-    use http::{Response, HttpError, HttpScheme, ErrorCode, ConnectionError};
+    use http::{Response, HttpError, HttpScheme, ErrorCode, ConnectionError, ConnectionId};
+    use http::{Header, PseudoHeaders, PrefaceMismatch};
     use std::error::Error;
 
     /// Tests that the `Response` struct correctly parses a status code from
@@ -46,6 +51,58 @@ mod root_tests {
         }
     }
 
+    /// Tests that the `PseudoHeaders` are extracted from the leading pseudo-header fields only.
+    #[test]
+    fn test_parse_pseudo_headers() {
+        {
+            // A request
+            let headers = vec![
+                Header::new(b":method", b"GET"),
+                Header::new(b":path", b"/index"),
+                Header::new(b":authority", &b"example.com"[..]),
+                Header::new(b":scheme", b"https"),
+                Header::new(b"accept", b"*/*"),
+            ];
+            let pseudo = PseudoHeaders::parse(&headers);
+            assert_eq!(pseudo, PseudoHeaders {
+                status: None,
+                method: Some(b"GET".to_vec()),
+                path: Some(b"/index".to_vec()),
+                authority: Some(b"example.com".to_vec()),
+                scheme: Some(b"https".to_vec()),
+            });
+        }
+        {
+            // A response
+            let headers = vec![Header::new(b":status", b"404")];
+            assert_eq!(PseudoHeaders::parse(&headers).status, Some(404));
+        }
+        {
+            // An invalid status
+            let headers = vec![Header::new(b":status", b"20")];
+            assert_eq!(PseudoHeaders::parse(&headers).status, None);
+        }
+        {
+            // Pseudo-headers after regular headers are not considered.
+            let headers = vec![
+                Header::new(b"accept", b"*/*"),
+                Header::new(b":method", b"GET"),
+            ];
+            assert_eq!(PseudoHeaders::parse(&headers), PseudoHeaders::default());
+        }
+    }
+
+    /// Tests that the trailers of a `Response` can be looked up by name.
+    #[test]
+    fn test_response_trailer() {
+        let mut resp = Response::new(1, vec![(b":status".to_vec(), b"200".to_vec())], vec![]);
+        assert_eq!(resp.trailer(b"grpc-status"), None);
+
+        resp.trailers.push(Header::new(b"grpc-status".to_vec(), b"0".to_vec()));
+        assert_eq!(resp.trailer(b"grpc-status"), Some(&b"0"[..]));
+        assert_eq!(resp.trailer(b"grpc-message"), None);
+    }
+
     #[test]
     fn test_connection_error_no_debug_data() {
         let err = ConnectionError::new(ErrorCode::ProtocolError);
@@ -73,81 +130,75 @@ mod root_tests {
         assert_eq!(err.description(), "Test");
     }
 
-    /// Tests that the `HttpScheme` enum returns the correct scheme strings for
-    /// the two variants.
-    #[test]
-    fn test_scheme_string() {
-        assert_eq!(HttpScheme::Http.as_bytes(), b"http");
-        assert_eq!(HttpScheme::Https.as_bytes(), b"https");
-    }
-
-    /// Make sure that the `HttpError` is both `Sync` and `Send`
+    /// Tests that a `ConnectionError` can be attributed to a connection, which shows in its
+    /// `Display` output.
     #[test]
-    fn _assert_error_is_sync_send() {
-        fn _is_sync_send<T: Sync + Send>() {}
-        _is_sync_send::<HttpError>();
-    }
-}
-
-#[cfg(test)]
-mod test_header {
-    use http::{Header, OwnedHeader};
-    use std::borrow::Cow;
-
-    fn _assert_is_static(_: Header<'static, 'static>) {}
+    fn test_connection_error_connection_id() {
+        let err = ConnectionError::new(ErrorCode::ProtocolError);
+        assert_eq!(err.connection_id(), None);
+        assert_eq!(err.to_string(), "ConnectionError: PROTOCOL_ERROR (0x1)");
 
-    #[test]
-    fn test_owned_to_header_is_static_lifetime() {
-        let owned = (vec![1u8], vec![2u8]);
-        _assert_is_static(owned.into());
+        let err = err.on_connection(ConnectionId(7));
+        assert_eq!(err.connection_id(), Some(ConnectionId(7)));
+        assert_eq!(err.error_code(), ErrorCode::ProtocolError);
+        assert_eq!(err.to_string(), "ConnectionError: PROTOCOL_ERROR (0x1) (on conn-7)");
     }
 
+    /// Tests that every new `ConnectionId` is different from the previous ones.
     #[test]
-    fn test_header_from_static_slices_lifetime() {
-        let header = Header::new(b":method", b"GET");
-        _assert_is_static(header);
+    fn test_connection_id_next() {
+        let first = ConnectionId::next();
+        let second = ConnectionId::next();
+        assert!(second > first);
+        assert_eq!(first.to_string(), format!("conn-{}", first.0));
     }
 
+    /// Tests that error codes convert from and to their numeric values and are shown by the names
+    /// that the spec gives them.
     #[test]
-    fn test_header_to_owned_header() {
-        let header = Header::new(b":method", b"GET");
-        let (name, value): OwnedHeader = header.into();
-
-        assert_eq!(name, b":method".to_vec());
-        assert_eq!(value, b"GET".to_vec());
+    fn test_error_code_names() {
+        assert_eq!(ErrorCode::from_u32(0xd), Some(ErrorCode::Http11Required));
+        assert_eq!(ErrorCode::from_u32(0xe), None);
+        assert_eq!(ErrorCode::from(0xe), ErrorCode::InternalError);
+        let code: u32 = ErrorCode::EnhanceYourCalm.into();
+        assert_eq!(code, 0xb);
+        assert_eq!(ErrorCode::Http11Required.name(), "HTTP_1_1_REQUIRED");
+        assert_eq!(ErrorCode::ProtocolError.to_string(), "PROTOCOL_ERROR (0x1)");
+
+        let err = HttpError::LocalConnectionError(ErrorCode::FlowControlError);
+        assert_eq!(err.to_string(), "HTTP/2 Error: Connection error FLOW_CONTROL_ERROR (0x3)");
+        let err = ConnectionError::with_debug_data(ErrorCode::NoError, b"bye".to_vec());
+        assert_eq!(HttpError::PeerConnectionError(err).to_string(),
+                   "HTTP/2 Error: ConnectionError: NO_ERROR (0x0): bye");
     }
 
+    /// Tests that the `NotHttp2` error shows what the peer sent instead of the preface.
     #[test]
-    fn test_partial_eq_of_headers() {
-        let fully_static = Header::new(b":method", b"GET");
-        let static_name = Header::new(b":method", b"GET".to_vec());
-        let other = Header::new(b":path", b"/");
-
-        assert!(fully_static == static_name);
-        assert!(fully_static != other);
-        assert!(static_name != other);
+    fn test_not_http2_error_display() {
+        let err = HttpError::NotHttp2(PrefaceMismatch {
+            received: b"HTTP/1.1 505 HTTP Version Not Supported\r\n".to_vec(),
+        });
+        assert_eq!(err.to_string(),
+                   "HTTP/2 Error: The peer responded with HTTP/1.x instead of HTTP/2: \
+                    \"HTTP/1.1 505 HTTP Version Not Supported\\r\\n\"");
+
+        let err = HttpError::NotHttp2(PrefaceMismatch { received: b"<html>".to_vec() });
+        assert_eq!(err.to_string(),
+                   "HTTP/2 Error: The peer did not send an HTTP/2 preface, received: \"<html>\"");
     }
 
+    /// Tests that the `HttpScheme` enum returns the correct scheme strings for
+    /// the two variants.
     #[test]
-    fn test_partial_eq_to_owned_header() {
-        let fully_static = Header::new(b":method", b"GET");
-        let owned: OwnedHeader = fully_static.clone().into();
-
-        assert!(fully_static == owned);
+    fn test_scheme_string() {
+        assert_eq!(HttpScheme::Http.as_bytes(), b"http");
+        assert_eq!(HttpScheme::Https.as_bytes(), b"https");
     }
 
+    /// Make sure that the `HttpError` is both `Sync` and `Send`
     #[test]
-    fn test_clone_keeps_borrows() {
-        let header = Header::new(b":method", b"GET");
-        let clone = header.clone();
-
-        match clone.name {
-            Cow::Owned(_) => panic!("Expected a borrowed name"),
-            _ => {},
-        };
-        match clone.value {
-            Cow::Owned(_) => panic!("Expected a borrowed value"),
-            _ => {},
-        };
+    fn _assert_error_is_sync_send() {
+        fn _is_sync_send<T: Sync + Send>() {}
+        _is_sync_send::<HttpError>();
     }
 }
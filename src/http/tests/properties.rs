@@ -0,0 +1,210 @@
+//! Property tests for the frames: random valid frames of every type have to survive a round trip
+//! through serialization and parsing unchanged, while random bytes (and random frames with a
+//! valid header, but random payloads) have to be either parsed or rejected, but never make the
+//! parsers panic.
+//!
+//! The frames are generated by a seeded `Rng`, so a failure can be reproduced by running the test
+//! again; the failing frame is part of the assertion message.
+
+use http::StreamId;
+use http::connection::HttpFrame;
+use http::frame::{
+    Frame,
+    RawFrame,
+    DataFrame, DataFlag,
+    HeadersFrame, HeadersFlag, StreamDependency,
+    PriorityFrame,
+    ContinuationFrame, ContinuationFlag,
+    RstStreamFrame,
+    SettingsFrame, HttpSetting,
+    GoawayFrame,
+    WindowUpdateFrame,
+    PingFrame,
+    AltSvcFrame,
+};
+use http::tests::common::{Rng, PROPERTY_CASES, serialize_frame, raw_frame_from_parts};
+
+/// Serializes the given frame, parses it back both as its own type and as an `HttpFrame`, and
+/// asserts that both give back the original frame.
+macro_rules! assert_round_trip {
+    ($frame:expr, $ty:ident) => {{
+        let frame = $frame;
+        let buf = serialize_frame(&frame);
+        let raw = RawFrame::parse(&buf).expect("Expected a complete raw frame");
+        assert_eq!(raw.len(), buf.len(), "Trailing bytes after {:?}", frame);
+        let parsed: Option<$ty> = Frame::from_raw(&raw);
+        assert_eq!(parsed.as_ref(), Some(&frame));
+        match HttpFrame::from_raw(&raw) {
+            Ok(HttpFrame::$ty(parsed)) => assert_eq!(parsed, frame),
+            other => panic!("Expected {:?}, got {:?}", frame, other),
+        }
+    }}
+}
+
+/// Returns a valid ID of a stream (i.e. not 0).
+fn stream_id(rng: &mut Rng) -> StreamId {
+    1 + rng.below(0x7fffffff) as StreamId
+}
+
+/// Returns up to `max_len` random bytes.
+fn bytes(rng: &mut Rng, max_len: u64) -> Vec<u8> {
+    let len = rng.below(max_len + 1) as usize;
+    (0..len).map(|_| rng.next_u32() as u8).collect()
+}
+
+fn coin(rng: &mut Rng) -> bool {
+    rng.below(2) == 0
+}
+
+fn data_frame(rng: &mut Rng) -> DataFrame<'static> {
+    let mut frame = DataFrame::with_data(stream_id(rng), bytes(rng, 512));
+    if coin(rng) {
+        frame.set_flag(DataFlag::EndStream);
+    }
+    if coin(rng) {
+        frame.set_padding(rng.next_u32() as u8);
+    }
+    frame
+}
+
+fn headers_frame(rng: &mut Rng) -> HeadersFrame<'static> {
+    let fragment = bytes(rng, 512);
+    let mut frame = if coin(rng) {
+        let dep = StreamDependency::new(rng.below(0x80000000) as StreamId,
+                                        rng.next_u32() as u8,
+                                        coin(rng));
+        HeadersFrame::with_dependency(fragment, stream_id(rng), dep)
+    } else {
+        HeadersFrame::new(fragment, stream_id(rng))
+    };
+    if coin(rng) {
+        frame.set_flag(HeadersFlag::EndStream);
+    }
+    if coin(rng) {
+        frame.set_flag(HeadersFlag::EndHeaders);
+    }
+    if coin(rng) {
+        frame.set_padding(rng.next_u32() as u8);
+    }
+    frame
+}
+
+fn priority_frame(rng: &mut Rng) -> PriorityFrame {
+    let dep = StreamDependency::new(rng.below(0x80000000) as StreamId,
+                                    rng.next_u32() as u8,
+                                    coin(rng));
+    PriorityFrame::new(stream_id(rng), dep)
+}
+
+fn continuation_frame(rng: &mut Rng) -> ContinuationFrame<'static> {
+    let mut frame = ContinuationFrame::new(bytes(rng, 512), stream_id(rng));
+    if coin(rng) {
+        frame.set_flag(ContinuationFlag::EndHeaders);
+    }
+    frame
+}
+
+fn settings_frame(rng: &mut Rng) -> SettingsFrame {
+    if rng.below(4) == 0 {
+        return SettingsFrame::new_ack();
+    }
+    let mut frame = SettingsFrame::new();
+    for _ in 0..rng.below(8) {
+        // Includes the ids of a couple of unknown settings.
+        let id = 1 + rng.below(8) as u16;
+        frame.add_setting(HttpSetting::from_id(id, rng.edgy_u32()));
+    }
+    frame
+}
+
+fn window_update_frame(rng: &mut Rng) -> WindowUpdateFrame {
+    let increment = 1 + rng.below(0x7fffffff) as u32;
+    if coin(rng) {
+        WindowUpdateFrame::for_connection(increment)
+    } else {
+        WindowUpdateFrame::for_stream(stream_id(rng), increment)
+    }
+}
+
+fn ping_frame(rng: &mut Rng) -> PingFrame {
+    if coin(rng) {
+        PingFrame::new_ack(rng.next_u64())
+    } else {
+        PingFrame::new(rng.next_u64())
+    }
+}
+
+/// Parses the given raw frame as every type of frame, only making sure that nothing panics.
+fn parse_as_any(raw: &RawFrame) {
+    let _ = HttpFrame::from_raw(raw);
+    let _: Option<DataFrame> = Frame::from_raw(raw);
+    let _: Option<HeadersFrame> = Frame::from_raw(raw);
+    let _: Option<PriorityFrame> = Frame::from_raw(raw);
+    let _: Option<ContinuationFrame> = Frame::from_raw(raw);
+    let _: Option<RstStreamFrame> = Frame::from_raw(raw);
+    let _: Option<SettingsFrame> = Frame::from_raw(raw);
+    let _: Option<GoawayFrame> = Frame::from_raw(raw);
+    let _: Option<WindowUpdateFrame> = Frame::from_raw(raw);
+    let _: Option<PingFrame> = Frame::from_raw(raw);
+    let _: Option<AltSvcFrame> = Frame::from_raw(raw);
+}
+
+/// Tests that random valid frames of every type are parsed back into the same frames.
+#[test]
+fn test_frame_round_trip_property() {
+    let mut rng = Rng::new(2236);
+    for _ in 0..PROPERTY_CASES {
+        assert_round_trip!(data_frame(&mut rng), DataFrame);
+        assert_round_trip!(headers_frame(&mut rng), HeadersFrame);
+        assert_round_trip!(priority_frame(&mut rng), PriorityFrame);
+        assert_round_trip!(continuation_frame(&mut rng), ContinuationFrame);
+        assert_round_trip!(RstStreamFrame::with_raw_error_code(stream_id(&mut rng),
+                                                               rng.edgy_u32()),
+                           RstStreamFrame);
+        assert_round_trip!(settings_frame(&mut rng), SettingsFrame);
+        let debug_data = bytes(&mut rng, 64);
+        assert_round_trip!(GoawayFrame::with_debug_data(rng.below(0x80000000) as StreamId,
+                                                        rng.edgy_u32(),
+                                                        &debug_data),
+                           GoawayFrame);
+        assert_round_trip!(GoawayFrame::with_raw_error_code(rng.below(0x80000000) as StreamId,
+                                                            rng.edgy_u32()),
+                           GoawayFrame);
+        assert_round_trip!(window_update_frame(&mut rng), WindowUpdateFrame);
+        assert_round_trip!(ping_frame(&mut rng), PingFrame);
+        let origin = bytes(&mut rng, 64);
+        let field_value = bytes(&mut rng, 64);
+        assert_round_trip!(AltSvcFrame::for_origin(&origin, &field_value), AltSvcFrame);
+        assert_round_trip!(AltSvcFrame::for_stream(stream_id(&mut rng), &field_value),
+                           AltSvcFrame);
+    }
+}
+
+/// Tests that the parsers never panic on random bytes.
+#[test]
+fn test_parse_random_bytes_property() {
+    let mut rng = Rng::new(4);
+    for _ in 0..PROPERTY_CASES {
+        let buf = bytes(&mut rng, 64);
+        if let Some(raw) = RawFrame::parse(&buf) {
+            parse_as_any(&raw);
+        }
+    }
+}
+
+/// Tests that the parsers never panic on frames that have a well-formed header of a known type,
+/// but random flags, stream IDs and payloads, which exercise the payload parsers a lot more than
+/// entirely random bytes would.
+#[test]
+fn test_parse_random_payloads_property() {
+    let mut rng = Rng::new(41);
+    for _ in 0..PROPERTY_CASES {
+        let payload = bytes(&mut rng, 64);
+        let header = (payload.len() as u32,
+                      rng.below(0xb) as u8,
+                      rng.next_u32() as u8,
+                      if coin(&mut rng) { 0 } else { rng.next_u32() });
+        let raw = raw_frame_from_parts(header, payload);
+        parse_as_any(&raw);
+    }
+}
@@ -0,0 +1,558 @@
+//! The module implements the tracing of connections and streams: each connection, and each
+//! request made or served over one, is reported as a `Span` once it is over, carrying the
+//! attributes that distributed tracing systems expect (the method, path and status of requests,
+//! the bytes exchanged and the durations).
+//!
+//! Spans are handed to the `SpanSink` set by `set_sink`, which can pass them on to the tracing
+//! system of the application (e.g. an OpenTelemetry exporter) or simply log them (`LogSink`).
+//! The connections, the `SimpleServer` and the async `Client` report their spans on their own,
+//! so no manual instrumentation is needed.
+//!
+//! The tracing is only performed when the crate is built with the `tracing` feature. Without it,
+//! setting a sink has no effect and no spans are ever recorded, so that the tracing costs nothing
+//! when it is not used.
+//!
+//! The trace context of a request, carried by the W3C `traceparent` and `tracestate` headers, is
+//! represented by a `TraceContext`. The async `Client` can generate and propagate it on all of
+//! its requests (see `Client::set_trace_propagation`), while servers can extract it from the
+//! requests that they handle (see `ServerRequest::trace_context`). Either way, the context is
+//! attached to the span of the request. Propagating trace contexts does not require the
+//! `tracing` feature.
+use std::fmt;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature="tracing")]
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use http::{ConnectionId, StreamId, Header, StaticHeader};
+
+/// The name of the header that carries the trace and parent IDs of a request.
+pub const TRACEPARENT: &'static [u8] = b"traceparent";
+/// The name of the header that carries vendor-specific trace information.
+pub const TRACESTATE: &'static [u8] = b"tracestate";
+
+/// The trace context of a request, as defined by the W3C Trace Context recommendation.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TraceContext {
+    /// The ID of the whole trace.
+    pub trace_id: [u8; 16],
+    /// The ID of the span that the request is made from (the `parent-id` of the header).
+    pub parent_id: [u8; 8],
+    /// The trace flags; the lowest bit tells whether the trace is sampled.
+    pub flags: u8,
+    /// The value of the `tracestate` header(s), if any, which is propagated unchanged.
+    pub tracestate: Option<Vec<u8>>,
+}
+
+impl TraceContext {
+    /// Starts a new, sampled trace, with random trace and parent IDs.
+    pub fn new() -> TraceContext {
+        let mut trace_id = [0; 16];
+        trace_id[..8].copy_from_slice(&random_id());
+        trace_id[8..].copy_from_slice(&random_id());
+        TraceContext {
+            trace_id: trace_id,
+            parent_id: random_id(),
+            flags: 0x01,
+            tracestate: None,
+        }
+    }
+
+    /// Returns the context to propagate on a request made as part of this trace: the trace ID,
+    /// flags and state are kept, while the parent ID is replaced by a new, random one.
+    pub fn child(&self) -> TraceContext {
+        TraceContext {
+            trace_id: self.trace_id,
+            parent_id: random_id(),
+            flags: self.flags,
+            tracestate: self.tracestate.clone(),
+        }
+    }
+
+    /// Returns whether the trace is sampled, i.e. whether the caller may have recorded it.
+    pub fn is_sampled(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+
+    /// Parses the value of a `traceparent` header. Returns `None` if the value is invalid, in
+    /// which case the spec requires the header to be ignored.
+    ///
+    /// Values of versions newer than `00` are parsed as far as version `00` goes, as the spec
+    /// requires.
+    pub fn parse(traceparent: &[u8]) -> Option<TraceContext> {
+        if traceparent.len() < 55 {
+            return None;
+        }
+        let mut version = [0; 1];
+        if !parse_hex(&traceparent[..2], &mut version) || version[0] == 0xff {
+            return None;
+        }
+        if version[0] == 0 && traceparent.len() != 55 {
+            return None;
+        }
+        if traceparent.len() > 55 && traceparent[55] != b'-' {
+            return None;
+        }
+        if traceparent[2] != b'-' || traceparent[35] != b'-' || traceparent[52] != b'-' {
+            return None;
+        }
+        let mut trace_id = [0; 16];
+        let mut parent_id = [0; 8];
+        let mut flags = [0; 1];
+        if !parse_hex(&traceparent[3..35], &mut trace_id) ||
+                !parse_hex(&traceparent[36..52], &mut parent_id) ||
+                !parse_hex(&traceparent[53..55], &mut flags) {
+            return None;
+        }
+        // All-zero IDs are invalid.
+        if trace_id == [0; 16] || parent_id == [0; 8] {
+            return None;
+        }
+
+        Some(TraceContext {
+            trace_id: trace_id,
+            parent_id: parent_id,
+            flags: flags[0],
+            tracestate: None,
+        })
+    }
+
+    /// Extracts the trace context from the given headers, if they carry a valid `traceparent`
+    /// header. Multiple `tracestate` headers are combined into one value.
+    pub fn from_headers(headers: &[Header]) -> Option<TraceContext> {
+        let mut context = match headers.iter().find(|h| h.name() == TRACEPARENT) {
+            Some(header) => match TraceContext::parse(header.value()) {
+                Some(context) => context,
+                None => return None,
+            },
+            None => return None,
+        };
+        for header in headers.iter().filter(|h| h.name() == TRACESTATE) {
+            let state = context.tracestate.get_or_insert_with(Vec::new);
+            if !state.is_empty() {
+                state.push(b',');
+            }
+            state.extend_from_slice(header.value());
+        }
+        Some(context)
+    }
+
+    /// Returns the value of the `traceparent` header that carries the context.
+    pub fn traceparent(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+
+    /// Returns the headers that carry the context: the `traceparent` and, if there is any state,
+    /// the `tracestate`.
+    pub fn to_headers(&self) -> Vec<StaticHeader> {
+        let mut headers = vec![Header::new(TRACEPARENT.to_vec(), self.traceparent())];
+        if let Some(ref state) = self.tracestate {
+            headers.push(Header::new(TRACESTATE.to_vec(), state.clone()));
+        }
+        headers
+    }
+
+    /// Replaces any trace context headers among the given headers with the ones that carry this
+    /// context.
+    pub fn apply_to(&self, headers: &mut Vec<StaticHeader>) {
+        headers.retain(|h| h.name() != TRACEPARENT && h.name() != TRACESTATE);
+        headers.extend(self.to_headers());
+    }
+}
+
+/// The context is shown as the value of its `traceparent` header.
+impl fmt::Display for TraceContext {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(fmt, "00-"));
+        try!(write_hex(fmt, &self.trace_id));
+        try!(write!(fmt, "-"));
+        try!(write_hex(fmt, &self.parent_id));
+        write!(fmt, "-{:02x}", self.flags)
+    }
+}
+
+/// Writes the given bytes as lowercase hex digits.
+fn write_hex(fmt: &mut fmt::Formatter, bytes: &[u8]) -> fmt::Result {
+    for byte in bytes {
+        try!(write!(fmt, "{:02x}", byte));
+    }
+    Ok(())
+}
+
+/// Parses the given lowercase hex digits into the given buffer, which they need to fill exactly.
+/// Returns whether they could be parsed.
+fn parse_hex(digits: &[u8], out: &mut [u8]) -> bool {
+    fn value(digit: u8) -> Option<u8> {
+        match digit {
+            b'0'..=b'9' => Some(digit - b'0'),
+            b'a'..=b'f' => Some(digit - b'a' + 10),
+            _ => None,
+        }
+    }
+    if digits.len() != 2 * out.len() {
+        return false;
+    }
+    for (byte, pair) in out.iter_mut().zip(digits.chunks(2)) {
+        match (value(pair[0]), value(pair[1])) {
+            (Some(high), Some(low)) => *byte = high << 4 | low,
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Makes the random IDs of different calls differ even if the hasher keys happen to repeat.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns a random, non-zero 8-byte ID. The randomness comes from the randomly keyed hasher of
+/// the standard library: good enough for IDs, but not cryptographically secure.
+fn random_id() -> [u8; 8] {
+    loop {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        let id = hasher.finish();
+        if id != 0 {
+            let mut buf = [0; 8];
+            for (i, byte) in buf.iter_mut().enumerate() {
+                *byte = (id >> (56 - 8 * i)) as u8;
+            }
+            return buf;
+        }
+    }
+}
+
+/// The side of the connection that a span was recorded on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+/// What a span covers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SpanKind {
+    /// The whole lifetime of a connection.
+    Connection,
+    /// A single request and its response, exchanged on a stream.
+    Stream,
+}
+
+/// A finished span, along with its attributes.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Span {
+    pub kind: SpanKind,
+    /// The side of the connection, if known. Connections used directly through an
+    /// `HttpConnection`, rather than as a client or server connection, have none.
+    pub role: Option<Role>,
+    pub connection_id: ConnectionId,
+    /// The stream of a `Stream` span.
+    pub stream_id: Option<StreamId>,
+    /// The method of the request of a `Stream` span.
+    pub method: Option<Vec<u8>>,
+    /// The path of the request of a `Stream` span.
+    pub path: Option<Vec<u8>>,
+    /// The status of the response of a `Stream` span, if one was exchanged.
+    pub status: Option<u16>,
+    /// The trace context of the request of a `Stream` span, if it carried one: the one that was
+    /// sent by a client, or received by a server.
+    pub trace: Option<TraceContext>,
+    /// The number of body bytes sent, in DATA frames.
+    pub bytes_sent: u64,
+    /// The number of body bytes received, in DATA frames.
+    pub bytes_received: u64,
+    pub started_at: Instant,
+    /// How long the span lasted; only known once it is finished.
+    pub duration: Duration,
+}
+
+impl Span {
+    /// Starts the span of a connection.
+    pub fn connection(role: Option<Role>, connection_id: ConnectionId) -> Span {
+        Span::new(SpanKind::Connection, role, connection_id, None)
+    }
+
+    /// Starts the span of a request exchanged on the given stream.
+    pub fn stream(role: Role, connection_id: ConnectionId, stream_id: StreamId) -> Span {
+        Span::new(SpanKind::Stream, Some(role), connection_id, Some(stream_id))
+    }
+
+    fn new(kind: SpanKind, role: Option<Role>, connection_id: ConnectionId,
+           stream_id: Option<StreamId>)
+            -> Span {
+        Span {
+            kind: kind,
+            role: role,
+            connection_id: connection_id,
+            stream_id: stream_id,
+            method: None,
+            path: None,
+            status: None,
+            trace: None,
+            bytes_sent: 0,
+            bytes_received: 0,
+            started_at: Instant::now(),
+            duration: Duration::from_secs(0),
+        }
+    }
+
+    /// Returns the name of the span, following the kind and the role, e.g.
+    /// `http2.server.stream`.
+    pub fn name(&self) -> &'static str {
+        match (self.role, self.kind) {
+            (Some(Role::Client), SpanKind::Connection) => "http2.client.connection",
+            (Some(Role::Client), SpanKind::Stream) => "http2.client.stream",
+            (Some(Role::Server), SpanKind::Connection) => "http2.server.connection",
+            (Some(Role::Server), SpanKind::Stream) => "http2.server.stream",
+            (None, SpanKind::Connection) => "http2.connection",
+            (None, SpanKind::Stream) => "http2.stream",
+        }
+    }
+
+    /// Finishes the span now and hands it to the sink.
+    pub fn finish(mut self) {
+        self.duration = self.started_at.elapsed();
+        record(&self);
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(fmt, "{} {}", self.name(), self.connection_id));
+        if let Some(stream_id) = self.stream_id {
+            try!(write!(fmt, " stream={}", stream_id));
+        }
+        if let Some(ref method) = self.method {
+            try!(write!(fmt, " method={}", String::from_utf8_lossy(method)));
+        }
+        if let Some(ref path) = self.path {
+            try!(write!(fmt, " path={}", String::from_utf8_lossy(path)));
+        }
+        if let Some(status) = self.status {
+            try!(write!(fmt, " status={}", status));
+        }
+        if let Some(ref trace) = self.trace {
+            try!(write!(fmt, " traceparent={}", trace));
+        }
+        write!(fmt, " sent={} received={} duration={:?}",
+               self.bytes_sent, self.bytes_received, self.duration)
+    }
+}
+
+/// The trait is implemented by the receivers of finished spans.
+pub trait SpanSink: Send {
+    /// Invoked with every span once it is finished.
+    fn on_span(&mut self, span: &Span);
+}
+
+impl<F> SpanSink for F where F: FnMut(&Span) + Send {
+    fn on_span(&mut self, span: &Span) {
+        self(span)
+    }
+}
+
+/// A `SpanSink` that logs every span (at the `info` level, with the `solicit::tracing` target).
+pub struct LogSink;
+
+impl SpanSink for LogSink {
+    fn on_span(&mut self, span: &Span) {
+        info!(target: "solicit::tracing", "{}", span);
+    }
+}
+
+/// The sink that the spans of the whole process are handed to.
+#[cfg(feature="tracing")]
+static SINK: Mutex<Option<Box<SpanSink>>> = Mutex::new(None);
+
+/// Sets the sink that all spans are handed to from now on, process-wide. Without the `tracing`
+/// feature, the sink is dropped right away.
+#[cfg(feature="tracing")]
+pub fn set_sink<S>(sink: S) where S: SpanSink + 'static {
+    *SINK.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(sink));
+}
+
+/// Sets the sink that all spans are handed to from now on, process-wide. Without the `tracing`
+/// feature, the sink is dropped right away.
+#[cfg(not(feature="tracing"))]
+pub fn set_sink<S>(_sink: S) where S: SpanSink + 'static {}
+
+/// Removes the sink, if any, which stops the recording of spans.
+#[cfg(feature="tracing")]
+pub fn clear_sink() {
+    *SINK.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Removes the sink, if any, which stops the recording of spans.
+#[cfg(not(feature="tracing"))]
+pub fn clear_sink() {}
+
+/// Returns whether spans are being recorded, i.e. whether a sink is set. Without the `tracing`
+/// feature, they never are.
+#[cfg(feature="tracing")]
+pub fn is_enabled() -> bool {
+    SINK.lock().unwrap_or_else(|e| e.into_inner()).is_some()
+}
+
+/// Returns whether spans are being recorded, i.e. whether a sink is set. Without the `tracing`
+/// feature, they never are.
+#[cfg(not(feature="tracing"))]
+#[inline]
+pub fn is_enabled() -> bool {
+    false
+}
+
+/// Hands the given finished span to the sink, if there is one.
+#[cfg(feature="tracing")]
+pub fn record(span: &Span) {
+    if let Some(ref mut sink) = *SINK.lock().unwrap_or_else(|e| e.into_inner()) {
+        sink.on_span(span);
+    }
+}
+
+/// Hands the given finished span to the sink, if there is one.
+#[cfg(not(feature="tracing"))]
+#[inline]
+pub fn record(_span: &Span) {}
+
+/// Traces the lifetime of a connection, counting the bytes that it exchanges and recording its
+/// span once it is dropped. Without the `tracing` feature, it is a zero-sized token.
+#[derive(Debug)]
+pub struct ConnectionTrace {
+    #[cfg(feature="tracing")]
+    span: Span,
+}
+
+impl ConnectionTrace {
+    /// Starts tracing the connection with the given ID.
+    #[cfg(feature="tracing")]
+    pub fn new(connection_id: ConnectionId) -> ConnectionTrace {
+        ConnectionTrace { span: Span::connection(None, connection_id) }
+    }
+
+    /// Starts tracing the connection with the given ID.
+    #[cfg(not(feature="tracing"))]
+    #[inline]
+    pub fn new(_connection_id: ConnectionId) -> ConnectionTrace {
+        ConnectionTrace {}
+    }
+
+    /// Sets the side of the connection that it is traced on.
+    #[cfg(feature="tracing")]
+    pub fn set_role(&mut self, role: Role) {
+        self.span.role = Some(role);
+    }
+
+    /// Sets the side of the connection that it is traced on.
+    #[cfg(not(feature="tracing"))]
+    #[inline]
+    pub fn set_role(&mut self, _role: Role) {}
+
+    /// Counts the given number of body bytes as sent.
+    #[cfg(feature="tracing")]
+    pub fn data_sent(&mut self, len: u32) {
+        self.span.bytes_sent += len as u64;
+    }
+
+    /// Counts the given number of body bytes as sent.
+    #[cfg(not(feature="tracing"))]
+    #[inline]
+    pub fn data_sent(&mut self, _len: u32) {}
+
+    /// Counts the given number of body bytes as received.
+    #[cfg(feature="tracing")]
+    pub fn data_received(&mut self, len: u32) {
+        self.span.bytes_received += len as u64;
+    }
+
+    /// Counts the given number of body bytes as received.
+    #[cfg(not(feature="tracing"))]
+    #[inline]
+    pub fn data_received(&mut self, _len: u32) {}
+}
+
+#[cfg(feature="tracing")]
+impl Drop for ConnectionTrace {
+    fn drop(&mut self) {
+        if is_enabled() {
+            self.span.clone().finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Span, Role, TraceContext};
+    use http::{ConnectionId, Header};
+
+    /// Tests that spans are named after their kind and role and shown with their attributes.
+    #[test]
+    fn test_span_name_and_display() {
+        let mut span = Span::stream(Role::Server, ConnectionId(4), 3);
+        span.method = Some(b"GET".to_vec());
+        span.path = Some(b"/index".to_vec());
+        span.status = Some(200);
+        span.bytes_sent = 12;
+        assert_eq!(span.name(), "http2.server.stream");
+        assert_eq!(span.to_string(),
+                   "http2.server.stream conn-4 stream=3 method=GET path=/index status=200 \
+                    sent=12 received=0 duration=0ns");
+
+        let span = Span::connection(Some(Role::Client), ConnectionId(4));
+        assert_eq!(span.name(), "http2.client.connection");
+        assert_eq!(Span::connection(None, ConnectionId(4)).name(), "http2.connection");
+    }
+
+    /// Tests that valid `traceparent` values are parsed and shown back unchanged, while invalid
+    /// ones are rejected.
+    #[test]
+    fn test_trace_context_parse() {
+        let value = b"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let context = TraceContext::parse(value).unwrap();
+        assert_eq!(context.trace_id[0], 0x4b);
+        assert_eq!(context.parent_id[7], 0xb7);
+        assert!(context.is_sampled());
+        assert_eq!(context.traceparent(), value.to_vec());
+        // Newer versions may append fields.
+        assert!(TraceContext::parse(b"01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-ff")
+                    .is_some());
+
+        let invalid: &[&[u8]] = &[
+            b"",
+            b"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-",
+            b"00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            b"00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            b"00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            b"ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            b"00_4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            b"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-0x",
+        ];
+        for value in invalid {
+            assert!(TraceContext::parse(value).is_none(), "{:?}", value);
+        }
+    }
+
+    /// Tests that the context is extracted from headers, combining the `tracestate` headers, and
+    /// that its child continues the trace.
+    #[test]
+    fn test_trace_context_headers() {
+        let mut headers = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b"traceparent".to_vec(),
+                        b"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00".to_vec()),
+            Header::new(b"tracestate".to_vec(), b"a=1".to_vec()),
+            Header::new(b"tracestate".to_vec(), b"b=2".to_vec()),
+        ];
+        let context = TraceContext::from_headers(&headers).unwrap();
+        assert_eq!(context.tracestate, Some(b"a=1,b=2".to_vec()));
+        assert!(!context.is_sampled());
+        assert!(TraceContext::from_headers(&headers[..1]).is_none());
+
+        let child = context.child();
+        assert_eq!((child.trace_id, child.flags), (context.trace_id, context.flags));
+        assert!(child.parent_id != context.parent_id);
+        child.apply_to(&mut headers);
+        assert_eq!(headers.len(), 3);
+        assert_eq!(TraceContext::from_headers(&headers), Some(child));
+    }
+}
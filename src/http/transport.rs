@@ -10,11 +10,13 @@
 
 use std::io;
 use std::io::{Read, Write};
+use std::mem;
 use std::net::TcpStream;
-use std::net::Shutdown;
+use std::net::{Shutdown, SocketAddr};
+use std::time::Duration;
 
-use http::HttpResult;
-use http::frame::{FrameIR, RawFrame, unpack_header};
+use http::{HttpError, HttpResult, ErrorCode, PrefaceMismatch, DEFAULT_MAX_FRAME_SIZE};
+use http::frame::{FrameIR, RawFrame, FrameHeaderBuffer, unpack_header};
 use http::connection::{SendFrame, ReceiveFrame, HttpFrame};
 
 /// A trait that any struct that wants to provide the transport layer for
@@ -68,6 +70,210 @@ pub trait TransportStream: Read + Write + Sized {
     /// If successful, all handles to the stream created by the `try_split` operation will start
     /// receiving an error for any IO operations.
     fn close(&mut self) -> Result<(), io::Error>;
+
+    /// Sets the timeout for read operations on the stream. If `None` is given, reads block
+    /// indefinitely.
+    ///
+    /// Once the timeout elapses without any bytes being available, the read returns an
+    /// `io::Error` of either the `WouldBlock` or the `TimedOut` kind.
+    ///
+    /// The default implementation returns an error, indicating that the stream does not support
+    /// read timeouts.
+    fn set_read_timeout(&self, _dur: Option<Duration>) -> Result<(), io::Error> {
+        Err(io::Error::new(io::ErrorKind::Other, "Read timeouts are not supported"))
+    }
+
+    /// Returns the address of the peer at the other end of the stream, if it is known.
+    ///
+    /// The default implementation returns `None`.
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+/// Returns `true` if the given `io::Error` is the result of a read timing out (as set by the
+/// `TransportStream::set_read_timeout` method).
+pub fn is_timeout(err: &io::Error) -> bool {
+    match err.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => true,
+        _ => false,
+    }
+}
+
+/// The direction in which bytes flow through a `TransportStream`, as seen from the endpoint that
+/// owns it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    /// The bytes that are read from the stream, i.e. received from the peer.
+    Read,
+    /// The bytes that are written to the stream, i.e. sent to the peer.
+    Write,
+}
+
+/// Reads the header of the next frame from the given `TransportStream`.
+///
+/// If the stream is at an end of file before any byte of the header is read, the peer has closed
+/// the connection in between two frames, which is signaled by an `HttpError::PeerClosed` error.
+/// (The reader cannot know whether the close was graceful, so it is never reported as such.)
+/// An end of file in the middle of the header is a regular IO error, as the frame is truncated.
+pub fn read_frame_header<TS: TransportStream>(ts: &mut TS) -> HttpResult<FrameHeaderBuffer> {
+    let mut buf = [0; 9];
+    let read = try!(ts.read(&mut buf));
+    if read == 0 {
+        return Err(HttpError::PeerClosed { graceful: false });
+    }
+    try!(TransportStream::read_exact(ts, &mut buf[read..]));
+    Ok(buf)
+}
+
+/// Checks that the payload of the frame with the given header is no longer than `max_frame_size`,
+/// the largest one that we allow the peer to send (our SETTINGS_MAX_FRAME_SIZE).
+///
+/// A longer frame is a connection error of type FRAME_SIZE_ERROR (section 4.2.), which is
+/// detected before any of its payload is read, so that it is never buffered.
+pub fn check_frame_size(header: &FrameHeaderBuffer, max_frame_size: u32) -> HttpResult<()> {
+    let (len, frame_type, _, stream_id) = unpack_header(header);
+    if len > max_frame_size {
+        debug!("Frame of type {} on stream {} is {} bytes long, over the maximum of {}",
+               frame_type, stream_id, len, max_frame_size);
+        return Err(HttpError::LocalConnectionError(ErrorCode::FrameSizeError));
+    }
+    Ok(())
+}
+
+/// Reads the next full frame from the given `TransportStream`, without parsing it, e.g. in order to
+/// hand it over to another thread. A frame whose payload is longer than `max_frame_size` is
+/// refused before it is read (see `check_frame_size`).
+///
+/// The end of file is handled as by `read_frame_header`. If the read times out in the middle of
+/// the frame, the bytes read so far are lost; a `FrameReader` keeps them instead.
+pub fn read_raw_frame<TS: TransportStream>(ts: &mut TS, max_frame_size: u32)
+        -> HttpResult<RawFrame<'static>> {
+    FrameReader::new().read_frame(ts, max_frame_size)
+}
+
+/// Reads frames from a `TransportStream` whose reads may time out (see
+/// `TransportStream::set_read_timeout`).
+///
+/// When a read fails in the middle of a frame, the bytes of the frame that were already read are
+/// kept, so that the next `read_frame` call continues the same frame, rather than reading the
+/// rest of it as if it were the start of a new one.
+#[derive(Clone, Debug, Default)]
+pub struct FrameReader {
+    /// The bytes of the frame that is being read.
+    buf: Vec<u8>,
+}
+
+impl FrameReader {
+    /// Creates a new `FrameReader`, which is yet to read any part of a frame.
+    pub fn new() -> FrameReader {
+        FrameReader { buf: Vec::new() }
+    }
+
+    /// Returns whether a frame was partially read by a previous `read_frame` call.
+    pub fn is_mid_frame(&self) -> bool {
+        !self.buf.is_empty()
+    }
+
+    /// Reads the next full frame (or the rest of the one that a previous call was reading) from
+    /// the given `TransportStream`, without parsing it. A frame whose payload is longer than
+    /// `max_frame_size` is refused before its payload is read (see `check_frame_size`).
+    ///
+    /// The end of file is handled as by `read_frame_header`. Any IO error, including a read
+    /// timing out, is returned as is, keeping whatever part of the frame was read.
+    pub fn read_frame<TS: TransportStream>(&mut self, ts: &mut TS, max_frame_size: u32)
+            -> HttpResult<RawFrame<'static>> {
+        self.read(ts, max_frame_size, false)
+    }
+
+    /// Reads the first frame that the peer sends on the connection (or the rest of it), same as
+    /// `read_frame`, except that its header is checked as by `read_preface_frame_header` as soon
+    /// as it is read, so that a peer that is not speaking HTTP/2 is detected as such.
+    pub fn read_preface_frame<TS: TransportStream>(&mut self, ts: &mut TS)
+            -> HttpResult<RawFrame<'static>> {
+        self.read(ts, DEFAULT_MAX_FRAME_SIZE, true)
+    }
+
+    /// Reads the frame, checking its header as the one of the peer's preface frame, if asked to.
+    fn read<TS: TransportStream>(&mut self, ts: &mut TS, max_frame_size: u32, preface: bool)
+            -> HttpResult<RawFrame<'static>> {
+        loop {
+            let filled = self.buf.len();
+            let total_len = if filled < 9 {
+                9
+            } else {
+                let mut header = [0; 9];
+                header.copy_from_slice(&self.buf[..9]);
+                if preface {
+                    if let Err(e) = check_preface_frame_header(ts, header) {
+                        self.buf.clear();
+                        return Err(e);
+                    }
+                }
+                try!(check_frame_size(&header, max_frame_size));
+                9 + unpack_header(&header).0 as usize
+            };
+            if filled == total_len {
+                return Ok(RawFrame::from(mem::replace(&mut self.buf, Vec::new())));
+            }
+
+            self.buf.resize(total_len, 0);
+            let res = ts.read(&mut self.buf[filled..]);
+            let read = *res.as_ref().unwrap_or(&0);
+            self.buf.truncate(filled + read);
+            match res {
+                Ok(0) if filled == 0 => return Err(HttpError::PeerClosed { graceful: false }),
+                Ok(0) => {
+                    return Err(HttpError::from(io::Error::new(io::ErrorKind::Other,
+                                                              "Not enough bytes")));
+                },
+                Ok(_) => {},
+                Err(e) => return Err(HttpError::from(e)),
+            }
+        }
+    }
+}
+
+/// The maximum number of bytes that are collected for diagnostics when the peer turns out not to
+/// be speaking HTTP/2.
+const PREFACE_DIAGNOSTICS_LEN: usize = 128;
+
+/// Reads the header of the first frame that the peer sends on the connection from the given
+/// `TransportStream`. As part of the peer's connection preface, it has to be the header of a
+/// (non-ACK) SETTINGS frame.
+///
+/// If the header could not possibly belong to such a frame, the peer is most likely not speaking
+/// HTTP/2 at all (e.g. an HTTP/1.1 server responding with an error page). Rather than attempting
+/// to read the rest of a bogus frame, an `HttpError::NotHttp2` error is returned, carrying the
+/// received bytes, along with the ones that the peer has already sent beyond the header (as
+/// returned by a single further read, up to a small limit).
+pub fn read_preface_frame_header<TS: TransportStream>(ts: &mut TS)
+        -> HttpResult<FrameHeaderBuffer> {
+    let header = try!(read_frame_header(ts));
+    check_preface_frame_header(ts, header)
+}
+
+/// Checks the given header, which was just read from the given `TransportStream`, as the header
+/// of the peer's preface frame, as described for `read_preface_frame_header`.
+fn check_preface_frame_header<TS: TransportStream>(ts: &mut TS, header: FrameHeaderBuffer)
+        -> HttpResult<FrameHeaderBuffer> {
+    let (len, frame_type, flags, stream_id) = unpack_header(&header);
+    // A SETTINGS frame (type 0x4) without the ACK flag (0x1), on stream 0, whose payload consists
+    // of whole settings (6 octets each).
+    let is_settings = frame_type == 0x4 && flags & 0x1 == 0 && stream_id == 0 &&
+                      len % 6 == 0 && len <= DEFAULT_MAX_FRAME_SIZE;
+    if is_settings {
+        return Ok(header);
+    }
+
+    let mut received = header.to_vec();
+    let mut buf = [0; PREFACE_DIAGNOSTICS_LEN];
+    // Whatever the peer sent after the header is only a nice-to-have, so errors are ignored.
+    if let Ok(read) = ts.read(&mut buf[..PREFACE_DIAGNOSTICS_LEN - received.len()]) {
+        received.extend_from_slice(&buf[..read]);
+    }
+    debug!("Invalid preface received: {:?}", String::from_utf8_lossy(&received));
+    Err(HttpError::NotHttp2(PrefaceMismatch { received: received }))
 }
 
 impl TransportStream for TcpStream {
@@ -78,6 +284,14 @@ impl TransportStream for TcpStream {
     fn close(&mut self) -> Result<(), io::Error> {
         self.shutdown(Shutdown::Both)
     }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> Result<(), io::Error> {
+        TcpStream::set_read_timeout(self, dur)
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        TcpStream::peer_addr(self).ok()
+    }
 }
 
 impl<T> SendFrame for T where T: TransportStream {
@@ -93,9 +307,18 @@ impl<T> SendFrame for T where T: TransportStream {
 /// `TransportStream` and uses it to provide HTTP/2 frames, when asked for one, by reading from the
 /// stream.
 /// The implementation always allocates a new buffer on the heap for every incoming frame.
+///
+/// Frames that are longer than the maximum frame size (see `ReceiveFrame::set_max_frame_size`)
+/// are refused with a FRAME_SIZE_ERROR before their payload is read.
+///
+/// If the stream has a read timeout, a `FrameReader` that outlives the `TransportReceiveFrame`
+/// should be given (see `with_reader`), so that a timeout in the middle of a frame does not lose
+/// the part of the frame that was already read.
 pub struct TransportReceiveFrame<'a, TS> where TS: TransportStream + 'a {
     ts: &'a mut TS,
     raw_frame: Option<RawFrame<'a>>,
+    max_frame_size: u32,
+    reader: Option<&'a mut FrameReader>,
 }
 
 impl<'a, TS> TransportReceiveFrame<'a, TS> where TS: TransportStream {
@@ -105,19 +328,30 @@ impl<'a, TS> TransportReceiveFrame<'a, TS> where TS: TransportStream {
         TransportReceiveFrame {
             ts: ts,
             raw_frame: None,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            reader: None,
+        }
+    }
+
+    /// Create a new `TransportReceiveFrame` that reads the frames from the given `TransportStream`
+    /// by means of the given `FrameReader`, continuing any frame that it was in the middle of.
+    pub fn with_reader(ts: &'a mut TS, reader: &'a mut FrameReader)
+            -> TransportReceiveFrame<'a, TS> {
+        TransportReceiveFrame {
+            ts: ts,
+            raw_frame: None,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            reader: Some(reader),
         }
     }
 }
 
-impl<'a, TS> ReceiveFrame for TransportReceiveFrame<'a, TS> where TS: TransportStream {
-    fn recv_frame(&mut self) -> HttpResult<HttpFrame> {
-        let raw_header = {
-            let mut buf = [0; 9];
-            try!(TransportStream::read_exact(self.ts, &mut buf));
-            buf
-        };
+impl<'a, TS> TransportReceiveFrame<'a, TS> where TS: TransportStream {
+    /// Reads the rest of the frame whose header was already read and parses it.
+    fn recv_frame_with_header(&mut self, raw_header: FrameHeaderBuffer) -> HttpResult<HttpFrame> {
         let header = unpack_header(&raw_header);
         trace!("Received frame header {:?}", header);
+        try!(check_frame_size(&raw_header, self.max_frame_size));
 
         let total_len = 9 + header.0 as usize;
         // Now prepare the buffer that will hold the entire frame.
@@ -138,6 +372,30 @@ impl<'a, TS> ReceiveFrame for TransportReceiveFrame<'a, TS> where TS: TransportS
     }
 }
 
+impl<'a, TS> ReceiveFrame for TransportReceiveFrame<'a, TS> where TS: TransportStream {
+    fn recv_frame(&mut self) -> HttpResult<HttpFrame> {
+        if let Some(ref mut reader) = self.reader {
+            self.raw_frame = Some(try!(reader.read_frame(self.ts, self.max_frame_size)));
+            return HttpFrame::from_raw(self.raw_frame.as_ref().unwrap());
+        }
+        let raw_header = try!(read_frame_header(self.ts));
+        self.recv_frame_with_header(raw_header)
+    }
+
+    fn recv_preface_frame(&mut self) -> HttpResult<HttpFrame> {
+        if let Some(ref mut reader) = self.reader {
+            self.raw_frame = Some(try!(reader.read_preface_frame(self.ts)));
+            return HttpFrame::from_raw(self.raw_frame.as_ref().unwrap());
+        }
+        let raw_header = try!(read_preface_frame_header(self.ts));
+        self.recv_frame_with_header(raw_header)
+    }
+
+    fn set_max_frame_size(&mut self, max_frame_size: u32) {
+        self.max_frame_size = max_frame_size;
+    }
+}
+
 #[cfg(feature="tls")]
 use openssl::ssl::SslStream;
 #[cfg(feature="tls")]
@@ -149,23 +407,35 @@ impl TransportStream for SslStream<TcpStream> {
     fn close(&mut self) -> Result<(), io::Error> {
         self.get_ref().shutdown(Shutdown::Both)
     }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> Result<(), io::Error> {
+        self.get_ref().set_read_timeout(dur)
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.get_ref().peer_addr().ok()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{TransportStream, TransportReceiveFrame};
+    use std::time::Duration;
+
+    use super::{TransportStream, TransportReceiveFrame, FrameReader, read_raw_frame, is_timeout};
 
     use http::tests::common::{
         serialize_frame,
         build_stub_from_frames,
         StubTransportStream,
     };
-    use http::HttpError;
+    use http::{HttpError, ErrorCode, PrefaceMismatch};
     use http::connection::{HttpFrame, SendFrame, ReceiveFrame};
     use http::frame::{
         RawFrame,
         DataFrame,
         HeadersFrame,
+        SettingsFrame,
+        HttpSetting,
         pack_header,
     };
 
@@ -183,6 +453,16 @@ mod tests {
                 sender.send_frame(frame).unwrap();
                 ret
             },
+            HttpFrame::PriorityFrame(frame) => {
+                let ret = serialize_frame(&frame);
+                sender.send_frame(frame).unwrap();
+                ret
+            },
+            HttpFrame::AltSvcFrame(frame) => {
+                let ret = serialize_frame(&frame);
+                sender.send_frame(frame).unwrap();
+                ret
+            },
             HttpFrame::RstStreamFrame(frame) => {
                 let ret = serialize_frame(&frame);
                 sender.send_frame(frame).unwrap();
@@ -203,6 +483,16 @@ mod tests {
                 sender.send_frame(frame).unwrap();
                 ret
             },
+            HttpFrame::PingFrame(frame) => {
+                let ret = serialize_frame(&frame);
+                sender.send_frame(frame).unwrap();
+                ret
+            },
+            HttpFrame::ContinuationFrame(frame) => {
+                let ret = serialize_frame(&frame);
+                sender.send_frame(frame).unwrap();
+                ret
+            },
             HttpFrame::UnknownFrame(frame) => {
                 let ret = serialize_frame(&frame);
                 let raw: RawFrame = frame.into();
@@ -293,7 +583,60 @@ mod tests {
             assert_equal(frame, receiver.recv_frame().unwrap());
         }
         // Attempting to read after EOF yields an error
-        assert!(receiver.recv_frame().is_err());
+        assert_eq!(receiver.recv_frame().err().unwrap(), HttpError::PeerClosed { graceful: false });
+    }
+
+    /// Tests that the peer's preface SETTINGS frame is received normally.
+    #[test]
+    fn test_recv_preface_frame_settings() {
+        let mut settings = SettingsFrame::new();
+        settings.add_setting(HttpSetting::MaxConcurrentStreams(10));
+        let frames = vec![HttpFrame::SettingsFrame(settings.clone())];
+        let mut stream = StubTransportStream::with_stub_content(&build_stub_from_frames(&frames));
+        let mut receiver = TransportReceiveFrame::new(&mut stream);
+
+        assert_eq!(receiver.recv_preface_frame().unwrap(), HttpFrame::SettingsFrame(settings));
+    }
+
+    /// Tests that a peer responding with HTTP/1.1 instead of a preface is detected, without
+    /// trying to read a frame of the bogus length that the status line encodes.
+    #[test]
+    fn test_recv_preface_frame_http1() {
+        let response = b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n";
+        let mut stream = StubTransportStream::with_stub_content(response);
+        let mut receiver = TransportReceiveFrame::new(&mut stream);
+
+        match receiver.recv_preface_frame() {
+            Err(HttpError::NotHttp2(mismatch)) => {
+                assert!(mismatch.is_http1());
+                assert_eq!(mismatch.received, response.to_vec());
+            },
+            _ => panic!("Expected a NotHttp2 error"),
+        };
+    }
+
+    /// Tests that the bytes kept for diagnostics are limited.
+    #[test]
+    fn test_recv_preface_frame_diagnostics_limit() {
+        let garbage = vec![b'<'; 1024];
+        let mut stream = StubTransportStream::with_stub_content(&garbage);
+        let mut receiver = TransportReceiveFrame::new(&mut stream);
+
+        assert_eq!(receiver.recv_preface_frame().err().unwrap(),
+                   HttpError::NotHttp2(PrefaceMismatch { received: vec![b'<'; 128] }));
+    }
+
+    /// Tests that an EOF in the middle of a frame header is not considered a clean close.
+    #[test]
+    fn test_recv_frame_eof_within_header() {
+        let serialized = serialize_frame(&DataFrame::with_data(1, vec![1, 2, 3]));
+        let mut stream = StubTransportStream::with_stub_content(&serialized[..3]);
+        let mut receiver = TransportReceiveFrame::new(&mut stream);
+
+        assert!(match receiver.recv_frame() {
+            Err(HttpError::IoError(_)) => true,
+            _ => false,
+        });
     }
 
     /// Tests that the implementation of `ReceiveFrame` for `TransportReceiveFrame` types
@@ -323,12 +666,82 @@ mod tests {
     /// a corresponding error is returned.
     #[test]
     fn test_recv_frame_invalid() {
-        // A DATA header which is attached to stream 0
+        // A HEADERS frame which is attached to stream 0 is a PROTOCOL_ERROR (section 6.2.)
         let serialized = serialize_frame(&HeadersFrame::new(vec![], 0));
         let mut stream = StubTransportStream::with_stub_content(&serialized);
         let mut receiver = TransportReceiveFrame::new(&mut stream);
 
-        assert_eq!(receiver.recv_frame().err().unwrap(), HttpError::InvalidFrame);
+        assert_eq!(receiver.recv_frame().err().unwrap(),
+                   HttpError::LocalConnectionError(ErrorCode::ProtocolError));
     }
 
+    /// Tests that a frame longer than the maximum frame size is refused with a FRAME_SIZE_ERROR
+    /// before its payload is read, and that raising the maximum lets it through.
+    #[test]
+    fn test_recv_frame_too_large() {
+        let serialized = serialize_frame(&DataFrame::with_data(1, vec![0; 20_000]));
+        {
+            // Only the header is there: reading any of the payload would fail.
+            let mut stream = StubTransportStream::with_stub_content(&serialized[..9]);
+            let mut receiver = TransportReceiveFrame::new(&mut stream);
+
+            assert_eq!(receiver.recv_frame().err().unwrap(),
+                       HttpError::LocalConnectionError(ErrorCode::FrameSizeError));
+        }
+        {
+            let mut stream = StubTransportStream::with_stub_content(&serialized[..9]);
+            assert_eq!(read_raw_frame(&mut stream, 16_384).err().unwrap(),
+                       HttpError::LocalConnectionError(ErrorCode::FrameSizeError));
+        }
+        {
+            let mut stream = StubTransportStream::with_stub_content(&serialized);
+            let mut receiver = TransportReceiveFrame::new(&mut stream);
+            receiver.set_max_frame_size(32_768);
+
+            match receiver.recv_frame().unwrap() {
+                HttpFrame::DataFrame(frame) => assert_eq!(frame.data.len(), 20_000),
+                _ => panic!("Expected a DATA frame"),
+            }
+        }
+    }
+
+    /// Tests that a `FrameReader` keeps the bytes of a frame that were read before a read timed
+    /// out, both within the header and within the payload, and continues the frame once the rest
+    /// of it arrives.
+    #[test]
+    fn test_frame_reader_timeout_mid_frame() {
+        let serialized = serialize_frame(&DataFrame::with_data(1, vec![1, 2, 3]));
+        let mut stream = StubTransportStream::with_stub_content(&serialized[..5]);
+        stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let mut reader = FrameReader::new();
+
+        for &(start, end) in &[(5, 10), (10, serialized.len())] {
+            match reader.read_frame(&mut stream, 16_384) {
+                Err(HttpError::IoError(ref e)) if is_timeout(e) => {},
+                res => panic!("Expected a timeout, got {:?}", res),
+            }
+            assert!(reader.is_mid_frame());
+            stream.append_content(&serialized[start..end]);
+        }
+        let raw = reader.read_frame(&mut stream, 16_384).unwrap();
+        assert_eq!(raw.serialize(), serialized);
+        assert!(!reader.is_mid_frame());
+
+        {
+            // Through a `TransportReceiveFrame` too.
+            let mut stream = StubTransportStream::with_stub_content(&serialized[..10]);
+            stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+            let mut reader = FrameReader::new();
+            {
+                let mut receiver = TransportReceiveFrame::with_reader(&mut stream, &mut reader);
+                assert!(receiver.recv_frame().is_err());
+            }
+            stream.append_content(&serialized[10..]);
+            let mut receiver = TransportReceiveFrame::with_reader(&mut stream, &mut reader);
+            match receiver.recv_frame().unwrap() {
+                HttpFrame::DataFrame(frame) => assert_eq!(&frame.data[..], &[1, 2, 3]),
+                _ => panic!("Expected a DATA frame"),
+            }
+        }
+    }
 }
@@ -0,0 +1,453 @@
+//! The module contains the checks that received header blocks need to pass in order not to be
+//! considered malformed, as defined in [section 8.1.2.](http://http2.github.io/http2-spec/#rfc.section.8.1.2)
+//! of the spec.
+//!
+//! A malformed header block is a stream error of type `PROTOCOL_ERROR`.
+use std::error::Error;
+use std::fmt;
+use std::str;
+
+use http::{Header, StatusCode};
+
+/// The reasons for which a header block can be malformed.
+#[derive(Clone, PartialEq, Debug)]
+pub enum MalformedHeaders {
+    /// A pseudo-header field that the header block must contain is missing.
+    MissingPseudoHeader(&'static str),
+    /// The header block contains a pseudo-header field that is not allowed in it, e.g. a
+    /// request pseudo-header in a response or any pseudo-header in trailers.
+    UnexpectedPseudoHeader(Vec<u8>),
+    /// A pseudo-header field follows a regular header field.
+    PseudoHeaderAfterRegular(Vec<u8>),
+    /// A pseudo-header field appears more than once in the header block.
+    DuplicatePseudoHeader(Vec<u8>),
+    /// The `:status` pseudo-header is not a three-digit status code.
+    InvalidStatus,
+    /// The `content-length` header is not a decimal number or it is given more than once with
+    /// different values.
+    InvalidContentLength,
+    /// The `te` header has a value other than `trailers`.
+    InvalidTe,
+}
+
+impl fmt::Display for MalformedHeaders {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MalformedHeaders::MissingPseudoHeader(name) => {
+                write!(fmt, "Missing pseudo-header {}", name)
+            },
+            MalformedHeaders::UnexpectedPseudoHeader(ref name) => {
+                write!(fmt, "Unexpected pseudo-header {}", String::from_utf8_lossy(name))
+            },
+            MalformedHeaders::PseudoHeaderAfterRegular(ref name) => {
+                write!(fmt, "Pseudo-header {} after a regular header", String::from_utf8_lossy(name))
+            },
+            MalformedHeaders::DuplicatePseudoHeader(ref name) => {
+                write!(fmt, "Duplicate pseudo-header {}", String::from_utf8_lossy(name))
+            },
+            MalformedHeaders::InvalidStatus |
+            MalformedHeaders::InvalidContentLength |
+            MalformedHeaders::InvalidTe => write!(fmt, "{}", self.description()),
+        }
+    }
+}
+
+impl Error for MalformedHeaders {
+    fn description(&self) -> &str {
+        match *self {
+            MalformedHeaders::MissingPseudoHeader(_) => "Missing pseudo-header",
+            MalformedHeaders::UnexpectedPseudoHeader(_) => "Unexpected pseudo-header",
+            MalformedHeaders::PseudoHeaderAfterRegular(_) => "Pseudo-header after a regular header",
+            MalformedHeaders::DuplicatePseudoHeader(_) => "Duplicate pseudo-header",
+            MalformedHeaders::InvalidStatus => "Invalid :status pseudo-header",
+            MalformedHeaders::InvalidContentLength => "Invalid content-length header",
+            MalformedHeaders::InvalidTe => "The te header may only contain trailers",
+        }
+    }
+}
+
+/// Returns whether the header is a pseudo-header field.
+#[inline]
+fn is_pseudo(header: &Header) -> bool {
+    header.name().starts_with(b":")
+}
+
+/// Checks that all pseudo-headers precede the regular header fields and that each of them is one
+/// of the `allowed` ones, appearing at most once.
+fn check_pseudo_headers(headers: &[Header], allowed: &[&[u8]]) -> Result<(), MalformedHeaders> {
+    let mut regular_seen = false;
+    let mut seen: Vec<&[u8]> = Vec::new();
+    for header in headers {
+        if !is_pseudo(header) {
+            regular_seen = true;
+            continue;
+        }
+        if regular_seen {
+            return Err(MalformedHeaders::PseudoHeaderAfterRegular(header.name().to_vec()));
+        }
+        if !allowed.contains(&header.name()) {
+            return Err(MalformedHeaders::UnexpectedPseudoHeader(header.name().to_vec()));
+        }
+        // A second value for the same pseudo-header would let the two ends of a proxied
+        // connection disagree on which one applies.
+        if seen.contains(&header.name()) {
+            return Err(MalformedHeaders::DuplicatePseudoHeader(header.name().to_vec()));
+        }
+        seen.push(header.name());
+    }
+    Ok(())
+}
+
+/// Validates the `te` header of a header block, which must not carry any value other than
+/// `trailers` (section 8.1.2.2.).
+///
+/// The check applies to header blocks that are about to be sent, as well as received ones.
+pub fn validate_te(headers: &[Header]) -> Result<(), MalformedHeaders> {
+    let invalid = headers.iter().any(|h| h.name() == b"te" && h.value() != b"trailers");
+    if invalid {
+        Err(MalformedHeaders::InvalidTe)
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns the value of the pseudo-header with the given name, if the header block contains it.
+#[inline]
+fn pseudo_header<'a>(headers: &'a [Header], name: &[u8]) -> Option<&'a [u8]> {
+    headers.iter().find(|h| h.name() == name).map(|h| h.value())
+}
+
+/// Validates the pseudo-header fields of a request's header block: it may only contain the
+/// request pseudo-header fields, each of them at most once (section 8.1.2.3.).
+///
+/// Whether the request carries all the pseudo-headers it needs is left up to the application.
+pub fn validate_request_headers(headers: &[Header]) -> Result<(), MalformedHeaders> {
+    try!(check_pseudo_headers(headers, &[b":method", b":scheme", b":authority", b":path"]));
+    validate_te(headers)
+}
+
+/// Validates the header block of a response: it must contain a valid `:status` and no other
+/// pseudo-header fields (section 8.1.2.4.).
+pub fn validate_response(headers: &[Header]) -> Result<(), MalformedHeaders> {
+    try!(check_pseudo_headers(headers, &[b":status"]));
+    try!(validate_te(headers));
+    let status = match pseudo_header(headers, b":status") {
+        Some(status) => status,
+        None => return Err(MalformedHeaders::MissingPseudoHeader(":status")),
+    };
+    // Parsed in the same way as the status of a received `Response`.
+    match StatusCode::parse(status) {
+        Some(_) => Ok(()),
+        None => Err(MalformedHeaders::InvalidStatus),
+    }
+}
+
+/// Validates a header block that carries trailers: it must not contain any pseudo-header fields
+/// (section 8.1.).
+pub fn validate_trailers(headers: &[Header]) -> Result<(), MalformedHeaders> {
+    try!(check_pseudo_headers(headers, &[]));
+    validate_te(headers)
+}
+
+/// Returns the body length that the header block declares in its `content-length` header, if
+/// any.
+///
+/// Repeated `content-length` headers are only accepted if all of them carry the same value.
+pub fn content_length(headers: &[Header]) -> Result<Option<u64>, MalformedHeaders> {
+    let mut length = None;
+    for header in headers.iter().filter(|h| h.name() == b"content-length") {
+        let value = header.value();
+        let parsed = if !value.is_empty() && value.iter().all(|b| b.is_ascii_digit()) {
+            str::from_utf8(value).ok().and_then(|s| s.parse::<u64>().ok())
+        } else {
+            None
+        };
+        match (parsed, length) {
+            (None, _) => return Err(MalformedHeaders::InvalidContentLength),
+            (Some(parsed), Some(length)) if parsed != length => {
+                return Err(MalformedHeaders::InvalidContentLength);
+            },
+            (parsed, _) => length = parsed,
+        }
+    }
+    Ok(length)
+}
+
+/// The limits, beyond the size of the header list, that a request exceeded.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LimitExceeded {
+    /// The header block has more fields (pseudo-headers included) than allowed.
+    HeaderCount,
+    /// The `:path` is longer than allowed.
+    PathLength,
+}
+
+impl LimitExceeded {
+    /// Returns the status code of the response that rejects the request: 431 (Request Header
+    /// Fields Too Large) or 414 (URI Too Long).
+    pub fn status(&self) -> &'static [u8] {
+        match *self {
+            LimitExceeded::HeaderCount => b"431",
+            LimitExceeded::PathLength => b"414",
+        }
+    }
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LimitExceeded::HeaderCount => write!(fmt, "Too many header fields"),
+            LimitExceeded::PathLength => write!(fmt, "The :path is too long"),
+        }
+    }
+}
+
+/// Checks the header block of a request against the given limits to the number of its fields and
+/// to the length of its `:path`, if any.
+///
+/// Unlike a byte limit on the header list, the count catches requests that are made of a huge
+/// number of tiny header fields, which are expensive to process all the same.
+pub fn check_limits(headers: &[Header],
+                    max_header_count: Option<usize>,
+                    max_path_length: Option<usize>)
+        -> Result<(), LimitExceeded> {
+    if max_header_count.map_or(false, |max| headers.len() > max) {
+        return Err(LimitExceeded::HeaderCount);
+    }
+    let path_length = pseudo_header(headers, b":path").map_or(0, |path| path.len());
+    if max_path_length.map_or(false, |max| path_length > max) {
+        return Err(LimitExceeded::PathLength);
+    }
+    Ok(())
+}
+
+/// Keeps track of the number of body octets received on a stream whose header block declared the
+/// length of the body, so that a mismatch can be detected (section 8.1.2.6.).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BodyLength {
+    /// The body length declared by the `content-length` header
+    pub declared: u64,
+    /// The number of body octets received so far
+    pub received: u64,
+}
+
+impl BodyLength {
+    /// Creates a new `BodyLength` that expects a body of exactly `declared` octets.
+    pub fn new(declared: u64) -> BodyLength {
+        BodyLength {
+            declared: declared,
+            received: 0,
+        }
+    }
+
+    /// Records that the given number of body octets was received. Returns `false` if the body is
+    /// now longer than the declared length.
+    pub fn data_received(&mut self, len: usize) -> bool {
+        self.received += len as u64;
+        self.received <= self.declared
+    }
+
+    /// Returns whether exactly the declared number of octets was received.
+    pub fn is_complete(&self) -> bool {
+        self.received == self.declared
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        validate_request_headers,
+        validate_response,
+        validate_trailers,
+        validate_te,
+        content_length,
+        check_limits,
+        BodyLength,
+        MalformedHeaders,
+        LimitExceeded,
+    };
+    use http::Header;
+
+    /// Tests that valid responses pass the validation.
+    #[test]
+    fn test_validate_response_valid() {
+        assert!(validate_response(&[Header::new(b":status", b"200")]).is_ok());
+        assert!(validate_response(&[
+            Header::new(b":status", b"404"),
+            Header::new(b"content-length".to_vec(), b"0".to_vec()),
+        ]).is_ok());
+    }
+
+    /// Tests that responses without a `:status` or with an invalid one are malformed.
+    #[test]
+    fn test_validate_response_status() {
+        assert_eq!(validate_response(&[Header::new(b"server".to_vec(), b"x".to_vec())]),
+                   Err(MalformedHeaders::MissingPseudoHeader(":status")));
+        assert_eq!(validate_response(&[]),
+                   Err(MalformedHeaders::MissingPseudoHeader(":status")));
+        assert_eq!(validate_response(&[Header::new(b":status", b"20")]),
+                   Err(MalformedHeaders::InvalidStatus));
+        assert_eq!(validate_response(&[Header::new(b":status", b"abc")]),
+                   Err(MalformedHeaders::InvalidStatus));
+        assert_eq!(validate_response(&[Header::new(b":status", b"600")]),
+                   Err(MalformedHeaders::InvalidStatus));
+        assert_eq!(validate_response(&[Header::new(b":status", b"+99")]),
+                   Err(MalformedHeaders::InvalidStatus));
+        assert_eq!(validate_response(&[Header::new(b":status", b"204")]), Ok(()));
+    }
+
+    /// Tests that request pseudo-headers in a response and misplaced pseudo-headers are
+    /// rejected.
+    #[test]
+    fn test_validate_response_pseudo_headers() {
+        assert_eq!(validate_response(&[
+                       Header::new(b":status", b"200"),
+                       Header::new(b":path", b"/"),
+                   ]),
+                   Err(MalformedHeaders::UnexpectedPseudoHeader(b":path".to_vec())));
+        assert_eq!(validate_response(&[
+                       Header::new(b"server".to_vec(), b"x".to_vec()),
+                       Header::new(b":status", b"200"),
+                   ]),
+                   Err(MalformedHeaders::PseudoHeaderAfterRegular(b":status".to_vec())));
+    }
+
+    /// Tests that a response with more than one `:status` is malformed.
+    #[test]
+    fn test_validate_response_duplicate_status() {
+        assert_eq!(validate_response(&[
+                       Header::new(b":status", b"200"),
+                       Header::new(b":status", b"404"),
+                   ]),
+                   Err(MalformedHeaders::DuplicatePseudoHeader(b":status".to_vec())));
+    }
+
+    /// Tests that valid requests pass the validation, including ones missing pseudo-headers.
+    #[test]
+    fn test_validate_request_valid() {
+        assert!(validate_request_headers(&[
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"http"),
+            Header::new(b":authority", &b"example.com"[..]),
+            Header::new(b":path", b"/"),
+            Header::new(b"accept".to_vec(), b"*/*".to_vec()),
+        ]).is_ok());
+        assert!(validate_request_headers(&[Header::new(b":method", b"GET")]).is_ok());
+    }
+
+    /// Tests that requests with duplicated or response pseudo-headers are malformed.
+    #[test]
+    fn test_validate_request_pseudo_headers() {
+        assert_eq!(validate_request_headers(&[
+                       Header::new(b":method", b"GET"),
+                       Header::new(b":scheme", b"http"),
+                       Header::new(b":path", b"/"),
+                       Header::new(b":path", b"/admin"),
+                   ]),
+                   Err(MalformedHeaders::DuplicatePseudoHeader(b":path".to_vec())));
+        assert_eq!(validate_request_headers(&[
+                       Header::new(b":method", b"GET"),
+                       Header::new(b":method", b"POST"),
+                   ]),
+                   Err(MalformedHeaders::DuplicatePseudoHeader(b":method".to_vec())));
+        assert_eq!(validate_request_headers(&[
+                       Header::new(b":method", b"GET"),
+                       Header::new(b":status", b"200"),
+                   ]),
+                   Err(MalformedHeaders::UnexpectedPseudoHeader(b":status".to_vec())));
+    }
+
+    /// Tests that trailers may not contain pseudo-headers.
+    #[test]
+    fn test_validate_trailers() {
+        assert!(validate_trailers(&[Header::new(b"grpc-status".to_vec(), b"0".to_vec())]).is_ok());
+        assert_eq!(validate_trailers(&[Header::new(b":status", b"200")]),
+                   Err(MalformedHeaders::UnexpectedPseudoHeader(b":status".to_vec())));
+    }
+
+    /// Tests that the declared body length is correctly extracted from the headers.
+    #[test]
+    fn test_content_length() {
+        assert_eq!(content_length(&[Header::new(b":status", b"200")]), Ok(None));
+        assert_eq!(content_length(&[Header::new(b"content-length".to_vec(), b"42".to_vec())]),
+                   Ok(Some(42)));
+        // The same value repeated is fine...
+        assert_eq!(content_length(&[
+                       Header::new(b"content-length".to_vec(), b"42".to_vec()),
+                       Header::new(b"content-length".to_vec(), b"42".to_vec()),
+                   ]),
+                   Ok(Some(42)));
+        // ...differing ones are not.
+        assert_eq!(content_length(&[
+                       Header::new(b"content-length".to_vec(), b"42".to_vec()),
+                       Header::new(b"content-length".to_vec(), b"7".to_vec()),
+                   ]),
+                   Err(MalformedHeaders::InvalidContentLength));
+        for value in &[&b""[..], &b"-1"[..], &b"+1"[..], &b"1a"[..], &b"99999999999999999999"[..]] {
+            assert_eq!(content_length(&[Header::new(b"content-length".to_vec(), value.to_vec())]),
+                       Err(MalformedHeaders::InvalidContentLength));
+        }
+    }
+
+    /// Tests that the `BodyLength` detects bodies that are too long or too short.
+    #[test]
+    fn test_body_length() {
+        let mut length = BodyLength::new(5);
+        assert!(!length.is_complete());
+        assert!(length.data_received(3));
+        assert!(!length.is_complete());
+        assert!(length.data_received(2));
+        assert!(length.is_complete());
+        assert!(!length.data_received(1));
+        assert!(!length.is_complete());
+
+        let length = BodyLength::new(0);
+        assert!(length.is_complete());
+    }
+
+    /// Tests that `te` may only carry `trailers`, in any kind of header block.
+    #[test]
+    fn test_validate_te() {
+        let te = |value: &[u8]| Header::new(b"te".to_vec(), value.to_vec());
+        assert!(validate_te(&[]).is_ok());
+        assert!(validate_te(&[te(b"trailers")]).is_ok());
+        assert_eq!(validate_te(&[te(b"gzip")]), Err(MalformedHeaders::InvalidTe));
+        assert_eq!(validate_te(&[te(b"trailers"), te(b"chunked")]),
+                   Err(MalformedHeaders::InvalidTe));
+
+        assert!(validate_request_headers(&[
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"http"),
+            Header::new(b":path", b"/"),
+            te(b"trailers"),
+        ]).is_ok());
+        assert_eq!(validate_request_headers(&[
+                       Header::new(b":method", b"GET"),
+                       Header::new(b":scheme", b"http"),
+                       Header::new(b":path", b"/"),
+                       te(b"deflate"),
+                   ]),
+                   Err(MalformedHeaders::InvalidTe));
+        assert_eq!(validate_response(&[Header::new(b":status", b"200"), te(b"gzip")]),
+                   Err(MalformedHeaders::InvalidTe));
+        assert_eq!(validate_trailers(&[te(b"gzip")]), Err(MalformedHeaders::InvalidTe));
+    }
+
+    /// Tests that the limits to the number of header fields and to the length of the `:path` are
+    /// only enforced when set.
+    #[test]
+    fn test_check_limits() {
+        let headers = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":path", b"/abcdef"),
+            Header::new(b"x-a", b"1"),
+            Header::new(b"x-b", b"2"),
+        ];
+        assert_eq!(check_limits(&headers, None, None), Ok(()));
+        assert_eq!(check_limits(&headers, Some(4), Some(7)), Ok(()));
+        assert_eq!(check_limits(&headers, Some(3), None), Err(LimitExceeded::HeaderCount));
+        assert_eq!(check_limits(&headers, None, Some(6)), Err(LimitExceeded::PathLength));
+        assert_eq!(LimitExceeded::HeaderCount.status(), b"431");
+        assert_eq!(LimitExceeded::PathLength.status(), b"414");
+    }
+}
@@ -0,0 +1,310 @@
+//! The module centralizes the arithmetic of flow control windows (section 6.9. of the spec).
+//!
+//! Windows are signed, since a change of the SETTINGS_INITIAL_WINDOW_SIZE setting can leave a
+//! stream's window negative, while the increments of WINDOW_UPDATE frames and the payload lengths
+//! that consume the windows are unsigned. Mixing the two with plain casts and operators is how
+//! overflows creep in, which then either panic (in debug builds) or silently desynchronize the
+//! two endpoints' views of a window. All computations on windows are meant to go through the
+//! operations defined here instead, all of which are checked (or explicitly saturating).
+//!
+//! The behavior at the limit is the same everywhere: a window can never be larger than 2^31 - 1
+//! (`MAX_WINDOW_SIZE`). An operation that would take a window past it fails, leaving the window
+//! as it was; the connection is expected to treat that as a FLOW_CONTROL_ERROR. Values that are
+//! only ever derived locally (e.g. the windows that the adaptive flow control grows to) saturate
+//! at the limit instead.
+use std::cmp;
+
+/// The largest size that a flow control window can have (2^31 - 1).
+pub const MAX_WINDOW_SIZE: u32 = 0x7fffffff;
+
+/// Clamps the given size so that it does not exceed the largest window size.
+#[inline]
+pub fn clamp(size: u32) -> u32 {
+    cmp::min(size, MAX_WINDOW_SIZE)
+}
+
+/// Adds the given delta to a (non-negative) window size, saturating at the largest window size.
+#[inline]
+pub fn saturating_add(size: u32, delta: u32) -> u32 {
+    clamp(size.saturating_add(delta))
+}
+
+/// Returns the increment that a WINDOW_UPDATE needs to carry in order to grow a window of the
+/// `current` size to the `target` size.
+///
+/// Returns `None` if no update is needed (the target is not larger than the current size) or if
+/// the target exceeds the largest window size.
+pub fn increment_to(current: u32, target: u32) -> Option<u32> {
+    if target > MAX_WINDOW_SIZE {
+        return None;
+    }
+    match target.checked_sub(current) {
+        Some(0) | None => None,
+        Some(increment) => Some(increment),
+    }
+}
+
+/// The struct represents the size of a flow control window.
+///
+/// It exposes methods that allow the manipulation of window sizes, such that they can never
+/// overflow the spec-mandated upper bound.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WindowSize(i32);
+impl WindowSize {
+    /// Tries to increase the window size by the given delta. If the WindowSize would overflow the
+    /// maximum allowed value (2^31 - 1), returns an error case. If the increase succeeds, returns
+    /// `Ok`.
+    ///
+    /// A delta larger than 2^31 - 1 is never valid (a WINDOW_UPDATE carries the increment in 31
+    /// bits), so it is refused even if the window is negative enough to take it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use solicit::http::WindowSize;
+    ///
+    /// let mut window_size = WindowSize::new(65_535);
+    /// assert_eq!(window_size.size(), 65_535);
+    /// // An increase within the bounds...
+    /// assert!(window_size.try_increase(100).is_ok());
+    /// assert_eq!(window_size.size(), 65_635);
+    /// // An increase that would overflow
+    /// assert!(window_size.try_increase(0x7fffffff).is_err());
+    /// assert_eq!(window_size.size(), 65_635);
+    /// ```
+    pub fn try_increase(&mut self, delta: u32) -> Result<(), ()> {
+        // Someone's provided a delta that would definitely overflow the window size.
+        if delta > MAX_WINDOW_SIZE {
+            return Err(())
+        }
+        // Now it is safe to cast the delta to the `i32`.
+        match self.0.checked_add(delta as i32) {
+            None => {
+                // When the add overflows, we will have went over the maximum allowed size of the
+                // window size...
+                Err(())
+            },
+            Some(next_val) => {
+                // The addition didn't overflow, so the next window size is in the range allowed by
+                // the spec.
+                self.0 = next_val;
+                Ok(())
+            }
+        }
+    }
+
+    /// Tries to decrease the size of the window by the given delta.
+    ///
+    /// There are situations where the window size should legitimately be allowed to become
+    /// negative, so the only situation where the result is an error is if the window size would
+    /// underflow, as this would definitely cause the peers to lose sync.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use solicit::http::WindowSize;
+    ///
+    /// let mut window_size = WindowSize::new(65_535);
+    /// assert_eq!(window_size.size(), 65_535);
+    /// // A decrease...
+    /// assert!(window_size.try_decrease(100).is_ok());
+    /// assert_eq!(window_size.size(), 65_435);
+    /// // A decrease that does not underflow
+    /// assert!(window_size.try_decrease(0x7fffffff).is_ok());
+    /// assert_eq!(window_size.size(), -2147418212);
+    /// // A decrease that *would* underflow
+    /// assert!(window_size.try_decrease(0x7fffffff).is_err());
+    /// assert_eq!(window_size.size(), -2147418212);
+    /// ```
+    pub fn try_decrease(&mut self, delta: i32) -> Result<(), ()> {
+        match self.0.checked_sub(delta) {
+            Some(new) => {
+                self.0 = new;
+                Ok(())
+            },
+            None => Err(()),
+        }
+    }
+
+    /// Tries to decrease the size of the window by the length of a DATA frame's payload that was
+    /// sent or received.
+    ///
+    /// Fails if the length is larger than any window can be (2^31 - 1) or if the window size
+    /// would underflow, leaving the window unchanged.
+    pub fn try_consume(&mut self, len: u32) -> Result<(), ()> {
+        if len > MAX_WINDOW_SIZE {
+            return Err(());
+        }
+        self.try_decrease(len as i32)
+    }
+
+    /// Tries to adjust the window of a stream to a change of the SETTINGS_INITIAL_WINDOW_SIZE
+    /// setting from `old` to `new`, i.e. to change its size by the difference between the two,
+    /// as required by section 6.9.2. of the spec.
+    ///
+    /// The window may become negative, but it must not exceed 2^31 - 1; the change fails (with
+    /// the window left unchanged) if it would, or if either of the settings is not a valid
+    /// window size to begin with. The peer is expected to treat a failure as a connection error
+    /// of type FLOW_CONTROL_ERROR.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use solicit::http::WindowSize;
+    ///
+    /// let mut window_size = WindowSize::new(100);
+    /// // A decrease, which may take the window below 0...
+    /// assert!(window_size.try_adjust_initial(65_535, 0).is_ok());
+    /// assert_eq!(window_size.size(), 100 - 65_535);
+    /// // ...and an increase that would take it past 2^31 - 1.
+    /// assert!(window_size.try_adjust_initial(0, 0x7fffffff).is_ok());
+    /// assert!(window_size.try_adjust_initial(0, 65_535).is_err());
+    /// assert_eq!(window_size.size(), 100 - 65_535 + 0x7fffffff);
+    /// ```
+    pub fn try_adjust_initial(&mut self, old: u32, new: u32) -> Result<(), ()> {
+        if old > MAX_WINDOW_SIZE || new > MAX_WINDOW_SIZE {
+            return Err(());
+        }
+        // Both settings fit into 31 bits, so neither the difference nor the new size can
+        // overflow an `i64`.
+        let size = self.0 as i64 + (new as i64 - old as i64);
+        if size > MAX_WINDOW_SIZE as i64 || size < i32::MIN as i64 {
+            return Err(());
+        }
+        self.0 = size as i32;
+        Ok(())
+    }
+
+    /// Creates a new `WindowSize` with the given initial size.
+    pub fn new(size: i32) -> WindowSize {
+        WindowSize(size)
+    }
+    /// Returns the current size of the window.
+    ///
+    /// The size is actually allowed to become negative (for instance if the peer changes its
+    /// intial window size in the settings); therefore, the return is an `i32`.
+    pub fn size(&self) -> i32 { self.0 }
+
+    /// Returns the number of octets that the window currently allows to be sent, i.e. its size
+    /// if it is positive and 0 otherwise.
+    #[inline]
+    pub fn available(&self) -> u32 {
+        cmp::max(self.0, 0) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WindowSize, MAX_WINDOW_SIZE, clamp, saturating_add, increment_to};
+    use http::tests::common::{Rng, PROPERTY_CASES};
+
+    /// Returns a window size, biased towards the limits.
+    fn window_size(rng: &mut Rng) -> i32 {
+        match rng.below(4) {
+            0 => MAX_WINDOW_SIZE as i32 - rng.below(4) as i32,
+            1 => i32::MIN + rng.below(4) as i32,
+            2 => rng.below(200_000) as i32 - 100_000,
+            _ => rng.next_u32() as i32,
+        }
+    }
+
+    /// Tests the operations right at the limits of the window size.
+    #[test]
+    fn test_window_size_limits() {
+        let mut window = WindowSize::new(0);
+        assert!(window.try_increase(MAX_WINDOW_SIZE).is_ok());
+        assert_eq!(window.size(), MAX_WINDOW_SIZE as i32);
+        assert!(window.try_increase(1).is_err());
+        assert!(window.try_consume(MAX_WINDOW_SIZE).is_ok());
+        assert!(window.try_consume(MAX_WINDOW_SIZE + 1).is_err());
+        assert_eq!(window.size(), 0);
+        assert_eq!(window.available(), 0);
+        assert!(window.try_consume(10).is_ok());
+        assert_eq!(window.available(), 0);
+
+        assert_eq!(clamp(0xffffffff), MAX_WINDOW_SIZE);
+        assert_eq!(saturating_add(MAX_WINDOW_SIZE - 1, 2), MAX_WINDOW_SIZE);
+        assert_eq!(increment_to(65_535, MAX_WINDOW_SIZE), Some(MAX_WINDOW_SIZE - 65_535));
+        assert_eq!(increment_to(65_535, MAX_WINDOW_SIZE + 1), None);
+        assert_eq!(increment_to(65_535, 65_535), None);
+    }
+
+    /// Tests that increasing and consuming a window either gives the exact result or fails
+    /// without changing the window, depending on whether the result is a valid window size.
+    #[test]
+    fn test_window_size_increase_consume_property() {
+        let mut rng = Rng::new(2235);
+        for _ in 0..PROPERTY_CASES {
+            let size = window_size(&mut rng);
+            let delta = rng.edgy_u32();
+
+            let mut window = WindowSize::new(size);
+            let expected = size as i64 + delta as i64;
+            match window.try_increase(delta) {
+                Ok(()) => assert_eq!(window.size() as i64, expected),
+                Err(()) => {
+                    // An increment is never larger than 2^31 - 1, as it is carried in 31 bits.
+                    assert!(delta > MAX_WINDOW_SIZE || expected > MAX_WINDOW_SIZE as i64);
+                    assert_eq!(window.size(), size);
+                },
+            }
+
+            let mut window = WindowSize::new(size);
+            let expected = size as i64 - delta as i64;
+            match window.try_consume(delta) {
+                Ok(()) => {
+                    assert_eq!(window.size() as i64, expected);
+                    // Giving back what was consumed restores the window.
+                    window.try_increase(delta).unwrap();
+                    assert_eq!(window.size(), size);
+                },
+                Err(()) => {
+                    assert!(delta > MAX_WINDOW_SIZE || expected < i32::MIN as i64);
+                    assert_eq!(window.size(), size);
+                },
+            }
+            assert!(window.available() <= MAX_WINDOW_SIZE);
+        }
+    }
+
+    /// Tests that adjusting a window to a changed initial window size shifts it by the
+    /// difference, never past the limit.
+    #[test]
+    fn test_window_size_adjust_initial_property() {
+        let mut rng = Rng::new(692);
+        for _ in 0..PROPERTY_CASES {
+            let size = window_size(&mut rng);
+            let (old, new) = (rng.edgy_u32(), rng.edgy_u32());
+
+            let mut window = WindowSize::new(size);
+            let expected = size as i64 + new as i64 - old as i64;
+            let valid = old <= MAX_WINDOW_SIZE && new <= MAX_WINDOW_SIZE &&
+                        expected <= MAX_WINDOW_SIZE as i64 && expected >= i32::MIN as i64;
+            assert_eq!(window.try_adjust_initial(old, new).is_ok(), valid);
+            if valid {
+                assert_eq!(window.size() as i64, expected);
+                window.try_adjust_initial(new, old).unwrap();
+            }
+            assert_eq!(window.size(), size);
+        }
+    }
+
+    /// Tests that the helpers on plain sizes never produce a size past the limit.
+    #[test]
+    fn test_window_helpers_property() {
+        let mut rng = Rng::new(31);
+        for _ in 0..PROPERTY_CASES {
+            let (a, b) = (rng.edgy_u32(), rng.edgy_u32());
+            assert_eq!(clamp(a) as u64, ::std::cmp::min(a as u64, MAX_WINDOW_SIZE as u64));
+            let sum = saturating_add(a, b);
+            assert_eq!(sum as u64, ::std::cmp::min(a as u64 + b as u64, MAX_WINDOW_SIZE as u64));
+            match increment_to(a, b) {
+                Some(increment) => {
+                    assert!(increment > 0 && b <= MAX_WINDOW_SIZE);
+                    assert_eq!(a as u64 + increment as u64, b as u64);
+                },
+                None => assert!(b <= a || b > MAX_WINDOW_SIZE),
+            }
+        }
+    }
+}
@@ -1,12 +1,28 @@
 #![doc(html_root_url="https://mlalic.github.io/solicit/")]
+// Without the `std` feature, only the protocol layers that need nothing but `alloc` are built:
+// framing, settings and the stream state machine. The header encoding and decoding is left out,
+// as the `hpack` crate requires `std`, and so is everything that sends or receives headers.
+#![cfg_attr(not(feature="std"), no_std)]
 
-#[macro_use] extern crate log;
-extern crate hpack;
+#[cfg_attr(feature="std", macro_use)] extern crate log;
+#[cfg(feature="std")] extern crate hpack;
+#[cfg(not(feature="std"))] extern crate alloc;
 #[cfg(feature="tls")] extern crate openssl;
+#[cfg(feature="http-types")] extern crate http as http_types;
+#[cfg(feature="json")] extern crate serde;
+#[cfg(feature="json")] extern crate serde_json;
+#[cfg(feature="compression")] extern crate flate2;
+#[cfg(feature="compression")] extern crate brotli;
+
+#[cfg(not(feature="std"))]
+#[path="nostd/mod.rs"]
+mod std;
 
 pub mod http;
+#[cfg(feature="std")]
 pub mod client;
+#[cfg(feature="std")]
 pub mod server;
 
-mod tests {
-}
+#[cfg(all(test, feature="std"))]
+mod tests;
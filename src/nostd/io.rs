@@ -0,0 +1,170 @@
+//! A minimal replacement for `std::io`, providing just enough of it for frames to be serialized
+//! into an in-memory buffer.
+use alloc::vec::Vec;
+use core::cmp;
+use core::fmt;
+use core::result;
+
+/// The kinds of errors that the shim can produce.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorKind {
+    /// A parameter was incorrect (e.g. seeking before the start of a buffer).
+    InvalidInput,
+    /// A write returned that it wrote zero bytes.
+    WriteZero,
+    /// Any other error.
+    Other,
+}
+
+/// The error type of the IO operations.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Error {
+    kind: ErrorKind,
+}
+
+impl Error {
+    /// Returns the kind of the error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Error {
+        Error { kind: kind }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "IO error: {:?}", self.kind)
+    }
+}
+
+/// The result of the IO operations.
+pub type Result<T> = result::Result<T, Error>;
+
+/// The positions that a `Seek` can move to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+/// A source of bytes.
+pub trait Read {
+    /// Reads bytes into the given buffer, returning how many were read.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+}
+
+/// A sink of bytes.
+pub trait Write {
+    /// Writes bytes from the given buffer, returning how many were written.
+    fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+    /// Flushes any buffered bytes.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Writes the whole buffer.
+    fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match try!(self.write(buf)) {
+                0 => return Err(ErrorKind::WriteZero.into()),
+                n => buf = &buf[n..],
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A cursor that can be moved within a stream of bytes.
+pub trait Seek {
+    /// Moves to the given position, returning the new position from the start.
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64>;
+}
+
+impl<'a> Read for &'a [u8] {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = cmp::min(buf.len(), self.len());
+        buf[..len].copy_from_slice(&self[..len]);
+        *self = &self[len..];
+        Ok(len)
+    }
+}
+
+/// Copies all bytes from the reader into the writer, returning how many were copied.
+pub fn copy<R, W>(reader: &mut R, writer: &mut W) -> Result<u64>
+        where R: Read + ?Sized, W: Write + ?Sized {
+    let mut buf = [0; 1024];
+    let mut total = 0;
+    loop {
+        let len = try!(reader.read(&mut buf));
+        if len == 0 {
+            return Ok(total);
+        }
+        try!(writer.write_all(&buf[..len]));
+        total += len as u64;
+    }
+}
+
+/// Wraps an in-memory buffer, so that it can be written to and seeked within.
+#[derive(Clone, Debug, Default)]
+pub struct Cursor<T> {
+    inner: T,
+    pos: u64,
+}
+
+impl<T> Cursor<T> {
+    /// Creates a new cursor at the start of the given buffer.
+    pub fn new(inner: T) -> Cursor<T> {
+        Cursor { inner: inner, pos: 0 }
+    }
+
+    /// Returns the wrapped buffer.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl Write for Cursor<Vec<u8>> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let pos = self.pos as usize;
+        if self.inner.len() < pos {
+            self.inner.resize(pos, 0);
+        }
+        // Overwrite whatever is already there, then append the rest.
+        let overlap = cmp::min(self.inner.len() - pos, buf.len());
+        self.inner[pos..pos + overlap].copy_from_slice(&buf[..overlap]);
+        self.inner.extend_from_slice(&buf[overlap..]);
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+}
+
+impl Seek for Cursor<Vec<u8>> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => {
+                self.pos = n;
+                return Ok(n);
+            },
+            SeekFrom::End(n) => (self.inner.len() as u64, n),
+            SeekFrom::Current(n) => (self.pos, n),
+        };
+        let new = if offset >= 0 {
+            base.checked_add(offset as u64)
+        } else {
+            base.checked_sub(offset.wrapping_neg() as u64)
+        };
+        match new {
+            Some(n) => {
+                self.pos = n;
+                Ok(n)
+            },
+            None => Err(ErrorKind::InvalidInput.into()),
+        }
+    }
+}
@@ -0,0 +1,9 @@
+//! Stands in for the `std` crate in builds without the `std` feature.
+//!
+//! The module re-exports the parts of `core` and `alloc` that the protocol layers use, under the
+//! same paths as in `std`, so that those layers can be written once against `std::*` paths.
+//! Only a minimal subset of `std::io` is provided, enough for serializing frames into a buffer.
+pub use core::*;
+pub use alloc::{borrow, collections, fmt, sync, vec};
+
+pub mod io;
@@ -0,0 +1,222 @@
+//! The module implements access logging for the `SimpleServer`: a record of every request that
+//! was answered, with the request line, the status and size of the response and how long it took.
+//!
+//! An `AccessLogger` is given to the server using `SimpleServer::set_access_logger` and invoked
+//! with an `AccessLogEntry` once the response to a request has been fully flushed. Requests whose
+//! streams are reset before a response is sent are not logged. Any closure taking an entry is a
+//! logger, while `CommonLogFormat` writes the entries out in the Common Log Format.
+//!
+//! ```rust,no_run
+//! use std::net::TcpListener;
+//! use solicit::http::Response;
+//! use solicit::server::SimpleServer;
+//! use solicit::server::access_log::CommonLogFormat;
+//!
+//! let listener = TcpListener::bind("127.0.0.1:8080").unwrap();
+//! let stream = listener.accept().unwrap().0;
+//! let mut server = SimpleServer::new(stream, |req| Response::new(req.stream_id, vec![
+//!     (b":status".to_vec(), b"204".to_vec()),
+//! ], vec![])).unwrap();
+//! server.set_access_logger(CommonLogFormat::stdout());
+//! loop {
+//!     server.handle_next().unwrap();
+//! }
+//! ```
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use http::StreamId;
+
+/// The record of a request that a `SimpleServer` answered.
+#[derive(Clone, Debug)]
+pub struct AccessLogEntry<'a> {
+    pub stream_id: StreamId,
+    /// The value of the request's `:method` pseudo-header (empty if missing).
+    pub method: &'a [u8],
+    /// The value of the request's `:path` pseudo-header (empty if missing).
+    pub path: &'a [u8],
+    /// The status of the response, unless it did not carry a valid one.
+    pub status: Option<u16>,
+    /// The size of the request body received by the time the handler was invoked.
+    pub bytes_received: u64,
+    /// The size of the response body that the handler returned.
+    pub bytes_sent: u64,
+    /// When the request's HEADERS were received.
+    pub received_at: SystemTime,
+    /// The time from receiving the request's HEADERS until the last frame of the response was
+    /// sent.
+    pub duration: Duration,
+    /// The address of the client, if the transport stream knows it.
+    pub peer_addr: Option<SocketAddr>,
+}
+
+/// The trait is implemented by types that record the requests answered by a `SimpleServer`.
+pub trait AccessLogger {
+    /// Records the given request. Invoked once for each request, after its response is flushed.
+    fn log(&mut self, entry: &AccessLogEntry);
+}
+
+/// Closures that take an entry are loggers, e.g. ones that feed the entries into metrics.
+impl<F> AccessLogger for F where F: FnMut(&AccessLogEntry) {
+    fn log(&mut self, entry: &AccessLogEntry) {
+        self(entry)
+    }
+}
+
+/// An `AccessLogger` that writes each entry out as a line in the Common Log Format, e.g.
+///
+/// ```text
+/// 127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /index.html HTTP/2.0" 200 2326
+/// ```
+///
+/// The times are in UTC. Errors writing the log are ignored, other than being logged themselves.
+pub struct CommonLogFormat<W> where W: Write {
+    writer: W,
+}
+
+impl<W> CommonLogFormat<W> where W: Write {
+    /// Creates a new `CommonLogFormat` logger that writes to the given writer.
+    pub fn new(writer: W) -> CommonLogFormat<W> {
+        CommonLogFormat { writer: writer }
+    }
+
+    /// Returns the writer to which the log is written.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl CommonLogFormat<io::Stdout> {
+    /// Creates a new `CommonLogFormat` logger that writes to the standard output.
+    pub fn stdout() -> CommonLogFormat<io::Stdout> {
+        CommonLogFormat::new(io::stdout())
+    }
+}
+
+impl<W> AccessLogger for CommonLogFormat<W> where W: Write {
+    fn log(&mut self, entry: &AccessLogEntry) {
+        if let Err(e) = self.writer.write_all(format_entry(entry).as_bytes()) {
+            debug!("Failed to write the access log: {:?}", e);
+        }
+    }
+}
+
+/// Formats the entry as a line (including the line break) in the Common Log Format. The fields
+/// that are not known are dashes; so is the size of an empty response body.
+pub fn format_entry(entry: &AccessLogEntry) -> String {
+    let host = entry.peer_addr.map_or("-".to_string(), |addr| addr.ip().to_string());
+    let status = entry.status.map_or("-".to_string(), |status| status.to_string());
+    let size = if entry.bytes_sent == 0 {
+        "-".to_string()
+    } else {
+        entry.bytes_sent.to_string()
+    };
+    format!("{} - - [{}] \"{} {} HTTP/2.0\" {} {}\n",
+            host,
+            format_time(entry.received_at),
+            escape(entry.method),
+            escape(entry.path),
+            status,
+            size)
+}
+
+/// Returns the bytes as a string that can go into the quoted request line: anything that is not
+/// printable ASCII, as well as quotes and backslashes, is escaped as `\xHH`.
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => escaped.push_str(&format!("\\x{:02X}", byte)),
+            0x20..=0x7e => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\x{:02X}", byte)),
+        }
+    }
+    escaped
+}
+
+/// Formats the time as the Common Log Format does, in UTC, e.g. `10/Oct/2000:13:55:36 +0000`.
+/// Times before the Unix epoch are formatted as the epoch itself.
+fn format_time(time: SystemTime) -> String {
+    const MONTHS: [&'static str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun",
+                                        "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let secs = time.duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0);
+    let (year, month, day) = civil_from_days(secs / 86_400);
+    let secs_of_day = secs % 86_400;
+    format!("{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+            day,
+            MONTHS[(month - 1) as usize],
+            year,
+            secs_of_day / 3600,
+            secs_of_day % 3600 / 60,
+            secs_of_day % 60)
+}
+
+/// Converts a number of days since the Unix epoch into a (proleptic Gregorian) date, as a
+/// `(year, month, day)` triple, counting the months and days from 1.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // Shift the epoch to 0000-03-01, so that leap days end the (400 year) eras and their years.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 -
+                       day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{AccessLogEntry, AccessLogger, CommonLogFormat, format_time};
+
+    fn entry<'a>(method: &'a [u8], path: &'a [u8]) -> AccessLogEntry<'a> {
+        AccessLogEntry {
+            stream_id: 1,
+            method: method,
+            path: path,
+            status: Some(200),
+            bytes_received: 0,
+            bytes_sent: 2326,
+            received_at: UNIX_EPOCH + Duration::from_secs(971_186_136),
+            duration: Duration::from_millis(5),
+            peer_addr: Some("127.0.0.1:54321".parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(UNIX_EPOCH), "01/Jan/1970:00:00:00 +0000");
+        assert_eq!(format_time(UNIX_EPOCH + Duration::from_secs(971_186_136)),
+                   "10/Oct/2000:13:55:36 +0000");
+        // A leap day and the last second of a leap year.
+        assert_eq!(format_time(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+                   "29/Feb/2000:00:00:00 +0000");
+        assert_eq!(format_time(UNIX_EPOCH + Duration::from_secs(1_735_689_599)),
+                   "31/Dec/2024:23:59:59 +0000");
+    }
+
+    /// Tests that the entries are written out as lines in the Common Log Format.
+    #[test]
+    fn test_common_log_format() {
+        let mut logger = CommonLogFormat::new(Vec::new());
+        logger.log(&entry(b"GET", b"/index.html"));
+
+        let mut unknown = entry(b"POST", b"/a b\"c\xff");
+        unknown.status = None;
+        unknown.bytes_sent = 0;
+        unknown.peer_addr = None;
+        logger.log(&unknown);
+
+        let log = String::from_utf8(logger.into_inner()).unwrap();
+        assert_eq!(log,
+                   "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /index.html HTTP/2.0\" \
+                    200 2326\n\
+                    - - - [10/Oct/2000:13:55:36 +0000] \"POST /a b\\x22c\\xFF HTTP/2.0\" - -\n");
+    }
+}
@@ -0,0 +1,585 @@
+//! The module implements the compression of response bodies (gzip or brotli), negotiated from the
+//! `accept-encoding` header of the request.
+//!
+//! Responses that the handler returns in full are compressed by wrapping the handler's `Service`
+//! in a `Compress` layer. Responses whose body is streamed on an `OpenStream` are compressed as
+//! they are sent instead: the encoding is negotiated with `negotiate`, the response headers are
+//! adjusted with `encode_headers`, and each chunk is passed through the stream's `Encoder`,
+//! which flushes it, so that the client can decode everything sent so far.
+//!
+//! Bodies of a content type that is already compressed (images, audio, video, archives) are
+//! never compressed again. The module is only available with the `compression` feature.
+//!
+//! ```rust,no_run
+//! use std::net::TcpListener;
+//! use solicit::http::Response;
+//! use solicit::server::{SimpleServer, ServerRequest};
+//! use solicit::server::compression::Compress;
+//!
+//! let listener = TcpListener::bind("127.0.0.1:8080").unwrap();
+//! let stream = listener.accept().unwrap().0;
+//! let hello = |req: ServerRequest| Response::new(req.stream_id, vec![
+//!     (b":status".to_vec(), b"200".to_vec()),
+//!     (b"content-type".to_vec(), b"text/plain".to_vec()),
+//! ], vec![b'a'; 4096]);
+//! let mut server = SimpleServer::with_service(stream, Compress::new(hello),
+//!                                             Default::default()).unwrap();
+//! loop {
+//!     server.handle_next().unwrap();
+//! }
+//! ```
+use std::io::{self, Write};
+use std::mem;
+
+use brotli::CompressorWriter;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+use http::{Header, Response, HttpResult};
+use http::transport::TransportStream;
+use super::{ServerRequest, OpenStream};
+use super::service::Service;
+
+/// The smallest body that the `Compress` layer compresses by default. Smaller bodies do not get
+/// any smaller, once the overhead of the encoding is accounted for.
+pub const DEFAULT_MIN_SIZE: usize = 256;
+
+/// The quality (0-11) at which brotli compresses. The higher levels are too slow for compressing
+/// responses on the fly.
+const BROTLI_QUALITY: u32 = 5;
+/// The base 2 logarithm of the brotli window size.
+const BROTLI_WINDOW: u32 = 22;
+/// The size of the brotli encoder's internal buffer.
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+/// The content codings with which a response body can be compressed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    /// Returns the name of the coding, as used in the `accept-encoding` and `content-encoding`
+    /// headers.
+    pub fn name(&self) -> &'static [u8] {
+        match *self {
+            Encoding::Gzip => b"gzip",
+            Encoding::Brotli => b"br",
+        }
+    }
+}
+
+/// Returns the encoding that the request's `accept-encoding` header prefers, if it accepts
+/// either of the supported ones.
+///
+/// The encodings are ranked by their quality values (`q=0` rules one out). An encoding that is
+/// not listed gets the quality of the `*` wildcard, if there is one. On a tie, brotli is
+/// preferred, since it compresses better.
+pub fn negotiate(req: &ServerRequest) -> Option<Encoding> {
+    let mut gzip = None;
+    let mut brotli = None;
+    let mut wildcard = None;
+    for header in req.headers.iter().filter(|h| h.name() == b"accept-encoding") {
+        for coding in header.value().split(|&b| b == b',') {
+            let mut params = coding.split(|&b| b == b';');
+            let name = trim(params.next().unwrap_or(b"")).to_ascii_lowercase();
+            let quality = params.filter_map(parse_quality).next().unwrap_or(1000);
+            match &name[..] {
+                b"gzip" | b"x-gzip" => gzip = Some(quality),
+                b"br" => brotli = Some(quality),
+                b"*" => wildcard = Some(quality),
+                _ => {},
+            }
+        }
+    }
+    let gzip = gzip.or(wildcard).unwrap_or(0);
+    let brotli = brotli.or(wildcard).unwrap_or(0);
+    if brotli > 0 && brotli >= gzip {
+        Some(Encoding::Brotli)
+    } else if gzip > 0 {
+        Some(Encoding::Gzip)
+    } else {
+        None
+    }
+}
+
+/// Parses a `q=<value>` parameter into thousandths, so that the values can be compared exactly.
+/// Returns `None` for any other parameter, or a value that is not a valid quality.
+fn parse_quality(param: &[u8]) -> Option<u16> {
+    let param = trim(param);
+    if param.len() < 2 || !param[..2].eq_ignore_ascii_case(b"q=") {
+        return None;
+    }
+    let value = &param[2..];
+    let (int, frac) = match value.iter().position(|&b| b == b'.') {
+        Some(pos) => (&value[..pos], &value[pos + 1..]),
+        None => (value, &b""[..]),
+    };
+    if int.len() != 1 || frac.len() > 3 || !frac.iter().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut quality = match int[0] {
+        b'0' => 0,
+        b'1' => 1000,
+        _ => return None,
+    };
+    for (i, &digit) in frac.iter().enumerate() {
+        quality += (digit - b'0') as u16 * [100, 10, 1][i];
+    }
+    if quality > 1000 { None } else { Some(quality) }
+}
+
+/// Returns the bytes without any leading or trailing spaces (or tabs).
+fn trim(bytes: &[u8]) -> &[u8] {
+    let is_space = |b: &u8| *b == b' ' || *b == b'\t';
+    let start = bytes.iter().position(|b| !is_space(b)).unwrap_or(bytes.len());
+    let end = bytes.iter().rposition(|b| !is_space(b)).map_or(start, |end| end + 1);
+    &bytes[start..end]
+}
+
+/// Returns whether a body with the given `content-type` is worth compressing, i.e. it is not of a
+/// type that is already compressed (images other than SVG, audio, video, fonts and archives).
+pub fn is_compressible(content_type: &[u8]) -> bool {
+    let media = content_type.split(|&b| b == b';').next().unwrap_or(b"");
+    let media = trim(media).to_ascii_lowercase();
+    if media.is_empty() {
+        return false;
+    }
+    if media.starts_with(b"image/") {
+        return &media[..] == b"image/svg+xml";
+    }
+    if media.starts_with(b"audio/") || media.starts_with(b"video/") ||
+            media.starts_with(b"font/woff") {
+        return false;
+    }
+    match &media[..] {
+        b"application/gzip" | b"application/x-gzip" | b"application/zip" |
+        b"application/x-bzip2" | b"application/x-xz" | b"application/x-7z-compressed" |
+        b"application/x-rar-compressed" | b"application/zstd" | b"application/octet-stream" |
+        b"application/pdf" => false,
+        _ => true,
+    }
+}
+
+/// Returns the value of the (first) header with the given name, if there is one.
+fn header_value<'h>(headers: &'h [Header], name: &[u8]) -> Option<&'h [u8]> {
+    headers.iter().find(|h| h.name() == name).map(|h| h.value())
+}
+
+/// Adjusts the headers of a response whose body is sent compressed with the given encoding: the
+/// `content-encoding` is set, `vary: accept-encoding` is added, since the body now depends on the
+/// request's `accept-encoding`, and the `content-length` is removed, since it no longer holds.
+///
+/// A strong `etag` is made weak, since the compressed body is no longer byte-for-byte the one
+/// that the tag was given to.
+pub fn encode_headers(headers: &mut Vec<Header<'static, 'static>>, encoding: Encoding) {
+    headers.retain(|h| h.name() != b"content-length");
+    for header in headers.iter_mut().filter(|h| h.name() == b"etag") {
+        if header.value().starts_with(b"\"") {
+            let mut weak = b"W/".to_vec();
+            weak.extend_from_slice(header.value());
+            *header = Header::new(&b"etag"[..], weak);
+        }
+    }
+    headers.push(Header::new(&b"content-encoding"[..], encoding.name()));
+    add_vary(headers);
+}
+
+/// Adds `vary: accept-encoding` to the headers, unless it is already there.
+fn add_vary(headers: &mut Vec<Header<'static, 'static>>) {
+    let varies = headers.iter().filter(|h| h.name() == b"vary").any(|h| {
+        h.value().split(|&b| b == b',').any(|field| {
+            let field = trim(field);
+            field == b"*" || field.eq_ignore_ascii_case(b"accept-encoding")
+        })
+    });
+    if !varies {
+        headers.push(Header::new(b"vary", &b"accept-encoding"[..]));
+    }
+}
+
+/// The state of the compressor behind an `Encoder`.
+enum Compressor {
+    Gzip(GzEncoder<Vec<u8>>),
+    Brotli(CompressorWriter<Vec<u8>>),
+}
+
+/// Compresses a body incrementally, as its chunks become available.
+pub struct Encoder {
+    encoding: Encoding,
+    compressor: Compressor,
+}
+
+impl Encoder {
+    /// Creates a new `Encoder` that compresses with the given encoding.
+    pub fn new(encoding: Encoding) -> Encoder {
+        let compressor = match encoding {
+            Encoding::Gzip => Compressor::Gzip(GzEncoder::new(Vec::new(), Compression::default())),
+            Encoding::Brotli => {
+                Compressor::Brotli(CompressorWriter::new(Vec::new(),
+                                                         BROTLI_BUFFER_SIZE,
+                                                         BROTLI_QUALITY,
+                                                         BROTLI_WINDOW))
+            },
+        };
+        Encoder {
+            encoding: encoding,
+            compressor: compressor,
+        }
+    }
+
+    /// Returns the encoding with which the encoder compresses.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Compresses the given chunk of the body, returning the compressed bytes.
+    ///
+    /// The compressor is flushed, so that everything returned so far can be decoded on its own,
+    /// at the cost of a somewhat worse compression ratio than compressing the body at once.
+    pub fn encode(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        try!(self.write(data));
+        match self.compressor {
+            Compressor::Gzip(ref mut gz) => {
+                try!(gz.flush());
+                Ok(mem::replace(gz.get_mut(), Vec::new()))
+            },
+            Compressor::Brotli(ref mut br) => {
+                try!(br.flush());
+                Ok(mem::replace(br.get_mut(), Vec::new()))
+            },
+        }
+    }
+
+    /// Ends the compressed body, returning the bytes that remain to be sent.
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        match self.compressor {
+            Compressor::Gzip(gz) => gz.finish(),
+            Compressor::Brotli(br) => Ok(br.into_inner()),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match self.compressor {
+            Compressor::Gzip(ref mut gz) => gz.write_all(data),
+            Compressor::Brotli(ref mut br) => br.write_all(data),
+        }
+    }
+}
+
+/// Compresses the whole of the given body with the given encoding.
+pub fn compress(encoding: Encoding, body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = Encoder::new(encoding);
+    try!(encoder.write(body));
+    encoder.finish()
+}
+
+/// A `Service` layer that compresses the bodies of the responses of the wrapped service, with the
+/// encoding that each request's `accept-encoding` prefers.
+///
+/// A response is left alone if its body is empty or smaller than the minimum size, it is not a
+/// `200` (or other successful, non-`204`) response, its content type is missing or already
+/// compressed, it already has a `content-encoding`, or it has `cache-control: no-transform`.
+pub struct Compress<S> {
+    inner: S,
+    min_size: usize,
+}
+
+impl<S> Compress<S> where S: Service {
+    /// Wraps the given service, compressing its responses of at least `DEFAULT_MIN_SIZE` bytes.
+    pub fn new(inner: S) -> Compress<S> {
+        Compress {
+            inner: inner,
+            min_size: DEFAULT_MIN_SIZE,
+        }
+    }
+
+    /// Sets the size below which response bodies are not compressed.
+    pub fn with_min_size(mut self, min_size: usize) -> Compress<S> {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Returns whether the response is one whose body can be compressed. Partial content (206) is
+    /// never compressed, as its `content-range` refers to the bytes of the uncompressed body.
+    fn is_eligible(&self, response: &Response<'static, 'static>) -> bool {
+        match response.status_code() {
+            Ok(status) if (200..300).contains(&status) && status != 204 && status != 206 => {},
+            _ => return false,
+        }
+        if response.body.is_empty() || response.body.len() < self.min_size {
+            return false;
+        }
+        let headers = &response.headers;
+        if header_value(headers, b"content-encoding").is_some() {
+            return false;
+        }
+        let no_transform = headers.iter().filter(|h| h.name() == b"cache-control").any(|h| {
+            h.value().split(|&b| b == b',').any(|d| trim(d).eq_ignore_ascii_case(b"no-transform"))
+        });
+        !no_transform && header_value(headers, b"content-type").map_or(false, is_compressible)
+    }
+}
+
+impl<S> Service for Compress<S> where S: Service {
+    fn call(&mut self, request: ServerRequest) -> Response<'static, 'static> {
+        let encoding = negotiate(&request);
+        let mut response = self.inner.call(request);
+        if !self.is_eligible(&response) {
+            return response;
+        }
+        // The body would have been compressed for a request accepting it, so whichever way it is
+        // sent, caches need to know that the response depends on the `accept-encoding`.
+        let encoding = match encoding {
+            Some(encoding) => encoding,
+            None => {
+                add_vary(&mut response.headers);
+                return response;
+            },
+        };
+        match compress(encoding, &response.body) {
+            Ok(body) => {
+                trace!("Compressed a response body of {} bytes to {} bytes ({:?})",
+                       response.body.len(),
+                       body.len(),
+                       encoding);
+                response.body = body;
+                encode_headers(&mut response.headers, encoding);
+            },
+            Err(e) => {
+                debug!("Failed to compress a response body, sending it as is: {:?}", e);
+                add_vary(&mut response.headers);
+            },
+        }
+        response
+    }
+}
+
+impl<'a, TS> OpenStream<'a, TS> where TS: TransportStream + 'a {
+    /// Compresses the given bytes with the encoder and sends them on the stream, blocking until
+    /// they are written out.
+    ///
+    /// The response headers need to have been adjusted for the encoding (see `encode_headers`).
+    pub fn send_encoded(&mut self, encoder: &mut Encoder, data: &[u8]) -> HttpResult<()> {
+        let encoded = try!(encoder.encode(data));
+        if encoded.is_empty() {
+            return Ok(());
+        }
+        self.send(&encoded)
+    }
+
+    /// Sends the end of the compressed body and ends our side of the stream.
+    pub fn close_encoded(mut self, encoder: Encoder) -> HttpResult<()> {
+        let encoded = try!(encoder.finish());
+        if !encoded.is_empty() {
+            try!(self.send(&encoded));
+        }
+        self.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use brotli::Decompressor;
+    use flate2::read::GzDecoder;
+
+    use http::{Header, Response, PseudoHeaders};
+    use server::ServerRequest;
+    use server::service::Service;
+    use super::{Encoding, Encoder, Compress, negotiate, is_compressible, encode_headers};
+
+    fn decode(encoding: Encoding, body: &[u8]) -> Vec<u8> {
+        let mut decoded = Vec::new();
+        match encoding {
+            Encoding::Gzip => GzDecoder::new(body).read_to_end(&mut decoded).unwrap(),
+            Encoding::Brotli => Decompressor::new(body, 4096).read_to_end(&mut decoded).unwrap(),
+        };
+        decoded
+    }
+
+    /// Calls the service with a `GET` request with the given `accept-encoding`, if any.
+    fn call<S: Service>(service: &mut S, accept: Option<&'static [u8]>)
+            -> Response<'static, 'static> {
+        let mut headers = vec![Header::new(b":method", b"GET")];
+        if let Some(accept) = accept {
+            headers.push(Header::new(&b"accept-encoding"[..], accept));
+        }
+        let pseudo_headers = PseudoHeaders::parse(&headers);
+        service.call(ServerRequest {
+            stream_id: 1,
+            headers: &headers,
+            pseudo_headers: &pseudo_headers,
+            body: &[],
+            trailers: &[],
+        })
+    }
+
+    fn negotiated(accept: &'static [u8]) -> Option<Encoding> {
+        let headers = vec![Header::new(&b"accept-encoding"[..], accept)];
+        let pseudo_headers = PseudoHeaders::parse(&headers);
+        negotiate(&ServerRequest {
+            stream_id: 1,
+            headers: &headers,
+            pseudo_headers: &pseudo_headers,
+            body: &[],
+            trailers: &[],
+        })
+    }
+
+    fn header<'a>(response: &'a Response<'static, 'static>, name: &[u8]) -> Option<&'a [u8]> {
+        response.headers.iter().find(|h| h.name() == name).map(|h| h.value())
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiated(b"gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiated(b"gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiated(b"br;q=0.5, gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiated(b"GZIP ; Q=0.8, br;q=0.75"), Some(Encoding::Gzip));
+        assert_eq!(negotiated(b"*"), Some(Encoding::Brotli));
+        assert_eq!(negotiated(b"*;q=0.1, gzip;q=0.2"), Some(Encoding::Gzip));
+        assert_eq!(negotiated(b"*, br;q=0"), Some(Encoding::Gzip));
+        assert_eq!(negotiated(b"gzip;q=0, br;q=0"), None);
+        assert_eq!(negotiated(b"identity, deflate"), None);
+        assert_eq!(negotiated(b""), None);
+        // An invalid quality value does not count.
+        assert_eq!(negotiated(b"br;q=2, gzip;q=0.001"), Some(Encoding::Brotli));
+    }
+
+    #[test]
+    fn test_is_compressible() {
+        assert!(is_compressible(b"text/html; charset=utf-8"));
+        assert!(is_compressible(b"application/json"));
+        assert!(is_compressible(b"IMAGE/SVG+XML"));
+        assert!(!is_compressible(b"image/png"));
+        assert!(!is_compressible(b"video/mp4"));
+        assert!(!is_compressible(b"font/woff2"));
+        assert!(!is_compressible(b"application/gzip"));
+        assert!(!is_compressible(b"application/zip"));
+        assert!(!is_compressible(b""));
+    }
+
+    /// Tests that a body compressed in chunks decodes to the concatenation of the chunks, and
+    /// that each chunk can be decoded as soon as it is encoded.
+    #[test]
+    fn test_encoder_round_trip() {
+        for &encoding in &[Encoding::Gzip, Encoding::Brotli] {
+            let mut encoder = Encoder::new(encoding);
+            assert_eq!(encoder.encoding(), encoding);
+            let mut body = encoder.encode(b"data: first\n\n").unwrap();
+            assert!(!body.is_empty());
+            body.extend(encoder.encode(&vec![b'x'; 10_000]).unwrap());
+            body.extend(encoder.finish().unwrap());
+
+            let mut expected = b"data: first\n\n".to_vec();
+            expected.extend(vec![b'x'; 10_000]);
+            assert_eq!(decode(encoding, &body), expected);
+            assert!(body.len() < 1000);
+        }
+    }
+
+    /// Tests that the `Compress` layer compresses eligible responses with the negotiated encoding
+    /// and leaves the rest alone.
+    #[test]
+    fn test_compress_service() {
+        let body = b"Lorem ipsum dolor sit amet. ".repeat(100);
+        let mut service = Compress::new(|req: ServerRequest| {
+            Response::new(req.stream_id, vec![
+                (b":status".to_vec(), b"200".to_vec()),
+                (b"content-type".to_vec(), b"text/plain".to_vec()),
+                (b"content-length".to_vec(), b"2800".to_vec()),
+            ], b"Lorem ipsum dolor sit amet. ".repeat(100))
+        });
+
+        for &(accept, encoding) in &[(&b"gzip"[..], Encoding::Gzip),
+                                     (&b"br, gzip"[..], Encoding::Brotli)] {
+            let response = call(&mut service, Some(accept));
+            assert_eq!(header(&response, b"content-encoding"), Some(encoding.name()));
+            assert_eq!(header(&response, b"vary"), Some(&b"accept-encoding"[..]));
+            assert_eq!(header(&response, b"content-length"), None);
+            assert!(response.body.len() < body.len());
+            assert_eq!(decode(encoding, &response.body), body);
+        }
+
+        // Without an acceptable encoding, the body is sent as is, but still varies.
+        let response = call(&mut service, None);
+        assert_eq!(header(&response, b"content-encoding"), None);
+        assert_eq!(header(&response, b"vary"), Some(&b"accept-encoding"[..]));
+        assert_eq!(response.body, body);
+    }
+
+    /// Tests that responses that are not worth compressing are left untouched.
+    #[test]
+    fn test_compress_service_skips() {
+        let responses = vec![
+            // Too small.
+            (vec![(b"content-type".to_vec(), b"text/plain".to_vec())], b"tiny".to_vec()),
+            // Already compressed.
+            (vec![(b"content-type".to_vec(), b"image/png".to_vec())], vec![0; 1000]),
+            (vec![(b"content-type".to_vec(), b"text/plain".to_vec()),
+                  (b"content-encoding".to_vec(), b"gzip".to_vec())], vec![0; 1000]),
+            // No content type.
+            (vec![], vec![0; 1000]),
+            // The response must not be transformed.
+            (vec![(b"content-type".to_vec(), b"text/plain".to_vec()),
+                  (b"cache-control".to_vec(), b"public, no-transform".to_vec())], vec![0; 1000]),
+        ];
+        for (headers, body) in responses {
+            let mut service = Compress::new(move |req: ServerRequest| {
+                let mut all = vec![(b":status".to_vec(), b"200".to_vec())];
+                all.extend(headers.clone());
+                Response::new(req.stream_id, all, body.clone())
+            });
+            let response = call(&mut service, Some(b"gzip, br"));
+            assert_eq!(header(&response, b"vary"), None);
+            assert!(response.body == b"tiny".to_vec() || response.body == vec![0; 1000]);
+        }
+
+        // Not a successful response with a body.
+        let mut service = Compress::new(|req: ServerRequest| {
+            Response::new(req.stream_id, vec![
+                (b":status".to_vec(), b"404".to_vec()),
+                (b"content-type".to_vec(), b"text/plain".to_vec()),
+            ], vec![b'a'; 1000])
+        }).with_min_size(0);
+        let response = call(&mut service, Some(b"gzip"));
+        assert_eq!(response.body, vec![b'a'; 1000]);
+
+        // Partial content.
+        let mut service = Compress::new(|req: ServerRequest| {
+            Response::new(req.stream_id, vec![
+                (b":status".to_vec(), b"206".to_vec()),
+                (b"content-type".to_vec(), b"text/plain".to_vec()),
+                (b"content-range".to_vec(), b"bytes 0-999/2000".to_vec()),
+            ], vec![b'a'; 1000])
+        });
+        let response = call(&mut service, Some(b"gzip"));
+        assert_eq!(header(&response, b"content-encoding"), None);
+        assert_eq!(response.body, vec![b'a'; 1000]);
+    }
+
+    #[test]
+    fn test_encode_headers() {
+        let mut headers = vec![
+            Header::new(b":status", b"200"),
+            Header::new(&b"content-length"[..], &b"100"[..]),
+            Header::new(b"vary", &b"Accept-Encoding"[..]),
+            Header::new(&b"etag"[..], &b"\"v1\""[..]),
+        ];
+        encode_headers(&mut headers, Encoding::Brotli);
+        assert_eq!(headers, vec![
+            Header::new(b":status", b"200"),
+            Header::new(b"vary", &b"Accept-Encoding"[..]),
+            Header::new(&b"etag"[..], &b"W/\"v1\""[..]),
+            Header::new(&b"content-encoding"[..], &b"br"[..]),
+        ]);
+
+        // A weak tag stays as it is.
+        let mut headers = vec![Header::new(&b"etag"[..], &b"W/\"v1\""[..])];
+        encode_headers(&mut headers, Encoding::Gzip);
+        assert_eq!(headers[0], Header::new(&b"etag"[..], &b"W/\"v1\""[..]));
+    }
+}
@@ -1,22 +1,392 @@
 //! The module contains a simple HTTP/2 server implementation.
 
-use http::{Response, StaticResponse, HttpResult, HttpError, HttpScheme, StreamId, Header};
-use http::transport::{TransportStream, TransportReceiveFrame};
-use http::connection::{HttpConnection, EndStream, SendStatus};
+use std::io;
+use std::cmp;
+use std::mem;
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{self, Sender, Receiver, TryRecvError};
+use std::thread;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime};
+
+use http::{
+    ConnectionId,
+    Response,
+    StaticResponse,
+    HttpResult,
+    HttpError,
+    HttpScheme,
+    StreamId,
+    Header,
+    ErrorCode,
+    PseudoHeaders,
+};
+use http::transport::{TransportStream, TransportReceiveFrame, FrameReader, is_timeout};
+use http::connection::{HttpConnection, HttpFrame, EndStream, SendStatus, SendFrame, ReceiveFrame};
+use http::connection::ConnectionActivity;
 use http::session::{
     DefaultSessionState,
     SessionState,
     Stream,
     DefaultStream,
+    HeaderEvent,
 };
 use http::session::Server as ServerMarker;
 use http::server::{ServerConnection, StreamFactory};
+use http::config::Http2Config;
+use http::frame::{HttpSetting, FrameIR, RawFrame};
+use http::settings::SettingsState;
+use http::tracing::{self, Span, Role, TraceContext};
+
+pub mod service;
+pub mod sse;
+pub mod access_log;
+#[cfg(feature="compression")]
+pub mod compression;
+
+use self::service::{Service, ServiceHandler, into_handler};
+use self::access_log::{AccessLogger, AccessLogEntry};
 
 /// The struct represents a fully received request.
 pub struct ServerRequest<'a, 'n, 'v> where 'n: 'a, 'v: 'a {
     pub stream_id: StreamId,
     pub headers: &'a [Header<'n, 'v>],
+    /// The request's pseudo-headers (`:method`, `:path`, etc.), already extracted from `headers`.
+    pub pseudo_headers: &'a PseudoHeaders,
+    /// The body of the request. For streaming requests, this is only the part of the body that
+    /// was received before the handler was invoked; the rest can be read as it arrives using
+    /// `SimpleServer::request_body`.
     pub body: &'a [u8],
+    /// The trailers, i.e. the header block that the client sent after the body (empty if there
+    /// was none). Since the handler only sees a request once the client ended its stream, the
+    /// trailers are always complete; the only exception are streaming requests, which are handed
+    /// out before any trailers can arrive.
+    pub trailers: &'a [Header<'n, 'v>],
+}
+
+impl<'a, 'n, 'v> ServerRequest<'a, 'n, 'v> {
+    /// Returns the value of the trailer with the given name, if the request has such a trailer.
+    pub fn trailer(&self, name: &[u8]) -> Option<&[u8]> {
+        self.trailers.iter().find(|h| h.name() == name).map(|h| h.value())
+    }
+
+    /// Returns the trace context that the request carries in its `traceparent` and `tracestate`
+    /// headers, if any (see `http::tracing`). Requests made as part of handling this one should
+    /// carry its `child` context.
+    pub fn trace_context(&self) -> Option<TraceContext> {
+        TraceContext::from_headers(self.headers)
+    }
+}
+
+/// The timing of a request handled by a `SimpleServer`, reported once its response is flushed.
+#[derive(Clone, Debug)]
+pub struct RequestTiming<'a> {
+    pub stream_id: StreamId,
+    /// The value of the request's `:method` pseudo-header (empty if missing).
+    pub method: &'a [u8],
+    /// The value of the request's `:path` pseudo-header (empty if missing).
+    pub path: &'a [u8],
+    /// The time from receiving the request's HEADERS until the last frame of the response was
+    /// sent, including the time the request spent waiting to be handled and its response waited
+    /// to be sent.
+    pub duration: Duration,
+    /// The number of requests in flight on the connection when the response was flushed,
+    /// including this one.
+    pub in_flight: usize,
+}
+
+/// A request whose response is yet to be flushed.
+struct PendingRequest {
+    received_at: Instant,
+    method: Vec<u8>,
+    path: Vec<u8>,
+    /// Whether the handler already produced the response.
+    responded: bool,
+    /// The status of the response, once known.
+    status: Option<u16>,
+    /// The size of the request body received until the handler was invoked.
+    bytes_received: u64,
+    /// The size of the response body that the handler produced.
+    bytes_sent: u64,
+    /// The trace context that the request carried, if any.
+    trace: Option<TraceContext>,
+}
+
+impl PendingRequest {
+    fn new(pseudo_headers: &PseudoHeaders, headers: &[Header]) -> PendingRequest {
+        PendingRequest {
+            received_at: Instant::now(),
+            method: pseudo_headers.method.clone().unwrap_or_else(Vec::new),
+            path: pseudo_headers.path.clone().unwrap_or_else(Vec::new),
+            responded: false,
+            status: None,
+            bytes_received: 0,
+            bytes_sent: 0,
+            trace: TraceContext::from_headers(headers),
+        }
+    }
+
+    /// Returns the span of the request (see `http::tracing`).
+    fn span(&self, connection_id: ConnectionId, stream_id: StreamId) -> Span {
+        let mut span = Span::stream(Role::Server, connection_id, stream_id);
+        span.started_at = self.received_at;
+        span.method = Some(self.method.clone());
+        span.path = Some(self.path.clone());
+        span.status = self.status;
+        span.bytes_sent = self.bytes_sent;
+        span.bytes_received = self.bytes_received;
+        span.trace = self.trace.clone();
+        span
+    }
+}
+
+/// Returns the time that is left until the given deadline. If the deadline has already passed,
+/// an `HttpError::HandshakeTimeout` error is returned.
+fn time_left(deadline: Instant) -> HttpResult<Duration> {
+    let now = Instant::now();
+    if now >= deadline {
+        Err(HttpError::HandshakeTimeout)
+    } else {
+        Ok(deadline - now)
+    }
+}
+
+/// Maps the result of a step of the preface exchange, such that a read that timed out once the
+/// given deadline (if any) passed is reported as an `HttpError::HandshakeTimeout`. Before that, the
+/// read merely would have blocked (e.g. on a non-blocking stream) and its error is kept as is.
+fn handshake_result(res: HttpResult<()>, deadline: Option<Instant>) -> HttpResult<()> {
+    match res {
+        Err(HttpError::IoError(ref e)) if is_timeout(e) && is_past(deadline) => {
+            Err(HttpError::HandshakeTimeout)
+        },
+        res => res,
+    }
+}
+
+/// Returns whether the given deadline has passed (a missing one never does).
+fn is_past(deadline: Option<Instant>) -> bool {
+    deadline.map_or(false, |deadline| Instant::now() >= deadline)
+}
+
+/// An `io::Read` adapter that gives each read on the wrapped `TransportStream` only as much time
+/// as is left until a deadline, so that a client trickling in the preface cannot extend the
+/// handshake indefinitely.
+///
+/// Once the deadline passes, reads fail with an `io::ErrorKind::TimedOut` error.
+struct DeadlineReader<'a, TS> where TS: TransportStream + 'a {
+    stream: &'a mut TS,
+    deadline: Instant,
+}
+
+impl<'a, TS> DeadlineReader<'a, TS> where TS: TransportStream + 'a {
+    fn new(stream: &'a mut TS, deadline: Instant) -> DeadlineReader<'a, TS> {
+        DeadlineReader {
+            stream: stream,
+            deadline: deadline,
+        }
+    }
+}
+
+impl<'a, TS> io::Read for DeadlineReader<'a, TS> where TS: TransportStream + 'a {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let now = Instant::now();
+        if now >= self.deadline {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Handshake deadline passed"));
+        }
+        try!(self.stream.set_read_timeout(Some(self.deadline - now)));
+        self.stream.read(buf)
+    }
+}
+
+/// Returns whether the request with the given pseudo-headers is a `CONNECT` request.
+fn is_connect(pseudo_headers: &PseudoHeaders) -> bool {
+    pseudo_headers.method.as_ref().map_or(false, |method| &method[..] == b"CONNECT")
+}
+
+/// Returns a response that defers answering the streaming request on the given stream: the stream
+/// is kept open without sending anything, until the response is started using
+/// `OpenStream::start_response`. This lets the response depend on the rest of the request body or
+/// on other work done outside of the handler (e.g. the response of an upstream server).
+///
+/// Only streaming requests (see `SimpleServer::set_streaming_filter`) can be deferred.
+pub fn defer(stream_id: StreamId) -> StaticResponse {
+    Response {
+        stream_id: stream_id,
+        headers: Vec::new(),
+        body: Vec::new(),
+        trailers: Vec::new(),
+    }
+}
+
+/// A stream that is kept open after the response headers were sent, so that DATA frames can
+/// continue to be exchanged in both directions, until either side ends it.
+///
+/// Streams are kept open for 2xx responses to `CONNECT` requests (i.e. tunnels, section 8.3.) and
+/// to the requests that the server's streaming filter selects (e.g. gRPC bidirectional streaming
+/// calls or server-sent events).
+///
+/// Obtained from `SimpleServer::open_stream` (or `SimpleServer::tunnel`).
+pub struct OpenStream<'a, TS> where TS: TransportStream + 'a {
+    stream_id: StreamId,
+    conn: &'a mut ServerConnection<SimpleFactory>,
+    sender: &'a mut TS,
+}
+
+/// A stream that was taken over for tunneling, after a `CONNECT` request was answered with a 2xx
+/// response. The DATA frames of the stream carry the tunneled bytes.
+pub type Tunnel<'a, TS> = OpenStream<'a, TS>;
+
+impl<'a, TS> OpenStream<'a, TS> where TS: TransportStream + 'a {
+    /// Returns the ID of the stream.
+    pub fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+
+    /// Returns the bytes that the client sent on the stream since the last call.
+    pub fn take_received(&mut self) -> HttpResult<Vec<u8>> {
+        let stream = try!(self.stream_mut());
+        Ok(mem::replace(&mut stream.body, Vec::new()))
+    }
+
+    /// Returns whether the client ended its side of the stream, i.e. no more bytes will be
+    /// received on it.
+    pub fn is_closed_remote(&self) -> bool {
+        self.conn.state.get_stream_ref(self.stream_id).map_or(true, |s| s.is_closed_remote())
+    }
+
+    /// Sends the headers of the response on a stream whose response was deferred (see `defer`),
+    /// blocking until they are written out. The body can then be sent using `send` and `close`.
+    ///
+    /// Informational (1xx) headers can be sent first, by calling the method once for each of
+    /// them before the final headers. Headers that are out of sequence (e.g. a second set of
+    /// final headers) result in an `HttpError::InvalidHeaderPhase`, without sending anything.
+    pub fn start_response(&mut self, headers: Vec<Header<'static, 'static>>) -> HttpResult<()> {
+        try!(self.stream_mut());
+        try!(self.conn.queue_response(headers, self.stream_id, EndStream::No));
+        self.flush()
+    }
+
+    /// Sends the given bytes on the stream, blocking until they are written out.
+    ///
+    /// The final headers of the response need to have been sent already; otherwise an
+    /// `HttpError::InvalidHeaderPhase` is returned.
+    pub fn send(&mut self, data: &[u8]) -> HttpResult<()> {
+        try!(self.stream_mut());
+        try!(self.conn.advance_response_phase(self.stream_id, &[HeaderEvent::Data]));
+        try!(self.stream_mut()).push_data(data);
+        self.flush()
+    }
+
+    /// Ends our side of the stream, once all the bytes sent on it are written out.
+    pub fn close(mut self) -> HttpResult<()> {
+        try!(self.stream_mut());
+        try!(self.conn.advance_response_phase(self.stream_id, &[HeaderEvent::EndStream]));
+        try!(self.stream_mut()).finish_data();
+        self.flush()
+    }
+
+    /// Ends our side of the stream with the given trailers, once all the bytes sent on it are
+    /// written out, blocking until the trailers are written out as well.
+    pub fn send_trailers(mut self, trailers: Vec<Header<'static, 'static>>) -> HttpResult<()> {
+        try!(self.stream_mut());
+        try!(self.conn.queue_trailers(trailers, self.stream_id));
+        try!(self.stream_mut()).finish_data();
+        self.flush()
+    }
+
+    fn stream_mut(&mut self) -> HttpResult<&mut DefaultStream> {
+        self.conn.state.get_stream_mut(self.stream_id).ok_or(HttpError::UnknownStreamId)
+    }
+
+    fn flush(&mut self) -> HttpResult<()> {
+        while let SendStatus::Sent = try!(self.conn.send_next(self.sender)) {}
+        Ok(())
+    }
+}
+
+/// Reads the body of a streaming request as it arrives, e.g. so that a proxy can pipe an upload
+/// through to an upstream server without buffering all of it.
+///
+/// Reading blocks while none of the body is buffered, handling the next frames received on the
+/// connection (as `SimpleServer::handle_next` would, so other requests keep being served) until
+/// more of it arrives. The end of the body is reached once the client ends its side of the stream.
+///
+/// The bytes are released from flow control as they are read: each read sends WINDOW_UPDATE
+/// frames for the connection and the stream, so that the client can keep sending.
+///
+/// Obtained from `SimpleServer::request_body`.
+pub struct RequestBody<'a, TS, H>
+        where TS: TransportStream + 'a,
+              H: FnMut(ServerRequest) -> Response<'static, 'static> + 'a {
+    stream_id: StreamId,
+    server: &'a mut SimpleServer<TS, H>,
+}
+
+impl<'a, TS, H> RequestBody<'a, TS, H>
+        where TS: TransportStream + 'a,
+              H: FnMut(ServerRequest) -> Response<'static, 'static> + 'a {
+    /// Returns the ID of the stream that the request was received on.
+    pub fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+
+    /// Moves up to `buf.len()` bytes of the body that were received so far into the buffer,
+    /// releasing them from flow control. Returns `None` once the whole body was read.
+    fn read_received(&mut self, buf: &mut [u8]) -> HttpResult<Option<usize>> {
+        let (len, closed_remote) = match self.server.conn.state.get_stream_mut(self.stream_id) {
+            Some(stream) => {
+                let len = cmp::min(buf.len(), stream.body.len());
+                buf[..len].copy_from_slice(&stream.body[..len]);
+                stream.body.drain(..len);
+                (len, stream.is_closed_remote())
+            },
+            None => (0, true),
+        };
+        if len == 0 {
+            return Ok(if closed_remote { None } else { Some(0) });
+        }
+
+        let increment = len as u32;
+        let server = &mut *self.server;
+        // No more DATA can arrive on a stream that the client ended, but the connection's window
+        // needs to be replenished regardless.
+        if !closed_remote {
+            try!(server.conn.send_window_update(self.stream_id, increment, &mut server.sender));
+        }
+        try!(server.conn.send_window_update(0, increment, &mut server.sender));
+        Ok(Some(len))
+    }
+}
+
+impl<'a, TS, H> io::Read for RequestBody<'a, TS, H>
+        where TS: TransportStream + 'a,
+              H: FnMut(ServerRequest) -> Response<'static, 'static> + 'a {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match try!(self.read_received(buf).map_err(into_io_error)) {
+                None => return Ok(0),
+                Some(0) => try!(self.server.handle_next().map_err(into_io_error)),
+                Some(len) => return Ok(len),
+            }
+        }
+    }
+}
+
+/// The error returned once the reader thread of a duplex server stopped, after having reported
+/// the error that stopped it.
+fn reader_stopped() -> HttpError {
+    HttpError::from(io::Error::new(io::ErrorKind::BrokenPipe, "The reader thread stopped"))
+}
+
+/// Wraps the given `HttpError` into an `io::Error`, unless it is one already.
+fn into_io_error(err: HttpError) -> io::Error {
+    match err {
+        HttpError::IoError(e) => e,
+        err => io::Error::new(io::ErrorKind::Other, err),
+    }
 }
 
 /// A simple implementation of the `http::server::StreamFactory` trait that creates new
@@ -31,11 +401,26 @@ impl StreamFactory for SimpleFactory {
 
 /// The struct implements a simple HTTP/2 server that allows users to register a request handler (a
 /// callback taking a `ServerRequest` and returning a `Response`) which is run on all received
-/// requests.
+/// requests. Types implementing the `service::Service` trait (e.g. routing or middleware stacks)
+/// can be used instead of a closure by creating the server using `with_service`.
+///
+/// `CONNECT` requests (e.g. sent to a forward proxy) are handed to the handler as soon as their
+/// headers arrive, rather than once the client ends the stream. If the handler responds with a 2xx
+/// status, the stream is kept open as a tunnel, which can be taken over using `tunnel`. All other
+/// requests are passed on exactly as received, including any absolute-form `:path`. Streaming
+/// endpoints work the same way, for the requests selected using `set_streaming_filter`: their
+/// streams can be taken over using `open_stream`, and their bodies read as they arrive using
+/// `request_body`.
 ///
 /// The `handle_next` method needs to be called regularly in order to have the server process
 /// received frames, as well as send out the responses.
 ///
+/// Optionally, an idle timeout can be set with `set_idle_timeout`. Once no frame is received for
+/// the given duration while there are no active streams, the server sends a GOAWAY frame to the
+/// client and `handle_next` returns an `HttpError::ConnectionIdle` error. The timeouts and the
+/// settings advertised to the client can also be given all at once in an `Http2Config`, by
+/// creating the server using `with_config`.
+///
 /// This is an exceedingly simple implementation of an HTTP/2 server and is mostly an example of
 /// how the `solicit::http` API can be used to make one.
 ///
@@ -69,6 +454,7 @@ impl StreamFactory for SimpleFactory {
 ///                     Header::new(b"x-solicit".to_vec(), b"Hello, World!".to_vec()),
 ///                 ],
 ///                 body: vec![65],
+///                 trailers: vec![],
 ///                 stream_id: req.stream_id,
 ///            }
 ///         }).unwrap();
@@ -91,96 +477,997 @@ pub struct SimpleServer<TS, H>
     conn: ServerConnection<SimpleFactory>,
     receiver: TS,
     sender: TS,
+    /// Keeps the part of a frame that was read before a read timed out.
+    frame_reader: FrameReader,
     handler: H,
+    /// The ID of the last stream for which the handler was invoked.
+    last_stream_id: StreamId,
+    /// How long the connection is allowed to go without receiving any frames while there are no
+    /// active streams.
+    idle_timeout: Option<Duration>,
+    /// How long the connection can go without receiving any frames before a PING is sent.
+    keepalive: Option<Duration>,
+    /// How long the connection has gone without receiving any frames, as the sum of the read
+    /// timeouts that elapsed since the last one.
+    quiet_for: Duration,
+    /// The progress of the connection preface exchange.
+    handshake: Handshake,
+    /// Called with the timing of each request once its response is flushed.
+    timing_hook: Option<Box<FnMut(&RequestTiming) + Send>>,
+    /// Requests that take longer than this to complete are logged.
+    slow_request_threshold: Option<Duration>,
+    /// Records each request once its response is flushed.
+    access_logger: Option<Box<AccessLogger + Send>>,
+    /// The address of the client, if the transport stream knows it.
+    peer_addr: Option<SocketAddr>,
+    /// The requests that are timed, until their responses are flushed.
+    pending_requests: HashMap<StreamId, PendingRequest>,
+    /// Selects the requests that are handed to the handler as soon as their headers arrive and
+    /// whose streams are kept open after a 2xx response.
+    streaming_filter: Option<Box<FnMut(&ServerRequest) -> bool + Send>>,
+    /// The streams that are kept open after their response headers were sent.
+    open_streams: HashSet<StreamId>,
+    /// How many responses are prepared at most before they are flushed.
+    max_queued_responses: Option<usize>,
+    /// How long the handler can spend on requests before the responses prepared so far are
+    /// flushed.
+    flush_deadline: Option<Duration>,
+    /// The number of responses that were dropped, as their streams were unknown or closed.
+    dropped_responses: u64,
+    /// Called with each response that is dropped.
+    dropped_response_hook: Option<Box<FnMut(&StaticResponse) + Send>>,
+    /// How many frames are sent at most by a single flush of the streams.
+    max_frames_per_flush: Option<usize>,
+    /// How many bytes are sent at most (give or take a frame) by a single flush of the streams.
+    max_bytes_per_flush: Option<usize>,
+    /// Whether the last flush ran out of its budget, possibly leaving data unsent.
+    flush_pending: bool,
+    /// The frames read by the reader thread, if the server is duplex (see `enable_duplex`).
+    duplex: Option<DuplexReader>,
+}
+
+/// The receiving end of the reader thread of a duplex `SimpleServer`.
+struct DuplexReader {
+    /// Lets the thread start reading, which it only does once the handshake is done, as the
+    /// preface is read by the server itself.
+    start: Option<Sender<()>>,
+    /// The frames read by the thread, along with the errors that it ran into. The thread stops
+    /// after the first error that is not a read timeout.
+    frames: Receiver<HttpResult<RawFrame<'static>>>,
+}
+
+impl DuplexReader {
+    /// Lets the thread start reading, unless it already does.
+    fn start(&mut self) {
+        if let Some(start) = self.start.take() {
+            // The thread only stops once it got to read, so it is still there.
+            let _ = start.send(());
+        }
+    }
+}
+
+/// A `ReceiveFrame` that provides a single frame that was already read, or the error that reading
+/// it failed with, so that the connection reacts to it as to any other receive error (e.g. with
+/// a GOAWAY for a frame that is too large).
+struct ReadyFrame {
+    res: Option<HttpResult<RawFrame<'static>>>,
+    raw: Option<RawFrame<'static>>,
+}
+
+impl ReadyFrame {
+    fn new(res: HttpResult<RawFrame<'static>>) -> ReadyFrame {
+        ReadyFrame { res: Some(res), raw: None }
+    }
+}
+
+impl ReceiveFrame for ReadyFrame {
+    fn recv_frame(&mut self) -> HttpResult<HttpFrame> {
+        match self.res.take() {
+            Some(Ok(raw)) => self.raw = Some(raw),
+            Some(Err(e)) => return Err(e),
+            None => {},
+        }
+        match self.raw {
+            Some(ref raw) => HttpFrame::from_raw(raw),
+            None => Err(reader_stopped()),
+        }
+    }
+}
+
+/// A `SendFrame` that counts the bytes of the frames that it passes on.
+struct CountingSender<'a, S> where S: SendFrame + 'a {
+    sender: &'a mut S,
+    written: usize,
+}
+
+impl<'a, S> SendFrame for CountingSender<'a, S> where S: SendFrame + 'a {
+    fn send_frame<F: FrameIR>(&mut self, frame: F) -> HttpResult<()> {
+        let mut buf = io::Cursor::new(Vec::with_capacity(1024));
+        try!(frame.serialize_into(&mut buf));
+        let buf = buf.into_inner();
+        self.written += buf.len();
+        self.sender.send_frame(RawFrame::from(buf))
+    }
+}
+
+impl<TS> SimpleServer<TS, ServiceHandler> where TS: TransportStream {
+    /// Creates a new `SimpleServer` whose requests are handled by the given `Service`, using the
+    /// given configuration for the connection.
+    pub fn with_service<S>(stream: TS, service: S, config: Http2Config)
+            -> HttpResult<SimpleServer<TS, ServiceHandler>>
+            where S: Service + Send + 'static {
+        SimpleServer::with_config(stream, into_handler(service), config)
+    }
+}
+
+/// Tracks whether a `SimpleServer` has exchanged the connection preface with its client.
+#[derive(Clone, PartialEq, Debug)]
+enum Handshake {
+    /// The preface is yet to be exchanged, by the given deadline, if any. The bytes of the
+    /// client's magic that were already read are kept, in case a read fails part way through it.
+    Pending(Option<Instant>, Vec<u8>),
+    /// The client's magic was received and the server's SETTINGS sent, while the client's
+    /// SETTINGS frame is yet to be received (by the server's `FrameReader`).
+    AwaitingSettings(Option<Instant>),
+    /// The preface was exchanged and frames can be handled.
+    Done,
+    /// The preface exchange failed, so the connection cannot be used any further.
+    Failed,
+}
+
+impl<TS, H> SimpleServer<TS, H>
+        where TS: TransportStream + Send + 'static,
+              H: FnMut(ServerRequest) -> Response<'static, 'static> {
+    /// Makes the server duplex: frames are read from the client by a thread of their own, so
+    /// that `handle_next` can keep sending the queued data while waiting for the next frame, and
+    /// handle each frame as soon as it is ready, instead of only once everything that can be sent
+    /// is sent. Large uploads and downloads on the same connection then progress simultaneously,
+    /// and the client is never kept from writing by a server that is busy writing itself.
+    ///
+    /// A duplex server flushes a single frame at a time in between checking for frames (unless
+    /// `set_max_frames_per_flush` or `set_max_bytes_per_flush` allow for more). As the data that
+    /// is not sent yet is flushed by the following `handle_next` calls, the server needs to keep
+    /// calling it, as usual.
+    ///
+    /// The reader thread starts once the handshake is done and stops when the connection fails or
+    /// is closed.
+    pub fn enable_duplex(&mut self) -> HttpResult<()> {
+        if self.duplex.is_some() {
+            return Ok(());
+        }
+        let mut receiver = try!(self.receiver.try_split());
+        let (start_tx, start_rx) = mpsc::channel();
+        let (frames_tx, frames_rx) = mpsc::channel();
+        let connection_id = self.conn.id();
+        // The frames may be as large as the server advertises in its preface.
+        let max_frame_size = self.conn.config().max_frame_size;
+        thread::spawn(move || {
+            if start_rx.recv().is_err() {
+                return;
+            }
+            // Read timeouts are passed on to the server, but the part of the frame that was
+            // read before one is kept, so the frame is continued by the next read.
+            let mut reader = FrameReader::new();
+            loop {
+                let res = reader.read_frame(&mut receiver, max_frame_size);
+                let fatal = match res {
+                    Ok(_) => false,
+                    Err(HttpError::IoError(ref e)) => !is_timeout(e),
+                    Err(_) => true,
+                };
+                if frames_tx.send(res).is_err() || fatal {
+                    break;
+                }
+            }
+            debug!("{}: Reader thread halting", connection_id);
+        });
+
+        let mut duplex = DuplexReader { start: Some(start_tx), frames: frames_rx };
+        if self.is_handshake_done() {
+            duplex.start();
+        }
+        self.duplex = Some(duplex);
+        Ok(())
+    }
 }
 
 impl<TS, H> SimpleServer<TS, H>
         where TS: TransportStream, H: FnMut(ServerRequest) -> Response<'static, 'static> {
     /// Creates a new `SimpleServer` that will use the given `TransportStream` to communicate to
     /// the client. Assumes that the stream is fully uninitialized -- no preface sent or read yet.
-    pub fn new(mut stream: TS, handler: H) -> HttpResult<SimpleServer<TS, H>> {
-        // First assert that the preface is received
-        let mut preface = [0; 24];
-        TransportStream::read_exact(&mut stream, &mut preface).unwrap();
-        if &preface != b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n" {
-            return Err(HttpError::UnableToConnect);
-        }
+    ///
+    /// The call does not block: the connection preface is exchanged by the first `handle_next`
+    /// call. If the client sends something other than the HTTP/2 magic, that call returns an
+    /// `HttpError::InvalidPreface` error.
+    pub fn new(stream: TS, handler: H) -> HttpResult<SimpleServer<TS, H>> {
+        SimpleServer::with_config(stream, handler, Http2Config::default())
+    }
+
+    /// Creates a new `SimpleServer`, same as `new`, except that the client is given at most
+    /// `timeout` (counted from the creation of the server) to send its full connection preface
+    /// (the magic, followed by its SETTINGS frame).
+    ///
+    /// If the preface is not received in time, the `handle_next` call performing the handshake
+    /// returns an `HttpError::HandshakeTimeout` error. This prevents clients that connect, but
+    /// never (or only very slowly) send the preface from tying up the server indefinitely.
+    ///
+    /// The underlying `TransportStream` needs to support read timeouts.
+    pub fn with_handshake_timeout(stream: TS, handler: H, timeout: Duration)
+            -> HttpResult<SimpleServer<TS, H>> {
+        let config = Http2Config::default().handshake_timeout(Some(timeout));
+        SimpleServer::with_config(stream, handler, config)
+    }
 
-        let conn = HttpConnection::new(HttpScheme::Http);
+    /// Creates a new `SimpleServer` whose connection uses the given configuration.
+    ///
+    /// This is how a server advertises larger flow control windows or frame sizes, or a limit on
+    /// the concurrent streams, instead of the protocol defaults that `new` sticks to; a plain list
+    /// of settings can be turned into a config with `Http2Config::with_settings`.
+    ///
+    /// The config's settings are advertised to the client in the server's preface and its
+    /// timeouts are applied as by `with_handshake_timeout`, `set_idle_timeout` and
+    /// `set_keepalive`, respectively.
+    pub fn with_config(stream: TS, handler: H, config: Http2Config)
+            -> HttpResult<SimpleServer<TS, H>> {
+        let handshake_timeout = config.handshake_timeout;
+        let idle_timeout = config.idle_timeout;
+        let keepalive = config.keepalive;
+        let conn = HttpConnection::with_config(HttpScheme::Http, config);
         let state = DefaultSessionState::<ServerMarker, _>::new();
         let conn = ServerConnection::with_connection(conn, state, SimpleFactory);
+
+        let peer_addr = stream.peer_addr();
         let mut server = SimpleServer {
             conn: conn,
             receiver: try!(stream.try_split()),
             sender: stream,
+            frame_reader: FrameReader::new(),
             handler: handler,
+            last_stream_id: 0,
+            idle_timeout: None,
+            keepalive: None,
+            quiet_for: Duration::from_secs(0),
+            handshake: Handshake::Pending(handshake_timeout.map(|timeout| {
+                Instant::now() + timeout
+            }), Vec::new()),
+            timing_hook: None,
+            slow_request_threshold: None,
+            access_logger: None,
+            peer_addr: peer_addr,
+            pending_requests: HashMap::new(),
+            streaming_filter: None,
+            open_streams: HashSet::new(),
+            max_queued_responses: None,
+            flush_deadline: None,
+            dropped_responses: 0,
+            dropped_response_hook: None,
+            max_frames_per_flush: None,
+            max_bytes_per_flush: None,
+            flush_pending: false,
+            duplex: None,
         };
+        if idle_timeout.is_some() {
+            try!(server.set_idle_timeout(idle_timeout));
+        }
+        if keepalive.is_some() {
+            try!(server.set_keepalive(keepalive));
+        }
 
-        // Initialize the connection -- send own settings and process the peer's
-        try!(server.conn.send_settings(&mut server.sender));
-        try!(server.conn.expect_settings(
-            &mut TransportReceiveFrame::new(&mut server.receiver),
-            &mut server.sender));
-
-        // Set up done
         Ok(server)
     }
 
+    /// Returns `true` once the connection preface has been exchanged with the client.
+    pub fn is_handshake_done(&self) -> bool {
+        self.handshake == Handshake::Done
+    }
+
+    /// Performs the server side of the connection preface exchange, failing if it does not
+    /// complete by the given deadline (if any).
+    ///
+    /// A read that would block leaves the exchange where it is, so that the following call
+    /// continues it: the bytes read up to that point are kept, as is the server's SETTINGS frame
+    /// having been sent.
+    fn handshake(&mut self) -> HttpResult<()> {
+        if let Handshake::Pending(deadline, ref mut received) = self.handshake {
+            // First assert that the preface is received
+            let res = match deadline {
+                None => self.conn.continue_preface(&mut self.receiver, received),
+                Some(deadline) => {
+                    let mut reader = DeadlineReader::new(&mut self.receiver, deadline);
+                    self.conn.continue_preface(&mut reader, received)
+                },
+            };
+            try!(handshake_result(res, deadline));
+
+            // Initialize the connection -- send own settings and process the peer's
+            try!(self.conn.send_preface(&mut self.sender));
+        }
+        if let Handshake::Pending(deadline, _) = self.handshake {
+            self.handshake = Handshake::AwaitingSettings(deadline);
+        }
+
+        if let Handshake::AwaitingSettings(deadline) = self.handshake {
+            if let Some(deadline) = deadline {
+                try!(self.receiver.set_read_timeout(Some(try!(time_left(deadline)))));
+            }
+            let res = self.conn.expect_settings(
+                &mut TransportReceiveFrame::with_reader(&mut self.receiver, &mut self.frame_reader),
+                &mut self.sender);
+            try!(handshake_result(res, deadline));
+            if deadline.is_some() {
+                // Back to the timeout that applies to the established connection.
+                try!(self.receiver.set_read_timeout(self.read_timeout()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets the idle timeout of the connection. If `None` is given (the default), the connection
+    /// is never considered idle.
+    ///
+    /// The timeout is implemented as a read timeout on the underlying `TransportStream`, which
+    /// means that the stream needs to support them.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> HttpResult<()> {
+        self.idle_timeout = timeout;
+        let read_timeout = self.read_timeout();
+        try!(self.receiver.set_read_timeout(read_timeout));
+        Ok(())
+    }
+
+    /// Returns the currently configured idle timeout.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Sets the keepalive interval of the connection: once no frame is received for the given
+    /// duration, a PING is sent to the client. If `None` is given (the default), no PINGs are
+    /// sent.
+    ///
+    /// When an idle timeout is also set, PINGs are only sent while there are active streams, so
+    /// that their acknowledgements do not keep an otherwise idle connection open.
+    ///
+    /// Like the idle timeout, the keepalive is implemented as a read timeout on the underlying
+    /// `TransportStream`.
+    pub fn set_keepalive(&mut self, interval: Option<Duration>) -> HttpResult<()> {
+        self.keepalive = interval;
+        let read_timeout = self.read_timeout();
+        try!(self.receiver.set_read_timeout(read_timeout));
+        Ok(())
+    }
+
+    /// Returns the currently configured keepalive interval.
+    pub fn keepalive(&self) -> Option<Duration> {
+        self.keepalive
+    }
+
+    /// Returns the read timeout that the underlying stream needs so that both the idle timeout
+    /// and the keepalive can be handled.
+    fn read_timeout(&self) -> Option<Duration> {
+        match (self.idle_timeout, self.keepalive) {
+            (Some(idle), Some(keepalive)) => Some(cmp::min(idle, keepalive)),
+            (idle, keepalive) => idle.or(keepalive),
+        }
+    }
+
+    /// Sets a callback that is invoked with the `RequestTiming` of every request, once its
+    /// response is fully flushed to the client.
+    pub fn set_timing_hook<F>(&mut self, hook: F)
+            where F: FnMut(&RequestTiming) + Send + 'static {
+        self.timing_hook = Some(Box::new(hook));
+    }
+
+    /// Sets the logger that records every request, once its response is fully flushed to the
+    /// client (see the `access_log` module).
+    pub fn set_access_logger<L>(&mut self, logger: L) where L: AccessLogger + Send + 'static {
+        self.access_logger = Some(Box::new(logger));
+    }
+
+    /// Returns the address of the client, if the underlying stream knows it.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Sets a callback that is invoked whenever the client sends a SETTINGS frame, with the
+    /// settings that it carries and the resulting state of all of the client's settings. This
+    /// lets the application adapt to mid-connection changes, e.g. stop planning pushes once the
+    /// client disables them.
+    ///
+    /// The SETTINGS frame of the client's preface is passed to the callback as well, as long as
+    /// the callback is set before the handshake completes (i.e. before the first `handle_next`).
+    pub fn set_settings_hook<F>(&mut self, hook: F)
+            where F: FnMut(&[HttpSetting], &SettingsState) + Send + 'static {
+        self.conn.set_settings_hook(hook);
+    }
+
+    /// Returns the client's settings that are currently in effect.
+    pub fn peer_settings(&self) -> &SettingsState {
+        self.conn.peer_settings()
+    }
+
+    /// Sets the duration after which a request is considered slow. The method, path and duration
+    /// of slow requests are logged (at the `warn` level) once their response is flushed. If
+    /// `None` is given (the default), no requests are logged.
+    pub fn set_slow_request_threshold(&mut self, threshold: Option<Duration>) {
+        self.slow_request_threshold = threshold;
+    }
+
+    /// Sets a filter that selects the requests of streaming endpoints (e.g. gRPC bidirectional
+    /// streaming calls). Such requests are handed to the handler as soon as their headers arrive
+    /// (the body received so far, if any, is included), and if the handler responds with a 2xx
+    /// status, the stream is kept open after the response body is sent, so that both sides can
+    /// continue to send DATA on it (see `open_stream`).
+    ///
+    /// The filter can be invoked more than once for the same request, until the request is
+    /// handed to the handler.
+    pub fn set_streaming_filter<F>(&mut self, filter: F)
+            where F: FnMut(&ServerRequest) -> bool + Send + 'static {
+        self.streaming_filter = Some(Box::new(filter));
+    }
+
+    /// Sets how many responses are prepared at most before they are flushed to the client. When
+    /// many requests are ready at once (e.g. under bursty load), this bounds how long the first
+    /// responses wait behind the handler working on the later ones. If `None` is given (the
+    /// default), the handler is invoked for all of the ready requests before any of their
+    /// responses are flushed.
+    ///
+    /// At least one response is always prepared before flushing, so `Some(0)` behaves like
+    /// `Some(1)`.
+    pub fn set_max_queued_responses(&mut self, max: Option<usize>) {
+        self.max_queued_responses = max;
+    }
+
+    /// Returns the currently configured maximum number of responses queued before a flush.
+    pub fn max_queued_responses(&self) -> Option<usize> {
+        self.max_queued_responses
+    }
+
+    /// Sets how long the handler can spend on ready requests before the responses prepared so
+    /// far are flushed to the client, after which the remaining requests are handled. If `None`
+    /// is given (the default), there is no such deadline.
+    ///
+    /// The deadline is only checked in between invocations of the handler, so a single slow
+    /// request still delays its own response (but not the ones prepared before it).
+    pub fn set_flush_deadline(&mut self, deadline: Option<Duration>) {
+        self.flush_deadline = deadline;
+    }
+
+    /// Returns the currently configured flush deadline.
+    pub fn flush_deadline(&self) -> Option<Duration> {
+        self.flush_deadline
+    }
+
+    /// Sets how many frames are sent at most each time the streams are flushed, i.e. after each
+    /// frame that is handled. If `None` is given (the default), the streams are flushed until
+    /// there is nothing left that can be sent.
+    ///
+    /// Bounding the flush keeps a single large response from monopolizing `handle_next`: the
+    /// rest of its data is sent by the following calls, in between the frames received from the
+    /// client (e.g. the WINDOW_UPDATE frames that let it continue and PINGs that need to be
+    /// acknowledged in time). Since `handle_next` blocks until the next frame arrives, an idle
+    /// timeout or a keepalive should be set as well, so that the remaining data is also flushed
+    /// when the client stays quiet.
+    ///
+    /// At least one frame is always sent, so `Some(0)` behaves like `Some(1)`.
+    pub fn set_max_frames_per_flush(&mut self, max: Option<usize>) {
+        self.max_frames_per_flush = max;
+    }
+
+    /// Returns the currently configured maximum number of frames sent per flush.
+    pub fn max_frames_per_flush(&self) -> Option<usize> {
+        self.max_frames_per_flush
+    }
+
+    /// Sets how many bytes are sent at most each time the streams are flushed, same as
+    /// `set_max_frames_per_flush`. The flush stops once the limit is reached, so the frame that
+    /// reaches it is still sent in full. If `None` is given (the default), there is no such limit.
+    pub fn set_max_bytes_per_flush(&mut self, max: Option<usize>) {
+        self.max_bytes_per_flush = max;
+    }
+
+    /// Returns the currently configured maximum number of bytes sent per flush.
+    pub fn max_bytes_per_flush(&self) -> Option<usize> {
+        self.max_bytes_per_flush
+    }
+
+    /// Sets a callback that is invoked with every response that the handler returns for a stream
+    /// that is unknown or already closed (e.g. reset by the client before the handler finished).
+    /// Such responses are dropped, leaving the connection unaffected.
+    pub fn set_dropped_response_hook<F>(&mut self, hook: F)
+            where F: FnMut(&StaticResponse) + Send + 'static {
+        self.dropped_response_hook = Some(Box::new(hook));
+    }
+
+    /// Returns the number of responses that were dropped so far, as their streams were unknown
+    /// or already closed.
+    pub fn dropped_responses(&self) -> u64 {
+        self.dropped_responses
+    }
+
+    /// Returns when the connection was established and when it last received and sent a frame,
+    /// e.g. for closing connections past a certain age.
+    pub fn activity(&self) -> ConnectionActivity {
+        self.conn.activity()
+    }
+
+    /// Returns the ID of the connection, e.g. for attributing the server's log lines and errors
+    /// to it when serving several connections.
+    pub fn connection_id(&self) -> ConnectionId {
+        self.conn.id()
+    }
+
+    /// Returns the IDs of the streams that are currently kept open (including tunnels), in
+    /// ascending order.
+    pub fn open_streams(&self) -> Vec<StreamId> {
+        self.open_stream_ids(|_| true)
+    }
+
+    /// Takes over the open stream with the given ID, if any.
+    pub fn open_stream(&mut self, stream_id: StreamId) -> Option<OpenStream<TS>> {
+        if !self.open_streams.contains(&stream_id) {
+            return None;
+        }
+        Some(OpenStream {
+            stream_id: stream_id,
+            conn: &mut self.conn,
+            sender: &mut self.sender,
+        })
+    }
+
+    /// Ends the open stream with the given ID with the given trailers, once the data sent on it
+    /// is written out (see `OpenStream::send_trailers`).
+    ///
+    /// Returns an `HttpError::UnknownStreamId` if there is no such open stream.
+    pub fn send_trailers(&mut self, stream_id: StreamId, trailers: Vec<Header<'static, 'static>>)
+            -> HttpResult<()> {
+        match self.open_stream(stream_id) {
+            Some(stream) => stream.send_trailers(trailers),
+            None => Err(HttpError::UnknownStreamId),
+        }
+    }
+
+    /// Returns a reader of the rest of the body of the streaming request on the open stream with
+    /// the given ID, if any (see `RequestBody`).
+    ///
+    /// The body needs to be read before our side of the stream is closed: once both sides are
+    /// closed, the stream is gone, along with any of the body that was not read yet.
+    pub fn request_body(&mut self, stream_id: StreamId) -> Option<RequestBody<TS, H>> {
+        if !self.open_streams.contains(&stream_id) {
+            return None;
+        }
+        Some(RequestBody {
+            stream_id: stream_id,
+            server: self,
+        })
+    }
+
+    /// Returns the IDs of the streams that are currently open as tunnels, in ascending order.
+    pub fn tunnels(&self) -> Vec<StreamId> {
+        self.open_stream_ids(|s| is_connect(&s.pseudo_headers))
+    }
+
+    /// Takes over the stream with the given ID for tunneling, if it is a tunnel established by a
+    /// `CONNECT` request.
+    pub fn tunnel(&mut self, stream_id: StreamId) -> Option<Tunnel<TS>> {
+        let connect = self.conn.state.get_stream_ref(stream_id)
+                                     .map_or(false, |s| is_connect(&s.pseudo_headers));
+        if connect { self.open_stream(stream_id) } else { None }
+    }
+
+    /// Returns the IDs of the open streams that are not fully closed yet and that match the given
+    /// predicate, in ascending order.
+    fn open_stream_ids<P>(&self, predicate: P) -> Vec<StreamId>
+            where P: Fn(&DefaultStream) -> bool {
+        let state = &self.conn.state;
+        let mut ids: Vec<_> = self.open_streams.iter().cloned().filter(|&id| {
+            state.get_stream_ref(id).map_or(false, |s| !s.is_closed() && predicate(s))
+        }).collect();
+        ids.sort();
+        ids
+    }
+
+    /// Returns whether requests need to be timed, which they also are in order to be traced or
+    /// logged.
+    fn is_timing(&self) -> bool {
+        self.timing_hook.is_some() || self.slow_request_threshold.is_some() ||
+            self.access_logger.is_some() || tracing::is_enabled()
+    }
+
     /// Handles the next incoming frame, blocking to receive it if nothing is available on the
     /// underlying stream.
     ///
+    /// The first call performs the connection preface exchange with the client instead, returning
+    /// once it is complete (or with an error if it fails). A read during the exchange that would
+    /// block (e.g. on a non-blocking stream) is returned as an `HttpError::IoError` without
+    /// failing it: the bytes read so far are kept and the next call continues the exchange. Once
+    /// the exchange failed, all further calls fail with an `HttpError::ConnectionClosed` error.
+    ///
     /// Handling the frame can trigger the handler callback. Any responses returned by the handler
-    /// are immediately flushed out to the client (blocking the call until it's done).
+    /// are immediately flushed out to the client (blocking the call until it's done): either all
+    /// at once, or in batches, as configured by `set_max_queued_responses` and
+    /// `set_flush_deadline`.
+    ///
+    /// If an idle timeout is set and it elapses before a frame is received, the connection is
+    /// closed with a GOAWAY frame, unless there are still active streams (in which case the call
+    /// returns without handling anything). An `HttpError::ConnectionIdle` error is returned when
+    /// the connection gets closed. A read timeout in the middle of a frame keeps the part of the
+    /// frame that was already read, so that the following call picks up where it left off.
+    ///
+    /// When the flush is bounded (see `set_max_frames_per_flush`), data that did not fit into the
+    /// budget is sent by the following calls, after handling their frame or read timeout.
     pub fn handle_next(&mut self) -> HttpResult<()> {
-        try!(self.conn.handle_next_frame(
-            &mut TransportReceiveFrame::new(&mut self.receiver),
-            &mut self.sender));
-        let responses = try!(self.handle_requests());
-        try!(self.prepare_responses(responses));
-        try!(self.flush_streams());
+        match self.handshake {
+            Handshake::Pending(..) | Handshake::AwaitingSettings(_) => {
+                if let Err(e) = self.handshake() {
+                    // A read that would block (or timed out ahead of the handshake deadline) loses
+                    // nothing, so the exchange can go on with the next call.
+                    let resumable = match e {
+                        HttpError::IoError(ref e) => is_timeout(e),
+                        _ => false,
+                    };
+                    if !resumable {
+                        self.handshake = Handshake::Failed;
+                    }
+                    return Err(e);
+                }
+                self.handshake = Handshake::Done;
+                if let Some(ref mut duplex) = self.duplex {
+                    duplex.start();
+                }
+                return Ok(());
+            },
+            Handshake::Failed => return Err(HttpError::ConnectionClosed),
+            Handshake::Done => {},
+        }
+
+        let res = if self.duplex.is_some() {
+            self.handle_ready_frame()
+        } else {
+            self.conn.handle_next_frame(
+                &mut TransportReceiveFrame::with_reader(&mut self.receiver, &mut self.frame_reader),
+                &mut self.sender)
+        };
+        match res {
+            Err(HttpError::IoError(ref e)) if self.read_timeout().is_some() && is_timeout(e) => {
+                try!(self.handle_timeout());
+                if self.flush_pending {
+                    try!(self.flush_streams());
+                    try!(self.reap_streams());
+                }
+                return Ok(());
+            },
+            res => try!(res),
+        };
+        self.quiet_for = Duration::from_secs(0);
+        self.track_requests();
+        loop {
+            let (responses, more) = try!(self.handle_requests());
+            try!(self.prepare_responses(responses));
+            try!(self.flush_streams());
+            if !more {
+                break;
+            }
+        }
         try!(self.reap_streams());
 
         Ok(())
     }
 
-    /// Invokes the request handler for each fully received request. Collects all the responses
-    /// into the returned `Vec`.
-    fn handle_requests(&mut self) -> HttpResult<Vec<StaticResponse>> {
+    /// Handles the next frame read by the reader thread of a duplex server, flushing the streams
+    /// for as long as there is none ready and data is left to send.
+    fn handle_ready_frame(&mut self) -> HttpResult<()> {
+        let res = loop {
+            let next = self.duplex.as_ref().unwrap().frames.try_recv();
+            match next {
+                Ok(res) => break res,
+                Err(TryRecvError::Empty) if self.flush_pending => {
+                    try!(self.flush_streams());
+                    try!(self.reap_streams());
+                },
+                Err(TryRecvError::Empty) => {
+                    match self.duplex.as_ref().unwrap().frames.recv() {
+                        Ok(res) => break res,
+                        Err(_) => return Err(reader_stopped()),
+                    }
+                },
+                Err(TryRecvError::Disconnected) => return Err(reader_stopped()),
+            }
+        };
+        self.conn.handle_next_frame(&mut ReadyFrame::new(res), &mut self.sender)
+    }
+
+    /// Handles the read timeout elapsing, by closing the connection if it has been idle for too
+    /// long, or sending a keepalive PING.
+    fn handle_timeout(&mut self) -> HttpResult<()> {
+        if let Some(timeout) = self.read_timeout() {
+            self.quiet_for += timeout;
+        }
+        let elapsed = self.quiet_for;
+        let active = self.conn.state.iter().next().is_some();
+        if let Some(idle_timeout) = self.idle_timeout {
+            if elapsed >= idle_timeout {
+                return self.handle_idle(active);
+            }
+        }
+        if let Some(keepalive) = self.keepalive {
+            if elapsed >= keepalive && (active || self.idle_timeout.is_none()) {
+                debug!("No frames received for {:?}; sending a keepalive PING", elapsed);
+                try!(self.conn.send_ping(0, &mut self.sender));
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles the idle timeout elapsing. If no streams are active, the connection is closed with
+    /// a GOAWAY(NO_ERROR) frame and a `ConnectionIdle` error is returned.
+    fn handle_idle(&mut self, active: bool) -> HttpResult<()> {
+        if active {
+            debug!("Idle timeout elapsed, but streams are still active");
+            return Ok(());
+        }
+
+        debug!("Idle timeout elapsed; closing the connection");
+        try!(self.conn.send_goaway(self.last_stream_id, ErrorCode::NoError, &mut self.sender));
+        Err(HttpError::ConnectionIdle)
+    }
+
+    /// Starts timing the requests whose HEADERS were just received.
+    fn track_requests(&mut self) {
+        if !self.is_timing() {
+            return;
+        }
+        let pending = &mut self.pending_requests;
+        for (&stream_id, stream) in self.conn.state.iter() {
+            if let Some(ref headers) = stream.headers {
+                pending.entry(stream_id).or_insert_with(|| {
+                    PendingRequest::new(&stream.pseudo_headers, headers)
+                });
+            }
+        }
+    }
+
+    /// Reports the timing of the requests whose responses were fully flushed. Requests whose
+    /// streams are gone without a response (e.g. reset by the client) are no longer timed.
+    fn report_flushed(&mut self) {
+        if self.pending_requests.is_empty() {
+            return;
+        }
+        let in_flight = self.pending_requests.len();
+        let finished: Vec<_> = {
+            let state = &self.conn.state;
+            self.pending_requests.iter().filter_map(|(&stream_id, request)| {
+                match state.get_stream_ref(stream_id) {
+                    None => Some((stream_id, false)),
+                    Some(stream) if stream.is_closed_local() => {
+                        Some((stream_id, request.responded))
+                    },
+                    Some(_) => None,
+                }
+            }).collect()
+        };
+
+        let now = Instant::now();
+        for (stream_id, responded) in finished {
+            let request = self.pending_requests.remove(&stream_id).unwrap();
+            if tracing::is_enabled() {
+                request.span(self.conn.id(), stream_id).finish();
+            }
+            if !responded {
+                continue;
+            }
+            let timing = RequestTiming {
+                stream_id: stream_id,
+                method: &request.method,
+                path: &request.path,
+                duration: now - request.received_at,
+                in_flight: in_flight,
+            };
+            if let Some(threshold) = self.slow_request_threshold {
+                if timing.duration >= threshold {
+                    warn!("Slow request on stream {}: {} {} took {:?}",
+                          stream_id,
+                          String::from_utf8_lossy(timing.method),
+                          String::from_utf8_lossy(timing.path),
+                          timing.duration);
+                }
+            }
+            if let Some(ref mut hook) = self.timing_hook {
+                hook(&timing);
+            }
+            if let Some(ref mut logger) = self.access_logger {
+                let received_at = SystemTime::now() - timing.duration;
+                logger.log(&AccessLogEntry {
+                    stream_id: stream_id,
+                    method: &request.method,
+                    path: &request.path,
+                    status: request.status,
+                    bytes_received: request.bytes_received,
+                    bytes_sent: request.bytes_sent,
+                    received_at: received_at,
+                    duration: timing.duration,
+                    peer_addr: self.peer_addr,
+                });
+            }
+        }
+    }
+
+    /// Invokes the request handler for each fully received request, as well as each streaming
+    /// request (including `CONNECT` requests) that is yet to be answered. Collects the responses
+    /// into the returned `Vec`, each along with whether its request is a streaming one.
+    ///
+    /// Stops early once the batch is full (see `set_max_queued_responses` and
+    /// `set_flush_deadline`), in which case the returned flag is `true`: the responses should be
+    /// flushed before the remaining requests are handled by calling this again.
+    fn handle_requests(&mut self) -> HttpResult<(Vec<(StaticResponse, bool)>, bool)> {
+        let max_queued = self.max_queued_responses;
+        let deadline = self.flush_deadline.map(|deadline| Instant::now() + deadline);
         let handler = &mut self.handler;
-        let closed = self.conn.state.iter()
-                       .filter(|&(_, ref s)| s.is_closed_remote());
-        let responses = closed.map(|(&stream_id, stream)| {
+        let last_stream_id = &mut self.last_stream_id;
+        let streaming_filter = &mut self.streaming_filter;
+        let open_streams = &self.open_streams;
+        // Streams that got reset are already fully closed and get no response. Neither do streams
+        // whose response body is still being sent.
+        let unanswered = self.conn.state.iter().filter(|&(id, ref s)| {
+            !open_streams.contains(id) && !s.is_closed() && !s.is_closed_local() &&
+                !s.has_outgoing_data()
+        });
+        let mut responses = Vec::new();
+        for (&stream_id, stream) in unanswered {
+            if !responses.is_empty() {
+                let full = max_queued.map_or(false, |max| responses.len() >= max) ||
+                           deadline.map_or(false, |deadline| Instant::now() >= deadline);
+                if full {
+                    return Ok((responses, true));
+                }
+            }
+            // Streams are only ever created once their headers arrive, but a stream without
+            // headers cannot be turned into a request, so it is skipped rather than trusted.
+            let headers = match stream.headers {
+                Some(ref headers) => headers,
+                None => continue,
+            };
             let req = ServerRequest {
                 stream_id: stream_id,
-                headers: stream.headers.as_ref().unwrap(),
+                headers: headers,
+                pseudo_headers: &stream.pseudo_headers,
                 body: &stream.body,
+                trailers: stream.trailers.as_ref().map_or(&[], |trailers| &trailers[..]),
             };
-            handler(req)
-        });
+            let streaming = is_connect(&stream.pseudo_headers) ||
+                            streaming_filter.as_mut().map_or(false, |filter| filter(&req));
+            // A request is only complete once the client ended the stream: either with the last
+            // DATA frame or with the HEADERS frame that carries the trailers.
+            if !streaming && !stream.is_closed_remote() {
+                continue;
+            }
+            if stream_id > *last_stream_id {
+                *last_stream_id = stream_id;
+            }
+            responses.push((handler(req), streaming));
+        }
 
-        Ok(responses.collect())
+        Ok((responses, false))
     }
 
     /// Prepares the streams for each of the given responses. Headers for each response are
-    /// immediately sent and the data staged into the streams' outgoing buffer.
-    fn prepare_responses(&mut self, responses: Vec<Response>) -> HttpResult<()> {
-        for response in responses.into_iter() {
-            try!(self.conn.start_response(
-                    response.headers,
-                    response.stream_id,
-                    EndStream::No,
-                    &mut self.sender));
-            let mut stream = self.conn.state.get_stream_mut(response.stream_id).unwrap();
-            stream.set_full_data(response.body);
+    /// queued, to be sent along with the data that is staged into the streams' outgoing buffer
+    /// once the streams are flushed.
+    ///
+    /// Responses without a body end the stream already with the HEADERS frame, in which case
+    /// nothing is staged and the stream is considered closed on our end.
+    ///
+    /// A 2xx response to a streaming request (e.g. a `CONNECT` request) leaves its stream open
+    /// after the response body is sent. So does a deferred response (see `defer`), for which
+    /// nothing is sent until `OpenStream::start_response` is called.
+    ///
+    /// Responses for streams that are unknown or already closed are dropped (see
+    /// `set_dropped_response_hook`).
+    fn prepare_responses(&mut self, responses: Vec<(StaticResponse, bool)>) -> HttpResult<()> {
+        for (response, streaming) in responses.into_iter() {
+            let open = self.conn.state.get_stream_ref(response.stream_id).map_or(false, |s| {
+                !s.is_closed() && !s.is_closed_local()
+            });
+            if !open {
+                self.drop_response(response);
+                continue;
+            }
+            let success = response.status_code().ok().map_or(false, |s| s / 100 == 2);
+            let deferred = response.headers.is_empty();
+            if streaming && (success || deferred) {
+                try!(self.start_open_response(response));
+                continue;
+            }
+            let end_stream = if response.ends_stream_on_headers() {
+                EndStream::Yes
+            } else {
+                EndStream::No
+            };
+            self.mark_responded(&response);
+            try!(self.conn.queue_response(response.headers, response.stream_id, end_stream));
+            {
+                let stream = try!(self.conn.state.get_stream_mut(response.stream_id)
+                                                 .ok_or(HttpError::UnknownStreamId));
+                match end_stream {
+                    EndStream::Yes => stream.close_local(),
+                    EndStream::No => stream.set_full_data(response.body),
+                }
+            }
+            if !response.trailers.is_empty() {
+                try!(self.conn.queue_trailers(response.trailers, response.stream_id));
+            }
         }
 
         Ok(())
     }
 
-    /// Flushes the outgoing buffers of all streams.
+    /// Marks the request that the given response answers as responded to, if it is timed.
+    fn mark_responded(&mut self, response: &StaticResponse) {
+        let received = self.conn.state.get_stream_ref(response.stream_id).map_or(0, |s| {
+            s.body.len()
+        });
+        if let Some(request) = self.pending_requests.get_mut(&response.stream_id) {
+            request.responded = true;
+            request.status = response.status_code().ok();
+            request.bytes_received = received as u64;
+            request.bytes_sent = response.body.len() as u64;
+        }
+    }
+
+    /// Drops a response whose stream is unknown or already closed, counting it and notifying the
+    /// hook, if any.
+    fn drop_response(&mut self, response: StaticResponse) {
+        debug!("{}: Dropping the response for the unknown or closed stream {}",
+               self.conn.id(), response.stream_id);
+        self.dropped_responses += 1;
+        if let Some(ref mut hook) = self.dropped_response_hook {
+            hook(&response);
+        }
+    }
+
+    /// Queues the headers of a response that keeps its stream open and stages its body, leaving
+    /// the stream open for further data.
+    /// The headers of a deferred response are left for `OpenStream::start_response`.
+    fn start_open_response(&mut self, response: StaticResponse) -> HttpResult<()> {
+        debug!("Keeping stream {} open after the response", response.stream_id);
+        self.mark_responded(&response);
+        if response.headers.is_empty() {
+            self.open_streams.insert(response.stream_id);
+            return Ok(());
+        }
+        try!(self.conn.queue_response(response.headers, response.stream_id, EndStream::No));
+        let stream = try!(self.conn.state.get_stream_mut(response.stream_id)
+                                         .ok_or(HttpError::UnknownStreamId));
+        stream.push_data(&response.body);
+        self.open_streams.insert(response.stream_id);
+
+        Ok(())
+    }
+
+    /// Flushes the outgoing buffers of all streams, as far as the flush budget allows, reporting
+    /// the timing of the requests whose responses are now complete.
     #[inline]
     fn flush_streams(&mut self) -> HttpResult<()> {
-        while let SendStatus::Sent = try!(self.conn.send_next_data(&mut self.sender)) {}
+        self.flush_pending = false;
+        // A duplex server checks for ready frames in between every frame that it sends, unless
+        // configured otherwise.
+        let max_frames = match (self.max_frames_per_flush, self.max_bytes_per_flush) {
+            (None, None) if self.duplex.is_some() => Some(1),
+            (max_frames, _) => max_frames,
+        };
+        let mut frames = 0;
+        let mut sender = CountingSender { sender: &mut self.sender, written: 0 };
+        while let SendStatus::Sent = try!(self.conn.send_next(&mut sender)) {
+            frames += 1;
+            let exhausted = max_frames.map_or(false, |max| frames >= max) ||
+                            self.max_bytes_per_flush.map_or(false, |max| sender.written >= max);
+            if exhausted {
+                debug!("Flush budget exhausted after {} frames ({} bytes)",
+                       frames, sender.written);
+                self.flush_pending = true;
+                break;
+            }
+        }
+        self.report_flushed();
 
         Ok(())
     }
@@ -189,7 +1476,1220 @@ impl<TS, H> SimpleServer<TS, H>
     #[inline]
     fn reap_streams(&mut self) -> HttpResult<()> {
         // Moves the streams out of the state and then drops them
-        let _ = self.conn.state.get_closed();
+        for stream in self.conn.state.get_closed() {
+            if let Some(stream_id) = stream.stream_id {
+                self.open_streams.remove(&stream_id);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{Read, Write};
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{SimpleServer, ServerRequest, defer};
+    use super::service::Service;
+    use super::sse;
+    use super::access_log::AccessLogEntry;
+
+    use http::{Header, Response, HttpError, HttpResult, ErrorCode};
+    use http::connection::{HttpFrame, ReceiveFrame};
+    use http::frame::RawFrame;
+    use http::frame::{SettingsFrame, HeadersFrame, HeadersFlag, HttpSetting, Frame};
+    use http::frame::{DataFrame, DataFlag, PingFrame, WindowUpdateFrame, pack_header};
+    use http::config::Http2Config;
+    use http::duplex;
+    use http::transport::{TransportReceiveFrame, is_timeout};
+    use http::tests::common::{StubTransportStream, build_stub_from_frames, parse_frames};
+    use hpack;
+
+    /// Builds the bytes that a client would send in order to issue a single GET request on
+    /// stream 1, including the preface.
+    fn build_get_request() -> Vec<u8> {
+        build_request(true)
+    }
+
+    /// Builds the bytes that a client would send in order to start a GET request on stream 1,
+    /// including the preface. If `end_stream` is `false`, the request is left unfinished.
+    fn build_request(end_stream: bool) -> Vec<u8> {
+        let headers = [(&b":method"[..], &b"GET"[..]), (&b":path"[..], &b"/"[..])];
+        build_request_with_headers(&headers, end_stream, vec![])
+    }
+
+    /// Builds the bytes that a client would send in order to start a request with the given
+    /// headers on stream 1, including the preface, followed by the given frames.
+    fn build_request_with_headers(headers: &[(&[u8], &[u8])],
+                                  end_stream: bool,
+                                  frames: Vec<HttpFrame>)
+                                  -> Vec<u8> {
+        let mut buf = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n".to_vec();
+        let fragment = hpack::Encoder::new().encode(headers.iter().cloned());
+        let mut headers = HeadersFrame::new(fragment, 1);
+        headers.set_flag(HeadersFlag::EndHeaders);
+        if end_stream {
+            headers.set_flag(HeadersFlag::EndStream);
+        }
+        let mut all = vec![
+            HttpFrame::SettingsFrame(SettingsFrame::new()),
+            HttpFrame::HeadersFrame(headers),
+        ];
+        all.extend(frames);
+        buf.extend(build_stub_from_frames(&all));
+        buf
+    }
+
+    /// Runs a `SimpleServer` that responds with the given body to a single request and returns
+    /// all frames that were sent on stream 1.
+    fn respond_with_body(body: Vec<u8>) -> Vec<Vec<u8>> {
+        let stream = StubTransportStream::with_stub_content(&build_get_request());
+        let mut server = SimpleServer::new(stream.clone(), |req| {
+            Response {
+                headers: vec![Header::new(b":status", b"204")],
+                body: body.clone(),
+                trailers: vec![],
+                stream_id: req.stream_id,
+            }
+        }).unwrap();
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+
+        parse_frames(&stream.get_written()).into_iter()
+                                           .filter(|raw| raw.header().3 == 1)
+                                           .map(|raw| raw.serialize())
+                                           .collect()
+    }
+
+    /// Tests that a response with an empty body is concluded by its HEADERS frame, without
+    /// sending an empty DATA frame.
+    #[test]
+    fn test_empty_body_ends_stream_on_headers() {
+        let sent = respond_with_body(vec![]);
+
+        assert_eq!(sent.len(), 1);
+        let raw = sent[0].clone().into();
+        match HttpFrame::from_raw(&raw).unwrap() {
+            HttpFrame::HeadersFrame(frame) => assert!(frame.is_end_of_stream()),
+            _ => panic!("Expected a HEADERS frame"),
+        }
+    }
+
+    /// Tests that a response with a body is sent as a HEADERS frame followed by DATA that ends
+    /// the stream.
+    #[test]
+    fn test_body_ends_stream_on_data() {
+        let sent = respond_with_body(vec![1, 2, 3]);
+
+        assert_eq!(sent.len(), 2);
+        let headers = sent[0].clone().into();
+        match HttpFrame::from_raw(&headers).unwrap() {
+            HttpFrame::HeadersFrame(frame) => assert!(!frame.is_end_of_stream()),
+            _ => panic!("Expected a HEADERS frame"),
+        }
+        let data = sent[1].clone().into();
+        match HttpFrame::from_raw(&data).unwrap() {
+            HttpFrame::DataFrame(frame) => {
+                assert!(frame.is_end_of_stream());
+                assert_eq!(&frame.data[..], &[1, 2, 3]);
+                assert_eq!(frame.get_stream_id(), 1);
+            },
+            _ => panic!("Expected a DATA frame"),
+        }
+    }
+
+    /// Tests that once the idle timeout elapses without any active streams, the server sends a
+    /// GOAWAY(NO_ERROR) frame and signals that the connection is idle.
+    #[test]
+    fn test_idle_timeout_closes_connection() {
+        let stream = StubTransportStream::with_stub_content(&build_get_request());
+        let mut server = SimpleServer::new(stream.clone(), |req| {
+            Response {
+                headers: vec![Header::new(b":status", b"200")],
+                body: vec![],
+                trailers: vec![],
+                stream_id: req.stream_id,
+            }
+        }).unwrap();
+        server.set_idle_timeout(Some(Duration::from_secs(1))).unwrap();
+        // The request is handled as usual...
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+
+        // ...but then nothing else arrives.
+        assert_eq!(server.handle_next().err().unwrap(), HttpError::ConnectionIdle);
+
+        let written = parse_frames(&stream.get_written());
+        let raw = written.last().unwrap();
+        match HttpFrame::from_raw(raw).unwrap() {
+            HttpFrame::GoawayFrame(frame) => {
+                assert_eq!(frame.error_code(), ErrorCode::NoError);
+                assert_eq!(frame.last_stream_id(), 1);
+            },
+            _ => panic!("Expected a GOAWAY frame"),
+        }
+    }
+
+    /// Tests that the idle timeout does not close the connection while there are active streams.
+    #[test]
+    fn test_idle_timeout_active_streams() {
+        let stream = StubTransportStream::with_stub_content(&build_request(false));
+        let mut server = SimpleServer::new(stream.clone(), |req| {
+            Response {
+                headers: vec![Header::new(b":status", b"200")],
+                body: vec![],
+                trailers: vec![],
+                stream_id: req.stream_id,
+            }
+        }).unwrap();
+        server.set_idle_timeout(Some(Duration::from_secs(1))).unwrap();
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+
+        // The request is still incomplete, so the timeout does not close the connection.
+        assert!(server.handle_next().is_ok());
+        let written = parse_frames(&stream.get_written());
+        assert!(written.iter().all(|raw| raw.header().1 != 0x7));
+    }
+
+    /// Tests that a read timing out in the middle of a frame does not lose the part of the frame
+    /// that was already read: the next `handle_next` call continues reading the same frame.
+    #[test]
+    fn test_read_timeout_mid_frame() {
+        let data = build_stub_from_frames(&vec![HttpFrame::DataFrame({
+            let mut frame = DataFrame::with_data(1, vec![1, 2, 3]);
+            frame.set_flag(DataFlag::EndStream);
+            frame
+        })]);
+        let mut content = build_request(false);
+        content.extend_from_slice(&data[..5]);
+        let stream = StubTransportStream::with_stub_content(&content);
+        let bodies = Rc::new(RefCell::new(Vec::new()));
+        let handler_bodies = bodies.clone();
+        let mut server = SimpleServer::new(stream.clone(), move |req| {
+            handler_bodies.borrow_mut().push(req.body.to_vec());
+            Response {
+                headers: vec![Header::new(b":status", b"200")],
+                body: vec![],
+                trailers: vec![],
+                stream_id: req.stream_id,
+            }
+        }).unwrap();
+        server.set_idle_timeout(Some(Duration::from_secs(1))).unwrap();
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+
+        // The read times out after the first 5 bytes of the DATA frame...
+        server.handle_next().unwrap();
+        assert!(bodies.borrow().is_empty());
+        // ...and the rest of the frame arrives later.
+        stream.append_content(&data[5..]);
+        server.handle_next().unwrap();
+
+        assert_eq!(*bodies.borrow(), vec![vec![1, 2, 3]]);
+        let written = parse_frames(&stream.get_written());
+        assert!(written.iter().any(|raw| raw.header().1 == 0x1 && raw.header().3 == 1));
+        assert!(written.iter().all(|raw| raw.header().1 != 0x7));
+    }
+
+    /// Tests that the timing hook is invoked with the request's method and path once its response
+    /// is flushed.
+    #[test]
+    fn test_timing_hook() {
+        let stream = StubTransportStream::with_stub_content(&build_get_request());
+        let mut server = SimpleServer::new(stream.clone(), |req| {
+            Response {
+                headers: vec![Header::new(b":status", b"200")],
+                body: vec![1, 2, 3],
+                trailers: vec![],
+                stream_id: req.stream_id,
+            }
+        }).unwrap();
+        let timings = Arc::new(Mutex::new(Vec::new()));
+        {
+            let timings = timings.clone();
+            server.set_timing_hook(move |timing| {
+                timings.lock().unwrap().push((timing.stream_id,
+                                              timing.method.to_vec(),
+                                              timing.path.to_vec(),
+                                              timing.in_flight));
+            });
+        }
+        // Also exercise the slow request log.
+        server.set_slow_request_threshold(Some(Duration::from_secs(0)));
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+
+        assert_eq!(*timings.lock().unwrap(), vec![(1, b"GET".to_vec(), b"/".to_vec(), 1)]);
+        assert!(server.pending_requests.is_empty());
+    }
+
+    /// Tests that the access logger records the request once its response is flushed.
+    #[test]
+    fn test_access_logger() {
+        let stream = StubTransportStream::with_stub_content(&build_get_request());
+        let mut server = SimpleServer::new(stream.clone(), |req| {
+            Response {
+                headers: vec![Header::new(b":status", b"404")],
+                body: vec![1, 2, 3],
+                trailers: vec![],
+                stream_id: req.stream_id,
+            }
+        }).unwrap();
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        {
+            let entries = entries.clone();
+            server.set_access_logger(move |entry: &AccessLogEntry| {
+                entries.lock().unwrap().push((entry.stream_id,
+                                              entry.method.to_vec(),
+                                              entry.path.to_vec(),
+                                              entry.status,
+                                              entry.bytes_sent,
+                                              entry.peer_addr));
+            });
+        }
+        assert_eq!(server.peer_addr(), None);
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+
+        assert_eq!(*entries.lock().unwrap(),
+                   vec![(1, b"GET".to_vec(), b"/".to_vec(), Some(404), 3, None)]);
+        assert!(server.pending_requests.is_empty());
+    }
+
+    /// Tests that a response for a stream that the client did not open is dropped and reported,
+    /// without affecting the connection.
+    #[test]
+    fn test_dropped_response() {
+        let stream = StubTransportStream::with_stub_content(&build_get_request());
+        let mut server = SimpleServer::new(stream.clone(), |req| {
+            Response {
+                headers: vec![Header::new(b":status", b"200")],
+                body: vec![1, 2, 3],
+                trailers: vec![],
+                stream_id: req.stream_id + 2,
+            }
+        }).unwrap();
+        let dropped = Arc::new(Mutex::new(Vec::new()));
+        {
+            let dropped = dropped.clone();
+            server.set_dropped_response_hook(move |response| {
+                dropped.lock().unwrap().push(response.stream_id);
+            });
+        }
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+
+        assert_eq!(server.dropped_responses(), 1);
+        assert_eq!(*dropped.lock().unwrap(), vec![3]);
+        let headers_sent = parse_frames(&stream.get_written()).iter().any(|raw| {
+            match HttpFrame::from_raw(raw) {
+                Ok(HttpFrame::HeadersFrame(_)) => true,
+                _ => false,
+            }
+        });
+        assert!(!headers_sent);
+    }
+
+    /// Tests that the settings hook sees the SETTINGS that the client sends mid-connection.
+    #[test]
+    fn test_settings_hook() {
+        let mut update = SettingsFrame::new();
+        update.add_setting(HttpSetting::EnablePush(0));
+        let headers = [(&b":method"[..], &b"GET"[..]),
+                       (&b":scheme"[..], &b"http"[..]),
+                       (&b":path"[..], &b"/"[..])];
+        let request = build_request_with_headers(&headers,
+                                                 true,
+                                                 vec![HttpFrame::SettingsFrame(update)]);
+        let stream = StubTransportStream::with_stub_content(&request);
+        let mut server = SimpleServer::new(stream.clone(), |req| {
+            Response::new(req.stream_id, vec![(b":status".to_vec(), b"200".to_vec())], vec![])
+        }).unwrap();
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        {
+            let changes = changes.clone();
+            server.set_settings_hook(move |settings, state| {
+                changes.lock().unwrap().push((settings.to_vec(), state.enable_push));
+            });
+        }
+        assert!(server.peer_settings().enable_push);
+        // The handshake, the request and then the update.
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+
+        assert_eq!(*changes.lock().unwrap(), vec![
+            (vec![], true),
+            (vec![HttpSetting::EnablePush(0)], false),
+        ]);
+        assert!(!server.peer_settings().enable_push);
+    }
+
+    /// Tests that requests are not timed unless a hook or a threshold is set.
+    #[test]
+    fn test_no_timing_by_default() {
+        let stream = StubTransportStream::with_stub_content(&build_request(false));
+        let mut server = SimpleServer::new(stream.clone(), |req| {
+            Response {
+                headers: vec![Header::new(b":status", b"200")],
+                body: vec![],
+                trailers: vec![],
+                stream_id: req.stream_id,
+            }
+        }).unwrap();
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+
+        assert!(server.pending_requests.is_empty());
+    }
+
+    /// Performs the handshake of a new `SimpleServer` on the given stream, returning its result.
+    fn handshake(stream: StubTransportStream, timeout: Option<Duration>) -> HttpResult<()> {
+        let handler = |_: ServerRequest| -> Response<'static, 'static> {
+            panic!("No request expected")
+        };
+        let mut server = match timeout {
+            None => SimpleServer::new(stream, handler).unwrap(),
+            Some(timeout) => SimpleServer::with_handshake_timeout(stream, handler, timeout).unwrap(),
+        };
+        assert!(!server.is_handshake_done());
+        try!(server.handle_next());
+        assert!(server.is_handshake_done());
         Ok(())
     }
+
+    /// Tests that creating the server does not read anything from the stream, leaving the
+    /// preface exchange to the first `handle_next` call.
+    #[test]
+    fn test_new_does_not_block() {
+        let stream = StubTransportStream::with_stub_content(&build_get_request());
+        let mut server = SimpleServer::new(stream.clone(), |req| {
+            Response {
+                headers: vec![Header::new(b":status", b"200")],
+                body: vec![],
+                trailers: vec![],
+                stream_id: req.stream_id,
+            }
+        }).unwrap();
+        // Nothing exchanged yet.
+        assert!(!server.is_handshake_done());
+        assert_eq!(stream.get_written().len(), 0);
+
+        server.handle_next().unwrap();
+
+        assert!(server.is_handshake_done());
+        // The server sent its preface and acknowledged the client's settings.
+        assert_eq!(parse_frames(&stream.get_written()).len(), 2);
+    }
+
+    /// Tests that the server reports an invalid preface instead of panicking.
+    #[test]
+    fn test_invalid_preface() {
+        let mut buf = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n".to_vec();
+        buf.extend(build_get_request());
+        let stream = StubTransportStream::with_stub_content(&buf);
+
+        let res = handshake(stream, None);
+
+        assert_eq!(res.err().unwrap(), HttpError::InvalidPreface);
+    }
+
+    /// Tests that a server whose preface exchange failed does not attempt it again, failing all
+    /// further calls instead.
+    #[test]
+    fn test_handshake_failed() {
+        let mut buf = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n".to_vec();
+        buf.extend(build_get_request());
+        let stream = StubTransportStream::with_stub_content(&buf);
+        let mut server = SimpleServer::new(stream.clone(), |_: ServerRequest| {
+            panic!("No request expected")
+        }).unwrap();
+
+        assert_eq!(server.handle_next().err().unwrap(), HttpError::InvalidPreface);
+        assert_eq!(server.handle_next().err().unwrap(), HttpError::ConnectionClosed);
+        assert_eq!(server.handle_next().err().unwrap(), HttpError::ConnectionClosed);
+        assert!(!server.is_handshake_done());
+        assert_eq!(stream.get_written().len(), 0);
+    }
+
+    /// Tests that a client that disconnects before sending the preface results in an error
+    /// instead of a panic.
+    #[test]
+    fn test_preface_eof() {
+        let stream = StubTransportStream::with_stub_content(b"PRI * HTTP/2.0");
+
+        let res = handshake(stream, None);
+
+        assert!(match res {
+            Err(HttpError::IoError(_)) => true,
+            _ => false,
+        });
+    }
+
+    /// Tests that a `SimpleServer` created with a config advertises its settings and sends a
+    /// keepalive PING once no frames arrive for the configured interval.
+    #[test]
+    fn test_config_settings_and_keepalive() {
+        let stream = StubTransportStream::with_stub_content(&build_request(false));
+        let config = Http2Config::new()
+            .max_concurrent_streams(Some(10))
+            .keepalive(Some(Duration::from_secs(1)));
+        let mut server = SimpleServer::with_config(stream.clone(), |req| {
+            Response {
+                headers: vec![Header::new(b":status", b"200")],
+                body: vec![],
+                trailers: vec![],
+                stream_id: req.stream_id,
+            }
+        }, config).unwrap();
+        assert_eq!(server.keepalive(), Some(Duration::from_secs(1)));
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+        // The request is not finished, but nothing else arrives.
+        server.handle_next().unwrap();
+
+        let written = parse_frames(&stream.get_written());
+        match HttpFrame::from_raw(&written[0]).unwrap() {
+            HttpFrame::SettingsFrame(frame) => {
+                assert!(!frame.is_ack());
+                assert_eq!(frame.settings, vec![HttpSetting::MaxConcurrentStreams(10)]);
+            },
+            _ => panic!("Expected a SETTINGS frame"),
+        }
+        match HttpFrame::from_raw(written.last().unwrap()).unwrap() {
+            HttpFrame::PingFrame(frame) => assert!(!frame.is_ack()),
+            _ => panic!("Expected a PING frame"),
+        }
+    }
+
+    /// Tests that the requests of a forward proxy reach the handler untouched, with the
+    /// absolute-form `:path`.
+    #[test]
+    fn test_absolute_form_path() {
+        let headers = [(&b":method"[..], &b"GET"[..]),
+                       (&b":scheme"[..], &b"http"[..]),
+                       (&b":path"[..], &b"http://example.com/a?b"[..])];
+        let stream = StubTransportStream::with_stub_content(
+            &build_request_with_headers(&headers, true, vec![]));
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let seen = paths.clone();
+        let mut server = SimpleServer::new(stream, move |req| {
+            seen.lock().unwrap().push(req.pseudo_headers.path.clone().unwrap());
+            Response::new(req.stream_id, vec![(b":status".to_vec(), b"200".to_vec())], vec![])
+        }).unwrap();
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+
+        assert_eq!(*paths.lock().unwrap(), vec![b"http://example.com/a?b".to_vec()]);
+    }
+
+    /// Tests that a request with trailers is only handed to the handler once the trailers ended
+    /// the stream, with the trailers kept apart from the headers.
+    #[test]
+    fn test_request_trailers() {
+        let headers = [(&b":method"[..], &b"POST"[..]),
+                       (&b":scheme"[..], &b"http"[..]),
+                       (&b":path"[..], &b"/upload"[..])];
+        let fragment = hpack::Encoder::new().encode(vec![(&b"checksum"[..], &b"abc"[..])]);
+        let mut trailers = HeadersFrame::new(fragment, 1);
+        trailers.set_flag(HeadersFlag::EndHeaders);
+        trailers.set_flag(HeadersFlag::EndStream);
+        let frames = vec![
+            HttpFrame::DataFrame(DataFrame::with_data(1, b"body".to_vec())),
+            HttpFrame::HeadersFrame(trailers),
+        ];
+        let stream = StubTransportStream::with_stub_content(
+            &build_request_with_headers(&headers, false, frames));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let mut server = SimpleServer::new(stream, move |req| {
+            assert!(req.headers.iter().all(|h| h.name() != b"checksum"));
+            let checksum = req.trailer(b"checksum").map(|value| value.to_vec());
+            seen.lock().unwrap().push((req.body.to_vec(), checksum));
+            Response::new(req.stream_id, vec![(b":status".to_vec(), b"200".to_vec())], vec![])
+        }).unwrap();
+        // The SETTINGS, HEADERS and DATA frames do not complete the request...
+        for _ in 0..3 {
+            server.handle_next().unwrap();
+        }
+        assert!(requests.lock().unwrap().is_empty());
+        // ...the trailers do.
+        server.handle_next().unwrap();
+
+        assert_eq!(*requests.lock().unwrap(), vec![(b"body".to_vec(), Some(b"abc".to_vec()))]);
+    }
+
+    /// Tests that a `CONNECT` request is handed to the handler once its headers arrive and that
+    /// a 2xx response turns its stream into a tunnel carrying DATA in both directions.
+    #[test]
+    fn test_connect_tunnel() {
+        let headers = [(&b":method"[..], &b"CONNECT"[..]),
+                       (&b":authority"[..], &b"example.com:443"[..])];
+        let mut end = DataFrame::with_data(1, b"!".to_vec());
+        end.set_flag(DataFlag::EndStream);
+        let frames = vec![
+            HttpFrame::DataFrame(DataFrame::with_data(1, b"ping".to_vec())),
+            HttpFrame::DataFrame(end),
+        ];
+        let stream = StubTransportStream::with_stub_content(
+            &build_request_with_headers(&headers, false, frames));
+        let calls = Arc::new(Mutex::new(0));
+        let counter = calls.clone();
+        let mut server = SimpleServer::new(stream.clone(), move |req| {
+            *counter.lock().unwrap() += 1;
+            assert_eq!(req.pseudo_headers.authority, Some(b"example.com:443".to_vec()));
+            Response::new(req.stream_id, vec![(b":status".to_vec(), b"200".to_vec())], vec![])
+        }).unwrap();
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+        assert_eq!(server.tunnels(), vec![1]);
+        assert!(server.tunnel(3).is_none());
+
+        server.handle_next().unwrap();
+        {
+            let mut tunnel = server.tunnel(1).unwrap();
+            assert_eq!(tunnel.take_received().unwrap(), b"ping".to_vec());
+            assert!(!tunnel.is_closed_remote());
+            tunnel.send(b"pong").unwrap();
+        }
+        server.handle_next().unwrap();
+        {
+            let mut tunnel = server.tunnel(1).unwrap();
+            assert_eq!(tunnel.take_received().unwrap(), b"!".to_vec());
+            assert!(tunnel.is_closed_remote());
+            tunnel.close().unwrap();
+        }
+        // The tunnel is gone once both sides are closed.
+        assert!(server.tunnels().is_empty());
+        assert_eq!(*calls.lock().unwrap(), 1);
+
+        let sent: Vec<_> = parse_frames(&stream.get_written()).into_iter()
+                                                               .filter(|raw| raw.header().3 == 1)
+                                                               .collect();
+        match HttpFrame::from_raw(&sent[0]).unwrap() {
+            HttpFrame::HeadersFrame(frame) => assert!(!frame.is_end_of_stream()),
+            _ => panic!("Expected a HEADERS frame"),
+        }
+        match HttpFrame::from_raw(&sent[1]).unwrap() {
+            HttpFrame::DataFrame(frame) => {
+                assert_eq!(&frame.data[..], b"pong");
+                assert!(!frame.is_end_of_stream());
+            },
+            _ => panic!("Expected a DATA frame"),
+        }
+        match HttpFrame::from_raw(&sent[2]).unwrap() {
+            HttpFrame::DataFrame(frame) => {
+                assert!(frame.data.is_empty());
+                assert!(frame.is_end_of_stream());
+            },
+            _ => panic!("Expected a DATA frame"),
+        }
+    }
+
+    /// Tests that the streams of the requests selected by the streaming filter are kept open
+    /// after the response headers, with DATA flowing in both directions.
+    #[test]
+    fn test_bidirectional_streaming() {
+        let headers = [(&b":method"[..], &b"POST"[..]),
+                       (&b":scheme"[..], &b"http"[..]),
+                       (&b":path"[..], &b"/chat"[..]),
+                       (&b"content-type"[..], &b"application/grpc"[..])];
+        let mut last = DataFrame::with_data(1, b"b".to_vec());
+        last.set_flag(DataFlag::EndStream);
+        let frames = vec![
+            HttpFrame::DataFrame(DataFrame::with_data(1, b"a".to_vec())),
+            HttpFrame::DataFrame(last),
+        ];
+        let stream = StubTransportStream::with_stub_content(
+            &build_request_with_headers(&headers, false, frames));
+        let mut server = SimpleServer::new(stream.clone(), |req| {
+            Response::new(req.stream_id, vec![(b":status".to_vec(), b"200".to_vec())], vec![])
+        }).unwrap();
+        server.set_streaming_filter(|req| {
+            req.headers.iter().any(|h| {
+                h.name() == b"content-type" && h.value() == b"application/grpc"
+            })
+        });
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+        assert_eq!(server.open_streams(), vec![1]);
+        // Only tunnels are available through `tunnel`.
+        assert!(server.tunnel(1).is_none());
+
+        let mut received = Vec::new();
+        for reply in &[b"A", b"B"] {
+            server.handle_next().unwrap();
+            let mut open = server.open_stream(1).unwrap();
+            received.extend(open.take_received().unwrap());
+            open.send(*reply).unwrap();
+        }
+        {
+            let open = server.open_stream(1).unwrap();
+            assert!(open.is_closed_remote());
+            open.close().unwrap();
+        }
+        assert_eq!(received, b"ab".to_vec());
+        assert!(server.open_streams().is_empty());
+
+        let data: Vec<_> = parse_frames(&stream.get_written()).into_iter().filter_map(|raw| {
+            match HttpFrame::from_raw(&raw) {
+                Ok(HttpFrame::DataFrame(frame)) => {
+                    Some((frame.data.to_vec(), frame.is_end_of_stream()))
+                },
+                _ => None,
+            }
+        }).collect();
+        assert_eq!(data, vec![(b"A".to_vec(), false), (b"B".to_vec(), false), (vec![], true)]);
+    }
+
+    /// Tests that the body of a streaming request can be read as it arrives, with the read bytes
+    /// released from flow control.
+    #[test]
+    fn test_request_body_reader() {
+        let headers = [(&b":method"[..], &b"POST"[..]),
+                       (&b":scheme"[..], &b"http"[..]),
+                       (&b":path"[..], &b"/upload"[..])];
+        let mut last = DataFrame::with_data(1, b"world".to_vec());
+        last.set_flag(DataFlag::EndStream);
+        let frames = vec![
+            HttpFrame::DataFrame(DataFrame::with_data(1, b"hello ".to_vec())),
+            HttpFrame::DataFrame(last),
+        ];
+        let stream = StubTransportStream::with_stub_content(
+            &build_request_with_headers(&headers, false, frames));
+        let mut server = SimpleServer::new(stream.clone(), |req| {
+            Response::new(req.stream_id, vec![(b":status".to_vec(), b"200".to_vec())], vec![])
+        }).unwrap();
+        server.set_streaming_filter(|_| true);
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+        assert!(server.request_body(3).is_none());
+
+        let mut body = Vec::new();
+        {
+            let mut reader = server.request_body(1).unwrap();
+            let mut buf = [0; 4];
+            assert_eq!(reader.read(&mut buf).unwrap(), 4);
+            body.extend_from_slice(&buf);
+            reader.read_to_end(&mut body).unwrap();
+        }
+        assert_eq!(body, b"hello world".to_vec());
+
+        let updates: Vec<_> = parse_frames(&stream.get_written()).into_iter().filter_map(|raw| {
+            match HttpFrame::from_raw(&raw) {
+                Ok(HttpFrame::WindowUpdateFrame(frame)) => {
+                    Some((frame.get_stream_id(), frame.increment()))
+                },
+                _ => None,
+            }
+        }).collect();
+        let released = |stream_id| {
+            updates.iter().filter(|u| u.0 == stream_id).map(|u| u.1).sum::<u32>()
+        };
+        assert_eq!(updates[..2], [(1, 4), (0, 4)]);
+        assert_eq!(released(0), 11);
+        // The stream's window is no longer replenished once the client ended the stream.
+        assert_eq!(released(1), 6);
+    }
+
+    /// Tests that a deferred response sends nothing until it is started, after the request body
+    /// was read.
+    #[test]
+    fn test_deferred_response() {
+        let headers = [(&b":method"[..], &b"POST"[..]),
+                       (&b":scheme"[..], &b"http"[..]),
+                       (&b":path"[..], &b"/upload"[..])];
+        let mut last = DataFrame::with_data(1, b"hi".to_vec());
+        last.set_flag(DataFlag::EndStream);
+        let stream = StubTransportStream::with_stub_content(
+            &build_request_with_headers(&headers, false, vec![HttpFrame::DataFrame(last)]));
+        let mut server = SimpleServer::new(stream.clone(), |req| defer(req.stream_id)).unwrap();
+        server.set_streaming_filter(|_| true);
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+        assert_eq!(server.open_streams(), vec![1]);
+        let is_response = |raw: &RawFrame| {
+            match HttpFrame::from_raw(raw) {
+                Ok(HttpFrame::HeadersFrame(_)) | Ok(HttpFrame::DataFrame(_)) => true,
+                _ => false,
+            }
+        };
+        assert!(!parse_frames(&stream.get_written()).iter().any(&is_response));
+
+        let mut body = Vec::new();
+        server.request_body(1).unwrap().read_to_end(&mut body).unwrap();
+        {
+            let mut response = server.open_stream(1).unwrap();
+            response.start_response(vec![Header::new(b":status", b"201")]).unwrap();
+            response.send(&body).unwrap();
+            response.close().unwrap();
+        }
+
+        let frames: Vec<_> = parse_frames(&stream.get_written()).into_iter()
+                                                                 .filter(&is_response)
+                                                                 .collect();
+        assert_eq!(frames.len(), 3);
+        match HttpFrame::from_raw(&frames[0]) {
+            Ok(HttpFrame::HeadersFrame(frame)) => assert!(!frame.is_end_of_stream()),
+            _ => panic!("Expected the response HEADERS first"),
+        }
+        match HttpFrame::from_raw(&frames[1]) {
+            Ok(HttpFrame::DataFrame(frame)) => assert_eq!(&frame.data[..], b"hi"),
+            _ => panic!("Expected the echoed body"),
+        }
+        assert!(server.open_streams().is_empty());
+    }
+
+    /// Tests that the trailers of a handler's response are sent after its body, ending the stream
+    /// in place of the last DATA frame.
+    #[test]
+    fn test_response_trailers() {
+        let headers = [(&b":method"[..], &b"GET"[..]),
+                       (&b":scheme"[..], &b"http"[..]),
+                       (&b":path"[..], &b"/"[..])];
+        let stream = StubTransportStream::with_stub_content(
+            &build_request_with_headers(&headers, true, vec![]));
+        let mut server = SimpleServer::new(stream.clone(), |req| {
+            Response {
+                stream_id: req.stream_id,
+                headers: vec![Header::new(b":status", b"200")],
+                body: b"ok".to_vec(),
+                trailers: vec![Header::new(b"grpc-status".to_vec(), b"0".to_vec())],
+            }
+        }).unwrap();
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+
+        let end_stream: Vec<_> = parse_frames(&stream.get_written()).iter().filter_map(|raw| {
+            match HttpFrame::from_raw(raw) {
+                Ok(HttpFrame::HeadersFrame(frame)) => Some(("headers", frame.is_end_of_stream())),
+                Ok(HttpFrame::DataFrame(frame)) => Some(("data", frame.is_end_of_stream())),
+                _ => None,
+            }
+        }).collect();
+        assert_eq!(end_stream, vec![("headers", false), ("data", false), ("headers", true)]);
+    }
+
+    /// Tests that a deferred response can send informational headers before the final ones and
+    /// end with trailers, while sequences that are not allowed are rejected.
+    #[test]
+    fn test_deferred_response_trailers() {
+        let headers = [(&b":method"[..], &b"POST"[..]),
+                       (&b":scheme"[..], &b"http"[..]),
+                       (&b":path"[..], &b"/upload"[..])];
+        let stream = StubTransportStream::with_stub_content(
+            &build_request_with_headers(&headers, true, vec![]));
+        let mut server = SimpleServer::new(stream.clone(), |req| defer(req.stream_id)).unwrap();
+        server.set_streaming_filter(|_| true);
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+        {
+            let mut response = server.open_stream(1).unwrap();
+            // No DATA before the final headers.
+            match response.send(b"early") {
+                Err(HttpError::InvalidHeaderPhase(_)) => {},
+                _ => panic!("Expected the DATA to be rejected"),
+            }
+            response.start_response(vec![Header::new(b":status", b"103")]).unwrap();
+            response.start_response(vec![Header::new(b":status", b"200")]).unwrap();
+            match response.start_response(vec![Header::new(b":status", b"200")]) {
+                Err(HttpError::InvalidHeaderPhase(_)) => {},
+                _ => panic!("Expected the second final headers to be rejected"),
+            }
+            response.send(b"body").unwrap();
+        }
+        server.send_trailers(1, vec![Header::new(b"grpc-status".to_vec(), b"0".to_vec())])
+              .unwrap();
+
+        let frames: Vec<_> = parse_frames(&stream.get_written()).iter().filter_map(|raw| {
+            match HttpFrame::from_raw(raw) {
+                Ok(HttpFrame::HeadersFrame(frame)) => Some(("headers", frame.is_end_of_stream())),
+                Ok(HttpFrame::DataFrame(frame)) => Some(("data", frame.is_end_of_stream())),
+                _ => None,
+            }
+        }).collect();
+        assert_eq!(frames, vec![
+            ("headers", false), ("headers", false), ("data", false), ("headers", true),
+        ]);
+        assert!(server.open_streams().is_empty());
+        assert!(server.send_trailers(1, vec![]).is_err());
+    }
+
+    /// Tests that an event stream is served on a GET request, with each event (and heartbeat)
+    /// flushed in its own DATA frame.
+    #[test]
+    fn test_server_sent_events() {
+        let headers = [(&b":method"[..], &b"GET"[..]),
+                       (&b":scheme"[..], &b"http"[..]),
+                       (&b":path"[..], &b"/events"[..]),
+                       (&b"accept"[..], &b"text/event-stream"[..])];
+        let stream = StubTransportStream::with_stub_content(
+            &build_request_with_headers(&headers, true, vec![]));
+        let mut server = SimpleServer::new(stream.clone(), |req| sse::response(req.stream_id))
+                                      .unwrap();
+        server.set_streaming_filter(|req| sse::accepts_events(req));
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+        {
+            let mut events = server.open_stream(1).unwrap();
+            events.send_event(&sse::Event::new("1").with_id("a")).unwrap();
+            events.send_heartbeat("").unwrap();
+            events.close().unwrap();
+        }
+
+        let data: Vec<_> = parse_frames(&stream.get_written()).into_iter().filter_map(|raw| {
+            match HttpFrame::from_raw(&raw) {
+                Ok(HttpFrame::DataFrame(frame)) => Some(frame.data.to_vec()),
+                _ => None,
+            }
+        }).collect();
+        assert_eq!(data, vec![b"id: a\ndata: 1\n\n".to_vec(), b":\n\n".to_vec(), vec![]]);
+    }
+
+    /// Tests that a `SimpleServer` created with a `Service` has it respond to the requests.
+    #[test]
+    fn test_server_with_service() {
+        struct Echo;
+        impl Service for Echo {
+            fn call(&mut self, req: ServerRequest) -> Response<'static, 'static> {
+                let path = req.pseudo_headers.path.clone().unwrap_or_default();
+                Response::new(req.stream_id, vec![(b":status".to_vec(), b"200".to_vec())], path)
+            }
+        }
+
+        let stream = StubTransportStream::with_stub_content(&build_get_request());
+        let mut server = SimpleServer::with_service(stream.clone(), Echo, Http2Config::new())
+                                      .unwrap();
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+
+        let data = parse_frames(&stream.get_written()).into_iter().filter_map(|raw| {
+            match HttpFrame::from_raw(&raw) {
+                Ok(HttpFrame::DataFrame(frame)) => Some(frame.data.to_vec()),
+                _ => None,
+            }
+        }).collect::<Vec<_>>();
+        assert_eq!(data, vec![b"/".to_vec()]);
+    }
+
+    /// Runs a `SimpleServer` on which three requests become ready at once, returning how many
+    /// bytes were written to the client by the time that the handler was invoked for each one.
+    fn written_before_each_response(max_queued_responses: Option<usize>,
+                                    flush_deadline: Option<Duration>)
+                                    -> Vec<usize> {
+        let headers = [(&b":method"[..], &b"GET"[..]),
+                       (&b":scheme"[..], &b"http"[..]),
+                       (&b":path"[..], &b"/"[..])];
+        let fragment = hpack::Encoder::new().encode(headers.iter().cloned());
+        let mut frames: Vec<_> = [3, 5].iter().map(|&stream_id| {
+            let mut frame = HeadersFrame::new(fragment.clone(), stream_id);
+            frame.set_flag(HeadersFlag::EndHeaders);
+            HttpFrame::HeadersFrame(frame)
+        }).collect();
+        frames.push(HttpFrame::PingFrame(PingFrame::new(0)));
+        let stream = StubTransportStream::with_stub_content(
+            &build_request_with_headers(&headers, false, frames));
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let mut server = {
+            let (stream, written) = (stream.clone(), written.clone());
+            SimpleServer::new(stream.clone(), move |req| {
+                written.borrow_mut().push(stream.get_written().len());
+                Response::new(req.stream_id, vec![(b":status".to_vec(), b"404".to_vec())],
+                              b"gone".to_vec())
+            }).unwrap()
+        };
+        // The requests are held back until the PING arrives.
+        let ready = Arc::new(AtomicBool::new(false));
+        let filter_ready = ready.clone();
+        server.set_streaming_filter(move |_| filter_ready.load(Ordering::SeqCst));
+        server.set_max_queued_responses(max_queued_responses);
+        server.set_flush_deadline(flush_deadline);
+        for _ in 0..4 {
+            server.handle_next().unwrap();
+        }
+        assert!(written.borrow().is_empty());
+        ready.store(true, Ordering::SeqCst);
+        server.handle_next().unwrap();
+
+        let written = written.borrow().clone();
+        written
+    }
+
+    /// Tests that by default, all ready requests are handled before any response is flushed,
+    /// while a maximum number of queued responses has them flushed in between.
+    #[test]
+    fn test_max_queued_responses() {
+        let all_at_once = written_before_each_response(None, None);
+        assert_eq!(all_at_once.len(), 3);
+        assert!(all_at_once.iter().all(|&len| len == all_at_once[0]));
+
+        let one_by_one = written_before_each_response(Some(1), None);
+        assert_eq!(one_by_one.len(), 3);
+        assert!(one_by_one[0] < one_by_one[1] && one_by_one[1] < one_by_one[2]);
+        assert_eq!(one_by_one[0], all_at_once[0]);
+
+        let in_pairs = written_before_each_response(Some(2), None);
+        assert_eq!(in_pairs[0], in_pairs[1]);
+        assert!(in_pairs[1] < in_pairs[2]);
+    }
+
+    /// Tests that once the flush deadline elapses, the responses prepared so far are flushed
+    /// before the handler is invoked for the remaining requests.
+    #[test]
+    fn test_flush_deadline() {
+        let written = written_before_each_response(None, Some(Duration::from_secs(0)));
+        assert_eq!(written.len(), 3);
+        assert!(written[0] < written[1] && written[1] < written[2]);
+
+        let written = written_before_each_response(Some(2), Some(Duration::from_secs(60)));
+        assert_eq!(written[0], written[1]);
+        assert!(written[1] < written[2]);
+    }
+
+    /// Runs a `SimpleServer` that responds with a large body to a request that is followed by a
+    /// PING, returning the kinds of the frames that it sends after the handshake.
+    fn frames_around_ping(max_frames: Option<usize>, max_bytes: Option<usize>)
+                          -> Vec<&'static str> {
+        let headers = [(&b":method"[..], &b"GET"[..]),
+                       (&b":scheme"[..], &b"http"[..]),
+                       (&b":path"[..], &b"/"[..])];
+        // The WINDOW_UPDATE frames only give the server more chances to flush.
+        let frames = vec![
+            HttpFrame::PingFrame(PingFrame::new(7)),
+            HttpFrame::WindowUpdateFrame(WindowUpdateFrame::for_connection(1)),
+            HttpFrame::WindowUpdateFrame(WindowUpdateFrame::for_connection(1)),
+        ];
+        let stream = StubTransportStream::with_stub_content(
+            &build_request_with_headers(&headers, true, frames));
+        let mut server = SimpleServer::new(stream.clone(), |req| {
+            // Sent in three DATA frames, of at most the default maximum frame size.
+            Response {
+                headers: vec![Header::new(b":status", b"200")],
+                body: vec![0; 40000],
+                trailers: vec![],
+                stream_id: req.stream_id,
+            }
+        }).unwrap();
+        server.set_max_frames_per_flush(max_frames);
+        server.set_max_bytes_per_flush(max_bytes);
+        for _ in 0..5 {
+            server.handle_next().unwrap();
+        }
+
+        parse_frames(&stream.get_written()).iter().filter_map(|raw| {
+            match HttpFrame::from_raw(raw) {
+                Ok(HttpFrame::HeadersFrame(_)) => Some("headers"),
+                Ok(HttpFrame::DataFrame(_)) => Some("data"),
+                Ok(HttpFrame::PingFrame(ref frame)) if frame.is_ack() => Some("ping"),
+                _ => None,
+            }
+        }).collect()
+    }
+
+    /// Tests that a bounded flush leaves the rest of a large response to the following
+    /// `handle_next` calls, so that the frames that arrive in the meantime are handled first.
+    #[test]
+    fn test_flush_budget() {
+        let unbounded = frames_around_ping(None, None);
+        assert_eq!(unbounded, vec!["headers", "data", "data", "data", "ping"]);
+
+        let by_frames = frames_around_ping(Some(2), None);
+        assert_eq!(by_frames, vec!["headers", "data", "ping", "data", "data"]);
+
+        // The frame that exhausts the byte budget is still sent in full.
+        let by_bytes = frames_around_ping(None, Some(1));
+        assert_eq!(by_bytes, vec!["headers", "ping", "data", "data", "data"]);
+    }
+
+    /// Tests that a duplex server handles a frame that is ready as soon as possible, instead of
+    /// first sending all of the data that is queued.
+    #[test]
+    fn test_duplex_server() {
+        let headers = [(&b":method"[..], &b"GET"[..]),
+                       (&b":scheme"[..], &b"http"[..]),
+                       (&b":path"[..], &b"/"[..])];
+        let frames = vec![HttpFrame::PingFrame(PingFrame::new(7))];
+        let (mut client_end, server_end) = duplex::pipe();
+        client_end.write_all(&build_request_with_headers(&headers, true, frames)).unwrap();
+        let mut server = SimpleServer::new(server_end, |req| {
+            Response {
+                headers: vec![Header::new(b":status", b"200")],
+                body: vec![0; 20000],
+                trailers: vec![],
+                stream_id: req.stream_id,
+            }
+        }).unwrap();
+        server.enable_duplex().unwrap();
+        server.handle_next().unwrap();
+        // Once the reader thread waits for more, both the HEADERS and the PING are ready.
+        client_end.wait_for_peer_read();
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+
+        let mut sent = Vec::new();
+        let mut receiver = TransportReceiveFrame::new(&mut client_end);
+        while sent.last() != Some(&"ping") {
+            match receiver.recv_frame().unwrap() {
+                HttpFrame::HeadersFrame(_) => sent.push("headers"),
+                HttpFrame::DataFrame(_) => sent.push("data"),
+                HttpFrame::PingFrame(ref frame) if frame.is_ack() => sent.push("ping"),
+                _ => {},
+            }
+        }
+        assert_eq!(sent, vec!["headers", "ping"]);
+    }
+
+    /// Tests that the reader thread of a duplex server keeps the part of a frame that it read
+    /// before a read timed out, continuing the frame once the rest of it arrives.
+    #[test]
+    fn test_duplex_server_read_timeout_mid_frame() {
+        let data = build_stub_from_frames(&vec![HttpFrame::DataFrame({
+            let mut frame = DataFrame::with_data(1, vec![1, 2, 3]);
+            frame.set_flag(DataFlag::EndStream);
+            frame
+        })]);
+        let (mut client_end, server_end) = duplex::pipe();
+        let mut request = build_request(false);
+        request.extend_from_slice(&data[..5]);
+        client_end.write_all(&request).unwrap();
+        let bodies = Rc::new(RefCell::new(Vec::new()));
+        let handler_bodies = bodies.clone();
+        let mut server = SimpleServer::new(server_end, move |req| {
+            handler_bodies.borrow_mut().push(req.body.to_vec());
+            Response {
+                headers: vec![Header::new(b":status", b"200")],
+                body: vec![],
+                trailers: vec![],
+                stream_id: req.stream_id,
+            }
+        }).unwrap();
+        server.set_idle_timeout(Some(Duration::from_secs(1))).unwrap();
+        server.enable_duplex().unwrap();
+        server.handle_next().unwrap();
+        server.handle_next().unwrap();
+
+        // The reader thread times out after the first 5 bytes of the DATA frame...
+        client_end.wait_for_peer_read();
+        client_end.advance_time(Duration::from_secs(1));
+        server.handle_next().unwrap();
+        assert!(bodies.borrow().is_empty());
+        // ...and the rest of the frame arrives later.
+        client_end.write_all(&data[5..]).unwrap();
+        server.handle_next().unwrap();
+
+        assert_eq!(*bodies.borrow(), vec![vec![1, 2, 3]]);
+    }
+
+    /// Tests that a duplex server refuses a frame larger than its maximum frame size without
+    /// reading it, closing the connection with a FRAME_SIZE_ERROR.
+    #[test]
+    fn test_duplex_server_frame_too_large() {
+        let (mut client_end, server_end) = duplex::pipe();
+        let mut request = build_request(false);
+        // Just the header of a DATA frame that claims a payload of 1 MiB.
+        request.extend_from_slice(&pack_header(&(1 << 20, 0x0, 0, 1)));
+        client_end.write_all(&request).unwrap();
+        let mut server = SimpleServer::new(server_end, |req| {
+            Response {
+                headers: vec![Header::new(b":status", b"200")],
+                body: vec![],
+                trailers: vec![],
+                stream_id: req.stream_id,
+            }
+        }).unwrap();
+        server.enable_duplex().unwrap();
+        server.handle_next().unwrap();
+
+        let mut res = server.handle_next();
+        while let Ok(()) = res {
+            res = server.handle_next();
+        }
+        assert_eq!(res.err().unwrap(), HttpError::LocalConnectionError(ErrorCode::FrameSizeError));
+        let mut receiver = TransportReceiveFrame::new(&mut client_end);
+        loop {
+            match receiver.recv_frame().unwrap() {
+                HttpFrame::GoawayFrame(frame) => {
+                    assert_eq!(frame.error_code(), ErrorCode::FrameSizeError);
+                    break;
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Tests that the server gives up on a client that does not send the full preface in time,
+    /// while reads that would block ahead of the deadline leave the handshake pending.
+    #[test]
+    fn test_handshake_timeout() {
+        let timeout = Duration::from_millis(50);
+        // Only a part of the magic is sent, or the magic is there, but no SETTINGS frame follows.
+        let stalled: [&[u8]; 2] = [b"PRI * HTTP/2.0", b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n"];
+        for content in stalled.iter() {
+            let stream = StubTransportStream::with_stub_content(content);
+            let handler = |_: ServerRequest| -> Response<'static, 'static> {
+                panic!("No request expected")
+            };
+            let mut server = SimpleServer::with_handshake_timeout(stream, handler, timeout)
+                                          .unwrap();
+            // The server waits for the rest of the preface...
+            match server.handle_next() {
+                Err(HttpError::IoError(ref e)) => assert!(is_timeout(e)),
+                res => panic!("Expected a read that would block, got {:?}", res),
+            }
+            assert!(!server.is_handshake_done());
+            // ...which does not arrive in time.
+            thread::sleep(timeout);
+            assert_eq!(server.handle_next().err().unwrap(), HttpError::HandshakeTimeout);
+            assert_eq!(server.handle_next().err().unwrap(), HttpError::ConnectionClosed);
+        }
+        {
+            // The full preface arrives in time.
+            let stream = StubTransportStream::with_stub_content(&build_get_request());
+            assert!(handshake(stream, Some(timeout)).is_ok());
+        }
+    }
+
+    /// Tests that a preface that trickles in over a stream whose reads would block is not lost:
+    /// each `handle_next` call continues the handshake where the previous one left off.
+    #[test]
+    fn test_handshake_resumes_after_would_block() {
+        let stream = StubTransportStream::with_stub_content(b"PRI * HTTP");
+        let handler = |_: ServerRequest| -> Response<'static, 'static> {
+            panic!("No request expected")
+        };
+        let mut server = SimpleServer::with_handshake_timeout(
+            stream.clone(), handler, Duration::from_secs(60)).unwrap();
+        let settings = SettingsFrame::new();
+        let settings = build_stub_from_frames(&vec![HttpFrame::SettingsFrame(settings)]);
+        let parts: [&[u8]; 3] = [b"/2.0\r\n\r\nSM\r\n\r\n", &settings[..4], &settings[4..]];
+
+        for part in parts.iter() {
+            match server.handle_next() {
+                Err(HttpError::IoError(ref e)) => assert!(is_timeout(e)),
+                res => panic!("Expected a read that would block, got {:?}", res),
+            }
+            assert!(!server.is_handshake_done());
+            stream.append_content(part);
+        }
+
+        server.handle_next().unwrap();
+        assert!(server.is_handshake_done());
+        // The server sent its own SETTINGS only once, followed by the ACK of the client's.
+        let written = parse_frames(&stream.get_written());
+        assert_eq!(written.len(), 2);
+        match (HttpFrame::from_raw(&written[0]), HttpFrame::from_raw(&written[1])) {
+            (Ok(HttpFrame::SettingsFrame(ours)), Ok(HttpFrame::SettingsFrame(ack))) => {
+                assert!(!ours.is_ack());
+                assert!(ack.is_ack());
+            },
+            _ => panic!("Expected the server's SETTINGS and an ACK"),
+        }
+    }
 }
@@ -0,0 +1,120 @@
+//! The module defines the `Service` trait, which abstracts over whatever turns the requests
+//! received by a server into responses.
+//!
+//! Anything from a single closure to a full routing and middleware stack can be a `Service`, as
+//! long as it produces a response for each request. Closures are services as they are, while
+//! other types implement the trait themselves. The trait is object-safe, so services can also be
+//! composed at runtime as `BoxedService`s.
+//!
+//! ```rust
+//! use solicit::http::{Response, Header};
+//! use solicit::server::ServerRequest;
+//! use solicit::server::service::{Service, BoxedService};
+//!
+//! /// A middleware that adds a `server` header to the responses of the wrapped service.
+//! struct ServerName(BoxedService);
+//!
+//! impl Service for ServerName {
+//!     fn call(&mut self, request: ServerRequest) -> Response<'static, 'static> {
+//!         let mut response = self.0.call(request);
+//!         response.headers.push(Header::new(b"server", b"solicit"));
+//!         response
+//!     }
+//! }
+//!
+//! let hello = |req: ServerRequest| Response::new(req.stream_id, vec![
+//!     (b":status".to_vec(), b"200".to_vec()),
+//! ], b"Hello".to_vec());
+//! let service = ServerName(Box::new(hello));
+//! # let _ = service;
+//! ```
+use http::Response;
+use super::ServerRequest;
+
+/// The trait is implemented by types that produce a response for each request received by a
+/// server.
+pub trait Service {
+    /// Handles the given request, returning the response that should be sent for it.
+    ///
+    /// The `stream_id` of the response needs to match the one of the request.
+    fn call(&mut self, request: ServerRequest) -> Response<'static, 'static>;
+}
+
+/// A `Service` whose concrete type is erased, e.g. one that is assembled at runtime out of
+/// several layers.
+pub type BoxedService = Box<Service + Send>;
+
+/// Closures that turn a request into a response are services, which allows them to be passed
+/// wherever a `Service` is expected.
+impl<F> Service for F where F: FnMut(ServerRequest) -> Response<'static, 'static> {
+    fn call(&mut self, request: ServerRequest) -> Response<'static, 'static> {
+        self(request)
+    }
+}
+
+impl Service for BoxedService {
+    fn call(&mut self, request: ServerRequest) -> Response<'static, 'static> {
+        (**self).call(request)
+    }
+}
+
+/// The request handler that a `SimpleServer` uses when it is given a `Service` (see
+/// `SimpleServer::with_service`).
+pub type ServiceHandler = Box<FnMut(ServerRequest) -> Response<'static, 'static> + Send>;
+
+/// Adapts the given `Service` into a request handler that a `SimpleServer` can invoke.
+pub fn into_handler<S>(mut service: S) -> ServiceHandler where S: Service + Send + 'static {
+    Box::new(move |request| service.call(request))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Service, BoxedService, into_handler};
+    use http::{Header, Response, PseudoHeaders};
+    use server::ServerRequest;
+
+    /// A service that responds with the number of requests that it has handled so far.
+    struct Counter {
+        count: usize,
+    }
+
+    impl Service for Counter {
+        fn call(&mut self, request: ServerRequest) -> Response<'static, 'static> {
+            self.count += 1;
+            Response::new(request.stream_id,
+                          vec![(b":status".to_vec(), b"200".to_vec())],
+                          self.count.to_string().into_bytes())
+        }
+    }
+
+    /// Calls the service with a request on the given stream.
+    fn call<S: Service>(service: &mut S, stream_id: u32) -> Response<'static, 'static> {
+        let headers = vec![Header::new(b":method", b"GET")];
+        let pseudo_headers = PseudoHeaders::parse(&headers);
+        service.call(ServerRequest {
+            stream_id: stream_id,
+            headers: &headers,
+            pseudo_headers: &pseudo_headers,
+            body: &[],
+            trailers: &[],
+        })
+    }
+
+    /// Tests that closures, boxed services and their handlers all delegate to the service.
+    #[test]
+    fn test_service_adapters() {
+        let mut closure = |req: ServerRequest| {
+            Response::new(req.stream_id, vec![(b":status".to_vec(), b"204".to_vec())], vec![])
+        };
+        assert_eq!(call(&mut closure, 3).status_code().unwrap(), 204);
+
+        let mut boxed: BoxedService = Box::new(Counter { count: 0 });
+        assert_eq!(call(&mut boxed, 1).body, b"1".to_vec());
+        assert_eq!(call(&mut boxed, 3).body, b"2".to_vec());
+
+        let mut handler = into_handler(boxed);
+        let response = call(&mut handler, 5);
+        assert_eq!(response.stream_id, 5);
+        assert_eq!(response.body, b"3".to_vec());
+    }
+}
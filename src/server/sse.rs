@@ -0,0 +1,191 @@
+//! The module implements a helper for serving server-sent events (SSE) over HTTP/2.
+//!
+//! An event stream is a long-lived response whose body is a sequence of events, each made up of
+//! `event:`, `id:` and `data:` lines and ended by an empty line. Requests for one are selected
+//! using `accepts_events` as the server's streaming filter and answered with `response`; the
+//! events are then sent on the stream that is kept open for the response, each one in its own
+//! DATA frame(s) and flushed as soon as it is sent.
+//!
+//! ```rust,no_run
+//! use std::net::TcpListener;
+//! use solicit::server::SimpleServer;
+//! use solicit::server::sse::{self, Event};
+//!
+//! let listener = TcpListener::bind("127.0.0.1:8080").unwrap();
+//! let stream = listener.accept().unwrap().0;
+//! let mut server = SimpleServer::new(stream, |req| sse::response(req.stream_id)).unwrap();
+//! server.set_streaming_filter(|req| sse::accepts_events(req));
+//! loop {
+//!     server.handle_next().unwrap();
+//!     for stream_id in server.open_streams() {
+//!         let mut stream = server.open_stream(stream_id).unwrap();
+//!         stream.send_event(&Event::new("tick").with_event("clock")).unwrap();
+//!     }
+//! }
+//! ```
+use http::{Header, Response, StaticResponse, StreamId, HttpResult};
+use http::transport::TransportStream;
+use super::{ServerRequest, OpenStream};
+
+/// The media type of event streams.
+pub const CONTENT_TYPE: &'static [u8] = b"text/event-stream";
+
+/// A single server-sent event.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Event {
+    /// The type of the event, if other than the default (`message`).
+    pub event: Option<String>,
+    /// The ID of the event, which the client sends back in `last-event-id` when reconnecting.
+    pub id: Option<String>,
+    /// The data of the event. Each line of it (ending with CRLF, CR or LF) is sent in its own
+    /// `data:` line.
+    pub data: String,
+}
+
+impl Event {
+    /// Creates a new `Event` with the given data.
+    pub fn new<D: Into<String>>(data: D) -> Event {
+        Event {
+            event: None,
+            id: None,
+            data: data.into(),
+        }
+    }
+
+    /// Sets the type of the event.
+    pub fn with_event<E: Into<String>>(mut self, event: E) -> Event {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// Sets the ID of the event.
+    pub fn with_id<I: Into<String>>(mut self, id: I) -> Event {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Returns the event formatted for the event stream, including the empty line that
+    /// dispatches it.
+    ///
+    /// Line breaks in the type or the ID would end their lines early, so they are dropped.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        if let Some(ref event) = self.event {
+            push_field(&mut buf, "event", &strip_line_breaks(event));
+        }
+        if let Some(ref id) = self.id {
+            push_field(&mut buf, "id", &strip_line_breaks(id));
+        }
+        // Lines may end with CRLF, a lone CR or a lone LF, as in the event stream itself.
+        let data = self.data.replace("\r\n", "\n");
+        for line in data.split(|c| c == '\r' || c == '\n') {
+            push_field(&mut buf, "data", line);
+        }
+        buf.push(b'\n');
+        buf
+    }
+}
+
+/// Appends a `name: value` line to the buffer.
+fn push_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend_from_slice(name.as_bytes());
+    buf.extend_from_slice(b": ");
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(b'\n');
+}
+
+/// Returns the value without any line breaks.
+fn strip_line_breaks(value: &str) -> String {
+    value.chars().filter(|&c| c != '\r' && c != '\n').collect()
+}
+
+/// Returns whether the request asks for an event stream, i.e. its `accept` header includes
+/// `text/event-stream`.
+pub fn accepts_events(req: &ServerRequest) -> bool {
+    req.headers.iter().filter(|h| h.name() == b"accept").any(|h| {
+        h.value().split(|&b| b == b',').any(|media| trim(media).starts_with(CONTENT_TYPE))
+    })
+}
+
+/// Returns the bytes without any leading spaces.
+fn trim(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|&b| b != b' ').unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+/// Returns the response that starts an event stream on the given stream.
+pub fn response(stream_id: StreamId) -> StaticResponse {
+    Response {
+        stream_id: stream_id,
+        headers: vec![
+            Header::new(b":status", b"200"),
+            Header::new(&b"content-type"[..], CONTENT_TYPE),
+            Header::new(&b"cache-control"[..], &b"no-cache"[..]),
+        ],
+        body: Vec::new(),
+        trailers: Vec::new(),
+    }
+}
+
+impl<'a, TS> OpenStream<'a, TS> where TS: TransportStream + 'a {
+    /// Sends the given event on the stream, blocking until it is written out.
+    pub fn send_event(&mut self, event: &Event) -> HttpResult<()> {
+        self.send(&event.to_bytes())
+    }
+
+    /// Sends a comment line, which clients ignore, but which keeps the stream from looking idle
+    /// to any intermediaries. If the comment is empty, a bare `:` is sent.
+    pub fn send_heartbeat(&mut self, comment: &str) -> HttpResult<()> {
+        let mut buf = b":".to_vec();
+        if !comment.is_empty() {
+            buf.push(b' ');
+            buf.extend_from_slice(strip_line_breaks(comment).as_bytes());
+        }
+        buf.extend_from_slice(b"\n\n");
+        self.send(&buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, accepts_events, response};
+    use http::{Header, PseudoHeaders};
+    use server::ServerRequest;
+
+    /// Tests that events are formatted into the lines of the event stream.
+    #[test]
+    fn test_event_to_bytes() {
+        assert_eq!(Event::new("hi").to_bytes(), b"data: hi\n\n".to_vec());
+
+        let event = Event::new("one\ntwo\r\nthree").with_event("update").with_id("4\n2");
+        assert_eq!(event.to_bytes(),
+                   b"event: update\nid: 42\ndata: one\ndata: two\ndata: three\n\n".to_vec());
+        // A lone CR ends a line as well.
+        assert_eq!(Event::new("one\rtwo\r\rthree\n").to_bytes(),
+                   b"data: one\ndata: two\ndata: \ndata: three\ndata: \n\n".to_vec());
+    }
+
+    /// Tests that requests for event streams are recognized by their `accept` header.
+    #[test]
+    fn test_accepts_events() {
+        fn accepts(headers: Vec<Header>) -> bool {
+            let pseudo_headers = PseudoHeaders::parse(&headers);
+            accepts_events(&ServerRequest {
+                stream_id: 1,
+                headers: &headers,
+                pseudo_headers: &pseudo_headers,
+                body: &[],
+                trailers: &[],
+            })
+        }
+
+        assert!(accepts(vec![Header::new(b"accept", &b"text/event-stream"[..])]));
+        assert!(accepts(vec![Header::new(b"accept", &b"text/html, text/event-stream"[..])]));
+        assert!(!accepts(vec![Header::new(b"accept", &b"text/html"[..])]));
+        assert!(!accepts(vec![]));
+
+        let response = response(3);
+        assert_eq!(response.stream_id, 3);
+        assert_eq!(response.status_code().unwrap(), 200);
+    }
+}
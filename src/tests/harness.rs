@@ -0,0 +1,125 @@
+//! A harness that runs a `SimpleServer` and an async `Client` against each other within the test
+//! process, connected by an in-memory pipe.
+//!
+//! The server runs on its own thread, while the client runs its usual background threads. As the
+//! pipe measures read timeouts in virtual time, which only passes when the test advances it,
+//! scenarios that depend on timeouts (idle connections, keepalive PINGs, stalled streams...) play
+//! out the same way on every run, however slow the machine.
+use std::error;
+use std::fmt;
+use std::io;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use http::{HttpError, HttpResult, HttpScheme, Response};
+use http::client::{ClientStream, HttpConnect, HttpConnectError, write_preface};
+use http::duplex::{self, DuplexStream};
+use http::transport::TransportStream;
+use client::Client;
+use server::{SimpleServer, ServerRequest};
+
+/// Connects the client to its end of the pipe.
+pub struct DuplexConnector(pub DuplexStream);
+
+/// The error of writing the client preface to the pipe.
+#[derive(Debug)]
+pub struct DuplexConnectError(io::Error);
+
+impl fmt::Display for DuplexConnectError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "In-memory connect error: {}", self.0)
+    }
+}
+
+impl error::Error for DuplexConnectError {
+    fn description(&self) -> &str {
+        "In-memory connect error"
+    }
+}
+
+impl HttpConnectError for DuplexConnectError {}
+
+impl HttpConnect for DuplexConnector {
+    type Stream = DuplexStream;
+    type Err = DuplexConnectError;
+
+    fn connect(self) -> Result<ClientStream<DuplexStream>, DuplexConnectError> {
+        let mut stream = self.0;
+        try!(write_preface(&mut stream).map_err(DuplexConnectError));
+        Ok(ClientStream(stream, HttpScheme::Http, "localhost".into()))
+    }
+}
+
+/// A client and a server, connected by an in-memory pipe.
+pub struct Harness {
+    /// The client, connected to the server.
+    pub client: Client,
+    /// A handle to the client's end of the pipe.
+    pipe: DuplexStream,
+    /// The thread running the server, returning the error that stopped it.
+    server: Option<JoinHandle<HttpResult<()>>>,
+}
+
+impl Harness {
+    /// Starts a `SimpleServer` that handles requests with the given handler, along with a client
+    /// connected to it.
+    pub fn new<H>(handler: H) -> Harness
+            where H: FnMut(ServerRequest) -> Response<'static, 'static> + Send + 'static {
+        Harness::with_server(move |stream| {
+            let mut server = try!(SimpleServer::new(stream, handler));
+            loop {
+                try!(server.handle_next());
+            }
+        })
+    }
+
+    /// Starts a client connected to the server that the given closure runs on the other end of
+    /// the pipe. This allows the test to set the server up as it needs to (e.g. with an idle
+    /// timeout), or to script its side of the connection frame by frame.
+    pub fn with_server<F>(serve: F) -> Harness
+            where F: FnOnce(DuplexStream) -> HttpResult<()> + Send + 'static {
+        let (client_end, server_end) = duplex::pipe();
+        let pipe = client_end.try_split().unwrap();
+        let server = thread::spawn(move || serve(server_end));
+        let client = Client::with_connector(DuplexConnector(client_end)).unwrap();
+
+        Harness {
+            client: client,
+            pipe: pipe,
+            server: Some(server),
+        }
+    }
+
+    /// Waits until the server has processed everything that the client sent and is waiting for
+    /// more, and then advances the virtual time by the given duration.
+    pub fn advance_time(&self, duration: Duration) {
+        self.pipe.wait_for_peer_read();
+        self.pipe.advance_time(duration);
+    }
+
+    /// Waits for the server to stop on its own, returning the error that stopped it. The peer
+    /// closing the connection counts as a clean stop.
+    pub fn join_server(mut self) -> HttpResult<()> {
+        let server = self.server.take().unwrap();
+        match server.join().unwrap() {
+            Err(HttpError::PeerClosed { .. }) => Ok(()),
+            res => res,
+        }
+    }
+
+    /// Closes the connection and waits for the server to stop, returning the error that stopped
+    /// it, if any other than the connection being closed.
+    pub fn finish(mut self) -> HttpResult<()> {
+        let _ = self.pipe.close();
+        self.join_server()
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let _ = self.pipe.close();
+        if let Some(server) = self.server.take() {
+            let _ = server.join();
+        }
+    }
+}
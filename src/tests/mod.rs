@@ -0,0 +1,784 @@
+//! Tests that run the client and the server of the crate against each other.
+
+mod harness;
+
+use std::cell::RefCell;
+use std::io::{Cursor, Read};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use http::{HttpError, HttpResult, Header, Response, ErrorCode};
+use http::config::Http2Config;
+use http::connection::{HttpFrame, ReceiveFrame, SendFrame};
+use http::duplex;
+use http::frame::{SettingsFrame, RstStreamFrame, GoawayFrame, Frame};
+use http::transport::TransportReceiveFrame;
+use client::{Client, Multipart};
+use server::{self, SimpleServer, ServerRequest};
+use self::harness::{Harness, DuplexConnector};
+
+/// Responds with the path of the request.
+fn echo_path(req: ServerRequest) -> Response<'static, 'static> {
+    Response::new(req.stream_id,
+                  vec![(b":status".to_vec(), b"200".to_vec())],
+                  req.pseudo_headers.path.clone().unwrap_or_default())
+}
+
+/// Tests that requests issued by the client are answered by the server.
+#[test]
+fn test_harness_requests() {
+    let harness = Harness::new(echo_path);
+
+    let first = harness.client.get(b"/first", &[]).unwrap();
+    let second = harness.client.get(b"/second", &[]).unwrap();
+
+    assert_eq!(first.recv().unwrap().body, b"/first".to_vec());
+    let second = second.recv().unwrap();
+    assert_eq!(second.status_code().unwrap(), 200);
+    assert_eq!(second.body, b"/second".to_vec());
+    assert!(harness.finish().is_ok());
+}
+
+/// Tests that the server closes the connection once it is idle for long enough, as measured by
+/// the virtual time of the harness.
+#[test]
+fn test_harness_idle_timeout() {
+    let harness = Harness::with_server(|stream| {
+        let mut server = try!(SimpleServer::new(stream, echo_path));
+        try!(server.set_idle_timeout(Some(Duration::from_secs(60))));
+        loop {
+            try!(server.handle_next());
+        }
+    });
+    let response = harness.client.get(b"/", &[]).unwrap().recv().unwrap();
+    assert_eq!(response.status_code().unwrap(), 200);
+
+    // Not idle for long enough yet...
+    harness.advance_time(Duration::from_secs(30));
+    let response = harness.client.get(b"/again", &[]).unwrap().recv().unwrap();
+    assert_eq!(response.body, b"/again".to_vec());
+    // ...and the request restarted the timeout.
+    harness.advance_time(Duration::from_secs(30));
+    harness.advance_time(Duration::from_secs(30));
+
+    assert_eq!(harness.join_server().err().unwrap(), HttpError::ConnectionIdle);
+}
+
+/// Tests that requests which the server wants made over HTTP/1.1 are handed to the fallback of
+/// the client, which can respond to them in its place.
+#[test]
+fn test_harness_http11_fallback() {
+    // A server that resets every request with HTTP_1_1_REQUIRED.
+    let harness = Harness::with_server(|mut stream| {
+        let mut magic = [0; 24];
+        try!(stream.read_exact(&mut magic));
+        try!(stream.send_frame(SettingsFrame::new()));
+        loop {
+            let stream_id = match try!(TransportReceiveFrame::new(&mut stream).recv_frame()) {
+                HttpFrame::HeadersFrame(frame) => frame.get_stream_id(),
+                _ => continue,
+            };
+            try!(stream.send_frame(RstStreamFrame::new(stream_id, ErrorCode::Http11Required)));
+        }
+    });
+    let (tx, rx) = mpsc::channel();
+    harness.client.set_http11_fallback(move |req| {
+        tx.send(req.path.clone()).unwrap();
+        req.respond(Response::new(0,
+                                  vec![(b":status".to_vec(), b"200".to_vec())],
+                                  b"over HTTP/1.1".to_vec()));
+    }).unwrap();
+
+    let response = harness.client.get(b"/legacy", &[]).unwrap().recv().unwrap();
+
+    assert_eq!(response.body, b"over HTTP/1.1".to_vec());
+    assert_eq!(rx.recv().unwrap(), b"/legacy".to_vec());
+    assert!(harness.finish().is_ok());
+}
+
+/// Tests that a client which reconnects carries the requests that it has yet to send over to a
+/// new connection when the server closes the current one with a GOAWAY, while the requests that
+/// were in flight fail.
+#[test]
+fn test_client_reconnect_carries_queued_requests() {
+    let (servers_tx, servers_rx) = mpsc::channel();
+    let mut connections = 0;
+    let client = Client::with_reconnect(move || {
+        let (client_end, mut server_end) = duplex::pipe();
+        let first = connections == 0;
+        connections += 1;
+        servers_tx.send(thread::spawn(move || -> Result<(), HttpError> {
+            if !first {
+                let mut server = try!(SimpleServer::new(server_end, echo_path));
+                loop {
+                    try!(server.handle_next());
+                }
+            }
+            // The first connection is closed once the client has as many requests in flight as
+            // it allows, without answering any of them.
+            let mut magic = [0; 24];
+            try!(server_end.read_exact(&mut magic));
+            try!(server_end.send_frame(SettingsFrame::new()));
+            let mut requests = 0;
+            while requests < 3 {
+                match try!(TransportReceiveFrame::new(&mut server_end).recv_frame()) {
+                    HttpFrame::HeadersFrame(_) => requests += 1,
+                    _ => {},
+                }
+            }
+            server_end.send_frame(GoawayFrame::new(0, ErrorCode::NoError))
+        })).unwrap();
+        DuplexConnector(client_end)
+    }, Http2Config::default()).unwrap();
+
+    let paths: Vec<&[u8]> = vec![b"/1", b"/2", b"/3", b"/4", b"/5"];
+    let responses: Vec<_> = paths.iter().map(|path| client.get(path, &[]).unwrap()).collect();
+    let responses: Vec<_> = responses.into_iter().map(|rx| rx.recv().ok()).collect();
+
+    assert!(responses[..3].iter().all(|response| response.is_none()));
+    assert_eq!(responses[3].as_ref().unwrap().body, b"/4".to_vec());
+    assert_eq!(responses[4].as_ref().unwrap().body, b"/5".to_vec());
+    assert_eq!(client.stats().unwrap().reconnects, 1);
+    drop(client);
+    for server in servers_rx.iter() {
+        let _ = server.join();
+    }
+}
+
+/// Tests that a client which reconnects retries establishing its new connection, as far as its
+/// retry policy allows.
+#[test]
+fn test_client_reconnect_retry_policy() {
+    use std::sync::Arc;
+    use client::ExponentialBackoff;
+
+    let mut connections = 0;
+    let client = Client::with_reconnect(move || {
+        let (client_end, mut server_end) = duplex::pipe();
+        connections += 1;
+        match connections {
+            // The first server goes away as soon as it gets a request...
+            1 => {
+                thread::spawn(move || -> HttpResult<()> {
+                    let mut magic = [0; 24];
+                    try!(server_end.read_exact(&mut magic));
+                    try!(server_end.send_frame(SettingsFrame::new()));
+                    loop {
+                        let mut receiver = TransportReceiveFrame::new(&mut server_end);
+                        if let HttpFrame::HeadersFrame(_) = try!(receiver.recv_frame()) {
+                            break;
+                        }
+                    }
+                    server_end.send_frame(GoawayFrame::new(0, ErrorCode::NoError))
+                });
+            },
+            // ...the next one cannot be connected to...
+            2 => ::http::transport::TransportStream::close(&mut server_end).unwrap(),
+            // ...while the one after that works.
+            _ => {
+                thread::spawn(move || -> HttpResult<()> {
+                    let mut server = try!(SimpleServer::new(server_end, echo_path));
+                    loop {
+                        try!(server.handle_next());
+                    }
+                });
+            },
+        }
+        DuplexConnector(client_end)
+    }, Http2Config::default()).unwrap();
+    let policy = ExponentialBackoff::new(Duration::from_millis(1), Duration::from_millis(10), 3);
+    client.set_retry_policy(Arc::new(policy)).unwrap();
+
+    assert!(client.get(b"/1", &[]).unwrap().recv().is_err());
+    assert_eq!(client.get(b"/2", &[]).unwrap().recv().unwrap().body, b"/2".to_vec());
+    assert_eq!(client.stats().unwrap().reconnects, 1);
+}
+
+/// Tests that a request body streamed by the client can be read by the server as it arrives,
+/// with the response deferred until all of it was read.
+#[test]
+fn test_streamed_upload_deferred_response() {
+    let harness = Harness::with_server(|stream| {
+        let pending = Rc::new(RefCell::new(Vec::new()));
+        let handler_pending = pending.clone();
+        let mut server = try!(SimpleServer::new(stream, move |req: ServerRequest| {
+            handler_pending.borrow_mut().push(req.stream_id);
+            server::defer(req.stream_id)
+        }));
+        server.set_streaming_filter(|_| true);
+        loop {
+            try!(server.handle_next());
+            let ready: Vec<_> = pending.borrow_mut().drain(..).collect();
+            for stream_id in ready {
+                let mut body = Vec::new();
+                try!(server.request_body(stream_id).unwrap().read_to_end(&mut body)
+                           .map_err(HttpError::from));
+                let mut stream = server.open_stream(stream_id).unwrap();
+                try!(stream.start_response(vec![Header::new(b":status", b"200")]));
+                try!(stream.send(&body));
+                try!(stream.close());
+            }
+        }
+    });
+
+    let (body, rx) = harness.client.request_streaming(None, b"POST", b"/upload", &[]).unwrap();
+    body.send(b"hello ".to_vec()).unwrap();
+    body.send(b"world".to_vec()).unwrap();
+    body.finish().unwrap();
+
+    let response = rx.recv().unwrap();
+    assert_eq!(response.status_code().unwrap(), 200);
+    assert_eq!(response.body, b"hello world".to_vec());
+    assert!(harness.finish().is_ok());
+}
+
+/// Tests that a multipart form posted by the client reaches the server along with the
+/// `content-type` that announces its boundary, its file part read from its source in chunks.
+#[test]
+fn test_client_post_multipart() {
+    let harness = Harness::new(|req: ServerRequest| {
+        let content_type = req.headers.iter()
+                                      .find(|h| h.name() == b"content-type")
+                                      .map(|h| h.value().to_vec())
+                                      .unwrap_or_default();
+        Response::new(req.stream_id,
+                      vec![(b":status".to_vec(), b"200".to_vec()),
+                           (b"x-content-type".to_vec(), content_type)],
+                      req.body.to_vec())
+    });
+    let contents = vec![b'x'; 20_000];
+    let form = Multipart::with_boundary("b0undary".to_string())
+        .text("name", b"value")
+        .file("upload", "x.txt", b"text/plain", Cursor::new(contents.clone()));
+
+    let rx = harness.client.post_multipart(b"/form", &[], form).unwrap();
+
+    let response = rx.recv().unwrap();
+    let content_type = response.headers.iter()
+                                       .find(|h| h.name() == b"x-content-type")
+                                       .map(|h| h.value().to_vec());
+    assert_eq!(content_type, Some(b"multipart/form-data; boundary=b0undary".to_vec()));
+    let mut expected = b"--b0undary\r\n\
+                         content-disposition: form-data; name=\"name\"\r\n\r\nvalue\r\n\
+                         --b0undary\r\n\
+                         content-disposition: form-data; name=\"upload\"; filename=\"x.txt\"\r\n\
+                         content-type: text/plain\r\n\r\n".to_vec();
+    expected.extend_from_slice(&contents);
+    expected.extend_from_slice(b"\r\n--b0undary--\r\n");
+    assert_eq!(response.body, expected);
+    assert!(harness.finish().is_ok());
+}
+
+/// Tests that a duplex server exchanges bodies in both directions at once: the second upload is
+/// received while the response to the first one is being sent.
+#[test]
+fn test_duplex_server_bodies() {
+    let harness = Harness::with_server(|stream| {
+        let mut server = try!(SimpleServer::new(stream, |req: ServerRequest| {
+            Response::new(req.stream_id,
+                          vec![(b":status".to_vec(), b"200".to_vec())],
+                          req.body.to_vec())
+        }));
+        try!(server.enable_duplex());
+        loop {
+            try!(server.handle_next());
+        }
+    });
+
+    let upload: Vec<u8> = (0..30000).map(|i| i as u8).collect();
+    let first = harness.client.request(b"POST", b"/1", &[], Some(upload.clone())).unwrap();
+    let second = harness.client.request(b"POST", b"/2", &[], Some(upload.clone())).unwrap();
+
+    assert_eq!(first.recv().unwrap().body, upload);
+    assert_eq!(second.recv().unwrap().body, upload);
+    assert!(harness.finish().is_ok());
+}
+
+/// Tests that both the client and the server record the spans of a request, as well as of their
+/// connections, once a sink is set.
+#[cfg(feature="tracing")]
+#[test]
+fn test_tracing_spans() {
+    use std::sync::{Arc, Mutex};
+    use http::tracing::{self, Role, SpanKind};
+
+    let spans = Arc::new(Mutex::new(Vec::new()));
+    {
+        let spans = spans.clone();
+        tracing::set_sink(move |span: &tracing::Span| spans.lock().unwrap().push(span.clone()));
+    }
+    let harness = Harness::new(echo_path);
+    let client_id = harness.client.stats().unwrap().connection_id;
+    let response = harness.client.post(b"/traced", &[], b"12345".to_vec()).unwrap();
+    assert_eq!(response.recv().unwrap().body, b"/traced".to_vec());
+    drop(harness);
+    tracing::clear_sink();
+
+    let spans = spans.lock().unwrap();
+    let client = spans.iter().find(|span| {
+        span.role == Some(Role::Client) && span.kind == SpanKind::Stream &&
+            span.connection_id == client_id
+    }).unwrap();
+    assert_eq!(client.method, Some(b"POST".to_vec()));
+    assert_eq!(client.status, Some(200));
+    assert_eq!((client.bytes_sent, client.bytes_received), (5, 7));
+    let server = spans.iter().find(|span| {
+        span.role == Some(Role::Server) && span.path == Some(b"/traced".to_vec())
+    }).unwrap();
+    assert_eq!(server.status, Some(200));
+    assert_eq!((server.bytes_sent, server.bytes_received), (7, 5));
+    let server_connection = spans.iter().find(|span| {
+        span.kind == SpanKind::Connection && span.connection_id == server.connection_id
+    }).unwrap();
+    assert_eq!(server_connection.role, Some(Role::Server));
+    assert_eq!(server_connection.bytes_sent, 7);
+}
+
+/// Tests that a client propagating trace contexts starts a new trace for a request without one
+/// and continues the trace of a request that carries one, and that the server extracts them.
+#[test]
+fn test_trace_propagation() {
+    use http::tracing::TraceContext;
+
+    fn echo_trace(req: ServerRequest) -> Response<'static, 'static> {
+        let trace = req.trace_context().expect("Expected a trace context");
+        let mut body = trace.traceparent();
+        if let Some(state) = trace.tracestate {
+            body.push(b' ');
+            body.extend(state);
+        }
+        Response::new(req.stream_id, vec![(b":status".to_vec(), b"200".to_vec())], body)
+    }
+    let harness = Harness::new(echo_trace);
+    harness.client.set_trace_propagation(true).unwrap();
+
+    let body = harness.client.get(b"/", &[]).unwrap().recv().unwrap().body;
+    let root = TraceContext::parse(&body).expect("Expected a traceparent");
+    assert!(root.is_sampled());
+
+    let mut parent = TraceContext::new();
+    parent.tracestate = Some(b"vendor=abc".to_vec());
+    let body = harness.client.get(b"/", &parent.to_headers()).unwrap().recv().unwrap().body;
+    let mut parts = body.splitn(2, |&b| b == b' ');
+    let child = TraceContext::parse(parts.next().unwrap()).expect("Expected a traceparent");
+    assert_eq!(parts.next(), Some(&b"vendor=abc"[..]));
+    assert_eq!(child.trace_id, parent.trace_id);
+    assert!(child.parent_id != parent.parent_id);
+    assert!(root.trace_id != parent.trace_id);
+    assert!(harness.finish().is_ok());
+}
+
+/// Tests that a client with a maximum connection age closes an expired connection with a GOAWAY
+/// and sends the next request on a new connection.
+#[test]
+fn test_client_rotates_expired_connection() {
+    let (servers_tx, servers_rx) = mpsc::channel();
+    let config = Http2Config::default().max_connection_age(Some(Duration::from_millis(50)));
+    let client = Client::with_reconnect(move || {
+        let (client_end, server_end) = duplex::pipe();
+        servers_tx.send(thread::spawn(move || -> HttpError {
+            let mut server = match SimpleServer::new(server_end, echo_path) {
+                Ok(server) => server,
+                Err(e) => return e,
+            };
+            loop {
+                if let Err(e) = server.handle_next() {
+                    return e;
+                }
+            }
+        })).unwrap();
+        DuplexConnector(client_end)
+    }, config).unwrap();
+
+    assert_eq!(client.get(b"/1", &[]).unwrap().recv().unwrap().body, b"/1".to_vec());
+    assert_eq!(client.stats().unwrap().reconnects, 0);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(client.get(b"/2", &[]).unwrap().recv().unwrap().body, b"/2".to_vec());
+    assert_eq!(client.stats().unwrap().reconnects, 1);
+
+    drop(client);
+    let errors: Vec<_> = servers_rx.iter().map(|server| server.join().unwrap()).collect();
+    assert_eq!(errors.len(), 2);
+    // The first connection was closed with a GOAWAY.
+    match errors[0] {
+        HttpError::PeerConnectionError(ref e) => assert_eq!(e.error_code(), ErrorCode::NoError),
+        ref e => panic!("Expected a GOAWAY, got {:?}", e),
+    }
+}
+
+/// Tests that a client pool only opens a new connection once the ones it has are saturated, up
+/// to its limit, and then spreads the requests over the least loaded connections.
+#[test]
+fn test_client_pool() {
+    use std::sync::{Arc, Condvar, Mutex};
+    use client::ClientPool;
+
+    // The servers hold on to the requests until they are released, so that they stay in flight.
+    let released = Arc::new((Mutex::new(false), Condvar::new()));
+    let server_released = released.clone();
+    let mut pool = ClientPool::new(move || {
+        let (client_end, server_end) = duplex::pipe();
+        let released = server_released.clone();
+        thread::spawn(move || -> HttpResult<()> {
+            let mut server = try!(SimpleServer::new(server_end, move |req: ServerRequest| {
+                let &(ref lock, ref cvar) = &*released;
+                let mut released = lock.lock().unwrap();
+                while !*released {
+                    released = cvar.wait(released).unwrap();
+                }
+                echo_path(req)
+            }));
+            loop {
+                try!(server.handle_next());
+            }
+        });
+        Client::with_connector(DuplexConnector(client_end))
+    }).unwrap();
+    pool.set_max_connections(2);
+
+    let responses: Vec<_> = (0..7).map(|i| {
+        pool.get(format!("/{}", i).as_bytes(), &[]).unwrap()
+    }).collect();
+    assert_eq!(pool.connections(), 2);
+    let loads: Vec<_> = pool.stats().iter().map(|s| (s.in_flight, s.queued)).collect();
+    // Each connection has 3 requests in flight, the default limit of a client; the last request
+    // is queued on the first connection, as the strategy picks it when all are equally loaded.
+    assert_eq!(loads, vec![(3, 1), (3, 0)]);
+
+    {
+        let &(ref lock, ref cvar) = &*released;
+        *lock.lock().unwrap() = true;
+        cvar.notify_all();
+    }
+    for (i, response) in responses.into_iter().enumerate() {
+        assert_eq!(response.recv().unwrap().body, format!("/{}", i).into_bytes());
+    }
+}
+
+/// Tests that a pool which hedges GET requests issues a request whose response headers are slow
+/// to arrive once more, on another connection, delivering the response that arrives first and
+/// cancelling the other request.
+#[test]
+fn test_client_pool_hedging() {
+    use client::ClientPool;
+
+    let (reset_tx, reset_rx) = mpsc::channel();
+    let mut opened = 0;
+    let mut pool = ClientPool::new(move || {
+        let (client_end, mut server_end) = duplex::pipe();
+        opened += 1;
+        if opened == 1 {
+            // The first server never responds, reporting the streams that get reset instead.
+            let reset_tx = reset_tx.clone();
+            thread::spawn(move || -> HttpResult<()> {
+                let mut magic = [0; 24];
+                try!(server_end.read_exact(&mut magic));
+                try!(server_end.send_frame(SettingsFrame::new()));
+                loop {
+                    let mut receiver = TransportReceiveFrame::new(&mut server_end);
+                    if let HttpFrame::RstStreamFrame(frame) = try!(receiver.recv_frame()) {
+                        let _ = reset_tx.send((frame.get_stream_id(), frame.error_code()));
+                    }
+                }
+            });
+        } else {
+            thread::spawn(move || -> HttpResult<()> {
+                let mut server = try!(SimpleServer::new(server_end, echo_path));
+                loop {
+                    try!(server.handle_next());
+                }
+            });
+        }
+        Client::with_connector(DuplexConnector(client_end))
+    }).unwrap();
+    pool.set_hedging(Some(Duration::from_millis(50)));
+
+    let response = pool.get(b"/slow", &[]).unwrap().recv().unwrap();
+
+    assert_eq!(response.body, b"/slow".to_vec());
+    assert_eq!(pool.connections(), 2);
+    assert_eq!(reset_rx.recv().unwrap(), (1, ErrorCode::Cancel));
+}
+
+/// Tests that the stats of a client count the requests that are in flight and the ones queued
+/// behind them, as far as the concurrency limit announced by the server allows.
+#[test]
+fn test_client_stats() {
+    use std::sync::{Arc, Condvar, Mutex};
+
+    // The server holds on to the requests until they are released, so that they stay in flight.
+    let released = Arc::new((Mutex::new(false), Condvar::new()));
+    let server_released = released.clone();
+    let harness = Harness::with_server(move |stream| {
+        let config = Http2Config::new().max_concurrent_streams(Some(2));
+        let mut server = try!(SimpleServer::with_config(stream, move |req: ServerRequest| {
+            let &(ref lock, ref cvar) = &*server_released;
+            let mut released = lock.lock().unwrap();
+            while !*released {
+                released = cvar.wait(released).unwrap();
+            }
+            echo_path(req)
+        }, config));
+        loop {
+            try!(server.handle_next());
+        }
+    });
+    // Makes sure that the server's limit is known before any request is sent.
+    harness.client.preconnect().unwrap();
+
+    let responses: Vec<_> = (0..4).map(|i| {
+        harness.client.get(format!("/{}", i).as_bytes(), &[]).unwrap()
+    }).collect();
+
+    let stats = harness.client.stats().unwrap();
+    assert_eq!(stats.peer_max_concurrent_streams, Some(2));
+    assert_eq!(stats.max_in_flight(), 2);
+    assert_eq!((stats.in_flight, stats.queued), (2, 2));
+
+    {
+        let &(ref lock, ref cvar) = &*released;
+        *lock.lock().unwrap() = true;
+        cvar.notify_all();
+    }
+    for (i, response) in responses.into_iter().enumerate() {
+        assert_eq!(response.recv().unwrap().body, format!("/{}", i).into_bytes());
+    }
+    let stats = harness.client.stats().unwrap();
+    assert_eq!((stats.in_flight, stats.queued), (0, 0));
+    assert!(harness.finish().is_ok());
+}
+
+/// Tests that preconnecting returns once the client knows the server's SETTINGS, before any
+/// request is issued, and right away once the connection is already set up.
+#[test]
+fn test_client_preconnect() {
+    let harness = Harness::with_server(|stream| {
+        let config = Http2Config::new().max_concurrent_streams(Some(5));
+        let mut server = try!(SimpleServer::with_config(stream, echo_path, config));
+        loop {
+            try!(server.handle_next());
+        }
+    });
+
+    harness.client.preconnect().unwrap();
+
+    let stats = harness.client.stats().unwrap();
+    assert_eq!(stats.peer_max_concurrent_streams, Some(5));
+    assert_eq!((stats.in_flight, stats.queued), (0, 0));
+    harness.client.preconnect().unwrap();
+    assert_eq!(harness.client.get(b"/", &[]).unwrap().recv().unwrap().body, b"/".to_vec());
+    assert!(harness.finish().is_ok());
+}
+
+/// Tests that preconnecting fails when the server closes the connection without sending its
+/// SETTINGS.
+#[test]
+fn test_client_preconnect_fails() {
+    let harness = Harness::with_server(|mut stream| {
+        let mut magic = [0; 24];
+        try!(stream.read_exact(&mut magic));
+        try!(::http::transport::TransportStream::close(&mut stream));
+        Ok(())
+    });
+
+    assert_eq!(harness.client.preconnect().err().unwrap(), HttpError::UnableToConnect);
+    assert!(harness.join_server().is_ok());
+}
+
+/// Tests that the `:authority` of a request is the one that it was issued with, whatever the host
+/// that the client is connected to, which the other requests keep using.
+#[test]
+fn test_client_request_with_authority() {
+    let harness = Harness::new(|req: ServerRequest| {
+        Response::new(req.stream_id,
+                      vec![(b":status".to_vec(), b"200".to_vec())],
+                      req.pseudo_headers.authority.clone().unwrap_or_default())
+    });
+
+    let proxied = harness.client.request_with_authority(
+        b"backend.example:8443", b"GET", b"/", &[], None).unwrap();
+    let direct = harness.client.get(b"/", &[]).unwrap();
+
+    assert_eq!(proxied.recv().unwrap().body, b"backend.example:8443".to_vec());
+    assert_eq!(direct.recv().unwrap().body, b"localhost".to_vec());
+    assert!(harness.finish().is_ok());
+}
+
+/// Tests that the client sends and receives header values larger than the maximum frame size,
+/// whose header blocks are split across CONTINUATION frames.
+#[test]
+fn test_client_large_headers() {
+    let harness = Harness::new(|req: ServerRequest| {
+        let token = req.headers.iter()
+                               .find(|h| h.name() == b"authorization")
+                               .map(|h| h.value().to_vec())
+                               .unwrap_or_default();
+        Response::new(req.stream_id,
+                      vec![(b":status".to_vec(), b"200".to_vec()),
+                           (b"x-token".to_vec(), token)],
+                      vec![])
+    });
+    // E.g. a large signed token.
+    let token = vec![b'x'; 64 * 1024 + 1];
+
+    let headers = [Header::new(b"authorization".to_vec(), token.clone())];
+    let response = harness.client.get(b"/", &headers).unwrap();
+
+    let response = response.recv().unwrap();
+    assert_eq!(response.status_code().ok(), Some(200));
+    let echoed = response.headers.iter().find(|h| h.name() == b"x-token").map(|h| h.value());
+    assert_eq!(echoed, Some(&token[..]));
+    assert!(harness.finish().is_ok());
+}
+
+/// Tests that warming a pool up opens as many connections as asked for, up to its limit, each
+/// of them set up by the time the call returns.
+#[test]
+fn test_client_pool_warm() {
+    use client::ClientPool;
+
+    let mut pool = ClientPool::new(move || {
+        let (client_end, server_end) = duplex::pipe();
+        thread::spawn(move || -> HttpResult<()> {
+            let config = Http2Config::new().max_concurrent_streams(Some(5));
+            let mut server = try!(SimpleServer::with_config(server_end, echo_path, config));
+            loop {
+                try!(server.handle_next());
+            }
+        });
+        Client::with_connector(DuplexConnector(client_end))
+    }).unwrap();
+    pool.set_max_connections(3);
+
+    pool.warm(2).unwrap();
+    assert_eq!(pool.connections(), 2);
+    // The server's SETTINGS are known on both connections, without any request.
+    let stats = pool.stats();
+    assert!(stats.iter().all(|s| s.peer_max_concurrent_streams == Some(5) && s.in_flight == 0));
+
+    pool.warm(10).unwrap();
+    assert_eq!(pool.connections(), 3);
+    pool.warm(1).unwrap();
+    assert_eq!(pool.connections(), 3);
+    assert_eq!(pool.get(b"/", &[]).unwrap().recv().unwrap().body, b"/".to_vec());
+}
+
+/// Tests that the HTTP/1.1 fallback of a pool is handed the requests that need HTTP/1.1 on any
+/// of its connections, including the ones opened after it was set.
+#[test]
+fn test_client_pool_http11_fallback() {
+    use client::{ClientPool, RoundRobin};
+
+    // The servers reset every request with HTTP_1_1_REQUIRED.
+    let mut pool = ClientPool::new(move || {
+        let (client_end, mut server_end) = duplex::pipe();
+        thread::spawn(move || -> HttpResult<()> {
+            let mut magic = [0; 24];
+            try!(server_end.read_exact(&mut magic));
+            try!(server_end.send_frame(SettingsFrame::new()));
+            loop {
+                let mut receiver = TransportReceiveFrame::new(&mut server_end);
+                let stream_id = match try!(receiver.recv_frame()) {
+                    HttpFrame::HeadersFrame(frame) => frame.get_stream_id(),
+                    _ => continue,
+                };
+                try!(server_end.send_frame(
+                        RstStreamFrame::new(stream_id, ErrorCode::Http11Required)));
+            }
+        });
+        Client::with_connector(DuplexConnector(client_end))
+    }).unwrap();
+    pool.set_balancing_strategy(RoundRobin::default());
+    let (tx, rx) = mpsc::channel();
+    pool.set_http11_fallback(move |req| {
+        tx.send(req.path.clone()).unwrap();
+        req.respond(Response::new(0,
+                                  vec![(b":status".to_vec(), b"200".to_vec())],
+                                  b"over HTTP/1.1".to_vec()));
+    });
+    pool.warm(2).unwrap();
+
+    let first = pool.get(b"/1", &[]).unwrap();
+    let second = pool.get(b"/2", &[]).unwrap();
+
+    assert_eq!(first.recv().unwrap().body, b"over HTTP/1.1".to_vec());
+    assert_eq!(second.recv().unwrap().body, b"over HTTP/1.1".to_vec());
+    let mut paths = vec![rx.recv().unwrap(), rx.recv().unwrap()];
+    paths.sort();
+    assert_eq!(paths, vec![b"/1".to_vec(), b"/2".to_vec()]);
+}
+
+/// Tests that a request issued with `request_checked` fails with the reason why its response
+/// headers are malformed, while those issued with `request` only see their channel disconnect.
+#[test]
+fn test_client_request_checked_malformed() {
+    use hpack;
+    use http::frame::{HeadersFrame, HeadersFlag};
+    use http::validation::MalformedHeaders;
+
+    // A server that responds to every request with an invalid status.
+    let harness = Harness::with_server(|mut stream| {
+        let mut magic = [0; 24];
+        try!(stream.read_exact(&mut magic));
+        try!(stream.send_frame(SettingsFrame::new()));
+        let mut encoder = hpack::Encoder::new();
+        loop {
+            let stream_id = match try!(TransportReceiveFrame::new(&mut stream).recv_frame()) {
+                HttpFrame::HeadersFrame(frame) => frame.get_stream_id(),
+                _ => continue,
+            };
+            let fragment = encoder.encode(vec![(&b":status"[..], &b"20x"[..])]);
+            let mut headers = HeadersFrame::new(fragment, stream_id);
+            headers.set_flag(HeadersFlag::EndHeaders);
+            try!(stream.send_frame(headers));
+        }
+    });
+
+    let checked = harness.client.request_checked(b"GET", b"/", &[], None).unwrap();
+    let plain = harness.client.get(b"/", &[]).unwrap();
+
+    let err = checked.recv().unwrap().err().unwrap();
+    assert_eq!(err, HttpError::MalformedHeaders(MalformedHeaders::InvalidStatus));
+    assert!(plain.recv().is_err());
+    assert!(harness.finish().is_ok());
+}
+
+/// Tests that a client connected to an HTTP/1.1 server fails the requests waiting on the
+/// connection, as well as preconnecting, with the status line that the server sent instead of
+/// its preface.
+#[test]
+fn test_client_not_http2() {
+    use std::io::Write;
+
+    // The server only responds once the request is issued.
+    let (issued_tx, issued_rx) = mpsc::channel();
+    let harness = Harness::with_server(move |mut stream| {
+        let mut magic = [0; 24];
+        try!(stream.read_exact(&mut magic));
+        issued_rx.recv().unwrap();
+        try!(stream.write_all(b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\n\r\n"));
+        Ok(())
+    });
+
+    let response = harness.client.request_checked(b"GET", b"/", &[], None).unwrap();
+    issued_tx.send(()).unwrap();
+
+    let mismatch = match harness.client.preconnect() {
+        Err(HttpError::NotHttp2(mismatch)) => mismatch,
+        res => panic!("Expected a NotHttp2 error, got {:?}", res),
+    };
+    assert!(mismatch.is_http1());
+    match response.recv().unwrap() {
+        Err(HttpError::NotHttp2(ref e)) => assert_eq!(*e, mismatch),
+        res => panic!("Expected a NotHttp2 error, got {:?}", res.map(|r| r.stream_id)),
+    }
+    // The requests issued later on fail the same way.
+    let response = harness.client.request_checked(b"GET", b"/", &[], None).unwrap();
+    match response.recv().unwrap() {
+        Err(HttpError::NotHttp2(ref e)) => assert_eq!(*e, mismatch),
+        res => panic!("Expected a NotHttp2 error, got {:?}", res.map(|r| r.stream_id)),
+    }
+    assert!(harness.join_server().is_ok());
+}